@@ -1,17 +1,24 @@
 package main
 
 import (
+	"archive/zip"
 	"context"
 	"crypto/rand"
 	"crypto/sha256"
 	"encoding/base64"
+	"encoding/hex"
 	"encoding/json"
+	"io"
 	"log"
 	"net/http"
 	"os"
 	"os/signal"
 	"path/filepath"
 	"regexp"
+	"sort"
+	"strconv"
+	"strings"
+	"sync"
 	"syscall"
 	"time"
 
@@ -20,22 +27,89 @@ import (
 	"golang.org/x/time/rate"
 )
 
+// version is reported by /health; release builds set it with
+// -ldflags "-X main.version=..."
+var version = "dev"
+
 type User struct {
-	Email     string    `json:"email"`
-	Password  string    `json:"-"`
-	Token     string    `json:"token,omitempty"`
+	Email        string        `json:"email"`
+	Password     string        `json:"-"`
+	Token        string        `json:"token,omitempty"`
+	DeviceTokens []DeviceToken `json:"device_tokens,omitempty"`
+	CreatedAt    time.Time     `json:"created_at"`
+}
+
+type DeviceToken struct {
+	Token     string    `json:"token"`
+	Scope     string    `json:"scope"`
 	CreatedAt time.Time `json:"created_at"`
 }
 
+type LinkCode struct {
+	Email     string
+	ExpiresAt time.Time
+}
+
+type LinkClaimRequest struct {
+	Code string `json:"code"`
+}
+
 type SyncData struct {
-	Files    map[string]string `json:"files"`
-	Packages []Package         `json:"packages"`
+	Files    map[string]string      `json:"files"`
+	Blobs    map[string]string      `json:"blobs,omitempty"`
+	Pointers map[string]FilePointer `json:"pointers,omitempty"`
+	Packages []Package              `json:"packages"`
+	Taps     []string               `json:"taps,omitempty"`
+	Brew     *BrewEnvironment       `json:"brew,omitempty"`
+}
+
+// BrewEnvironment records the pushing machine's brew prefix and HOMEBREW_*
+// variables so a restore can adjust paths for its own architecture.
+type BrewEnvironment struct {
+	Prefix string            `json:"prefix"`
+	Env    map[string]string `json:"env,omitempty"`
+}
+
+// FilePointer stands in for a file too large to sync; only its hash and
+// size are stored.
+type FilePointer struct {
+	Hash string `json:"hash"`
+	Size int64  `json:"size"`
 }
 
 type Package struct {
-	Name      string  `json:"name"`
-	Version   *string `json:"version,omitempty"`
-	Installed bool    `json:"installed"`
+	Name            string  `json:"name"`
+	Version         *string `json:"version,omitempty"`
+	Installed       bool    `json:"installed"`
+	Note            *string `json:"note,omitempty"`
+	Held            bool    `json:"held,omitempty"`
+	IsCask          bool    `json:"is_cask,omitempty"`
+	RequiresRosetta bool    `json:"requires_rosetta,omitempty"`
+}
+
+// TelemetryEvent is the entire payload of an opt-in usage report. It carries
+// no account or machine identifier.
+type TelemetryEvent struct {
+	Command    string `json:"command"`
+	DurationMs int64  `json:"duration_ms"`
+	Success    bool   `json:"success"`
+}
+
+// Usage is the storage an account takes: everything in its data directory,
+// with each synced file's share.
+type Usage struct {
+	Used  int64            `json:"used"`
+	Limit *int64           `json:"limit"`
+	Files map[string]int64 `json:"files"`
+}
+
+// Activity is one push as the account's activity log records it.
+type Activity struct {
+	At              string   `json:"at"`
+	Machine         string   `json:"machine,omitempty"`
+	Files           []string `json:"files"`
+	PackagesAdded   []string `json:"packages_added"`
+	PackagesRemoved []string `json:"packages_removed"`
 }
 
 type LoginRequest struct {
@@ -51,12 +125,36 @@ type RegisterRequest struct {
 const (
 	dataDir      = "/opt/kiwi/data"
 	usersDir     = "/opt/kiwi/users"
+	telemetryDir = "/opt/kiwi/telemetry"
+	templatesDir = "/opt/kiwi/templates"
 	authTokenEnv = "KIWI_AUTH_TOKEN"
+	// Per-account storage limit in bytes; unset means no limit
+	storageLimitEnv = "KIWI_STORAGE_LIMIT"
+
+	linkCodeTTL      = 10 * time.Minute
+	linkCodeAlphabet = "ABCDEFGHJKLMNPQRSTUVWXYZ23456789"
+	linkCodeLength   = 8
+	deviceTokenScope = "sync"
+
+	ndjsonFlushInterval = 50
+	maxBlobSize         = 64 << 20
+
+	// Names the pushing machine, for the activity log
+	machineHeader     = "X-Kiwi-Machine"
+	maxMachineNameLen = 128
 )
 
 var (
 	limiter    = rate.NewLimiter(rate.Every(time.Second), 10)
 	emailRegex = regexp.MustCompile(`^[a-zA-Z0-9._%+-]+@[a-zA-Z0-9.-]+\.[a-zA-Z]{2,}$`)
+
+	linkCodes   = make(map[string]LinkCode)
+	linkCodesMu sync.Mutex
+
+	blobHashRegex = regexp.MustCompile(`^[0-9a-f]{64}$`)
+	commandRegex  = regexp.MustCompile(`^[a-z][a-z0-9-]*( [a-z][a-z0-9-]*)*$`)
+	telemetryMu   sync.Mutex
+	activityMu    sync.Mutex
 )
 
 func generateToken() (string, error) {
@@ -67,6 +165,17 @@ func generateToken() (string, error) {
 	return base64.URLEncoding.EncodeToString(b), nil
 }
 
+func generateLinkCode() (string, error) {
+	b := make([]byte, linkCodeLength)
+	if _, err := rand.Read(b); err != nil {
+		return "", err
+	}
+	for i := range b {
+		b[i] = linkCodeAlphabet[int(b[i])%len(linkCodeAlphabet)]
+	}
+	return string(b), nil
+}
+
 func getUserPath(email string) string {
 	hash := sha256.Sum256([]byte(email))
 	userHash := base64.URLEncoding.EncodeToString(hash[:])
@@ -99,6 +208,31 @@ func saveUser(user *User) error {
 	return os.WriteFile(getUserPath(user.Email), data, 0600)
 }
 
+// loadSyncData reads the account's last push, or an empty manifest before
+// the first one.
+func loadSyncData(userDataDir string) (*SyncData, error) {
+	data, err := os.ReadFile(filepath.Join(userDataDir, "sync_data.json"))
+	if os.IsNotExist(err) {
+		return &SyncData{Files: make(map[string]string), Packages: make([]Package, 0)}, nil
+	}
+	if err != nil {
+		return nil, err
+	}
+	var syncData SyncData
+	if err := json.Unmarshal(data, &syncData); err != nil {
+		return nil, err
+	}
+	return &syncData, nil
+}
+
+func storageLimit() *int64 {
+	limit, err := strconv.ParseInt(os.Getenv(storageLimitEnv), 10, 64)
+	if err != nil || limit <= 0 {
+		return nil
+	}
+	return &limit
+}
+
 func authMiddleware(next http.HandlerFunc) http.HandlerFunc {
 	return func(w http.ResponseWriter, r *http.Request) {
 		auth := r.Header.Get("Authorization")
@@ -127,6 +261,7 @@ func authMiddleware(next http.HandlerFunc) http.HandlerFunc {
 		}
 
 		var foundUser *User
+		scope := "full"
 		for _, file := range files {
 			if file.IsDir() {
 				continue
@@ -143,6 +278,16 @@ func authMiddleware(next http.HandlerFunc) http.HandlerFunc {
 				foundUser = &user
 				break
 			}
+			for _, dt := range user.DeviceTokens {
+				if dt.Token == auth {
+					foundUser = &user
+					scope = dt.Scope
+					break
+				}
+			}
+			if foundUser != nil {
+				break
+			}
 		}
 
 		if foundUser == nil {
@@ -151,6 +296,7 @@ func authMiddleware(next http.HandlerFunc) http.HandlerFunc {
 		}
 
 		r.Header.Set("X-User-Email", foundUser.Email)
+		r.Header.Set("X-Token-Scope", scope)
 		next.ServeHTTP(w, r)
 	}
 }
@@ -158,6 +304,8 @@ func authMiddleware(next http.HandlerFunc) http.HandlerFunc {
 func rateLimitMiddleware(next http.HandlerFunc) http.HandlerFunc {
 	return func(w http.ResponseWriter, r *http.Request) {
 		if !limiter.Allow() {
+			// The limiter refills one token per second
+			w.Header().Set("Retry-After", "1")
 			http.Error(w, "Too many requests", http.StatusTooManyRequests)
 			return
 		}
@@ -285,6 +433,523 @@ func handleLogin(w http.ResponseWriter, r *http.Request) {
 	json.NewEncoder(w).Encode(user)
 }
 
+func handleLinkCreate(w http.ResponseWriter, r *http.Request) {
+	if r.Method != http.MethodPost {
+		http.Error(w, "Method not allowed", http.StatusMethodNotAllowed)
+		return
+	}
+
+	userEmail := r.Header.Get("X-User-Email")
+	if userEmail == "" {
+		http.Error(w, "Link codes can only be created for user accounts", http.StatusBadRequest)
+		return
+	}
+
+	// Device tokens are scoped to sync and cannot mint further links
+	if r.Header.Get("X-Token-Scope") != "full" {
+		http.Error(w, "Forbidden - token scope does not allow linking", http.StatusForbidden)
+		return
+	}
+
+	code, err := generateLinkCode()
+	if err != nil {
+		http.Error(w, "Internal server error", http.StatusInternalServerError)
+		return
+	}
+
+	expiresAt := time.Now().Add(linkCodeTTL)
+
+	linkCodesMu.Lock()
+	for c, lc := range linkCodes {
+		if time.Now().After(lc.ExpiresAt) {
+			delete(linkCodes, c)
+		}
+	}
+	linkCodes[code] = LinkCode{Email: userEmail, ExpiresAt: expiresAt}
+	linkCodesMu.Unlock()
+
+	w.Header().Set("Content-Type", "application/json")
+	json.NewEncoder(w).Encode(map[string]string{
+		"code":       code,
+		"expires_at": expiresAt.Format(time.RFC3339),
+	})
+}
+
+func handleLinkClaim(w http.ResponseWriter, r *http.Request) {
+	if r.Method != http.MethodPost {
+		http.Error(w, "Method not allowed", http.StatusMethodNotAllowed)
+		return
+	}
+
+	var req LinkClaimRequest
+	if err := json.NewDecoder(r.Body).Decode(&req); err != nil {
+		http.Error(w, "Invalid request body", http.StatusBadRequest)
+		return
+	}
+
+	code := strings.ToUpper(strings.TrimSpace(req.Code))
+
+	// Codes are single-use: remove it whether or not it is still valid
+	linkCodesMu.Lock()
+	lc, ok := linkCodes[code]
+	delete(linkCodes, code)
+	linkCodesMu.Unlock()
+
+	if !ok || time.Now().After(lc.ExpiresAt) {
+		http.Error(w, "Invalid or expired link code", http.StatusUnauthorized)
+		return
+	}
+
+	user, err := loadUser(lc.Email)
+	if err != nil {
+		http.Error(w, "Invalid or expired link code", http.StatusUnauthorized)
+		return
+	}
+
+	token, err := generateToken()
+	if err != nil {
+		http.Error(w, "Internal server error", http.StatusInternalServerError)
+		return
+	}
+
+	user.DeviceTokens = append(user.DeviceTokens, DeviceToken{
+		Token:     token,
+		Scope:     deviceTokenScope,
+		CreatedAt: time.Now(),
+	})
+	if err := saveUser(user); err != nil {
+		http.Error(w, "Failed to update user", http.StatusInternalServerError)
+		return
+	}
+
+	w.Header().Set("Content-Type", "application/json")
+	json.NewEncoder(w).Encode(map[string]string{
+		"email": user.Email,
+		"token": token,
+	})
+}
+
+func handleAccountExport(w http.ResponseWriter, r *http.Request) {
+	if r.Method != http.MethodGet {
+		http.Error(w, "Method not allowed", http.StatusMethodNotAllowed)
+		return
+	}
+
+	userEmail := r.Header.Get("X-User-Email")
+	if userEmail == "" {
+		http.Error(w, "Account export is only available for user accounts", http.StatusBadRequest)
+		return
+	}
+
+	user, err := loadUser(userEmail)
+	if err != nil {
+		http.Error(w, "Failed to load user", http.StatusInternalServerError)
+		return
+	}
+
+	// Never export credentials, only account metadata
+	account, err := json.MarshalIndent(map[string]interface{}{
+		"email":          user.Email,
+		"created_at":     user.CreatedAt,
+		"linked_devices": len(user.DeviceTokens),
+	}, "", "  ")
+	if err != nil {
+		http.Error(w, "Failed to marshal account data", http.StatusInternalServerError)
+		return
+	}
+
+	// Check every file before writing anything, so a file that can't be read
+	// fails the export instead of leaving it quietly incomplete
+	userDataDir := getUserDataDir(userEmail)
+	var files, unreadable []string
+	walkErr := filepath.WalkDir(userDataDir, func(path string, d os.DirEntry, err error) error {
+		if err != nil {
+			if path == userDataDir && os.IsNotExist(err) {
+				return filepath.SkipAll
+			}
+			unreadable = append(unreadable, path)
+			return nil
+		}
+		if d.IsDir() {
+			return nil
+		}
+		f, err := os.Open(path)
+		if err != nil {
+			unreadable = append(unreadable, path)
+			return nil
+		}
+		f.Close()
+		files = append(files, path)
+		return nil
+	})
+	if walkErr != nil || len(unreadable) > 0 {
+		for i, path := range unreadable {
+			if rel, err := filepath.Rel(userDataDir, path); err == nil {
+				unreadable[i] = rel
+			}
+		}
+		log.Printf("Account export failed; unreadable: %v (%v)", unreadable, walkErr)
+		http.Error(w, "Failed to read account data: "+strings.Join(unreadable, ", "), http.StatusInternalServerError)
+		return
+	}
+
+	w.Header().Set("Content-Type", "application/zip")
+	w.Header().Set("Content-Disposition", `attachment; filename="kiwi-account-export.zip"`)
+
+	archive := zip.NewWriter(w)
+
+	if f, err := archive.Create("account.json"); err == nil {
+		f.Write(account)
+	}
+
+	for _, path := range files {
+		rel, err := filepath.Rel(userDataDir, path)
+		if err != nil {
+			panic(http.ErrAbortHandler)
+		}
+		if err := copyToArchive(archive, filepath.Join("data", rel), path); err != nil {
+			// The status is already sent; cut the response short so the
+			// client sees a broken download rather than a partial export
+			log.Printf("Account export aborted at %s: %v", rel, err)
+			panic(http.ErrAbortHandler)
+		}
+	}
+	archive.Close()
+}
+
+// copyToArchive adds the file at path to archive under name.
+func copyToArchive(archive *zip.Writer, name, path string) error {
+	src, err := os.Open(path)
+	if err != nil {
+		return err
+	}
+	defer src.Close()
+	dst, err := archive.Create(name)
+	if err != nil {
+		return err
+	}
+	_, err = io.Copy(dst, src)
+	return err
+}
+
+func handleAccountDelete(w http.ResponseWriter, r *http.Request) {
+	if r.Method != http.MethodDelete {
+		http.Error(w, "Method not allowed", http.StatusMethodNotAllowed)
+		return
+	}
+
+	userEmail := r.Header.Get("X-User-Email")
+	if userEmail == "" {
+		http.Error(w, "Account deletion is only available for user accounts", http.StatusBadRequest)
+		return
+	}
+
+	if r.Header.Get("X-Token-Scope") != "full" {
+		http.Error(w, "Forbidden - token scope does not allow account deletion", http.StatusForbidden)
+		return
+	}
+
+	if err := os.RemoveAll(getUserDataDir(userEmail)); err != nil {
+		http.Error(w, "Failed to delete user data", http.StatusInternalServerError)
+		return
+	}
+
+	if err := os.Remove(getUserPath(userEmail)); err != nil && !os.IsNotExist(err) {
+		http.Error(w, "Failed to delete user", http.StatusInternalServerError)
+		return
+	}
+
+	linkCodesMu.Lock()
+	for c, lc := range linkCodes {
+		if lc.Email == userEmail {
+			delete(linkCodes, c)
+		}
+	}
+	linkCodesMu.Unlock()
+
+	w.Header().Set("Content-Type", "application/json")
+	w.Write([]byte(`{"status": "deleted"}`))
+}
+
+// handleAccountUsage reports the bytes the account's data takes on disk,
+// its limit, and how much of that each synced file accounts for.
+func handleAccountUsage(w http.ResponseWriter, r *http.Request) {
+	if r.Method != http.MethodGet {
+		http.Error(w, "Method not allowed", http.StatusMethodNotAllowed)
+		return
+	}
+
+	userDataDir := getUserDataDir(r.Header.Get("X-User-Email"))
+	usage := Usage{Limit: storageLimit(), Files: make(map[string]int64)}
+	err := filepath.WalkDir(userDataDir, func(path string, d os.DirEntry, err error) error {
+		if err != nil {
+			if path == userDataDir && os.IsNotExist(err) {
+				return filepath.SkipAll
+			}
+			return err
+		}
+		if d.IsDir() {
+			return nil
+		}
+		info, err := d.Info()
+		if err != nil {
+			return err
+		}
+		usage.Used += info.Size()
+		return nil
+	})
+	if err != nil {
+		http.Error(w, "Failed to measure storage", http.StatusInternalServerError)
+		return
+	}
+
+	syncData, err := loadSyncData(userDataDir)
+	if err != nil {
+		http.Error(w, "Failed to read sync data", http.StatusInternalServerError)
+		return
+	}
+	for name, content := range syncData.Files {
+		usage.Files[name] = int64(len(content))
+	}
+	for name, hash := range syncData.Blobs {
+		if info, err := os.Stat(filepath.Join(userDataDir, "blobs", hash)); err == nil {
+			usage.Files[name] = info.Size()
+		}
+	}
+
+	w.Header().Set("Content-Type", "application/json")
+	json.NewEncoder(w).Encode(usage)
+}
+
+// fileHashes maps each synced file to the hash of its contents, however it
+// was stored.
+func fileHashes(data *SyncData) map[string]string {
+	hashes := make(map[string]string)
+	for name, content := range data.Files {
+		sum := sha256.Sum256([]byte(content))
+		hashes[name] = hex.EncodeToString(sum[:])
+	}
+	for name, hash := range data.Blobs {
+		hashes[name] = hash
+	}
+	for name, pointer := range data.Pointers {
+		hashes[name] = pointer.Hash
+	}
+	return hashes
+}
+
+// pushActivity describes what a push changed compared with the state before it.
+func pushActivity(machine string, before, after *SyncData) Activity {
+	oldHashes, newHashes := fileHashes(before), fileHashes(after)
+	files := make([]string, 0)
+	for name, hash := range newHashes {
+		if oldHashes[name] != hash {
+			files = append(files, name)
+		}
+	}
+	for name := range oldHashes {
+		if _, ok := newHashes[name]; !ok {
+			files = append(files, name)
+		}
+	}
+	sort.Strings(files)
+
+	names := func(data *SyncData) map[string]bool {
+		set := make(map[string]bool)
+		for _, pkg := range data.Packages {
+			set[pkg.Name] = true
+		}
+		return set
+	}
+	oldPackages, newPackages := names(before), names(after)
+	added, removed := make([]string, 0), make([]string, 0)
+	for name := range newPackages {
+		if !oldPackages[name] {
+			added = append(added, name)
+		}
+	}
+	for name := range oldPackages {
+		if !newPackages[name] {
+			removed = append(removed, name)
+		}
+	}
+	sort.Strings(added)
+	sort.Strings(removed)
+
+	if len(machine) > maxMachineNameLen {
+		machine = machine[:maxMachineNameLen]
+	}
+	return Activity{
+		At:              time.Now().UTC().Format(time.RFC3339),
+		Machine:         machine,
+		Files:           files,
+		PackagesAdded:   added,
+		PackagesRemoved: removed,
+	}
+}
+
+// recordActivity appends one entry to the account's activity log.
+func recordActivity(userDataDir string, entry Activity) error {
+	line, err := json.Marshal(entry)
+	if err != nil {
+		return err
+	}
+	activityMu.Lock()
+	defer activityMu.Unlock()
+	f, err := os.OpenFile(filepath.Join(userDataDir, "activity.jsonl"), os.O_APPEND|os.O_CREATE|os.O_WRONLY, 0600)
+	if err != nil {
+		return err
+	}
+	defer f.Close()
+	_, err = f.Write(append(line, '\n'))
+	return err
+}
+
+// handleActivity lists every push logged for the account, oldest first.
+func handleActivity(w http.ResponseWriter, r *http.Request) {
+	if r.Method != http.MethodGet {
+		http.Error(w, "Method not allowed", http.StatusMethodNotAllowed)
+		return
+	}
+
+	data, err := os.ReadFile(filepath.Join(getUserDataDir(r.Header.Get("X-User-Email")), "activity.jsonl"))
+	if err != nil && !os.IsNotExist(err) {
+		http.Error(w, "Failed to read activity", http.StatusInternalServerError)
+		return
+	}
+	entries := make([]Activity, 0)
+	for _, line := range strings.Split(string(data), "\n") {
+		var entry Activity
+		if strings.TrimSpace(line) == "" || json.Unmarshal([]byte(line), &entry) != nil {
+			continue
+		}
+		entries = append(entries, entry)
+	}
+
+	w.Header().Set("Content-Type", "application/json")
+	json.NewEncoder(w).Encode(entries)
+}
+
+// streamSyncData writes the manifest as one JSON object per line so clients
+// can apply entries as they arrive instead of buffering the whole body.
+func streamSyncData(w http.ResponseWriter, syncData SyncData) {
+	w.Header().Set("Content-Type", "application/x-ndjson")
+	flusher, _ := w.(http.Flusher)
+	encoder := json.NewEncoder(w)
+
+	written := 0
+	emit := func(entry map[string]interface{}) {
+		encoder.Encode(entry)
+		written++
+		if flusher != nil && written%ndjsonFlushInterval == 0 {
+			flusher.Flush()
+		}
+	}
+
+	for _, tap := range syncData.Taps {
+		emit(map[string]interface{}{"type": "tap", "name": tap})
+	}
+	for _, pkg := range syncData.Packages {
+		emit(map[string]interface{}{"type": "package", "package": pkg})
+	}
+	for path, content := range syncData.Files {
+		emit(map[string]interface{}{"type": "file", "path": path, "content": content})
+	}
+	for path, hash := range syncData.Blobs {
+		emit(map[string]interface{}{"type": "blob", "path": path, "hash": hash})
+	}
+	for path, pointer := range syncData.Pointers {
+		emit(map[string]interface{}{"type": "pointer", "path": path, "pointer": pointer})
+	}
+	if syncData.Brew != nil {
+		emit(map[string]interface{}{"type": "brew", "environment": syncData.Brew})
+	}
+}
+
+// handleTemplates serves the shared template registry (index.json and the
+// files it lists) from templatesDir. It is public and read-only.
+func handleTemplates(w http.ResponseWriter, r *http.Request) {
+	if r.Method != http.MethodGet && r.Method != http.MethodHead {
+		http.Error(w, "Method not allowed", http.StatusMethodNotAllowed)
+		return
+	}
+	if strings.HasSuffix(r.URL.Path, "/") {
+		http.NotFound(w, r)
+		return
+	}
+	http.StripPrefix("/templates/", http.FileServer(http.Dir(templatesDir))).ServeHTTP(w, r)
+}
+
+// handleBlobsMissing reports which of the given content hashes the user's
+// blob store doesn't have yet, so clients only upload new content.
+func handleBlobsMissing(w http.ResponseWriter, r *http.Request) {
+	if r.Method != http.MethodPost {
+		http.Error(w, "Method not allowed", http.StatusMethodNotAllowed)
+		return
+	}
+
+	var hashes []string
+	if err := json.NewDecoder(r.Body).Decode(&hashes); err != nil {
+		http.Error(w, "Invalid request body", http.StatusBadRequest)
+		return
+	}
+
+	blobDir := filepath.Join(getUserDataDir(r.Header.Get("X-User-Email")), "blobs")
+	missing := make([]string, 0)
+	for _, hash := range hashes {
+		if !blobHashRegex.MatchString(hash) {
+			http.Error(w, "Invalid blob hash", http.StatusBadRequest)
+			return
+		}
+		if _, err := os.Stat(filepath.Join(blobDir, hash)); os.IsNotExist(err) {
+			missing = append(missing, hash)
+		}
+	}
+
+	w.Header().Set("Content-Type", "application/json")
+	json.NewEncoder(w).Encode(missing)
+}
+
+// handleBlob stores and serves file contents addressed by their SHA-256.
+func handleBlob(w http.ResponseWriter, r *http.Request) {
+	hash := strings.TrimPrefix(r.URL.Path, "/blobs/")
+	if !blobHashRegex.MatchString(hash) {
+		http.Error(w, "Invalid blob hash", http.StatusBadRequest)
+		return
+	}
+	blobDir := filepath.Join(getUserDataDir(r.Header.Get("X-User-Email")), "blobs")
+	blobPath := filepath.Join(blobDir, hash)
+
+	switch r.Method {
+	case http.MethodGet:
+		http.ServeFile(w, r, blobPath)
+
+	case http.MethodPut:
+		data, err := io.ReadAll(http.MaxBytesReader(w, r.Body, maxBlobSize))
+		if err != nil {
+			http.Error(w, "Invalid request body", http.StatusBadRequest)
+			return
+		}
+		sum := sha256.Sum256(data)
+		if hex.EncodeToString(sum[:]) != hash {
+			http.Error(w, "Content does not match hash", http.StatusBadRequest)
+			return
+		}
+		if err := os.MkdirAll(blobDir, 0755); err != nil {
+			http.Error(w, "Failed to create blob directory", http.StatusInternalServerError)
+			return
+		}
+		if err := os.WriteFile(blobPath, data, 0644); err != nil {
+			http.Error(w, "Failed to store blob", http.StatusInternalServerError)
+			return
+		}
+		w.WriteHeader(http.StatusCreated)
+
+	default:
+		http.Error(w, "Method not allowed", http.StatusMethodNotAllowed)
+	}
+}
+
 func handleSync(w http.ResponseWriter, r *http.Request) {
 	userEmail := r.Header.Get("X-User-Email")
 	if userEmail == "" && r.Header.Get("X-User-Role") != "admin" {
@@ -309,6 +974,15 @@ func handleSync(w http.ResponseWriter, r *http.Request) {
 			http.Error(w, "Failed to read sync data", http.StatusInternalServerError)
 			return
 		}
+		if strings.Contains(r.Header.Get("Accept"), "application/x-ndjson") {
+			var syncData SyncData
+			if err := json.Unmarshal(data, &syncData); err != nil {
+				http.Error(w, "Failed to read sync data", http.StatusInternalServerError)
+				return
+			}
+			streamSyncData(w, syncData)
+			return
+		}
 		w.Header().Set("Content-Type", "application/json")
 		w.Write(data)
 
@@ -323,6 +997,11 @@ func handleSync(w http.ResponseWriter, r *http.Request) {
 			http.Error(w, "Failed to create user directory", http.StatusInternalServerError)
 			return
 		}
+		previous, err := loadSyncData(userDataDir)
+		if err != nil {
+			http.Error(w, "Failed to read sync data", http.StatusInternalServerError)
+			return
+		}
 
 		data, err := json.MarshalIndent(syncData, "", "  ")
 		if err != nil {
@@ -334,6 +1013,10 @@ func handleSync(w http.ResponseWriter, r *http.Request) {
 			http.Error(w, "Failed to save sync data", http.StatusInternalServerError)
 			return
 		}
+		// The push itself succeeded; a gap in the log isn't worth failing it
+		if err := recordActivity(userDataDir, pushActivity(r.Header.Get(machineHeader), previous, &syncData)); err != nil {
+			log.Printf("Failed to record activity: %v", err)
+		}
 
 		w.WriteHeader(http.StatusOK)
 		w.Write([]byte(`{"status": "ok"}`))
@@ -343,6 +1026,46 @@ func handleSync(w http.ResponseWriter, r *http.Request) {
 	}
 }
 
+func handleTelemetry(w http.ResponseWriter, r *http.Request) {
+	if r.Method != http.MethodPost {
+		http.Error(w, "Method not allowed", http.StatusMethodNotAllowed)
+		return
+	}
+
+	var event TelemetryEvent
+	if err := json.NewDecoder(http.MaxBytesReader(w, r.Body, 1024)).Decode(&event); err != nil {
+		http.Error(w, "Invalid request body", http.StatusBadRequest)
+		return
+	}
+	if len(event.Command) > 64 || !commandRegex.MatchString(event.Command) || event.DurationMs < 0 {
+		http.Error(w, "Invalid telemetry event", http.StatusBadRequest)
+		return
+	}
+
+	// Store by day only; request metadata such as the client address is not kept
+	line, err := json.Marshal(event)
+	if err != nil {
+		http.Error(w, "Internal server error", http.StatusInternalServerError)
+		return
+	}
+	path := filepath.Join(telemetryDir, time.Now().UTC().Format("2006-01-02")+".jsonl")
+
+	telemetryMu.Lock()
+	defer telemetryMu.Unlock()
+	f, err := os.OpenFile(path, os.O_APPEND|os.O_CREATE|os.O_WRONLY, 0600)
+	if err != nil {
+		http.Error(w, "Failed to record event", http.StatusInternalServerError)
+		return
+	}
+	defer f.Close()
+	if _, err := f.Write(append(line, '\n')); err != nil {
+		http.Error(w, "Failed to record event", http.StatusInternalServerError)
+		return
+	}
+
+	w.WriteHeader(http.StatusNoContent)
+}
+
 func main() {
 	// Load .env file
 	if err := godotenv.Load(); err != nil {
@@ -350,7 +1073,7 @@ func main() {
 	}
 
 	// Ensure directories exist with proper permissions
-	for _, dir := range []string{dataDir, usersDir} {
+	for _, dir := range []string{dataDir, usersDir, telemetryDir} {
 		if err := os.MkdirAll(dir, 0755); err != nil {
 			log.Fatal("Failed to create directory:", err)
 		}
@@ -370,13 +1093,23 @@ func main() {
 
 	mux.HandleFunc("/health", func(w http.ResponseWriter, r *http.Request) {
 		w.Header().Set("Content-Type", "application/json")
-		json.NewEncoder(w).Encode(map[string]string{"status": "OK"})
+		json.NewEncoder(w).Encode(map[string]string{"status": "OK", "version": version})
 	})
 
 	// Apply middleware chain
 	mux.HandleFunc("/register", secureHeaders(rateLimitMiddleware(handleRegister)))
 	mux.HandleFunc("/login", secureHeaders(rateLimitMiddleware(handleLogin)))
 	mux.HandleFunc("/sync", secureHeaders(rateLimitMiddleware(authMiddleware(handleSync))))
+	mux.HandleFunc("/link", secureHeaders(rateLimitMiddleware(authMiddleware(handleLinkCreate))))
+	mux.HandleFunc("/link/claim", secureHeaders(rateLimitMiddleware(handleLinkClaim)))
+	mux.HandleFunc("/account/export", secureHeaders(rateLimitMiddleware(authMiddleware(handleAccountExport))))
+	mux.HandleFunc("/account/usage", secureHeaders(rateLimitMiddleware(authMiddleware(handleAccountUsage))))
+	mux.HandleFunc("/activity", secureHeaders(rateLimitMiddleware(authMiddleware(handleActivity))))
+	mux.HandleFunc("/account", secureHeaders(rateLimitMiddleware(authMiddleware(handleAccountDelete))))
+	mux.HandleFunc("/blobs/missing", secureHeaders(rateLimitMiddleware(authMiddleware(handleBlobsMissing))))
+	mux.HandleFunc("/blobs/", secureHeaders(rateLimitMiddleware(authMiddleware(handleBlob))))
+	mux.HandleFunc("/telemetry", secureHeaders(rateLimitMiddleware(handleTelemetry)))
+	mux.HandleFunc("/templates/", secureHeaders(rateLimitMiddleware(handleTemplates)))
 
 	port := os.Getenv("PORT")
 	if port == "" {
@@ -0,0 +1,173 @@
+//! The launchd agent that runs `kiwi watch` from login, so tracked dotfiles
+//! keep syncing without a terminal open.
+
+use crate::homebrew::{CommandRunner, SystemRunner};
+use crate::{KiwiError, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const LABEL: &str = "dev.kiwi.watch";
+
+pub struct Agent {
+    runner: Box<dyn CommandRunner>,
+    plist: PathBuf,
+}
+
+impl Default for Agent {
+    fn default() -> Self {
+        let home = dirs::home_dir().unwrap_or_default();
+        Self {
+            runner: Box::new(SystemRunner),
+            plist: home.join("Library/LaunchAgents").join(format!("{}.plist", LABEL)),
+        }
+    }
+}
+
+impl Agent {
+    /// An agent whose plist lives at `plist`, managed through `runner`.
+    pub fn with_runner(runner: impl CommandRunner + 'static, plist: PathBuf) -> Self {
+        Self { runner: Box::new(runner), plist }
+    }
+
+    pub fn plist_path(&self) -> &Path {
+        &self.plist
+    }
+
+    pub fn is_installed(&self) -> bool {
+        self.plist.exists()
+    }
+
+    /// Writes the plist for `program` and loads it, replacing any earlier
+    /// version. Output goes to `log`.
+    pub fn install(&self, program: &Path, log: &Path) -> Result<()> {
+        if let Some(parent) = self.plist.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        if let Some(parent) = log.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&self.plist, render_plist(program, log))?;
+
+        // A plist launchd refused would look installed, so it goes too
+        let loaded = self.load();
+        if loaded.is_err() {
+            let _ = fs::remove_file(&self.plist);
+        }
+        loaded
+    }
+
+    fn load(&self) -> Result<()> {
+        load(self.runner.as_ref(), &self.plist, LABEL)
+    }
+
+    /// Unloads the agent and deletes its plist. Returns whether it was installed.
+    pub fn uninstall(&self) -> Result<bool> {
+        if !self.is_installed() {
+            return Ok(false);
+        }
+        let domain = user_domain(self.runner.as_ref())?;
+        let _ = self.runner.run("launchctl", &["bootout", &format!("{}/{}", domain, LABEL)]);
+        fs::remove_file(&self.plist)?;
+        Ok(true)
+    }
+}
+
+/// Loads the job in `plist` into the user's launchd domain, replacing a
+/// running copy of `label`.
+pub fn load(runner: &dyn CommandRunner, plist: &Path, label: &str) -> Result<()> {
+    let domain = user_domain(runner)?;
+    // Loading over a running copy fails, so unload it first
+    let _ = runner.run("launchctl", &["bootout", &format!("{}/{}", domain, label)]);
+    let output = runner.run("launchctl", &["bootstrap", &domain, &plist.to_string_lossy()])?;
+    if !output.success {
+        return Err(KiwiError::Config(format!(
+            "launchctl couldn't load {}: {}",
+            plist.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+/// The launchd domain for the logged-in user, e.g. `gui/501`.
+fn user_domain(runner: &dyn CommandRunner) -> Result<String> {
+    let output = runner.run("id", &["-u"])?;
+    let uid = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if !output.success || uid.is_empty() {
+        return Err(KiwiError::Config("Couldn't determine the user id for launchd".to_string()));
+    }
+    Ok(format!("gui/{}", uid))
+}
+
+fn render_plist(program: &Path, log: &Path) -> String {
+    let escape = |path: &Path| path.display().to_string().replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;");
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>{label}</string>
+    <key>ProgramArguments</key>
+    <array>
+        <string>{program}</string>
+        <string>watch</string>
+    </array>
+    <key>RunAtLoad</key>
+    <true/>
+    <key>KeepAlive</key>
+    <dict>
+        <key>SuccessfulExit</key>
+        <false/>
+    </dict>
+    <key>StandardOutPath</key>
+    <string>{log}</string>
+    <key>StandardErrorPath</key>
+    <string>{log}</string>
+</dict>
+</plist>
+"#,
+        label = LABEL,
+        program = escape(program),
+        log = escape(log),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::homebrew::CommandOutput;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Launchctl {
+        calls: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl CommandRunner for Launchctl {
+        fn run(&self, program: &str, args: &[&str]) -> std::io::Result<CommandOutput> {
+            self.calls.lock().unwrap().push(format!("{} {}", program, args.join(" ")));
+            let stdout = if program == "id" { b"501\n".to_vec() } else { Vec::new() };
+            Ok(CommandOutput { success: true, stdout, stderr: Vec::new() })
+        }
+    }
+
+    #[test]
+    fn test_install_and_uninstall() {
+        let dir = std::env::temp_dir().join(format!("kiwi-agent-test-{}", std::process::id()));
+        let plist = dir.join("LaunchAgents").join(format!("{}.plist", LABEL));
+        let launchctl = Launchctl::default();
+        let agent = Agent::with_runner(launchctl.clone(), plist.clone());
+
+        agent.install(Path::new("/opt/homebrew/bin/kiwi"), &dir.join("logs/watch.log")).unwrap();
+        let written = fs::read_to_string(&plist).unwrap();
+        assert!(written.contains("<string>/opt/homebrew/bin/kiwi</string>"));
+        assert!(launchctl.calls.lock().unwrap().contains(&format!("launchctl bootstrap gui/501 {}", plist.display())));
+
+        assert!(agent.uninstall().unwrap());
+        assert!(!plist.exists());
+        assert!(!agent.uninstall().unwrap());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
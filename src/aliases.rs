@@ -0,0 +1,182 @@
+//! Shell aliases and functions kept in a kiwi-owned fragment that the rc file
+//! sources. The fragment is tracked like any dotfile, so it syncs too.
+
+use crate::{shell, Dotfiles, KiwiError, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Name of the rc block that sources the fragment.
+pub const RC_BLOCK: &str = "aliases";
+/// Name of the fragment inside the dotfiles dir.
+const TRACKED_NAME: &str = "kiwi-aliases.sh";
+const HEADER: &str = "# Managed by kiwi; edit with `kiwi alias add/remove`.\n";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// `alias name='command'`
+    Alias,
+    /// `name() { command; }`, for things aliases can't do like using arguments
+    Function,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Alias {
+    pub name: String,
+    pub command: String,
+    pub kind: Kind,
+}
+
+impl Alias {
+    fn to_line(&self) -> String {
+        match self.kind {
+            Kind::Alias => format!("alias {}='{}'", self.name, self.command.replace('\'', r"'\''")),
+            Kind::Function => format!("{}() {{ {}; }}", self.name, self.command.trim_end_matches(';')),
+        }
+    }
+
+    fn parse(line: &str) -> Option<Self> {
+        if let Some(rest) = line.strip_prefix("alias ") {
+            let (name, value) = rest.split_once('=')?;
+            let command = value.strip_prefix('\'')?.strip_suffix('\'')?.replace(r"'\''", "'");
+            return Some(Self { name: name.to_string(), command, kind: Kind::Alias });
+        }
+
+        let (name, rest) = line.split_once("() { ")?;
+        let command = rest.strip_suffix("; }")?.to_string();
+        Some(Self { name: name.to_string(), command, kind: Kind::Function })
+    }
+}
+
+pub struct Aliases {
+    path: PathBuf,
+}
+
+impl Aliases {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// `~/.config/kiwi/aliases.sh`
+    pub fn default_path() -> Result<PathBuf> {
+        let home = dirs::home_dir().ok_or_else(|| KiwiError::Config("Could not find home directory".to_string()))?;
+        Ok(home.join(".config/kiwi/aliases.sh"))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn list(&self) -> Result<Vec<Alias>> {
+        let Ok(contents) = fs::read_to_string(&self.path) else {
+            return Ok(Vec::new());
+        };
+        Ok(contents.lines().filter_map(Alias::parse).collect())
+    }
+
+    /// Adds or replaces an alias. Returns true when one with the same name was replaced.
+    pub fn add(&self, alias: Alias) -> Result<bool> {
+        validate_name(&alias.name)?;
+        if alias.command.trim().is_empty() || alias.command.contains('\n') {
+            return Err(KiwiError::ValidationError("Alias command must be a single non-empty line".to_string()));
+        }
+
+        let mut aliases = self.list()?;
+        let replaced = match aliases.iter_mut().find(|a| a.name == alias.name) {
+            Some(existing) => {
+                *existing = alias;
+                true
+            }
+            None => {
+                aliases.push(alias);
+                false
+            }
+        };
+        self.save(&aliases)?;
+        Ok(replaced)
+    }
+
+    /// Returns whether an alias was removed.
+    pub fn remove(&self, name: &str) -> Result<bool> {
+        let mut aliases = self.list()?;
+        let before = aliases.len();
+        aliases.retain(|a| a.name != name);
+        if aliases.len() == before {
+            return Ok(false);
+        }
+        self.save(&aliases)?;
+        Ok(true)
+    }
+
+    /// Makes sure the fragment is tracked for sync and sourced from the rc
+    /// file of `shell`. Returns the rc file when it had to be changed.
+    pub fn install(&self, dotfiles: &Dotfiles, shell: &str) -> Result<Option<PathBuf>> {
+        if !self.path.exists() {
+            self.save(&[])?;
+        }
+        let canonical = self.path.canonicalize()?;
+        if !dotfiles.list()?.iter().any(|d| d.path == canonical) {
+            dotfiles.add(&self.path, Some(TRACKED_NAME.to_string()), false)?;
+        }
+
+        if !matches!(shell, "zsh" | "bash") {
+            return Err(KiwiError::ValidationError(format!("Aliases aren't supported for {} yet; use zsh or bash", shell)));
+        }
+        let rc = shell::rc_file(shell)?;
+        let source = format!("[ -f \"{0}\" ] && . \"{0}\"", self.path.display());
+        Ok(shell::ensure_block(&rc, RC_BLOCK, &source)?.then_some(rc))
+    }
+
+    fn save(&self, aliases: &[Alias]) -> Result<()> {
+        let mut contents = HEADER.to_string();
+        for alias in aliases {
+            contents.push_str(&alias.to_line());
+            contents.push('\n');
+        }
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&self.path, contents)?;
+        Ok(())
+    }
+}
+
+fn validate_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && !name.starts_with('-')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'));
+    if valid {
+        Ok(())
+    } else {
+        Err(KiwiError::ValidationError(format!("Invalid alias name: {}", name)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_replace_and_remove_round_trip() {
+        let dir = std::env::temp_dir().join(format!("kiwi-aliases-test-{}", std::process::id()));
+        let aliases = Aliases::new(dir.join("aliases.sh"));
+
+        let gs = Alias { name: "gs".to_string(), command: "git status -sb".to_string(), kind: Kind::Alias };
+        let quoted = Alias { name: "say".to_string(), command: "echo 'hi there'".to_string(), kind: Kind::Alias };
+        let mkcd = Alias { name: "mkcd".to_string(), command: "mkdir -p \"$1\" && cd \"$1\"".to_string(), kind: Kind::Function };
+        assert!(!aliases.add(gs.clone()).unwrap());
+        assert!(!aliases.add(quoted.clone()).unwrap());
+        assert!(!aliases.add(mkcd.clone()).unwrap());
+        assert_eq!(aliases.list().unwrap(), vec![gs, quoted, mkcd]);
+
+        let updated = Alias { name: "gs".to_string(), command: "git status".to_string(), kind: Kind::Alias };
+        assert!(aliases.add(updated.clone()).unwrap());
+        assert!(aliases.remove("say").unwrap());
+        assert!(!aliases.remove("say").unwrap());
+        assert_eq!(aliases.list().unwrap()[0], updated);
+        assert_eq!(aliases.list().unwrap().len(), 2);
+
+        assert!(aliases.add(Alias { name: "rm -rf".to_string(), command: "x".to_string(), kind: Kind::Alias }).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,144 @@
+//! Conventions shared by every call to the kiwi server. Each request carries
+//! a fresh ID in `X-Request-Id`, and a failed one is reported with the
+//! server's own explanation and that ID, so support can find it in the
+//! server's logs.
+
+use crate::Result;
+use reqwest::{RequestBuilder, Response};
+use serde::Deserialize;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+/// Names the machine a push comes from, for the server's activity log
+pub const MACHINE_HEADER: &str = "X-Kiwi-Machine";
+
+/// The ID a response's request was sent with, kept in its extensions.
+#[derive(Debug, Clone)]
+pub struct RequestId(pub String);
+
+/// A random 128-bit ID, hex encoded.
+pub fn new_request_id() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let half = || {
+        // Each RandomState is freshly seeded, so this is random enough to
+        // tell requests apart without another dependency
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(count);
+        hasher.finish()
+    };
+    format!("{:016x}{:016x}", half(), half())
+}
+
+/// Sends `request` under `id`, remembering the ID on the response.
+pub async fn send_with_id(request: RequestBuilder, id: &str) -> Result<Response> {
+    match request.header(REQUEST_ID_HEADER, id).send().await {
+        Ok(mut response) => {
+            response.extensions_mut().insert(RequestId(id.to_string()));
+            Ok(response)
+        }
+        Err(e) => {
+            log::warn!("Request {} failed: {}", id, e);
+            Err(e.into())
+        }
+    }
+}
+
+/// Sends `request` under a fresh ID.
+pub async fn send(request: RequestBuilder) -> Result<Response> {
+    send_with_id(request, &new_request_id()).await
+}
+
+/// The request ID for `response`: the one the server echoed, else the one
+/// it was sent with.
+pub fn request_id(response: &Response) -> Option<String> {
+    response
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+        .or_else(|| response.extensions().get::<RequestId>().map(|id| id.0.clone()))
+}
+
+/// Error bodies as the server sends them: `{"error": "..."}`,
+/// `{"error": {"code": "...", "message": "..."}}`, or `{"message": "..."}`.
+#[derive(Deserialize)]
+struct ErrorBody {
+    #[serde(default)]
+    error: Option<ErrorDetail>,
+    #[serde(default)]
+    message: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ErrorDetail {
+    Text(String),
+    Structured {
+        #[serde(default)]
+        code: Option<String>,
+        #[serde(default)]
+        message: Option<String>,
+    },
+}
+
+/// What the server said went wrong, from a JSON error body or a short
+/// plain-text one.
+fn detail(body: &str) -> Option<String> {
+    let body = body.trim();
+    if let Ok(parsed) = serde_json::from_str::<ErrorBody>(body) {
+        let detail = match parsed.error {
+            Some(ErrorDetail::Text(text)) => Some(text),
+            Some(ErrorDetail::Structured { code, message }) => match (code, message) {
+                (Some(code), Some(message)) => Some(format!("{} ({})", message, code)),
+                (code, message) => message.or(code),
+            },
+            None => None,
+        };
+        return detail.or(parsed.message).filter(|detail| !detail.is_empty());
+    }
+    // Anything longer is likely an HTML error page
+    (!body.is_empty() && body.len() <= 200 && !body.starts_with('<')).then(|| body.to_string())
+}
+
+/// Describes a failed response as `action: status - detail (request ID ...)`
+/// and logs it.
+pub async fn failure(response: Response, action: &str) -> String {
+    let status = response.status();
+    let id = request_id(&response);
+    let detail = response.text().await.ok().as_deref().and_then(detail);
+
+    let mut message = format!("{}: {}", action, status);
+    if let Some(detail) = detail {
+        message.push_str(&format!(" - {}", detail));
+    }
+    if let Some(id) = id {
+        message.push_str(&format!(" (request ID {})", id));
+    }
+    log::warn!("{}", message);
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_details_and_request_ids() {
+        assert_eq!(detail(r#"{"error":"invalid token"}"#).as_deref(), Some("invalid token"));
+        assert_eq!(
+            detail(r#"{"error":{"code":"quota_exceeded","message":"Storage is full"}}"#).as_deref(),
+            Some("Storage is full (quota_exceeded)")
+        );
+        assert_eq!(detail(r#"{"message":"Try again later"}"#).as_deref(), Some("Try again later"));
+        assert_eq!(detail("Bad Gateway\n").as_deref(), Some("Bad Gateway"));
+        assert_eq!(detail("<html><body>502</body></html>"), None);
+        assert_eq!(detail(""), None);
+
+        let (a, b) = (new_request_id(), new_request_id());
+        assert_eq!(a.len(), 32);
+        assert_ne!(a, b);
+    }
+}
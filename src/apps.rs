@@ -0,0 +1,355 @@
+//! Settings of apps that keep them outside the usual dotfiles: in macOS
+//! preferences, or in files and bundles the app owns. `kiwi apps capture`
+//! exports an app's settings to a kiwi-owned file that is tracked like any
+//! dotfile, so it syncs, push refreshes the export, and `kiwi apps apply`
+//! puts it back on the other Mac.
+
+use crate::backups::Backups;
+use crate::homebrew::{CommandRunner, SystemRunner};
+use crate::{Dotfiles, KiwiError, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Where an app keeps the settings kiwi exports.
+pub enum Source {
+    /// A defaults domain, exported as a plist
+    Defaults(&'static str),
+    /// A file under the home directory, copied as is
+    File(&'static str),
+    /// A directory or bundle under the home directory, exported as a zip
+    Bundle(&'static str),
+}
+
+pub struct App {
+    /// What `kiwi apps` calls it, e.g. `iterm2`
+    pub name: &'static str,
+    pub title: &'static str,
+    pub source: Source,
+    /// Its process name, when it writes its settings back on quit and so
+    /// must not be running while they're applied
+    pub process: Option<&'static str>,
+    /// A shell command that makes the running app pick up applied settings
+    pub reload: Option<&'static str>,
+    /// The Homebrew cask that installs it; a restore that installs the cask
+    /// applies its captured settings
+    pub cask: Option<&'static str>,
+}
+
+pub const APPS: &[App] = &[
+    App {
+        name: "iterm2",
+        title: "iTerm2",
+        source: Source::Defaults("com.googlecode.iterm2"),
+        process: Some("iTerm2"),
+        reload: None,
+        cask: Some("iterm2"),
+    },
+    App {
+        name: "terminal",
+        title: "Terminal",
+        source: Source::Defaults("com.apple.Terminal"),
+        process: Some("Terminal"),
+        reload: None,
+        cask: None,
+    },
+    // Extensions, snippets, and quicklinks are in Raycast's encrypted
+    // database, which only its own export reads; this is the hotkey and
+    // general settings
+    App {
+        name: "raycast",
+        title: "Raycast",
+        source: Source::Defaults("com.raycast.macos"),
+        process: Some("Raycast"),
+        reload: None,
+        cask: Some("raycast"),
+    },
+    App {
+        name: "alfred",
+        title: "Alfred",
+        source: Source::Bundle("Library/Application Support/Alfred/Alfred.alfredpreferences"),
+        process: Some("Alfred"),
+        reload: None,
+        cask: Some("alfred"),
+    },
+    // Karabiner watches its config, but a restart also picks up a file
+    // that replaced it rather than changing it in place
+    App {
+        name: "karabiner",
+        title: "Karabiner-Elements",
+        source: Source::File(".config/karabiner/karabiner.json"),
+        process: None,
+        reload: Some("launchctl kickstart -k gui/$(id -u)/org.pqrs.karabiner.karabiner_console_user_server"),
+        cask: Some("karabiner-elements"),
+    },
+    App {
+        name: "rectangle",
+        title: "Rectangle",
+        source: Source::Defaults("com.knollsoft.Rectangle"),
+        process: Some("Rectangle"),
+        reload: None,
+        cask: Some("rectangle"),
+    },
+];
+
+pub fn find(name: &str) -> Result<&'static App> {
+    APPS.iter().find(|app| app.name == name).ok_or_else(|| {
+        let known: Vec<&str> = APPS.iter().map(|app| app.name).collect();
+        KiwiError::ValidationError(format!("Unknown app: {} (kiwi knows {})", name, known.join(", ")))
+    })
+}
+
+/// The app the cask `cask` installs, if kiwi knows its settings.
+pub fn for_cask(cask: &str) -> Option<&'static App> {
+    APPS.iter().find(|app| app.cask == Some(cask))
+}
+
+pub struct Apps {
+    runner: Box<dyn CommandRunner>,
+    dir: PathBuf,
+    home: PathBuf,
+}
+
+impl Apps {
+    /// Apps under `home` whose exports live in `dir`.
+    pub fn new(dir: PathBuf, home: PathBuf) -> Self {
+        Self { runner: Box::new(SystemRunner), dir, home }
+    }
+
+    pub fn with_runner(mut self, runner: impl CommandRunner + 'static) -> Self {
+        self.runner = Box::new(runner);
+        self
+    }
+
+    /// `~/.config/kiwi/apps`
+    pub fn default_dir() -> Result<PathBuf> {
+        let home = dirs::home_dir().ok_or_else(|| KiwiError::Config("Could not find home directory".to_string()))?;
+        Ok(home.join(".config/kiwi/apps"))
+    }
+
+    /// Where `app`'s settings are exported to.
+    pub fn export_path(&self, app: &App) -> PathBuf {
+        let extension = match app.source {
+            Source::Defaults(_) => "plist",
+            Source::File(path) => Path::new(path).extension().and_then(|e| e.to_str()).unwrap_or("conf"),
+            Source::Bundle(_) => "zip",
+        };
+        self.dir.join(format!("{}.{}", app.name, extension))
+    }
+
+    /// Whether this Mac has settings for `app` to capture.
+    pub fn has_settings(&self, app: &App) -> bool {
+        match app.source {
+            Source::Defaults(domain) => self.runner.run("defaults", &["read", domain]).is_ok_and(|output| output.success),
+            Source::File(path) | Source::Bundle(path) => self.home.join(path).exists(),
+        }
+    }
+
+    pub fn is_captured(&self, app: &App) -> bool {
+        self.export_path(app).exists()
+    }
+
+    /// The captured settings for `app`: the export, or on a Mac that only
+    /// pulled it, the copy in `dotfiles_dir`.
+    pub fn captured(&self, app: &App, dotfiles_dir: &Path) -> Option<PathBuf> {
+        let export = self.export_path(app);
+        if export.exists() {
+            return Some(export);
+        }
+        let name = export.file_name()?.to_string_lossy();
+        Some(dotfiles_dir.join(format!("kiwi-app-{}", name))).filter(|pulled| pulled.exists())
+    }
+
+    pub fn is_running(&self, app: &App) -> bool {
+        app.process.is_some_and(|process| self.runner.run("pgrep", &["-xq", process]).is_ok_and(|output| output.success))
+    }
+
+    /// Exports `app`'s settings and makes sure the export is tracked.
+    pub fn capture(&self, app: &App, dotfiles: &Dotfiles) -> Result<PathBuf> {
+        fs::create_dir_all(&self.dir)?;
+        let export = self.export_path(app);
+        let exported = export.to_string_lossy();
+        match app.source {
+            Source::Defaults(domain) => self.run(app, "defaults", &["export", domain, &exported])?,
+            Source::File(path) => {
+                fs::copy(self.home.join(path), &export)?;
+            }
+            Source::Bundle(path) => {
+                // ditto adds to an existing archive, so start from scratch
+                if export.exists() {
+                    fs::remove_file(&export)?;
+                }
+                let bundle = self.home.join(path);
+                self.run(app, "ditto", &["-c", "-k", "--sequesterRsrc", "--keepParent", &bundle.to_string_lossy(), &exported])?;
+            }
+        }
+
+        let canonical = export.canonicalize()?;
+        if !dotfiles.list()?.iter().any(|d| d.path == canonical) {
+            let name = export.file_name().unwrap_or_default().to_string_lossy();
+            dotfiles.add(&export, Some(format!("kiwi-app-{}", name)), false)?;
+        }
+        Ok(export)
+    }
+
+    /// Puts the captured settings back, keeping what they replace in
+    /// `backups`, and reloads the app if it has a way to.
+    pub fn apply(&self, app: &App, backups: &Backups) -> Result<()> {
+        let export = self.export_path(app);
+        if !export.exists() {
+            return Err(KiwiError::ValidationError(format!(
+                "No captured settings for {}; run `kiwi apps capture {}` on the Mac that has them",
+                app.title, app.name
+            )));
+        }
+        self.apply_from(app, &export, backups)
+    }
+
+    /// Like [`Self::apply`], from the captured settings at `export`.
+    pub fn apply_from(&self, app: &App, export: &Path, backups: &Backups) -> Result<()> {
+        if self.is_running(app) {
+            return Err(KiwiError::ValidationError(format!(
+                "{} is running and would write its own settings back when it quits; quit it (running kiwi from another terminal if need be) and try again",
+                app.title
+            )));
+        }
+
+        let exported = export.to_string_lossy();
+        match app.source {
+            Source::Defaults(domain) => self.run(app, "defaults", &["import", domain, &exported])?,
+            Source::File(path) => {
+                let target = self.home.join(path);
+                let contents = fs::read(export)?;
+                backups.save(app.name, &target, &contents)?;
+                if let Some(parent) = target.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::write(&target, contents)?;
+            }
+            Source::Bundle(path) => {
+                let bundle = self.home.join(path);
+                let parent = bundle.parent().unwrap_or(&self.home);
+                fs::create_dir_all(parent)?;
+                // Unpacking over the old bundle would leave files the export doesn't have
+                backups.save_dir(app.name, &bundle)?;
+                self.run(app, "ditto", &["-x", "-k", &exported, &parent.to_string_lossy()])?;
+            }
+        }
+
+        if let Some(reload) = app.reload {
+            self.run(app, "sh", &["-c", reload])?;
+        }
+        Ok(())
+    }
+
+    fn run(&self, app: &App, program: &str, args: &[&str]) -> Result<()> {
+        let output = self.runner.run(program, args)?;
+        if !output.success {
+            return Err(KiwiError::Config(format!(
+                "{} {} failed for {}: {}",
+                program,
+                args[0],
+                app.title,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl Default for Apps {
+    fn default() -> Self {
+        Self::new(Self::default_dir().unwrap_or_default(), dirs::home_dir().unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::homebrew::CommandOutput;
+    use std::sync::{Arc, Mutex};
+
+    /// Writes a plist for `defaults export`, records everything else that
+    /// runs, and reports Terminal as running.
+    #[derive(Clone, Default)]
+    struct Recorder {
+        calls: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl CommandRunner for Recorder {
+        fn run(&self, program: &str, args: &[&str]) -> std::io::Result<CommandOutput> {
+            let success = match (program, args) {
+                ("defaults", ["export", _, path]) => {
+                    fs::write(path, "<plist version=\"1.0\"><dict/></plist>\n")?;
+                    true
+                }
+                ("pgrep", [_, process]) => *process == "Terminal",
+                _ => {
+                    self.calls.lock().unwrap().push(format!("{} {}", program, args.join(" ")));
+                    true
+                }
+            };
+            Ok(CommandOutput { success, stdout: Vec::new(), stderr: Vec::new() })
+        }
+    }
+
+    #[test]
+    fn test_capture_tracks_export_and_apply_imports_it() {
+        let dir = std::env::temp_dir().join(format!("kiwi-apps-test-{}", std::process::id()));
+        let home = dir.join("home");
+        let dotfiles = Dotfiles::new(dir.join("dotfiles"), dir.join("dotfiles/dotfiles.json"));
+        let backups = Backups::new(&dir.join("dotfiles"));
+        let runner = Recorder::default();
+        let apps = Apps::new(dir.join("apps"), home.clone()).with_runner(runner.clone());
+        let iterm = find("iterm2").unwrap();
+        let terminal = find("terminal").unwrap();
+
+        assert!(apps.apply(iterm, &backups).is_err());
+        let path = apps.capture(iterm, &dotfiles).unwrap();
+        apps.capture(iterm, &dotfiles).unwrap();
+        let tracked = dotfiles.list().unwrap();
+        assert_eq!(tracked.len(), 1);
+        assert_eq!(tracked[0].path, path.canonicalize().unwrap());
+        assert!(apps.is_captured(iterm) && !apps.is_captured(terminal));
+
+        apps.apply(iterm, &backups).unwrap();
+        assert_eq!(*runner.calls.lock().unwrap(), vec![format!("defaults import com.googlecode.iterm2 {}", path.display())]);
+        apps.capture(terminal, &dotfiles).unwrap();
+        assert!(apps.apply(terminal, &backups).is_err());
+        assert!(find("hyper").is_err());
+
+        // A Mac that only pulled the export finds it in the dotfiles dir
+        let pulled = Apps::new(dir.join("elsewhere"), home.clone()).with_runner(runner.clone());
+        assert_eq!(for_cask("iterm2").map(|app| app.name), Some("iterm2"));
+        assert!(for_cask("firefox").is_none());
+        assert_eq!(pulled.captured(iterm, &dir.join("dotfiles")), Some(dir.join("dotfiles/kiwi-app-iterm2.plist")));
+        assert_eq!(pulled.captured(find("rectangle").unwrap(), &dir.join("dotfiles")), None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_file_settings_round_trip_and_reload() {
+        let dir = std::env::temp_dir().join(format!("kiwi-apps-file-test-{}", std::process::id()));
+        let home = dir.join("home");
+        let config = home.join(".config/karabiner/karabiner.json");
+        fs::create_dir_all(config.parent().unwrap()).unwrap();
+        fs::write(&config, "{\"profiles\": []}\n").unwrap();
+        let dotfiles = Dotfiles::new(dir.join("dotfiles"), dir.join("dotfiles/dotfiles.json"));
+        let backups = Backups::new(&dir.join("dotfiles"));
+        let runner = Recorder::default();
+        let apps = Apps::new(dir.join("apps"), home.clone()).with_runner(runner.clone());
+        let karabiner = find("karabiner").unwrap();
+
+        assert!(apps.has_settings(karabiner) && !apps.has_settings(find("alfred").unwrap()));
+        let export = apps.capture(karabiner, &dotfiles).unwrap();
+        assert_eq!(export.file_name().unwrap(), "karabiner.json");
+
+        fs::write(&config, "{\"profiles\": [\"changed\"]}\n").unwrap();
+        apps.apply(karabiner, &backups).unwrap();
+        assert_eq!(fs::read_to_string(&config).unwrap(), "{\"profiles\": []}\n");
+        assert_eq!(backups.runs().unwrap().len(), 1);
+        assert!(runner.calls.lock().unwrap()[0].starts_with("sh -c launchctl kickstart -k"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
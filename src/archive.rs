@@ -0,0 +1,228 @@
+use crate::export::Environment;
+use crate::{Result, KiwiError, crypto};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+
+const MANIFEST_ENTRY: &str = "kiwi/manifest.json";
+const PACKAGES_ENTRY: &str = "kiwi/packages.json";
+const TAPS_ENTRY: &str = "kiwi/taps.json";
+const FILES_PREFIX: &str = "files/";
+
+/// The contents of an environment archive after extraction.
+pub struct Archive {
+    pub environment: Environment,
+    /// Dotfile contents keyed by their restore location on this machine,
+    /// only for files the manifest lists
+    pub files: HashMap<PathBuf, Vec<u8>>,
+    pub packages: Option<Vec<u8>>,
+    pub taps: Option<Vec<u8>>,
+}
+
+/// Writes a zstd-compressed tarball of manifests and dotfile contents,
+/// encrypted with `passphrase` when one is given. Returns the number of files bundled.
+pub fn create(path: &Path, environment: &Environment, dotfiles_dir: &Path, passphrase: Option<&str>) -> Result<usize> {
+    let (data, bundled) = build(environment, dotfiles_dir, passphrase)?;
+    fs::write(path, data)?;
+    Ok(bundled)
+}
+
+/// Like [`create`], but returns the archive bytes along with the number of files bundled.
+pub fn build(environment: &Environment, dotfiles_dir: &Path, passphrase: Option<&str>) -> Result<(Vec<u8>, usize)> {
+    let mut builder = tar::Builder::new(Vec::new());
+
+    append(&mut builder, MANIFEST_ENTRY, serde_json::to_string_pretty(environment)?.as_bytes())?;
+    for (entry, file) in [(PACKAGES_ENTRY, "packages.json"), (TAPS_ENTRY, "taps.json")] {
+        let source = dotfiles_dir.join(file);
+        if source.exists() {
+            append(&mut builder, entry, &fs::read(source)?)?;
+        }
+    }
+
+    let mut bundled = 0;
+    for dotfile in &environment.dotfiles {
+        if !dotfile.path.is_file() {
+            continue;
+        }
+        append(&mut builder, &entry_name(&dotfile.path), &fs::read(&dotfile.path)?)?;
+        bundled += 1;
+    }
+
+    let tarball = builder.into_inner()?;
+    let mut data = zstd::encode_all(tarball.as_slice(), 0)?;
+    if let Some(passphrase) = passphrase {
+        data = crypto::encrypt_with_passphrase(&data, passphrase)?;
+    }
+    Ok((data, bundled))
+}
+
+/// Whether the archive at `path` needs a passphrase to open.
+pub fn is_encrypted(path: &Path) -> Result<bool> {
+    let mut header = [0u8; 32];
+    let read = fs::File::open(path)?.read(&mut header)?;
+    Ok(crypto::is_encrypted(&header[..read]))
+}
+
+pub fn extract(path: &Path, passphrase: Option<&str>) -> Result<Archive> {
+    open(fs::read(path)?, passphrase)
+}
+
+/// Unpacks archive bytes, e.g. ones received over the network.
+pub fn open(mut data: Vec<u8>, passphrase: Option<&str>) -> Result<Archive> {
+    if crypto::is_encrypted(&data) {
+        let passphrase = passphrase.ok_or_else(|| {
+            KiwiError::ValidationError("Archive is encrypted; a passphrase is required".to_string())
+        })?;
+        data = crypto::decrypt_with_passphrase(&data, passphrase)?;
+    }
+
+    let tarball = zstd::decode_all(data.as_slice())?;
+    let mut archive = tar::Archive::new(tarball.as_slice());
+
+    let mut environment = None;
+    let mut files = HashMap::new();
+    let mut packages = None;
+    let mut taps = None;
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.to_string_lossy().to_string();
+        let mut contents = Vec::new();
+        entry.read_to_end(&mut contents)?;
+
+        match name.as_str() {
+            MANIFEST_ENTRY => environment = Some(serde_json::from_slice::<Environment>(&contents)?),
+            PACKAGES_ENTRY => packages = Some(contents),
+            TAPS_ENTRY => taps = Some(contents),
+            _ => {
+                if let Some(target) = restore_path(&name)? {
+                    files.insert(target, contents);
+                }
+            }
+        }
+    }
+
+    let mut environment = environment.ok_or_else(|| {
+        KiwiError::ValidationError("Archive is missing its manifest".to_string())
+    })?;
+
+    // Point tracked paths at this machine's home directory
+    for dotfile in &mut environment.dotfiles {
+        if let Some(target) = restore_path(&entry_name(&dotfile.path))? {
+            dotfile.path = target;
+        }
+    }
+    // Anything else in the tarball wasn't exported by kiwi
+    files.retain(|target, _| environment.dotfiles.iter().any(|dotfile| &dotfile.path == target));
+
+    Ok(Archive { environment, files, packages, taps })
+}
+
+fn append(builder: &mut tar::Builder<Vec<u8>>, name: &str, contents: &[u8]) -> Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(contents.len() as u64);
+    header.set_mode(0o600);
+    header.set_mtime(chrono::Utc::now().timestamp() as u64);
+    header.set_cksum();
+    builder.append_data(&mut header, name, contents)?;
+    Ok(())
+}
+
+/// Files under the home directory are stored home-relative so archives
+/// restore correctly for a different username.
+fn entry_name(path: &Path) -> String {
+    let home = dirs::home_dir().unwrap_or_default();
+    match path.strip_prefix(&home) {
+        Ok(relative) => format!("{}home/{}", FILES_PREFIX, relative.display()),
+        Err(_) => format!("{}root/{}", FILES_PREFIX, path.display().to_string().trim_start_matches('/')),
+    }
+}
+
+/// Where a `files/` entry goes on this machine. Entries with `..` or other
+/// components that could leave their root are refused, like
+/// `Sync::file_target` refuses such remote keys.
+fn restore_path(entry: &str) -> Result<Option<PathBuf>> {
+    let Some(rest) = entry.strip_prefix(FILES_PREFIX) else {
+        return Ok(None);
+    };
+    let (root, relative) = if let Some(relative) = rest.strip_prefix("home/") {
+        let Some(home) = dirs::home_dir() else {
+            return Ok(None);
+        };
+        (home, relative)
+    } else if let Some(relative) = rest.strip_prefix("root/") {
+        (PathBuf::from("/"), relative)
+    } else {
+        return Ok(None);
+    };
+    let relative = Path::new(relative);
+    if relative.as_os_str().is_empty() || !relative.components().all(|c| matches!(c, Component::Normal(_))) {
+        return Err(KiwiError::ValidationError(format!("Refusing to restore unsafe path from archive: {}", entry)));
+    }
+    Ok(Some(root.join(relative)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_archive_roundtrip() {
+        let dir = std::env::temp_dir().join(format!("kiwi-archive-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("taps.json"), "[\"homebrew/cask-fonts\"]").unwrap();
+
+        let environment = Environment {
+            generated_at: "2024-01-01T00:00:00Z".to_string(),
+            environment: Some("dev".to_string()),
+            dotfiles: Vec::new(),
+            taps: vec!["homebrew/cask-fonts".to_string()],
+            packages: Vec::new(),
+            excluded_packages: Vec::new(),
+        };
+
+        let path = dir.join("setup.kiwi.tar.zst");
+        create(&path, &environment, &dir, Some("correct horse")).unwrap();
+        assert!(is_encrypted(&path).unwrap());
+        assert!(extract(&path, Some("wrong")).is_err());
+
+        let archive = extract(&path, Some("correct horse")).unwrap();
+        assert_eq!(archive.environment.environment.as_deref(), Some("dev"));
+        assert_eq!(archive.taps.as_deref(), Some(b"[\"homebrew/cask-fonts\"]".as_slice()));
+        assert!(archive.packages.is_none());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_only_listed_files_inside_their_root_are_restored() {
+        let environment = Environment {
+            generated_at: "2024-01-01T00:00:00Z".to_string(),
+            environment: None,
+            dotfiles: Vec::new(),
+            taps: Vec::new(),
+            packages: Vec::new(),
+            excluded_packages: Vec::new(),
+        };
+        let tarball = |entries: &[&str]| {
+            let mut builder = tar::Builder::new(Vec::new());
+            append(&mut builder, MANIFEST_ENTRY, serde_json::to_string(&environment).unwrap().as_bytes()).unwrap();
+            for entry in entries {
+                // Header::set_path refuses `..`, so write the name directly
+                let mut header = tar::Header::new_gnu();
+                header.as_gnu_mut().unwrap().name[..entry.len()].copy_from_slice(entry.as_bytes());
+                header.set_size(5);
+                header.set_mode(0o600);
+                header.set_cksum();
+                builder.append(&header, b"evil\n".as_slice()).unwrap();
+            }
+            zstd::encode_all(builder.into_inner().unwrap().as_slice(), 0).unwrap()
+        };
+
+        assert!(open(tarball(&["files/home/../../etc/sudoers"]), None).is_err());
+        assert!(open(tarball(&["files/root/../etc/hosts"]), None).is_err());
+        let archive = open(tarball(&["files/home/.zshrc", "files/root/etc/paths.d/evil"]), None).unwrap();
+        assert!(archive.files.is_empty());
+    }
+}
@@ -0,0 +1,302 @@
+//! An audit trail of what changed a Mac's setup: every command that can
+//! change something is journaled locally in `~/.kiwi/logs/audit.jsonl`,
+//! and `kiwi audit export` merges that with the server's record of pushes
+//! from every machine into one JSONL file, signed with an SSH key so anyone
+//! with the public key can check it with `ssh-keygen -Y verify`.
+
+use crate::homebrew::CommandRunner;
+use crate::sync::Activity;
+use crate::{Config, KiwiError, Result};
+use chrono::{DateTime, FixedOffset};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// The `ssh-keygen -Y` namespace exports are signed under.
+pub const NAMESPACE: &str = "kiwi-audit";
+
+/// Commands that only look, by their first word. Subcommands named like
+/// `list` or `show` only look too.
+const READ_ONLY: &[&str] = &[
+    "activity", "audit", "completions", "debug-bundle", "diff", "export", "help", "history",
+    "info", "list", "manpages", "policy", "search", "status", "which",
+];
+const READ_ONLY_SUBCOMMANDS: &[&str] = &["check", "env", "list", "outdated", "show", "status", "usage"];
+
+/// Arguments whose next value is replaced in the journal.
+const SECRET_WORDS: &[&str] = &["token", "password", "secret", "passphrase"];
+
+/// Commands with a positional that holds a value, by subcommand path: the
+/// index of that positional and the options that take a value of their own.
+/// Given `--secret`, any command's positionals after the first are hidden.
+const VALUE_POSITIONALS: &[(&str, usize, &[&str])] = &[("envvar set", 1, &["-e", "--env"])];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    /// RFC 3339
+    pub at: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub machine: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    /// A kiwi command run on this Mac
+    Command {
+        command: String,
+        args: Vec<String>,
+        success: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    /// A push the sync server took, from any machine
+    Push {
+        files: Vec<String>,
+        packages_added: Vec<String>,
+        packages_removed: Vec<String>,
+    },
+}
+
+impl From<Activity> for Record {
+    fn from(activity: Activity) -> Self {
+        Self {
+            at: activity.at,
+            machine: activity.machine,
+            user: None,
+            event: Event::Push {
+                files: activity.files,
+                packages_added: activity.packages_added,
+                packages_removed: activity.packages_removed,
+            },
+        }
+    }
+}
+
+pub fn journal_path() -> Result<PathBuf> {
+    let home = dirs::home_dir().ok_or_else(|| KiwiError::Config("Could not find home directory".to_string()))?;
+    Ok(home.join(".kiwi/logs/audit.jsonl"))
+}
+
+/// Whether running `command`, a subcommand path like `alias add`, with
+/// `args` belongs in the journal.
+pub fn is_audited(command: &str, args: &[String]) -> bool {
+    let first = command.split(' ').next().unwrap_or_default();
+    if first.is_empty() || READ_ONLY.contains(&first) {
+        return false;
+    }
+    if command.rsplit_once(' ').is_some_and(|(_, last)| READ_ONLY_SUBCOMMANDS.contains(&last)) {
+        return false;
+    }
+    match first {
+        "doctor" => args.iter().any(|arg| arg == "--fix"),
+        "check" => args.iter().any(|arg| arg == "--install"),
+        "autoremove" => !args.iter().any(|arg| arg == "--dry-run" || arg == "-n"),
+        _ => true,
+    }
+}
+
+/// `args` of `command` with secrets blanked out: the values of
+/// secret-looking options and keys, like the token in `kiwi config set
+/// sync_token abc`, and value positionals by command shape, like the value
+/// in `kiwi envvar set OPENAI_API_KEY sk-... --secret`.
+pub fn redact(command: &str, args: &[String]) -> Vec<String> {
+    let is_secret = |arg: &str| SECRET_WORDS.iter().any(|word| arg.to_lowercase().contains(word));
+    let shape = VALUE_POSITIONALS.iter().find(|(path, _, _)| *path == command);
+    let value_options = shape.map(|(_, _, options)| *options).unwrap_or_default();
+    let given_secret = args.iter().any(|arg| arg == "--secret");
+    let mut path = command.split(' ').peekable();
+    let mut positional = 0;
+    let mut redacted = Vec::with_capacity(args.len());
+    let mut hide_next = false;
+    let mut skip_next = false;
+    for arg in args {
+        if hide_next {
+            redacted.push("[redacted]".to_string());
+            hide_next = false;
+        } else if skip_next {
+            redacted.push(arg.clone());
+            skip_next = false;
+        } else if let Some((name, _)) = arg.split_once('=').filter(|(name, _)| is_secret(name)) {
+            redacted.push(format!("{}=[redacted]", name));
+        } else if arg.starts_with('-') {
+            hide_next = is_secret(arg);
+            skip_next = !hide_next && value_options.contains(&arg.as_str());
+            redacted.push(arg.clone());
+        } else if path.peek().is_some_and(|word| word == arg) {
+            path.next();
+            redacted.push(arg.clone());
+        } else {
+            let hidden = path.peek().is_none()
+                && (shape.is_some_and(|(_, index, _)| *index == positional) || (given_secret && positional > 0));
+            if path.peek().is_none() {
+                positional += 1;
+            }
+            hide_next = is_secret(arg);
+            redacted.push(if hidden { "[redacted]".to_string() } else { arg.clone() });
+        }
+    }
+    redacted
+}
+
+/// Journals a finished command. Nothing is written once kiwi has been
+/// torn down, so the journal doesn't bring `~/.kiwi` back.
+pub fn record_command(command: &str, args: &[String], outcome: &Result<()>) -> Result<()> {
+    if !is_audited(command, args) || !Config::config_path().is_ok_and(|path| path.exists()) {
+        return Ok(());
+    }
+    let record = Record {
+        at: chrono::Local::now().to_rfc3339(),
+        machine: Some(crate::ops::computer_name()),
+        user: std::env::var("USER").ok(),
+        event: Event::Command {
+            command: command.to_string(),
+            args: redact(command, args),
+            success: outcome.is_ok(),
+            error: outcome.as_ref().err().map(ToString::to_string),
+        },
+    };
+    append(&journal_path()?, &record)
+}
+
+pub fn append(path: &Path, record: &Record) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", serde_json::to_string(record)?)?;
+    Ok(())
+}
+
+/// The journal's records. Lines that don't parse are skipped.
+pub fn read(path: &Path) -> Result<Vec<Record>> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(text
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| match serde_json::from_str(line) {
+            Ok(record) => Some(record),
+            Err(e) => {
+                log::warn!("Skipping unreadable audit record: {}", e);
+                None
+            }
+        })
+        .collect())
+}
+
+/// `records` from `since` on, oldest first, one JSON object per line.
+pub fn to_jsonl(mut records: Vec<Record>, since: Option<DateTime<FixedOffset>>) -> Result<String> {
+    let parsed = |record: &Record| DateTime::parse_from_rfc3339(&record.at).ok();
+    records.retain(|record| since.is_none_or(|since| parsed(record).is_some_and(|at| at >= since)));
+    records.sort_by_key(parsed);
+    let mut jsonl = String::new();
+    for record in &records {
+        jsonl.push_str(&serde_json::to_string(record)?);
+        jsonl.push('\n');
+    }
+    Ok(jsonl)
+}
+
+/// Signs `file` with the SSH private key `key`, leaving the signature in
+/// `<file>.sig`. ssh-keygen asks for the key's passphrase itself.
+pub fn sign(runner: &dyn CommandRunner, key: &Path, file: &Path) -> Result<PathBuf> {
+    let signature = PathBuf::from(format!("{}.sig", file.display()));
+    // ssh-keygen won't replace an old signature
+    let _ = fs::remove_file(&signature);
+    let signed = runner.run_interactive(
+        "ssh-keygen",
+        &["-Y", "sign", "-n", NAMESPACE, "-f", &key.to_string_lossy(), &file.to_string_lossy()],
+    )?;
+    if !signed || !signature.exists() {
+        return Err(KiwiError::Config(format!("ssh-keygen couldn't sign {} with {}", file.display(), key.display())));
+    }
+    Ok(signature)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::homebrew::CommandOutput;
+
+    fn command(at: &str, command: &str) -> Record {
+        Record {
+            at: at.to_string(),
+            machine: Some("Laptop".to_string()),
+            user: Some("jane".to_string()),
+            event: Event::Command { command: command.to_string(), args: Vec::new(), success: true, error: None },
+        }
+    }
+
+    /// Stands in for ssh-keygen, writing the signature file it would.
+    struct Keygen;
+
+    impl CommandRunner for Keygen {
+        fn run(&self, _program: &str, args: &[&str]) -> std::io::Result<CommandOutput> {
+            fs::write(format!("{}.sig", args.last().unwrap()), "-----BEGIN SSH SIGNATURE-----\n")?;
+            Ok(CommandOutput { success: true, stdout: Vec::new(), stderr: Vec::new() })
+        }
+    }
+
+    #[test]
+    fn test_journal_and_export() {
+        let args = |line: &str| line.split(' ').map(str::to_string).collect::<Vec<_>>();
+        assert!(is_audited("add", &args("add ~/.zshrc")));
+        assert!(is_audited("alias add", &args("alias add ll ls -l")));
+        assert!(!is_audited("alias list", &args("alias list")));
+        assert!(!is_audited("status", &args("status")));
+        assert!(!is_audited("doctor", &args("doctor")));
+        assert!(is_audited("doctor", &args("doctor --fix")));
+        assert_eq!(
+            redact("config set", &args("config set sync_token abc --password=hunter2 --verbose")),
+            args("config set sync_token [redacted] --password=[redacted] --verbose")
+        );
+        assert_eq!(
+            redact("envvar set", &args("-v envvar set OPENAI_API_KEY sk-abc --secret")),
+            args("-v envvar set OPENAI_API_KEY [redacted] --secret")
+        );
+        assert_eq!(
+            redact("envvar set", &args("envvar set --env work EDITOR vim")),
+            args("envvar set --env work EDITOR [redacted]")
+        );
+
+        let dir = std::env::temp_dir().join(format!("kiwi-audit-test-{}", std::process::id()));
+        let journal = dir.join("audit.jsonl");
+        append(&journal, &command("2026-10-02T09:00:00+02:00", "add")).unwrap();
+        append(&journal, &command("2026-09-30T12:00:00+00:00", "install")).unwrap();
+        let mut file = OpenOptions::new().append(true).open(&journal).unwrap();
+        writeln!(file, "not json").unwrap();
+
+        let mut records = read(&journal).unwrap();
+        records.push(Record::from(Activity {
+            at: "2026-10-01T08:00:00+00:00".to_string(),
+            machine: Some("Desktop".to_string()),
+            files: vec!["zshrc".to_string()],
+            packages_added: Vec::new(),
+            packages_removed: Vec::new(),
+        }));
+        let since = DateTime::parse_from_rfc3339("2026-10-01T00:00:00+00:00").ok();
+        let jsonl = to_jsonl(records, since).unwrap();
+        let lines: Vec<serde_json::Value> = jsonl.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["type"], "push");
+        assert_eq!(lines[0]["machine"], "Desktop");
+        assert_eq!(lines[1]["type"], "command");
+        assert_eq!(lines[1]["command"], "add");
+
+        let export = dir.join("audit-export.jsonl");
+        fs::write(&export, &jsonl).unwrap();
+        let signature = sign(&Keygen, Path::new("/home/jane/.ssh/id_ed25519"), &export).unwrap();
+        assert_eq!(signature, dir.join("audit-export.jsonl.sig"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,88 @@
+//! The managed backups area. Before a pull or render overwrites a file, its
+//! old contents are copied to `<dotfiles dir>/backups/<run>/<name>`, one
+//! folder per run, so nothing piles up next to the user's files and
+//! `kiwi gc` can expire whole runs at once.
+
+use crate::Result;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub struct Backups {
+    root: PathBuf,
+    run: String,
+}
+
+impl Backups {
+    /// A new run under `dotfiles_dir`, named after the current time.
+    pub fn new(dotfiles_dir: &Path) -> Self {
+        Self {
+            root: dotfiles_dir.join("backups"),
+            run: chrono::Local::now().format("%Y%m%d-%H%M%S").to_string(),
+        }
+    }
+
+    /// Copies the file at `path` into this run as `name`, unless it doesn't
+    /// exist or already holds `incoming`. Returns where the copy went.
+    pub fn save(&self, name: &str, path: &Path, incoming: &[u8]) -> Result<Option<PathBuf>> {
+        let Ok(current) = fs::read(path) else {
+            return Ok(None);
+        };
+        if current == incoming {
+            return Ok(None);
+        }
+
+        let backup = self.root.join(&self.run).join(name);
+        if let Some(parent) = backup.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&backup, current)?;
+        Ok(Some(backup))
+    }
+
+    /// Moves the directory at `path` into this run as `name`, for bundles
+    /// that are replaced whole. Returns where it went.
+    pub fn save_dir(&self, name: &str, path: &Path) -> Result<Option<PathBuf>> {
+        if !path.is_dir() {
+            return Ok(None);
+        }
+        let backup = self.root.join(&self.run).join(name);
+        if let Some(parent) = backup.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(path, &backup)?;
+        Ok(Some(backup))
+    }
+
+    /// Every run folder, oldest first.
+    pub fn runs(&self) -> Result<Vec<PathBuf>> {
+        let Ok(entries) = fs::read_dir(&self.root) else {
+            return Ok(Vec::new());
+        };
+        let mut runs: Vec<PathBuf> = entries.flatten().map(|e| e.path()).filter(|p| p.is_dir()).collect();
+        runs.sort();
+        Ok(runs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_skips_missing_and_unchanged_files() {
+        let dir = std::env::temp_dir().join(format!("kiwi-backups-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join(".zshrc");
+        let backups = Backups::new(&dir.join("dotfiles"));
+
+        assert_eq!(backups.save(".zshrc", &file, b"new\n").unwrap(), None);
+        fs::write(&file, "old\n").unwrap();
+        assert_eq!(backups.save(".zshrc", &file, b"old\n").unwrap(), None);
+
+        let saved = backups.save(".zshrc", &file, b"new\n").unwrap().unwrap();
+        assert_eq!(fs::read_to_string(&saved).unwrap(), "old\n");
+        assert_eq!(backups.runs().unwrap(), vec![saved.parent().unwrap().to_path_buf()]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
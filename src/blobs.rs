@@ -0,0 +1,103 @@
+use crate::{KiwiError, Result};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// File contents stored by SHA-256, so identical files are kept once and
+/// renames or repeated pushes never copy data again.
+pub struct BlobStore {
+    dir: PathBuf,
+}
+
+/// Hex-encoded SHA-256 of `data`.
+pub fn hash(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// Whether `value` looks like a hash produced by [`hash`].
+pub fn is_hash(value: &str) -> bool {
+    value.len() == 64 && value.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
+
+impl BlobStore {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// Stores `data` if it isn't already present and returns its hash.
+    pub fn put(&self, data: &[u8]) -> Result<String> {
+        let hash = hash(data);
+        let path = self.path(&hash);
+        if !path.exists() {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            // Write then rename so a crash never leaves a truncated blob behind
+            let partial = path.with_extension("partial");
+            fs::write(&partial, data)?;
+            fs::rename(&partial, &path)?;
+        }
+        Ok(hash)
+    }
+
+    /// Stores data received from elsewhere after checking it matches `expected`.
+    pub fn put_verified(&self, expected: &str, data: &[u8]) -> Result<()> {
+        let actual = hash(data);
+        if actual != expected {
+            return Err(KiwiError::Sync(format!(
+                "Blob {} failed verification (got {})",
+                expected, actual
+            )));
+        }
+        self.put(data)?;
+        Ok(())
+    }
+
+    pub fn get(&self, hash: &str) -> Result<Vec<u8>> {
+        let path = self.path(hash);
+        fs::read(&path).map_err(|_| KiwiError::FileNotFound { path })
+    }
+
+    pub fn contains(&self, hash: &str) -> bool {
+        self.path(hash).exists()
+    }
+
+    /// Size of the stored blob, if it's here.
+    pub fn size(&self, hash: &str) -> Option<u64> {
+        fs::metadata(self.path(hash)).ok().map(|metadata| metadata.len())
+    }
+
+    /// Deletes blobs outside `keep` (and leftover partial writes) last written
+    /// before `cutoff`. Returns how many files went and their total size;
+    /// with `dry_run` nothing is deleted.
+    pub fn prune(&self, keep: &HashSet<String>, cutoff: SystemTime, dry_run: bool) -> Result<(usize, u64)> {
+        let (mut count, mut size) = (0, 0);
+        let Ok(shards) = fs::read_dir(&self.dir) else {
+            return Ok((count, size));
+        };
+        for shard in shards.flatten().filter(|entry| entry.path().is_dir()) {
+            for entry in fs::read_dir(shard.path())?.flatten() {
+                let hash = format!("{}{}", shard.file_name().to_string_lossy(), entry.file_name().to_string_lossy());
+                let metadata = entry.metadata()?;
+                let expired = metadata.modified().is_ok_and(|modified| modified < cutoff);
+                if keep.contains(&hash) || !expired {
+                    continue;
+                }
+                if !dry_run {
+                    fs::remove_file(entry.path())?;
+                }
+                count += 1;
+                size += metadata.len();
+            }
+        }
+        Ok((count, size))
+    }
+
+    /// Blobs are sharded by the first two hex digits to keep directories small.
+    fn path(&self, hash: &str) -> PathBuf {
+        let (shard, rest) = hash.split_at(hash.len().min(2));
+        self.dir.join(shard).join(rest)
+    }
+}
@@ -1,15 +1,24 @@
-use clap::{Parser, Subcommand, ValueEnum};
-use crate::{Result, Config, Homebrew, Dotfiles, Sync};
-use std::path::PathBuf;
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use crate::{Result, Config, Homebrew, Dotfiles, Sync, KiwiError, audit, ops, policy, project, provenance, link, ui, search, export, archive, transfer, migrate, agent, teardown, apps, jobs, gpg, ssh, hosts, network, tweaks, fixes, completions, diagnostics, starters, registry, aliases, envvars, keychain, gc, watch, xcode, table, shell};
+use std::path::{Path, PathBuf};
 use colored::*;
+use crate::theme::Themed;
+use crate::reporter::Reporter;
+use crate::dotfiles::{DriftStatus, LinkMode, SyncState, SyncSummary};
+use crate::plan::{FileAction, RestorePlan};
+use crate::sync::{MergeOptions, MergeSummary, PullOptions, PushOptions, Quota, RemoteStatus};
+use crate::environments::Environments;
 use std::io::{self, Write};
 use indicatif::{ProgressBar, ProgressStyle, MultiProgress};
 use std::fmt;
+use std::cell::RefCell;
+use dialoguer::{Confirm, MultiSelect, Password, Select, theme::ColorfulTheme};
 use std::time::Duration;
 
 const SPINNER_TEMPLATE: &str = "{spinner:.green} {prefix:.bold.dim} {wide_msg}";
-const PROGRESS_TEMPLATE: &str = "{spinner:.green} [{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {wide_msg}";
-const PROGRESS_CHARS: &str = "█▉▊▋▌▍▎▏  ";
+const TRANSFER_TEMPLATE: &str = "{spinner:.green} {wide_msg} [{bar:30}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})";
+/// Health checks slower than this are reported by `kiwi doctor`.
+const SLOW_SYNC_SERVER: Duration = Duration::from_secs(2);
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
 pub enum EnvType {
@@ -37,6 +46,180 @@ pub enum ListType {
     All,
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum ListSort {
+    Name,
+    Size,
+    Updated,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum AddMode {
+    /// Symlink from the dotfiles dir to the file (default)
+    Symlink,
+    /// Keep a copy in the dotfiles dir, refreshed from whichever side is newer
+    Copy,
+    /// Hard link the file into the dotfiles dir
+    Hardlink,
+    /// Keep a template in the dotfiles dir and render it to the file
+    Render,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum DiffAgainst {
+    /// The version recorded at the last push or pull
+    Synced,
+    /// The version on the sync server
+    Remote,
+    /// Both
+    All,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum ExportFormat {
+    Json,
+    Yaml,
+    Markdown,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum TelemetryAction {
+    On,
+    Off,
+    Status,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum PolicyAction {
+    /// Show what the policy requires, forbids, and locks
+    Show,
+    /// Check this Mac against the policy, failing on any violation
+    Check,
+}
+
+/// Renders operation progress on a spinner (or plain lines without one)
+/// and asks confirmations with dialoguer.
+struct CliReporter<'a> {
+    spinner: Option<&'a ProgressBar>,
+    assume_yes: bool,
+    /// Shows bytes transferred: the spinner turned into a bar, or a bar of
+    /// its own without one. Set by the first transfer, cleared on success.
+    transfer: RefCell<Option<ProgressBar>>,
+}
+
+impl<'a> CliReporter<'a> {
+    fn new(spinner: Option<&'a ProgressBar>, assume_yes: bool) -> Self {
+        Self { spinner, assume_yes, transfer: RefCell::new(None) }
+    }
+
+    /// The bar on screen, if any, which output has to go around.
+    fn bar(&self) -> Option<ProgressBar> {
+        self.spinner.cloned().or_else(|| self.transfer.borrow().clone())
+    }
+
+    fn println(&self, line: String) {
+        match self.bar() {
+            Some(bar) => bar.println(line),
+            None => println!("{}", line),
+        }
+    }
+
+    fn end_transfer(&self) {
+        let Some(bar) = self.transfer.borrow_mut().take() else {
+            return;
+        };
+        match self.spinner {
+            Some(spinner) => {
+                spinner.unset_length();
+                spinner.set_style(ProgressStyle::with_template(SPINNER_TEMPLATE).unwrap());
+            }
+            None => bar.finish_and_clear(),
+        }
+    }
+}
+
+impl Drop for CliReporter<'_> {
+    fn drop(&mut self) {
+        self.end_transfer();
+    }
+}
+
+impl Reporter for CliReporter<'_> {
+    fn progress(&self, message: &str) {
+        match self.bar() {
+            Some(bar) => bar.set_message(message.to_string()),
+            None => println!("{}", message.warning()),
+        }
+    }
+
+    fn success(&self, message: &str) {
+        self.end_transfer();
+        self.println(format!("  {} {}", "✓".success(), message));
+    }
+
+    fn warning(&self, message: &str) {
+        self.println(format!("  {} {}", "✗".error(), message));
+    }
+
+    fn confirm(&self, prompt: &str) -> bool {
+        if self.assume_yes {
+            return true;
+        }
+        let ask = || {
+            Confirm::with_theme(&ColorfulTheme::default())
+                .with_prompt(prompt)
+                .default(false)
+                .interact()
+                .unwrap_or(false)
+        };
+        match self.bar() {
+            Some(bar) => bar.suspend(ask),
+            None => ask(),
+        }
+    }
+
+    fn assumes_yes(&self) -> bool {
+        self.assume_yes
+    }
+
+    fn choose(&self, prompt: &str, choices: &[&str]) -> Option<usize> {
+        if self.assume_yes {
+            return None;
+        }
+        let ask = || {
+            Select::with_theme(&ColorfulTheme::default())
+                .with_prompt(prompt)
+                .items(choices)
+                .default(0)
+                .interact_opt()
+                .ok()
+                .flatten()
+        };
+        match self.bar() {
+            Some(bar) => bar.suspend(ask),
+            None => ask(),
+        }
+    }
+
+    fn transfer(&self, done: u64, total: u64) {
+        let mut transfer = self.transfer.borrow_mut();
+        let bar = transfer.get_or_insert_with(|| {
+            let bar = self.spinner.cloned().unwrap_or_else(ProgressBar::new_spinner);
+            bar.set_style(ProgressStyle::with_template(TRANSFER_TEMPLATE).unwrap().progress_chars("=> "));
+            bar
+        });
+        bar.set_length(total);
+        bar.set_position(done);
+    }
+
+    fn suspend(&self, step: &mut dyn FnMut()) {
+        match self.bar() {
+            Some(bar) => bar.suspend(step),
+            None => step(),
+        }
+    }
+}
+
 #[derive(Parser)]
 #[command(name = "kiwi")]
 #[command(about = "🥝 Kiwi - The Ultimate macOS Environment Manager", long_about = "A powerful CLI tool for seamlessly managing your macOS environment, including dotfiles, Homebrew packages, and cloud sync.")]
@@ -57,7 +240,7 @@ pub struct Cli {
 
 #[derive(Subcommand)]
 pub enum Commands {
-    /// Initialize or reconfigure the user's environment
+    /// Initialize or reconfigure the user's environment; without flags, a guided setup
     Init {
         /// Restore configurations and dotfiles from cloud backup
         #[arg(short, long)]
@@ -74,8 +257,37 @@ pub enum Commands {
         /// Skip interactive prompts
         #[arg(short = 'y', long)]
         yes: bool,
+        /// Starter config packs to install (zsh, tmux, git)
+        #[arg(short, long, value_delimiter = ',')]
+        starter: Vec<String>,
+    },
+    /// Restore this Mac from the sync server: files, taps, packages, and
+    /// recorded macOS settings
+    Restore {
+        /// Only show what would be restored, with sizes and an estimated duration
+        #[arg(long)]
+        plan: bool,
+        /// Also save the plan as JSON to this file
+        #[arg(short, long, requires = "plan")]
+        output: Option<PathBuf>,
+        /// Install packages from a seed made with `kiwi seed create`
+        #[arg(long, conflicts_with = "plan")]
+        seed: Option<PathBuf>,
+        /// Don't run packages' post-install commands
+        #[arg(long, conflicts_with = "plan")]
+        no_hooks: bool,
+        /// Skip interactive prompts
+        #[arg(short = 'y', long, conflicts_with = "plan")]
+        yes: bool,
+    },
+    /// Download the tracked packages ahead of time, for restoring on a Mac
+    /// with a poor connection
+    Seed {
+        #[command(subcommand)]
+        action: SeedCommands,
     },
-    /// Sync configuration files between local and cloud
+    /// Sync configuration files between local and cloud, both ways unless
+    /// --push or --pull is given
     Sync {
         /// Pull configurations from remote
         #[arg(long, conflicts_with = "push")]
@@ -86,26 +298,84 @@ pub enum Commands {
         /// Prefer local files over cloud in case of conflicts
         #[arg(short, long)]
         prefer_local: bool,
-        /// Force sync even if there are conflicts
+        /// With --push, overwrite files another machine changed since your last
+        /// sync; with --pull, replace local changes (backed up first) and apply
+        /// files that fail validation
         #[arg(short, long)]
         force: bool,
         /// Show a diff before syncing
         #[arg(short, long)]
         diff: bool,
+        /// Check the sync server and show its status without syncing
+        #[arg(long, conflicts_with_all = ["pull", "push"])]
+        status: bool,
+    },
+    /// Pull from remote, like `sync --pull`, optionally restoring only some files
+    Pull {
+        /// Restore just this tracked file, by path or dotfiles dir name; repeatable
+        #[arg(long, value_name = "FILE")]
+        only: Vec<String>,
+        /// Leave files that exist locally alone
+        #[arg(short, long)]
+        prefer_local: bool,
+        /// Replace files changed locally (backed up first) and apply pulled
+        /// files that fail validation
+        #[arg(short, long)]
+        force: bool,
+    },
+    /// Push this Mac's version of tracked files over the remote's, for
+    /// files a pull kept because they differ here
+    Adopt {
+        /// Tracked files, by path or dotfiles dir name
+        #[arg(required = true)]
+        files: Vec<String>,
     },
     /// Add a dotfile or configuration to sync
     Add {
-        /// Path to the file to add
+        /// Path to the file to add, a directory to track everything in it, or
+        /// a quoted glob like "~/.config/fish/**/*.fish" to add every match
         path: String,
         /// Alias for the file
         #[arg(short, long)]
         alias: Option<String>,
-        /// Create symlink automatically
-        #[arg(short, long)]
+        /// Move the file into the dotfiles dir and leave a symlink to it in
+        /// its place, so the dotfiles dir can be cloned and relinked elsewhere
+        #[arg(short, long, conflicts_with_all = ["template", "mode"])]
         symlink: bool,
         /// Skip backup of existing file
         #[arg(short = 'B', long)]
         no_backup: bool,
+        /// Add files over the large-file threshold anyway, and replace a
+        /// different file already in the dotfiles dir without asking
+        #[arg(short, long)]
+        force: bool,
+        /// Sync only the file's name, hash, and size, not its contents
+        #[arg(short, long, conflicts_with_all = ["template", "mode"])]
+        pointer: bool,
+        /// Track the file as a template with `{{ variable }}` placeholders (same as --mode render)
+        #[arg(short, long, conflicts_with = "mode")]
+        template: bool,
+        /// How the file is tied to its copy in the dotfiles dir
+        #[arg(short, long, value_enum)]
+        mode: Option<AddMode>,
+        /// Write the file through sudo, for system files like /etc/zshrc
+        /// that belong to root
+        #[arg(long)]
+        sudo: bool,
+        /// Don't sync its Finder tags and flags or clear its quarantine flag
+        #[arg(long)]
+        no_xattrs: bool,
+        /// With a glob, leave out matches by name, or by path when the pattern
+        /// has a /; matching a directory leaves out everything in it
+        #[arg(short = 'x', long, value_name = "PATTERN")]
+        exclude: Vec<String>,
+        /// Only apply the file in this environment; repeat for several
+        #[arg(short, long = "env", value_name = "ENV")]
+        env: Vec<String>,
+        /// Keep the file age-encrypted in the dotfiles dir and on the sync
+        /// server, to `encryption_recipient` or with a Keychain passphrase
+        #[arg(long, conflicts_with_all = ["template", "mode", "symlink", "pointer"])]
+        encrypt: bool,
     },
     /// Remove a dotfile or configuration from sync
     Remove {
@@ -118,6 +388,32 @@ pub enum Commands {
         #[arg(short, long)]
         force: bool,
     },
+    /// Save the passphrase for encrypted dotfiles in this Mac's Keychain,
+    /// so they're decrypted on sync
+    Unlock,
+    /// Link files added with --symlink back into place, as on a Mac the
+    /// dotfiles dir was just cloned to
+    Relink {
+        /// Replace files and links in the way, keeping files in backups
+        #[arg(short, long)]
+        force: bool,
+    },
+    /// List the versions of a tracked file kept in ~/.kiwi/history
+    History {
+        /// Path or name of the tracked file
+        path: PathBuf,
+    },
+    /// Put a tracked file back to a version from `kiwi history`
+    Rollback {
+        /// Path or name of the tracked file
+        path: PathBuf,
+        /// Version number, or the start of its hash
+        #[arg(long, value_name = "VERSION")]
+        to: String,
+        /// Don't ask after showing the changes
+        #[arg(short, long)]
+        yes: bool,
+    },
     /// Update packages and configurations
     Update {
         /// Update all dependencies and packages
@@ -132,6 +428,12 @@ pub enum Commands {
         /// Show changelog when available
         #[arg(short, long)]
         changelog: bool,
+        /// Upgrade outdated casks
+        #[arg(long)]
+        casks: bool,
+        /// Also upgrade casks that update themselves or use `version :latest`
+        #[arg(short, long, requires = "casks")]
+        greedy: bool,
     },
     /// Install packages via Homebrew
     Install {
@@ -144,8 +446,11 @@ pub enum Commands {
         #[arg(short, long)]
         tap: Option<String>,
         /// Install a specific version
-        #[arg(short, long)]
-        version: Option<String>,
+        #[arg(long, value_name = "VERSION")]
+        package_version: Option<String>,
+        /// Why this package is installed
+        #[arg(long)]
+        note: Option<String>,
     },
     /// List managed dotfiles and packages
     List {
@@ -158,6 +463,21 @@ pub enum Commands {
         /// Output in JSON format
         #[arg(short, long)]
         json: bool,
+        /// Only show items whose name, path, or note contains this
+        #[arg(short, long)]
+        filter: Option<String>,
+        /// Sort order; size and updated show the largest and newest first
+        #[arg(short, long, value_enum, default_value_t = ListSort::Name)]
+        sort: ListSort,
+        /// Only packages with updates, and dotfiles changed since the last push
+        #[arg(long)]
+        outdated_only: bool,
+        /// Only packages in the sync manifest, and dotfiles whose current contents were pushed
+        #[arg(long)]
+        synced_only: bool,
+        /// Leave out titles and table headers, for scripting
+        #[arg(long)]
+        no_header: bool,
     },
     /// Manage global configuration
     Config {
@@ -175,6 +495,45 @@ pub enum Commands {
         #[arg(short, long)]
         import: Option<PathBuf>,
     },
+    /// Show tracked dotfiles, whether they've drifted, and their content hashes
+    Status,
+    /// Keep running and push tracked dotfiles when they change, reloading config edits as they happen
+    Watch {
+        /// Run watch in the background from login, as a launchd agent
+        #[arg(long, conflicts_with = "uninstall")]
+        install: bool,
+        /// Stop the launchd agent and remove it
+        #[arg(long)]
+        uninstall: bool,
+        /// Don't push just because files matching this pattern changed
+        /// (supports * and ?; patterns with a / match the whole path)
+        #[arg(long, value_name = "PATTERN", conflicts_with = "unignore")]
+        ignore: Option<String>,
+        /// Remove a pattern added with --ignore
+        #[arg(long, value_name = "PATTERN")]
+        unignore: Option<String>,
+    },
+    /// Compare a tracked dotfile with its last-synced and remote versions
+    Diff {
+        /// Tracked file path, or its name in the dotfiles dir
+        file: String,
+        /// Which version to compare the local file with
+        #[arg(short, long, value_enum, default_value_t = DiffAgainst::All)]
+        against: DiffAgainst,
+        /// Show the two versions in columns
+        #[arg(short = 'y', long)]
+        side_by_side: bool,
+        /// Open an external diff tool instead, e.g. "opendiff" or "code --diff --wait"
+        #[arg(long, conflicts_with = "side_by_side")]
+        tool: Option<String>,
+    },
+    /// Remove expired backups, orphaned links, stale cache entries, and old file versions
+    #[command(alias = "prune")]
+    Gc {
+        /// Only show what would be removed
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+    },
     /// Check system health and configuration status
     Doctor {
         /// Fix detected issues automatically
@@ -183,17 +542,571 @@ pub enum Commands {
         /// Generate a report
         #[arg(short, long)]
         report: bool,
+        /// Run fixes without asking first (the commands are still shown and logged)
+        #[arg(short = 'y', long, requires = "fix")]
+        yes: bool,
+    },
+    /// Link a new machine to your account without typing a password
+    Link {
+        /// Code displayed by `kiwi link` on an already-authenticated machine
+        #[arg(short, long)]
+        code: Option<String>,
+    },
+    /// Show recent pushes from all your machines: who pushed, when, and what changed
+    Activity {
+        /// How many of the latest pushes to show
+        #[arg(short = 'n', long, default_value_t = 20)]
+        limit: usize,
+        /// Only show pushes from this machine
+        #[arg(short, long)]
+        machine: Option<String>,
+    },
+    /// Export the audit trail of changes and syncs
+    Audit {
+        #[command(subcommand)]
+        action: AuditCommands,
+    },
+    /// Check or install what the project you're in needs, per its .kiwi.toml
+    Project {
+        #[command(subcommand)]
+        action: ProjectCommands,
+    },
+    /// Tools for people who maintain their own Homebrew taps
+    TapMaintainer {
+        #[command(subcommand)]
+        action: TapMaintainerCommands,
+    },
+    /// Show or check the policy your organization deployed to this Mac
+    Policy {
+        #[arg(value_enum, default_value = "show")]
+        action: PolicyAction,
+    },
+    /// Manage your remote account and the data stored for it
+    Account {
+        #[command(subcommand)]
+        action: AccountCommands,
+    },
+    /// Manage shell aliases and functions in a synced, kiwi-owned file
+    Alias {
+        #[command(subcommand)]
+        action: AliasCommands,
+    },
+    /// Capture and apply settings of apps kiwi knows, like iTerm2, Raycast, and Karabiner
+    Apps {
+        #[command(subcommand)]
+        action: AppCommands,
+    },
+    /// Capture your crontab and LaunchAgents so they sync, and restore them after review
+    Jobs {
+        #[command(subcommand)]
+        action: JobCommands,
+    },
+    /// Manage /etc/hosts entries, like dev domains, that sync with your environment
+    Hosts {
+        #[command(subcommand)]
+        action: HostCommands,
+    },
+    /// Apply curated macOS settings, like key repeat, tap to click, and Dock autohide
+    Tweaks {
+        #[command(subcommand)]
+        action: TweakCommands,
+    },
+    /// Capture and re-apply DNS and proxy settings per network location
+    Network {
+        #[command(subcommand)]
+        action: NetworkCommands,
+    },
+    /// Set up an SSH key on this Mac: generate one if needed, add it to the agent and Keychain, and copy it
+    SshKey {
+        /// Also register the key with GitHub, using $GITHUB_TOKEN or a token you paste
+        #[arg(long)]
+        github: bool,
+    },
+    /// Manage exported environment variables, per environment and with Keychain-backed secrets
+    Envvar {
+        #[command(subcommand)]
+        action: EnvvarCommands,
+    },
+    /// Browse and install shared dotfile templates
+    Templates {
+        #[command(subcommand)]
+        action: TemplateCommands,
+    },
+    /// List, inspect, and switch environments
+    Env {
+        #[command(subcommand)]
+        action: EnvCommands,
+    },
+    /// Open an interactive dashboard of dotfiles, packages, and sync status
+    Ui,
+    /// Exclude packages from syncing (supports * and ? wildcards)
+    Exclude {
+        /// Package name or pattern; lists exclusions when omitted
+        pattern: Option<String>,
+        /// Remove the pattern from the exclusion list
+        #[arg(short, long, requires = "pattern")]
+        remove: bool,
+    },
+    /// Attach a note explaining why a package is installed
+    Note {
+        /// Package name
+        package: String,
+        /// Note text; clears the note when omitted
+        note: Option<String>,
+    },
+    /// Add commands a restore runs after installing a package, like a
+    /// shell integration installer; lists them when none are given
+    Hook {
+        /// Package name
+        package: String,
+        /// Commands to add, each run with `sh -c`
+        commands: Vec<String>,
+        /// Remove the package's commands
+        #[arg(long, conflicts_with = "commands")]
+        clear: bool,
+    },
+    /// Hold a package at its current version so updates skip it
+    Hold {
+        /// Package name
+        package: String,
+    },
+    /// Release a held package so it updates again
+    Unhold {
+        /// Package name
+        package: String,
+    },
+    /// Show brew and kiwi metadata for a package
+    Info {
+        /// Package name
+        package: String,
+        /// Output in JSON format
+        #[arg(short, long)]
+        json: bool,
+    },
+    /// Show where a tool on your PATH comes from, what it shadows, and whether kiwi tracks it
+    Which {
+        /// Name of the executable, e.g. python3
+        tool: String,
+        /// Output in JSON format
+        #[arg(short, long)]
+        json: bool,
+    },
+    /// Compare installed packages with the manifest and adopt, exclude, or reinstall
+    Reconcile {
+        /// Only show the differences
+        #[arg(short, long)]
+        dry_run: bool,
+    },
+    /// Uninstall dependencies nothing needs anymore, keeping what kiwi knows you want
+    Autoremove {
+        /// Only show what would be removed
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+        /// Don't ask before uninstalling
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// Export the full environment as JSON, YAML, or a markdown setup document
+    Export {
+        /// Output format
+        #[arg(short, long, value_enum, default_value_t = ExportFormat::Markdown)]
+        format: ExportFormat,
+        /// Write to a file instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Bundle manifests and dotfile contents into a .tar.zst archive
+        #[arg(long, conflicts_with_all = ["format", "output"])]
+        archive: Option<PathBuf>,
+        /// Encrypt the archive with a passphrase
+        #[arg(long, requires = "archive")]
+        encrypt: bool,
+        /// Write the environment to kiwi-environment.json and a GitHub Actions
+        /// workflow (to --output, or .github/workflows/kiwi.yml) that checks
+        /// it still installs
+        #[arg(long, conflicts_with_all = ["format", "archive"])]
+        github_action: bool,
+    },
+    /// Check that an environment exported as JSON or YAML installs, as CI does
+    Check {
+        /// The exported environment
+        #[arg(default_value = export::MANIFEST_FILE)]
+        manifest: PathBuf,
+        /// Add its taps and install what's missing, rather than only looking
+        /// its packages up
+        #[arg(long)]
+        install: bool,
+    },
+    /// Restore an environment from an archive created by `kiwi export --archive`,
+    /// or bring configs and packages over from a Time Machine backup or another account
+    Import {
+        /// Archive to restore from
+        #[arg(long, required_unless_present_any = ["from_backup", "from_home"])]
+        archive: Option<PathBuf>,
+        /// Time Machine backup (or a home folder inside one) to import from
+        #[arg(long, conflicts_with_all = ["archive", "from_home"])]
+        from_backup: Option<PathBuf>,
+        /// Another account's home folder on this Mac to import from (may need sudo)
+        #[arg(long, conflicts_with = "archive")]
+        from_home: Option<PathBuf>,
+        /// Install the archived taps and packages after restoring files
+        #[arg(short, long)]
+        install: bool,
+        /// Write the archived files without listing them for confirmation first
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// Send this Mac's environment to another Mac on the same network
+    Send {
+        /// Port to listen on (defaults to any free port)
+        #[arg(long, default_value_t = 0)]
+        port: u16,
+    },
+    /// Receive an environment from a Mac running `kiwi send`
+    Receive {
+        /// Connect to this host:port instead of browsing with Bonjour
+        #[arg(long)]
+        from: Option<String>,
+        /// Install the received taps and packages after restoring files
+        #[arg(short, long)]
+        install: bool,
+        /// Write the received files without listing them for confirmation first
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// Remove kiwi from this Mac: tracked files stay as real files; its state,
+    /// shell hooks, background agent, and Keychain entries go
+    Teardown {
+        /// Also delete everything stored on the sync server for your account
+        #[arg(long)]
+        delete_remote: bool,
+        /// Move the backups area to ~/kiwi-backups instead of deleting it
+        #[arg(long)]
+        keep_backups: bool,
+        /// Skip the confirmation prompt
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// Generate or install shell completion scripts
+    Completions {
+        /// Shell to generate completions for (defaults to $SHELL)
+        #[arg(value_enum)]
+        shell: Option<clap_complete::Shell>,
+        /// Install the script and wire up the shell's rc file if needed
+        #[arg(short, long, conflicts_with = "uninstall")]
+        install: bool,
+        /// Remove previously installed completions
+        #[arg(short, long)]
+        uninstall: bool,
+    },
+    /// Generate man pages for kiwi and all subcommands
+    Manpages {
+        /// Directory to write the man pages to
+        #[arg(short, long, default_value = "man")]
+        out_dir: PathBuf,
+    },
+    /// Search formulae, casks, and the Mac App Store
+    Search {
+        /// Search term
+        query: String,
+        /// Only print results, don't offer to install
+        #[arg(long)]
+        no_install: bool,
+    },
+    /// Manage anonymous usage telemetry (off unless you opt in)
+    Telemetry {
+        #[arg(value_enum, default_value = "status")]
+        action: TelemetryAction,
+    },
+    /// Collect diagnostics into a zip to attach to bug reports
+    DebugBundle {
+        /// Where to write the bundle
+        #[arg(short, long, default_value = "kiwi-debug-bundle.zip")]
+        output: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum AccountCommands {
+    /// Download everything stored server-side for your account
+    Export {
+        /// Where to write the archive
+        #[arg(short, long, default_value = "kiwi-account-export.zip")]
+        output: PathBuf,
+    },
+    /// Show how much server storage your synced files use
+    Usage {
+        /// How many of the largest files to list
+        #[arg(short, long, default_value_t = 10)]
+        top: usize,
+    },
+    /// Delete all remote data and local credentials
+    Delete {
+        /// Skip confirmation prompt
+        #[arg(short, long)]
+        force: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum AuditCommands {
+    /// Write every journaled command on this Mac and every push from all
+    /// your machines to a JSONL file, signed with an SSH key
+    Export {
+        /// Where to write the trail; the signature goes next to it as <OUTPUT>.sig
+        #[arg(short, long, default_value = "kiwi-audit.jsonl")]
+        output: PathBuf,
+        /// Only include records from this date on (YYYY-MM-DD)
+        #[arg(long)]
+        since: Option<String>,
+        /// SSH private key to sign with; defaults to the first key in ~/.ssh
+        #[arg(short, long)]
+        key: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ProjectCommands {
+    /// List what .kiwi.toml asks for that this Mac doesn't have, failing if
+    /// anything is missing
+    Check,
+    /// Install the packages .kiwi.toml asks for that are missing
+    Install {
+        /// Don't ask before large downloads
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// Print the shell commands that switch to this project's PATH and
+    /// variables, or back out of the last project's; the hook evals this
+    Env,
+    /// Let the hook apply this project's PATH and variables, as .kiwi.toml is now
+    Allow,
+    /// Stop applying this project's PATH and variables
+    Deny,
+    /// Activate projects' environments as you cd into them, from your shell's rc file
+    Hook,
+}
+
+#[derive(Subcommand)]
+pub enum TapMaintainerCommands {
+    /// List the formulae and casks in a tap that are behind their upstream
+    /// releases, per `brew livecheck`
+    Outdated {
+        /// Tap to check, as user/repo
+        tap: String,
+        /// Output every formula and cask in the tap as JSON, for automation
+        #[arg(short, long)]
+        json: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum AliasCommands {
+    /// Add or replace an alias
+    Add {
+        name: String,
+        /// Command the alias runs
+        command: String,
+        /// Define a shell function instead, so the command can use "$@" and "$1"
+        #[arg(short, long)]
+        function: bool,
+    },
+    /// List managed aliases
+    List,
+    /// Remove an alias
+    Remove {
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum HostCommands {
+    /// Point hostnames at an address, e.g. `kiwi hosts add 127.0.0.1 myapp.test`
+    Add {
+        address: std::net::IpAddr,
+        #[arg(required = true)]
+        names: Vec<String>,
+        /// Only apply the entry in this environment
+        #[arg(short, long)]
+        env: Option<String>,
+    },
+    /// List managed entries and whether /etc/hosts has them
+    List,
+    /// Remove a hostname
+    Remove {
+        name: String,
+    },
+    /// Write the entries for the current environment to /etc/hosts (uses sudo)
+    Apply,
+}
+
+#[derive(Subcommand)]
+pub enum SeedCommands {
+    /// Download every tracked package's bottles and casks into a directory
+    /// you can carry to another Mac, for `kiwi restore --seed`
+    Create {
+        dir: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum TweakCommands {
+    /// List the recipes kiwi knows and whether they're applied on this Mac
+    List,
+    /// Apply recipes and record them so they sync (the recorded ones when none are named)
+    Apply {
+        names: Vec<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum NetworkCommands {
+    /// List the locations captured for the current environment
+    List,
+    /// Save the current location's DNS servers, search domains, and proxies
+    Capture,
+    /// Apply a captured location's settings (the current location when omitted)
+    Apply {
+        location: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum AppCommands {
+    /// List the apps kiwi knows and whether their settings are captured
+    List,
+    /// Export an app's settings to a tracked file so they sync (asks which when none are named)
+    Capture {
+        names: Vec<String>,
+    },
+    /// Import captured settings into the apps on this Mac (all captured ones when none are named)
+    Apply {
+        names: Vec<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum JobCommands {
+    /// Show the crontab and LaunchAgents on this Mac and which are captured
+    List,
+    /// Copy the crontab and chosen LaunchAgents to tracked files so they sync
+    Capture,
+    /// Review each captured job, then install and load the ones you approve
+    Restore,
+}
+
+#[derive(Subcommand)]
+pub enum EnvvarCommands {
+    /// Set or replace a variable
+    Set {
+        name: String,
+        /// Value to export (prompted for when --secret is given without one)
+        value: Option<String>,
+        /// Only export it in this environment
+        #[arg(short, long)]
+        env: Option<String>,
+        /// Store the value in the Keychain; only a lookup is synced
+        #[arg(short, long)]
+        secret: bool,
+    },
+    /// Remove a variable
+    Unset {
+        name: String,
+        /// Remove the value for this environment instead of the shared one
+        #[arg(short, long)]
+        env: Option<String>,
+    },
+    /// List variables
+    List {
+        /// Only show variables exported in this environment
+        #[arg(short, long)]
+        env: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum TemplateCommands {
+    /// Search the registry by name or description (lists everything without a query)
+    Search {
+        query: Option<String>,
+    },
+    /// Review a template's changes as a diff, then apply them
+    Install {
+        name: String,
+        /// Apply without asking after showing the diff
+        #[arg(short = 'y', long)]
+        yes: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum EnvCommands {
+    /// List known environments, marking the active one
+    List,
+    /// Show an environment's package bundle and templated dotfiles
+    Show {
+        /// Environment to show (defaults to the active one)
+        name: Option<String>,
+    },
+    /// Switch the active environment, re-render templated dotfiles, and put
+    /// its dotfiles in place of other environments'
+    #[command(visible_alias = "switch")]
+    Use {
+        /// Environment to switch to
+        name: String,
+        /// Install packages from the environment's bundle
+        #[arg(short, long)]
+        install: bool,
+        /// Uninstall packages that only the previous environment's bundle uses
+        #[arg(short, long)]
+        prune: bool,
+        /// Skip confirmation prompts
+        #[arg(short = 'y', long)]
+        yes: bool,
     },
 }
 
 impl Cli {
+    /// Whether the command needs an authenticated session before it can run.
+    pub fn requires_auth(&self) -> bool {
+        !matches!(self.command, Commands::Link { code: Some(_) } | Commands::Manpages { .. } | Commands::Completions { .. } | Commands::Status | Commands::Diff { .. } | Commands::Send { .. } | Commands::Receive { .. } | Commands::Gc { .. } | Commands::Teardown { delete_remote: false, .. } | Commands::SshKey { .. } | Commands::Env { .. } | Commands::DebugBundle { .. } | Commands::Telemetry { .. } | Commands::Audit { .. } | Commands::Policy { .. } | Commands::Relink { .. } | Commands::Unlock | Commands::History { .. } | Commands::Rollback { .. } | Commands::Project { .. } | Commands::Check { .. } | Commands::TapMaintainer { .. } | Commands::Which { .. } | Commands::Autoremove { .. })
+    }
+
+    /// Whether this is a bare `kiwi init` at a terminal, which runs the
+    /// guided setup instead of acting on flags.
+    pub fn wants_wizard(&self) -> bool {
+        use std::io::IsTerminal;
+        matches!(&self.command, Commands::Init { restore: false, env: None, sync_homebrew: false, yes: false, starter, .. } if starter.is_empty())
+            && io::stdin().is_terminal()
+    }
+
+    /// The wizard's first question, asked before login since local mode
+    /// needs no account: sync through the kiwi server, or keep everything on
+    /// this Mac. Does nothing outside the wizard or once logged in.
+    pub fn choose_sync_mode(&self, config: &mut Config) -> Result<()> {
+        if !self.wants_wizard() || config.sync_token.is_some() {
+            return Ok(());
+        }
+        println!("{}", "🥝 Welcome to Kiwi! Let's set up this Mac.".success().bold());
+        let modes = [
+            "Cloud sync - keep this Mac in sync with your other Macs (needs an account)",
+            "Local only - manage this Mac's setup without an account",
+        ];
+        let chosen = Select::with_theme(&ColorfulTheme::default())
+            .with_prompt("How should kiwi keep your setup?")
+            .items(&modes)
+            .default(0)
+            .interact()
+            .map_err(|e| KiwiError::InvalidCommand(format!("Failed to read selection: {}", e)))?;
+        config.sync_url = match chosen {
+            0 => config.sync_url.clone().or_else(|| Config::default().sync_url),
+            _ => None,
+        };
+        config.save()
+    }
+
     pub async fn execute(&self) -> Result<()> {
-        let mut config = Config::load()?;
-        let mut homebrew = Homebrew::new(config.dotfiles_dir.join("packages.json"));
-        let dotfiles = Dotfiles::new(
-            config.dotfiles_dir.clone(),
-            config.dotfiles_dir.join("dotfiles.json"),
-        );
+        let ops::Context { mut config, dotfiles, mut homebrew, sync } = ops::Context::load()?;
+        crate::theme::init(&config.preferences);
 
         // Set up progress indicators
         let multi_progress = MultiProgress::new();
@@ -201,28 +1114,13 @@ impl Cli {
             .tick_chars("⠋⠙⠹⠸⠼⠴⠦⠧⠇⠏")
             .template(SPINNER_TEMPLATE)
             .unwrap();
-        let progress_style = ProgressStyle::default_bar()
-            .template(PROGRESS_TEMPLATE)
-            .unwrap()
-            .progress_chars(PROGRESS_CHARS);
-
-        // Clone the values we need before creating sync
-        let sync_url = config.sync_url.clone();
-        let sync_token = config.sync_token.clone();
-        let dotfiles_dir = config.dotfiles_dir.clone();
-
-        let sync = if let (Some(url), Some(token)) = (sync_url, sync_token) {
-            Some(Sync::new(
-                crate::sync::SyncConfig { url, token },
-                dotfiles_dir,
-            ))
-        } else {
-            None
-        };
 
         match &self.command {
-            Commands::Init { restore, env, env_name, sync_homebrew, yes } => {
-                println!("{}", "🥝 Welcome to Kiwi - The Ultimate macOS Environment Manager".green().bold());
+            Commands::Init { restore, env, env_name, sync_homebrew, yes, starter } => {
+                println!("{}", "🥝 Welcome to Kiwi - The Ultimate macOS Environment Manager".success().bold());
+                if self.wants_wizard() {
+                    return init_wizard(&mut config, &dotfiles, &mut homebrew, sync.as_ref()).await;
+                }
                 let spinner = multi_progress.add(ProgressBar::new_spinner());
                 spinner.set_style(spinner_style.clone());
                 spinner.set_prefix("[Init]");
@@ -237,10 +1135,41 @@ impl Cli {
                         env_type.to_string()
                     };
                     spinner.set_message(format!("Setting environment: {}", env_value));
-                    config.set("environment", env_value)?;
+                    let switch = ops::use_environment(&mut config, &mut homebrew, &env_value, false, false, &CliReporter::new(Some(&spinner), *yes))?;
+                    if !switch.files.applied.is_empty() || !switch.files.parked.is_empty() {
+                        spinner.println(format!(
+                            "{} {} dotfiles applied, {} from other environments put away",
+                            "✓".success(),
+                            switch.files.applied.len(),
+                            switch.files.parked.len()
+                        ));
+                    }
                     spinner.tick();
                 }
 
+                let packs: Vec<&starters::Pack> = if !starter.is_empty() {
+                    starter
+                        .iter()
+                        .map(|name| starters::find(name).ok_or_else(|| KiwiError::InvalidCommand(format!("Unknown starter pack: {}", name))))
+                        .collect::<Result<_>>()?
+                } else if *yes {
+                    Vec::new()
+                } else {
+                    let labels: Vec<String> = starters::PACKS
+                        .iter()
+                        .map(|pack| format!("{} - {}", pack.name, pack.description))
+                        .collect();
+                    let chosen = spinner.suspend(|| {
+                        MultiSelect::with_theme(&ColorfulTheme::default())
+                            .with_prompt("Add starter configs? (space to toggle, enter to skip or confirm)")
+                            .items(&labels)
+                            .interact()
+                    }).map_err(|e| KiwiError::InvalidCommand(format!("Failed to read selection: {}", e)))?;
+                    chosen.into_iter().map(|i| &starters::PACKS[i]).collect()
+                };
+
+                apply_starter_packs(&packs, &config, &dotfiles, &mut homebrew, &CliReporter::new(Some(&spinner), *yes))?;
+
                 if *sync_homebrew {
                     spinner.set_message("Scanning Homebrew packages...");
                     let packages = homebrew.list_installed()?;
@@ -248,303 +1177,731 @@ impl Cli {
                     if packages.is_empty() {
                         spinner.finish_with_message("No Homebrew packages found to sync.");
                     } else {
-                        let pb = multi_progress.add(ProgressBar::new(packages.len() as u64));
-                        pb.set_style(progress_style.clone());
-                        pb.set_prefix("[Packages]");
-                        
-                        for package in &packages {
-                            pb.set_message(format!("Processing {}", package.name));
-                            pb.inc(1);
-                            std::thread::sleep(Duration::from_millis(50)); // Simulate work
-                        }
-                        
-                        if !*yes {
-                            pb.finish_and_clear();
-                            print!("\n{}", "Do you want to sync these packages? [y/N]: ".blue());
-                            io::stdout().flush()?;
-                            
-                            let mut input = String::new();
-                            io::stdin().read_line(&mut input)?;
-                            
-                            if !input.trim().eq_ignore_ascii_case("y") {
-                                println!("{}", "Skipping package sync".yellow());
-                                return Ok(());
-                            }
+                        let selected = if *yes {
+                            packages
+                        } else {
+                            spinner.suspend(|| select_packages(packages))?
+                        };
+
+                        if selected.is_empty() {
+                            println!("{}", "Skipping package sync".warning());
+                            return Ok(());
                         }
-                        
-                        spinner.set_message("Syncing Homebrew packages...");
-                        homebrew.save_packages(&packages)?;
-                        spinner.finish_with_message("✓ Homebrew packages synced successfully".green().to_string());
+
+                        spinner.set_message(format!("Syncing {} Homebrew packages...", selected.len()));
+                        homebrew.save_packages(&selected)?;
+                        homebrew.save_taps(&homebrew.list_taps()?)?;
+                        spinner.finish_with_message("✓ Homebrew packages synced successfully".success().to_string());
                     }
                 }
 
                 if *restore {
                     spinner.set_message("Restoring from backup...");
                     if let Some(sync) = &sync {
-                        sync.pull(true).await?;
-                        spinner.finish_with_message("✓ Restore completed successfully".green().to_string());
+                        ops::restore(&config, sync, &CliReporter::new(Some(&spinner), *yes)).await?;
+                        spinner.finish_with_message("✓ Restore completed successfully".success().to_string());
                     }
                 }
                 
-                spinner.finish_with_message("✨ Initialization complete! Your environment is ready.".green().bold().to_string());
+                spinner.finish_with_message("✨ Initialization complete! Your environment is ready.".success().bold().to_string());
+            },
+            Commands::Restore { plan, output, seed, no_hooks, yes } => {
+                let Some(sync) = &sync else {
+                    println!("{}", "Sync not configured. Please set sync_url and sync_token in config.".error());
+                    return Ok(());
+                };
+                if *plan {
+                    let plan = ops::restore_plan(&config, sync).await?;
+                    print_restore_plan(&plan);
+                    if let Some(output) = output {
+                        std::fs::write(output, serde_json::to_string_pretty(&plan)?)?;
+                        println!("{} {}", "✓ Plan saved to".success(), output.display());
+                    }
+                    return Ok(());
+                }
+                let spinner = multi_progress.add(ProgressBar::new_spinner());
+                spinner.set_style(spinner_style.clone());
+                spinner.set_prefix("[Restore]");
+                spinner.enable_steady_tick(Duration::from_millis(100));
+                let options = ops::RestoreOptions { seed: seed.clone(), skip_hooks: *no_hooks };
+                ops::restore_with(&config, sync, options, &CliReporter::new(Some(&spinner), *yes)).await?;
+                spinner.finish_with_message("✓ Restore completed successfully".success().to_string());
             },
-            Commands::Sync { pull, push, prefer_local, force, diff } => {
-                println!("{}", "Syncing configurations...".blue().bold());
+            Commands::Seed { action } => match action {
+                SeedCommands::Create { dir } => {
+                    let spinner = multi_progress.add(ProgressBar::new_spinner());
+                    spinner.set_style(spinner_style.clone());
+                    spinner.set_prefix("[Seed]");
+                    spinner.enable_steady_tick(Duration::from_millis(100));
+                    let manifest = ops::create_seed(&config, dir, &CliReporter::new(Some(&spinner), false))?;
+                    spinner.finish_with_message(
+                        format!("✓ Downloaded {} package(s) into {}", manifest.packages.len(), dir.display()).success().to_string(),
+                    );
+                    if !manifest.failed.is_empty() {
+                        println!("{} {}", "Couldn't download:".warning(), manifest.failed.join(", "));
+                    }
+                    println!("Restore from it with `kiwi restore --seed {}`", dir.display());
+                }
+            },
+            Commands::Sync { pull, push, prefer_local, force, diff, status } => {
+                if *status {
+                    let Some(sync) = &sync else {
+                        println!("{}", "Sync not configured. Please set sync_url and sync_token in config.".error());
+                        return Ok(());
+                    };
+                    let remote = sync.check_remote().await;
+                    print_remote_status(&remote);
+                    if !remote.is_healthy() {
+                        return Err(KiwiError::Sync("Sync server check failed".to_string()));
+                    }
+                    return Ok(());
+                }
+                println!("{}", "Syncing configurations...".accent().bold());
                 if let Some(sync) = &sync {
                     if *push {
-                        println!("{}", "Preparing to push to remote...".yellow());
-                        let packages = homebrew.list_installed()?;
+                        println!("{}", "Preparing to push to remote...".warning());
+                        let packages = ops::pushable_packages(&homebrew)?;
                         
                         if *diff {
-                            println!("\n{}", "Changes to be pushed:".blue());
+                            println!("\n{}", "Changes to be pushed:".accent());
                             // TODO: Implement diff view
-                            println!("  {}", "Packages:".yellow());
+                            println!("  {}", "Packages:".warning());
                             for package in &packages {
                                 println!("    + {}", package.name);
                             }
                         }
                         
-                        if !*force && !*diff {
-                            print!("\n{}", "Continue with push? [y/N]: ".blue());
-                            io::stdout().flush()?;
-                            let mut input = String::new();
-                            io::stdin().read_line(&mut input)?;
-                            if !input.trim().eq_ignore_ascii_case("y") {
-                                println!("{}", "Push cancelled".yellow());
-                                return Ok(());
-                            }
-                        }
-                        
-                        println!("\n{}", "Homebrew packages to sync:".yellow());
+                        println!("\n{}", "Homebrew packages to sync:".warning());
                         for package in &packages {
                             let version_str = package.version.as_deref().unwrap_or("latest");
                             println!("  {} ({})", package.name, version_str);
                         }
                         
-                        homebrew.save_packages(&packages)?;
-                        
-                        println!("{}", "\nPushing to remote...".yellow());
-                        sync.push().await?;
-                        println!("{}", "✓ Push complete".green());
+                        if *force {
+                            println!("{}", "Force pushing (overwriting remote changes)...".warning());
+                        }
+
+                        let reporter = CliReporter::new(None, *force || *diff);
+                        let options = PushOptions { force: *force };
+                        match ops::sync_push(&mut homebrew, &dotfiles, sync, &packages, options, &reporter).await {
+                            Err(KiwiError::UserCancelled) => println!("{}", "Push cancelled".warning()),
+                            result => print_sync_summary(&result?),
+                        }
                     } else if *pull {
                         if *diff {
-                            println!("\n{}", "Fetching remote changes...".blue());
+                            println!("\n{}", "Fetching remote changes...".accent());
                             // TODO: Implement remote diff view
                         }
                         
-                        println!("{} {}", "Pulling from remote...".yellow(), 
-                            if *prefer_local { "(preferring local files)" } else { "" });
+                        if *prefer_local {
+                            println!("{}", "Preferring local files".warning());
+                        }
                         
                         if *force {
-                            println!("{}", "Force pulling (overwriting local changes)...".yellow());
+                            println!("{}", "Force pulling (overwriting local changes)...".warning());
                         }
                         
-                        sync.pull(*prefer_local).await?;
-                        println!("{}", "✓ Pull complete".green());
+                        let options = PullOptions { prefer_local: *prefer_local, force: *force, ..Default::default() };
+                        let summary = ops::sync_pull(&dotfiles, sync, options, &CliReporter::new(None, *force)).await?;
+                        print_sync_summary(&summary);
                     } else {
-                        println!("{}", "Please specify --push or --pull".red());
+                        let packages = ops::pushable_packages(&homebrew)?;
+                        let reporter = CliReporter::new(None, false);
+                        let options = MergeOptions { prefer_local: *prefer_local, ..Default::default() };
+                        let (merged, summary) = ops::sync_both(&mut homebrew, &dotfiles, sync, &packages, options, &reporter).await?;
+                        print_merge_summary(&merged);
+                        print_sync_summary(&summary);
                     }
                 } else {
-                    println!("{}", "Sync not configured. Please set sync_url and sync_token in config.".red());
+                    println!("{}", "Sync not configured. Please set sync_url and sync_token in config.".error());
+                }
+            },
+            Commands::Pull { only, prefer_local, force } => {
+                let Some(sync) = &sync else {
+                    println!("{}", "Sync not configured. Please set sync_url and sync_token in config.".error());
+                    return Ok(());
+                };
+                // Untracked items are taken as dotfiles dir names, so a file
+                // can be restored on a machine that doesn't track it yet
+                let mut names = Vec::new();
+                for item in only {
+                    names.push(match dotfiles.find(item)? {
+                        Some(dotfile) => dotfile.name(),
+                        None => item.clone(),
+                    });
                 }
+
+                let options = PullOptions { prefer_local: *prefer_local, force: *force, only: names.clone() };
+                let reporter = CliReporter::new(None, *force);
+                let summary = ops::sync_pull(&dotfiles, sync, options, &reporter).await?;
+                print_sync_summary(&summary);
+                ops::review_hosts(&config, &reporter);
+            },
+            Commands::Adopt { files } => {
+                let Some(sync) = &sync else {
+                    println!("{}", "Sync not configured. Please set sync_url and sync_token in config.".error());
+                    return Ok(());
+                };
+                let merged = ops::adopt(&dotfiles, sync, files, &CliReporter::new(None, false)).await?;
+                print_merge_summary(&merged);
             },
-            Commands::Add { path, alias, symlink, no_backup } => {
-                println!("{} {}", "Adding file:".blue().bold(), path);
+            Commands::Add { path, alias, symlink, no_backup, force, pointer, template, mode, sudo, no_xattrs, exclude, env, encrypt } => {
+                println!("{} {}", "Adding file:".accent().bold(), path);
+                if *encrypt && ops::encryption_key(&config).is_none() {
+                    println!("{}", "Encrypted dotfiles need a passphrase; it's kept in the Keychain and asked for once on each Mac.".warning());
+                    let passphrase = Password::with_theme(&ColorfulTheme::default())
+                        .with_prompt("Passphrase for encrypted dotfiles")
+                        .with_confirmation("Confirm passphrase", "Passphrases don't match")
+                        .interact()
+                        .map_err(|e| KiwiError::InvalidCommand(format!("Failed to read passphrase: {}", e)))?;
+                    crate::keychain::Keychain::default().set(ops::PASSPHRASE_ACCOUNT, &passphrase)?;
+                }
                 
+                let pattern = path;
                 let path = PathBuf::from(path);
-                if !*no_backup && path.exists() {
-                    let backup_path = path.with_extension("backup");
-                    println!("{} {}", "Creating backup:".yellow(), backup_path.display());
-                    std::fs::copy(&path, &backup_path)?;
+                let options = ops::AddOptions {
+                    alias: alias.clone(),
+                    backup: !*no_backup,
+                    size_limit: config.large_file_limit(),
+                    force: *force,
+                    pointer: *pointer,
+                    mode: match (mode, template) {
+                        (Some(AddMode::Copy), _) => LinkMode::Copy,
+                        (Some(AddMode::Hardlink), _) => LinkMode::Hardlink,
+                        (Some(AddMode::Render), _) | (None, true) => LinkMode::Render,
+                        (Some(AddMode::Symlink), _) => LinkMode::Symlink,
+                        (None, false) if *symlink => LinkMode::Stow,
+                        (None, false) if *encrypt => LinkMode::Encrypt,
+                        (None, false) => LinkMode::Symlink,
+                    },
+                    sudo: *sudo,
+                    skip_xattrs: *no_xattrs,
+                    environments: env.clone(),
+                };
+                if crate::dotfiles::is_glob(pattern) {
+                    let summary = ops::add_matching(&dotfiles, pattern, exclude, options, &CliReporter::new(None, false))?;
+                    for added in &summary.added {
+                        println!("  {} {}", "+".success(), added.display());
+                    }
+                    for (failed, reason) in &summary.failed {
+                        println!("  {} {}: {}", "✗".error(), failed.display(), reason);
+                    }
+                    if !summary.tracked.is_empty() {
+                        println!("  {}", format!("{} already tracked", summary.tracked.len()).dimmed());
+                    }
+                    println!("{} {} of {} matching files", "✓ Added".success(), summary.added.len(), summary.added.len() + summary.failed.len() + summary.tracked.len());
+                    return Ok(());
                 }
-                
-                dotfiles.add(path.as_path(), alias.clone())?;
-                
+                if !exclude.is_empty() {
+                    return Err(KiwiError::ValidationError("--exclude only applies to glob patterns".to_string()));
+                }
+                match ops::add_dotfile(&dotfiles, &path, options, &CliReporter::new(None, false)) {
+                    Err(KiwiError::UserCancelled) => {
+                        println!("{}", "Add cancelled".warning());
+                        return Ok(());
+                    },
+                    result => result?,
+                };
+
                 if *symlink {
-                    println!("{}", "Creating symlink...".yellow());
-                    // TODO: Implement symlink creation
+                    let target = dotfiles.find(&path.to_string_lossy())?.map(|d| config.dotfiles_dir.join(d.name()));
+                    if let Some(target) = target {
+                        println!("{} {} → {}", "Linked".accent(), path.display(), target.display());
+                    }
+                }
+                if path.is_dir() {
+                    let root = path.canonicalize()?;
+                    let count = dotfiles.list()?.iter().filter(|d| d.member && d.path.starts_with(&root)).count();
+                    println!("{} {} files in {}", "✓ Tracking".success(), count, root.display());
+                } else {
+                    println!("{}", "✓ File added successfully".success());
                 }
-                
-                println!("{}", "✓ File added successfully".green());
             },
             Commands::Remove { path, delete, force } => {
-                println!("{} {}", "Removing file:".blue().bold(), path);
+                println!("{} {}", "Removing file:".accent().bold(), path);
                 
                 let path = PathBuf::from(path);
                 
-                if *delete {
-                    if !*force {
-                        print!("{}", "Are you sure you want to delete the file? [y/N]: ".red());
-                        io::stdout().flush()?;
-                        let mut input = String::new();
-                        io::stdin().read_line(&mut input)?;
-                        if !input.trim().eq_ignore_ascii_case("y") {
-                            println!("{}", "Deletion cancelled".yellow());
-                            return Ok(());
-                        }
-                    }
-                    
-                    if path.exists() {
-                        std::fs::remove_file(&path)?;
-                        println!("{}", "File deleted".yellow());
-                    }
+                match ops::remove_dotfile(&dotfiles, &path, *delete, &CliReporter::new(None, *force)) {
+                    Ok(true) => println!("{}", "File deleted".warning()),
+                    Ok(false) => {},
+                    Err(KiwiError::UserCancelled) => {
+                        println!("{}", "Deletion cancelled".warning());
+                        return Ok(());
+                    },
+                    Err(e) => return Err(e),
                 }
-                
-                dotfiles.remove(path.as_path())?;
-                println!("{}", "✓ File removed successfully".green());
+                println!("{}", "✓ File removed successfully".success());
+            },
+            Commands::Unlock => {
+                let passphrase = Password::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Passphrase for encrypted dotfiles")
+                    .interact()
+                    .map_err(|e| KiwiError::InvalidCommand(format!("Failed to read passphrase: {}", e)))?;
+                let count = ops::unlock(&dotfiles, &crate::keychain::Keychain::default(), &passphrase)?;
+                println!("{} {} encrypted dotfile(s) will decrypt on sync", "✓ Passphrase saved;".success(), count);
+            },
+            Commands::Relink { force } => {
+                let summary = dotfiles.relink(*force)?;
+                for path in &summary.linked {
+                    println!("  {} {}", "✓".success(), path.display());
+                }
+                for path in &summary.blocked {
+                    println!("  {} {} holds something else", "!".error(), path.display());
+                }
+                for path in &summary.missing {
+                    println!("  {} {} isn't in the dotfiles dir", "✗".error(), path.display());
+                }
+                if summary.linked.is_empty() && summary.blocked.is_empty() && summary.missing.is_empty() {
+                    println!("{}", "✓ Everything is linked".success());
+                    return Ok(());
+                }
+                println!("{} {} file(s)", "✓ Linked".success(), summary.linked.len());
+                if !summary.blocked.is_empty() {
+                    println!("Run {} to replace what's in the way; files are kept in backups", "kiwi relink --force".accent());
+                }
+            },
+            Commands::History { path } => {
+                let (dotfile, versions) = dotfiles.versions(path)?;
+                println!("{} {}", "History of".accent().bold(), dotfile.path.display());
+                for (i, version) in versions.iter().enumerate() {
+                    let when = chrono::DateTime::from_timestamp(version.recorded_at as i64, 0)
+                        .map(|t| t.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M").to_string())
+                        .unwrap_or_default();
+                    let current = if i + 1 == versions.len() { "current".success().to_string() } else { String::new() };
+                    println!(
+                        "  {:>3}  {}  {}  {:>8}  {}",
+                        i + 1,
+                        version.short_hash().dimmed(),
+                        when,
+                        table::format_size(Some(version.size)),
+                        current
+                    );
+                }
+                if versions.len() > 1 {
+                    println!("Roll back with {}", format!("kiwi rollback {} --to <version>", path.display()).accent());
+                }
+            },
+            Commands::Rollback { path, to, yes } => {
+                let (dotfile, version, contents) = dotfiles.version_contents(path, to)?;
+                let current = std::fs::read(dotfiles.source_for(&dotfile)).unwrap_or_default();
+                if current == contents {
+                    println!("{} {} already matches version {}", "✓".success(), dotfile.path.display(), version.short_hash());
+                    return Ok(());
+                }
+                match (String::from_utf8(current), String::from_utf8(contents.clone())) {
+                    (Ok(old), Ok(new)) => print_unified(&crate::diff::unified(
+                        &dotfile.path.display().to_string(),
+                        version.short_hash(),
+                        &old,
+                        &new,
+                    )),
+                    _ => println!("{}", "Binary files differ".dimmed()),
+                }
+
+                let reporter = CliReporter::new(None, *yes);
+                if !reporter.confirm(&format!("Roll {} back to {}?", dotfile.path.display(), version.short_hash())) {
+                    println!("{}", "Rollback cancelled".warning());
+                    return Ok(());
+                }
+                let dotfile = dotfiles.rollback(path, &contents)?;
+                if dotfile.is_template() {
+                    dotfiles.render_paths(std::slice::from_ref(&dotfile.path), &crate::template::variables(&config))?;
+                }
+                println!("{} {} to {}", "✓ Rolled back".success(), dotfile.path.display(), version.short_hash());
             },
-            Commands::Update { all: update_all, package, force, changelog } => {
-                println!("{}", "Updating packages...".blue().bold());
+            Commands::Update { all: update_all, package, force, changelog, casks, greedy } => {
+                println!("{}", "Updating packages...".accent().bold());
                 
                 if *force {
-                    println!("{}", "Force updating (skipping checks)...".yellow());
+                    println!("{}", "Force updating (skipping checks)...".warning());
                 }
                 
                 if *update_all {
-                    println!("{}", "Updating all packages...".yellow());
+                    println!("{}", "Updating all packages...".warning());
                     homebrew.update(None)?;
                 } else if let Some(pkg) = package {
-                    println!("{} {}", "Updating package:".yellow(), pkg);
+                    println!("{} {}", "Updating package:".warning(), pkg);
                     homebrew.update(Some(pkg))?;
                 }
+
+                if *casks {
+                    println!("{}", if *greedy { "Upgrading casks (greedy)..." } else { "Upgrading casks..." }.warning());
+                    let upgraded = homebrew.update_casks(*greedy)?;
+                    if upgraded.is_empty() {
+                        println!("{}", "All casks are up to date".success());
+                    } else {
+                        for cask in &upgraded {
+                            println!("  {} {}", "↑".success(), cask);
+                        }
+                    }
+                }
                 
                 if *changelog {
-                    println!("{}", "\nFetching changelogs...".blue());
+                    println!("{}", "\nFetching changelogs...".accent());
                     // TODO: Implement changelog fetching
                 }
                 
-                println!("{}", "✓ Update complete".green());
+                println!("{}", "✓ Update complete".success());
             },
-            Commands::Install { package, no_deps, tap, version } => {
-                println!("{} {}", "Installing package:".blue().bold(), package);
+            Commands::Install { package, no_deps, tap, package_version, note } => {
+                if config.policy.is_forbidden(package) {
+                    return Err(KiwiError::PackageError {
+                        name: package.clone(),
+                        message: "Forbidden by your organization's policy".to_string(),
+                    });
+                }
+                println!("{} {}", "Installing package:".accent().bold(), package);
                 
                 if let Some(tap_name) = tap {
-                    println!("{} {}", "Using tap:".yellow(), tap_name);
+                    println!("{} {}", "Using tap:".warning(), tap_name);
                     // TODO: Implement tap handling
                 }
                 
-                if let Some(ver) = version {
-                    println!("{} {}", "Installing version:".yellow(), ver);
+                if let Some(ver) = package_version {
+                    println!("{} {}", "Installing version:".warning(), ver);
                     // TODO: Implement version-specific installation
                 }
                 
                 if *no_deps {
-                    println!("{}", "Installing without dependencies...".yellow());
+                    println!("{}", "Installing without dependencies...".warning());
                     // TODO: Implement no-deps installation
                 }
                 
-                homebrew.install(package)?;
-                println!("{}", "✓ Installation complete".green());
+                let estimate = ops::estimate_sizes(&config, &homebrew, std::slice::from_ref(package)).await;
+                if let Some(size) = estimate.sizes.get(package).filter(|size| size.download.is_some()) {
+                    println!(
+                        "{} {} download, {} on disk",
+                        "Size:".accent(),
+                        table::format_size(size.download),
+                        table::format_size(size.installed)
+                    );
+                }
+                if !ops::confirm_download(&config, &estimate, &CliReporter::new(None, false)) {
+                    println!("{}", "Installation cancelled".warning());
+                    return Ok(());
+                }
+
+                homebrew.install(package, note.clone())?;
+                println!("{}", "✓ Installation complete".success());
             },
-            Commands::List { type_, detailed, json } => {
+            Commands::List { type_, detailed, json, filter, sort, outdated_only, synced_only, no_header } => {
                 if *json {
                     // TODO: Implement JSON output
-                    println!("{}", "JSON output not yet implemented".yellow());
+                    println!("{}", "JSON output not yet implemented".warning());
                     return Ok(());
                 }
-                
-                println!("{}", "Listing items...".blue().bold());
-                match type_ {
-                    ListType::Dotfiles => {
-                        println!("{}", "Managed dotfiles:".yellow());
-                        let dotfiles = dotfiles.list()?;
-                        for dotfile in dotfiles {
-                            if *detailed {
-                                println!("  Path: {}", dotfile.path.display());
-                                // TODO: Add more detailed information
-                            } else {
-                                println!("  {}", dotfile.path.display());
-                            }
-                        }
+
+                let filter = ops::ListFilter {
+                    query: filter.clone(),
+                    sort: match sort {
+                        ListSort::Name => ops::SortBy::Name,
+                        ListSort::Size => ops::SortBy::Size,
+                        ListSort::Updated => ops::SortBy::Updated,
                     },
-                    ListType::Packages => {
-                        println!("{}", "Installed packages:".yellow());
-                        let packages = homebrew.list_installed()?;
-                        for package in packages {
-                            if *detailed {
-                                let version = package.version.unwrap_or_else(|| "latest".to_string());
-                                println!("  {} ({})", package.name, version);
-                                // TODO: Add more package details
-                            } else {
-                                println!("  {}", package.name);
+                    outdated_only: *outdated_only,
+                    synced_only: *synced_only,
+                    sizes: *detailed,
+                };
+
+                let header = !*no_header;
+                if header {
+                    println!("{}", "Listing items...".accent().bold());
+                }
+                if matches!(type_, ListType::Dotfiles | ListType::All) {
+                    if header {
+                        println!("\n{}", "Managed dotfiles:".accent());
+                    }
+                    let listed = ops::list_dotfiles(&dotfiles, &filter)?;
+                    if *detailed {
+                        let hashes = dotfiles.hashes(&listed)?;
+                        let mut rows = table::Table::new(&["NAME", "PATH", "SIZE", "UPDATED", "SYNCED", "TAGS", "SYNC"]);
+                        for dotfile in &listed {
+                            let source = dotfiles.source_for(dotfile);
+                            let metadata = std::fs::metadata(&source).ok();
+                            let updated = metadata
+                                .as_ref()
+                                .and_then(|m| m.modified().ok())
+                                .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+                                .map(|d| d.as_secs());
+                            let mut tags = vec![dotfile.mode.to_string()];
+                            if dotfile.pointer {
+                                tags.push("pointer".to_string());
                             }
+                            rows.row(vec![
+                                dotfile.name(),
+                                dotfile.path.display().to_string(),
+                                table::format_size(metadata.map(|m| m.len())),
+                                table::format_date(updated),
+                                table::format_date(dotfile.last_synced),
+                                tags.join(","),
+                                dotfile.sync_state(hashes.get(&source)).to_string(),
+                            ]);
                         }
-                    },
-                    ListType::All => {
-                        println!("{}", "Listing all items...".yellow());
-                        let dotfiles = dotfiles.list()?;
-                        let packages = homebrew.list_installed()?;
-                        
-                        println!("\n{}", "Dotfiles:".blue());
-                        for dotfile in dotfiles {
-                            if *detailed {
-                                println!("  Path: {}", dotfile.path.display());
-                                // TODO: Add more detailed information
-                            } else {
-                                println!("  {}", dotfile.path.display());
-                            }
+                        print_table(&rows, header);
+                    } else {
+                        for dotfile in listed {
+                            println!("{}{}", if header { "  " } else { "" }, dotfile.path.display());
                         }
-                        
-                        println!("\n{}", "Packages:".blue());
-                        for package in packages {
-                            if *detailed {
-                                let version = package.version.unwrap_or_else(|| "latest".to_string());
-                                println!("  {} ({})", package.name, version);
-                                // TODO: Add more package details
+                    }
+                }
+                if matches!(type_, ListType::Packages | ListType::All) {
+                    if header {
+                        println!("\n{}", "Installed packages:".accent());
+                    }
+                    let listed = ops::list_packages(&homebrew, &filter)?;
+                    if *detailed {
+                        let tracked: Vec<String> = homebrew.tracked().into_iter().map(|p| p.name).collect();
+                        let mut rows = table::Table::new(&["NAME", "VERSION", "SIZE", "UPDATED", "TAGS", "SYNC", "NOTE"]);
+                        for package in listed {
+                            let tags: Vec<&str> = [(package.is_cask, "cask"), (package.held, "held"), (package.requires_rosetta, "rosetta")]
+                                .iter()
+                                .filter_map(|(set, tag)| set.then_some(*tag))
+                                .collect();
+                            let sync_status = if homebrew.is_excluded(&package.name) {
+                                "excluded"
+                            } else if tracked.contains(&package.name) {
+                                "synced"
                             } else {
-                                println!("  {}", package.name);
-                            }
+                                "-"
+                            };
+                            rows.row(vec![
+                                package.name,
+                                package.version.unwrap_or_else(|| "latest".to_string()),
+                                table::format_size(package.size),
+                                table::format_date(package.last_update.or(package.install_time)),
+                                if tags.is_empty() { "-".to_string() } else { tags.join(",") },
+                                sync_status.to_string(),
+                                package.note.unwrap_or_default(),
+                            ]);
                         }
-                    },
+                        print_table(&rows, header);
+                    } else {
+                        for package in listed {
+                            println!("{}{}", if header { "  " } else { "" }, package.name);
+                        }
+                    }
                 }
             },
             Commands::Config { key, value, reset, export, import } => {
-                println!("{}", "Managing configuration...".blue().bold());
+                println!("{}", "Managing configuration...".accent().bold());
                 
                 if *reset {
-                    println!("{}", "Resetting configuration to defaults...".yellow());
+                    println!("{}", "Resetting configuration to defaults...".warning());
                     config = Config::default();
                     config.save()?;
-                    println!("{}", "✓ Configuration reset".green());
+                    println!("{}", "✓ Configuration reset".success());
                     return Ok(());
                 }
                 
                 if *export {
                     let config_json = serde_json::to_string_pretty(&config)?;
                     std::fs::write("kiwi-config.json", config_json)?;
-                    println!("{}", "✓ Configuration exported to kiwi-config.json".green());
+                    println!("{}", "✓ Configuration exported to kiwi-config.json".success());
                     return Ok(());
                 }
                 
                 if let Some(import_path) = import {
-                    println!("{} {}", "Importing configuration from:".yellow(), import_path.display());
+                    println!("{} {}", "Importing configuration from:".warning(), import_path.display());
                     let config_json = std::fs::read_to_string(import_path)?;
                     config = serde_json::from_str(&config_json)?;
                     config.save()?;
-                    println!("{}", "✓ Configuration imported".green());
+                    println!("{}", "✓ Configuration imported".success());
                     return Ok(());
                 }
                 
                 match (key, value) {
+                    (Some(k), Some(v)) if k.starts_with("brew.") => {
+                        println!("{} {} = {}", "Setting config:".warning(), k, v);
+                        if let Some(rc) = ops::set_brew_setting(&config, &dotfiles, &homebrew, k, v, &CliReporter::new(None, false))? {
+                            println!("{} {}", "Sourcing environment variables from".warning(), rc.display());
+                        }
+                        println!("{}", "✓ Configuration updated (open a new shell to apply it)".success());
+                    },
+                    (Some(k), None) if k.starts_with("brew.") => match ops::brew_setting(&dotfiles, k)? {
+                        Some(enabled) => println!("{} = {}", k.warning(), if enabled { "on" } else { "off" }),
+                        None => println!("{} {}", "Config key not found:".error(), k),
+                    },
                     (Some(k), Some(v)) => {
-                        println!("{} {} = {}", "Setting config:".yellow(), k, v);
+                        println!("{} {} = {}", "Setting config:".warning(), k, v);
                         config.set(k, v.clone())?;
-                        println!("{}", "✓ Configuration updated".green());
+                        println!("{}", "✓ Configuration updated".success());
                     },
                     (Some(k), None) => {
                         if let Some(v) = config.get(k) {
-                            println!("{} = {}", k.yellow(), v);
+                            println!("{} = {}", k.warning(), v);
                         } else {
-                            println!("{} {}", "Config key not found:".red(), k);
+                            println!("{} {}", "Config key not found:".error(), k);
                         }
                     },
                     (None, _) => {
-                        println!("{}", "Please specify a config key".red());
+                        println!("{}", "Please specify a config key".error());
                     },
                 }
             },
-            Commands::Doctor { fix, report } => {
-                println!("{}", "🏥 Running system health check...".blue().bold());
+            Commands::Status => {
+                let tracked = dotfiles.list()?;
+                if tracked.is_empty() {
+                    println!("{}", "No dotfiles tracked yet".warning());
+                    return Ok(());
+                }
+
+                let hashes = dotfiles.hashes(&tracked)?;
+                let mut drifted = 0;
+                let mut unlinked = false;
+                for dotfile in &tracked {
+                    let drift = dotfiles.drift(dotfile);
+                    unlinked |= dotfile.mode == LinkMode::Stow && matches!(drift, DriftStatus::Unlinked | DriftStatus::Foreign);
+                    let marker = match drift {
+                        DriftStatus::Clean => "✓".success(),
+                        DriftStatus::Missing => "✗".error(),
+                        DriftStatus::Unlinked => "~".warning(),
+                        DriftStatus::Foreign => "!".error(),
+                    };
+                    if drift != DriftStatus::Clean {
+                        drifted += 1;
+                    }
+                    let current = hashes.get(&dotfiles.source_for(dotfile));
+                    let hash = current.map(|h| &h[..12]).unwrap_or("-");
+                    let state = dotfile.sync_state(current);
+                    let state = match state {
+                        SyncState::Synced => format!("{:<12}", state).success(),
+                        SyncState::Modified => format!("{:<12}", state).warning(),
+                        SyncState::NeverSynced => format!("{:<12}", state).dimmed(),
+                    };
+                    println!("  {} {:<24} {}  {}  {}", marker, dotfile.name(), hash.dimmed(), state, dotfile.path.display());
+                }
+                println!("\n{} tracked, {} drifted", tracked.len(), drifted);
+                if unlinked {
+                    println!("Run {} to link files moved into the dotfiles dir back into place", "kiwi relink".accent());
+                }
+            },
+            Commands::Diff { file, against, side_by_side, tool } => {
+                let dotfile = dotfiles
+                    .find(file)?
+                    .ok_or_else(|| KiwiError::Dotfiles(format!("Not a tracked dotfile: {}", file)))?;
+                let name = dotfile.name();
+                let local = String::from_utf8_lossy(&std::fs::read(dotfiles.source_for(&dotfile))?).to_string();
+
+                let mut versions = Vec::new();
+                if matches!(against, DiffAgainst::Synced | DiffAgainst::All) {
+                    versions.push(("last synced", dotfiles.synced_contents(&dotfile)?));
+                }
+                if matches!(against, DiffAgainst::Remote | DiffAgainst::All) {
+                    match &sync {
+                        Some(sync) => match sync.remote_file(&name).await {
+                            Ok(remote) => versions.push(("remote", remote)),
+                            Err(e) if *against == DiffAgainst::All => {
+                                println!("{} {}", "Couldn't fetch the remote version:".warning(), e);
+                            },
+                            Err(e) => return Err(e),
+                        },
+                        None if *against == DiffAgainst::Remote => {
+                            return Err(KiwiError::InvalidConfig {
+                                key: "sync_token".to_string(),
+                                message: "Sync is not configured; set sync_url and sync_token".to_string(),
+                            });
+                        },
+                        None => println!("{}", "Sync not configured; skipping the remote version".dimmed()),
+                    }
+                }
+
+                let width = std::env::var("COLUMNS").ok().and_then(|c| c.parse::<usize>().ok()).unwrap_or(160).saturating_sub(3) / 2;
+                for (label, version) in versions {
+                    println!("\n{}", format!("{}: local vs {}", name, label).accent().bold());
+                    let Some(version) = version else {
+                        println!("  {}", format!("No {} version recorded", label).dimmed());
+                        continue;
+                    };
+                    let version = String::from_utf8_lossy(&version).to_string();
+                    if version == local {
+                        println!("  {}", "Identical".success());
+                    } else if let Some(tool) = tool {
+                        crate::diff::open_tool(tool, &name, &version, &local)?;
+                    } else if *side_by_side {
+                        for row in crate::diff::side_by_side(&version, &local, width) {
+                            println!("{}", row);
+                        }
+                    } else {
+                        let label = label.replace(' ', "-");
+                        print_unified(&crate::diff::unified(&format!("{}/{}", label, name), &format!("local/{}", name), &version, &local));
+                    }
+                }
+            },
+            Commands::Watch { install: true, .. } => {
+                let agent = agent::Agent::default();
+                install_agent(&agent)?;
+                println!("{} {}", "✓ kiwi watch now runs from login:".success(), agent.plist_path().display());
+            },
+            Commands::Watch { uninstall: true, .. } => {
+                if agent::Agent::default().uninstall()? {
+                    println!("{}", "✓ Stopped and removed the kiwi watch agent".success());
+                } else {
+                    println!("{}", "The kiwi watch agent isn't installed".warning());
+                }
+            },
+            Commands::Watch { ignore: Some(pattern), .. } => {
+                if config.add_watch_ignore(pattern)? {
+                    println!("{} {}", "✓ kiwi watch now ignores".success(), pattern);
+                } else {
+                    println!("{} {}", "Already ignored:".warning(), pattern);
+                }
+            },
+            Commands::Watch { unignore: Some(pattern), .. } => {
+                if config.remove_watch_ignore(pattern)? {
+                    println!("{} {}", "✓ kiwi watch no longer ignores".success(), pattern);
+                } else {
+                    println!("{} {}", "Pattern was not ignored:".warning(), pattern);
+                }
+            },
+            Commands::Watch { .. } => {
+                let config_path = Config::config_path()?;
+                println!("{} {} (Ctrl-C to stop)", "👀 Watching".accent().bold(), config.dotfiles_dir.display());
+                println!("  Checking every {} minutes; edits to {} apply on the fly", config.preferences.sync_interval_minutes, config_path.display());
+                if !config.watch_ignore.is_empty() {
+                    println!("  Also ignoring changes to {}", config.watch_ignore.join(", "));
+                }
+                if sync.is_none() {
+                    println!("{}", "Sync isn't configured yet; nothing will be pushed until it is.".warning());
+                }
+                watch::run(ops::Context::new(config.clone()), config_path, &CliReporter::new(None, false)).await?;
+            },
+            Commands::Gc { dry_run } => {
+                let options = gc::Options {
+                    retention: Duration::from_secs(u64::from(config.preferences.backup_retention_days) * 24 * 60 * 60),
+                    dry_run: *dry_run,
+                };
+                let spinner = ProgressBar::new_spinner();
+                spinner.set_style(spinner_style);
+                spinner.enable_steady_tick(Duration::from_millis(100));
+                let report = gc::collect(&config.dotfiles_dir, &dotfiles, &mut homebrew, &options, &CliReporter::new(Some(&spinner), false))?;
+                spinner.finish_and_clear();
+
+                if report.is_empty() {
+                    println!("{}", "✓ Nothing to clean up".success());
+                    return Ok(());
+                }
+                let verb = if *dry_run { "Would remove" } else { "Removed" };
+                for path in &report.backups {
+                    println!("  {} backup {}", verb, path.display());
+                }
+                for path in &report.orphaned_links {
+                    println!("  {} orphaned link {}", verb, path.display());
+                }
+                for name in &report.stale_packages {
+                    println!("  {} stale package entry {}", verb, name);
+                }
+                if report.stale_hashes > 0 {
+                    println!("  {} {} stale hash cache entries", verb, report.stale_hashes);
+                }
+                if report.blobs > 0 {
+                    println!("  {} {} old file versions", verb, report.blobs);
+                }
+                let freed = if *dry_run { "would be reclaimed" } else { "reclaimed" };
+                println!("\n{} {:.1} MB {}", "✓".success(), report.reclaimed as f64 / 1_048_576.0, freed);
+            },
+            Commands::Doctor { fix, report, yes } => {
+                println!("{}", "🏥 Running system health check...".accent().bold());
                 let spinner = ProgressBar::new_spinner();
                 spinner.set_style(spinner_style);
 
@@ -562,15 +1919,34 @@ impl Cli {
 
                 // Check sync setup
                 spinner.set_message("Checking sync configuration...");
-                let sync_issues = self.check_sync(sync.as_ref()).await?;
+                let remote = match &sync {
+                    Some(sync) => Some(sync.check_remote().await),
+                    None => None,
+                };
+                let sync_issues = self.check_sync(remote.as_ref());
+
+                spinner.set_message("Checking GPG and commit signing...");
+                let gpg_issues = self.check_gpg(&homebrew, &dotfiles)?;
+
+                spinner.set_message("Checking policy...");
+                let policy_issues = self.check_policy(&config, &homebrew);
+
+                spinner.set_message("Checking PATH for shadowed executables...");
+                let path_issues = path_conflicts(&homebrew)?.iter().map(ToString::to_string).collect();
 
                 spinner.finish_and_clear();
+                if let Some(remote) = &remote {
+                    print_remote_status(remote);
+                }
 
                 let all_issues = vec![
                     ("Configuration", config_issues),
                     ("Homebrew", homebrew_issues),
                     ("Dotfiles", dotfile_issues),
                     ("Sync", sync_issues),
+                    ("GPG", gpg_issues),
+                    ("Policy", policy_issues),
+                    ("PATH", path_issues),
                 ];
 
                 let total_issues: usize = all_issues.iter()
@@ -578,19 +1954,19 @@ impl Cli {
                     .sum();
 
                 if total_issues == 0 {
-                    println!("{}", "✅ All systems operational!".green().bold());
+                    println!("{}", "✅ All systems operational!".success().bold());
                 } else {
-                    println!("\n{} {} issue(s) found:", "⚠️".yellow(), total_issues);
+                    println!("\n{} {} issue(s) found:", "⚠️".warning(), total_issues);
                     
                     for (category, issues) in &all_issues {
                         if !issues.is_empty() {
-                            println!("\n{} {}:", "→".blue(), category);
+                            println!("\n{} {}:", "→".accent(), category);
                             for (i, issue) in issues.iter().enumerate() {
                                 println!("  {}. {}", i + 1, issue);
                                 
                                 if *fix {
-                                    if let Some(fix_msg) = self.try_fix_issue(category, issue, &config).await? {
-                                        println!("     {}", fix_msg.green());
+                                    if let Some(fix_msg) = self.try_fix_issue(category, issue, &config, &dotfiles, &mut homebrew, *yes).await? {
+                                        println!("     {}", fix_msg.success());
                                     }
                                 }
                             }
@@ -598,114 +1974,1668 @@ impl Cli {
                     }
 
                     if *report {
-                        self.generate_health_report(&all_issues)?;
-                        println!("\n{}", "📋 Health report generated: kiwi-health-report.md".green());
+                        std::fs::write("kiwi-health-report.md", self.health_report(&all_issues))?;
+                        println!("\n{}", "📋 Health report generated: kiwi-health-report.md".success());
                     }
 
                     if !*fix {
-                        println!("\n{}", "Run with --fix to attempt automatic repairs".yellow());
+                        println!("\n{}", "Run with --fix to attempt automatic repairs".warning());
                     }
                 }
             },
-        }
-        Ok(())
-    }
-
-    fn check_configuration(&self, config: &Config) -> Result<Vec<String>> {
-        let mut issues = Vec::new();
-        
-        if config.dotfiles_dir.to_string_lossy().is_empty() {
-            issues.push("Dotfiles directory not configured".to_string());
-        }
-        
-        if !config.dotfiles_dir.exists() {
-            issues.push("Dotfiles directory does not exist".to_string());
-        }
-        
-        // Check for required configuration values
-        if config.sync_url.is_none() {
-            issues.push("Sync URL not configured".to_string());
-        }
-        
-        if config.sync_token.is_none() {
-            issues.push("Sync token not configured".to_string());
-        }
-        
-        Ok(issues)
-    }
-
-    fn check_homebrew(&self, homebrew: &Homebrew) -> Result<Vec<String>> {
-        let mut issues = Vec::new();
-        
-        // Check if Homebrew is installed
-        if !std::path::Path::new("/usr/local/bin/brew").exists() 
-            && !std::path::Path::new("/opt/homebrew/bin/brew").exists() {
-            issues.push("Homebrew is not installed".to_string());
-        }
-        
-        // Check if packages.json exists and is valid
-        if let Err(_) = homebrew.list_installed() {
-            issues.push("Unable to read Homebrew packages".to_string());
-        }
-        
-        Ok(issues)
-    }
+            Commands::Link { code } => {
+                if let Some(code) = code {
+                    let url = config.sync_url.clone().ok_or_else(|| KiwiError::InvalidConfig {
+                        key: "sync_url".to_string(),
+                        message: "Sync URL is required to link this machine".to_string(),
+                    })?;
 
-    fn check_dotfiles(&self, dotfiles: &Dotfiles) -> Result<Vec<String>> {
-        let mut issues = Vec::new();
-        
-        // Check if dotfiles.json exists and is valid
-        if let Ok(files) = dotfiles.list() {
-            for file in files {
-                if !file.path.exists() {
-                    issues.push(format!("Dotfile not found: {}", file.path.display()));
+                    println!("{}", "Linking this machine...".accent().bold());
+                    let account = link::claim_code(&url, code).await?;
+                    config.sync_token = Some(account.token);
+                    config.save()?;
+                    println!("{} {}", "✓ Machine linked to".success(), account.email);
+                } else if let Some(sync) = &sync {
+                    let link_code = sync.create_link_code().await?;
+                    println!("{}", link::render_qr(&link_code.code)?);
+                    println!("{} {}", "Link code:".accent().bold(), link_code.code.bold());
+                    println!("{} {}", "Expires at:".warning(), link_code.expires_at);
+                    println!("\nOn the new machine run: kiwi link --code {}", link_code.code);
+                } else {
+                    println!("{}", "Sync not configured. Please set sync_url and sync_token in config.".error());
                 }
-            }
-        } else {
-            issues.push("Unable to read dotfiles configuration".to_string());
-        }
-        
-        Ok(issues)
-    }
-
-    async fn check_sync(&self, sync: Option<&Sync>) -> Result<Vec<String>> {
-        let mut issues = Vec::new();
-        
-        if sync.is_none() {
-            issues.push("Sync is not configured".to_string());
-            return Ok(issues);
-        }
-        
-        // Check if we can access the remote
-        if let Some(sync) = sync {
-            if let Err(e) = sync.check_remote_access().await {
-                issues.push(format!("Cannot access remote repository: {}", e));
-            }
-        }
-        
-        Ok(issues)
-    }
-
-    async fn try_fix_issue(&self, category: &str, issue: &str, config: &Config) -> Result<Option<String>> {
-        match (category, issue) {
-            ("Configuration", "Dotfiles directory does not exist") => {
-                std::fs::create_dir_all(&config.dotfiles_dir)?;
-                Ok(Some("Created dotfiles directory".to_string()))
+            },
+            Commands::Activity { limit, machine } => {
+                let Some(sync) = &sync else {
+                    println!("{}", "Sync not configured. Please set sync_url and sync_token in config.".error());
+                    return Ok(());
+                };
+                let mut activity = sync.activity().await?;
+                if let Some(machine) = machine {
+                    activity.retain(|entry| entry.machine.as_deref().is_some_and(|name| name.eq_ignore_ascii_case(machine)));
+                }
+                if activity.is_empty() {
+                    println!("{}", "No sync activity yet".warning());
+                    return Ok(());
+                }
+                let skip = activity.len().saturating_sub(*limit);
+                for entry in &activity[skip..] {
+                    let when = chrono::DateTime::parse_from_rfc3339(&entry.at)
+                        .map(|at| at.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M").to_string())
+                        .unwrap_or_else(|_| entry.at.clone());
+                    let machine = entry.machine.as_deref().unwrap_or("unknown machine");
+                    if entry.is_empty() {
+                        println!("{}  {}  {}", when.dimmed(), machine.accent(), "pushed, nothing changed".dimmed());
+                        continue;
+                    }
+                    let mut changed = Vec::new();
+                    if !entry.files.is_empty() {
+                        changed.push(format!("{} file{}", entry.files.len(), if entry.files.len() == 1 { "" } else { "s" }));
+                    }
+                    if !entry.packages_added.is_empty() || !entry.packages_removed.is_empty() {
+                        changed.push(format!("+{} -{} packages", entry.packages_added.len(), entry.packages_removed.len()));
+                    }
+                    println!("{}  {}  {}", when.dimmed(), machine.accent(), changed.join(", "));
+                    if !entry.files.is_empty() {
+                        println!("    {}", entry.files.join(", "));
+                    }
+                    for (sign, packages) in [("+", &entry.packages_added), ("-", &entry.packages_removed)] {
+                        if !packages.is_empty() {
+                            println!("    {} {}", sign, packages.join(", "));
+                        }
+                    }
+                }
+            },
+            Commands::Audit { action: AuditCommands::Export { output, since, key } } => {
+                let since = match since {
+                    Some(date) => {
+                        let date = chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                            .map_err(|_| KiwiError::ValidationError(format!("Invalid date: {} (expected YYYY-MM-DD)", date)))?;
+                        Some(date.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc().fixed_offset())
+                    },
+                    None => None,
+                };
+                let Some(key) = key.clone().or_else(|| ssh::SshKeys::default().existing().into_iter().next()) else {
+                    return Err(KiwiError::ValidationError(
+                        "No SSH key to sign the audit trail with; pass --key or create one with `kiwi ssh-key`".to_string(),
+                    ));
+                };
+
+                let mut records = audit::read(&audit::journal_path()?)?;
+                match &sync {
+                    Some(sync) => match sync.activity().await {
+                        Ok(activity) => records.extend(activity.into_iter().map(audit::Record::from)),
+                        Err(e) => println!("{} {}", "Leaving out pushes from other machines:".warning(), e),
+                    },
+                    None => println!("{}", "Sync not configured; only this Mac's journal is exported".warning()),
+                }
+                let jsonl = audit::to_jsonl(records, since)?;
+                std::fs::write(output, &jsonl)?;
+                let signature = audit::sign(&crate::homebrew::SystemRunner, &key, output)?;
+                println!("{} {} ({} records)", "✓ Audit trail written to".success(), output.display(), jsonl.lines().count());
+                println!("  Signed with {}: {}", key.display(), signature.display());
+                println!(
+                    "  Verify with: ssh-keygen -Y verify -f allowed_signers -I <signer> -n {} -s {} < {}",
+                    audit::NAMESPACE,
+                    signature.display(),
+                    output.display()
+                );
+            },
+            Commands::Account { action } => {
+                let Some(sync) = &sync else {
+                    println!("{}", "Sync not configured. Please set sync_url and sync_token in config.".error());
+                    return Ok(());
+                };
+
+                match action {
+                    AccountCommands::Export { output } => {
+                        println!("{}", "Exporting account data...".accent().bold());
+                        let archive = sync.export_account().await?;
+                        std::fs::write(output, archive)?;
+                        println!("{} {}", "✓ Account data exported to".success(), output.display());
+                    },
+                    AccountCommands::Usage { top } => {
+                        let usage = sync.usage().await?;
+                        println!("{} {}", "Storage:".accent().bold(), describe_quota(&usage.quota));
+                        if usage.quota.is_nearly_full() {
+                            println!("{}", "Storage is nearly full; pushes that add files may be refused".warning());
+                        }
+                        if usage.files.is_empty() {
+                            println!("{}", "The server doesn't itemize usage by file".warning());
+                        } else {
+                            println!("\n{}", "Largest files:".accent());
+                            let mut rows = table::Table::new(&["NAME", "SIZE"]);
+                            for (name, size) in usage.largest(*top) {
+                                rows.row(vec![name.to_string(), table::format_size(Some(size))]);
+                            }
+                            print_table(&rows, true);
+                        }
+                    },
+                    AccountCommands::Delete { force } => {
+                        if !*force {
+                            println!("{}", "This permanently deletes all remote data for your account and logs this machine out.".error());
+                            print!("{}", "Are you sure you want to delete your account? [y/N]: ".error());
+                            io::stdout().flush()?;
+                            let mut input = String::new();
+                            io::stdin().read_line(&mut input)?;
+                            if !input.trim().eq_ignore_ascii_case("y") {
+                                println!("{}", "Account deletion cancelled".warning());
+                                return Ok(());
+                            }
+                        }
+
+                        println!("{}", "Deleting remote account data...".warning());
+                        sync.delete_account().await?;
+
+                        config.sync_token = None;
+                        config.save()?;
+                        println!("{}", "✓ Account deleted and local credentials removed".success());
+                    },
+                }
+            },
+            Commands::Alias { action } => {
+                let manager = aliases::Aliases::new(aliases::Aliases::default_path()?);
+                match action {
+                    AliasCommands::Add { name, command, function } => {
+                        let kind = if *function { aliases::Kind::Function } else { aliases::Kind::Alias };
+                        let replaced = manager.add(aliases::Alias { name: name.clone(), command: command.clone(), kind })?;
+
+                        let shell = shell::detect().unwrap_or_else(|| "zsh".to_string());
+                        if let Some(rc) = manager.install(&dotfiles, &shell)? {
+                            println!("{} {}", "Sourcing aliases from".warning(), rc.display());
+                        }
+                        let verb = if replaced { "Updated" } else { "Added" };
+                        println!("{} {} (open a new shell to use it)", format!("✓ {}", verb).success(), name);
+                    },
+                    AliasCommands::List => {
+                        let all = manager.list()?;
+                        if all.is_empty() {
+                            println!("{}", "No aliases yet. Add one with `kiwi alias add <name> <command>`.".warning());
+                        }
+                        for alias in all {
+                            let marker = match alias.kind {
+                                aliases::Kind::Alias => "",
+                                aliases::Kind::Function => " (function)",
+                            };
+                            println!("  {}{} = {}", alias.name.accent().bold(), marker.dimmed(), alias.command);
+                        }
+                    },
+                    AliasCommands::Remove { name } => {
+                        if manager.remove(name)? {
+                            println!("{} {}", "✓ Removed".success(), name);
+                        } else {
+                            println!("{} {}", "No such alias:".warning(), name);
+                        }
+                    },
+                }
+            },
+            Commands::Apps { action } => {
+                let manager = apps::Apps::default();
+                match action {
+                    AppCommands::List => {
+                        for app in apps::APPS {
+                            let status = if manager.is_captured(app) {
+                                format!("captured in {}", manager.export_path(app).display()).success()
+                            } else if manager.has_settings(app) {
+                                "not captured".warning()
+                            } else {
+                                "no settings on this Mac".dimmed()
+                            };
+                            println!("  {:<10} {:<18} {}", app.name.accent().bold(), app.title, status);
+                        }
+                    },
+                    AppCommands::Capture { names } => {
+                        let chosen: Vec<&apps::App> = if names.is_empty() {
+                            let found: Vec<&apps::App> = apps::APPS.iter().filter(|app| manager.has_settings(app)).collect();
+                            if found.is_empty() {
+                                println!("{}", "None of the apps kiwi knows have settings on this Mac.".warning());
+                                return Ok(());
+                            }
+                            let labels: Vec<&str> = found.iter().map(|app| app.title).collect();
+                            let picked = MultiSelect::with_theme(&ColorfulTheme::default())
+                                .with_prompt("Capture settings for (space to toggle, enter to confirm)")
+                                .items(&labels)
+                                .defaults(&vec![true; labels.len()])
+                                .interact()
+                                .map_err(|e| KiwiError::InvalidCommand(format!("Failed to read answer: {}", e)))?;
+                            picked.into_iter().map(|i| found[i]).collect()
+                        } else {
+                            names.iter().map(|name| apps::find(name)).collect::<Result<_>>()?
+                        };
+                        for app in chosen {
+                            match manager.capture(app, &dotfiles) {
+                                Ok(path) => println!("  {} {} → {}", "✓".success(), app.title, path.display()),
+                                Err(e) => println!("  {} {}: {}", "✗".error(), app.title, e),
+                            }
+                        }
+                        println!("Push to sync them, then run `kiwi apps apply` on your other Mac.");
+                    },
+                    AppCommands::Apply { names } => {
+                        let chosen: Vec<&apps::App> = if names.is_empty() {
+                            apps::APPS.iter().filter(|app| manager.is_captured(app)).collect()
+                        } else {
+                            names.iter().map(|name| apps::find(name)).collect::<Result<_>>()?
+                        };
+                        if chosen.is_empty() {
+                            println!("{}", "No captured app settings yet. Pull, or run `kiwi apps capture` on the Mac that has them.".warning());
+                        }
+                        let backups = crate::backups::Backups::new(&config.dotfiles_dir);
+                        for app in chosen {
+                            match manager.apply(app, &backups) {
+                                Ok(()) if app.reload.is_some() => println!("  {} {}", "✓".success(), app.title),
+                                Ok(()) => println!("  {} {} (takes effect next time it opens)", "✓".success(), app.title),
+                                Err(e) => println!("  {} {}: {}", "✗".error(), app.title, e),
+                            }
+                        }
+                    },
+                }
+            },
+            Commands::Hosts { action } => {
+                let manager = hosts::Hosts::default();
+                let env = config.environment.as_deref();
+                match action {
+                    HostCommands::Add { address, names, env } => {
+                        let moved = manager.add(hosts::Entry { address: *address, names: names.clone(), env: env.clone() })?;
+                        manager.install(&dotfiles)?;
+                        let verb = if moved { "Moved" } else { "Added" };
+                        println!("{} {} -> {}", format!("✓ {}", verb).success(), names.join(" "), address);
+                        println!("Run `kiwi hosts apply` to write it to /etc/hosts.");
+                    },
+                    HostCommands::List => {
+                        let entries = manager.list()?;
+                        if entries.is_empty() {
+                            println!("{}", "No host entries yet. Add one with `kiwi hosts add <address> <hostname>`.".warning());
+                            return Ok(());
+                        }
+                        for entry in &entries {
+                            let scope = entry.env.as_ref().map(|env| format!(" ({} only)", env)).unwrap_or_default();
+                            println!("  {} {}{}", entry.address.to_string().accent().bold(), entry.names.join(" "), scope.dimmed());
+                        }
+                        if manager.is_applied(env)? {
+                            println!("{}", "✓ /etc/hosts is up to date".success());
+                        } else {
+                            println!("{}", "/etc/hosts differs; run `kiwi hosts apply`".warning());
+                        }
+                    },
+                    HostCommands::Remove { name } => {
+                        if manager.remove(name)? {
+                            println!("{} {} (run `kiwi hosts apply` to drop it from /etc/hosts)", "✓ Removed".success(), name);
+                        } else {
+                            println!("{} {}", "No such hostname:".warning(), name);
+                        }
+                    },
+                    HostCommands::Apply => {
+                        for line in manager.conflicts(env)? {
+                            println!("{} /etc/hosts also has `{}` outside kiwi's block, which may win", "⚠️".warning(), line);
+                        }
+                        if manager.apply(env)? {
+                            println!("{}", "✓ Updated /etc/hosts".success());
+                        } else {
+                            println!("{}", "✓ /etc/hosts is already up to date".success());
+                        }
+                    },
+                }
+            },
+            Commands::Tweaks { action } => {
+                let manager = tweaks::Tweaks::default();
+                match action {
+                    TweakCommands::List => {
+                        let recorded = manager.recorded();
+                        for recipe in tweaks::RECIPES {
+                            let status = if manager.is_applied(recipe) {
+                                "applied".success()
+                            } else if recorded.iter().any(|r| r == recipe.name) {
+                                "recorded, not applied here".warning()
+                            } else {
+                                "".normal()
+                            };
+                            println!("  {:<18} {} {}", recipe.name.accent().bold(), recipe.description, status);
+                        }
+                    },
+                    TweakCommands::Apply { names } => {
+                        let names = if names.is_empty() { manager.recorded() } else { names.clone() };
+                        if names.is_empty() {
+                            println!("{}", "No recipes recorded yet. Name one, e.g. `kiwi tweaks apply key-repeat`; `kiwi tweaks list` shows them all.".warning());
+                            return Ok(());
+                        }
+                        let recipes = names.iter().map(|name| tweaks::find(name)).collect::<Result<Vec<_>>>()?;
+                        for recipe in recipes {
+                            match manager.apply(recipe, &dotfiles) {
+                                Ok(()) => println!("  {} {}", "✓".success(), recipe.name),
+                                Err(e) => println!("  {} {}: {}", "✗".error(), recipe.name, e),
+                            }
+                        }
+                        println!("Some settings only take effect after you log out and back in.");
+                    },
+                }
+            },
+            Commands::Network { action } => {
+                let manager = network::Network::default();
+                let env = config.environment.as_deref();
+                match action {
+                    NetworkCommands::List => {
+                        let profile = manager.profile(env)?;
+                        if profile.locations.is_empty() {
+                            println!("{}", "No network settings captured yet. Run `kiwi network capture` while on a location.".warning());
+                            return Ok(());
+                        }
+                        let current = manager.current_location().ok();
+                        for (location, services) in &profile.locations {
+                            let marker = if current.as_deref() == Some(location.as_str()) { "*".success() } else { " ".normal() };
+                            println!("{} {}", marker, location.accent().bold());
+                            for service in services {
+                                let mut details = Vec::new();
+                                if !service.dns_servers.is_empty() {
+                                    details.push(format!("DNS {}", service.dns_servers.join(", ")));
+                                }
+                                if !service.search_domains.is_empty() {
+                                    details.push(format!("search {}", service.search_domains.join(", ")));
+                                }
+                                for (kind, proxy) in service.proxies.iter().filter(|(_, p)| p.enabled) {
+                                    details.push(format!("{} proxy {}:{}", kind, proxy.server, proxy.port));
+                                }
+                                if let Some(url) = &service.auto_proxy_url {
+                                    details.push(format!("PAC {}", url));
+                                }
+                                let details = if details.is_empty() { "defaults".dimmed() } else { details.join("; ").normal() };
+                                println!("    {}: {}", service.name, details);
+                            }
+                        }
+                    },
+                    NetworkCommands::Capture => {
+                        let location = manager.capture(env, &dotfiles)?;
+                        println!("{} {} in {}", "✓ Captured".success(), location, manager.profile_path(env).display());
+                    },
+                    NetworkCommands::Apply { location } => {
+                        let location = match location {
+                            Some(location) => location.clone(),
+                            None => manager.current_location()?,
+                        };
+                        let applied = manager.apply(env, &location)?;
+                        if let Some(location) = &applied.switched_to {
+                            println!("  {} switched to {}", "✓".success(), location);
+                        }
+                        for service in &applied.services {
+                            println!("  {} {}", "✓".success(), service);
+                        }
+                        for service in &applied.missing {
+                            println!("  {} {} isn't on this Mac", "~".warning(), service);
+                        }
+                        println!("{} {}", "✓ Applied".success(), location);
+                    },
+                }
+            },
+            Commands::SshKey { github } => {
+                setup_ssh_key(*github).await?;
+            },
+            Commands::Jobs { action } => {
+                let manager = jobs::Jobs::default();
+                match action {
+                    JobCommands::List => {
+                        let captured = manager.captured_agents()?;
+                        let crontab = manager.current_crontab()?;
+                        let crontab_status = if manager.crontab_path().exists() { "captured".success() } else { "not captured".warning() };
+                        match crontab {
+                            Some(contents) => println!("  {} ({} lines) {}", "crontab".accent().bold(), contents.lines().count(), crontab_status),
+                            None => println!("  {} {}", "crontab".accent().bold(), "none".dimmed()),
+                        }
+                        let installed = manager.installed_agents()?;
+                        if installed.is_empty() {
+                            println!("  {}", "No LaunchAgents of your own".dimmed());
+                        }
+                        for job in installed {
+                            let status = if captured.iter().any(|c| c.path.file_name() == job.path.file_name()) { "captured".success() } else { "not captured".warning() };
+                            println!("  {} runs {} {}", job.label.accent().bold(), job.schedule, status);
+                        }
+                    },
+                    JobCommands::Capture => {
+                        let crontab = manager.current_crontab()?;
+                        let installed = manager.installed_agents()?;
+                        let mut labels: Vec<String> = crontab.iter().map(|c| format!("crontab ({} lines)", c.lines().count())).collect();
+                        labels.extend(installed.iter().map(|job| format!("{} ({})", job.label, job.schedule)));
+                        if labels.is_empty() {
+                            println!("{}", "No crontab or LaunchAgents of your own to capture.".warning());
+                            return Ok(());
+                        }
+                        let picked = MultiSelect::with_theme(&ColorfulTheme::default())
+                            .with_prompt("Capture (space to toggle, enter to confirm)")
+                            .items(&labels)
+                            .defaults(&vec![true; labels.len()])
+                            .interact()
+                            .map_err(|e| KiwiError::InvalidCommand(format!("Failed to read answer: {}", e)))?;
+                        let offset = usize::from(crontab.is_some());
+                        for i in picked {
+                            if i < offset {
+                                manager.capture_crontab(&dotfiles)?;
+                                println!("  {} crontab", "✓".success());
+                                continue;
+                            }
+                            let job = &installed[i - offset];
+                            match manager.capture_agent(job, &dotfiles) {
+                                Ok(_) => println!("  {} {}", "✓".success(), job.label),
+                                Err(e) => println!("  {} {}: {}", "✗".error(), job.label, e),
+                            }
+                        }
+                        println!("Push to sync them, then run `kiwi jobs restore` on your other Mac.");
+                    },
+                    JobCommands::Restore => {
+                        let reporter = CliReporter::new(None, false);
+                        let captured_crontab = std::fs::read_to_string(manager.crontab_path()).ok();
+                        let agents = manager.captured_agents()?;
+                        if captured_crontab.is_none() && agents.is_empty() {
+                            println!("{}", "Nothing captured yet. Pull, or run `kiwi jobs capture` on the Mac that has them.".warning());
+                            return Ok(());
+                        }
+
+                        if let Some(captured) = &captured_crontab {
+                            let current = manager.current_crontab()?.unwrap_or_default();
+                            if current == *captured {
+                                println!("  {} crontab is already in place", "✓".success());
+                            } else {
+                                println!("\n{}", "crontab".accent().bold());
+                                print_unified(&crate::diff::unified("current crontab", "captured crontab", &current, captured));
+                                if reporter.confirm("Replace your crontab with the captured one?") {
+                                    manager.restore_crontab()?;
+                                    println!("  {} crontab restored", "✓".success());
+                                }
+                            }
+                        }
+
+                        let installed = manager.installed_agents()?;
+                        for job in &agents {
+                            println!("\n{}", job.label.accent().bold());
+                            println!("  runs:  {}", job.command.join(" "));
+                            println!("  when:  {}", job.schedule);
+                            if let Some(program) = job.missing_program() {
+                                println!("  {} {} isn't on this Mac; install it first or the job will fail", "⚠️".warning(), program);
+                            }
+                            if installed.iter().any(|i| i.label == job.label) {
+                                println!("  {} already installed here; loading replaces it", "•".dimmed());
+                            }
+                            if !reporter.confirm(&format!("Install and load {}?", job.label)) {
+                                continue;
+                            }
+                            match manager.restore_agent(job) {
+                                Ok(path) => println!("  {} loaded from {}", "✓".success(), path.display()),
+                                Err(e) => println!("  {} {}", "✗".error(), e),
+                            }
+                        }
+                    },
+                }
+            },
+            Commands::Envvar { action } => {
+                let manager = envvars::EnvVars::new(&dotfiles, envvars::EnvVars::default_path()?);
+                let vars = crate::template::variables(&config);
+                let keychain = keychain::Keychain::default();
+                match action {
+                    EnvvarCommands::Set { name, value, env, secret } => {
+                        let value = match value {
+                            Some(value) => value.clone(),
+                            None if *secret => Password::with_theme(&ColorfulTheme::default())
+                                .with_prompt(format!("Value for {}", name))
+                                .interact()
+                                .map_err(|e| KiwiError::InvalidCommand(format!("Failed to read value: {}", e)))?,
+                            None => return Err(KiwiError::InvalidCommand("A value is required unless --secret is given".to_string())),
+                        };
+                        let value = if *secret {
+                            let account = envvars::secret_account(name, env.as_deref());
+                            keychain.set(&account, &value)?;
+                            envvars::Value::Secret { account }
+                        } else {
+                            envvars::Value::Plain(value)
+                        };
+                        let replaced = manager.set(envvars::EnvVar { name: name.clone(), env: env.clone(), value }, &vars)?;
+
+                        let shell = shell::detect().unwrap_or_else(|| "zsh".to_string());
+                        if let Some(rc) = manager.install(&shell)? {
+                            println!("{} {}", "Sourcing environment variables from".warning(), rc.display());
+                        }
+                        let verb = if replaced { "Updated" } else { "Set" };
+                        let scope = env.as_ref().map(|env| format!(" for {}", env)).unwrap_or_default();
+                        println!("{} {}{} (open a new shell to use it)", format!("✓ {}", verb).success(), name, scope);
+                    },
+                    EnvvarCommands::Unset { name, env } => match manager.unset(name, env.as_deref(), &vars)? {
+                        Some(removed) => {
+                            if let envvars::Value::Secret { account } = removed.value {
+                                if let Err(e) = keychain.delete(&account) {
+                                    println!("{} {}", "Couldn't remove the Keychain entry:".warning(), e);
+                                }
+                            }
+                            println!("{} {}", "✓ Removed".success(), name);
+                        },
+                        None => println!("{} {}", "No such variable:".warning(), name),
+                    },
+                    EnvvarCommands::List { env } => {
+                        let all: Vec<_> = manager
+                            .list()?
+                            .into_iter()
+                            .filter(|var| env.is_none() || var.env.is_none() || var.env == *env)
+                            .collect();
+                        if all.is_empty() {
+                            println!("{}", "No variables yet. Set one with `kiwi envvar set <name> <value>`.".warning());
+                        }
+                        for var in all {
+                            let value = match &var.value {
+                                envvars::Value::Plain(value) => value.clone(),
+                                envvars::Value::Secret { .. } => "(secret in Keychain)".dimmed().to_string(),
+                            };
+                            let scope = var.env.map(|env| format!(" [{}]", env)).unwrap_or_default();
+                            println!("  {}{} = {}", var.name.accent().bold(), scope.dimmed(), value);
+                        }
+                    },
+                }
+            },
+            Commands::Templates { action } => {
+                let Some(url) = config.template_registry() else {
+                    println!("{}", "No template registry configured. Set one with `kiwi config template_registry <url>`.".error());
+                    return Ok(());
+                };
+                let registry = registry::Registry::new(url);
+                let index = registry.index().await?;
+
+                match action {
+                    TemplateCommands::Search { query } => {
+                        let matches = registry::search(&index, query.as_deref().unwrap_or(""));
+                        if matches.is_empty() {
+                            println!("{}", "No templates found".warning());
+                        }
+                        for entry in matches {
+                            let author = entry.author.as_deref().map(|a| format!(" by {}", a)).unwrap_or_default();
+                            println!("  {}{} - {}", entry.name.accent().bold(), author.dimmed(), entry.description);
+                            if !entry.packages.is_empty() {
+                                println!("    {} {}", "packages:".dimmed(), entry.packages.join(", "));
+                            }
+                        }
+                    },
+                    TemplateCommands::Install { name, yes } => {
+                        let entry = index.templates.iter().find(|e| e.name == *name)
+                            .ok_or_else(|| KiwiError::InvalidCommand(format!("Template not found: {}", name)))?;
+                        println!("{} {}", "Fetching template".accent().bold(), entry.name);
+                        let files = registry.fetch(entry).await?;
+
+                        let home = dirs::home_dir().ok_or_else(|| KiwiError::Config("Could not find home directory".to_string()))?;
+                        let vars = crate::template::variables(&config);
+                        for file in &files {
+                            let proposed = crate::template::render(&file.contents, &vars)?;
+                            let current = std::fs::read_to_string(home.join(&file.path)).unwrap_or_default();
+                            if current == proposed {
+                                println!("  {} {} unchanged", "=".dimmed(), file.path);
+                                continue;
+                            }
+                            print_unified(&registry::diff(&file.path, &current, &proposed));
+                        }
+
+                        let reporter = CliReporter::new(None, *yes);
+                        if !reporter.confirm(&format!("Apply {} files from {}?", files.len(), entry.name)) {
+                            println!("{}", "Template not applied".warning());
+                            return Ok(());
+                        }
+                        for path in registry::apply(&files, &home, &dotfiles, &vars)? {
+                            reporter.success(&path.display().to_string());
+                        }
+
+                        let wanted: Vec<&str> = entry.packages.iter().map(String::as_str).collect();
+                        install_missing(&mut homebrew, &wanted, &format!("Template {}", entry.name), &reporter);
+                        println!("{} {}", "✓ Installed template".success(), entry.name);
+                    },
+                }
+            },
+            Commands::Env { action } => {
+                let environments = Environments::load(&config.dotfiles_dir)?;
+                let active = config.environment.clone();
+
+                match action {
+                    EnvCommands::List => {
+                        println!("{}", "Environments:".accent().bold());
+                        for name in environments.names(active.as_deref()) {
+                            let packages = environments.profile(&name).packages.len();
+                            if active.as_deref() == Some(name.as_str()) {
+                                println!("  {} {} ({} packages)", "*".success(), name.success().bold(), packages);
+                            } else {
+                                println!("    {} ({} packages)", name, packages);
+                            }
+                        }
+                    },
+                    EnvCommands::Show { name } => {
+                        let Some(name) = name.clone().or(active) else {
+                            println!("{}", "No active environment. Switch with `kiwi env use <name>`.".warning());
+                            return Ok(());
+                        };
+                        println!("{} {}", "Environment:".accent().bold(), name);
+
+                        let packages = environments.profile(&name).packages;
+                        if packages.is_empty() {
+                            println!("  {}", "No package bundle (add one in environments.json)".dimmed());
+                        } else {
+                            let installed = homebrew.installed_names()?;
+                            println!("\n{}", "Packages:".accent());
+                            for package in &packages {
+                                let marker = if installed.contains(package) { "✓".success() } else { "✗".error() };
+                                println!("  {} {}", marker, package);
+                            }
+                        }
+
+                        let tracked = dotfiles.list()?;
+                        let templates: Vec<_> = tracked.iter().filter(|d| d.is_template()).collect();
+                        if !templates.is_empty() {
+                            println!("\n{}", "Templated dotfiles:".accent());
+                            for dotfile in templates {
+                                println!("  {}", dotfile.path.display());
+                            }
+                        }
+                        let own: Vec<_> = tracked.iter().filter(|d| d.environments.contains(&name)).collect();
+                        if !own.is_empty() {
+                            println!("\n{}", "Dotfiles only in this environment:".accent());
+                            for dotfile in own {
+                                println!("  {}", dotfile.path.display());
+                            }
+                        }
+                    },
+                    EnvCommands::Use { name, install, prune, yes } => {
+                        println!("{} {}", "Switching environment to".accent().bold(), name);
+                        let reporter = CliReporter::new(None, *yes);
+                        let switch = ops::use_environment(&mut config, &mut homebrew, name, *install, *prune, &reporter)?;
+
+                        for path in &switch.rendered {
+                            println!("  {} rendered {}", "✓".success(), path.display());
+                        }
+                        for path in &switch.files.applied {
+                            println!("  {} {}", "+".success(), path.display());
+                        }
+                        for path in &switch.files.parked {
+                            println!("  {} {} {}", "-".warning(), path.display(), "(kept in the dotfiles dir)".dimmed());
+                        }
+                        if !switch.failed.is_empty() {
+                            println!("{}", format!("{} packages failed", switch.failed.len()).warning());
+                        }
+                        match switch.previous {
+                            Some(previous) if previous != *name => {
+                                println!("{} {} → {}", "✓ Environment switched:".success(), previous, name)
+                            }
+                            _ => println!("{} {}", "✓ Active environment:".success(), name),
+                        }
+                    },
+                }
+            },
+            Commands::Ui => {
+                ui::Dashboard::new(&dotfiles, &homebrew, sync.as_ref()).run().await?;
+            },
+            Commands::Note { package, note } => {
+                homebrew.set_note(package, note.clone())?;
+                match note {
+                    Some(note) => println!("{} {}: {}", "✓ Note saved for".success(), package, note),
+                    None => println!("{} {}", "✓ Note cleared for".success(), package),
+                }
+            },
+            Commands::Hook { package, commands, clear } => {
+                if *clear {
+                    homebrew.set_post_install(package, Vec::new())?;
+                    println!("{} {}", "✓ Post-install commands cleared for".success(), package);
+                    return Ok(());
+                }
+                if !commands.is_empty() {
+                    homebrew.set_post_install(package, commands.clone())?;
+                    println!("{} {}", "✓ Post-install commands saved for".success(), package);
+                }
+                let hooks = homebrew.tracked().into_iter().find(|p| p.name == *package).map(|p| p.post_install).unwrap_or_default();
+                if hooks.is_empty() {
+                    println!("{} has no post-install commands", package);
+                }
+                for command in hooks {
+                    println!("  {}", command);
+                }
+            },
+            Commands::Info { package, json } => {
+                let info = homebrew.info(package)?;
+
+                if *json {
+                    println!("{}", serde_json::to_string_pretty(&info)?);
+                    return Ok(());
+                }
+
+                let format_time = |ts: Option<u64>| {
+                    ts.and_then(|t| chrono::DateTime::from_timestamp(t as i64, 0))
+                        .map(|t| t.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M").to_string())
+                        .unwrap_or_else(|| "-".to_string())
+                };
+
+                let kind = if info.is_cask { "cask" } else { "formula" };
+                println!("{} {}", info.name.bold(), format!("({})", kind).dimmed());
+                if let Some(desc) = &info.description {
+                    println!("  {}", desc);
+                }
+                println!("  {:<14} {}", "Homepage:".warning(), info.homepage.as_deref().unwrap_or("-"));
+                println!("  {:<14} {}", "Version:".warning(), info.version.as_deref().unwrap_or("not installed"));
+                println!("  {:<14} {}", "Size:".warning(), info.size.map(|s| format!("{:.1} MB", s as f64 / 1_048_576.0)).unwrap_or_else(|| "-".to_string()));
+                println!("  {:<14} {}", "Dependencies:".warning(), if info.dependencies.is_empty() { "-".to_string() } else { info.dependencies.join(", ") });
+                println!("  {:<14} {}", "Synced:".warning(), match (info.tracked, info.excluded) {
+                    (_, true) => "no (excluded)",
+                    (true, false) => "yes",
+                    (false, false) => "no",
+                });
+                println!("  {:<14} {}", "Held:".warning(), if info.held { "yes" } else { "no" });
+                println!("  {:<14} {}", "Installed:".warning(), format_time(info.install_time));
+                println!("  {:<14} {}", "Last update:".warning(), format_time(info.last_update));
+                if let Some(note) = &info.note {
+                    println!("  {:<14} {}", "Note:".warning(), note);
+                }
+                for command in &info.post_install {
+                    println!("  {:<14} {}", "Post-install:".warning(), command);
+                }
+            },
+            Commands::Which { tool, json } => {
+                let home = dirs::home_dir().ok_or_else(|| KiwiError::Config("Could not find home directory".to_string()))?;
+                let found = provenance::Provenance::from_env(home, PathBuf::from(homebrew.environment().prefix)).locate(tool);
+                if found.is_empty() {
+                    return Err(KiwiError::ValidationError(format!("{} isn't on your PATH", tool)));
+                }
+                let tracked_files = dotfiles.list().unwrap_or_default();
+                let tracking = |binary: &provenance::Binary| -> Option<&'static str> {
+                    if let Some(package) = binary.source.package() {
+                        let is_package = |name: &str| name == package || name.ends_with(&format!("/{}", package));
+                        if homebrew.is_excluded(package) {
+                            return Some("excluded");
+                        }
+                        return homebrew.tracked().iter().any(|p| is_package(&p.name)).then_some("package");
+                    }
+                    tracked_files.iter().any(|file| file.path == binary.path || file.path == binary.target).then_some("dotfile")
+                };
+
+                if *json {
+                    let report: Vec<serde_json::Value> = found.iter().enumerate().map(|(i, binary)| serde_json::json!({
+                        "path": binary.path,
+                        "target": binary.target,
+                        "source": binary.source,
+                        "runs": i == 0,
+                        "tracked": tracking(binary).is_some_and(|how| how != "excluded"),
+                    })).collect();
+                    println!("{}", serde_json::to_string_pretty(&report)?);
+                    return Ok(());
+                }
+
+                let winner = &found[0];
+                println!("{} {} {}", tool.bold(), "→".accent(), winner.path.display());
+                if winner.target != winner.path {
+                    println!("  {:<10} {}", "Resolves:".warning(), winner.target.display());
+                }
+                println!("  {:<10} {}", "From:".warning(), winner.source);
+                let tracked = match (tracking(winner), winner.source.package()) {
+                    (Some("package"), _) => "yes, in the package manifest".success().to_string(),
+                    (Some("dotfile"), _) => "yes, as a dotfile".success().to_string(),
+                    (Some(_), _) => "no (excluded)".to_string(),
+                    (None, Some(package)) => format!("no; run {} to adopt {}", "kiwi reconcile".accent(), package),
+                    (None, None) => "no".to_string(),
+                };
+                println!("  {:<10} {}", "Tracked:".warning(), tracked);
+                if found.len() > 1 {
+                    println!("\n{}", "Shadowed, further down your PATH:".warning());
+                    for binary in &found[1..] {
+                        println!("  {}  {}", binary.path.display(), binary.source.to_string().dimmed());
+                    }
+                }
+            },
+            Commands::Autoremove { dry_run, yes } => {
+                println!("{}", "Looking for unneeded dependencies...".accent().bold());
+                let plan = ops::autoremove_plan(&config, &homebrew)?;
+                for (name, reason) in &plan.kept {
+                    println!("  {} {} {}", "keep".success(), name, format!("({})", reason).dimmed());
+                }
+                for name in &plan.kept_dependencies {
+                    println!("  {} {} {}", "keep".success(), name, "(needed by a kept formula)".dimmed());
+                }
+                if plan.removable.is_empty() {
+                    println!("{}", "✓ Nothing to remove".success());
+                    return Ok(());
+                }
+                for name in &plan.removable {
+                    println!("  {} {}", "remove".warning(), name);
+                }
+                if *dry_run {
+                    return Ok(());
+                }
+
+                let removal = ops::autoremove(&mut homebrew, plan, &CliReporter::new(None, *yes))?;
+                if removal.removed.is_empty() {
+                    return Ok(());
+                }
+                println!("{} {} formula(e)", "✓ Removed".success(), removal.removed.len());
+                if !removal.adopted.is_empty() {
+                    println!("  {} {}", "Added to the manifest:".dimmed(), removal.adopted.join(", "));
+                }
+                let leftover = removal.removable.len() - removal.removed.len();
+                if leftover > 0 {
+                    println!("{} {} formula(e) are still installed", "⚠️".warning(), leftover);
+                }
+            },
+            Commands::Reconcile { dry_run } => {
+                println!("{}", "Reconciling installed packages with the manifest...".accent().bold());
+                let diff = homebrew.reconcile()?;
+
+                if diff.untracked.is_empty() && diff.missing.is_empty() {
+                    println!("{}", "✓ Manifest matches installed packages".success());
+                    return Ok(());
+                }
+
+                if !diff.untracked.is_empty() {
+                    println!("\n{}", "Installed but not managed:".warning());
+                    for name in &diff.untracked {
+                        println!("  + {}", name);
+                    }
+                }
+                if !diff.missing.is_empty() {
+                    println!("\n{}", "Managed but not installed:".warning());
+                    for name in &diff.missing {
+                        println!("  - {}", name);
+                    }
+                }
+
+                if *dry_run {
+                    return Ok(());
+                }
+
+                let theme = ColorfulTheme::default();
+                let read_selection = |e: dialoguer::Error| KiwiError::InvalidCommand(format!("Failed to read selection: {}", e));
+
+                if !diff.untracked.is_empty() {
+                    println!();
+                    let adopt = MultiSelect::with_theme(&theme)
+                        .with_prompt("Adopt into management (space to toggle, enter to confirm)")
+                        .items(&diff.untracked)
+                        .interact()
+                        .map_err(read_selection)?;
+                    for &i in &adopt {
+                        homebrew.adopt(&diff.untracked[i])?;
+                        println!("  {} adopted {}", "✓".success(), diff.untracked[i]);
+                    }
+
+                    let remaining: Vec<&String> = diff.untracked
+                        .iter()
+                        .enumerate()
+                        .filter(|(i, _)| !adopt.contains(i))
+                        .map(|(_, name)| name)
+                        .collect();
+                    if !remaining.is_empty() {
+                        let exclude = MultiSelect::with_theme(&theme)
+                            .with_prompt("Exclude from sync permanently")
+                            .items(&remaining)
+                            .interact()
+                            .map_err(read_selection)?;
+                        for &i in &exclude {
+                            config.add_exclude(remaining[i])?;
+                            println!("  {} excluded {}", "✓".success(), remaining[i]);
+                        }
+                    }
+                }
+
+                if !diff.missing.is_empty() {
+                    let reinstall = MultiSelect::with_theme(&theme)
+                        .with_prompt("Reinstall missing packages")
+                        .items(&diff.missing)
+                        .interact()
+                        .map_err(read_selection)?;
+                    for &i in &reinstall {
+                        println!("{} {}", "Installing package:".accent().bold(), diff.missing[i]);
+                        homebrew.reinstall(&diff.missing[i])?;
+                    }
+                    let skipped = diff.missing.len() - reinstall.len();
+                    if skipped > 0 {
+                        println!("{} {} package(s) still flagged as missing", "⚠️".warning(), skipped);
+                    }
+                }
+
+                println!("{}", "✓ Reconcile complete".success());
+            },
+            Commands::Export { archive: Some(archive_path), encrypt, .. } => {
+                let environment = export::Environment::collect(&config, &dotfiles, &homebrew)?;
+
+                let passphrase = if *encrypt {
+                    Some(Password::with_theme(&ColorfulTheme::default())
+                        .with_prompt("Archive passphrase")
+                        .with_confirmation("Confirm passphrase", "Passphrases don't match")
+                        .interact()
+                        .map_err(|e| KiwiError::InvalidCommand(format!("Failed to read passphrase: {}", e)))?)
+                } else {
+                    None
+                };
+
+                let bundled = archive::create(archive_path, &environment, &config.dotfiles_dir, passphrase.as_deref())?;
+                println!("{} {} ({} files{})", "✓ Environment archived to".success(), archive_path.display(), bundled,
+                    if *encrypt { ", encrypted" } else { "" });
+            },
+            Commands::Export { github_action: true, output, .. } => {
+                let workflow = output.clone().unwrap_or_else(|| PathBuf::from(export::WORKFLOW_FILE));
+                let environment = export::Environment::collect(&config, &dotfiles, &homebrew)?;
+                std::fs::write(export::MANIFEST_FILE, environment.to_json()?)?;
+                if let Some(parent) = workflow.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                std::fs::write(&workflow, export::github_action(export::MANIFEST_FILE, &workflow.to_string_lossy()))?;
+                println!("{} {}", "✓ Environment exported to".success(), export::MANIFEST_FILE);
+                println!("{} {}", "✓ Workflow written to".success(), workflow.display());
+                println!("Commit both; the workflow installs the environment on a macOS runner whenever it changes, and weekly");
+            },
+            Commands::Check { manifest, install } => {
+                let environment = export::Environment::load(manifest)?;
+                println!(
+                    "{} {} ({} packages, {} taps)",
+                    "Checking".accent().bold(),
+                    manifest.display(),
+                    environment.packages.len(),
+                    environment.taps.len()
+                );
+                let summary = ops::check_manifest(&homebrew, &environment, *install, &CliReporter::new(None, true))?;
+                for tap in &summary.tapped {
+                    println!("  {} tapped {}", "✓".success(), tap);
+                }
+                for package in &summary.installed {
+                    println!("  {} installed {}", "✓".success(), package);
+                }
+                for (name, error) in &summary.failed {
+                    println!("  {} {}: {}", "✗".error(), name, error.trim());
+                }
+                if !summary.failed.is_empty() {
+                    return Err(KiwiError::ValidationError(format!("{} of the environment's packages and taps failed", summary.failed.len())));
+                }
+                println!("{}", "✓ Everything in the environment installs".success());
+            },
+            Commands::TapMaintainer { action } => match action {
+                TapMaintainerCommands::Outdated { tap, json } => {
+                    if !*json {
+                        println!("{} {}", "Checking upstream releases for".accent().bold(), tap);
+                    }
+                    let bumps = homebrew.livecheck_tap(tap)?;
+                    if *json {
+                        println!("{}", serde_json::to_string_pretty(&bumps)?);
+                        return Ok(());
+                    }
+
+                    let outdated: Vec<_> = bumps.iter().filter(|bump| bump.outdated).collect();
+                    for bump in &outdated {
+                        println!(
+                            "  {} {} → {}",
+                            bump.name.bold(),
+                            bump.current.as_deref().unwrap_or("?"),
+                            bump.latest.as_deref().unwrap_or("?").success()
+                        );
+                    }
+                    let unknown: Vec<_> = bumps.iter().filter(|bump| bump.error.is_some()).collect();
+                    for bump in &unknown {
+                        println!("  {} {}: {}", "?".warning(), bump.name, bump.error.as_deref().unwrap_or_default().dimmed());
+                    }
+                    if outdated.is_empty() {
+                        println!("{} {} formulae and casks are up to date", "✓".success(), bumps.len() - unknown.len());
+                    } else {
+                        println!("{}", format!("{} of {} behind upstream", outdated.len(), bumps.len()).warning());
+                    }
+                },
+            },
+            Commands::Export { format, output, .. } => {
+                let environment = export::Environment::collect(&config, &dotfiles, &homebrew)?;
+                let rendered = match format {
+                    ExportFormat::Json => environment.to_json()?,
+                    ExportFormat::Yaml => environment.to_yaml()?,
+                    ExportFormat::Markdown => environment.to_markdown(),
+                };
+
+                match output {
+                    Some(path) => {
+                        std::fs::write(path, rendered)?;
+                        println!("{} {}", "✓ Environment exported to".success(), path.display());
+                    },
+                    None => print!("{}", rendered),
+                }
+            },
+            Commands::Import { from_backup: Some(backup), install, .. } => {
+                let homes = migrate::homes_in_backup(backup);
+                let old_home = match homes.len() {
+                    0 => return Err(KiwiError::ValidationError(format!("No home folder found in {}", backup.display()))),
+                    1 => homes[0].clone(),
+                    _ => {
+                        let labels: Vec<String> = homes.iter().map(|h| h.display().to_string()).collect();
+                        let chosen = Select::with_theme(&ColorfulTheme::default())
+                            .with_prompt("Import from which home folder?")
+                            .items(&labels)
+                            .default(0)
+                            .interact()
+                            .map_err(|e| KiwiError::InvalidCommand(format!("Failed to read selection: {}", e)))?;
+                        homes[chosen].clone()
+                    }
+                };
+                println!("{} {}", "Importing from backup:".accent().bold(), old_home.display());
+
+                let reporter = CliReporter::new(None, false);
+                let imported = ops::import_home(&dotfiles, &mut homebrew, &old_home, migrate::volume_root(&old_home), &reporter)?;
+                finish_home_import(&config, &mut homebrew, &imported, *install)?;
+            },
+            Commands::Import { from_home: Some(old_home), install, .. } => {
+                let home = dirs::home_dir().ok_or_else(|| KiwiError::Config("Could not determine home directory".to_string()))?;
+                if old_home.canonicalize().ok() == home.canonicalize().ok() {
+                    return Err(KiwiError::ValidationError("--from-home must be another account's home folder".to_string()));
+                }
+                if let Err(e) = std::fs::read_dir(old_home) {
+                    if e.kind() == io::ErrorKind::PermissionDenied {
+                        println!("{} Run it with sudo, e.g. `sudo kiwi import --from-home {}`", "⚠️".warning(), old_home.display());
+                        return Err(KiwiError::PermissionDenied { path: old_home.clone() });
+                    }
+                    return Err(e.into());
+                }
+                println!("{} {}", "Importing from:".accent().bold(), old_home.display());
+
+                // Same Mac, same brew: only the old account's kiwi manifest adds packages
+                let reporter = CliReporter::new(None, false);
+                let imported = ops::import_home(&dotfiles, &mut homebrew, old_home, None, &reporter)?;
+                finish_home_import(&config, &mut homebrew, &imported, *install)?;
+            },
+            Commands::Import { archive, install, yes, .. } => {
+                let archive_path = archive.as_ref().ok_or_else(|| KiwiError::InvalidCommand("--archive, --from-backup, or --from-home is required".to_string()))?;
+                println!("{} {}", "Importing environment from:".accent().bold(), archive_path.display());
+
+                let passphrase = if archive::is_encrypted(archive_path)? {
+                    Some(Password::with_theme(&ColorfulTheme::default())
+                        .with_prompt("Archive passphrase")
+                        .interact()
+                        .map_err(|e| KiwiError::InvalidCommand(format!("Failed to read passphrase: {}", e)))?)
+                } else {
+                    None
+                };
+                let contents = archive::extract(archive_path, passphrase.as_deref())?;
+                apply_archive(&contents, &config, &dotfiles, &mut homebrew, *install, *yes)?;
+                println!("{}", "✓ Import complete".success());
+            },
+            Commands::Send { port } => {
+                let environment = export::Environment::collect(&config, &dotfiles, &homebrew)?;
+                let code = transfer::one_time_code()?;
+                let normalized = transfer::normalize_code(&code);
+                let (payload, bundled) = archive::build(&environment, &config.dotfiles_dir, Some(&normalized))?;
+
+                let listener = tokio::net::TcpListener::bind(("0.0.0.0", *port)).await?;
+                let port = listener.local_addr()?.port();
+                let name = ops::computer_name();
+                let _advertisement = match transfer::advertise(&name, port) {
+                    Ok(advertisement) => Some(advertisement),
+                    Err(e) => {
+                        println!("{} Couldn't advertise over Bonjour ({}); on the other Mac run `kiwi receive --from <this Mac's address>:{}`",
+                            "⚠️".warning(), e, port);
+                        None
+                    }
+                };
+
+                println!("{} as \"{}\" on port {} ({} files)", "Sending environment".accent().bold(), name, port, bundled);
+                println!("On the other Mac run `kiwi receive` and enter this code:");
+                println!();
+                println!("    {}", code.bold());
+                println!();
+
+                let spinner = multi_progress.add(ProgressBar::new_spinner());
+                spinner.set_style(spinner_style.clone());
+                spinner.enable_steady_tick(Duration::from_millis(100));
+                spinner.set_message("Waiting for a receiver (Ctrl-C to stop)...");
+                let peer = transfer::serve(&listener, &payload, &normalized).await?;
+                spinner.finish_and_clear();
+                println!("{} {}", "✓ Environment sent to".success(), peer.ip());
+            },
+            Commands::Receive { from, install, yes } => {
+                let address = match from {
+                    Some(address) => address.clone(),
+                    None => {
+                        println!("{}", "Looking for Macs running kiwi send...".accent().bold());
+                        let senders = transfer::discover(Duration::from_secs(5)).await?;
+                        let name = match senders.len() {
+                            0 => return Err(KiwiError::Sync(
+                                "No senders found; run `kiwi send` on the other Mac, or pass --from host:port".to_string())),
+                            1 => senders[0].clone(),
+                            _ => {
+                                let chosen = Select::with_theme(&ColorfulTheme::default())
+                                    .with_prompt("Receive from")
+                                    .items(&senders)
+                                    .default(0)
+                                    .interact()
+                                    .map_err(|e| KiwiError::InvalidCommand(format!("Failed to read selection: {}", e)))?;
+                                senders[chosen].clone()
+                            }
+                        };
+                        transfer::resolve(&name, Duration::from_secs(5)).await?
+                    }
+                };
+
+                let code = Password::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Code shown by kiwi send")
+                    .interact()
+                    .map_err(|e| KiwiError::InvalidCommand(format!("Failed to read code: {}", e)))?;
+                println!("{} {}", "Receiving environment from:".accent().bold(), address);
+                let code = transfer::normalize_code(&code);
+                let incoming = transfer::fetch(&address, &code).await?;
+                let contents = archive::open(incoming.payload.clone(), Some(&code))
+                    .map_err(|_| KiwiError::ValidationError("Couldn't open the environment; check the code and try again".to_string()))?;
+                incoming.acknowledge().await?;
+
+                apply_archive(&contents, &config, &dotfiles, &mut homebrew, *install, *yes)?;
+                println!("{}", "✓ Receive complete".success());
+            },
+            Commands::Teardown { delete_remote, keep_backups, yes } => {
+                println!("{}", "This removes kiwi from this Mac:".warning().bold());
+                println!("  • tracked files stay where they are, as real files");
+                println!("  • ~/.kiwi and ~/.config/kiwi are deleted{}",
+                    if *keep_backups { " (backups move to ~/kiwi-backups)" } else { ", backups included" });
+                println!("  • kiwi's shell hooks, completions, background agent, and Keychain entries are removed");
+                if *delete_remote {
+                    println!("  • {}", "everything stored on the sync server for your account is deleted".error());
+                }
+                if !*yes && !CliReporter::new(None, false).confirm("Tear down kiwi?") {
+                    println!("{}", "Teardown cancelled".warning());
+                    return Ok(());
+                }
+
+                if *delete_remote {
+                    let Some(sync) = &sync else {
+                        println!("{}", "Sync is not configured, so there is no remote data to delete.".warning());
+                        return Ok(());
+                    };
+                    println!("{}", "Deleting remote account data...".warning());
+                    sync.delete_account().await?;
+                    println!("  {} remote data deleted", "✓".success());
+                }
+
+                let spinner = multi_progress.add(ProgressBar::new_spinner());
+                spinner.set_style(spinner_style.clone());
+                spinner.enable_steady_tick(Duration::from_millis(100));
+                let options = teardown::Options { keep_backups: *keep_backups };
+                let report = teardown::run(&config.dotfiles_dir, &dotfiles, &options, &CliReporter::new(Some(&spinner), true))?;
+                spinner.finish_and_clear();
+
+                for path in &report.restored {
+                    println!("  {} {} is a real file again", "✓".success(), path.display());
+                }
+                for path in &report.shell_files {
+                    println!("  {} cleaned up {}", "✓".success(), path.display());
+                }
+                if report.agent {
+                    println!("  {} removed the kiwi watch agent", "✓".success());
+                }
+                if report.keychain_entries > 0 {
+                    println!("  {} removed {} Keychain entries", "✓".success(), report.keychain_entries);
+                }
+                for path in &report.removed {
+                    println!("  {} deleted {}", "✓".success(), path.display());
+                }
+                if let Some(backups) = &report.backups {
+                    println!("  {} backups kept in {}", "•".dimmed(), backups.display());
+                }
+                if let Some(kept) = &report.kept {
+                    println!("  {} left {} alone; delete it yourself if it was only for kiwi", "•".dimmed(), kept.display());
+                }
+                println!("{}", "✓ kiwi is gone from this Mac. The brew shellenv line in your rc file was kept; remove the kiwi binary to finish.".success());
+            },
+            Commands::Completions { shell, install, uninstall } => {
+                let shell = completions::resolve_shell(*shell)?;
+
+                if *install {
+                    let report = completions::install(Cli::command(), shell)?;
+                    println!("{} {}", "✓ Installed completions to".success(), report.script.display());
+                    if let Some(rc) = report.rc_file {
+                        println!("{} {}", "Updated".warning(), rc.display());
+                    }
+                    println!("Restart your shell to enable completions.");
+                } else if *uninstall {
+                    let removed = completions::uninstall(shell)?;
+                    if removed.is_empty() {
+                        println!("{}", "No installed completions found".warning());
+                    }
+                    for path in removed {
+                        println!("{} {}", "✓ Cleaned up".success(), path.display());
+                    }
+                } else {
+                    io::stdout().write_all(&completions::generate(Cli::command(), shell))?;
+                }
+            },
+            Commands::Manpages { out_dir } => {
+                std::fs::create_dir_all(out_dir)?;
+                clap_mangen::generate_to(Cli::command(), out_dir)?;
+                let count = std::fs::read_dir(out_dir)?
+                    .flatten()
+                    .filter(|e| e.path().extension().is_some_and(|ext| ext == "1"))
+                    .count();
+                println!("{} {} man pages in {}", "✓ Generated".success(), count, out_dir.display());
+            },
+            Commands::Search { query, no_install } => {
+                println!("{} {}", "Searching for".accent().bold(), query);
+                let results = search::search(query, homebrew.brew_path()).await?;
+
+                if results.is_empty() {
+                    println!("{}", "No results found".warning());
+                    return Ok(());
+                }
+
+                let labels: Vec<String> = results
+                    .iter()
+                    .map(|r| match &r.description {
+                        Some(desc) => format!("{} [{}] - {}", r.name, r.source, desc),
+                        None => format!("{} [{}]", r.name, r.source),
+                    })
+                    .collect();
+
+                if *no_install {
+                    for label in &labels {
+                        println!("  {}", label);
+                    }
+                    return Ok(());
+                }
+
+                let chosen = MultiSelect::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Select packages to install (space to toggle, enter to confirm)")
+                    .items(&labels)
+                    .interact()
+                    .map_err(|e| KiwiError::InvalidCommand(format!("Failed to read selection: {}", e)))?;
+
+                for result in chosen.iter().map(|&i| &results[i]) {
+                    println!("{} {}", "Installing package:".accent().bold(), result.name);
+                    match (result.source, &result.app_id) {
+                        (search::Source::Mas, Some(app_id)) => search::install_mas(app_id).await?,
+                        _ => homebrew.install(&result.name, None)?,
+                    }
+                    println!("{}", "✓ Installation complete".success());
+                }
+            },
+            Commands::Telemetry { action } => {
+                match action {
+                    TelemetryAction::On | TelemetryAction::Off => {
+                        config.preferences.telemetry = *action == TelemetryAction::On;
+                        config.save()?;
+                    },
+                    TelemetryAction::Status => {},
+                }
+
+                if config.preferences.telemetry {
+                    println!("{}", "Telemetry is on".success().bold());
+                } else {
+                    println!("{}", "Telemetry is off".warning().bold());
+                }
+                println!("\nWhen on, kiwi sends only the following after each command:");
+                println!("  • the subcommand name (e.g. \"sync\"), never its arguments");
+                println!("  • how long it took");
+                println!("  • whether it succeeded");
+                println!("No account, machine identifier, paths, or package names are included.");
+                if let Some(url) = &config.sync_url {
+                    println!("Events are sent to {}/telemetry", url.trim_end_matches('/'));
+                }
+            },
+            Commands::Project { action } => {
+                let found = project::Project::find(&std::env::current_dir()?)?;
+                let required = || {
+                    let project = found.as_ref().ok_or_else(|| {
+                        KiwiError::ValidationError(format!("No {} here or in any directory above", project::MANIFEST))
+                    })?;
+                    println!("{} {} ({})", "Project:".accent().bold(), project.name(), project.root.display());
+                    Ok::<_, KiwiError>(project)
+                };
+                match action {
+                    ProjectCommands::Env => {
+                        let allowed = project::Allowed::new(project::Allowed::default_path()?);
+                        let found = match found {
+                            Some(project) if !allowed.is_allowed(&project)? => {
+                                eprintln!(
+                                    "kiwi: {} isn't allowed; review it and run `kiwi project allow` to use its environment",
+                                    project.root.join(project::MANIFEST).display()
+                                );
+                                None
+                            },
+                            found => found,
+                        };
+                        let changes = project::activate(found.as_ref(), |name| std::env::var(name).ok())?;
+                        print!("{}", project::script(&changes));
+                    },
+                    ProjectCommands::Allow => {
+                        let project = required()?;
+                        project.variables(None)?;
+                        project::Allowed::new(project::Allowed::default_path()?).allow(project)?;
+                        println!("{}", "✓ Allowed; the hook applies its environment until .kiwi.toml changes".success());
+                    },
+                    ProjectCommands::Deny => {
+                        let project = required()?;
+                        if project::Allowed::new(project::Allowed::default_path()?).deny(project)? {
+                            println!("{}", "✓ Its environment won't be applied anymore".success());
+                        } else {
+                            println!("{}", "It wasn't allowed".warning());
+                        }
+                    },
+                    ProjectCommands::Hook => {
+                        let shell = shell::detect().unwrap_or_else(|| "zsh".to_string());
+                        match project::install_hook(&shell)? {
+                            Some(rc) => println!("{} {} (open a new shell to use it)", "✓ Added the project hook to".success(), rc.display()),
+                            None => println!("{}", "✓ The project hook is already installed".success()),
+                        }
+                    },
+                    ProjectCommands::Check => {
+                        let project = required()?;
+                        let installed = homebrew.installed_names()?;
+                        let missing = project.missing(&installed, &std::env::var_os("PATH").unwrap_or_default());
+                        if missing.is_empty() {
+                            println!("{}", "✓ Everything this project needs is installed".success());
+                            return Ok(());
+                        }
+                        for package in &missing.packages {
+                            println!("  {} {} isn't installed", "✗".error(), package);
+                        }
+                        for (command, package) in &missing.tools {
+                            println!("  {} {} isn't on your PATH (from {})", "✗".error(), command, package);
+                        }
+                        println!("\nRun {} to install what's missing", "kiwi project install".accent());
+                        let count = missing.packages.len() + missing.tools.len();
+                        return Err(KiwiError::ValidationError(format!("{} requirement(s) missing", count)));
+                    },
+                    ProjectCommands::Install { yes } => {
+                        let project = required()?;
+                        let reporter = CliReporter::new(None, *yes);
+                        let summary = match ops::install_project(&config, &mut homebrew, project, &reporter).await {
+                            Err(KiwiError::UserCancelled) => {
+                                println!("{}", "Installation cancelled".warning());
+                                return Ok(());
+                            },
+                            result => result?,
+                        };
+                        for package in &summary.installed {
+                            println!("  {} {}", "✓".success(), package);
+                        }
+                        for (package, error) in &summary.failed {
+                            println!("  {} {}: {}", "✗".error(), package, error);
+                        }
+                        if summary.installed.is_empty() && summary.failed.is_empty() {
+                            println!("{}", "✓ Everything this project needs is installed".success());
+                        }
+                    },
+                }
+            },
+            Commands::Policy { action } => {
+                let policy = &config.policy;
+                if policy.is_empty() {
+                    println!("No policy is deployed at {}", policy::path().display());
+                    return Ok(());
+                }
+                match action {
+                    PolicyAction::Show => {
+                        println!("{} {}", "Policy:".accent().bold(), policy::path().display());
+                        if !policy.required_packages.is_empty() {
+                            println!("\n{}", "Required packages:".accent());
+                            for package in &policy.required_packages {
+                                println!("  {}", package);
+                            }
+                        }
+                        if !policy.forbidden_packages.is_empty() {
+                            println!("\n{}", "Forbidden packages:".accent());
+                            for package in &policy.forbidden_packages {
+                                println!("  {}", package);
+                            }
+                        }
+                        if !policy.locked.is_empty() {
+                            println!("\n{}", "Locked settings:".accent());
+                            for (key, value) in &policy.locked {
+                                println!("  {} = {}", key, value);
+                            }
+                        }
+                    },
+                    PolicyAction::Check => {
+                        let violations = policy.violations(&config, &homebrew.installed_names()?);
+                        if violations.is_empty() {
+                            println!("{}", "✓ This Mac complies with the policy".success());
+                            return Ok(());
+                        }
+                        for violation in &violations {
+                            println!("  {} {}", "✗".error(), violation);
+                        }
+                        println!("\nRun {} to install required packages", "kiwi doctor --fix".accent());
+                        return Err(KiwiError::ValidationError(format!("{} policy violation(s)", violations.len())));
+                    },
+                }
+            },
+            Commands::DebugBundle { output } => {
+                println!("{}", "🐞 Collecting diagnostics...".accent().bold());
+                let spinner = ProgressBar::new_spinner();
+                spinner.set_style(spinner_style);
+                spinner.set_message("Running health checks...");
+
+                let remote = match &sync {
+                    Some(sync) => Some(sync.check_remote().await),
+                    None => None,
+                };
+                let all_issues = vec![
+                    ("Configuration", self.check_configuration(&config)?),
+                    ("Homebrew", self.check_homebrew(&homebrew)?),
+                    ("Dotfiles", self.check_dotfiles(&dotfiles)?),
+                    ("Sync", self.check_sync(remote.as_ref())),
+                    ("GPG", self.check_gpg(&homebrew, &dotfiles)?),
+                ];
+
+                spinner.set_message("Writing bundle...");
+                let entries = diagnostics::create_bundle(output, &config, &self.health_report(&all_issues))?;
+                spinner.finish_and_clear();
+
+                println!("{} {}", "✓ Debug bundle written to".success(), output.display());
+                for entry in &entries {
+                    println!("  {}", entry);
+                }
+                println!("{}", "Credentials are redacted, but review the bundle before sharing it".warning());
+            },
+            Commands::Hold { package } => {
+                homebrew.hold(package)?;
+                println!("{} {}", "✓ Holding".success(), package);
+            },
+            Commands::Unhold { package } => {
+                homebrew.unhold(package)?;
+                println!("{} {}", "✓ Released hold on".success(), package);
+            },
+            Commands::Exclude { pattern, remove } => {
+                match pattern {
+                    Some(pattern) if *remove => {
+                        if config.remove_exclude(pattern)? {
+                            println!("{} {}", "✓ No longer excluding".success(), pattern);
+                        } else {
+                            println!("{} {}", "Pattern was not excluded:".warning(), pattern);
+                        }
+                    },
+                    Some(pattern) => {
+                        if config.add_exclude(pattern)? {
+                            println!("{} {}", "✓ Excluding from sync:".success(), pattern);
+                        } else {
+                            println!("{} {}", "Already excluded:".warning(), pattern);
+                        }
+                    },
+                    None => {
+                        if config.excluded_packages.is_empty() {
+                            println!("{}", "No packages are excluded".warning());
+                        } else {
+                            println!("{}", "Excluded packages:".warning());
+                            for pattern in &config.excluded_packages {
+                                println!("  {}", pattern);
+                            }
+                        }
+                    },
+                }
+            },
+        }
+        Ok(())
+    }
+
+    fn check_configuration(&self, config: &Config) -> Result<Vec<String>> {
+        let mut issues = Vec::new();
+        
+        if config.dotfiles_dir.to_string_lossy().is_empty() {
+            issues.push("Dotfiles directory not configured".to_string());
+        }
+        
+        if !config.dotfiles_dir.exists() {
+            issues.push("Dotfiles directory does not exist".to_string());
+        }
+        
+        // Check for required configuration values
+        if config.sync_url.is_none() {
+            issues.push("Sync URL not configured".to_string());
+        }
+        
+        if config.sync_token.is_none() {
+            issues.push("Sync token not configured".to_string());
+        }
+        
+        Ok(issues)
+    }
+
+    fn check_homebrew(&self, homebrew: &Homebrew) -> Result<Vec<String>> {
+        let mut issues = Vec::new();
+        
+        // Check if Homebrew is installed
+        if !homebrew.is_brew_installed() {
+            issues.push("Homebrew is not installed".to_string());
+        }
+
+        if !xcode::CommandLineTools::default().is_installed() {
+            issues.push("Xcode Command Line Tools are not installed".to_string());
+        }
+        
+        // Check if packages.json exists and is valid
+        if homebrew.list_installed().is_err() {
+            issues.push("Unable to read Homebrew packages".to_string());
+        }
+        
+        Ok(issues)
+    }
+
+    fn check_policy(&self, config: &Config, homebrew: &Homebrew) -> Vec<String> {
+        if config.policy.is_empty() {
+            return Vec::new();
+        }
+        // An unreadable Homebrew is already reported above
+        let Ok(installed) = homebrew.installed_names() else {
+            return Vec::new();
+        };
+        config.policy.violations(config, &installed)
+    }
+
+    fn check_dotfiles(&self, dotfiles: &Dotfiles) -> Result<Vec<String>> {
+        let mut issues = Vec::new();
+        
+        // Check if dotfiles.json exists and is valid
+        if let Ok(files) = dotfiles.list() {
+            for file in files {
+                if !file.path.exists() {
+                    issues.push(format!("Dotfile not found: {}", file.path.display()));
+                } else if !file.sudo && dotfiles.is_foreign(&file.path) {
+                    issues.push(format!(
+                        "{} belongs to root or another user; pulls can't write it (remove it and add it again with --sudo)",
+                        file.path.display()
+                    ));
+                }
+            }
+        } else {
+            issues.push("Unable to read dotfiles configuration".to_string());
+        }
+        
+        Ok(issues)
+    }
+
+    fn check_sync(&self, status: Option<&RemoteStatus>) -> Vec<String> {
+        let Some(status) = status else {
+            return vec!["Sync is not configured".to_string()];
+        };
+
+        let mut issues = Vec::new();
+        let error = status.error.as_deref().unwrap_or("no response");
+        if !status.reachable {
+            issues.push(format!("Cannot reach sync server: {}", error));
+        } else if !status.authenticated {
+            issues.push(format!("Cannot access remote repository: {}", error));
+        }
+        if status.latency.is_some_and(|latency| latency > SLOW_SYNC_SERVER) {
+            issues.push(format!("Sync server is slow to respond ({} ms)", status.latency.unwrap_or_default().as_millis()));
+        }
+        if let Some(quota) = status.quota.as_ref().filter(|quota| quota.is_nearly_full()) {
+            issues.push(format!("Sync storage is nearly full: {}", describe_quota(quota)));
+        }
+        issues
+    }
+
+    fn check_gpg(&self, homebrew: &Homebrew, dotfiles: &Dotfiles) -> Result<Vec<String>> {
+        Ok(gpg_checker(homebrew)?.check(dotfiles)?.iter().map(ToString::to_string).collect())
+    }
+
+    /// Fixes `issue` if kiwi knows how. Fixes that run commands show them
+    /// and ask first unless `yes`, and log what ran to `doctor.log`.
+    async fn try_fix_issue(&self, category: &str, issue: &str, config: &Config, dotfiles: &Dotfiles, homebrew: &mut Homebrew, yes: bool) -> Result<Option<String>> {
+        let reporter = CliReporter::new(None, yes);
+        let log = fixes::log_path()?;
+        match (category, issue) {
+            ("GPG", _) => {
+                // Issues arrive as text, so find the one it came from
+                let gpg = gpg_checker(homebrew)?;
+                let Some(found) = gpg.check(dotfiles)?.into_iter().find(|found| found.to_string() == issue) else {
+                    return Ok(None);
+                };
+                let commands = gpg.commands(&found);
+                if !approve_fix(&commands, &reporter, &log) {
+                    return Ok(None);
+                }
+                let fixed = gpg.fix(&found, dotfiles, homebrew);
+                log_fix(&commands, &log, fixed.is_ok());
+                fixed
+            },
+            ("Configuration", "Dotfiles directory does not exist") => {
+                std::fs::create_dir_all(&config.dotfiles_dir)?;
+                Ok(Some("Created dotfiles directory".to_string()))
             },
             ("Homebrew", "Homebrew is not installed") => {
-                // Install Homebrew
-                let install_script = "/bin/bash -c \"$(curl -fsSL https://raw.githubusercontent.com/Homebrew/install/HEAD/install.sh)\"";
-                std::process::Command::new("bash")
-                    .arg("-c")
-                    .arg(install_script)
-                    .output()?;
-                Ok(Some("Installed Homebrew".to_string()))
+                match ops::install_homebrew(config, &reporter).await {
+                    Ok(installed) => {
+                        *homebrew = installed;
+                        Ok(Some("Installed Homebrew".to_string()))
+                    },
+                    Err(e) => {
+                        println!("     {} {}", "Skipped:".warning(), e);
+                        Ok(None)
+                    },
+                }
+            },
+            ("Homebrew", "Xcode Command Line Tools are not installed") => {
+                let commands = [fixes::command_line("xcode-select", &["--install"])];
+                if !approve_fix(&commands, &reporter, &log) {
+                    return Ok(None);
+                }
+                let installed = xcode::CommandLineTools::default()
+                    .install(xcode::INSTALL_TIMEOUT, &CliReporter::new(None, true))
+                    .await;
+                log_fix(&commands, &log, installed.is_ok());
+                installed?;
+                Ok(Some("Installed Xcode Command Line Tools".to_string()))
+            },
+            ("Policy", _) => {
+                let Some(package) = config.policy.missing_package(issue) else {
+                    return Ok(None);
+                };
+                let commands = [fixes::command_line("brew", &["install", package])];
+                if !approve_fix(&commands, &reporter, &log) {
+                    return Ok(None);
+                }
+                let installed = homebrew.install(package, Some("Required by policy".to_string()));
+                log_fix(&commands, &log, installed.is_ok());
+                installed?;
+                Ok(Some(format!("Installed {}", package)))
+            },
+            ("PATH", _) => {
+                let conflicts = path_conflicts(homebrew)?;
+                let Some(formula) = conflicts.iter().find(|conflict| conflict.to_string() == issue).and_then(|conflict| conflict.unlinkable()) else {
+                    return Ok(None);
+                };
+                let commands = [fixes::command_line("brew", &["unlink", formula])];
+                if !approve_fix(&commands, &reporter, &log) {
+                    return Ok(None);
+                }
+                let unlinked = homebrew.unlink(formula);
+                log_fix(&commands, &log, unlinked.is_ok());
+                unlinked?;
+                Ok(Some(format!("Unlinked {}; `brew link {}` puts it back", formula, formula)))
             },
             _ => Ok(None),
         }
     }
 
-    fn generate_health_report(&self, issues: &[(&str, Vec<String>)]) -> Result<()> {
+    fn health_report(&self, issues: &[(&str, Vec<String>)]) -> String {
         let mut report = String::new();
         report.push_str("# Kiwi Health Report\n\n");
         report.push_str(&format!("Generated on: {}\n\n", chrono::Local::now()));
@@ -718,11 +3648,567 @@ impl Cli {
                 for issue in category_issues {
                     report.push_str(&format!("- ⚠️ {}\n", issue));
                 }
-                report.push_str("\n");
+                report.push('\n');
             }
         }
         
-        std::fs::write("kiwi-health-report.md", report)?;
-        Ok(())
+        report
+    }
+}
+
+/// Shows the commands a doctor fix is about to run and asks to go ahead,
+/// logging a refusal. A fix that runs nothing goes ahead without asking.
+fn approve_fix(commands: &[String], reporter: &CliReporter, log: &Path) -> bool {
+    if commands.is_empty() {
+        return true;
+    }
+    println!("     Will run:");
+    for command in commands {
+        println!("       {}", command.accent());
+    }
+    if reporter.confirm("Run it?") {
+        return true;
+    }
+    for command in commands {
+        let _ = fixes::log(log, command, "declined");
+    }
+    println!("     {}", "Skipped".warning());
+    false
+}
+
+/// Logs how the commands of a doctor fix went.
+fn log_fix(commands: &[String], log: &Path, succeeded: bool) {
+    for command in commands {
+        let _ = fixes::log(log, command, if succeeded { "ok" } else { "failed" });
+    }
+}
+
+/// Executables on this shell's PATH that more than one managed source provides.
+fn path_conflicts(homebrew: &Homebrew) -> Result<Vec<provenance::Conflict>> {
+    let home = dirs::home_dir().ok_or_else(|| KiwiError::Config("Could not find home directory".to_string()))?;
+    Ok(provenance::Provenance::from_env(home, PathBuf::from(homebrew.environment().prefix)).conflicts())
+}
+
+/// The GPG checks for this Mac's home and brew prefix.
+fn gpg_checker(homebrew: &Homebrew) -> Result<gpg::Gpg> {
+    let home = dirs::home_dir().ok_or_else(|| KiwiError::Config("Could not find home directory".to_string()))?;
+    Ok(gpg::Gpg::new(home, PathBuf::from(homebrew.environment().prefix)))
+}
+
+/// The guided `kiwi init`: environment, an optional cloud restore, which
+/// configs and packages to manage, starter packs, completions, and the
+/// background agent, one question at a time. Sync mode was already chosen
+/// by [`Cli::choose_sync_mode`].
+async fn init_wizard(config: &mut Config, dotfiles: &Dotfiles, homebrew: &mut Homebrew, sync: Option<&Sync>) -> Result<()> {
+    let theme = ColorfulTheme::default();
+    let answer_error = |e: dialoguer::Error| KiwiError::InvalidCommand(format!("Failed to read answer: {}", e));
+    let ask = |prompt: &str, default: bool| {
+        Confirm::with_theme(&theme).with_prompt(prompt).default(default).interact().map_err(answer_error)
+    };
+    let reporter = CliReporter::new(None, false);
+
+    let kinds = [EnvType::Dev, EnvType::Prod, EnvType::Design, EnvType::Custom];
+    let labels = [
+        "dev - a machine you write code on",
+        "prod - a machine that runs things and should change rarely",
+        "design - a machine for design tools",
+        "custom - name it yourself",
+    ];
+    let kind = kinds[Select::with_theme(&theme)
+        .with_prompt("What is this Mac for?")
+        .items(&labels)
+        .default(0)
+        .interact()
+        .map_err(answer_error)?];
+    let environment = match kind {
+        EnvType::Custom => dialoguer::Input::<String>::with_theme(&theme)
+            .with_prompt("Environment name")
+            .interact_text()
+            .map_err(answer_error)?,
+        _ => kind.to_string(),
+    };
+    config.set("environment", environment)?;
+
+    if let Some(sync) = sync {
+        if ask("Restore configs and packages from your cloud backup?", false)? {
+            ops::restore(config, sync, &reporter).await?;
+        }
+    }
+
+    // Private keys never sync, so a restored Mac can't reach GitHub until it has its own
+    if ssh::SshKeys::default().existing().is_empty() && ask("This Mac has no SSH key. Generate one?", true)? {
+        let github = ask("Register it with GitHub too?", false)?;
+        if let Err(e) = setup_ssh_key(github).await {
+            println!("  {} SSH key: {}", "✗".error(), e);
+        }
+    }
+
+    let home = dirs::home_dir().ok_or_else(|| KiwiError::Config("Could not find home directory".to_string()))?;
+    let tracked = dotfiles.list()?;
+    let found: Vec<migrate::Found> = migrate::find_configs(&home)
+        .into_iter()
+        .filter(|f| !tracked.iter().any(|d| d.path == f.source || f.source.canonicalize().is_ok_and(|p| p == d.path)))
+        .filter(|f| crate::sensitive::check(&f.source).is_none())
+        .collect();
+    if !found.is_empty() {
+        let labels: Vec<String> = found.iter().map(|f| format!("~/{}", f.relative.display())).collect();
+        let chosen = MultiSelect::with_theme(&theme)
+            .with_prompt("Track these configs? (space to toggle, enter to confirm)")
+            .items(&labels)
+            .defaults(&vec![true; labels.len()])
+            .interact()
+            .map_err(answer_error)?;
+        let mut taken: Vec<String> = tracked.iter().map(|d| d.name()).collect();
+        for found in chosen.into_iter().map(|i| &found[i]) {
+            let alias = migrate::alias_for(&found.relative, &taken);
+            let options = ops::AddOptions { alias: alias.clone(), size_limit: config.large_file_limit(), ..Default::default() };
+            match ops::add_dotfile(dotfiles, &found.source, options, &reporter) {
+                Ok(_) => {
+                    println!("  {} {}", "✓".success(), found.source.display());
+                    taken.push(alias.unwrap_or_else(|| found.relative.file_name().unwrap_or_default().to_string_lossy().to_string()));
+                }
+                Err(e) => println!("  {} {}: {}", "✗".error(), found.source.display(), e),
+            }
+        }
+    }
+
+    let labels: Vec<String> = starters::PACKS.iter().map(|pack| format!("{} - {}", pack.name, pack.description)).collect();
+    let chosen = MultiSelect::with_theme(&theme)
+        .with_prompt("Add starter configs? (space to toggle, enter to skip or confirm)")
+        .items(&labels)
+        .interact()
+        .map_err(answer_error)?;
+    let packs: Vec<&starters::Pack> = chosen.into_iter().map(|i| &starters::PACKS[i]).collect();
+    apply_starter_packs(&packs, config, dotfiles, homebrew, &reporter)?;
+
+    if ask("Let kiwi manage your Homebrew packages?", true)? {
+        let packages = homebrew.list_installed()?;
+        if packages.is_empty() {
+            println!("{}", "No Homebrew packages found".warning());
+        } else {
+            let selected = select_packages(packages)?;
+            homebrew.save_packages(&selected)?;
+            homebrew.save_taps(&homebrew.list_taps()?)?;
+            println!("  {} managing {} packages", "✓".success(), selected.len());
+        }
+    }
+
+    if ask("Install shell completions?", true)? {
+        match completions::resolve_shell(None).and_then(|shell| completions::install(Cli::command(), shell)) {
+            Ok(report) => println!("  {} completions in {}", "✓".success(), report.script.display()),
+            Err(e) => println!("  {} completions: {}", "✗".error(), e),
+        }
+    }
+
+    let agent = agent::Agent::default();
+    if !agent.is_installed() && ask("Sync changes in the background from login (kiwi watch)?", sync.is_some())? {
+        match install_agent(&agent) {
+            Ok(()) => println!("  {} {}", "✓".success(), agent.plist_path().display()),
+            Err(e) => println!("  {} background agent: {}", "✗".error(), e),
+        }
+    }
+
+    println!("{}", "✨ Initialization complete! Your environment is ready.".success().bold());
+    Ok(())
+}
+
+/// Makes sure this Mac has an SSH key in the agent and Keychain, generating
+/// one when it has none, then prints and copies the public half and, with
+/// `github`, registers it there.
+async fn setup_ssh_key(github: bool) -> Result<()> {
+    let keys = ssh::SshKeys::default();
+    let key = match keys.existing().into_iter().next() {
+        Some(key) => {
+            println!("Using {}", key.display());
+            key
+        }
+        None => {
+            let comment = format!("{}@{}", std::env::var("USER").unwrap_or_default(), ops::computer_name());
+            keys.generate(&comment)?
+        }
+    };
+    keys.add_to_agent(&key)?;
+    println!("  {} {} is in the agent and Keychain", "✓".success(), key.display());
+
+    let public_key = ssh::public_key(&key)?;
+    println!("{}", public_key);
+    if ssh::copy_to_clipboard(&public_key) {
+        println!("  {} copied to the clipboard", "✓".success());
+    }
+
+    if !github {
+        println!("Add it at https://github.com/settings/ssh/new, or run `kiwi ssh-key --github`.");
+        return Ok(());
+    }
+    let token = match std::env::var("GITHUB_TOKEN") {
+        Ok(token) if !token.is_empty() => token,
+        _ => Password::with_theme(&ColorfulTheme::default())
+            .with_prompt("GitHub token with the write:public_key scope")
+            .interact()
+            .map_err(|e| KiwiError::InvalidCommand(format!("Failed to read token: {}", e)))?,
+    };
+    ssh::upload_to_github(&token, &ops::computer_name(), &public_key).await?;
+    println!("  {} registered with GitHub as \"{}\"", "✓".success(), ops::computer_name());
+    Ok(())
+}
+
+/// Installs the `kiwi watch` launchd agent for this binary.
+fn install_agent(agent: &agent::Agent) -> Result<()> {
+    let program = std::env::current_exe()?;
+    let home = dirs::home_dir().ok_or_else(|| KiwiError::Config("Could not find home directory".to_string()))?;
+    agent.install(&program, &home.join(".kiwi/logs/watch.log"))
+}
+
+/// Asks which of `packages` kiwi should manage, all selected to start.
+fn select_packages(packages: Vec<crate::homebrew::Package>) -> Result<Vec<crate::homebrew::Package>> {
+    let labels: Vec<String> = packages
+        .iter()
+        .map(|p| format!("{} ({})", p.name, p.version.as_deref().unwrap_or("latest")))
+        .collect();
+    let chosen = MultiSelect::with_theme(&ColorfulTheme::default())
+        .with_prompt("Select the packages kiwi should manage (space to toggle, enter to confirm)")
+        .items(&labels)
+        .defaults(&vec![true; labels.len()])
+        .interact()
+        .map_err(|e| KiwiError::InvalidCommand(format!("Failed to read selection: {}", e)))?;
+    Ok(packages
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| chosen.contains(i))
+        .map(|(_, p)| p)
+        .collect())
+}
+
+/// Writes each starter pack's configs and installs the packages they need.
+fn apply_starter_packs(packs: &[&starters::Pack], config: &Config, dotfiles: &Dotfiles, homebrew: &mut Homebrew, reporter: &CliReporter) -> Result<()> {
+    if packs.is_empty() {
+        return Ok(());
+    }
+    let home = dirs::home_dir().ok_or_else(|| KiwiError::Config("Could not find home directory".to_string()))?;
+    let vars = crate::template::variables(config);
+    for pack in packs {
+        reporter.progress(&format!("Adding starter pack: {}", pack.name));
+        let applied = starters::apply(pack, &home, dotfiles, &vars)?;
+        for path in applied.written {
+            reporter.println(format!("  {} {}", "✓".success(), path.display()));
+        }
+        for path in applied.skipped {
+            reporter.println(format!("  {} {} already exists, left as is", "~".warning(), path.display()));
+        }
+    }
+
+    let wanted: Vec<&str> = packs.iter().flat_map(|pack| pack.packages.iter().copied()).collect();
+    install_missing(homebrew, &wanted, "Starter pack", reporter);
+    Ok(())
+}
+
+/// Restores an archive's files and manifests, tracks its dotfiles, and
+/// optionally installs its packages; shared by import and receive.
+fn apply_archive(contents: &archive::Archive, config: &Config, dotfiles: &Dotfiles, homebrew: &mut Homebrew, install: bool, yes: bool) -> Result<()> {
+    let mut paths: Vec<&PathBuf> = contents.files.keys().collect();
+    paths.sort();
+    if !paths.is_empty() {
+        println!("{}", "Files to write:".warning());
+        for path in &paths {
+            let existing = if path.exists() { " (replaces the current file)".dimmed().to_string() } else { String::new() };
+            println!("  {}{}", path.display(), existing);
+        }
+        if !CliReporter::new(None, yes).confirm(&format!("Write these {} file(s)?", paths.len())) {
+            return Err(KiwiError::UserCancelled);
+        }
+    }
+
+    let backups = config.preferences.backup_before_change.then(|| crate::backups::Backups::new(&config.dotfiles_dir));
+    let home = dirs::home_dir().unwrap_or_default();
+    for path in paths {
+        let data = &contents.files[path];
+        if let Some(backups) = &backups {
+            let name = path.strip_prefix(&home).unwrap_or(path).to_string_lossy().trim_start_matches('/').to_string();
+            if let Some(backup) = backups.save(&name, path, data)? {
+                println!("{} {}", "Backed up:".warning(), backup.display());
+            }
+        }
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, data)?;
+        println!("  {} {}", "✓".success(), path.display());
+    }
+
+    if let Some(packages) = &contents.packages {
+        std::fs::write(config.dotfiles_dir.join("packages.json"), packages)?;
+    }
+    if let Some(taps) = &contents.taps {
+        std::fs::write(config.dotfiles_dir.join("taps.json"), taps)?;
+    }
+
+    let tracked: Vec<PathBuf> = dotfiles.list()?.into_iter().map(|d| d.path).collect();
+    for dotfile in &contents.environment.dotfiles {
+        if dotfile.path.exists() && !tracked.contains(&dotfile.path) {
+            dotfiles.add_with_mode(&dotfile.path, dotfile.alias.clone(), dotfile.pointer, dotfile.mode)?;
+        }
+    }
+
+    if install {
+        *homebrew = ops::package_store(config);
+        println!("{}", "Restoring taps and packages...".warning());
+        let summary = homebrew.restore()?;
+        for name in summary.tapped.iter().chain(&summary.installed) {
+            println!("  {} {}", "✓".success(), name);
+        }
+        for (name, error) in &summary.failed {
+            println!("  {} {}: {}", "✗".error(), name, error);
+        }
+        ops::review_gatekeeper(config, homebrew, &summary.installed, &CliReporter::new(None, false));
+    }
+    Ok(())
+}
+
+/// Reports what an import from another home brought over, hands the files
+/// back from root under sudo, and installs the packages if asked.
+fn finish_home_import(config: &Config, homebrew: &mut Homebrew, imported: &ops::HomeImport, install: bool) -> Result<()> {
+    if let Some(home) = dirs::home_dir() {
+        let mut written = imported.imported.clone();
+        written.push(config.dotfiles_dir.clone());
+        migrate::hand_back(&written, &home)?;
+    }
+
+    for path in &imported.kept {
+        println!("  {} {} (this Mac's version differs; kept it)", "•".dimmed(), path.display());
+    }
+    for (path, reason) in &imported.skipped {
+        println!("  {} {}: {}", "⚠️".warning(), path.display(), reason);
+    }
+    println!("{} {} config(s), {} package(s), {} tap(s)", "Imported".success(), imported.imported.len(), imported.packages, imported.taps);
+
+    if install && imported.packages > 0 {
+        println!("{}", "Restoring taps and packages...".warning());
+        let summary = homebrew.restore()?;
+        for name in summary.tapped.iter().chain(&summary.installed) {
+            println!("  {} {}", "✓".success(), name);
+        }
+        for (name, error) in &summary.failed {
+            println!("  {} {}: {}", "✗".error(), name, error);
+        }
+        ops::review_gatekeeper(config, homebrew, &summary.installed, &CliReporter::new(None, false));
+    } else if imported.packages > 0 {
+        println!("Pass --install, or run `kiwi reconcile`, to install the packages.");
+    }
+    println!("{}", "✓ Import complete".success());
+    Ok(())
+}
+
+/// Prints a unified diff with added lines green and removed lines red.
+fn print_unified(diff: &str) {
+    for line in diff.lines() {
+        if line.starts_with('+') {
+            println!("{}", line.success());
+        } else if line.starts_with('-') {
+            println!("{}", line.error());
+        } else {
+            println!("{}", line);
+        }
+    }
+}
+
+/// Prints what a restore would do, section by section, skipping empty ones.
+fn print_restore_plan(plan: &RestorePlan) {
+    if plan.is_empty() {
+        println!("{}", "Nothing to restore; this Mac already has everything".success());
+        return;
+    }
+    println!("{}", "Restore plan:".accent().bold());
+    if plan.install_command_line_tools {
+        println!("  Install the Xcode Command Line Tools");
+    }
+    if plan.install_homebrew {
+        println!("  Install Homebrew");
+    }
+
+    if !plan.taps.is_empty() {
+        println!("\n{}", format!("Taps to add ({}):", plan.taps.len()).accent());
+        for tap in &plan.taps {
+            println!("  {}", tap);
+        }
+    }
+
+    if !plan.packages.is_empty() {
+        println!("\n{}", format!("Packages to install ({}):", plan.packages.len()).accent());
+        let mut rows = table::Table::new(&["NAME", "VERSION", "KIND", "DOWNLOAD", "INSTALLED"]);
+        for package in &plan.packages {
+            rows.row(vec![
+                package.name.clone(),
+                package.version.clone().unwrap_or_else(|| "-".to_string()),
+                if package.cask { "cask" } else { "formula" }.to_string(),
+                table::format_size(package.download_size),
+                table::format_size(package.installed_size),
+            ]);
+        }
+        print_table(&rows, true);
+        for package in plan.packages.iter().filter(|p| !p.post_install.is_empty()) {
+            println!("  {} then runs: {}", package.name, package.post_install.join("; "));
+        }
+        let mut total = format!(
+            "About {} to download, {} on disk",
+            table::format_size(Some(plan.download_size)),
+            table::format_size(Some(plan.installed_size))
+        );
+        if plan.unsized_packages > 0 {
+            total.push_str(&format!(", plus {} package(s) of unknown size", plan.unsized_packages));
+        }
+        println!("  {}", total);
+    }
+    if plan.already_installed > 0 {
+        println!("  {} package(s) already installed", plan.already_installed);
+    }
+
+    if !plan.files.is_empty() {
+        println!("\n{}", "Files:".accent());
+        for file in &plan.files {
+            match file.action {
+                FileAction::Write => println!("  {} {} (write)", "+".success(), file.name),
+                FileAction::Keep => println!("  {} {} (keep this Mac's copy)", "=".normal(), file.name),
+                FileAction::Pointer => println!("  {} {} (pointer only; not restored)", "!".warning(), file.name),
+            }
+        }
+    }
+
+    if !plan.settings.is_empty() {
+        println!("\n{}", "macOS settings to apply:".accent());
+        for setting in &plan.settings {
+            println!("  {}", setting.recipe.bold());
+            for command in &setting.commands {
+                println!("    {}", command);
+            }
+        }
+    }
+
+    let minutes = plan.estimated_duration().as_secs().div_ceil(60);
+    println!("\n{} about {} minute(s)", "Estimated time:".accent(), minutes);
+}
+
+/// Lists which way each file went in a two-way sync.
+fn print_merge_summary(summary: &MergeSummary) {
+    if summary.pushed.is_empty() && summary.pulled.is_empty() && summary.conflicts.is_empty() && summary.dropped.is_empty() {
+        println!("{}", "Everything is already in sync".success());
+        return;
+    }
+    println!("\n{}", "Files:".accent());
+    for name in &summary.pushed {
+        println!("  {} {} (pushed)", "↑".success(), name);
+    }
+    for name in &summary.pulled {
+        println!("  {} {} (pulled)", "↓".success(), name);
+    }
+    for name in &summary.dropped {
+        println!("  {} {} (deleted here; dropped from the remote)", "-".success(), name);
+    }
+    for name in &summary.conflicts {
+        println!("  {} {} (changed on both sides; left alone)", "!".warning(), name);
+    }
+    if !summary.conflicts.is_empty() {
+        println!("{}", "Settle them with `kiwi sync --push --force` or `kiwi sync --pull --force`".warning());
+    }
+}
+
+/// Lists what a dotfiles refresh did, one line per file.
+fn print_sync_summary(summary: &SyncSummary) {
+    if summary.is_empty() {
+        return;
+    }
+    println!("\n{}", "Dotfiles:".accent());
+    for path in &summary.copied {
+        println!("  {} {} (updated from remote)", "↓".success(), path.display());
+    }
+    for path in &summary.relinked {
+        println!("  {} {} (relinked)", "~".warning(), path.display());
+    }
+    for path in &summary.modified {
+        println!("  {} {} (local changes)", "•".accent(), path.display());
+    }
+    for path in &summary.missing {
+        println!("  {} {} (missing)", "✗".error(), path.display());
+    }
+    for path in &summary.locked {
+        println!("  {} {} (encrypted; run `kiwi unlock`)", "!".warning(), path.display());
+    }
+}
+
+/// One line per fact from a sync server check; what the server didn't
+/// report is left out.
+fn print_remote_status(status: &RemoteStatus) {
+    println!("\n{}", "Sync server:".accent());
+    let mark = |ok: bool| if ok { "✓".success() } else { "✗".error() };
+    match status.latency {
+        Some(latency) => println!("  {} Reachable ({} ms)", mark(true), latency.as_millis()),
+        None => println!("  {} Not reachable", mark(false)),
+    }
+    if status.reachable {
+        let answer = if status.authenticated { "Token accepted" } else { "Token rejected" };
+        println!("  {} {}", mark(status.authenticated), answer);
+    }
+    if let Some(version) = &status.server_version {
+        println!("  Server version: {}", version);
+    }
+    if let Some(quota) = &status.quota {
+        println!("  Storage: {}", describe_quota(quota));
+    }
+    if let Some(error) = &status.error {
+        println!("  {}", error.error());
+    }
+}
+
+/// `1.2 MB of 1024.0 MB used (0%)`, or just what's used without a limit.
+fn describe_quota(quota: &Quota) -> String {
+    match quota.limit {
+        Some(limit) if limit > 0 => format!(
+            "{} of {} used ({}%)",
+            table::format_size(Some(quota.used)),
+            table::format_size(Some(limit)),
+            quota.used.saturating_mul(100) / limit
+        ),
+        _ => format!("{} used", table::format_size(Some(quota.used))),
+    }
+}
+
+/// Prints a table indented under a section title, with a bold header row;
+/// without `header` only the bare rows are printed.
+fn print_table(table: &table::Table, header: bool) {
+    for (i, line) in table.render(header).iter().enumerate() {
+        match (header, i) {
+            (false, _) => println!("{}", line),
+            (true, 0) => println!("  {}", line.bold()),
+            (true, _) => println!("  {}", line),
+        }
+    }
+}
+
+/// Offers to install the packages in `wanted` that aren't installed yet,
+/// recording `note` as the reason. Failures are reported and skipped.
+fn install_missing(homebrew: &mut Homebrew, wanted: &[&str], note: &str, reporter: &dyn Reporter) {
+    let installed = homebrew.installed_names().unwrap_or_default();
+    let mut missing: Vec<&str> = wanted.iter().copied().filter(|p| !installed.iter().any(|i| i == p)).collect();
+    missing.sort();
+    missing.dedup();
+    if missing.is_empty() || !reporter.confirm(&format!("Install {}?", missing.join(", "))) {
+        return;
     }
-} 
\ No newline at end of file
+
+    for package in missing {
+        reporter.progress(&format!("Installing {}...", package));
+        match homebrew.install(package, Some(note.to_string())) {
+            Ok(()) => reporter.success(&format!("installed {}", package)),
+            Err(e) => reporter.warning(&format!("{}: {}", package, e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cli_definition() {
+        Cli::command().debug_assert();
+    }
+}
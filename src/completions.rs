@@ -0,0 +1,83 @@
+use crate::{Result, KiwiError, shell};
+use clap_complete::Shell;
+use std::fs;
+use std::path::PathBuf;
+
+pub const RC_BLOCK: &str = "completions";
+
+pub struct InstallReport {
+    pub script: PathBuf,
+    /// Set when the shell's rc file had to be updated to load the script
+    pub rc_file: Option<PathBuf>,
+}
+
+/// Resolves the shell to generate completions for, defaulting to `$SHELL`.
+pub fn resolve_shell(shell: Option<Shell>) -> Result<Shell> {
+    shell
+        .or_else(Shell::from_env)
+        .ok_or_else(|| KiwiError::ValidationError("Could not detect your shell; pass it explicitly".to_string()))
+}
+
+pub fn generate(mut cmd: clap::Command, shell: Shell) -> Vec<u8> {
+    let mut script = Vec::new();
+    clap_complete::generate(shell, &mut cmd, "kiwi", &mut script);
+    script
+}
+
+/// Writes the completion script where the shell looks for it and wires up
+/// the rc file when the location isn't loaded by default.
+pub fn install(cmd: clap::Command, shell: Shell) -> Result<InstallReport> {
+    let script = script_path(shell)?;
+    if let Some(parent) = script.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&script, generate(cmd, shell))?;
+
+    let rc_file = match shell {
+        Shell::Zsh => {
+            let rc = shell::rc_file("zsh")?;
+            let dir = script.parent().unwrap_or(&script).display().to_string();
+            let body = format!("fpath=({} $fpath)\nautoload -Uz compinit && compinit", dir);
+            shell::ensure_block(&rc, RC_BLOCK, &body)?.then_some(rc)
+        }
+        // bash-completion and fish both autoload from the directories we write to
+        _ => None,
+    };
+
+    Ok(InstallReport { script, rc_file })
+}
+
+/// Removes the installed script and any rc block. Returns the paths that changed.
+pub fn uninstall(shell: Shell) -> Result<Vec<PathBuf>> {
+    let mut removed = Vec::new();
+
+    let script = script_path(shell)?;
+    if script.exists() {
+        fs::remove_file(&script)?;
+        removed.push(script);
+    }
+
+    if shell == Shell::Zsh {
+        let rc = shell::rc_file("zsh")?;
+        if shell::remove_block(&rc, RC_BLOCK)? {
+            removed.push(rc);
+        }
+    }
+
+    Ok(removed)
+}
+
+fn script_path(shell: Shell) -> Result<PathBuf> {
+    let home = dirs::home_dir().ok_or_else(|| {
+        KiwiError::Config("Could not find home directory".to_string())
+    })?;
+    match shell {
+        Shell::Zsh => Ok(home.join(".zsh/completions/_kiwi")),
+        Shell::Bash => Ok(home.join(".local/share/bash-completion/completions/kiwi")),
+        Shell::Fish => Ok(home.join(".config/fish/completions/kiwi.fish")),
+        other => Err(KiwiError::ValidationError(format!(
+            "Installing completions for {} isn't supported; redirect `kiwi completions {}` instead",
+            other, other
+        ))),
+    }
+}
@@ -1,6 +1,7 @@
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use crate::{Result, KiwiError};
+use crate::policy::Policy;
 use std::fs;
 use std::collections::HashMap;
 
@@ -16,6 +17,15 @@ pub struct Config {
     pub preferences: Preferences,
     #[serde(default)]
     pub custom_settings: HashMap<String, String>,
+    #[serde(default)]
+    pub excluded_packages: Vec<String>,
+    /// Tracked files whose changes alone don't make `kiwi watch` push, on
+    /// top of the built-in ones
+    #[serde(default)]
+    pub watch_ignore: Vec<String>,
+    /// The admin's policy, read alongside the config rather than from it
+    #[serde(skip)]
+    pub policy: Policy,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -34,6 +44,30 @@ pub struct Preferences {
     pub max_parallel_downloads: u32,
     #[serde(default = "default_backup_retention_days")]
     pub backup_retention_days: u32,
+    #[serde(default = "default_color")]
+    pub color: String,
+    #[serde(default = "default_accent_color")]
+    pub accent_color: String,
+    #[serde(default = "default_success_color")]
+    pub success_color: String,
+    #[serde(default = "default_warning_color")]
+    pub warning_color: String,
+    #[serde(default = "default_error_color")]
+    pub error_color: String,
+    #[serde(default = "default_telemetry")]
+    pub telemetry: bool,
+    /// Files larger than this need `--force` or `--pointer` to be added; 0 disables the check
+    #[serde(default = "default_large_file_threshold_mb")]
+    pub large_file_threshold_mb: u64,
+    /// How often `kiwi watch` checks for changes to sync
+    #[serde(default = "default_sync_interval_minutes")]
+    pub sync_interval_minutes: u64,
+    /// Remove the quarantine from apps a restore installs, so Gatekeeper doesn't stop them
+    #[serde(default = "default_clear_quarantine")]
+    pub clear_quarantine: bool,
+    /// Installs that download more than this ask first; 0 never asks
+    #[serde(default = "default_download_confirm_threshold_mb")]
+    pub download_confirm_threshold_mb: u64,
 }
 
 // Default value functions
@@ -44,6 +78,19 @@ fn default_show_progress_bars() -> bool { true }
 fn default_verbose_output() -> bool { false }
 fn default_max_parallel_downloads() -> u32 { 4 }
 fn default_backup_retention_days() -> u32 { 30 }
+fn default_color() -> String { "auto".to_string() }
+fn default_accent_color() -> String { "blue".to_string() }
+fn default_success_color() -> String { "green".to_string() }
+fn default_warning_color() -> String { "yellow".to_string() }
+fn default_error_color() -> String { "red".to_string() }
+fn default_telemetry() -> bool { false }
+fn default_large_file_threshold_mb() -> u64 { 10 }
+fn default_sync_interval_minutes() -> u64 { 15 }
+fn default_clear_quarantine() -> bool { false }
+fn default_download_confirm_threshold_mb() -> u64 { 1024 }
+
+const COLOR_MODES: &[&str] = &["auto", "always", "never"];
+const THEME_KEYS: &[&str] = &["accent_color", "success_color", "warning_color", "error_color"];
 
 impl Default for Preferences {
     fn default() -> Self {
@@ -55,6 +102,16 @@ impl Default for Preferences {
             verbose_output: default_verbose_output(),
             max_parallel_downloads: default_max_parallel_downloads(),
             backup_retention_days: default_backup_retention_days(),
+            color: default_color(),
+            accent_color: default_accent_color(),
+            success_color: default_success_color(),
+            warning_color: default_warning_color(),
+            error_color: default_error_color(),
+            telemetry: default_telemetry(),
+            large_file_threshold_mb: default_large_file_threshold_mb(),
+            sync_interval_minutes: default_sync_interval_minutes(),
+            clear_quarantine: default_clear_quarantine(),
+            download_confirm_threshold_mb: default_download_confirm_threshold_mb(),
         }
     }
 }
@@ -69,6 +126,9 @@ impl Default for Config {
             environment: None,
             preferences: Preferences::default(),
             custom_settings: HashMap::new(),
+            excluded_packages: Vec::new(),
+            watch_ignore: Vec::new(),
+            policy: Policy::default(),
         }
     }
 }
@@ -78,21 +138,31 @@ impl Config {
         let config_path = Self::config_path()?;
         
         if !config_path.exists() {
-            let config = Config::default();
+            let mut config = Config::default();
             config.save()?;
+            config.policy = Policy::load()?;
+            config.apply_policy();
             return Ok(config);
         }
 
-        let contents = fs::read_to_string(&config_path).map_err(|e| {
+        Self::load_from(&config_path)
+    }
+
+    /// Reads and validates the config at `path` without creating it.
+    pub fn load_from(config_path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(config_path).map_err(|e| {
             KiwiError::Config(format!("Failed to read config file: {}", e))
         })?;
 
-        let config: Config = serde_json::from_str(&contents).map_err(|e| {
+        let mut config: Config = serde_json::from_str(&contents).map_err(|e| {
             KiwiError::Config(format!("Invalid config file format: {}", e))
         })?;
 
         // Validate and fix any issues
         config.validate()?;
+
+        config.policy = Policy::load()?;
+        config.apply_policy();
         
         Ok(config)
     }
@@ -121,24 +191,98 @@ impl Config {
         Ok(())
     }
 
-    fn config_path() -> Result<PathBuf> {
+    pub fn config_path() -> Result<PathBuf> {
         let home = dirs::home_dir().ok_or_else(|| {
             KiwiError::Config("Could not find home directory".to_string())
         })?;
         Ok(home.join(".kiwi/config.json"))
     }
 
-    pub fn get(&self, key: &str) -> Option<&str> {
+    pub fn get(&self, key: &str) -> Option<String> {
         match key {
-            "dotfiles_dir" => Some(self.dotfiles_dir.to_str()?),
-            "sync_url" => self.sync_url.as_deref(),
-            "sync_token" => self.sync_token.as_deref(),
-            "environment" => self.environment.as_deref(),
-            _ => self.custom_settings.get(key).map(|s| s.as_str()),
+            "dotfiles_dir" => Some(self.dotfiles_dir.to_str()?.to_string()),
+            "sync_url" => self.sync_url.clone(),
+            "sync_token" => self.sync_token.clone(),
+            "environment" => self.environment.clone(),
+            "color" => Some(self.preferences.color.clone()),
+            "accent_color" => Some(self.preferences.accent_color.clone()),
+            "success_color" => Some(self.preferences.success_color.clone()),
+            "warning_color" => Some(self.preferences.warning_color.clone()),
+            "error_color" => Some(self.preferences.error_color.clone()),
+            "large_file_threshold_mb" => Some(self.preferences.large_file_threshold_mb.to_string()),
+            "sync_interval_minutes" => Some(self.preferences.sync_interval_minutes.to_string()),
+            "clear_quarantine" => Some(self.preferences.clear_quarantine.to_string()),
+            "download_confirm_threshold_mb" => Some(self.preferences.download_confirm_threshold_mb.to_string()),
+            _ => self.custom_settings.get(key).cloned(),
+        }
+    }
+
+    /// Where `kiwi templates` looks for its index: the `template_registry`
+    /// setting, or the sync server's `/templates/index.json`.
+    pub fn template_registry(&self) -> Option<String> {
+        self.custom_settings.get("template_registry").cloned().or_else(|| {
+            self.sync_url
+                .as_ref()
+                .map(|url| format!("{}/templates/index.json", url.trim_end_matches('/')))
+        })
+    }
+
+    /// The age recipient encrypted dotfiles are encrypted to, with the
+    /// identity file that decrypts them: `encryption_identity`, or
+    /// `~/.kiwi/identity.txt`.
+    pub fn encryption_recipient(&self) -> Option<(String, PathBuf)> {
+        let recipient = self.custom_settings.get("encryption_recipient")?.clone();
+        let identity = match self.custom_settings.get("encryption_identity") {
+            Some(identity) => PathBuf::from(identity),
+            None => dirs::home_dir()?.join(".kiwi/identity.txt"),
+        };
+        Some((recipient, identity))
+    }
+
+    /// The large-file threshold in bytes, or `None` when the check is disabled.
+    pub fn large_file_limit(&self) -> Option<u64> {
+        match self.preferences.large_file_threshold_mb {
+            0 => None,
+            mb => Some(mb * 1024 * 1024),
+        }
+    }
+
+    /// Download size in bytes above which installs ask first, or `None` when
+    /// they never do.
+    pub fn download_confirm_limit(&self) -> Option<u64> {
+        match self.preferences.download_confirm_threshold_mb {
+            0 => None,
+            mb => Some(mb * 1024 * 1024),
         }
     }
 
     pub fn set(&mut self, key: &str, value: String) -> Result<()> {
+        if let Some(locked) = self.policy.lock(key).filter(|locked| *locked != value) {
+            return Err(KiwiError::InvalidConfig {
+                key: key.to_string(),
+                message: format!("Locked to {} by your organization's policy", locked),
+            });
+        }
+        self.assign(key, value)?;
+        self.save()?;
+        Ok(())
+    }
+
+    /// Overrides the config's values with the ones the policy locks, in
+    /// memory only; a locked value this kiwi can't take is left for
+    /// `kiwi doctor` to report.
+    pub fn apply_policy(&mut self) {
+        for (key, value) in self.policy.locked.clone() {
+            if self.get(&key).as_deref() != Some(value.as_str()) {
+                if let Err(e) = self.assign(&key, value) {
+                    log::warn!("Couldn't apply the policy's {}: {}", key, e);
+                }
+            }
+        }
+    }
+
+    /// `set` without saving.
+    fn assign(&mut self, key: &str, value: String) -> Result<()> {
         match key {
             "dotfiles_dir" => {
                 let path = PathBuf::from(&value);
@@ -170,14 +314,120 @@ impl Config {
                 }
                 self.environment = Some(value);
             }
+            "color" => {
+                if !COLOR_MODES.contains(&value.as_str()) {
+                    return Err(KiwiError::InvalidConfig {
+                        key: key.to_string(),
+                        message: "Color mode must be one of: auto, always, never".to_string(),
+                    });
+                }
+                self.preferences.color = value;
+            }
+            "accent_color" | "success_color" | "warning_color" | "error_color" => {
+                if value.parse::<colored::Color>().is_err() {
+                    return Err(KiwiError::InvalidConfig {
+                        key: key.to_string(),
+                        message: format!("Unknown color: {}", value),
+                    });
+                }
+                match key {
+                    "accent_color" => self.preferences.accent_color = value,
+                    "success_color" => self.preferences.success_color = value,
+                    "warning_color" => self.preferences.warning_color = value,
+                    _ => self.preferences.error_color = value,
+                }
+            }
+            "large_file_threshold_mb" => {
+                self.preferences.large_file_threshold_mb = value.parse().map_err(|_| KiwiError::InvalidConfig {
+                    key: key.to_string(),
+                    message: "Threshold must be a whole number of megabytes (0 disables it)".to_string(),
+                })?;
+            }
+            "sync_interval_minutes" => {
+                self.preferences.sync_interval_minutes = value.parse().ok().filter(|minutes| *minutes > 0).ok_or_else(|| KiwiError::InvalidConfig {
+                    key: key.to_string(),
+                    message: "Interval must be a whole number of minutes greater than 0".to_string(),
+                })?;
+            }
+            "clear_quarantine" => {
+                self.preferences.clear_quarantine = value.parse().map_err(|_| KiwiError::InvalidConfig {
+                    key: key.to_string(),
+                    message: "Must be true or false".to_string(),
+                })?;
+            }
+            "download_confirm_threshold_mb" => {
+                self.preferences.download_confirm_threshold_mb = value.parse().map_err(|_| KiwiError::InvalidConfig {
+                    key: key.to_string(),
+                    message: "Threshold must be a whole number of megabytes (0 never asks)".to_string(),
+                })?;
+            }
+            "encryption_recipient" => {
+                if !value.starts_with("age1") {
+                    return Err(KiwiError::InvalidConfig {
+                        key: key.to_string(),
+                        message: "Must be an age X25519 recipient (age1...), as age-keygen prints".to_string(),
+                    });
+                }
+                self.custom_settings.insert(key.to_string(), value);
+            }
             _ => {
                 self.custom_settings.insert(key.to_string(), value);
             }
         }
-        self.save()?;
         Ok(())
     }
 
+    pub fn add_exclude(&mut self, pattern: &str) -> Result<bool> {
+        if pattern.trim().is_empty() {
+            return Err(KiwiError::InvalidConfig {
+                key: "excluded_packages".to_string(),
+                message: "Exclusion pattern cannot be empty".to_string(),
+            });
+        }
+        if self.excluded_packages.iter().any(|p| p == pattern) {
+            return Ok(false);
+        }
+        self.excluded_packages.push(pattern.to_string());
+        self.save()?;
+        Ok(true)
+    }
+
+    pub fn remove_exclude(&mut self, pattern: &str) -> Result<bool> {
+        let before = self.excluded_packages.len();
+        self.excluded_packages.retain(|p| p != pattern);
+        if self.excluded_packages.len() == before {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    /// Adds a `kiwi watch` ignore rule. Returns false if it was already there.
+    pub fn add_watch_ignore(&mut self, pattern: &str) -> Result<bool> {
+        if pattern.trim().is_empty() {
+            return Err(KiwiError::InvalidConfig {
+                key: "watch_ignore".to_string(),
+                message: "Ignore pattern cannot be empty".to_string(),
+            });
+        }
+        if self.watch_ignore.iter().any(|p| p == pattern) {
+            return Ok(false);
+        }
+        self.watch_ignore.push(pattern.to_string());
+        self.save()?;
+        Ok(true)
+    }
+
+    pub fn remove_watch_ignore(&mut self, pattern: &str) -> Result<bool> {
+        let before = self.watch_ignore.len();
+        self.watch_ignore.retain(|p| p != pattern);
+        if self.watch_ignore.len() == before {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+
     pub fn validate(&self) -> Result<()> {
         // Validate dotfiles directory
         if !self.dotfiles_dir.exists() {
@@ -214,6 +464,13 @@ impl Config {
             });
         }
 
+        if self.preferences.sync_interval_minutes == 0 {
+            return Err(KiwiError::InvalidConfig {
+                key: "sync_interval_minutes".to_string(),
+                message: "Must be greater than 0".to_string(),
+            });
+        }
+
         if self.preferences.backup_retention_days == 0 {
             return Err(KiwiError::InvalidConfig {
                 key: "backup_retention_days".to_string(),
@@ -221,6 +478,23 @@ impl Config {
             });
         }
 
+        if !COLOR_MODES.contains(&self.preferences.color.as_str()) {
+            return Err(KiwiError::InvalidConfig {
+                key: "color".to_string(),
+                message: "Color mode must be one of: auto, always, never".to_string(),
+            });
+        }
+
+        for key in THEME_KEYS {
+            let value = self.get(key).unwrap_or_default();
+            if value.parse::<colored::Color>().is_err() {
+                return Err(KiwiError::InvalidConfig {
+                    key: key.to_string(),
+                    message: format!("Unknown color: {}", value),
+                });
+            }
+        }
+
         Ok(())
     }
 
@@ -233,6 +507,18 @@ impl Config {
             self.custom_settings.insert(key.clone(), value.clone());
         }
 
+        // Merge exclusion patterns
+        for pattern in &other.excluded_packages {
+            if !self.excluded_packages.contains(pattern) {
+                self.excluded_packages.push(pattern.clone());
+            }
+        }
+        for pattern in &other.watch_ignore {
+            if !self.watch_ignore.contains(pattern) {
+                self.watch_ignore.push(pattern.clone());
+            }
+        }
+
         // Only update optional fields if they are Some in other
         if other.sync_url.is_some() {
             self.sync_url = other.sync_url.clone();
@@ -0,0 +1,128 @@
+use crate::{Result, KiwiError};
+use age::secrecy::SecretString;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+const AGE_MAGIC: &[u8] = b"age-encryption.org/v1";
+
+/// Whether the data is an age-encrypted payload.
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(AGE_MAGIC)
+}
+
+pub fn encrypt_with_passphrase(data: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+    let encryptor = age::Encryptor::with_user_passphrase(SecretString::from(passphrase.to_string()));
+
+    let mut encrypted = Vec::new();
+    let mut writer = encryptor.wrap_output(&mut encrypted)?;
+    writer.write_all(data)?;
+    writer.finish()?;
+    Ok(encrypted)
+}
+
+pub fn decrypt_with_passphrase(data: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+    let decryptor = age::Decryptor::new_buffered(data).map_err(|e| {
+        KiwiError::ValidationError(format!("Not a valid encrypted payload: {}", e))
+    })?;
+    let identity = age::scrypt::Identity::new(SecretString::from(passphrase.to_string()));
+
+    let mut reader = decryptor
+        .decrypt(std::iter::once(&identity as &dyn age::Identity))
+        .map_err(|e| KiwiError::AuthError(format!("Failed to decrypt: {}", e)))?;
+
+    let mut decrypted = Vec::new();
+    reader.read_to_end(&mut decrypted)?;
+    Ok(decrypted)
+}
+
+/// What encrypted dotfiles are encrypted and decrypted with.
+#[derive(Debug, Clone)]
+pub enum Key {
+    /// An age X25519 recipient (`age1...`), decrypted with the identities
+    /// in a file such as one `age-keygen` wrote
+    Recipient { recipient: String, identity: PathBuf },
+    Passphrase(String),
+}
+
+impl Key {
+    pub fn encrypt(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let recipient = match self {
+            Key::Passphrase(passphrase) => return encrypt_with_passphrase(data, passphrase),
+            Key::Recipient { recipient, .. } => age::x25519::Recipient::from_str(recipient)
+                .map_err(|e| KiwiError::ValidationError(format!("Invalid age recipient {}: {}", recipient, e)))?,
+        };
+        let encryptor = age::Encryptor::with_recipients(std::iter::once(&recipient as &dyn age::Recipient))
+            .map_err(|e| KiwiError::ValidationError(format!("Failed to encrypt: {}", e)))?;
+
+        let mut encrypted = Vec::new();
+        let mut writer = encryptor.wrap_output(&mut encrypted)?;
+        writer.write_all(data)?;
+        writer.finish()?;
+        Ok(encrypted)
+    }
+
+    pub fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let identity = match self {
+            Key::Passphrase(passphrase) => return decrypt_with_passphrase(data, passphrase),
+            Key::Recipient { identity, .. } => identity,
+        };
+        let identities = age::IdentityFile::from_file(identity.to_string_lossy().to_string())
+            .map_err(|e| KiwiError::Config(format!("Failed to read age identity {}: {}", identity.display(), e)))?
+            .into_identities()
+            .map_err(|e| KiwiError::Config(format!("Invalid age identity {}: {}", identity.display(), e)))?;
+        let decryptor = age::Decryptor::new_buffered(data).map_err(|e| {
+            KiwiError::ValidationError(format!("Not a valid encrypted payload: {}", e))
+        })?;
+
+        let mut reader = decryptor
+            .decrypt(identities.iter().map(|identity| identity.as_ref() as &dyn age::Identity))
+            .map_err(|e| KiwiError::AuthError(format!("Failed to decrypt: {}", e)))?;
+
+        let mut decrypted = Vec::new();
+        reader.read_to_end(&mut decrypted)?;
+        Ok(decrypted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use age::secrecy::ExposeSecret;
+    use std::fs;
+
+    /// Writes a fresh identity file to `dir` and returns its key.
+    fn recipient_key(dir: &std::path::Path, name: &str) -> Key {
+        let identity = age::x25519::Identity::generate();
+        let path = dir.join(name);
+        fs::write(&path, format!("{}\n", identity.to_string().expose_secret())).unwrap();
+        Key::Recipient { recipient: identity.to_public().to_string(), identity: path }
+    }
+
+    #[test]
+    fn test_passphrase_roundtrip() {
+        let key = Key::Passphrase("correct horse".to_string());
+        let encrypted = key.encrypt(b"export API_KEY=secret\n").unwrap();
+        assert!(is_encrypted(&encrypted));
+        assert_eq!(key.decrypt(&encrypted).unwrap(), b"export API_KEY=secret\n");
+
+        let wrong = Key::Passphrase("battery staple".to_string());
+        assert!(matches!(wrong.decrypt(&encrypted), Err(KiwiError::AuthError(_))));
+    }
+
+    #[test]
+    fn test_recipient_roundtrip() {
+        let dir = std::env::temp_dir().join(format!("kiwi-crypto-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let key = recipient_key(&dir, "key.txt");
+
+        let encrypted = key.encrypt(b"export API_KEY=secret\n").unwrap();
+        assert!(is_encrypted(&encrypted));
+        assert_eq!(key.decrypt(&encrypted).unwrap(), b"export API_KEY=secret\n");
+
+        let wrong = recipient_key(&dir, "other.txt");
+        assert!(matches!(wrong.decrypt(&encrypted), Err(KiwiError::AuthError(_))));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
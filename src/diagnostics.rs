@@ -0,0 +1,164 @@
+use crate::{Config, KiwiError, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const LOG_FILE: &str = "logs/kiwi.log";
+const LAST_FAILURE_FILE: &str = "last-failure.json";
+const RECENT_LOG_LINES: usize = 200;
+const REDACTED: &str = "[redacted]";
+const SENSITIVE_KEY_HINTS: &[&str] = &["token", "secret", "password", "key"];
+
+/// Context captured when a command fails, so it can be attached to bug reports.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FailureContext {
+    pub occurred_at: String,
+    pub kiwi_version: String,
+    pub command: Vec<String>,
+    pub error: String,
+    pub suggestion: Option<String>,
+}
+
+/// Records a failed invocation to the log and as the last failure.
+pub fn record_failure(error: &KiwiError) -> Result<()> {
+    let context = FailureContext {
+        occurred_at: chrono::Utc::now().to_rfc3339(),
+        kiwi_version: env!("CARGO_PKG_VERSION").to_string(),
+        command: redact_args(std::env::args().skip(1).collect()),
+        error: error.to_string(),
+        suggestion: error.suggestion(),
+    };
+
+    let dir = kiwi_dir()?;
+    let log_path = dir.join(LOG_FILE);
+    if let Some(parent) = log_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut log = OpenOptions::new().create(true).append(true).open(&log_path)?;
+    writeln!(log, "{} ERROR kiwi {}: {}", context.occurred_at, context.command.join(" "), context.error)?;
+
+    fs::write(dir.join(LAST_FAILURE_FILE), serde_json::to_string_pretty(&context)?)?;
+    Ok(())
+}
+
+/// Writes a zip with sanitized config, recent logs, the doctor report,
+/// tool versions, and the last failure. Returns the entries written.
+pub fn create_bundle(path: &Path, config: &Config, doctor_report: &str) -> Result<Vec<String>> {
+    let dir = kiwi_dir()?;
+    let mut entries: Vec<(&str, String)> = vec![
+        ("versions.txt", versions(config)),
+        ("config.json", serde_json::to_string_pretty(&sanitized_config(config)?)?),
+        ("doctor.md", doctor_report.to_string()),
+    ];
+
+    if let Ok(log) = fs::read_to_string(dir.join(LOG_FILE)) {
+        let lines: Vec<&str> = log.lines().collect();
+        let start = lines.len().saturating_sub(RECENT_LOG_LINES);
+        entries.push(("logs/kiwi.log", lines[start..].join("\n")));
+    }
+    if let Ok(failure) = fs::read_to_string(dir.join(LAST_FAILURE_FILE)) {
+        entries.push((LAST_FAILURE_FILE, failure));
+    }
+
+    let file = fs::File::create(path)?;
+    let mut zip = zip::ZipWriter::new(file);
+    let options = zip::write::SimpleFileOptions::default()
+        .compression_method(zip::CompressionMethod::Deflated)
+        .unix_permissions(0o600);
+
+    let home = dirs::home_dir().map(|h| h.display().to_string());
+    let mut written = Vec::new();
+    for (name, contents) in entries {
+        let contents = match &home {
+            Some(home) => contents.replace(home.as_str(), "~"),
+            None => contents,
+        };
+        zip.start_file(name, options).map_err(zip_error)?;
+        zip.write_all(contents.as_bytes())?;
+        written.push(name.to_string());
+    }
+    zip.finish().map_err(zip_error)?;
+
+    Ok(written)
+}
+
+/// The config as JSON with credentials and secret-looking custom settings removed.
+pub fn sanitized_config(config: &Config) -> Result<serde_json::Value> {
+    let mut value = serde_json::to_value(config)?;
+    if !value["sync_token"].is_null() {
+        value["sync_token"] = REDACTED.into();
+    }
+    if let Some(custom) = value["custom_settings"].as_object_mut() {
+        for (key, setting) in custom.iter_mut() {
+            if is_sensitive(key) {
+                *setting = REDACTED.into();
+            }
+        }
+    }
+    Ok(value)
+}
+
+fn versions(config: &Config) -> String {
+    let brew = crate::homebrew::resolve_brew(config.get("brew_prefix").as_deref())
+        .map(|brew| brew.to_string_lossy().to_string())
+        .unwrap_or_else(|| "brew".to_string());
+    let mut report = format!("kiwi: {}\n", env!("CARGO_PKG_VERSION"));
+    report.push_str(&format!("os: {} ({})\n", std::env::consts::OS, std::env::consts::ARCH));
+
+    let probes: [(&str, &str, &[&str]); 3] = [
+        ("brew", &brew, &["--version"]),
+        ("macos", "sw_vers", &[]),
+        ("uname", "uname", &["-a"]),
+    ];
+    for (label, program, args) in probes {
+        let version = Command::new(program)
+            .args(args)
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+            .unwrap_or_else(|| "unavailable".to_string());
+        report.push_str(&format!("{}: {}\n", label, version.replace('\n', "\n  ")));
+    }
+    report
+}
+
+fn is_sensitive(key: &str) -> bool {
+    let key = key.to_lowercase();
+    SENSITIVE_KEY_HINTS.iter().any(|hint| key.contains(hint))
+}
+
+/// Hides values that follow a secret-looking key or flag, such as
+/// `kiwi config sync_token <value>` or `--token=<value>`.
+fn redact_args(args: Vec<String>) -> Vec<String> {
+    let mut redacted = Vec::with_capacity(args.len());
+    let mut hide_next = false;
+    for arg in args {
+        if hide_next {
+            redacted.push(REDACTED.to_string());
+            hide_next = false;
+            continue;
+        }
+        match arg.split_once('=') {
+            Some((flag, _)) if is_sensitive(flag) => redacted.push(format!("{}={}", flag, REDACTED)),
+            _ => {
+                hide_next = is_sensitive(&arg);
+                redacted.push(arg);
+            }
+        }
+    }
+    redacted
+}
+
+fn kiwi_dir() -> Result<PathBuf> {
+    let home = dirs::home_dir().ok_or_else(|| {
+        KiwiError::Config("Could not find home directory".to_string())
+    })?;
+    Ok(home.join(".kiwi"))
+}
+
+fn zip_error(e: zip::result::ZipError) -> KiwiError {
+    KiwiError::Io(std::io::Error::other(e))
+}
@@ -0,0 +1,93 @@
+//! Text diffs between versions of a dotfile, for `kiwi diff`: unified,
+//! side by side, or handed to an external tool.
+
+use crate::{KiwiError, Result};
+use similar::{ChangeTag, TextDiff};
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// A unified diff from `old` to `new`, with `a/` and `b/` headers.
+pub fn unified(old_label: &str, new_label: &str, old: &str, new: &str) -> String {
+    TextDiff::from_lines(old, new)
+        .unified_diff()
+        .header(old_label, new_label)
+        .to_string()
+}
+
+/// `old` and `new` in two columns of `width` characters each, with a gutter
+/// marking changed (`|`), removed (`<`), and added (`>`) lines.
+pub fn side_by_side(old: &str, new: &str, width: usize) -> Vec<String> {
+    let diff = TextDiff::from_lines(old, new);
+    let mut rows = Vec::new();
+    let mut removed: Vec<String> = Vec::new();
+    let mut added: Vec<String> = Vec::new();
+
+    let flush = |rows: &mut Vec<String>, removed: &mut Vec<String>, added: &mut Vec<String>| {
+        for i in 0..removed.len().max(added.len()) {
+            let (left, right) = (removed.get(i), added.get(i));
+            let gutter = match (left, right) {
+                (Some(_), Some(_)) => '|',
+                (Some(_), None) => '<',
+                _ => '>',
+            };
+            rows.push(row(left.map_or("", String::as_str), gutter, right.map_or("", String::as_str), width));
+        }
+        removed.clear();
+        added.clear();
+    };
+
+    for change in diff.iter_all_changes() {
+        let line = change.value().trim_end_matches('\n').to_string();
+        match change.tag() {
+            ChangeTag::Delete => removed.push(line),
+            ChangeTag::Insert => added.push(line),
+            ChangeTag::Equal => {
+                flush(&mut rows, &mut removed, &mut added);
+                rows.push(row(&line, ' ', &line, width));
+            }
+        }
+    }
+    flush(&mut rows, &mut removed, &mut added);
+    rows
+}
+
+fn row(left: &str, gutter: char, right: &str, width: usize) -> String {
+    let left: String = left.chars().take(width).collect();
+    let right: String = right.chars().take(width).collect();
+    format!("{:<width$} {} {}", left, gutter, right, width = width).trim_end().to_string()
+}
+
+/// Opens `tool` (e.g. `opendiff` or `code --diff --wait`) on two versions,
+/// written to scratch files named after `name`.
+pub fn open_tool(tool: &str, name: &str, old: &str, new: &str) -> Result<()> {
+    let mut words = tool.split_whitespace();
+    let program = words.next().ok_or_else(|| KiwiError::ValidationError("--tool needs a command".to_string()))?;
+
+    let dir = std::env::temp_dir().join(format!("kiwi-diff-{}", std::process::id()));
+    fs::create_dir_all(&dir)?;
+    let file_name = Path::new(name).file_name().map_or_else(|| "file".into(), |n| n.to_string_lossy().to_string());
+    let (old_path, new_path) = (dir.join(format!("a-{}", file_name)), dir.join(format!("b-{}", file_name)));
+    fs::write(&old_path, old)?;
+    fs::write(&new_path, new)?;
+
+    let status = Command::new(program).args(words).arg(&old_path).arg(&new_path).status();
+    let _ = fs::remove_dir_all(&dir);
+    match status {
+        // diff(1) and friends exit 1 when the files differ
+        Ok(status) if status.code().is_some_and(|code| code <= 1) => Ok(()),
+        Ok(status) => Err(KiwiError::Dotfiles(format!("{} exited with {}", program, status))),
+        Err(e) => Err(KiwiError::Dotfiles(format!("Couldn't run {}: {}", program, e))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_side_by_side_pairs_changes() {
+        let rows = side_by_side("a\nb\nc\n", "a\nB\nc\nd\n", 4);
+        assert_eq!(rows, vec!["a      a", "b    | B", "c      c", "     > d"]);
+    }
+}
@@ -1,18 +1,310 @@
 use std::path::{Path, PathBuf};
 use std::fs;
-use crate::{Result, KiwiError};
+use std::os::unix::fs::MetadataExt;
+use crate::backups::Backups;
+use crate::blobs::BlobStore;
+use crate::hashing::HashCache;
+use crate::crypto::Key;
+use crate::history::{History, Version};
+use crate::homebrew::{matches_pattern, CommandRunner, SystemRunner};
+use crate::sensitive::{self, Sensitivity};
+use crate::{sudo, Result, KiwiError};
+use std::collections::{HashMap, HashSet};
+use std::sync::OnceLock;
 use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Top-level entries of the dotfiles dir that hold kiwi's own state, which
+/// no tracked file or remote entry may take.
+pub const RESERVED_NAMES: &[&str] = &[
+    "backups", "blobs", "brew.json", "dotfiles.json", "environments.json", "hash-cache.json",
+    crate::sync::LAST_SYNC_FILE, "packages.json", "taps.json",
+];
+
+/// Whether the dotfiles dir entry `name` would land on kiwi's own state.
+/// Compared without case, as the filesystem on a Mac usually does.
+pub fn is_reserved(name: &str) -> bool {
+    let top = name.split('/').next().unwrap_or_default();
+    RESERVED_NAMES.iter().any(|reserved| reserved.eq_ignore_ascii_case(top))
+}
+
+/// Refuses a dotfiles dir name outside the dir or on kiwi's own state.
+fn check_name(dotfile: &Dotfile) -> Result<()> {
+    let name = dotfile.name();
+    if name.is_empty() || !Path::new(&name).components().all(|c| matches!(c, std::path::Component::Normal(_))) {
+        return Err(KiwiError::Dotfiles(format!("{} can't be used as a name in the dotfiles dir", name)));
+    }
+    if is_reserved(&name) {
+        return Err(KiwiError::Dotfiles(format!("{} is reserved for kiwi's own state; pick another name with --alias", name)));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Dotfile {
+    /// Stored as `~/...` when it's in the home directory, so the index
+    /// works for any account name
     pub path: PathBuf,
     pub alias: Option<String>,
     pub synced: bool,
+    /// Oversized file synced as a name, hash, and size only
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pointer: bool,
+    /// How `path` and its entry in the dotfiles dir are tied together
+    #[serde(default, skip_serializing_if = "LinkMode::is_default")]
+    pub mode: LinkMode,
+    /// Manifests from before modes recorded templates as a flag
+    #[serde(default, rename = "template", skip_serializing)]
+    legacy_template: bool,
+    /// Hash of the contents as of the last push or pull
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    /// When the contents were last pushed or pulled, in Unix seconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_synced: Option<u64>,
+    /// When kiwi last wrote the file, from a pull or a template render
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_applied: Option<u64>,
+    /// Modification time of the source as of the last sync or apply
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_mtime: Option<u64>,
+    /// Owned by root or another user, so kiwi writes it through `sudo`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub sudo: bool,
+    /// Leave its extended attributes, quarantine included, out of sync
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub skip_xattrs: bool,
+    /// A directory tracked as a whole: its dotfiles dir entry links the
+    /// directory, and the files in it are tracked as members
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub directory: bool,
+    /// A file inside a tracked directory, named `<directory>/<path in it>`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub member: bool,
+    /// Environments the file belongs to; one tagged for none belongs to all
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub environments: Vec<String>,
+}
+
+/// Entries never tracked as part of a directory.
+const SKIPPED_IN_DIRECTORIES: &[&str] = &[".git", ".DS_Store"];
+
+impl Dotfile {
+    fn new(path: PathBuf, alias: Option<String>, mode: LinkMode) -> Self {
+        Self {
+            path,
+            alias,
+            synced: false,
+            pointer: false,
+            mode,
+            legacy_template: false,
+            hash: None,
+            last_synced: None,
+            last_applied: None,
+            source_mtime: None,
+            sudo: false,
+            skip_xattrs: false,
+            directory: false,
+            member: false,
+            environments: Vec::new(),
+        }
+    }
+
+    /// The entry name inside the dotfiles dir, and the key used when syncing.
+    pub fn name(&self) -> String {
+        match &self.alias {
+            Some(alias) => alias.clone(),
+            None => self.path.file_name().unwrap_or_default().to_string_lossy().to_string(),
+        }
+    }
+
+    /// The top-level entry in the dotfiles dir: its own, or its directory's
+    /// for a member.
+    pub fn entry_name(&self) -> String {
+        let name = self.name();
+        match name.split_once('/') {
+            Some((directory, _)) if self.member => directory.to_string(),
+            _ => name,
+        }
+    }
+
+    /// The dotfiles dir holds a template that is rendered to `path`.
+    pub fn is_template(&self) -> bool {
+        self.mode == LinkMode::Render
+    }
+
+    /// Whether the file is applied in `environment`. With no active
+    /// environment, every file is.
+    pub fn in_environment(&self, environment: Option<&str>) -> bool {
+        match environment {
+            Some(environment) if !self.environments.is_empty() => self.environments.iter().any(|e| e == environment),
+            _ => true,
+        }
+    }
+
+    /// How contents hashing to `current` compare with the last push or pull.
+    pub fn sync_state(&self, current: Option<&String>) -> SyncState {
+        match (&self.hash, current) {
+            (None, _) => SyncState::NeverSynced,
+            (Some(synced), Some(current)) if synced == current => SyncState::Synced,
+            _ => SyncState::Modified,
+        }
+    }
+}
+
+/// How a tracked file and its entry in the dotfiles dir are kept in step.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkMode {
+    /// The dotfiles dir holds a symlink to the file
+    #[default]
+    Symlink,
+    /// The dotfiles dir holds a copy, refreshed from whichever side is newer
+    Copy,
+    /// The file and its dotfiles dir entry share one inode
+    Hardlink,
+    /// The dotfiles dir holds a template that is rendered to the file
+    Render,
+    /// The file lives in the dotfiles dir, and its path is a symlink to it
+    Stow,
+    /// The dotfiles dir holds an age-encrypted copy, so only ciphertext is
+    /// ever pushed
+    Encrypt,
+}
+
+impl LinkMode {
+    fn is_default(&self) -> bool {
+        *self == LinkMode::default()
+    }
+}
+
+impl std::fmt::Display for LinkMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LinkMode::Symlink => write!(f, "symlink"),
+            LinkMode::Copy => write!(f, "copy"),
+            LinkMode::Hardlink => write!(f, "hardlink"),
+            LinkMode::Render => write!(f, "render"),
+            LinkMode::Stow => write!(f, "stow"),
+            LinkMode::Encrypt => write!(f, "encrypt"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncState {
+    /// Contents match what was last pushed or pulled
+    Synced,
+    /// Contents changed since the last push or pull
+    Modified,
+    /// Never pushed or pulled from this machine
+    NeverSynced,
+}
+
+impl std::fmt::Display for SyncState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SyncState::Synced => write!(f, "synced"),
+            SyncState::Modified => write!(f, "modified"),
+            SyncState::NeverSynced => write!(f, "never synced"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DriftStatus {
+    /// Source file exists and the dotfiles dir link points at it
+    Clean,
+    /// Source file no longer exists
+    Missing,
+    /// The link in the dotfiles dir is missing or points elsewhere
+    Unlinked,
+    /// A stowed file's path holds a file or link kiwi didn't put there
+    Foreign,
+}
+
+impl std::fmt::Display for DriftStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DriftStatus::Clean => write!(f, "clean"),
+            DriftStatus::Missing => write!(f, "missing"),
+            DriftStatus::Unlinked => write!(f, "unlinked"),
+            DriftStatus::Foreign => write!(f, "foreign"),
+        }
+    }
+}
+
+/// What [`Dotfiles::sync`] changed or found.
+#[derive(Debug, Default)]
+pub struct SyncSummary {
+    /// Links or copies in the dotfiles dir that were recreated
+    pub relinked: Vec<PathBuf>,
+    /// Source files overwritten with the copy in the dotfiles dir
+    pub copied: Vec<PathBuf>,
+    /// Files whose contents changed since their last push or pull
+    pub modified: Vec<PathBuf>,
+    /// Tracked files with no readable source
+    pub missing: Vec<PathBuf>,
+    /// Encrypted files left as they were, as there's no key here for them
+    pub locked: Vec<PathBuf>,
+}
+
+impl SyncSummary {
+    pub fn is_empty(&self) -> bool {
+        self.relinked.is_empty()
+            && self.copied.is_empty()
+            && self.modified.is_empty()
+            && self.missing.is_empty()
+            && self.locked.is_empty()
+    }
+}
+
+/// What [`Dotfiles::relink`] did with the stowed files.
+#[derive(Debug, Default)]
+pub struct RelinkSummary {
+    /// Paths that now link to their file in the dotfiles dir
+    pub linked: Vec<PathBuf>,
+    /// Paths holding something else, left alone
+    pub blocked: Vec<PathBuf>,
+    /// Files the dotfiles dir doesn't have, so there's nothing to link to
+    pub missing: Vec<PathBuf>,
+}
+
+/// What [`Dotfiles::apply_environment`] did with the files tagged for
+/// environments.
+#[derive(Debug, Default)]
+pub struct EnvironmentFiles {
+    /// Files of the environment that were put back in place
+    pub applied: Vec<PathBuf>,
+    /// Files of other environments that were put away in the dotfiles dir
+    pub parked: Vec<PathBuf>,
+}
+
+/// How [`Dotfiles::link_home`] left a stowed file.
+enum HomeLink {
+    Placed,
+    Linked,
+    Blocked,
+    Missing,
 }
 
 pub struct Dotfiles {
     dotfiles_dir: PathBuf,
     dotfiles_file: PathBuf,
+    /// Back files up before sync or render overwrites them
+    backup: bool,
+    /// What `~` stands for in the index
+    home: Option<PathBuf>,
+    /// Runs `sudo` for files tracked with it
+    runner: Box<dyn CommandRunner>,
+    /// Files tagged for other environments are left out of sync and render
+    environment: Option<String>,
+    /// Keeps every version of the files kiwi sees
+    history: Option<History>,
+    /// Encrypts and decrypts encrypted files, once found
+    key: OnceLock<Option<Key>>,
+    /// Finds the key the first time an encrypted file needs it
+    lookup_key: Option<Box<dyn Fn() -> Option<Key> + Send + Sync>>,
 }
 
 impl Dotfiles {
@@ -20,52 +312,379 @@ impl Dotfiles {
         Self {
             dotfiles_dir,
             dotfiles_file,
+            backup: false,
+            home: dirs::home_dir().map(|home| home.canonicalize().unwrap_or(home)),
+            runner: Box::new(SystemRunner),
+            environment: None,
+            history: None,
+            key: OnceLock::new(),
+            lookup_key: None,
         }
     }
 
-    pub fn add(&self, path: &Path, alias: Option<String>) -> Result<()> {
-        let path = path.canonicalize()?;
+    pub fn with_runner(mut self, runner: impl CommandRunner + 'static) -> Self {
+        self.runner = Box::new(runner);
+        self
+    }
+
+    /// Resolves `~` in the index against `home` instead of this user's home.
+    pub fn with_home(mut self, home: PathBuf) -> Self {
+        self.home = Some(home);
+        self
+    }
+
+    /// Copies files to the managed backups area before they're overwritten.
+    pub fn with_backups(mut self, enabled: bool) -> Self {
+        self.backup = enabled;
+        self
+    }
+
+    /// Applies only the files tagged for `environment`, and those tagged for none.
+    pub fn with_environment(mut self, environment: Option<String>) -> Self {
+        self.environment = environment;
+        self
+    }
+
+    /// Records each new version of a tracked file in the history at `dir`.
+    pub fn with_history(mut self, dir: PathBuf) -> Self {
+        self.history = Some(History::new(dir));
+        self
+    }
+
+    /// Encrypts and decrypts encrypted files with `key`.
+    pub fn with_key(self, key: Key) -> Self {
+        let _ = self.key.set(Some(key));
+        self
+    }
+
+    /// Looks the key for encrypted files up with `lookup` when one is first
+    /// needed, so commands that never touch them never ask for it.
+    pub fn with_key_lookup(mut self, lookup: impl Fn() -> Option<Key> + Send + Sync + 'static) -> Self {
+        self.lookup_key = Some(Box::new(lookup));
+        self
+    }
+
+    fn key(&self) -> Result<&Key> {
+        self.key
+            .get_or_init(|| self.lookup_key.as_ref().and_then(|lookup| lookup()))
+            .as_ref()
+            .ok_or_else(|| {
+                KiwiError::Dotfiles(
+                    "No key for encrypted dotfiles here; set encryption_recipient, or add a file with --encrypt to store a passphrase".to_string(),
+                )
+            })
+    }
+
+    fn applies(&self, dotfile: &Dotfile) -> bool {
+        dotfile.in_environment(self.environment.as_deref())
+    }
+
+    fn backups(&self) -> Option<Backups> {
+        self.backup.then(|| Backups::new(&self.dotfiles_dir))
+    }
+
+    pub fn add(&self, path: &Path, alias: Option<String>, pointer: bool) -> Result<()> {
+        self.add_with_mode(path, alias, pointer, LinkMode::Symlink)
+    }
+
+    /// Starts tracking `path`, placing it in the dotfiles dir as `mode` says.
+    pub fn add_with_mode(&self, path: &Path, alias: Option<String>, pointer: bool, mode: LinkMode) -> Result<()> {
+        if let Some(Sensitivity::Deny(reason)) = sensitive::check(&path.canonicalize()?) {
+            return Err(KiwiError::Dotfiles(format!("Refusing to track {}: it holds {}", path.display(), reason)));
+        }
+        if mode == LinkMode::Render {
+            return self.add_template(path, alias);
+        }
+        let path = self.resolve(path)?;
         
         if !path.exists() {
             return Err(KiwiError::Dotfiles(format!("File does not exist: {}", path.display())));
         }
+        check_name(&Dotfile::new(path.clone(), alias.clone(), mode))?;
 
         let mut dotfiles = self.load_dotfiles()?;
         
         if dotfiles.iter().any(|d| d.path == path) {
             return Err(KiwiError::Dotfiles(format!("File already tracked: {}", path.display())));
         }
+        if let Some(directory) = dotfiles.iter().find(|d| d.directory && path.starts_with(&d.path)) {
+            return Err(KiwiError::Dotfiles(format!(
+                "{} is already tracked as part of {}",
+                path.display(),
+                directory.path.display()
+            )));
+        }
+        if path.is_dir() {
+            return self.add_directory(path, alias, pointer, mode, dotfiles);
+        }
 
-        let dotfile = Dotfile {
-            path: path.clone(),
-            alias: alias.clone(),
-            synced: false,
-        };
+        let dotfile = Dotfile { pointer, ..Dotfile::new(path.clone(), alias.clone(), mode) };
 
-        let target = self.dotfiles_dir.join(alias.unwrap_or_else(|| path.file_name().unwrap().to_string_lossy().to_string()));
+        let target = self.target_for(&dotfile);
         
         if let Some(parent) = target.parent() {
             fs::create_dir_all(parent)?;
         }
 
-        if target.exists() {
-            fs::remove_file(&target)?;
+        self.clear_target(&dotfile, &target)?;
+        self.place(&dotfile, &target)?;
+        if let Some(history) = &self.history {
+            history.record(&dotfile.path, &fs::read(self.source_for(&dotfile))?)?;
         }
 
-        std::os::unix::fs::symlink(&path, &target)?;
+        dotfiles.push(dotfile);
+        self.save_dotfiles(&dotfiles)?;
+
+        Ok(())
+    }
+
+    /// Tracks `path` as a template: its current contents become the
+    /// template in the dotfiles dir, and `path` is rewritten from it on render.
+    pub fn add_template(&self, path: &Path, alias: Option<String>) -> Result<()> {
+        let path = path.canonicalize()?;
+        let mut dotfiles = self.load_dotfiles()?;
+        if dotfiles.iter().any(|d| d.path == path) {
+            return Err(KiwiError::Dotfiles(format!("File already tracked: {}", path.display())));
+        }
+
+        let dotfile = Dotfile::new(path.clone(), alias, LinkMode::Render);
+        check_name(&dotfile)?;
+
+        let source = self.source_for(&dotfile);
+        if let Some(parent) = source.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        self.clear_target(&dotfile, &source)?;
+        fs::copy(&path, &source)?;
 
         dotfiles.push(dotfile);
         self.save_dotfiles(&dotfiles)?;
+        Ok(())
+    }
+
+    /// Tracks the directory at `path` as a whole: its dotfiles dir entry is
+    /// a link to the directory, and every file in it is synced as a member.
+    /// Files added to the directory later are picked up as they appear.
+    fn add_directory(&self, path: PathBuf, alias: Option<String>, pointer: bool, mode: LinkMode, mut dotfiles: Vec<Dotfile>) -> Result<()> {
+        if mode != LinkMode::Symlink || pointer {
+            return Err(KiwiError::Dotfiles(format!(
+                "{} is a directory; directories can only be tracked with the default mode",
+                path.display()
+            )));
+        }
+        let dotfiles_dir = self.dotfiles_dir.canonicalize().unwrap_or_else(|_| self.dotfiles_dir.clone());
+        if dotfiles_dir.starts_with(&path) || path.starts_with(&dotfiles_dir) {
+            return Err(KiwiError::Dotfiles(format!("Can't track {}: it overlaps the dotfiles dir", path.display())));
+        }
+        if let Some(inside) = dotfiles.iter().find(|d| d.path.starts_with(&path)) {
+            return Err(KiwiError::Dotfiles(format!(
+                "{} is already tracked; remove it before tracking {} as a whole",
+                inside.path.display(),
+                path.display()
+            )));
+        }
 
+        let root = Dotfile { directory: true, ..Dotfile::new(path, alias, mode) };
+        let target = self.target_for(&root);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        self.clear_target(&root, &target)?;
+        self.place(&root, &target)?;
+
+        dotfiles.push(root);
+        self.refresh_members(&mut dotfiles);
+        self.save_dotfiles(&dotfiles)
+    }
+
+    /// Brings the members of each tracked directory in line with the files
+    /// in it, keeping the sync state of those still there. Directories that
+    /// are missing keep their members, since a pull may be about to fill
+    /// them. Returns whether anything changed.
+    fn refresh_members(&self, dotfiles: &mut Vec<Dotfile>) -> bool {
+        let roots: Vec<Dotfile> = dotfiles.iter().filter(|d| d.directory && d.path.is_dir()).cloned().collect();
+        let mut changed = false;
+        for root in roots {
+            let prefix = format!("{}/", root.name());
+            let files = directory_files(&root.path);
+            let names: HashSet<String> = files.iter().map(|relative| format!("{}{}", prefix, relative.display())).collect();
+
+            let before = dotfiles.len();
+            dotfiles.retain(|d| !d.member || !d.name().starts_with(&prefix) || names.contains(&d.name()));
+            changed |= dotfiles.len() != before;
+
+            let known: HashSet<String> = dotfiles.iter().filter(|d| d.member).map(Dotfile::name).collect();
+            for relative in files {
+                let name = format!("{}{}", prefix, relative.display());
+                if known.contains(&name) {
+                    continue;
+                }
+                let member = Dotfile::new(root.path.join(&relative), Some(name), root.mode);
+                dotfiles.push(Dotfile { member: true, skip_xattrs: root.skip_xattrs, ..member });
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// `path` made absolute the way the index records it: resolved through
+    /// links, except a stowed file's own link into the dotfiles dir, since
+    /// that link is the path being tracked.
+    fn resolve(&self, path: &Path) -> Result<PathBuf> {
+        let stowed = fs::read_link(path).is_ok_and(|link| {
+            link.starts_with(&self.dotfiles_dir)
+                || self.dotfiles_dir.canonicalize().is_ok_and(|dir| link.starts_with(dir))
+        });
+        match (stowed, path.parent(), path.file_name()) {
+            (true, Some(parent), Some(name)) => {
+                let parent = if parent.as_os_str().is_empty() { Path::new(".") } else { parent };
+                Ok(parent.canonicalize()?.join(name))
+            }
+            _ => Ok(path.canonicalize()?),
+        }
+    }
+
+    /// Whether `path` belongs to root or another user, so it can only be
+    /// tracked to be written through `sudo`.
+    pub fn is_foreign(&self, path: &Path) -> bool {
+        sudo::is_foreign(path, &self.dotfiles_dir)
+    }
+
+    /// Marks the tracked file at `path` as written through `sudo`, or not.
+    pub fn set_sudo(&self, path: &Path, enabled: bool) -> Result<()> {
+        self.change(path, |dotfile| dotfile.sudo = enabled)
+    }
+
+    /// Tags the tracked file at `path` with the environments it belongs to;
+    /// none makes it belong to all of them again.
+    pub fn set_environments(&self, path: &Path, environments: &[String]) -> Result<()> {
+        let path = self.resolve(path)?;
+        let reason = self.load_dotfiles()?.into_iter().find(|d| d.path == path).and_then(|dotfile| {
+            if dotfile.directory || dotfile.member {
+                Some("a tracked directory")
+            } else if dotfile.sudo {
+                Some("written through sudo")
+            } else {
+                None
+            }
+        });
+        if let Some(reason) = reason {
+            return Err(KiwiError::Dotfiles(format!("{} is {}, so it can't belong to an environment", path.display(), reason)));
+        }
+        self.change(&path, |dotfile| dotfile.environments = environments.to_vec())
+    }
+
+    /// Leaves the tracked file at `path`'s extended attributes out of sync, or not.
+    pub fn set_skip_xattrs(&self, path: &Path, skipped: bool) -> Result<()> {
+        self.change(path, |dotfile| dotfile.skip_xattrs = skipped)
+    }
+
+    /// Applies `change` to the tracked file at `path` and saves it.
+    fn change(&self, path: &Path, mut change: impl FnMut(&mut Dotfile)) -> Result<()> {
+        let path = self.resolve(path)?;
+        let mut found = false;
+        self.update(|dotfile| {
+            // A directory's setting holds for the files in it too
+            let matches = dotfile.path == path || (dotfile.member && dotfile.path.starts_with(&path));
+            found |= matches;
+            if matches {
+                change(dotfile);
+            }
+            matches
+        })?;
+        if !found {
+            return Err(KiwiError::Dotfiles(format!("File not tracked: {}", path.display())));
+        }
         Ok(())
     }
 
-    pub fn remove(&self, path: &Path) -> Result<()> {
+    /// Writes `contents` to `dotfile`'s file, through `sudo` when it was
+    /// tracked that way. Files kiwi isn't allowed to write otherwise are a
+    /// [`KiwiError::PermissionDenied`].
+    pub fn write_file(&self, dotfile: &Dotfile, contents: &[u8]) -> Result<()> {
+        // What's about to be replaced may never have been seen. Rendered
+        // output and decrypted contents aren't kept; their sources are
+        if let Some(history) = self.history.as_ref().filter(|_| self.source_for(dotfile) == dotfile.path) {
+            if let Ok(current) = fs::read(&dotfile.path) {
+                history.record(&dotfile.path, &current)?;
+            }
+        }
+        if dotfile.sudo {
+            return sudo::write(self.runner.as_ref(), &self.dotfiles_dir, &dotfile.path, contents);
+        }
+        fs::write(&dotfile.path, contents).map_err(|e| match e.kind() {
+            std::io::ErrorKind::PermissionDenied => KiwiError::PermissionDenied { path: dotfile.path.clone() },
+            _ => e.into(),
+        })
+    }
+
+    /// The entry already in the dotfiles dir where tracking `path` as
+    /// `alias` would go, when it holds something other than `path`'s
+    /// contents and adding would replace it.
+    pub fn occupied(&self, path: &Path, alias: Option<&str>) -> Result<Option<PathBuf>> {
         let path = path.canonicalize()?;
+        let name = match alias {
+            Some(alias) => alias.to_string(),
+            None => path.file_name().unwrap_or_default().to_string_lossy().to_string(),
+        };
+        let target = self.dotfiles_dir.join(name);
+        // Dangling links hold nothing to lose
+        if fs::metadata(&target).is_err() || fs::read_link(&target).is_ok_and(|link| link == path) {
+            return Ok(None);
+        }
+        if path.is_dir() {
+            return Ok(Some(target));
+        }
+        Ok((fs::read(&target).ok() != Some(fs::read(&path)?)).then_some(target))
+    }
+
+    /// Clears `target` for `dotfile`'s new entry, first keeping a copy in
+    /// the backups area of anything there that differs from the file.
+    fn clear_target(&self, dotfile: &Dotfile, target: &Path) -> Result<()> {
+        let Ok(metadata) = target.symlink_metadata() else {
+            return Ok(());
+        };
+        let backups = Backups::new(&self.dotfiles_dir);
+        if metadata.is_dir() {
+            backups.save_dir(&dotfile.name(), target)?;
+            return Ok(());
+        }
+        if !dotfile.directory {
+            backups.save(&dotfile.name(), target, &fs::read(&dotfile.path)?)?;
+        }
+        fs::remove_file(target)?;
+        Ok(())
+    }
+
+    pub fn remove(&self, path: &Path) -> Result<()> {
+        let path = self.resolve(path)?;
         let mut dotfiles = self.load_dotfiles()?;
 
         if let Some(index) = dotfiles.iter().position(|d| d.path == path) {
             let dotfile = &dotfiles[index];
+            if dotfile.mode == LinkMode::Stow {
+                self.unstow(dotfile)?;
+                dotfiles.remove(index);
+                return self.save_dotfiles(&dotfiles);
+            }
+            if dotfile.member {
+                return Err(KiwiError::Dotfiles(format!(
+                    "{} is part of a tracked directory; remove {} to stop tracking it",
+                    path.display(),
+                    dotfile.entry_name()
+                )));
+            }
+            if dotfile.directory {
+                let target = self.target_for(dotfile);
+                match target.symlink_metadata() {
+                    Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(&target)?,
+                    Ok(_) => fs::remove_file(&target)?,
+                    Err(_) => {}
+                }
+                let prefix = format!("{}/", dotfile.name());
+                dotfiles.retain(|d| d.path != path && !(d.member && d.name().starts_with(&prefix)));
+                return self.save_dotfiles(&dotfiles);
+            }
             
             if let Some(alias) = &dotfile.alias {
                 let target = self.dotfiles_dir.join(alias);
@@ -88,19 +707,591 @@ impl Dotfiles {
         Ok(())
     }
 
+    /// Tracked files, including the files in tracked directories but not
+    /// the directories themselves.
     pub fn list(&self) -> Result<Vec<Dotfile>> {
-        Ok(self.load_dotfiles()?)
+        Ok(self.load_dotfiles()?.into_iter().filter(|d| !d.directory).collect())
     }
 
-    pub fn sync(&self, _prefer_local: bool) -> Result<()> {
-        let dotfiles = self.load_dotfiles()?;
-        
-        for dotfile in dotfiles {
-            if !dotfile.synced {
+    /// The tracked dotfile at path `item`, or with `item` as its name in
+    /// the dotfiles dir.
+    pub fn find(&self, item: &str) -> Result<Option<Dotfile>> {
+        let canonical = self.resolve(Path::new(item)).ok();
+        Ok(self
+            .load_dotfiles()?
+            .into_iter()
+            .find(|d| Some(&d.path) == canonical.as_ref() || d.name() == item))
+    }
+
+    pub fn drift(&self, dotfile: &Dotfile) -> DriftStatus {
+        // Another environment's files are meant to be away
+        if !self.applies(dotfile) {
+            return DriftStatus::Clean;
+        }
+        if dotfile.mode == LinkMode::Stow {
+            return if !self.target_for(dotfile).is_file() {
+                DriftStatus::Missing
+            } else if self.is_placed(dotfile) {
+                DriftStatus::Clean
+            } else if dotfile.path.symlink_metadata().is_err() {
+                DriftStatus::Unlinked
+            } else {
+                DriftStatus::Foreign
+            };
+        }
+        if !dotfile.path.exists() {
+            return DriftStatus::Missing;
+        }
+        let placed = match dotfile.mode {
+            // Rendered files are regular files; only the template must exist
+            LinkMode::Render => self.source_for(dotfile).is_file(),
+            // A stale copy is refreshed on sync, so only its absence counts
+            LinkMode::Copy | LinkMode::Encrypt => self.target_for(dotfile).is_file(),
+            LinkMode::Symlink | LinkMode::Hardlink | LinkMode::Stow => self.is_placed(dotfile),
+        };
+        if placed {
+            DriftStatus::Clean
+        } else {
+            DriftStatus::Unlinked
+        }
+    }
+
+    /// Whether the dotfiles dir entry is the link or copy `mode` calls for.
+    fn is_placed(&self, dotfile: &Dotfile) -> bool {
+        // Members are in place when their directory's link is
+        if dotfile.member {
+            let entry = self.dotfiles_dir.join(dotfile.entry_name());
+            return fs::read_link(&entry).is_ok_and(|link| dotfile.path.starts_with(link));
+        }
+        let target = self.target_for(dotfile);
+        match dotfile.mode {
+            LinkMode::Symlink => fs::read_link(&target).is_ok_and(|link| link == dotfile.path),
+            LinkMode::Hardlink => match (fs::symlink_metadata(&target), fs::metadata(&dotfile.path)) {
+                (Ok(entry), Ok(source)) => entry.is_file() && entry.dev() == source.dev() && entry.ino() == source.ino(),
+                _ => false,
+            },
+            LinkMode::Copy => {
+                target.symlink_metadata().is_ok_and(|m| m.is_file())
+                    && fs::read(&target).ok().is_some_and(|entry| fs::read(&dotfile.path).ok() == Some(entry))
+            }
+            LinkMode::Render => true,
+            LinkMode::Stow => target.is_file() && fs::read_link(&dotfile.path).is_ok_and(|link| link == target),
+            LinkMode::Encrypt => match (self.key(), fs::read(&target)) {
+                (Ok(key), Ok(entry)) => key.decrypt(&entry).ok().is_some_and(|plain| fs::read(&dotfile.path).ok() == Some(plain)),
+                _ => false,
+            },
+        }
+    }
+
+    /// Creates the dotfiles dir entry for a non-template dotfile.
+    fn place(&self, dotfile: &Dotfile, target: &Path) -> Result<()> {
+        match dotfile.mode {
+            LinkMode::Symlink => std::os::unix::fs::symlink(&dotfile.path, target)?,
+            LinkMode::Copy => {
+                fs::copy(&dotfile.path, target)?;
+            }
+            LinkMode::Hardlink => fs::hard_link(&dotfile.path, target).map_err(|e| {
+                KiwiError::Dotfiles(format!(
+                    "Can't hard link {}: {} (hard links need the file and the dotfiles dir on one volume)",
+                    dotfile.path.display(),
+                    e
+                ))
+            })?,
+            LinkMode::Render => {}
+            LinkMode::Stow => {
+                move_file(&dotfile.path, target)?;
+                std::os::unix::fs::symlink(target, &dotfile.path)?;
+            }
+            LinkMode::Encrypt => fs::write(target, self.key()?.encrypt(&fs::read(&dotfile.path)?)?)?,
+        }
+        Ok(())
+    }
+
+    /// Links each stowed file's path to its file in the dotfiles dir, as on
+    /// a Mac the dotfiles dir was just cloned or restored to. A path that
+    /// holds a different file or a link elsewhere is left alone unless
+    /// `force`, which moves a file there to the backups area first. A file
+    /// at a path whose copy in the dotfiles dir is gone is stowed again.
+    pub fn relink(&self, force: bool) -> Result<RelinkSummary> {
+        let mut summary = RelinkSummary::default();
+        for dotfile in self.load_dotfiles()?.iter().filter(|d| d.mode == LinkMode::Stow) {
+            match self.link_home(dotfile, force)? {
+                HomeLink::Placed => {}
+                HomeLink::Linked => summary.linked.push(dotfile.path.clone()),
+                HomeLink::Blocked => summary.blocked.push(dotfile.path.clone()),
+                HomeLink::Missing => summary.missing.push(dotfile.path.clone()),
+            }
+        }
+        Ok(summary)
+    }
+
+    fn link_home(&self, dotfile: &Dotfile, force: bool) -> Result<HomeLink> {
+        if self.is_placed(dotfile) {
+            return Ok(HomeLink::Placed);
+        }
+        let target = self.target_for(dotfile);
+        let existing = dotfile.path.symlink_metadata().ok();
+        if !target.is_file() {
+            if !existing.is_some_and(|m| m.is_file()) {
+                return Ok(HomeLink::Missing);
+            }
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            self.place(dotfile, &target)?;
+            return Ok(HomeLink::Linked);
+        }
+
+        if let Some(existing) = existing {
+            let contents = fs::read(&target)?;
+            let same = existing.is_file() && fs::read(&dotfile.path).ok() == Some(contents.clone());
+            if existing.is_dir() || !(same || force) {
+                return Ok(HomeLink::Blocked);
+            }
+            Backups::new(&self.dotfiles_dir).save(&dotfile.name(), &dotfile.path, &contents)?;
+            fs::remove_file(&dotfile.path)?;
+        } else if let Some(parent) = dotfile.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        std::os::unix::fs::symlink(&target, &dotfile.path)?;
+        Ok(HomeLink::Linked)
+    }
+
+    /// Moves a stowed file back from the dotfiles dir to its path, in place
+    /// of kiwi's link.
+    fn unstow(&self, dotfile: &Dotfile) -> Result<()> {
+        let target = self.target_for(dotfile);
+        let ours = fs::read_link(&dotfile.path).is_ok_and(|link| link == target);
+        if !ours && dotfile.path.symlink_metadata().is_ok() {
+            return Err(KiwiError::Dotfiles(format!(
+                "{} isn't kiwi's link anymore; move it aside to get the file back from {}",
+                dotfile.path.display(),
+                target.display()
+            )));
+        }
+        if ours {
+            fs::remove_file(&dotfile.path)?;
+        }
+        if target.is_file() {
+            move_file(&target, &dotfile.path)?;
+        }
+        Ok(())
+    }
+
+    /// Puts away the files tagged for environments other than this one, and
+    /// puts back this environment's. A put-away file's contents wait in its
+    /// dotfiles dir entry, which sync keeps in step with pushes and pulls.
+    /// Templates are left to [`Self::render_all`].
+    pub fn apply_environment(&self) -> Result<EnvironmentFiles> {
+        let mut files = EnvironmentFiles::default();
+        for dotfile in self.load_dotfiles()?.iter().filter(|d| !self.applies(d)) {
+            if self.park(dotfile)? {
+                files.parked.push(dotfile.path.clone());
+            }
+        }
+        let summary = self.sync_where(false, |dotfile| !dotfile.environments.is_empty())?;
+        files.applied = summary.copied;
+        for path in summary.relinked {
+            if !files.applied.contains(&path) {
+                files.applied.push(path);
+            }
+        }
+        Ok(files)
+    }
+
+    /// Whether `dotfile` was put away by [`Self::park`]: its path is empty
+    /// and its dotfiles dir entry is a plain file instead of a link.
+    fn is_parked(&self, dotfile: &Dotfile) -> bool {
+        matches!(dotfile.mode, LinkMode::Symlink | LinkMode::Copy | LinkMode::Hardlink)
+            && dotfile.path.symlink_metadata().is_err()
+            && self.target_for(dotfile).symlink_metadata().is_ok_and(|m| m.is_file())
+    }
+
+    /// Removes `dotfile` from its path, keeping its contents in the dotfiles
+    /// dir. Returns whether there was anything to put away.
+    fn park(&self, dotfile: &Dotfile) -> Result<bool> {
+        let Ok(metadata) = dotfile.path.symlink_metadata() else {
+            return Ok(false);
+        };
+        let target = self.target_for(dotfile);
+        match dotfile.mode {
+            LinkMode::Stow | LinkMode::Encrypt if !self.is_placed(dotfile) => return Ok(false),
+            LinkMode::Stow | LinkMode::Encrypt | LinkMode::Render => {}
+            LinkMode::Symlink | LinkMode::Copy | LinkMode::Hardlink => {
+                if !metadata.is_file() {
+                    return Ok(false);
+                }
+                let contents = fs::read(&dotfile.path)?;
+                if target.symlink_metadata().is_ok() {
+                    fs::remove_file(&target)?;
+                } else if let Some(parent) = target.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::write(&target, contents)?;
+            }
+        }
+        fs::remove_file(&dotfile.path)?;
+        Ok(true)
+    }
+
+    /// The recorded versions of the tracked file at `path`, oldest first.
+    pub fn versions(&self, path: &Path) -> Result<(Dotfile, Vec<Version>)> {
+        let dotfile = self.tracked(path)?;
+        let history = self.history()?;
+        // The file as it is now counts, even if nothing has looked at it since it changed
+        if let Ok(current) = fs::read(self.source_for(&dotfile)) {
+            history.record(&dotfile.path, &current)?;
+        }
+        let versions = history.versions(&dotfile.path)?;
+        Ok((dotfile, versions))
+    }
+
+    /// Contents of the tracked file at `path` as of `version`, a number from
+    /// [`Self::versions`] or the start of its hash.
+    pub fn version_contents(&self, path: &Path, version: &str) -> Result<(Dotfile, Version, Vec<u8>)> {
+        let dotfile = self.tracked(path)?;
+        let history = self.history()?;
+        let version = history.find(&dotfile.path, version)?;
+        let contents = history.contents(&version)?;
+        Ok((dotfile, version, contents))
+    }
+
+    /// Puts back `contents` from [`Self::version_contents`], recording them
+    /// as the newest version. A templated dotfile's template is what's put
+    /// back; it still needs rendering.
+    pub fn rollback(&self, path: &Path, contents: &[u8]) -> Result<Dotfile> {
+        let dotfile = self.tracked(path)?;
+        let source = self.source_for(&dotfile);
+        if source == dotfile.path {
+            self.write_file(&dotfile, contents)?;
+        } else {
+            if let Ok(current) = fs::read(&source) {
+                self.history()?.record(&dotfile.path, &current)?;
+            }
+            fs::write(&source, contents)?;
+        }
+        self.history()?.record(&dotfile.path, contents)?;
+        Ok(dotfile)
+    }
+
+    fn tracked(&self, path: &Path) -> Result<Dotfile> {
+        self.find(&path.to_string_lossy())?
+            .filter(|dotfile| !dotfile.directory)
+            .ok_or_else(|| KiwiError::Dotfiles(format!("File not tracked: {}", path.display())))
+    }
+
+    fn history(&self) -> Result<&History> {
+        self.history.as_ref().ok_or_else(|| KiwiError::Dotfiles("Version history is off".to_string()))
+    }
+
+    /// Content hashes of the given dotfiles' sources (see [`Self::source_for`]),
+    /// hashed in parallel and answered from the cache for files whose size and
+    /// mtime haven't changed. Missing or unreadable files are left out.
+    pub fn hashes(&self, dotfiles: &[Dotfile]) -> Result<HashMap<PathBuf, String>> {
+        let paths: Vec<PathBuf> = dotfiles.iter().map(|d| self.source_for(d)).collect();
+        let mut cache = HashCache::load(self.dotfiles_dir.join("hash-cache.json"));
+        let hashes = cache.hash_all(&paths);
+        cache.save()?;
+        Ok(hashes)
+    }
+
+    /// Which of the given dotfiles still match their last push or pull.
+    pub fn pushed(&self, dotfiles: &[Dotfile]) -> Result<HashSet<PathBuf>> {
+        let hashes = self.hashes(dotfiles)?;
+        Ok(dotfiles
+            .iter()
+            .filter(|d| d.sync_state(hashes.get(&self.source_for(d))) == SyncState::Synced)
+            .map(|d| d.path.clone())
+            .collect())
+    }
+
+    /// The contents as of the last push or pull, if the blob store has them.
+    pub fn synced_contents(&self, dotfile: &Dotfile) -> Result<Option<Vec<u8>>> {
+        let store = BlobStore::new(self.dotfiles_dir.join("blobs"));
+        match &dotfile.hash {
+            Some(hash) if store.contains(hash) => Ok(Some(store.get(hash)?)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Records that the dotfiles named in `hashes` now match the remote,
+    /// after a push or pull. With `applied`, kiwi also just wrote them.
+    /// Names that aren't tracked here are ignored.
+    pub fn record_synced(&self, hashes: &HashMap<String, String>, applied: bool) -> Result<()> {
+        let now = now();
+        self.update(|dotfile| {
+            let Some(hash) = hashes.get(&dotfile.name()) else {
+                return false;
+            };
+            dotfile.hash = Some(hash.clone());
+            dotfile.synced = true;
+            dotfile.last_synced = Some(now);
+            if applied {
+                dotfile.last_applied = Some(now);
+            }
+            true
+        })
+    }
+
+    /// The file whose contents are synced: the template for templated
+    /// dotfiles, the file in the dotfiles dir for stowed and encrypted ones
+    /// and ones put away for another environment, the tracked file itself
+    /// otherwise.
+    pub fn source_for(&self, dotfile: &Dotfile) -> PathBuf {
+        if matches!(dotfile.mode, LinkMode::Render | LinkMode::Stow | LinkMode::Encrypt) || self.is_parked(dotfile) {
+            self.target_for(dotfile)
+        } else {
+            dotfile.path.clone()
+        }
+    }
+
+    /// Renders every templated dotfile to its path and returns the files
+    /// that changed. All templates are rendered before any file is written,
+    /// so a broken template leaves everything untouched.
+    pub fn render_all(&self, vars: &HashMap<String, String>) -> Result<Vec<PathBuf>> {
+        self.render_where(vars, |_| true)
+    }
+
+    /// Like [`Self::render_all`], limited to the templated dotfiles at `paths`.
+    pub fn render_paths(&self, paths: &[PathBuf], vars: &HashMap<String, String>) -> Result<Vec<PathBuf>> {
+        self.render_where(vars, |dotfile| paths.contains(&dotfile.path))
+    }
+
+    fn render_where(&self, vars: &HashMap<String, String>, include: impl Fn(&Dotfile) -> bool) -> Result<Vec<PathBuf>> {
+        let mut pending = Vec::new();
+        for dotfile in self.load_dotfiles()?.into_iter().filter(|d| d.is_template() && self.applies(d) && include(d)) {
+            let template = fs::read_to_string(self.source_for(&dotfile))?;
+            let rendered = crate::template::render(&template, vars).map_err(|e| match e {
+                KiwiError::Template(message) => KiwiError::Template(format!("{}: {}", dotfile.name(), message)),
+                other => other,
+            })?;
+            if fs::read_to_string(&dotfile.path).ok().as_deref() != Some(rendered.as_str()) {
+                pending.push((dotfile, rendered));
+            }
+        }
+
+        let backups = self.backups();
+        for (dotfile, rendered) in &pending {
+            if let Some(backups) = &backups {
+                backups.save(&dotfile.name(), &dotfile.path, rendered.as_bytes())?;
+            }
+            self.write_file(dotfile, rendered.as_bytes())?;
+        }
+        let written: Vec<PathBuf> = pending.into_iter().map(|(dotfile, _)| dotfile.path).collect();
+        if !written.is_empty() {
+            let now = now();
+            self.update(|dotfile| {
+                let applied = written.contains(&dotfile.path);
+                if applied {
+                    dotfile.last_applied = Some(now);
+                }
+                applied
+            })?;
+        }
+        Ok(written)
+    }
+
+    /// Applies `change` to every tracked dotfile and saves the list if it
+    /// reported a change for any. Changed entries get their source mtime refreshed.
+    fn update(&self, mut change: impl FnMut(&mut Dotfile) -> bool) -> Result<()> {
+        let mut dotfiles = self.load_dotfiles()?;
+        let mut changed = false;
+        for dotfile in &mut dotfiles {
+            if change(dotfile) {
+                dotfile.source_mtime = mtime(&self.source_for(dotfile));
+                changed = true;
+            }
+        }
+        if changed {
+            self.save_dotfiles(&dotfiles)?;
+        }
+        Ok(())
+    }
+
+    fn target_for(&self, dotfile: &Dotfile) -> PathBuf {
+        self.dotfiles_dir.join(dotfile.name())
+    }
+
+    /// Brings the dotfiles dir in line with the tracked files: recreates
+    /// missing or stale links, and where a pull left a plain file in place of
+    /// a link, copies it over the source first unless `prefer_local`. Then
+    /// refreshes hashes and each file's `synced` flag against its last sync.
+    pub fn sync(&self, prefer_local: bool) -> Result<SyncSummary> {
+        self.sync_where(prefer_local, |_| true)
+    }
+
+    /// Like [`Self::sync`], limited to the dotfiles with the given names.
+    pub fn sync_named(&self, prefer_local: bool, names: &[String]) -> Result<SyncSummary> {
+        self.sync_where(prefer_local, |dotfile| names.contains(&dotfile.name()))
+    }
+
+    fn sync_where(&self, prefer_local: bool, include: impl Fn(&Dotfile) -> bool) -> Result<SyncSummary> {
+        let mut dotfiles = self.load_dotfiles()?;
+        let mut summary = SyncSummary::default();
+        let backups = self.backups();
+
+        // Directories are relinked whatever's included, so their members can be
+        for dotfile in dotfiles.iter().filter(|d| !d.is_template() && !d.member && (d.directory || include(d) && self.applies(d))) {
+            if self.is_placed(dotfile) {
+                continue;
+            }
+            if dotfile.mode == LinkMode::Encrypt {
+                self.sync_encrypted(dotfile, prefer_local, &mut summary)?;
+                continue;
+            }
+            // Something else at a stowed file's path is for `kiwi relink --force`
+            if dotfile.mode == LinkMode::Stow {
+                if let HomeLink::Linked = self.link_home(dotfile, false)? {
+                    summary.relinked.push(dotfile.path.clone());
+                }
+                continue;
+            }
+            if dotfile.directory {
+                self.relink_directory(dotfile, prefer_local, &mut summary)?;
+                continue;
+            }
+
+            // A plain file newer than the source is a pulled version, or an
+            // edit to a copy; either way it replaces the source
+            let target = self.target_for(dotfile);
+            let metadata = target.symlink_metadata().ok();
+            if let Some(entry) = metadata.as_ref().filter(|m| m.is_file()) {
+                let newer = match fs::metadata(&dotfile.path) {
+                    Ok(source) => !prefer_local && entry.modified().ok() > source.modified().ok(),
+                    Err(_) => true,
+                };
+                let contents = fs::read(&target)?;
+                if newer && fs::read(&dotfile.path).ok().as_deref() != Some(contents.as_slice()) {
+                    if let Some(backups) = &backups {
+                        backups.save(&dotfile.name(), &dotfile.path, &contents)?;
+                    }
+                    if let Some(parent) = dotfile.path.parent() {
+                        fs::create_dir_all(parent)?;
+                    }
+                    self.write_file(dotfile, &contents)?;
+                    summary.copied.push(dotfile.path.clone());
+                }
+            }
+
+            if !dotfile.path.exists() {
                 continue;
             }
+            if metadata.is_some() {
+                fs::remove_file(&target)?;
+            } else if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            self.place(dotfile, &target)?;
+            summary.relinked.push(dotfile.path.clone());
+        }
+
+        // Files a pull brought into a directory are members from now on
+        self.refresh_members(&mut dotfiles);
+        let files: Vec<Dotfile> = dotfiles.iter().filter(|d| !d.directory).cloned().collect();
+        let hashes = self.hashes(&files)?;
+        for dotfile in dotfiles.iter_mut().filter(|d| !d.directory && include(d)) {
+            let source = self.source_for(dotfile);
+            let Some(current) = hashes.get(&source) else {
+                summary.missing.push(dotfile.path.clone());
+                continue;
+            };
+            if let Some(history) = &self.history {
+                history.record_hashed(&dotfile.path, current, &source)?;
+            }
+            let state = dotfile.sync_state(Some(current));
+            dotfile.synced = state == SyncState::Synced;
+            dotfile.source_mtime = mtime(&source);
+            if state == SyncState::Modified {
+                summary.modified.push(dotfile.path.clone());
+            }
         }
 
+        self.save_dotfiles(&dotfiles)?;
+        Ok(summary)
+    }
+
+    /// [`Self::sync_where`] for an encrypted file that differs from its
+    /// encrypted copy: a copy newer than the file, as a pull leaves it, is
+    /// decrypted over the file unless `prefer_local`, and otherwise the
+    /// file is encrypted over the copy.
+    fn sync_encrypted(&self, dotfile: &Dotfile, prefer_local: bool, summary: &mut SyncSummary) -> Result<()> {
+        let Ok(key) = self.key() else {
+            summary.locked.push(dotfile.path.clone());
+            return Ok(());
+        };
+        let target = self.target_for(dotfile);
+        let source = fs::metadata(&dotfile.path).ok();
+        if let Some(entry) = fs::metadata(&target).ok().filter(|m| m.is_file()) {
+            let newer = match &source {
+                Some(source) => !prefer_local && entry.modified().ok() > source.modified().ok(),
+                None => true,
+            };
+            if newer {
+                let contents = key.decrypt(&fs::read(&target)?)?;
+                if let Some(backups) = self.backups() {
+                    backups.save(&dotfile.name(), &dotfile.path, &contents)?;
+                }
+                if let Some(parent) = dotfile.path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                self.write_file(dotfile, &contents)?;
+                summary.copied.push(dotfile.path.clone());
+                return Ok(());
+            }
+        }
+
+        if source.is_none() {
+            return Ok(());
+        }
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        self.place(dotfile, &target)?;
+        summary.relinked.push(dotfile.path.clone());
+        Ok(())
+    }
+
+    /// [`Self::sync_where`] for a directory whose link isn't in place. A
+    /// plain directory a pull left there instead has its files copied into
+    /// the tracked directory when they're new there, or newer unless
+    /// `prefer_local`; then the link is recreated.
+    fn relink_directory(&self, root: &Dotfile, prefer_local: bool, summary: &mut SyncSummary) -> Result<()> {
+        let target = self.target_for(root);
+        let metadata = target.symlink_metadata().ok();
+        if metadata.as_ref().is_some_and(|m| m.is_dir()) {
+            let backups = self.backups();
+            for relative in directory_files(&target) {
+                let pulled = target.join(&relative);
+                let path = root.path.join(&relative);
+                let newer = match (fs::metadata(&pulled), fs::metadata(&path)) {
+                    (Ok(entry), Ok(source)) => !prefer_local && entry.modified().ok() > source.modified().ok(),
+                    _ => true,
+                };
+                let contents = fs::read(&pulled)?;
+                if newer && fs::read(&path).ok().as_deref() != Some(contents.as_slice()) {
+                    if let Some(backups) = &backups {
+                        backups.save(&format!("{}/{}", root.name(), relative.display()), &path, &contents)?;
+                    }
+                    if let Some(parent) = path.parent() {
+                        fs::create_dir_all(parent)?;
+                    }
+                    fs::write(&path, &contents)?;
+                    summary.copied.push(path);
+                }
+            }
+            fs::remove_dir_all(&target)?;
+        } else if metadata.is_some() {
+            fs::remove_file(&target)?;
+        }
+
+        if !root.path.is_dir() {
+            return Ok(());
+        }
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        self.place(root, &target)?;
+        summary.relinked.push(root.path.clone());
         Ok(())
     }
 
@@ -110,13 +1301,565 @@ impl Dotfiles {
         }
 
         let contents = fs::read_to_string(&self.dotfiles_file)?;
-        let dotfiles: Vec<Dotfile> = serde_json::from_str(&contents)?;
+        let mut dotfiles: Vec<Dotfile> = serde_json::from_str(&contents)?;
+        for dotfile in dotfiles.iter_mut().filter(|d| d.legacy_template) {
+            dotfile.mode = LinkMode::Render;
+        }
+
+        // Indexes from before home-relative paths are rewritten on first load
+        let mut stale = false;
+        if let Some(home) = &self.home {
+            stale = dotfiles.iter().any(|d| d.path.starts_with(home));
+            for dotfile in &mut dotfiles {
+                if let Ok(relative) = dotfile.path.strip_prefix("~") {
+                    dotfile.path = home.join(relative);
+                }
+            }
+        }
+        // Tracked directories are enumerated afresh every time
+        stale |= self.refresh_members(&mut dotfiles);
+        if stale {
+            self.save_dotfiles(&dotfiles)?;
+        }
         Ok(dotfiles)
     }
 
     fn save_dotfiles(&self, dotfiles: &[Dotfile]) -> Result<()> {
-        let contents = serde_json::to_string_pretty(dotfiles)?;
+        let stored: Vec<Dotfile> = dotfiles
+            .iter()
+            .map(|dotfile| {
+                let relative = self.home.as_ref().and_then(|home| dotfile.path.strip_prefix(home).ok());
+                match relative {
+                    Some(relative) => Dotfile { path: Path::new("~").join(relative), ..dotfile.clone() },
+                    None => dotfile.clone(),
+                }
+            })
+            .collect();
+        let contents = serde_json::to_string_pretty(&stored)?;
         fs::write(&self.dotfiles_file, contents)?;
         Ok(())
     }
-} 
\ No newline at end of file
+}
+
+/// Whether `pattern` has wildcards, so `kiwi add` expands it.
+pub fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?'])
+}
+
+/// The directory a glob pattern is searched from: its components before
+/// the first with a wildcard, with `~` expanded.
+pub fn glob_base(pattern: &str) -> PathBuf {
+    let pattern = expand_home(pattern);
+    let literal: Vec<&str> = pattern.split('/').take_while(|component| !is_glob(component)).collect();
+    match literal.join("/") {
+        base if base.is_empty() && pattern.starts_with('/') => PathBuf::from("/"),
+        base if base.is_empty() => PathBuf::from("."),
+        base => PathBuf::from(base),
+    }
+}
+
+/// Files matching `pattern`, sorted. `*` and `?` match within one path
+/// component and `**` matches any number of directories; `~` is the home
+/// directory. Matches are dropped when one of `excludes` matches them or a
+/// directory they're in, by name for rules without a `/` and by path
+/// otherwise. `.git` folders and links to directories aren't searched.
+pub fn expand_glob(pattern: &str, excludes: &[String]) -> Vec<PathBuf> {
+    let base = glob_base(pattern);
+    let pattern = expand_home(pattern);
+    let wanted: Vec<&str> = pattern.split('/').skip_while(|c| !is_glob(c)).filter(|c| !c.is_empty()).collect();
+    let excludes: Vec<String> = excludes.iter().map(|rule| expand_home(rule)).collect();
+    let rules: Vec<Vec<&str>> = excludes.iter().map(|rule| rule.split('/').filter(|c| !c.is_empty()).collect()).collect();
+    let excluded = |path: &Path| {
+        excludes.iter().zip(&rules).any(|(rule, parts)| {
+            path.ancestors().any(|ancestor| match rule.contains('/') {
+                true => glob_matches(parts, &components(ancestor)),
+                false => ancestor.file_name().is_some_and(|name| matches_pattern(&name.to_string_lossy(), rule)),
+            })
+        })
+    };
+
+    let mut found = Vec::new();
+    let mut pending = vec![base.clone()];
+    while let Some(current) = pending.pop() {
+        let Ok(entries) = fs::read_dir(&current) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            if SKIPPED_IN_DIRECTORIES.iter().any(|skipped| entry.file_name() == *skipped) {
+                continue;
+            }
+            let path = entry.path();
+            if entry.file_type().is_ok_and(|kind| kind.is_dir()) {
+                pending.push(path);
+                continue;
+            }
+            let Ok(relative) = path.strip_prefix(&base) else {
+                continue;
+            };
+            if path.is_file() && glob_matches(&wanted, &components(relative)) && !excluded(&path) {
+                found.push(path);
+            }
+        }
+    }
+    found.sort();
+    found
+}
+
+/// Whether the path `components` match the glob `pattern` components.
+fn glob_matches(pattern: &[&str], components: &[String]) -> bool {
+    match pattern.split_first() {
+        None => components.is_empty(),
+        Some((&"**", rest)) => (0..=components.len()).any(|skip| glob_matches(rest, &components[skip..])),
+        Some((first, rest)) => components
+            .split_first()
+            .is_some_and(|(name, tail)| matches_pattern(name, first) && glob_matches(rest, tail)),
+    }
+}
+
+fn components(path: &Path) -> Vec<String> {
+    path.iter()
+        .filter(|c| *c != "/")
+        .map(|c| c.to_string_lossy().to_string())
+        .collect()
+}
+
+/// `path` with a leading `~` replaced by the home directory.
+fn expand_home(path: &str) -> String {
+    match (path.strip_prefix('~'), dirs::home_dir()) {
+        (Some(rest), Some(home)) if rest.is_empty() || rest.starts_with('/') => format!("{}{}", home.display(), rest),
+        _ => path.to_string(),
+    }
+}
+
+/// Files under `dir`, relative to it and sorted. Symlinks are left out, so
+/// a link can't pull in files from elsewhere, and so are files that must
+/// never leave this Mac.
+fn directory_files(dir: &Path) -> Vec<PathBuf> {
+    let mut found = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        let Ok(entries) = fs::read_dir(&current) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            if SKIPPED_IN_DIRECTORIES.iter().any(|skipped| entry.file_name() == *skipped) {
+                continue;
+            }
+            let path = entry.path();
+            match entry.file_type() {
+                Ok(kind) if kind.is_dir() => pending.push(path),
+                Ok(kind) if kind.is_file() && !matches!(sensitive::check(&path), Some(Sensitivity::Deny(_))) => {
+                    if let Ok(relative) = path.strip_prefix(dir) {
+                        found.push(relative.to_path_buf());
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    found.sort();
+    found
+}
+
+/// Moves the file at `from` to `to`, copying it over when they're on
+/// different volumes.
+fn move_file(from: &Path, to: &Path) -> Result<()> {
+    if fs::rename(from, to).is_err() {
+        fs::copy(from, to)?;
+        fs::remove_file(from)?;
+    }
+    Ok(())
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+fn mtime(path: &Path) -> Option<u64> {
+    let modified = fs::metadata(path).ok()?.modified().ok()?;
+    Some(modified.duration_since(UNIX_EPOCH).ok()?.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sync_copies_pulled_files_and_relinks() {
+        let dir = std::env::temp_dir().join(format!("kiwi-dotfiles-sync-test-{}", std::process::id()));
+        let dotfiles_dir = dir.join("dotfiles");
+        fs::create_dir_all(&dotfiles_dir).unwrap();
+        let dotfiles = Dotfiles::new(dotfiles_dir.clone(), dotfiles_dir.join("dotfiles.json"));
+        let source = dir.join(".zshrc");
+        fs::write(&source, "local\n").unwrap();
+        dotfiles.add(&source, None, false).unwrap();
+        let source = source.canonicalize().unwrap();
+
+        // A pull that found no link leaves a plain file in the dotfiles dir
+        let target = dotfiles_dir.join(".zshrc");
+        fs::remove_file(&target).unwrap();
+        fs::write(&target, "remote\n").unwrap();
+
+        let summary = dotfiles.sync(true).unwrap();
+        assert_eq!(summary.relinked, vec![source.clone()]);
+        assert!(summary.copied.is_empty());
+        assert_eq!(fs::read_to_string(&source).unwrap(), "local\n");
+
+        fs::remove_file(&target).unwrap();
+        fs::write(&target, "remote\n").unwrap();
+        let summary = dotfiles.sync(false).unwrap();
+        assert_eq!(summary.copied, vec![source.clone()]);
+        assert_eq!(fs::read_link(&target).unwrap(), source);
+        assert_eq!(fs::read_to_string(&source).unwrap(), "remote\n");
+        assert!(dotfiles.sync(false).unwrap().is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_index_stores_home_relative_paths() {
+        let dir = std::env::temp_dir().join(format!("kiwi-dotfiles-home-test-{}", std::process::id()));
+        let home = dir.join("Users/jane");
+        let dotfiles_dir = home.join(".kiwi/dotfiles");
+        fs::create_dir_all(&dotfiles_dir).unwrap();
+        let home = home.canonicalize().unwrap();
+        let index = dotfiles_dir.join("dotfiles.json");
+        fs::write(home.join(".zshrc"), "export EDITOR=nvim\n").unwrap();
+        let legacy = format!(
+            r#"[{{"path": "{}", "alias": null, "synced": true}}, {{"path": "/etc/zshrc", "alias": "etc-zshrc", "synced": false}}]"#,
+            home.join(".zshrc").display()
+        );
+        fs::write(&index, legacy).unwrap();
+
+        // Absolute paths in the home are rewritten as soon as they're read
+        let dotfiles = Dotfiles::new(dotfiles_dir.clone(), index.clone()).with_home(home.clone());
+        let paths: Vec<PathBuf> = dotfiles.list().unwrap().into_iter().map(|d| d.path).collect();
+        assert_eq!(paths, vec![home.join(".zshrc"), PathBuf::from("/etc/zshrc")]);
+        let stored: Vec<serde_json::Value> = serde_json::from_str(&fs::read_to_string(&index).unwrap()).unwrap();
+        assert_eq!(stored[0]["path"], "~/.zshrc");
+        assert_eq!(stored[1]["path"], "/etc/zshrc");
+
+        // Another account reads the same index against its own home
+        let other = Dotfiles::new(dotfiles_dir, index).with_home(PathBuf::from("/Users/jd"));
+        assert_eq!(other.list().unwrap()[0].path, PathBuf::from("/Users/jd/.zshrc"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Records `sudo cp` and does the copy.
+    #[derive(Clone, Default)]
+    struct Sudo(std::sync::Arc<std::sync::Mutex<Vec<String>>>);
+
+    impl CommandRunner for Sudo {
+        fn run(&self, program: &str, args: &[&str]) -> std::io::Result<crate::homebrew::CommandOutput> {
+            if let ("sudo", ["cp", from, to]) = (program, args) {
+                fs::copy(from, to)?;
+                self.0.lock().unwrap().push(to.to_string());
+            }
+            Ok(crate::homebrew::CommandOutput { success: true, stdout: Vec::new(), stderr: Vec::new() })
+        }
+    }
+
+    #[test]
+    fn test_sudo_files_are_written_through_sudo() {
+        let dir = std::env::temp_dir().join(format!("kiwi-dotfiles-sudo-test-{}", std::process::id()));
+        let dotfiles_dir = dir.join("dotfiles");
+        fs::create_dir_all(&dotfiles_dir).unwrap();
+        let runner = Sudo::default();
+        let dotfiles = Dotfiles::new(dotfiles_dir.clone(), dotfiles_dir.join("dotfiles.json")).with_runner(runner.clone());
+        let zshrc = dir.join("zshrc");
+        fs::write(&zshrc, "# system\n").unwrap();
+        assert!(!dotfiles.is_foreign(&zshrc));
+
+        dotfiles.add(&zshrc, None, false).unwrap();
+        let tracked = dotfiles.find("zshrc").unwrap().unwrap();
+        dotfiles.write_file(&tracked, b"# plain\n").unwrap();
+        assert!(runner.0.lock().unwrap().is_empty());
+
+        dotfiles.set_sudo(&zshrc, true).unwrap();
+        let tracked = dotfiles.find("zshrc").unwrap().unwrap();
+        assert!(tracked.sudo);
+        dotfiles.write_file(&tracked, b"# via sudo\n").unwrap();
+        assert_eq!(fs::read_to_string(&zshrc).unwrap(), "# via sudo\n");
+        assert_eq!(*runner.0.lock().unwrap(), vec![zshrc.canonicalize().unwrap().display().to_string()]);
+        assert!(dotfiles.set_sudo(&dir.join("dotfiles/dotfiles.json"), true).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_add_keeps_what_it_replaces() {
+        let dir = std::env::temp_dir().join(format!("kiwi-dotfiles-add-test-{}", std::process::id()));
+        let dotfiles_dir = dir.join("dotfiles");
+        fs::create_dir_all(&dotfiles_dir).unwrap();
+        let dotfiles = Dotfiles::new(dotfiles_dir.clone(), dotfiles_dir.join("dotfiles.json"));
+        let source = dir.join(".zshrc");
+        fs::write(&source, "local\n").unwrap();
+
+        // A pulled copy that matches is replaced without fuss
+        fs::write(dotfiles_dir.join(".zshrc"), "local\n").unwrap();
+        assert_eq!(dotfiles.occupied(&source, None).unwrap(), None);
+
+        fs::write(dotfiles_dir.join(".zshrc"), "pulled\n").unwrap();
+        assert_eq!(dotfiles.occupied(&source, None).unwrap(), Some(dotfiles_dir.join(".zshrc")));
+        assert_eq!(dotfiles.occupied(&source, Some("zshrc")).unwrap(), None);
+        dotfiles.add(&source, None, false).unwrap();
+        assert_eq!(dotfiles.occupied(&source, None).unwrap(), None);
+        let runs = Backups::new(&dotfiles_dir).runs().unwrap();
+        assert_eq!(fs::read_to_string(runs[0].join(".zshrc")).unwrap(), "pulled\n");
+
+        // Names that would land on kiwi's own state or outside the dir are refused
+        let manifest = fs::read_to_string(dotfiles_dir.join("dotfiles.json")).unwrap();
+        fs::write(dir.join("vimrc"), "set number\n").unwrap();
+        for alias in ["packages.json", "Blobs/x", "../vimrc"] {
+            assert!(dotfiles.add(&dir.join("vimrc"), Some(alias.to_string()), false).is_err());
+        }
+        fs::write(dir.join("taps.json"), "[]").unwrap();
+        assert!(dotfiles.add(&dir.join("taps.json"), None, false).is_err());
+        assert_eq!(fs::read_to_string(dotfiles_dir.join("dotfiles.json")).unwrap(), manifest);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_copy_and_hardlink_modes() {
+        let dir = std::env::temp_dir().join(format!("kiwi-dotfiles-mode-test-{}", std::process::id()));
+        let dotfiles_dir = dir.join("dotfiles");
+        fs::create_dir_all(&dotfiles_dir).unwrap();
+        let dotfiles = Dotfiles::new(dotfiles_dir.clone(), dotfiles_dir.join("dotfiles.json"));
+        fs::write(dir.join("gitconfig"), "[core]\n").unwrap();
+        fs::write(dir.join("vimrc"), "set number\n").unwrap();
+        dotfiles.add_with_mode(&dir.join("gitconfig"), None, false, LinkMode::Copy).unwrap();
+        dotfiles.add_with_mode(&dir.join("vimrc"), None, false, LinkMode::Hardlink).unwrap();
+
+        let tracked = dotfiles.list().unwrap();
+        assert_eq!(tracked.iter().map(|d| d.mode).collect::<Vec<_>>(), vec![LinkMode::Copy, LinkMode::Hardlink]);
+        assert!(tracked.iter().all(|d| dotfiles.drift(d) == DriftStatus::Clean));
+        assert!(!dotfiles_dir.join("gitconfig").symlink_metadata().unwrap().is_symlink());
+
+        // Hard links share writes; copies are refreshed from the newer side
+        fs::write(dir.join("vimrc"), "set hidden\n").unwrap();
+        assert_eq!(fs::read_to_string(dotfiles_dir.join("vimrc")).unwrap(), "set hidden\n");
+        fs::write(dir.join("gitconfig"), "[core]\n\teditor = nvim\n").unwrap();
+        let summary = dotfiles.sync(false).unwrap();
+        assert_eq!(summary.relinked.len(), 1);
+        assert_eq!(fs::read_to_string(dotfiles_dir.join("gitconfig")).unwrap(), "[core]\n\teditor = nvim\n");
+
+        // Manifests from before modes still load their templates
+        fs::write(
+            dotfiles_dir.join("dotfiles.json"),
+            r#"[{"path": "/tmp/x", "alias": null, "synced": false, "template": true}]"#,
+        )
+        .unwrap();
+        assert!(dotfiles.list().unwrap()[0].is_template());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_stowed_files_move_into_the_dotfiles_dir() {
+        let dir = std::env::temp_dir().join(format!("kiwi-dotfiles-stow-test-{}", std::process::id()));
+        let dotfiles_dir = dir.join("dotfiles");
+        fs::create_dir_all(&dotfiles_dir).unwrap();
+        let dotfiles = Dotfiles::new(dotfiles_dir.clone(), dotfiles_dir.join("dotfiles.json"));
+        let zshrc = dir.join(".zshrc");
+        fs::write(&zshrc, "export A=1\n").unwrap();
+        dotfiles.add_with_mode(&zshrc, None, false, LinkMode::Stow).unwrap();
+
+        let stowed = dotfiles_dir.join(".zshrc");
+        assert!(!stowed.symlink_metadata().unwrap().is_symlink());
+        assert_eq!(fs::read_link(&zshrc).unwrap(), stowed);
+        assert_eq!(fs::read_to_string(&zshrc).unwrap(), "export A=1\n");
+        let dotfile = dotfiles.find(&zshrc.to_string_lossy()).unwrap().unwrap();
+        assert_eq!(dotfile.path, zshrc);
+        assert_eq!(dotfiles.source_for(&dotfile), stowed);
+        assert_eq!(dotfiles.drift(&dotfile), DriftStatus::Clean);
+        assert!(dotfiles.add_with_mode(&zshrc, None, false, LinkMode::Stow).is_err());
+
+        // A fresh Mac with the dotfiles dir cloned has nothing at the path yet
+        fs::remove_file(&zshrc).unwrap();
+        assert_eq!(dotfiles.drift(&dotfile), DriftStatus::Unlinked);
+        assert_eq!(dotfiles.relink(false).unwrap().linked, vec![zshrc.clone()]);
+        assert_eq!(dotfiles.drift(&dotfile), DriftStatus::Clean);
+
+        // Something else in the way is only replaced with force
+        fs::remove_file(&zshrc).unwrap();
+        fs::write(&zshrc, "export B=2\n").unwrap();
+        assert_eq!(dotfiles.drift(&dotfile), DriftStatus::Foreign);
+        assert!(dotfiles.sync(false).unwrap().relinked.is_empty());
+        assert_eq!(dotfiles.relink(false).unwrap().blocked, vec![zshrc.clone()]);
+        assert_eq!(dotfiles.relink(true).unwrap().linked, vec![zshrc.clone()]);
+        assert_eq!(fs::read_to_string(&zshrc).unwrap(), "export A=1\n");
+
+        // Untracking puts the file back where it was
+        dotfiles.remove(&zshrc).unwrap();
+        assert!(!zshrc.symlink_metadata().unwrap().is_symlink());
+        assert_eq!(fs::read_to_string(&zshrc).unwrap(), "export A=1\n");
+        assert!(!stowed.exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_environments_put_away_each_others_files() {
+        let dir = std::env::temp_dir().join(format!("kiwi-dotfiles-env-test-{}", std::process::id()));
+        let dotfiles_dir = dir.join("dotfiles");
+        fs::create_dir_all(&dotfiles_dir).unwrap();
+        let index = dotfiles_dir.join("dotfiles.json");
+        let dev = Dotfiles::new(dotfiles_dir.clone(), index.clone()).with_environment(Some("dev".to_string()));
+        let prod = Dotfiles::new(dotfiles_dir.clone(), index).with_environment(Some("prod".to_string()));
+        let (npmrc, kubeconfig, zshrc) = (dir.join(".npmrc"), dir.join(".kubeconfig"), dir.join(".zshrc"));
+        fs::write(&npmrc, "registry=http://localhost:4873\n").unwrap();
+        fs::write(&kubeconfig, "context: prod\n").unwrap();
+        fs::write(&zshrc, "export A=1\n").unwrap();
+        dev.add(&npmrc, None, false).unwrap();
+        dev.add_with_mode(&kubeconfig, None, false, LinkMode::Stow).unwrap();
+        dev.add(&zshrc, None, false).unwrap();
+        dev.set_environments(&npmrc, &["dev".to_string()]).unwrap();
+        dev.set_environments(&kubeconfig, &["prod".to_string(), "staging".to_string()]).unwrap();
+
+        let files = dev.apply_environment().unwrap();
+        assert_eq!(files.parked, vec![kubeconfig.clone()]);
+        assert!(files.applied.is_empty());
+        assert!(kubeconfig.symlink_metadata().is_err());
+        assert!(dev.list().unwrap().iter().all(|d| dev.drift(d) == DriftStatus::Clean));
+
+        let files = prod.apply_environment().unwrap();
+        assert_eq!(files.parked, vec![npmrc.clone()]);
+        assert_eq!(files.applied, vec![kubeconfig.clone()]);
+        assert_eq!(fs::read_to_string(&kubeconfig).unwrap(), "context: prod\n");
+        assert!(npmrc.symlink_metadata().is_err());
+        let parked = prod.find(".npmrc").unwrap().unwrap();
+        assert_eq!(prod.source_for(&parked), dotfiles_dir.join(".npmrc"));
+        assert!(prod.sync(false).unwrap().missing.is_empty());
+        assert!(npmrc.symlink_metadata().is_err());
+        assert_eq!(fs::read_to_string(&zshrc).unwrap(), "export A=1\n");
+
+        let files = dev.apply_environment().unwrap();
+        assert_eq!(files.applied, vec![npmrc.clone()]);
+        assert_eq!(fs::read_to_string(&npmrc).unwrap(), "registry=http://localhost:4873\n");
+        assert_eq!(fs::read_link(dotfiles_dir.join(".npmrc")).unwrap(), npmrc);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_history_keeps_versions_for_rollback() {
+        let dir = std::env::temp_dir().join(format!("kiwi-dotfiles-history-test-{}", std::process::id()));
+        let dotfiles_dir = dir.join("dotfiles");
+        fs::create_dir_all(&dotfiles_dir).unwrap();
+        let dotfiles = Dotfiles::new(dotfiles_dir.clone(), dotfiles_dir.join("dotfiles.json")).with_history(dir.join("history"));
+        let zshrc = dir.join(".zshrc");
+        fs::write(&zshrc, "export A=1\n").unwrap();
+        dotfiles.add(&zshrc, None, false).unwrap();
+
+        fs::write(&zshrc, "export A=2\n").unwrap();
+        dotfiles.sync(true).unwrap();
+        fs::write(&zshrc, "exprot A=3\n").unwrap();
+        let (_, versions) = dotfiles.versions(&zshrc).unwrap();
+        assert_eq!(versions.len(), 3);
+
+        let (dotfile, version, contents) = dotfiles.version_contents(&zshrc, "2").unwrap();
+        assert_eq!(dotfile.path, zshrc);
+        assert_eq!(version, versions[1]);
+        dotfiles.rollback(&zshrc, &contents).unwrap();
+        assert_eq!(fs::read_to_string(&zshrc).unwrap(), "export A=2\n");
+        let (_, versions) = dotfiles.versions(&zshrc).unwrap();
+        assert_eq!(versions.len(), 4);
+        assert_eq!(versions[3].hash, versions[1].hash);
+
+        let untracked = dir.join(".vimrc");
+        fs::write(&untracked, "set number\n").unwrap();
+        assert!(dotfiles.versions(&untracked).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_encrypted_files_keep_only_ciphertext_in_the_dotfiles_dir() {
+        use age::secrecy::ExposeSecret;
+
+        let dir = std::env::temp_dir().join(format!("kiwi-dotfiles-encrypt-test-{}", std::process::id()));
+        let dotfiles_dir = dir.join("dotfiles");
+        fs::create_dir_all(&dotfiles_dir).unwrap();
+        let identity = age::x25519::Identity::generate();
+        let identity_file = dir.join("identity.txt");
+        fs::write(&identity_file, format!("{}\n", identity.to_string().expose_secret())).unwrap();
+        let key = Key::Recipient { recipient: identity.to_public().to_string(), identity: identity_file };
+        let index = dotfiles_dir.join("dotfiles.json");
+        let dotfiles = Dotfiles::new(dotfiles_dir.clone(), index.clone()).with_key(key.clone());
+        let netrc = dir.join(".netrc");
+        fs::write(&netrc, "machine api.example.com password hunter2\n").unwrap();
+        dotfiles.add_with_mode(&netrc, None, false, LinkMode::Encrypt).unwrap();
+
+        let entry = dotfiles_dir.join(".netrc");
+        let dotfile = dotfiles.find(".netrc").unwrap().unwrap();
+        assert_eq!(dotfiles.source_for(&dotfile), entry);
+        assert!(crate::crypto::is_encrypted(&fs::read(&entry).unwrap()));
+        assert!(dotfiles.sync(true).unwrap().relinked.is_empty());
+
+        // Edits here are encrypted over the copy; a newer copy, as a pull
+        // leaves it, is decrypted over the file
+        fs::write(&netrc, "machine api.example.com password hunter3\n").unwrap();
+        assert_eq!(dotfiles.sync(true).unwrap().relinked, vec![netrc.clone()]);
+        assert_eq!(key.decrypt(&fs::read(&entry).unwrap()).unwrap(), b"machine api.example.com password hunter3\n");
+        fs::write(&entry, key.encrypt(b"machine api.example.com password pulled\n").unwrap()).unwrap();
+        let later = SystemTime::now() + std::time::Duration::from_secs(60);
+        fs::File::options().write(true).open(&entry).unwrap().set_modified(later).unwrap();
+        assert_eq!(dotfiles.sync(false).unwrap().copied, vec![netrc.clone()]);
+        assert_eq!(fs::read_to_string(&netrc).unwrap(), "machine api.example.com password pulled\n");
+
+        // Without the key the file is left alone
+        fs::write(&netrc, "machine api.example.com password local\n").unwrap();
+        let keyless = Dotfiles::new(dotfiles_dir.clone(), index);
+        assert_eq!(keyless.sync(true).unwrap().locked, vec![netrc.clone()]);
+        assert_eq!(fs::read_to_string(&netrc).unwrap(), "machine api.example.com password local\n");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_directories_are_tracked_as_a_whole() {
+        let dir = std::env::temp_dir().join(format!("kiwi-dotfiles-dir-test-{}", std::process::id()));
+        let dotfiles_dir = dir.join("dotfiles");
+        let nvim = dir.join("config/nvim");
+        fs::create_dir_all(nvim.join("lua")).unwrap();
+        fs::create_dir_all(nvim.join(".git")).unwrap();
+        fs::create_dir_all(&dotfiles_dir).unwrap();
+        fs::write(nvim.join("init.lua"), "require('plugins')\n").unwrap();
+        fs::write(nvim.join("lua/plugins.lua"), "return {}\n").unwrap();
+        fs::write(nvim.join(".git/HEAD"), "ref: refs/heads/main\n").unwrap();
+        let dotfiles = Dotfiles::new(dotfiles_dir.clone(), dotfiles_dir.join("dotfiles.json"));
+        let names = || dotfiles.list().unwrap().iter().map(Dotfile::name).collect::<Vec<_>>();
+
+        dotfiles.add(&nvim, None, false).unwrap();
+        let nvim = nvim.canonicalize().unwrap();
+        assert_eq!(fs::read_link(dotfiles_dir.join("nvim")).unwrap(), nvim);
+        assert_eq!(names(), vec!["nvim/init.lua", "nvim/lua/plugins.lua"]);
+        assert!(dotfiles.list().unwrap().iter().all(|d| dotfiles.drift(d) == DriftStatus::Clean));
+        assert!(dotfiles.add(&nvim.join("init.lua"), None, false).is_err());
+        assert!(dotfiles.remove(&nvim.join("init.lua")).is_err());
+
+        // Picked up as the directory changes
+        fs::write(nvim.join("lua/keys.lua"), "vim.g.mapleader = ' '\n").unwrap();
+        fs::remove_file(nvim.join("lua/plugins.lua")).unwrap();
+        assert_eq!(names(), vec!["nvim/init.lua", "nvim/lua/keys.lua"]);
+
+        // A pulled directory in place of the link is merged back in
+        fs::remove_file(dotfiles_dir.join("nvim")).unwrap();
+        fs::create_dir_all(dotfiles_dir.join("nvim/after")).unwrap();
+        fs::write(dotfiles_dir.join("nvim/after/ftplugin.lua"), "-- pulled\n").unwrap();
+        let summary = dotfiles.sync(false).unwrap();
+        assert_eq!(summary.copied, vec![nvim.join("after/ftplugin.lua")]);
+        assert_eq!(summary.relinked, vec![nvim.clone()]);
+        assert_eq!(fs::read_link(dotfiles_dir.join("nvim")).unwrap(), nvim);
+        assert_eq!(names(), vec!["nvim/init.lua", "nvim/lua/keys.lua", "nvim/after/ftplugin.lua"]);
+
+        dotfiles.remove(&nvim).unwrap();
+        assert!(names().is_empty());
+        assert!(dotfiles_dir.join("nvim").symlink_metadata().is_err());
+        assert!(nvim.join("init.lua").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,58 @@
+use crate::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Environments every user has, matching the `EnvType` choices in `kiwi init`.
+pub const BUILT_IN: &[&str] = &["dev", "prod", "design"];
+
+/// What belongs to an environment beyond its name.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Profile {
+    /// Packages installed when switching to the environment
+    #[serde(default)]
+    pub packages: Vec<String>,
+}
+
+/// Environment profiles, stored in `environments.json` in the dotfiles dir:
+///
+/// ```json
+/// { "dev": { "packages": ["node", "postgresql@16"] } }
+/// ```
+pub struct Environments {
+    profiles: BTreeMap<String, Profile>,
+}
+
+impl Environments {
+    pub fn load(dotfiles_dir: &Path) -> Result<Self> {
+        let file = Self::file(dotfiles_dir);
+        let profiles = if file.exists() {
+            serde_json::from_str(&fs::read_to_string(&file)?)?
+        } else {
+            BTreeMap::new()
+        };
+        Ok(Self { profiles })
+    }
+
+    /// Built-in environments followed by custom ones, plus `active` if it
+    /// has no profile yet.
+    pub fn names(&self, active: Option<&str>) -> Vec<String> {
+        let mut names: Vec<String> = BUILT_IN.iter().map(|n| n.to_string()).collect();
+        for name in self.profiles.keys().map(String::as_str).chain(active) {
+            if !names.iter().any(|n| n == name) {
+                names.push(name.to_string());
+            }
+        }
+        names
+    }
+
+    /// The profile for `name`; environments without one have an empty bundle.
+    pub fn profile(&self, name: &str) -> Profile {
+        self.profiles.get(name).cloned().unwrap_or_default()
+    }
+
+    fn file(dotfiles_dir: &Path) -> PathBuf {
+        dotfiles_dir.join("environments.json")
+    }
+}
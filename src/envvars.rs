@@ -0,0 +1,263 @@
+//! Exported environment variables kept in a kiwi-owned shell fragment. The
+//! fragment is a templated dotfile: per-environment values sit in
+//! `{{#if environment == "..."}}` blocks, so `kiwi env use` re-renders it and
+//! the template syncs like any other dotfile. Secrets are stored in the
+//! Keychain and only a lookup placeholder is written.
+
+use crate::{keychain, shell, Dotfiles, KiwiError, Result};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::PathBuf;
+
+/// Name of the rc block that sources the fragment.
+pub const RC_BLOCK: &str = "env";
+/// Name of the fragment inside the dotfiles dir.
+const TRACKED_NAME: &str = "kiwi-env.sh";
+const HEADER: &str = "# Managed by kiwi; edit with `kiwi envvar set/unset`.\n";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Plain(String),
+    /// Read from the Keychain entry `account` when the shell starts
+    Secret { account: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnvVar {
+    pub name: String,
+    /// Only exported in this environment; `None` means every environment
+    pub env: Option<String>,
+    pub value: Value,
+}
+
+impl EnvVar {
+    fn to_line(&self) -> String {
+        match &self.value {
+            Value::Plain(value) => format!("export {}='{}'", self.name, value.replace('\'', r"'\''")),
+            Value::Secret { account } => format!("export {}=\"{}\"", self.name, keychain::lookup_command(account)),
+        }
+    }
+
+    fn parse(line: &str, env: Option<&str>) -> Option<Self> {
+        let (name, value) = line.strip_prefix("export ")?.split_once('=')?;
+        let value = if let Some(quoted) = value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')) {
+            Value::Plain(quoted.replace(r"'\''", "'"))
+        } else {
+            let placeholder = value.strip_prefix('"')?.strip_suffix('"')?;
+            Value::Secret { account: keychain::parse_lookup(placeholder)?.to_string() }
+        };
+        Some(Self { name: name.to_string(), env: env.map(str::to_string), value })
+    }
+}
+
+/// The Keychain account for a secret variable, distinct per environment.
+pub fn secret_account(name: &str, env: Option<&str>) -> String {
+    match env {
+        Some(env) => format!("{}@{}", name, env),
+        None => name.to_string(),
+    }
+}
+
+pub struct EnvVars<'a> {
+    dotfiles: &'a Dotfiles,
+    path: PathBuf,
+}
+
+impl<'a> EnvVars<'a> {
+    /// `path` is where the rendered fragment lives; see [`Self::default_path`].
+    pub fn new(dotfiles: &'a Dotfiles, path: PathBuf) -> Self {
+        Self { dotfiles, path }
+    }
+
+    /// `~/.config/kiwi/env.sh`
+    pub fn default_path() -> Result<PathBuf> {
+        let home = dirs::home_dir().ok_or_else(|| KiwiError::Config("Could not find home directory".to_string()))?;
+        Ok(home.join(".config/kiwi/env.sh"))
+    }
+
+    pub fn list(&self) -> Result<Vec<EnvVar>> {
+        match self.template()? {
+            Some(template) => Ok(parse(&fs::read_to_string(template)?)),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Adds or replaces a variable and re-renders the fragment with `vars`.
+    /// Returns true when a value for the same name and environment was replaced.
+    pub fn set(&self, var: EnvVar, vars: &HashMap<String, String>) -> Result<bool> {
+        validate(&var)?;
+        let mut all = self.list()?;
+        let replaced = match all.iter_mut().find(|v| v.name == var.name && v.env == var.env) {
+            Some(existing) => {
+                *existing = var;
+                true
+            }
+            None => {
+                all.push(var);
+                false
+            }
+        };
+        self.save(&all, vars)?;
+        Ok(replaced)
+    }
+
+    /// Removes a variable and returns it, so callers can clean up its secret.
+    pub fn unset(&self, name: &str, env: Option<&str>, vars: &HashMap<String, String>) -> Result<Option<EnvVar>> {
+        let mut all = self.list()?;
+        let Some(index) = all.iter().position(|v| v.name == name && v.env.as_deref() == env) else {
+            return Ok(None);
+        };
+        let removed = all.remove(index);
+        self.save(&all, vars)?;
+        Ok(Some(removed))
+    }
+
+    /// Sources the fragment from the rc file of `shell`. Returns the rc file
+    /// when it had to be changed.
+    pub fn install(&self, shell: &str) -> Result<Option<PathBuf>> {
+        if !matches!(shell, "zsh" | "bash") {
+            return Err(KiwiError::ValidationError(format!("Environment variables aren't supported for {} yet; use zsh or bash", shell)));
+        }
+        let rc = shell::rc_file(shell)?;
+        let source = format!("[ -f \"{0}\" ] && . \"{0}\"", self.path.display());
+        Ok(shell::ensure_block(&rc, RC_BLOCK, &source)?.then_some(rc))
+    }
+
+    fn save(&self, all: &[EnvVar], vars: &HashMap<String, String>) -> Result<()> {
+        let template = match self.template()? {
+            Some(template) => template,
+            None => {
+                if let Some(parent) = self.path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::write(&self.path, HEADER)?;
+                self.dotfiles.add_template(&self.path, Some(TRACKED_NAME.to_string()))?;
+                self.template()?.expect("fragment was just tracked")
+            }
+        };
+
+        fs::write(&template, format(all))?;
+        self.dotfiles.render_paths(&[self.path.canonicalize()?], vars)?;
+        Ok(())
+    }
+
+    /// The template source of the fragment, once it is tracked.
+    fn template(&self) -> Result<Option<PathBuf>> {
+        let Ok(canonical) = self.path.canonicalize() else {
+            return Ok(None);
+        };
+        Ok(self
+            .dotfiles
+            .list()?
+            .iter()
+            .find(|d| d.path == canonical && d.is_template())
+            .map(|d| self.dotfiles.source_for(d)))
+    }
+}
+
+fn parse(template: &str) -> Vec<EnvVar> {
+    let mut env: Option<&str> = None;
+    let mut vars = Vec::new();
+    for line in template.lines().map(str::trim) {
+        if let Some(name) = line
+            .strip_prefix("{{#if environment == \"")
+            .and_then(|rest| rest.strip_suffix("\"}}"))
+        {
+            env = Some(name);
+        } else if line == "{{/if}}" {
+            env = None;
+        } else if let Some(var) = EnvVar::parse(line, env) {
+            vars.push(var);
+        }
+    }
+    vars
+}
+
+fn format(all: &[EnvVar]) -> String {
+    let mut contents = HEADER.to_string();
+    let mut by_env: BTreeMap<&str, Vec<&EnvVar>> = BTreeMap::new();
+    for var in all {
+        match &var.env {
+            None => {
+                contents.push_str(&var.to_line());
+                contents.push('\n');
+            }
+            Some(env) => by_env.entry(env).or_default().push(var),
+        }
+    }
+    for (env, vars) in by_env {
+        contents.push_str(&format!("{{{{#if environment == \"{}\"}}}}\n", env));
+        for var in vars {
+            contents.push_str(&var.to_line());
+            contents.push('\n');
+        }
+        contents.push_str("{{/if}}\n");
+    }
+    contents
+}
+
+/// Whether `name` can be exported as is: `[A-Za-z_][A-Za-z0-9_]*`.
+pub fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_') && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn validate(var: &EnvVar) -> Result<()> {
+    if !is_valid_name(&var.name) {
+        return Err(KiwiError::ValidationError(format!("Invalid variable name: {}", var.name)));
+    }
+    if let Some(env) = &var.env {
+        if env.is_empty() || !env.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-') {
+            return Err(KiwiError::ValidationError(format!("Invalid environment name: {}", env)));
+        }
+    }
+    if let Value::Plain(value) = &var.value {
+        if value.contains('\n') || value.contains("{{") {
+            return Err(KiwiError::ValidationError("Values can't contain newlines or `{{`".to_string()));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fragment_renders_per_environment() {
+        let dir = std::env::temp_dir().join(format!("kiwi-envvars-test-{}", std::process::id()));
+        let dotfiles_dir = dir.join("dotfiles");
+        fs::create_dir_all(&dotfiles_dir).unwrap();
+        let dotfiles = Dotfiles::new(dotfiles_dir.clone(), dotfiles_dir.join("dotfiles.json"));
+        let fragment = dir.join("env.sh");
+        let envvars = EnvVars::new(&dotfiles, fragment.clone());
+        let vars = HashMap::from([("environment".to_string(), "dev".to_string())]);
+
+        let editor = EnvVar { name: "EDITOR".to_string(), env: None, value: Value::Plain("it's nvim".to_string()) };
+        let api = EnvVar { name: "API_URL".to_string(), env: Some("dev".to_string()), value: Value::Plain("http://localhost".to_string()) };
+        let prod_api = EnvVar { name: "API_URL".to_string(), env: Some("prod".to_string()), value: Value::Plain("https://api".to_string()) };
+        let token = EnvVar {
+            name: "TOKEN".to_string(),
+            env: Some("prod".to_string()),
+            value: Value::Secret { account: secret_account("TOKEN", Some("prod")) },
+        };
+        for var in [&editor, &api, &prod_api, &token] {
+            assert!(!envvars.set(var.clone(), &vars).unwrap());
+        }
+        assert_eq!(envvars.list().unwrap(), vec![editor, api, prod_api, token]);
+
+        let rendered = fs::read_to_string(&fragment).unwrap();
+        assert!(rendered.contains("export EDITOR='it'\\''s nvim'"));
+        assert!(rendered.contains("export API_URL='http://localhost'"));
+        assert!(!rendered.contains("https://api") && !rendered.contains("TOKEN"));
+
+        assert!(envvars.unset("API_URL", Some("dev"), &vars).unwrap().is_some());
+        assert!(envvars.unset("API_URL", Some("dev"), &vars).unwrap().is_none());
+        assert!(!fs::read_to_string(&fragment).unwrap().contains("API_URL"));
+
+        let bad = EnvVar { name: "1X".to_string(), env: None, value: Value::Plain("x".to_string()) };
+        assert!(envvars.set(bad, &vars).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -47,6 +47,25 @@ pub enum KiwiError {
 
     #[error("Operation cancelled by user")]
     UserCancelled,
+
+    #[error("Sync server is busy or rate limiting requests{}", retry_hint(.retry_after))]
+    RateLimited { retry_after: Option<u64> },
+
+    #[error("The remote changed since your last sync: {}", .files.join(", "))]
+    Conflict { files: Vec<String> },
+
+    #[error("Template error: {0}")]
+    Template(String),
+
+    #[error("File too large: {path} is {size} bytes (limit {limit})")]
+    FileTooLarge { path: PathBuf, size: u64, limit: u64 },
+}
+
+fn retry_hint(retry_after: &Option<u64>) -> String {
+    match retry_after {
+        Some(seconds) => format!("; retry in {}s", seconds),
+        None => String::new(),
+    }
 }
 
 impl KiwiError {
@@ -56,6 +75,7 @@ impl KiwiError {
             KiwiError::ValidationError(_) |
             KiwiError::UserCancelled |
             KiwiError::InvalidConfig { .. } |
+            KiwiError::FileTooLarge { .. } |
             KiwiError::FileNotFound { .. }
         )
     }
@@ -75,7 +95,10 @@ impl KiwiError {
                 Some(format!("Please check if the file exists at: {}", path.display()))
             }
             KiwiError::PermissionDenied { path } => {
-                Some(format!("Try running with sudo or check file permissions at: {}", path.display()))
+                Some(format!(
+                    "{} belongs to root or another user; check its permissions, or if it's a system file like /etc/zshrc, track it with `kiwi add --sudo` so kiwi writes it through sudo",
+                    path.display()
+                ))
             }
             KiwiError::InvalidConfig { key, .. } => {
                 Some(format!("Try updating the configuration with: kiwi config {} <value>", key))
@@ -86,6 +109,15 @@ impl KiwiError {
             KiwiError::Network(_) => {
                 Some("Check your internet connection and try again".to_string())
             }
+            KiwiError::RateLimited { .. } => {
+                Some("Wait a moment before syncing again".to_string())
+            }
+            KiwiError::Conflict { .. } => {
+                Some("Pull to get the remote changes, or push with --force to overwrite them".to_string())
+            }
+            KiwiError::FileTooLarge { .. } => {
+                Some("Add it with --pointer to keep it out of sync, or --force to sync it anyway".to_string())
+            }
             _ => None
         }
     }
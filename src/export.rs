@@ -0,0 +1,144 @@
+use crate::dotfiles::Dotfile;
+use crate::homebrew::Package;
+use crate::{Result, Config, Dotfiles, Homebrew, KiwiError};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Where `kiwi export --github-action` writes the environment manifest the
+/// workflow checks.
+pub const MANIFEST_FILE: &str = "kiwi-environment.json";
+/// Where it writes the workflow, unless told otherwise.
+pub const WORKFLOW_FILE: &str = ".github/workflows/kiwi.yml";
+/// Where the workflow builds kiwi from.
+const KIWI_GIT: &str = "https://github.com/ojowwalker77/kiwi-cli";
+
+/// A snapshot of everything kiwi manages, used by the export formats.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Environment {
+    pub generated_at: String,
+    pub environment: Option<String>,
+    pub dotfiles: Vec<Dotfile>,
+    pub taps: Vec<String>,
+    pub packages: Vec<Package>,
+    pub excluded_packages: Vec<String>,
+}
+
+impl Environment {
+    pub fn collect(config: &Config, dotfiles: &Dotfiles, homebrew: &Homebrew) -> Result<Self> {
+        Ok(Self {
+            generated_at: chrono::Local::now().to_rfc3339(),
+            environment: config.environment.clone(),
+            dotfiles: dotfiles.list()?,
+            taps: homebrew.load_taps()?,
+            packages: homebrew.tracked(),
+            excluded_packages: config.excluded_packages.clone(),
+        })
+    }
+
+    /// Reads an environment exported as JSON, or as YAML when the file ends
+    /// in `.yml` or `.yaml`.
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("yml" | "yaml") => serde_yaml::from_str(&contents)
+                .map_err(|e| KiwiError::Config(format!("Invalid manifest {}: {}", path.display(), e))),
+            _ => serde_json::from_str(&contents)
+                .map_err(|e| KiwiError::Config(format!("Invalid manifest {}: {}", path.display(), e))),
+        }
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn to_yaml(&self) -> Result<String> {
+        serde_yaml::to_string(self).map_err(|e| {
+            KiwiError::Config(format!("Failed to serialize YAML: {}", e))
+        })
+    }
+
+    /// Renders a human-readable "my setup" document.
+    pub fn to_markdown(&self) -> String {
+        let mut doc = String::new();
+        doc.push_str("# My Setup\n\n");
+        doc.push_str(&format!("Generated by kiwi on {}\n\n", self.generated_at));
+        if let Some(env) = &self.environment {
+            doc.push_str(&format!("Environment: **{}**\n\n", env));
+        }
+
+        doc.push_str("## Dotfiles\n\n");
+        if self.dotfiles.is_empty() {
+            doc.push_str("_No dotfiles tracked._\n\n");
+        } else {
+            for dotfile in &self.dotfiles {
+                match &dotfile.alias {
+                    Some(alias) => doc.push_str(&format!("- `{}` ({})\n", dotfile.path.display(), alias)),
+                    None => doc.push_str(&format!("- `{}`\n", dotfile.path.display())),
+                }
+            }
+            doc.push('\n');
+        }
+
+        if !self.taps.is_empty() {
+            doc.push_str("## Taps\n\n");
+            for tap in &self.taps {
+                doc.push_str(&format!("- `{}`\n", tap));
+            }
+            doc.push('\n');
+        }
+
+        let (casks, formulae): (Vec<&Package>, Vec<&Package>) = self.packages.iter().partition(|p| p.is_cask);
+        for (title, packages) in [("Formulae", formulae), ("Applications", casks)] {
+            if packages.is_empty() {
+                continue;
+            }
+            doc.push_str(&format!("## {}\n\n", title));
+            doc.push_str("| Package | Version | Note |\n");
+            doc.push_str("| --- | --- | --- |\n");
+            for package in packages {
+                doc.push_str(&format!(
+                    "| {} | {} | {} |\n",
+                    package.name,
+                    package.version.as_deref().unwrap_or("latest"),
+                    package.note.as_deref().unwrap_or("").replace('|', "\\|"),
+                ));
+            }
+            doc.push('\n');
+        }
+
+        doc
+    }
+}
+
+/// A GitHub Actions workflow that installs everything in the committed
+/// `manifest` on a macOS runner with `kiwi check --install`, on changes to
+/// it and every week, so a manifest that stopped installing is caught.
+pub fn github_action(manifest: &str, workflow: &str) -> String {
+    format!(
+        r#"# Generated by `kiwi export --github-action`
+name: Verify kiwi environment
+
+on:
+  push:
+    paths: ["{manifest}", "{workflow}"]
+  pull_request:
+    paths: ["{manifest}", "{workflow}"]
+  schedule:
+    - cron: "0 6 * * 1"
+  workflow_dispatch:
+
+jobs:
+  verify:
+    runs-on: macos-latest
+    steps:
+      - uses: actions/checkout@v4
+      - name: Install kiwi
+        run: cargo install --locked --git {git} kiwi
+      - name: Check the environment installs
+        run: kiwi check "{manifest}" --install
+"#,
+        manifest = manifest,
+        workflow = workflow,
+        git = KIWI_GIT,
+    )
+}
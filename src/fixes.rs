@@ -0,0 +1,120 @@
+//! Safety around `kiwi doctor --fix`: fixes that run commands show them
+//! first, and everything that ran is logged to `~/.kiwi/logs/doctor.log`.
+//! The Homebrew installer is downloaded and checked against its SHA-256
+//! before it runs, rather than piped from curl into bash.
+
+use crate::{KiwiError, Result};
+use sha2::{Digest, Sha256};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+pub const HOMEBREW_INSTALLER_URL: &str = "https://raw.githubusercontent.com/Homebrew/install/HEAD/install.sh";
+
+/// Quotes `args` the way a shell would need them, so what's shown can be
+/// pasted and run as is.
+pub fn command_line(program: &str, args: &[&str]) -> String {
+    std::iter::once(program)
+        .chain(args.iter().copied())
+        .map(|arg| {
+            if !arg.is_empty() && arg.chars().all(|c| c.is_ascii_alphanumeric() || "-_./=:@+,".contains(c)) {
+                arg.to_string()
+            } else {
+                format!("'{}'", arg.replace('\'', r"'\''"))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Where fixes are logged.
+pub fn log_path() -> Result<PathBuf> {
+    let home = dirs::home_dir().ok_or_else(|| KiwiError::Config("Could not find home directory".to_string()))?;
+    Ok(home.join(".kiwi/logs/doctor.log"))
+}
+
+/// Where restores log the post-install commands they ran.
+pub fn hooks_log_path() -> Result<PathBuf> {
+    let home = dirs::home_dir().ok_or_else(|| KiwiError::Config("Could not find home directory".to_string()))?;
+    Ok(home.join(".kiwi/logs/hooks.log"))
+}
+
+/// Appends a timestamped line for `command` and what came of it to `path`.
+pub fn log(path: &Path, command: &str, outcome: &str) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{} {} -> {}", chrono::Local::now().to_rfc3339(), command, outcome)?;
+    Ok(())
+}
+
+pub fn sha256(contents: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(contents))
+}
+
+/// A downloaded Homebrew installer, ready to run once reviewed.
+pub struct Installer {
+    pub path: PathBuf,
+    pub sha256: String,
+    /// Whether it matched a pinned checksum; `false` when none was pinned
+    pub verified: bool,
+}
+
+/// Downloads the Homebrew installer to `dir`. With `expected`, a script
+/// whose SHA-256 differs is deleted and refused.
+pub async fn download_homebrew_installer(dir: &Path, expected: Option<&str>) -> Result<Installer> {
+    let response = reqwest::get(HOMEBREW_INSTALLER_URL).await?;
+    if !response.status().is_success() {
+        return Err(KiwiError::Homebrew(format!("Couldn't download the Homebrew installer: {}", response.status())));
+    }
+    let script = response.bytes().await?;
+    save_installer(dir, &script, expected)
+}
+
+fn save_installer(dir: &Path, script: &[u8], expected: Option<&str>) -> Result<Installer> {
+    let sha256 = sha256(script);
+    if let Some(expected) = expected {
+        if !expected.trim().eq_ignore_ascii_case(&sha256) {
+            return Err(KiwiError::Homebrew(format!(
+                "The Homebrew installer's SHA-256 is {}, not the pinned {}; it wasn't run",
+                sha256,
+                expected.trim()
+            )));
+        }
+    }
+    fs::create_dir_all(dir)?;
+    let path = dir.join("homebrew-install.sh");
+    fs::write(&path, script)?;
+    Ok(Installer { path, sha256, verified: expected.is_some() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_installer_checksum_and_log() {
+        let dir = std::env::temp_dir().join(format!("kiwi-fixes-test-{}", std::process::id()));
+        let script = b"#!/bin/bash\necho installing\n";
+        let sum = sha256(script);
+
+        assert!(save_installer(&dir, script, Some("0000")).is_err());
+        assert!(!dir.join("homebrew-install.sh").exists());
+        let installer = save_installer(&dir, script, Some(&sum.to_uppercase())).unwrap();
+        assert!(installer.verified);
+        assert!(!save_installer(&dir, script, None).unwrap().verified);
+
+        assert_eq!(command_line("git", &["config", "--global", "commit.gpgsign", "true"]), "git config --global commit.gpgsign true");
+        assert_eq!(command_line("/bin/bash", &["/Users/Jane Doe/it's.sh"]), "/bin/bash '/Users/Jane Doe/it'\\''s.sh'");
+
+        let log_file = dir.join("doctor.log");
+        log(&log_file, "xcode-select --install", "ok").unwrap();
+        log(&log_file, "/bin/bash install.sh", "declined").unwrap();
+        let logged = fs::read_to_string(&log_file).unwrap();
+        assert_eq!(logged.lines().count(), 2);
+        assert!(logged.lines().last().unwrap().ends_with("/bin/bash install.sh -> declined"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
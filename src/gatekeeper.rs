@@ -0,0 +1,113 @@
+//! Gatekeeper, which checks downloaded apps the first time they open.
+//! Apps installed from casks carry the quarantine attribute, so a restore
+//! can leave a Dock full of apps that each ask for approval, or that
+//! macOS refuses to open at all.
+
+use crate::homebrew::{CommandRunner, SystemRunner};
+use crate::{KiwiError, Result};
+use std::path::Path;
+
+const QUARANTINE: &str = "com.apple.quarantine";
+
+/// What happens the first time an app is opened.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Status {
+    /// Not quarantined; it opens without a prompt
+    Ready,
+    /// Quarantined but signed and notarized; macOS asks once
+    NeedsApproval,
+    /// Quarantined and rejected by Gatekeeper; it won't open until allowed
+    /// in System Settings or the quarantine is cleared
+    Blocked(String),
+}
+
+pub struct Gatekeeper {
+    runner: Box<dyn CommandRunner>,
+}
+
+impl Default for Gatekeeper {
+    fn default() -> Self {
+        Self { runner: Box::new(SystemRunner) }
+    }
+}
+
+impl Gatekeeper {
+    pub fn with_runner(runner: impl CommandRunner + 'static) -> Self {
+        Self { runner: Box::new(runner) }
+    }
+
+    pub fn is_quarantined(&self, app: &Path) -> bool {
+        let app = app.to_string_lossy();
+        self.runner.run("xattr", &["-p", QUARANTINE, &app]).is_ok_and(|output| output.success)
+    }
+
+    /// Gatekeeper only looks at quarantined apps, so the others are ready
+    /// whatever `spctl` would say about them.
+    pub fn status(&self, app: &Path) -> Status {
+        if !self.is_quarantined(app) {
+            return Status::Ready;
+        }
+        let path = app.to_string_lossy();
+        match self.runner.run("spctl", &["--assess", "--type", "execute", &path]) {
+            Ok(output) if output.success => Status::NeedsApproval,
+            // spctl prints `<path>: rejected` and, sometimes, why on the next line
+            Ok(output) => {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                let reason = stderr
+                    .lines()
+                    .map(|line| line.strip_prefix(path.as_ref()).map_or(line, |rest| rest.trim_start_matches(':')).trim())
+                    .filter(|line| !line.is_empty())
+                    .collect::<Vec<_>>()
+                    .join("; ");
+                Status::Blocked(if reason.is_empty() { "rejected".to_string() } else { reason })
+            }
+            Err(e) => Status::Blocked(e.to_string()),
+        }
+    }
+
+    /// Removes the quarantine attribute from the app and everything in it.
+    pub fn clear_quarantine(&self, app: &Path) -> Result<()> {
+        let path = app.to_string_lossy();
+        let output = self.runner.run("xattr", &["-dr", QUARANTINE, &path])?;
+        if !output.success {
+            return Err(KiwiError::Config(format!(
+                "Couldn't clear the quarantine on {}: {}",
+                app.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::homebrew::CommandOutput;
+
+    /// Quarantines every app but `Plain.app`; spctl accepts only `Signed.app`.
+    struct Apps;
+
+    impl CommandRunner for Apps {
+        fn run(&self, program: &str, args: &[&str]) -> std::io::Result<CommandOutput> {
+            let app = args.last().copied().unwrap_or_default();
+            let (success, stderr) = match program {
+                "xattr" => (!app.ends_with("Plain.app"), String::new()),
+                "spctl" if app.ends_with("Signed.app") => (true, String::new()),
+                _ => (false, format!("{}: rejected\nsource=Unnotarized Developer ID\n", app)),
+            };
+            Ok(CommandOutput { success, stdout: Vec::new(), stderr: stderr.into_bytes() })
+        }
+    }
+
+    #[test]
+    fn test_status() {
+        let gatekeeper = Gatekeeper::with_runner(Apps);
+        assert_eq!(gatekeeper.status(Path::new("/Applications/Plain.app")), Status::Ready);
+        assert_eq!(gatekeeper.status(Path::new("/Applications/Signed.app")), Status::NeedsApproval);
+        assert_eq!(
+            gatekeeper.status(Path::new("/Applications/Odd.app")),
+            Status::Blocked("rejected; source=Unnotarized Developer ID".to_string())
+        );
+    }
+}
@@ -0,0 +1,175 @@
+//! Housekeeping for `kiwi gc`: removes what kiwi leaves behind over time,
+//! namely expired backup runs, symlinks in the dotfiles dir that no tracked
+//! dotfile owns, stale manifest and hash cache entries, and old blobs.
+
+use crate::backups::Backups;
+use crate::blobs::BlobStore;
+use crate::hashing::HashCache;
+use crate::reporter::Reporter;
+use crate::{Dotfiles, Homebrew, Result};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Backups and blobs younger than this are kept
+    pub retention: Duration,
+    /// Report what would go without deleting anything
+    pub dry_run: bool,
+}
+
+/// What was (or, in a dry run, would be) removed.
+#[derive(Debug, Default)]
+pub struct Report {
+    pub backups: Vec<PathBuf>,
+    pub orphaned_links: Vec<PathBuf>,
+    pub stale_packages: Vec<String>,
+    pub stale_hashes: usize,
+    /// Stored file versions no tracked file uses anymore
+    pub blobs: usize,
+    /// Bytes freed on disk
+    pub reclaimed: u64,
+}
+
+impl Report {
+    pub fn is_empty(&self) -> bool {
+        self.backups.is_empty()
+            && self.orphaned_links.is_empty()
+            && self.stale_packages.is_empty()
+            && self.stale_hashes == 0
+            && self.blobs == 0
+    }
+}
+
+pub fn collect(dotfiles_dir: &Path, dotfiles: &Dotfiles, homebrew: &mut Homebrew, options: &Options, reporter: &dyn Reporter) -> Result<Report> {
+    let mut report = Report::default();
+    let cutoff = SystemTime::now() - options.retention;
+    let tracked = dotfiles.list()?;
+
+    reporter.progress("Looking for expired backups and orphaned links");
+    let owned: HashSet<PathBuf> = tracked.iter().map(|d| dotfiles_dir.join(d.entry_name())).collect();
+    for path in walk(dotfiles_dir)? {
+        let metadata = path.symlink_metadata()?;
+        if metadata.file_type().is_symlink() && !owned.contains(&path) {
+            remove(&path, metadata.len(), options.dry_run, &mut report.reclaimed)?;
+            report.orphaned_links.push(path);
+        }
+    }
+    // Only runs in the managed backups area; a `.backup` file anywhere else
+    // may be the user's own
+    for run in Backups::new(dotfiles_dir).runs()? {
+        if fs::metadata(&run)?.modified().is_ok_and(|modified| modified < cutoff) {
+            report.reclaimed += walk(&run)?.iter().filter_map(|f| f.symlink_metadata().ok()).map(|m| m.len()).sum::<u64>();
+            if !options.dry_run {
+                fs::remove_dir_all(&run)?;
+            }
+            report.backups.push(run);
+        }
+    }
+
+    reporter.progress("Checking the package manifest");
+    match homebrew.stale() {
+        Ok(stale) => {
+            if !stale.is_empty() && !options.dry_run {
+                let manifest = dotfiles_dir.join("packages.json");
+                let before = fs::metadata(&manifest).map_or(0, |m| m.len());
+                homebrew.forget(&stale)?;
+                let after = fs::metadata(&manifest).map_or(0, |m| m.len());
+                report.reclaimed += before.saturating_sub(after);
+            }
+            report.stale_packages = stale;
+        }
+        Err(e) => reporter.warning(&format!("Skipping the package manifest: {}", e)),
+    }
+
+    reporter.progress("Pruning old file versions");
+    let sources: Vec<PathBuf> = tracked.iter().map(|d| dotfiles.source_for(d)).collect();
+    let keep: HashSet<String> = dotfiles.hashes(&tracked)?.into_values().collect();
+    let mut cache = HashCache::load(dotfiles_dir.join("hash-cache.json"));
+    report.stale_hashes = cache.retain(&sources);
+    if !options.dry_run {
+        cache.save()?;
+    }
+    let (blobs, size) = BlobStore::new(dotfiles_dir.join("blobs")).prune(&keep, cutoff, options.dry_run)?;
+    report.blobs = blobs;
+    report.reclaimed += size;
+
+    Ok(report)
+}
+
+fn remove(path: &Path, size: u64, dry_run: bool, reclaimed: &mut u64) -> Result<()> {
+    if !dry_run {
+        fs::remove_file(path)?;
+    }
+    *reclaimed += size;
+    Ok(())
+}
+
+/// Every entry under `dir`, without following symlinks or entering the blob
+/// store or backups area.
+fn walk(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut found = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if entry.file_type()?.is_dir() {
+                if entry.file_name() != "blobs" && entry.file_name() != "backups" {
+                    pending.push(path);
+                }
+            } else {
+                found.push(path);
+            }
+        }
+    }
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reporter::Silent;
+
+    #[test]
+    fn test_collect_removes_orphans_and_old_blobs() {
+        let dir = std::env::temp_dir().join(format!("kiwi-gc-test-{}", std::process::id()));
+        let dotfiles_dir = dir.join("dotfiles");
+        fs::create_dir_all(&dotfiles_dir).unwrap();
+        let dotfiles = Dotfiles::new(dotfiles_dir.clone(), dotfiles_dir.join("dotfiles.json"));
+        let mut homebrew = Homebrew::new(dotfiles_dir.join("packages.json"));
+
+        let zshrc = dir.join(".zshrc");
+        fs::write(&zshrc, "export EDITOR=vim\n").unwrap();
+        dotfiles.add(&zshrc, None, false).unwrap();
+        std::os::unix::fs::symlink(dir.join("gone"), dotfiles_dir.join("old-alias")).unwrap();
+        fs::write(dotfiles_dir.join("packages.backup"), "{}").unwrap();
+        let run = dotfiles_dir.join("backups/20260101-090000");
+        fs::create_dir_all(&run).unwrap();
+        fs::write(run.join("zshrc"), "# old\n").unwrap();
+
+        let store = BlobStore::new(dotfiles_dir.join("blobs"));
+        let current = store.put(b"export EDITOR=vim\n").unwrap();
+        let old = store.put(b"export EDITOR=nano\n").unwrap();
+
+        let options = Options { retention: Duration::ZERO, dry_run: true };
+        let report = collect(&dotfiles_dir, &dotfiles, &mut homebrew, &options, &Silent::default()).unwrap();
+        assert_eq!(report.orphaned_links, vec![dotfiles_dir.join("old-alias")]);
+        assert_eq!(report.backups, vec![run.clone()]);
+        assert_eq!(report.blobs, 1);
+        assert!(store.contains(&old), "dry run deletes nothing");
+
+        let options = Options { dry_run: false, ..options };
+        collect(&dotfiles_dir, &dotfiles, &mut homebrew, &options, &Silent::default()).unwrap();
+        assert!(dotfiles_dir.join("old-alias").symlink_metadata().is_err());
+        assert!(dotfiles_dir.join(".zshrc").symlink_metadata().is_ok());
+        assert!(!run.exists() && dotfiles_dir.join("packages.backup").exists());
+        assert!(store.contains(&current) && !store.contains(&old));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,319 @@
+//! GPG setup for signed commits. Only `gpg.conf` and `gpg-agent.conf` are
+//! ever tracked; private keys stay in each Mac's keyring (see
+//! `sensitive`). `kiwi doctor` reports what would make signing behave
+//! differently from one Mac to the next, and `--fix` repairs what it can.
+
+use crate::homebrew::{CommandRunner, SystemRunner};
+use crate::{fixes, Dotfiles, Homebrew, KiwiError, Result};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Config files worth syncing, relative to `~/.gnupg`.
+pub const CONFIG_FILES: &[&str] = &["gpg.conf", "gpg-agent.conf"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Issue {
+    /// git signs commits but there's no gpg to do it
+    NotInstalled,
+    /// `~/.gnupg` config files that exist but aren't tracked
+    Untracked(Vec<PathBuf>),
+    PinentryMissing,
+    /// gpg-agent asks for passphrases with something other than this Mac's
+    /// pinentry-mac, so GUI git clients can't sign
+    PinentryNotSet,
+    /// `gpg.program` names a gpg that isn't here, e.g. synced from a Mac
+    /// with the other brew prefix
+    ProgramMissing(String),
+    /// `user.signingkey` is set but the key isn't in this Mac's keyring
+    KeyMissing(String),
+    /// A signing key is set but commits aren't signed by default
+    SigningOff,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Issue::NotInstalled => write!(f, "git is set to sign commits but GPG is not installed"),
+            Issue::Untracked(paths) => {
+                let names: Vec<String> = paths.iter().map(|p| p.display().to_string()).collect();
+                write!(f, "GPG config isn't tracked: {}", names.join(", "))
+            }
+            Issue::PinentryMissing => write!(f, "pinentry-mac is not installed, so GUI apps can't ask for your GPG passphrase"),
+            Issue::PinentryNotSet => write!(f, "gpg-agent isn't set to use pinentry-mac"),
+            Issue::ProgramMissing(program) => write!(f, "git's gpg.program {} doesn't exist on this Mac", program),
+            Issue::KeyMissing(key) => write!(
+                f,
+                "Signing key {} isn't in this Mac's keyring; import it with `gpg --import` (kiwi never syncs private keys)",
+                key
+            ),
+            Issue::SigningOff => write!(f, "A signing key is set but commit.gpgsign is off"),
+        }
+    }
+}
+
+pub struct Gpg {
+    runner: Box<dyn CommandRunner>,
+    home: PathBuf,
+    /// Brew's prefix here, where gpg and pinentry-mac are installed
+    prefix: PathBuf,
+}
+
+impl Gpg {
+    pub fn new(home: PathBuf, prefix: PathBuf) -> Self {
+        Self { runner: Box::new(SystemRunner), home, prefix }
+    }
+
+    pub fn with_runner(mut self, runner: impl CommandRunner + 'static) -> Self {
+        self.runner = Box::new(runner);
+        self
+    }
+
+    fn pinentry(&self) -> PathBuf {
+        self.prefix.join("bin/pinentry-mac")
+    }
+
+    fn agent_conf(&self) -> PathBuf {
+        self.home.join(".gnupg/gpg-agent.conf")
+    }
+
+    /// Everything that would make signing differ from another Mac's.
+    pub fn check(&self, dotfiles: &Dotfiles) -> Result<Vec<Issue>> {
+        let mut issues = Vec::new();
+        let key = self.git_config("user.signingkey");
+        let signing = self.git_config("commit.gpgsign").is_some_and(|value| value == "true");
+        if !self.succeeds("gpg", &["--version"]) {
+            if key.is_some() || signing {
+                issues.push(Issue::NotInstalled);
+            }
+            return Ok(issues);
+        }
+
+        let tracked: Vec<PathBuf> = dotfiles.list()?.into_iter().map(|d| d.path).collect();
+        let untracked: Vec<PathBuf> = CONFIG_FILES
+            .iter()
+            .map(|name| self.home.join(".gnupg").join(name))
+            .filter(|path| path.exists())
+            .filter(|path| !path.canonicalize().is_ok_and(|canonical| tracked.contains(&canonical)))
+            .collect();
+        if !untracked.is_empty() {
+            issues.push(Issue::Untracked(untracked));
+        }
+
+        if !self.pinentry().exists() {
+            issues.push(Issue::PinentryMissing);
+        } else if pinentry_program(&fs::read_to_string(self.agent_conf()).unwrap_or_default()) != Some(self.pinentry()) {
+            issues.push(Issue::PinentryNotSet);
+        }
+
+        if let Some(program) = self.git_config("gpg.program") {
+            if program.starts_with('/') && !Path::new(&program).exists() {
+                issues.push(Issue::ProgramMissing(program));
+            }
+        }
+        if let Some(key) = key {
+            if !self.has_secret_key(&key) {
+                issues.push(Issue::KeyMissing(key));
+            } else if !signing {
+                issues.push(Issue::SigningOff);
+            }
+        }
+        Ok(issues)
+    }
+
+    /// The commands `fix` runs for `issue`, for showing before it does.
+    pub fn commands(&self, issue: &Issue) -> Vec<String> {
+        let gpg = self.prefix.join("bin/gpg");
+        let (program, args): (&str, Vec<&str>) = match issue {
+            Issue::NotInstalled => ("brew", vec!["install", "gnupg"]),
+            Issue::PinentryMissing => ("brew", vec!["install", "pinentry-mac"]),
+            Issue::ProgramMissing(_) => ("git", vec!["config", "--global", "gpg.program", gpg.to_str().unwrap_or_default()]),
+            Issue::SigningOff => ("git", vec!["config", "--global", "commit.gpgsign", "true"]),
+            Issue::PinentryNotSet | Issue::Untracked(_) | Issue::KeyMissing(_) => ("", Vec::new()),
+        };
+        let mut commands = Vec::new();
+        if !program.is_empty() {
+            commands.push(fixes::command_line(program, &args));
+        }
+        if matches!(issue, Issue::PinentryMissing | Issue::PinentryNotSet) {
+            commands.push(fixes::command_line("gpgconf", &["--kill", "gpg-agent"]));
+        }
+        commands
+    }
+
+    /// Repairs `issue`, installing missing tools through `homebrew` so
+    /// they're tracked too. Returns what was done, or `None` when it's not
+    /// something kiwi can fix.
+    pub fn fix(&self, issue: &Issue, dotfiles: &Dotfiles, homebrew: &mut Homebrew) -> Result<Option<String>> {
+        let done = match issue {
+            Issue::NotInstalled => {
+                homebrew.install("gnupg", Some("Signs git commits".to_string()))?;
+                "Installed gnupg".to_string()
+            }
+            Issue::Untracked(paths) => {
+                for path in paths {
+                    dotfiles.add(path, None, false)?;
+                }
+                format!("Tracking {} GPG config file(s)", paths.len())
+            }
+            Issue::PinentryMissing => {
+                homebrew.install("pinentry-mac", Some("Passphrase prompts for GPG".to_string()))?;
+                self.set_pinentry()?;
+                "Installed pinentry-mac and pointed gpg-agent at it".to_string()
+            }
+            Issue::PinentryNotSet => {
+                self.set_pinentry()?;
+                format!("gpg-agent now uses {}", self.pinentry().display())
+            }
+            Issue::ProgramMissing(_) => {
+                let gpg = self.prefix.join("bin/gpg");
+                self.set_git_config("gpg.program", &gpg.to_string_lossy())?;
+                format!("gpg.program now points at {}", gpg.display())
+            }
+            Issue::SigningOff => {
+                self.set_git_config("commit.gpgsign", "true")?;
+                "Commits are now signed by default".to_string()
+            }
+            Issue::KeyMissing(_) => return Ok(None),
+        };
+        Ok(Some(done))
+    }
+
+    /// Points gpg-agent at this Mac's pinentry-mac and restarts it.
+    fn set_pinentry(&self) -> Result<()> {
+        let path = self.agent_conf();
+        let current = fs::read_to_string(&path).unwrap_or_default();
+        let mut lines: Vec<String> = current
+            .lines()
+            .filter(|line| !line.trim_start().starts_with("pinentry-program"))
+            .map(str::to_string)
+            .collect();
+        lines.push(format!("pinentry-program {}", self.pinentry().display()));
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, lines.join("\n") + "\n")?;
+        // The agent reads its config at start; the next gpg call starts a new one
+        let _ = self.runner.run("gpgconf", &["--kill", "gpg-agent"]);
+        Ok(())
+    }
+
+    /// Whether a secret key whose fingerprint ends with `key`, or whose user
+    /// ID mentions it, is in the keyring.
+    fn has_secret_key(&self, key: &str) -> bool {
+        let Ok(output) = self.runner.run("gpg", &["--list-secret-keys", "--with-colons"]) else {
+            return false;
+        };
+        let wanted = key.trim_start_matches("0x").to_uppercase();
+        String::from_utf8_lossy(&output.stdout).lines().any(|line| {
+            let fields: Vec<&str> = line.split(':').collect();
+            match fields.first() {
+                Some(&"fpr") => fields.get(9).is_some_and(|fpr| fpr.ends_with(&wanted)),
+                Some(&"uid") => fields.get(9).is_some_and(|uid| uid.contains(key)),
+                _ => false,
+            }
+        })
+    }
+
+    fn git_config(&self, key: &str) -> Option<String> {
+        let output = self.runner.run("git", &["config", "--global", "--get", key]).ok()?;
+        let value = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        (output.success && !value.is_empty()).then_some(value)
+    }
+
+    fn set_git_config(&self, key: &str, value: &str) -> Result<()> {
+        if !self.succeeds("git", &["config", "--global", key, value]) {
+            return Err(KiwiError::Config(format!("Couldn't set git's {}", key)));
+        }
+        Ok(())
+    }
+
+    fn succeeds(&self, program: &str, args: &[&str]) -> bool {
+        self.runner.run(program, args).is_ok_and(|output| output.success)
+    }
+}
+
+/// The `pinentry-program` an agent config sets.
+fn pinentry_program(conf: &str) -> Option<PathBuf> {
+    conf.lines()
+        .rev()
+        .find_map(|line| line.trim().strip_prefix("pinentry-program"))
+        .map(|rest| PathBuf::from(rest.trim()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::homebrew::CommandOutput;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    /// A Mac with gpg, one secret key, and whatever global git config the
+    /// test sets.
+    #[derive(Clone, Default)]
+    struct Machine {
+        git: Arc<Mutex<HashMap<String, String>>>,
+    }
+
+    impl CommandRunner for Machine {
+        fn run(&self, program: &str, args: &[&str]) -> std::io::Result<CommandOutput> {
+            let mut git = self.git.lock().unwrap();
+            let (success, stdout) = match (program, args) {
+                ("git", ["config", "--global", "--get", key]) => match git.get(*key) {
+                    Some(value) => (true, value.clone()),
+                    None => (false, String::new()),
+                },
+                ("git", ["config", "--global", key, value]) => {
+                    git.insert(key.to_string(), value.to_string());
+                    (true, String::new())
+                }
+                ("gpg", ["--list-secret-keys", ..]) => (
+                    true,
+                    "sec:u:255:22:3AA5C34371567BD2:1700000000:::u:::scESC:::+:::ed25519:::0:\n\
+                     fpr:::::::::0123456789ABCDEF01233AA5C34371567BD2:\n\
+                     uid:u::::1700000000::HASH::Jane Doe <jane@example.com>::::::::::0:\n"
+                        .to_string(),
+                ),
+                _ => (true, String::new()),
+            };
+            Ok(CommandOutput { success, stdout: stdout.into_bytes(), stderr: Vec::new() })
+        }
+    }
+
+    #[test]
+    fn test_check_and_fix_signing() {
+        let dir = std::env::temp_dir().join(format!("kiwi-gpg-test-{}", std::process::id()));
+        let home = dir.join("home");
+        let prefix = dir.join("brew");
+        fs::create_dir_all(home.join(".gnupg")).unwrap();
+        fs::create_dir_all(prefix.join("bin")).unwrap();
+        fs::write(prefix.join("bin/pinentry-mac"), "").unwrap();
+        fs::write(prefix.join("bin/gpg"), "").unwrap();
+        fs::write(home.join(".gnupg/gpg-agent.conf"), "default-cache-ttl 600\npinentry-program /usr/local/bin/pinentry-mac\n").unwrap();
+        let dotfiles = Dotfiles::new(dir.join("dotfiles"), dir.join("dotfiles/dotfiles.json"));
+        let mut homebrew = Homebrew::new(dir.join("dotfiles/packages.json"));
+        let machine = Machine::default();
+        machine.git.lock().unwrap().insert("user.signingkey".to_string(), "3AA5C34371567BD2".to_string());
+        machine.git.lock().unwrap().insert("gpg.program".to_string(), "/nonexistent/bin/gpg".to_string());
+        let gpg = Gpg::new(home.clone(), prefix.clone()).with_runner(machine.clone());
+
+        let issues = gpg.check(&dotfiles).unwrap();
+        assert_eq!(issues, vec![
+            Issue::Untracked(vec![home.join(".gnupg/gpg-agent.conf")]),
+            Issue::PinentryNotSet,
+            Issue::ProgramMissing("/nonexistent/bin/gpg".to_string()),
+            Issue::SigningOff,
+        ]);
+        for issue in &issues {
+            assert!(gpg.fix(issue, &dotfiles, &mut homebrew).unwrap().is_some());
+        }
+        assert_eq!(gpg.check(&dotfiles).unwrap(), vec![]);
+        let conf = fs::read_to_string(home.join(".gnupg/gpg-agent.conf")).unwrap();
+        assert_eq!(conf, format!("default-cache-ttl 600\npinentry-program {}\n", prefix.join("bin/pinentry-mac").display()));
+
+        machine.git.lock().unwrap().insert("user.signingkey".to_string(), "jane@example.org".to_string());
+        assert!(gpg.check(&dotfiles).unwrap().contains(&Issue::KeyMissing("jane@example.org".to_string())));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,129 @@
+use crate::{blobs, Result};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry {
+    size: u64,
+    mtime_ns: u64,
+    hash: String,
+}
+
+/// Remembers file hashes keyed by size and mtime, so unchanged files are
+/// never re-read. Misses are hashed in parallel.
+pub struct HashCache {
+    path: PathBuf,
+    entries: HashMap<PathBuf, Entry>,
+    dirty: bool,
+}
+
+impl HashCache {
+    /// Loads the cache from `path`, starting empty if it's missing or unreadable.
+    pub fn load(path: PathBuf) -> Self {
+        let entries = fs::read_to_string(&path)
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+        Self { path, entries, dirty: false }
+    }
+
+    /// Hashes every file, reusing cached hashes when size and mtime are
+    /// unchanged. Files that can't be read are left out of the result.
+    pub fn hash_all(&mut self, paths: &[PathBuf]) -> HashMap<PathBuf, String> {
+        let mut hashes = HashMap::new();
+        let mut misses = Vec::new();
+
+        for path in paths {
+            let Some((size, mtime_ns)) = stamp(path) else {
+                continue;
+            };
+            match self.entries.get(path) {
+                Some(entry) if entry.size == size && entry.mtime_ns == mtime_ns => {
+                    hashes.insert(path.clone(), entry.hash.clone());
+                }
+                _ => misses.push((path.clone(), size, mtime_ns)),
+            }
+        }
+
+        let hashed: Vec<(PathBuf, Entry)> = misses
+            .into_par_iter()
+            .filter_map(|(path, size, mtime_ns)| {
+                let data = fs::read(&path).ok()?;
+                Some((path, Entry { size, mtime_ns, hash: blobs::hash(&data) }))
+            })
+            .collect();
+
+        self.dirty |= !hashed.is_empty();
+        for (path, entry) in hashed {
+            hashes.insert(path.clone(), entry.hash.clone());
+            self.entries.insert(path, entry);
+        }
+        hashes
+    }
+
+    /// Forgets every file not in `keep`. Returns how many entries went.
+    pub fn retain(&mut self, keep: &[PathBuf]) -> usize {
+        let before = self.entries.len();
+        self.entries.retain(|path, _| keep.contains(path));
+        let removed = before - self.entries.len();
+        self.dirty |= removed > 0;
+        removed
+    }
+
+    /// Writes the cache back if anything was hashed since it was loaded.
+    pub fn save(&self) -> Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&self.path, serde_json::to_string(&self.entries)?)?;
+        Ok(())
+    }
+}
+
+fn stamp(path: &Path) -> Option<(u64, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+    if !metadata.is_file() {
+        return None;
+    }
+    let mtime = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some((metadata.len(), mtime.as_nanos() as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_reuses_and_refreshes_hashes() {
+        let dir = std::env::temp_dir().join(format!("kiwi-hashing-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("zshrc");
+        fs::write(&file, "export EDITOR=vim\n").unwrap();
+        let cache_path = dir.join("hash-cache.json");
+
+        let mut cache = HashCache::load(cache_path.clone());
+        let first = cache.hash_all(&[file.clone(), dir.join("missing")]);
+        assert_eq!(first.len(), 1);
+        assert_eq!(first[&file], blobs::hash(b"export EDITOR=vim\n"));
+        cache.save().unwrap();
+
+        // A reloaded cache answers without rehashing and has nothing to save
+        let mut cache = HashCache::load(cache_path.clone());
+        assert_eq!(cache.hash_all(std::slice::from_ref(&file)), first);
+        assert!(!cache.dirty);
+
+        // A size change invalidates the entry
+        fs::write(&file, "export EDITOR=nvim\n").unwrap();
+        let second = cache.hash_all(std::slice::from_ref(&file));
+        assert_eq!(second[&file], blobs::hash(b"export EDITOR=nvim\n"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,148 @@
+//! Local version history for tracked dotfiles, in `~/.kiwi/history`. Each
+//! time kiwi sees a file with contents it hasn't recorded, on sync or push or
+//! before overwriting it, those contents go to a blob store by hash, and the
+//! file's list of versions in `index.json` grows by one. Nothing here is
+//! synced; `kiwi history` and `kiwi rollback` work offline.
+
+use crate::blobs::{self, BlobStore};
+use crate::{KiwiError, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Characters of a hash shown for, and enough to pick, a version.
+pub const SHORT_HASH: usize = 8;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Version {
+    pub hash: String,
+    /// When the contents were first seen, in Unix seconds
+    pub recorded_at: u64,
+    pub size: u64,
+}
+
+impl Version {
+    pub fn short_hash(&self) -> &str {
+        &self.hash[..SHORT_HASH.min(self.hash.len())]
+    }
+}
+
+pub struct History {
+    dir: PathBuf,
+}
+
+impl History {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// `~/.kiwi/history`
+    pub fn default_path() -> Result<PathBuf> {
+        let home = dirs::home_dir().ok_or_else(|| KiwiError::Config("Could not find home directory".to_string()))?;
+        Ok(home.join(".kiwi/history"))
+    }
+
+    /// Records `contents` as the newest version of the file at `path`,
+    /// unless they already are. Returns whether a version was added.
+    pub fn record(&self, path: &Path, contents: &[u8]) -> Result<bool> {
+        let hash = blobs::hash(contents);
+        let mut index = self.load()?;
+        let versions = index.entry(path.to_path_buf()).or_default();
+        if versions.last().is_some_and(|last| last.hash == hash) {
+            return Ok(false);
+        }
+        self.blobs().put(contents)?;
+        versions.push(Version { hash, recorded_at: chrono::Utc::now().timestamp() as u64, size: contents.len() as u64 });
+        self.save(&index)?;
+        Ok(true)
+    }
+
+    /// Like [`Self::record`] for a file whose hash is already known, reading
+    /// `source` only when `hash` is new.
+    pub fn record_hashed(&self, path: &Path, hash: &str, source: &Path) -> Result<bool> {
+        if self.versions(path)?.last().is_some_and(|last| last.hash == hash) {
+            return Ok(false);
+        }
+        match fs::read(source) {
+            Ok(contents) => self.record(path, &contents),
+            Err(_) => Ok(false),
+        }
+    }
+
+    /// The file's versions, oldest first.
+    pub fn versions(&self, path: &Path) -> Result<Vec<Version>> {
+        Ok(self.load()?.remove(path).unwrap_or_default())
+    }
+
+    /// The version of `path` that `version` names: its number in
+    /// [`Self::versions`], counting from 1, or the start of its hash.
+    pub fn find(&self, path: &Path, version: &str) -> Result<Version> {
+        let versions = self.versions(path)?;
+        let found = match version.parse::<usize>() {
+            Ok(number) if version.len() < SHORT_HASH => number.checked_sub(1).and_then(|i| versions.get(i)).cloned(),
+            _ => {
+                let matching: Vec<&Version> = versions.iter().filter(|v| v.hash.starts_with(version)).collect();
+                match matching.as_slice() {
+                    [] => None,
+                    [only] => Some((*only).clone()),
+                    // The same contents can come back; any of them will do
+                    [first, rest @ ..] if rest.iter().all(|v| v.hash == first.hash) => Some((*first).clone()),
+                    _ => return Err(KiwiError::ValidationError(format!("{} matches more than one version; give more of the hash", version))),
+                }
+            }
+        };
+        found.ok_or_else(|| KiwiError::ValidationError(format!("{} has no version {}; see `kiwi history {}`", path.display(), version, path.display())))
+    }
+
+    pub fn contents(&self, version: &Version) -> Result<Vec<u8>> {
+        self.blobs().get(&version.hash)
+    }
+
+    fn blobs(&self) -> BlobStore {
+        BlobStore::new(self.dir.join("blobs"))
+    }
+
+    fn load(&self) -> Result<BTreeMap<PathBuf, Vec<Version>>> {
+        match fs::read_to_string(self.dir.join("index.json")) {
+            Ok(contents) => Ok(serde_json::from_str(&contents)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn save(&self, index: &BTreeMap<PathBuf, Vec<Version>>) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        fs::write(self.dir.join("index.json"), serde_json::to_string_pretty(index)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_versions_are_recorded_once_and_found_by_number_or_hash() {
+        let dir = std::env::temp_dir().join(format!("kiwi-history-test-{}", std::process::id()));
+        let history = History::new(dir.join("history"));
+        let zshrc = Path::new("/Users/jd/.zshrc");
+
+        assert!(history.record(zshrc, b"export A=1\n").unwrap());
+        assert!(!history.record(zshrc, b"export A=1\n").unwrap());
+        assert!(history.record(zshrc, b"export A=2\n").unwrap());
+        assert!(history.record(zshrc, b"export A=1\n").unwrap());
+        let versions = history.versions(zshrc).unwrap();
+        assert_eq!(versions.len(), 3);
+        assert_eq!(versions[0].hash, versions[2].hash);
+
+        let second = history.find(zshrc, "2").unwrap();
+        assert_eq!(history.contents(&second).unwrap(), b"export A=2\n");
+        assert_eq!(history.find(zshrc, second.short_hash()).unwrap(), second);
+        assert_eq!(history.find(zshrc, versions[0].short_hash()).unwrap().hash, versions[0].hash);
+        assert!(history.find(zshrc, "4").is_err());
+        assert!(history.find(Path::new("/Users/jd/.vimrc"), "1").is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
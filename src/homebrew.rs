@@ -1,8 +1,9 @@
 use std::process::Command;
-use crate::{Result, KiwiError};
+use crate::{sizes, Result, KiwiError};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
-use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::collections::{BTreeMap, HashMap};
+use std::cell::OnceCell;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -20,11 +21,239 @@ pub struct Package {
     pub size: Option<u64>,
     #[serde(default)]
     pub is_cask: bool,
+    #[serde(default)]
+    pub note: Option<String>,
+    #[serde(default)]
+    pub held: bool,
+    /// An Intel-only cask that needs Rosetta 2 on Apple Silicon
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub requires_rosetta: bool,
+    /// Commands a restore runs after installing the package
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub post_install: Vec<String>,
+}
+
+/// Where brew lives and how it's configured, recorded so a restore on the
+/// other architecture can fix up paths.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BrewEnvironment {
+    /// `/opt/homebrew` on Apple Silicon, `/usr/local` on Intel
+    pub prefix: String,
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+}
+
+/// HOMEBREW_* variables that `brew shellenv` derives from the prefix.
+const SHELLENV_VARS: &[&str] = &["HOMEBREW_PREFIX", "HOMEBREW_CELLAR", "HOMEBREW_REPOSITORY"];
+/// What `brew info` says about a name no formula or cask has.
+const UNKNOWN_PACKAGE: &[&str] = &["No available formula", "No available cask", "No formulae or casks found"];
+
+impl BrewEnvironment {
+    /// Keeps the HOMEBREW_* variables from `vars`, leaving out the ones
+    /// `brew shellenv` sets and anything that looks like a credential.
+    pub fn capture(prefix: String, vars: impl IntoIterator<Item = (String, String)>) -> Self {
+        let env = vars
+            .into_iter()
+            .filter(|(name, _)| name.starts_with("HOMEBREW_") && !SHELLENV_VARS.contains(&name.as_str()))
+            .filter(|(name, _)| !["TOKEN", "PASSWORD", "SECRET"].iter().any(|word| name.contains(word)))
+            .collect();
+        Self { prefix, env }
+    }
+
+    /// The recorded variables with paths under the recorded prefix moved to `prefix`.
+    pub fn env_for(&self, prefix: &str) -> BTreeMap<String, String> {
+        self.env
+            .iter()
+            .map(|(name, value)| {
+                let value = match value.strip_prefix(&self.prefix) {
+                    Some(rest) if rest.is_empty() || rest.starts_with('/') => format!("{}{}", prefix, rest),
+                    _ => value.clone(),
+                };
+                (name.clone(), value)
+            })
+            .collect()
+    }
+}
+
+/// Where brew installs by default on this machine's architecture.
+pub fn default_prefix() -> &'static str {
+    match std::env::consts::ARCH {
+        "aarch64" => "/opt/homebrew",
+        _ => "/usr/local",
+    }
+}
+
+/// Where brew's executable is: under `configured_prefix` when one is set
+/// (for installs elsewhere), else under `$HOMEBREW_PREFIX`, on the `PATH`,
+/// or in the standard prefixes, this architecture's first. `None` when
+/// brew isn't installed, or not under the configured prefix.
+pub fn resolve_brew(configured_prefix: Option<&str>) -> Option<PathBuf> {
+    if let Some(prefix) = configured_prefix {
+        let brew = Path::new(prefix).join("bin/brew");
+        return brew.is_file().then_some(brew);
+    }
+    let from_env = std::env::var("HOMEBREW_PREFIX").ok().map(|prefix| Path::new(&prefix).join("bin/brew"));
+    let on_path = std::env::var_os("PATH")
+        .map(|path| std::env::split_paths(&path).map(|dir| dir.join("brew")).collect::<Vec<_>>())
+        .unwrap_or_default();
+    let standard = [default_prefix(), "/opt/homebrew", "/usr/local"].map(|prefix| Path::new(prefix).join("bin/brew"));
+    from_env.into_iter().chain(on_path).chain(standard).find(|brew| brew.is_file())
+}
+
+/// Brew's view of a package, normalized across formulae and casks.
+struct BrewInfo {
+    description: Option<String>,
+    homepage: Option<String>,
+    version: Option<String>,
+    dependencies: Vec<String>,
+    is_cask: bool,
+    requires_rosetta: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PackageInfo {
+    pub name: String,
+    pub description: Option<String>,
+    pub homepage: Option<String>,
+    pub version: Option<String>,
+    pub dependencies: Vec<String>,
+    pub size: Option<u64>,
+    pub is_cask: bool,
+    /// Whether the package is in kiwi's sync set
+    pub tracked: bool,
+    pub excluded: bool,
+    pub held: bool,
+    pub install_time: Option<u64>,
+    pub last_update: Option<u64>,
+    pub note: Option<String>,
+    pub post_install: Vec<String>,
+}
+
+fn dir_size(path: &std::path::Path) -> u64 {
+    let Ok(entries) = std::fs::read_dir(path) else {
+        return 0;
+    };
+    entries
+        .flatten()
+        .map(|entry| match entry.metadata() {
+            Ok(meta) if meta.is_dir() => dir_size(&entry.path()),
+            Ok(meta) => meta.len(),
+            Err(_) => 0,
+        })
+        .sum()
+}
+
+#[derive(Debug, Default)]
+pub struct Reconciliation {
+    /// Installed locally but not in the manifest
+    pub untracked: Vec<String>,
+    /// In the manifest but not installed locally
+    pub missing: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct RestoreSummary {
+    pub tapped: Vec<String>,
+    pub installed: Vec<String>,
+    pub failed: Vec<(String, String)>,
+}
+
+/// A formula or cask in a tap against its newest upstream release, as
+/// `brew livecheck` found it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TapBump {
+    pub name: String,
+    pub is_cask: bool,
+    /// Version in the tap
+    pub current: Option<String>,
+    /// Newest version upstream
+    pub latest: Option<String>,
+    pub outdated: bool,
+    /// Why livecheck couldn't tell, when it couldn't
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// What a finished command produced. Kept separate from `std::process::Output`
+/// so tests can build one without spawning anything.
+#[derive(Debug, Clone, Default)]
+pub struct CommandOutput {
+    pub success: bool,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+/// Runs external programs on behalf of `Homebrew`. Tests swap in a runner
+/// with canned output so brew doesn't need to be installed.
+pub trait CommandRunner: Send + Sync {
+    fn run(&self, program: &str, args: &[&str]) -> std::io::Result<CommandOutput>;
+
+    /// Runs with the terminal attached, for programs that prompt. Returns
+    /// whether it succeeded; test runners answer it like `run`.
+    fn run_interactive(&self, program: &str, args: &[&str]) -> std::io::Result<bool> {
+        self.run(program, args).map(|output| output.success)
+    }
+}
+
+/// Spawns the real program.
+pub struct SystemRunner;
+
+impl CommandRunner for SystemRunner {
+    fn run(&self, program: &str, args: &[&str]) -> std::io::Result<CommandOutput> {
+        let output = Command::new(program).args(args).output()?;
+        Ok(CommandOutput {
+            success: output.status.success(),
+            stdout: output.stdout,
+            stderr: output.stderr,
+        })
+    }
+
+    fn run_interactive(&self, program: &str, args: &[&str]) -> std::io::Result<bool> {
+        Ok(Command::new(program).args(args).status()?.success())
+    }
 }
 
 pub struct Homebrew {
     packages_file: PathBuf,
+    taps_file: PathBuf,
     cache: HashMap<String, Package>,
+    excludes: Vec<String>,
+    prefix: OnceCell<Option<PathBuf>>,
+    /// The brew executable, from [`resolve_brew`]
+    brew: PathBuf,
+    /// `HOMEBREW_CACHE` for brew to download to and install from
+    download_cache: Option<PathBuf>,
+    runner: Box<dyn CommandRunner>,
+}
+
+/// Matches a package name against an exclusion pattern supporting `*` and `?` wildcards.
+pub fn matches_pattern(name: &str, pattern: &str) -> bool {
+    let name: Vec<char> = name.chars().collect();
+    let pattern: Vec<char> = pattern.chars().collect();
+    let (mut n, mut p) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            n += 1;
+            p += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, n));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            n = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+pub fn is_excluded(name: &str, patterns: &[String]) -> bool {
+    patterns.iter().any(|pattern| matches_pattern(name, pattern))
 }
 
 impl Homebrew {
@@ -38,10 +267,103 @@ impl Homebrew {
             HashMap::new()
         };
 
-        Self { packages_file, cache }
+        let taps_file = packages_file.with_file_name("taps.json");
+        Self {
+            packages_file,
+            taps_file,
+            cache,
+            excludes: Vec::new(),
+            prefix: OnceCell::new(),
+            brew: resolve_brew(None).unwrap_or_else(|| PathBuf::from("brew")),
+            download_cache: None,
+            runner: Box::new(SystemRunner),
+        }
+    }
+
+    /// Uses the brew under `prefix` instead of looking for one, for
+    /// installs outside the standard places. `None` keeps the lookup.
+    pub fn with_brew_prefix(mut self, prefix: Option<&str>) -> Self {
+        if let Some(prefix) = prefix {
+            self.brew = Path::new(prefix).join("bin/brew");
+        }
+        self
+    }
+
+    /// The brew executable kiwi runs.
+    pub fn brew_path(&self) -> &Path {
+        &self.brew
+    }
+
+    /// The first line of `brew --version`, which only works once brew can
+    /// find its own installation.
+    pub fn version(&self) -> Result<String> {
+        let output = self.brew(&["--version"])?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        match stdout.lines().next() {
+            Some(line) if output.success => Ok(line.trim().to_string()),
+            _ => Err(KiwiError::Homebrew(format!(
+                "`{} --version` failed: {}",
+                self.brew.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ))),
+        }
+    }
+
+    /// Whether brew is installed where kiwi looks for it.
+    pub fn is_brew_installed(&self) -> bool {
+        self.brew.is_file()
     }
 
-    pub fn install(&mut self, package: &str) -> Result<()> {
+    pub fn with_runner(mut self, runner: impl CommandRunner + 'static) -> Self {
+        self.runner = Box::new(runner);
+        self
+    }
+
+    /// Has brew download to and install from `dir` instead of its own cache,
+    /// without updating itself first, so it can work from a seed offline.
+    pub fn with_download_cache(mut self, dir: PathBuf) -> Self {
+        self.download_cache = Some(dir);
+        self
+    }
+
+    fn brew(&self, args: &[&str]) -> Result<CommandOutput> {
+        let brew = self.brew.to_string_lossy();
+        let Some(dir) = &self.download_cache else {
+            return Ok(self.runner.run(&brew, args)?);
+        };
+        let cache = format!("HOMEBREW_CACHE={}", dir.display());
+        let mut with_env = vec![cache.as_str(), "HOMEBREW_NO_AUTO_UPDATE=1", &brew];
+        with_env.extend_from_slice(args);
+        Ok(self.runner.run("env", &with_env)?)
+    }
+
+    /// Downloads `package` and its dependencies without installing them.
+    pub fn fetch(&self, package: &str, is_cask: bool) -> Result<()> {
+        let output = if is_cask {
+            self.brew(&["fetch", "--cask", package])?
+        } else {
+            self.brew(&["fetch", "--deps", package])?
+        };
+        if !output.success {
+            return Err(KiwiError::PackageError {
+                name: package.to_string(),
+                message: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Packages matching any of these patterns are never written to the manifest.
+    pub fn with_excludes(mut self, excludes: Vec<String>) -> Self {
+        self.excludes = excludes;
+        self
+    }
+
+    pub fn is_excluded(&self, name: &str) -> bool {
+        is_excluded(name, &self.excludes)
+    }
+
+    pub fn install(&mut self, package: &str, note: Option<String>) -> Result<()> {
         // Check if package is already installed
         if self.is_installed(package)? {
             return Err(KiwiError::PackageError {
@@ -52,27 +374,199 @@ impl Homebrew {
 
         // Check if it's a cask
         let is_cask = self.is_cask(package)?;
-        let install_cmd = if is_cask { "install --cask" } else { "install" };
+        self.brew_install(package, is_cask)?;
+
+        self.add_package(package, note)?;
+        Ok(())
+    }
 
-        let output = Command::new("brew")
-            .args(install_cmd.split_whitespace())
-            .arg(package)
-            .output()?;
+    fn brew_install(&self, package: &str, is_cask: bool) -> Result<()> {
+        let output = if is_cask {
+            self.brew(&["install", "--cask", package])?
+        } else {
+            self.brew(&["install", package])?
+        };
 
-        if !output.status.success() {
+        if !output.success {
             return Err(KiwiError::PackageError {
                 name: package.to_string(),
                 message: String::from_utf8_lossy(&output.stderr).to_string(),
             });
         }
+        Ok(())
+    }
+
+    pub fn list_taps(&self) -> Result<Vec<String>> {
+        let output = self.brew(&["tap"])?;
+
+        if !output.success {
+            return Err(KiwiError::Homebrew("Failed to list taps".to_string()));
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(|line| line.trim().to_string())
+            .filter(|line| !line.is_empty())
+            .collect())
+    }
 
-        self.add_package(package)?;
+    pub fn save_taps(&self, taps: &[String]) -> Result<()> {
+        let contents = serde_json::to_string_pretty(taps)?;
+        std::fs::write(&self.taps_file, contents)?;
         Ok(())
     }
 
+    pub fn load_taps(&self) -> Result<Vec<String>> {
+        if !self.taps_file.exists() {
+            return Ok(Vec::new());
+        }
+        let contents = std::fs::read_to_string(&self.taps_file)?;
+        Ok(serde_json::from_str(&contents)?)
+    }
+
+    /// Installs every tracked package that is missing locally, re-adding the
+    /// recorded taps first so third-party formulae resolve.
+    pub fn restore(&mut self) -> Result<RestoreSummary> {
+        let mut summary = self.restore_taps()?;
+        let missing = self.missing()?;
+        self.install_missing(&missing, &mut summary);
+        Ok(summary)
+    }
+
+    /// Re-adds the recorded taps that aren't tapped here.
+    pub fn restore_taps(&self) -> Result<RestoreSummary> {
+        self.add_taps(self.load_taps()?)
+    }
+
+    /// Adds the `taps` that aren't tapped here.
+    pub fn add_taps(&self, taps: Vec<String>) -> Result<RestoreSummary> {
+        let mut summary = RestoreSummary::default();
+        let current_taps = self.list_taps()?;
+        for tap in taps {
+            if current_taps.contains(&tap) {
+                continue;
+            }
+            let output = self.brew(&["tap", &tap])?;
+            if output.success {
+                summary.tapped.push(tap);
+            } else {
+                summary.failed.push((tap, String::from_utf8_lossy(&output.stderr).trim().to_string()));
+            }
+        }
+        Ok(summary)
+    }
+
+    /// Tracked packages that aren't excluded or installed, sorted by name.
+    pub fn missing(&self) -> Result<Vec<Package>> {
+        let mut missing = Vec::new();
+        for package in self.tracked() {
+            if !self.is_installed(&package.name)? {
+                missing.push(package);
+            }
+        }
+        Ok(missing)
+    }
+
+    /// Installs `packages`, adding what came of each to `summary`.
+    pub fn install_missing(&self, packages: &[Package], summary: &mut RestoreSummary) {
+        for package in packages {
+            match self.brew_install(&package.name, package.is_cask) {
+                Ok(()) => summary.installed.push(package.name.clone()),
+                Err(e) => summary.failed.push((package.name.clone(), e.to_string())),
+            }
+        }
+    }
+
+    /// Where `names` would be downloaded from, per `brew info`. Fails when
+    /// brew doesn't know one of them, like a formula from a tap not added yet.
+    pub fn downloads(&self, names: &[String]) -> Result<Vec<sizes::Download>> {
+        let mut args = vec!["info", "--json=v2"];
+        args.extend(names.iter().map(String::as_str));
+        let output = self.brew(&args)?;
+        if !output.success {
+            return Err(KiwiError::Homebrew(format!(
+                "Couldn't look up download sizes: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        sizes::downloads(&output.stdout, std::env::consts::ARCH)
+    }
+
+    /// Attaches a note explaining why a package is installed. An empty note clears it.
+    pub fn set_note(&mut self, package: &str, note: Option<String>) -> Result<()> {
+        self.ensure_tracked(package)?;
+
+        if let Some(p) = self.cache.get_mut(package) {
+            p.note = note.filter(|n| !n.trim().is_empty());
+        }
+        self.save_cache()
+    }
+
+    /// Adds `commands` to what a restore runs after installing `package`, or
+    /// with none, clears them.
+    pub fn set_post_install(&mut self, package: &str, commands: Vec<String>) -> Result<()> {
+        self.ensure_tracked(package)?;
+
+        if let Some(p) = self.cache.get_mut(package) {
+            if commands.is_empty() {
+                p.post_install.clear();
+            } else {
+                p.post_install.extend(commands.into_iter().filter(|c| !c.trim().is_empty()));
+            }
+        }
+        self.save_cache()
+    }
+
+    /// Holds a package at its current version. Formulae are pinned with brew;
+    /// casks can't be pinned, so kiwi skips them in its own update path.
+    pub fn hold(&mut self, package: &str) -> Result<()> {
+        self.set_held(package, true)
+    }
+
+    pub fn unhold(&mut self, package: &str) -> Result<()> {
+        self.set_held(package, false)
+    }
+
+    fn set_held(&mut self, package: &str, held: bool) -> Result<()> {
+        self.ensure_tracked(package)?;
+
+        if !self.is_cask(package)? {
+            let output = self.brew(&[if held { "pin" } else { "unpin" }, package])?;
+
+            if !output.success {
+                return Err(KiwiError::PackageError {
+                    name: package.to_string(),
+                    message: String::from_utf8_lossy(&output.stderr).to_string(),
+                });
+            }
+        }
+
+        if let Some(p) = self.cache.get_mut(package) {
+            p.held = held;
+        }
+        self.save_cache()
+    }
+
+    pub fn is_held(&self, package: &str) -> bool {
+        self.cache.get(package).is_some_and(|p| p.held)
+    }
+
+    /// Adopts an installed package into the cache so metadata can be attached to it.
+    fn ensure_tracked(&mut self, package: &str) -> Result<()> {
+        if self.cache.contains_key(package) {
+            return Ok(());
+        }
+        if !self.is_installed(package)? {
+            return Err(KiwiError::PackageError {
+                name: package.to_string(),
+                message: "Package is not installed".to_string(),
+            });
+        }
+        self.add_package(package, None)
+    }
+
     pub fn update(&mut self, package: Option<&str>) -> Result<()> {
-        let mut command = Command::new("brew");
-        command.arg("upgrade");
+        let mut args = vec!["upgrade".to_string()];
 
         if let Some(pkg) = package {
             if !self.is_installed(pkg)? {
@@ -81,12 +575,28 @@ impl Homebrew {
                     message: "Package is not installed".to_string(),
                 });
             }
-            command.arg(pkg);
+            if self.is_held(pkg) {
+                return Err(KiwiError::PackageError {
+                    name: pkg.to_string(),
+                    message: "Package is held; run `kiwi unhold` first".to_string(),
+                });
+            }
+            args.push(pkg.to_string());
+        } else if self.cache.values().any(|p| p.held) {
+            // brew upgrade would still touch held casks, so upgrade an explicit list
+            let upgradable: Vec<String> = self.outdated()?
+                .into_iter()
+                .filter(|name| !self.is_held(name))
+                .collect();
+            if upgradable.is_empty() {
+                return Ok(());
+            }
+            args.extend(upgradable);
         }
 
-        let output = command.output()?;
+        let output = self.brew(&args.iter().map(String::as_str).collect::<Vec<_>>())?;
 
-        if !output.status.success() {
+        if !output.success {
             return Err(KiwiError::PackageError {
                 name: package.unwrap_or("all").to_string(),
                 message: String::from_utf8_lossy(&output.stderr).to_string(),
@@ -104,7 +614,7 @@ impl Homebrew {
                 p.last_update = Some(now);
             }
         } else {
-            for p in self.cache.values_mut() {
+            for p in self.cache.values_mut().filter(|p| !p.held) {
                 p.last_update = Some(now);
             }
         }
@@ -113,13 +623,83 @@ impl Homebrew {
         Ok(())
     }
 
+    /// Upgrades outdated casks. With `greedy`, apps that update themselves
+    /// (`auto_updates true`) or use `version :latest` are upgraded too.
+    pub fn update_casks(&mut self, greedy: bool) -> Result<Vec<String>> {
+        let mut outdated_args = vec!["outdated", "--cask", "--quiet"];
+        if greedy {
+            outdated_args.push("--greedy");
+        }
+        let output = self.brew(&outdated_args)?;
+
+        if !output.success {
+            return Err(KiwiError::Homebrew("Failed to check outdated casks".to_string()));
+        }
+
+        let casks: Vec<String> = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(|line| line.trim().to_string())
+            .filter(|name| !name.is_empty() && !self.is_held(name))
+            .collect();
+
+        if casks.is_empty() {
+            return Ok(casks);
+        }
+
+        let mut args = vec!["upgrade", "--cask"];
+        if greedy {
+            args.push("--greedy");
+        }
+        args.extend(casks.iter().map(String::as_str));
+        let output = self.brew(&args)?;
+
+        if !output.success {
+            return Err(KiwiError::PackageError {
+                name: "casks".to_string(),
+                message: String::from_utf8_lossy(&output.stderr).to_string(),
+            });
+        }
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        for name in &casks {
+            if let Some(p) = self.cache.get_mut(name) {
+                p.last_update = Some(now);
+            }
+        }
+
+        self.refresh_cask_versions()?;
+        Ok(casks)
+    }
+
+    /// Records the currently installed cask versions in the cache.
+    fn refresh_cask_versions(&mut self) -> Result<()> {
+        let output = self.brew(&["list", "--cask", "--versions"])?;
+
+        if !output.success {
+            return Err(KiwiError::Homebrew("Failed to list installed casks".to_string()));
+        }
+
+        for line in String::from_utf8_lossy(&output.stdout).lines() {
+            let mut parts = line.split_whitespace();
+            let (Some(name), Some(version)) = (parts.next(), parts.last()) else {
+                continue;
+            };
+            if let Some(p) = self.cache.get_mut(name) {
+                p.version = Some(version.to_string());
+                p.is_cask = true;
+            }
+        }
+
+        self.save_cache()
+    }
+
     pub fn list_installed(&self) -> Result<Vec<Package>> {
-        let output = Command::new("brew")
-            .arg("list")
-            .arg("--versions")
-            .output()?;
+        let output = self.brew(&["list", "--versions"])?;
 
-        if !output.status.success() {
+        if !output.success {
             return Err(KiwiError::Homebrew("Failed to list installed packages".to_string()));
         }
 
@@ -144,19 +724,26 @@ impl Homebrew {
                 last_update: None,
                 size: None,
                 is_cask: false,
+                note: None,
+                held: false,
+                requires_rosetta: false,
+                post_install: Vec::new(),
             };
 
             // Get package info
             if let Ok(info) = self.get_package_info(&name) {
                 package.dependencies = info.dependencies;
-                package.size = info.size;
                 package.is_cask = info.is_cask;
+                package.requires_rosetta = info.requires_rosetta;
             }
 
             // Get cached metadata
             if let Some(cached) = self.cache.get(&name) {
                 package.install_time = cached.install_time;
                 package.last_update = cached.last_update;
+                package.note = cached.note.clone();
+                package.held = cached.held;
+                package.post_install = cached.post_install.clone();
             }
 
             packages.push(package);
@@ -165,61 +752,497 @@ impl Homebrew {
         Ok(packages)
     }
 
-    fn is_installed(&self, package: &str) -> Result<bool> {
-        let output = Command::new("brew")
-            .arg("list")
-            .arg(package)
-            .output()?;
+    pub fn outdated(&self) -> Result<Vec<String>> {
+        let output = self.brew(&["outdated", "--quiet"])?;
+
+        if !output.success {
+            return Err(KiwiError::Homebrew("Failed to check outdated packages".to_string()));
+        }
 
-        Ok(output.status.success())
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(|line| line.trim().to_string())
+            .filter(|line| !line.is_empty())
+            .collect())
     }
 
-    fn is_cask(&self, package: &str) -> Result<bool> {
-        let output = Command::new("brew")
-            .args(["info", "--cask", package])
-            .output()?;
+    /// Names of every installed formula and cask, without per-package metadata lookups.
+    pub fn installed_names(&self) -> Result<Vec<String>> {
+        let mut names = Vec::new();
+        for kind in ["--formula", "--cask"] {
+            let output = self.brew(&["list", kind, "-1"])?;
+            if !output.success {
+                return Err(KiwiError::Homebrew("Failed to list installed packages".to_string()));
+            }
+            names.extend(
+                String::from_utf8_lossy(&output.stdout)
+                    .lines()
+                    .map(|line| line.trim().to_string())
+                    .filter(|line| !line.is_empty()),
+            );
+        }
+        Ok(names)
+    }
 
-        Ok(output.status.success())
+    /// Packages recorded in the manifest, sorted by name.
+    pub fn tracked(&self) -> Vec<Package> {
+        let mut packages: Vec<Package> = self.cache
+            .values()
+            .filter(|p| !self.is_excluded(&p.name))
+            .cloned()
+            .collect();
+        packages.sort_by(|a, b| a.name.cmp(&b.name));
+        packages
     }
 
-    fn get_package_info(&self, package: &str) -> Result<Package> {
-        let output = Command::new("brew")
-            .args(["info", "--json=v2", package])
-            .output()?;
+    /// Diffs brew's installed set against the manifest.
+    pub fn reconcile(&self) -> Result<Reconciliation> {
+        let installed = self.installed_names()?;
+
+        let mut untracked: Vec<String> = installed
+            .iter()
+            .filter(|name| !self.cache.contains_key(*name) && !self.is_excluded(name))
+            .cloned()
+            .collect();
+        let mut missing: Vec<String> = self.cache
+            .keys()
+            .filter(|name| !installed.contains(name) && !self.is_excluded(name))
+            .cloned()
+            .collect();
+
+        untracked.sort();
+        missing.sort();
+        Ok(Reconciliation { untracked, missing })
+    }
+
+    /// Manifest entries that can never be restored: excluded packages, and
+    /// missing packages brew reports unknown. A package from a tap that
+    /// isn't tapped here, or a `brew info` that fails for another reason,
+    /// like being offline, keeps its entry.
+    pub fn stale(&self) -> Result<Vec<String>> {
+        let mut stale: Vec<String> = self.cache.keys().filter(|name| self.is_excluded(name)).cloned().collect();
+        let missing = self.reconcile()?.missing;
+        let taps = if missing.iter().any(|name| name.contains('/')) { self.list_taps()? } else { Vec::new() };
+        for name in missing {
+            if name.rsplit_once('/').is_some_and(|(tap, _)| !taps.iter().any(|t| t.eq_ignore_ascii_case(tap))) {
+                continue;
+            }
+            let output = self.brew(&["info", &name])?;
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            if !output.success && UNKNOWN_PACKAGE.iter().any(|message| stderr.contains(message)) {
+                stale.push(name);
+            }
+        }
+        stale.sort();
+        Ok(stale)
+    }
+
+    /// Drops manifest entries without touching what's installed.
+    pub fn forget(&mut self, packages: &[String]) -> Result<()> {
+        self.cache.retain(|name, _| !packages.contains(name));
+        self.save_cache()
+    }
+
+    /// Turns Homebrew's own analytics reporting on or off.
+    pub fn set_analytics(&self, enabled: bool) -> Result<()> {
+        let output = self.brew(&["analytics", if enabled { "on" } else { "off" }])?;
+        if !output.success {
+            return Err(KiwiError::Homebrew(String::from_utf8_lossy(&output.stderr).trim().to_string()));
+        }
+        Ok(())
+    }
+
+    /// Brings an already-installed package under kiwi management.
+    pub fn adopt(&mut self, package: &str) -> Result<()> {
+        self.add_package(package, None)
+    }
+
+    /// Adds manifest entries for packages that aren't tracked or excluded
+    /// yet, leaving existing entries alone. Returns how many were added.
+    pub fn track(&mut self, packages: &[Package]) -> Result<usize> {
+        let mut added = 0;
+        for package in packages {
+            if self.cache.contains_key(&package.name) || self.is_excluded(&package.name) {
+                continue;
+            }
+            self.cache.insert(package.name.clone(), package.clone());
+            added += 1;
+        }
+        if added > 0 {
+            self.save_cache()?;
+        }
+        Ok(added)
+    }
 
-        if !output.status.success() {
+    /// Uninstalls a package and stops tracking it.
+    pub fn uninstall(&mut self, package: &str) -> Result<()> {
+        let output = self.brew(&["uninstall", package])?;
+        if !output.success {
             return Err(KiwiError::PackageError {
                 name: package.to_string(),
-                message: "Failed to get package info".to_string(),
+                message: String::from_utf8_lossy(&output.stderr).to_string(),
             });
         }
 
+        if self.cache.remove(package).is_some() {
+            self.save_cache()?;
+        }
+        Ok(())
+    }
+
+    /// Takes a formula's executables off `PATH` without uninstalling it;
+    /// `brew link` puts them back.
+    pub fn unlink(&self, formula: &str) -> Result<()> {
+        let output = self.brew(&["unlink", formula])?;
+        if !output.success {
+            return Err(KiwiError::PackageError {
+                name: formula.to_string(),
+                message: String::from_utf8_lossy(&output.stderr).to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Formulae `brew autoremove` would uninstall: installed only as
+    /// dependencies, of formulae that no longer need them.
+    pub fn orphans(&self) -> Result<Vec<String>> {
+        let output = self.brew(&["autoremove", "--dry-run"])?;
+        if !output.success {
+            return Err(KiwiError::Homebrew(String::from_utf8_lossy(&output.stderr).trim().to_string()));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with("==>"))
+            .map(String::from)
+            .collect())
+    }
+
+    /// The installed formulae `formulae` depend on, directly or not.
+    pub fn installed_dependencies(&self, formulae: &[String]) -> Result<Vec<String>> {
+        if formulae.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut args = vec!["deps", "--installed"];
+        args.extend(formulae.iter().map(String::as_str));
+        let output = self.brew(&args)?;
+        if !output.success {
+            return Err(KiwiError::Homebrew(String::from_utf8_lossy(&output.stderr).trim().to_string()));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).split_whitespace().map(String::from).collect())
+    }
+
+    /// Marks formulae as installed on request, so `brew autoremove` leaves
+    /// them and what they depend on alone.
+    pub fn mark_requested(&self, formulae: &[String]) -> Result<()> {
+        if formulae.is_empty() {
+            return Ok(());
+        }
+        let mut args = vec!["tab", "--installed-on-request"];
+        args.extend(formulae.iter().map(String::as_str));
+        let output = self.brew(&args)?;
+        if !output.success {
+            return Err(KiwiError::Homebrew(String::from_utf8_lossy(&output.stderr).trim().to_string()));
+        }
+        Ok(())
+    }
+
+    /// Runs `brew autoremove`.
+    pub fn autoremove(&self) -> Result<()> {
+        let output = self.brew(&["autoremove"])?;
+        if !output.success {
+            return Err(KiwiError::Homebrew(String::from_utf8_lossy(&output.stderr).trim().to_string()));
+        }
+        Ok(())
+    }
+
+    /// Installs a tracked package that is missing locally.
+    pub fn reinstall(&mut self, package: &str) -> Result<()> {
+        let is_cask = self.cache.get(package).is_some_and(|p| p.is_cask);
+        self.brew_install(package, is_cask)?;
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        if let Some(p) = self.cache.get_mut(package) {
+            p.install_time = Some(now);
+            p.last_update = Some(now);
+        }
+        self.save_cache()
+    }
+
+    /// Compares every formula and cask in `tap` with its newest upstream
+    /// release, through `brew livecheck`. Packages livecheck skips or fails
+    /// on are kept, with the reason as their error.
+    pub fn livecheck_tap(&self, tap: &str) -> Result<Vec<TapBump>> {
+        if !self.list_taps()?.iter().any(|tapped| tapped == tap) {
+            return Err(KiwiError::Homebrew(format!("{} isn't tapped here; run `brew tap {}` first", tap, tap)));
+        }
+        // Exits non-zero when any check fails, with the rest still in the output
+        let output = self.brew(&["livecheck", "--tap", tap, "--json", "--full-name"])?;
+
         #[derive(Deserialize)]
-        struct BrewInfo {
-            dependencies: Vec<String>,
-            installed: Vec<InstalledInfo>,
+        struct Check {
+            formula: Option<String>,
+            cask: Option<String>,
+            version: Option<Versions>,
+            status: Option<String>,
+            #[serde(default)]
+            messages: Vec<String>,
         }
 
         #[derive(Deserialize)]
-        struct InstalledInfo {
-            size: Option<u64>,
+        struct Versions {
+            current: Option<String>,
+            latest: Option<String>,
+            #[serde(default)]
+            outdated: bool,
         }
 
-        let info: BrewInfo = serde_json::from_slice(&output.stdout)?;
+        let checks: Vec<Check> = serde_json::from_slice(&output.stdout).map_err(|_| {
+            KiwiError::Homebrew(format!("brew livecheck failed for {}: {}", tap, String::from_utf8_lossy(&output.stderr).trim()))
+        })?;
+        Ok(checks
+            .into_iter()
+            .filter_map(|check| {
+                let is_cask = check.cask.is_some();
+                let name = check.formula.or(check.cask)?;
+                let error = check.status.map(|status| match check.messages.first() {
+                    Some(message) => format!("{}: {}", status, message),
+                    None => status,
+                });
+                let versions = check.version.unwrap_or(Versions { current: None, latest: None, outdated: false });
+                Some(TapBump { name, is_cask, current: versions.current, latest: versions.latest, outdated: versions.outdated, error })
+            })
+            .collect())
+    }
+
+    /// Whether brew can find `package`, from the taps added here.
+    pub fn knows(&self, package: &Package) -> Result<bool> {
+        let kind = if package.is_cask { "--cask" } else { "--formula" };
+        Ok(self.brew(&["info", kind, &package.name])?.success)
+    }
+
+    fn is_installed(&self, package: &str) -> Result<bool> {
+        Ok(self.brew(&["list", package])?.success)
+    }
+
+    fn is_cask(&self, package: &str) -> Result<bool> {
+        Ok(self.brew(&["info", "--cask", package])?.success)
+    }
+
+    fn get_package_info(&self, package: &str) -> Result<Package> {
+        let info = self.brew_info(package)?;
 
         Ok(Package {
             name: package.to_string(),
-            version: None,
+            version: info.version,
             installed: true,
             dependencies: info.dependencies,
             install_time: None,
             last_update: None,
-            size: info.installed.first().and_then(|i| i.size),
-            is_cask: false,
+            size: None,
+            is_cask: info.is_cask,
+            note: None,
+            held: false,
+            requires_rosetta: info.requires_rosetta,
+            post_install: Vec::new(),
+        })
+    }
+
+    /// Queries `brew info --json=v2`, which wraps results in `formulae` and `casks`.
+    fn brew_info(&self, package: &str) -> Result<BrewInfo> {
+        let output = self.brew(&["info", "--json=v2", package])?;
+
+        if !output.success {
+            return Err(KiwiError::PackageError {
+                name: package.to_string(),
+                message: "Failed to get package info".to_string(),
+            });
+        }
+
+        #[derive(Deserialize)]
+        struct InfoV2 {
+            #[serde(default)]
+            formulae: Vec<FormulaInfo>,
+            #[serde(default)]
+            casks: Vec<CaskInfo>,
+        }
+
+        #[derive(Deserialize)]
+        struct FormulaInfo {
+            desc: Option<String>,
+            homepage: Option<String>,
+            #[serde(default)]
+            dependencies: Vec<String>,
+            #[serde(default)]
+            installed: Vec<InstalledInfo>,
+        }
+
+        #[derive(Deserialize)]
+        struct InstalledInfo {
+            version: String,
+        }
+
+        #[derive(Deserialize)]
+        struct CaskInfo {
+            desc: Option<String>,
+            homepage: Option<String>,
+            installed: Option<String>,
+            #[serde(default)]
+            depends_on: serde_json::Value,
+            caveats: Option<String>,
+        }
+
+        let info: InfoV2 = serde_json::from_slice(&output.stdout)?;
+
+        if let Some(formula) = info.formulae.into_iter().next() {
+            return Ok(BrewInfo {
+                description: formula.desc,
+                homepage: formula.homepage,
+                version: formula.installed.last().map(|i| i.version.clone()),
+                dependencies: formula.dependencies,
+                is_cask: false,
+                requires_rosetta: false,
+            });
+        }
+
+        if let Some(cask) = info.casks.into_iter().next() {
+            // Intel-only casks declare `depends_on arch: :x86_64`; others say so in a caveat
+            let intel_only = cask.depends_on["arch"].as_array().is_some_and(|arches| {
+                !arches.is_empty() && arches.iter().all(|arch| arch["type"] == "intel")
+            });
+            let requires_rosetta = intel_only || cask.caveats.is_some_and(|caveats| caveats.contains("Rosetta"));
+            return Ok(BrewInfo {
+                description: cask.desc,
+                homepage: cask.homepage,
+                version: cask.installed,
+                dependencies: Vec::new(),
+                is_cask: true,
+                requires_rosetta,
+            });
+        }
+
+        Err(KiwiError::PackageError {
+            name: package.to_string(),
+            message: "No formula or cask with this name".to_string(),
+        })
+    }
+
+    /// `brew --prefix`, asked once.
+    fn prefix(&self) -> Option<&PathBuf> {
+        self.prefix
+            .get_or_init(|| {
+                let output = self.brew(&["--prefix"]).ok()?;
+                output
+                    .success
+                    .then(|| PathBuf::from(String::from_utf8_lossy(&output.stdout).trim()))
+            })
+            .as_ref()
+    }
+
+    /// This machine's prefix and HOMEBREW_* variables.
+    pub fn environment(&self) -> BrewEnvironment {
+        let prefix = self
+            .prefix()
+            .map(|p| p.display().to_string())
+            .unwrap_or_else(|| default_prefix().to_string());
+        BrewEnvironment::capture(prefix, std::env::vars())
+    }
+
+    /// Records [`Self::environment`] in `brew.json` next to the manifest.
+    pub fn save_environment(&self) -> Result<()> {
+        let contents = serde_json::to_string_pretty(&self.environment())?;
+        std::fs::write(self.packages_file.with_file_name("brew.json"), contents)?;
+        Ok(())
+    }
+
+    /// The environment recorded by the last push or pull, if any.
+    pub fn load_environment(&self) -> Result<Option<BrewEnvironment>> {
+        let path = self.packages_file.with_file_name("brew.json");
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_str(&std::fs::read_to_string(path)?)?))
+    }
+
+    /// Size on disk of the package's Cellar (or Caskroom) directory. This
+    /// walks the whole directory, so only callers that show the size ask.
+    pub fn install_dir_size(&self, package: &str, is_cask: bool) -> Option<u64> {
+        let root = self.prefix()?.join(if is_cask { "Caskroom" } else { "Cellar" });
+        let dir = root.join(package);
+        dir.exists().then(|| dir_size(&dir))
+    }
+
+    /// Where the cask's `.app` bundles were installed, from the `app`
+    /// artifacts brew records and the `--appdir` in `HOMEBREW_CASK_OPTS`.
+    pub fn cask_apps(&self, cask: &str) -> Result<Vec<PathBuf>> {
+        let output = self.brew(&["info", "--json=v2", "--cask", cask])?;
+        if !output.success {
+            return Err(KiwiError::PackageError {
+                name: cask.to_string(),
+                message: "Failed to get cask info".to_string(),
+            });
+        }
+
+        #[derive(Deserialize)]
+        struct InfoV2 {
+            #[serde(default)]
+            casks: Vec<CaskArtifacts>,
+        }
+
+        #[derive(Deserialize)]
+        struct CaskArtifacts {
+            #[serde(default)]
+            artifacts: Vec<serde_json::Value>,
+        }
+
+        let appdir = std::env::var("HOMEBREW_CASK_OPTS")
+            .ok()
+            .and_then(|opts| opts.split_whitespace().find_map(|opt| opt.strip_prefix("--appdir=").map(PathBuf::from)))
+            .unwrap_or_else(|| PathBuf::from("/Applications"));
+
+        let info: InfoV2 = serde_json::from_slice(&output.stdout)?;
+        let apps = info
+            .casks
+            .into_iter()
+            .flat_map(|cask| cask.artifacts)
+            // An `app` stanza is the bundle's name, plus a `target` when brew renames it
+            .filter_map(|artifact| {
+                let stanza = artifact.get("app")?.as_array()?;
+                let target = stanza.iter().find_map(|part| part.get("target")?.as_str());
+                let name = target.or_else(|| stanza.iter().find_map(|part| part.as_str()))?;
+                Some(appdir.join(std::path::Path::new(name).file_name()?))
+            })
+            .collect();
+        Ok(apps)
+    }
+
+    /// Combines brew metadata with what kiwi knows about a package.
+    pub fn info(&self, package: &str) -> Result<PackageInfo> {
+        let brew = self.brew_info(package)?;
+        let cached = self.cache.get(package);
+
+        Ok(PackageInfo {
+            name: package.to_string(),
+            description: brew.description,
+            homepage: brew.homepage,
+            version: brew.version,
+            dependencies: brew.dependencies,
+            size: self.install_dir_size(package, brew.is_cask),
+            is_cask: brew.is_cask,
+            tracked: cached.is_some(),
+            excluded: self.is_excluded(package),
+            held: cached.is_some_and(|p| p.held),
+            install_time: cached.and_then(|p| p.install_time),
+            last_update: cached.and_then(|p| p.last_update),
+            note: cached.and_then(|p| p.note.clone()),
+            post_install: cached.map(|p| p.post_install.clone()).unwrap_or_default(),
         })
     }
 
-    fn add_package(&mut self, package: &str) -> Result<()> {
+    fn add_package(&mut self, package: &str, note: Option<String>) -> Result<()> {
         let now = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap()
@@ -237,11 +1260,16 @@ impl Homebrew {
                 last_update: Some(now),
                 size: None,
                 is_cask: false,
+                note: None,
+                held: false,
+                requires_rosetta: false,
+                post_install: Vec::new(),
             }
         };
 
         pkg.install_time = Some(now);
         pkg.last_update = Some(now);
+        pkg.note = note;
 
         self.cache.insert(package.to_string(), pkg);
         self.save_cache()?;
@@ -249,14 +1277,18 @@ impl Homebrew {
     }
 
     fn save_cache(&self) -> Result<()> {
-        let contents = serde_json::to_string_pretty(&self.cache)?;
+        let cache: HashMap<&String, &Package> = self.cache
+            .iter()
+            .filter(|(name, _)| !self.is_excluded(name))
+            .collect();
+        let contents = serde_json::to_string_pretty(&cache)?;
         std::fs::write(&self.packages_file, contents)?;
         Ok(())
     }
 
     pub fn save_packages(&mut self, packages: &[Package]) -> Result<()> {
         let mut cache = HashMap::new();
-        for package in packages {
+        for package in packages.iter().filter(|p| !self.is_excluded(&p.name)) {
             cache.insert(package.name.clone(), package.clone());
         }
         
@@ -264,4 +1296,224 @@ impl Homebrew {
         self.save_cache()?;
         Ok(())
     }
-} 
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Answers brew invocations from a fixed table; anything else fails.
+    struct Canned(HashMap<String, CommandOutput>);
+
+    impl Canned {
+        fn new(responses: &[(&str, &str)]) -> Self {
+            Self(responses
+                .iter()
+                .map(|(args, stdout)| (args.to_string(), CommandOutput {
+                    success: true,
+                    stdout: stdout.as_bytes().to_vec(),
+                    stderr: Vec::new(),
+                }))
+                .collect())
+        }
+    }
+
+    impl CommandRunner for Canned {
+        fn run(&self, _program: &str, args: &[&str]) -> std::io::Result<CommandOutput> {
+            Ok(self.0.get(&args.join(" ")).cloned().unwrap_or(CommandOutput {
+                success: false,
+                stdout: Vec::new(),
+                stderr: b"Error: No available formula".to_vec(),
+            }))
+        }
+    }
+
+    fn homebrew(name: &str, runner: Canned) -> Homebrew {
+        let dir = std::env::temp_dir().join(format!("kiwi-homebrew-test-{}-{}", name, std::process::id()));
+        Homebrew::new(dir.join("packages.json")).with_runner(runner)
+    }
+
+    #[test]
+    fn test_list_installed_parses_versions_and_info() {
+        let info = r#"{"formulae":[{"desc":"Distributed VCS","homepage":"https://git-scm.com","dependencies":["gettext","pcre2"],"installed":[{"version":"2.44.0"}]}],"casks":[]}"#;
+        let brew = homebrew("list", Canned::new(&[
+            ("list --versions", "git 2.44.0\nfirefox 124.0\n"),
+            ("info --json=v2 git", info),
+        ]));
+
+        let packages = brew.list_installed().unwrap();
+        assert_eq!(packages.len(), 2);
+        assert_eq!(packages[0].name, "git");
+        assert_eq!(packages[0].version.as_deref(), Some("2.44.0"));
+        assert_eq!(packages[0].dependencies, vec!["gettext", "pcre2"]);
+        // Info lookups that fail fall back to the listing alone
+        assert_eq!(packages[1].version.as_deref(), Some("124.0"));
+        assert!(packages[1].dependencies.is_empty());
+    }
+
+    #[test]
+    fn test_intel_only_casks_need_rosetta() {
+        let intel = r#"{"formulae":[],"casks":[{"desc":"Old app","installed":"1.0","depends_on":{"arch":[{"type":"intel","bits":64}]}}]}"#;
+        let caveat = r#"{"formulae":[],"casks":[{"installed":"2.0","caveats":"oldtool is built for Intel macOS and so requires Rosetta 2 to be installed."}]}"#;
+        let universal = r#"{"formulae":[],"casks":[{"installed":"3.0","depends_on":{"macos":{">=":["11"]}}}]}"#;
+        let brew = homebrew("rosetta", Canned::new(&[
+            ("list --versions", "old-app 1.0\noldtool 2.0\nnew-app 3.0\n"),
+            ("info --json=v2 old-app", intel),
+            ("info --json=v2 oldtool", caveat),
+            ("info --json=v2 new-app", universal),
+        ]));
+
+        let needs: Vec<bool> = brew.list_installed().unwrap().iter().map(|p| p.requires_rosetta).collect();
+        assert_eq!(needs, vec![true, true, false]);
+    }
+
+    #[test]
+    fn test_cask_apps() {
+        let info = r#"{"formulae":[],"casks":[{"artifacts":[{"uninstall":[{"quit":"org.mozilla.firefox"}]},{"app":["Firefox.app"]},{"app":["Tool.app",{"target":"Renamed Tool.app"}]}]}]}"#;
+        let brew = homebrew("apps", Canned::new(&[("info --json=v2 --cask firefox", info)]));
+
+        std::env::remove_var("HOMEBREW_CASK_OPTS");
+        let apps = brew.cask_apps("firefox").unwrap();
+        assert_eq!(apps, vec![
+            PathBuf::from("/Applications/Firefox.app"),
+            PathBuf::from("/Applications/Renamed Tool.app"),
+        ]);
+        assert!(brew.cask_apps("missing").is_err());
+    }
+
+    #[test]
+    fn test_livecheck_tap_compares_with_upstream() {
+        let checks = r#"[
+            {"formula":"acme/tools/widget","version":{"current":"1.2.0","latest":"1.3.1","outdated":true,"newer_than_upstream":false},"meta":{}},
+            {"cask":"acme/tools/widget-app","version":{"current":"2.0","latest":"2.0","outdated":false,"newer_than_upstream":false}},
+            {"formula":"acme/tools/legacy","status":"error","messages":["Unable to get versions"]}
+        ]"#;
+        let brew = homebrew("livecheck", Canned::new(&[
+            ("tap", "acme/tools\nhomebrew/cask\n"),
+            ("livecheck --tap acme/tools --json --full-name", checks),
+        ]));
+
+        let bumps = brew.livecheck_tap("acme/tools").unwrap();
+        assert_eq!(bumps.len(), 3);
+        assert_eq!(bumps[0], TapBump {
+            name: "acme/tools/widget".to_string(),
+            is_cask: false,
+            current: Some("1.2.0".to_string()),
+            latest: Some("1.3.1".to_string()),
+            outdated: true,
+            error: None,
+        });
+        assert!(bumps[1].is_cask && !bumps[1].outdated);
+        assert_eq!(bumps[2].error.as_deref(), Some("error: Unable to get versions"));
+        assert!(brew.livecheck_tap("acme/other").is_err());
+    }
+
+    #[test]
+    fn test_brew_environment_moves_paths_between_prefixes() {
+        let recorded = BrewEnvironment::capture("/usr/local".to_string(), [
+            ("HOMEBREW_PREFIX".to_string(), "/usr/local".to_string()),
+            ("HOMEBREW_GITHUB_API_TOKEN".to_string(), "ghp_x".to_string()),
+            ("HOMEBREW_BUNDLE_FILE".to_string(), "/usr/local/etc/Brewfile".to_string()),
+            ("HOMEBREW_NO_ANALYTICS".to_string(), "1".to_string()),
+            ("HOMEBREW_TEMP".to_string(), "/usr/localtmp".to_string()),
+            ("PATH".to_string(), "/usr/local/bin".to_string()),
+        ]);
+        assert_eq!(recorded.env.len(), 3);
+
+        let env = recorded.env_for("/opt/homebrew");
+        assert_eq!(env["HOMEBREW_BUNDLE_FILE"], "/opt/homebrew/etc/Brewfile");
+        assert_eq!(env["HOMEBREW_NO_ANALYTICS"], "1");
+        assert_eq!(env["HOMEBREW_TEMP"], "/usr/localtmp");
+    }
+
+    #[test]
+    fn test_reconcile_and_install_errors() {
+        let mut brew = homebrew("reconcile", Canned::new(&[
+            ("list --formula -1", "git\nripgrep\n"),
+            ("list --cask -1", "firefox\n"),
+            ("list git", ""),
+        ])).with_excludes(vec!["fire*".to_string()]);
+        brew.cache.insert("wget".to_string(), Package {
+            name: "wget".to_string(),
+            version: None,
+            installed: true,
+            dependencies: Vec::new(),
+            install_time: None,
+            last_update: None,
+            size: None,
+            is_cask: false,
+            note: None,
+            held: false,
+            requires_rosetta: false,
+            post_install: Vec::new(),
+        });
+
+        let reconciliation = brew.reconcile().unwrap();
+        assert_eq!(reconciliation.untracked, vec!["git", "ripgrep"]);
+        assert_eq!(reconciliation.missing, vec!["wget"]);
+
+        match brew.install("git", None) {
+            Err(KiwiError::PackageError { name, message }) => {
+                assert_eq!(name, "git");
+                assert_eq!(message, "Package is already installed");
+            }
+            _ => panic!("expected an already-installed error"),
+        }
+        assert!(matches!(brew.list_taps(), Err(KiwiError::Homebrew(_))));
+    }
+
+    #[test]
+    fn test_stale_only_drops_what_brew_reports_unknown() {
+        let mut brew = homebrew("stale", Canned::new(&[
+            ("list --formula -1", "git\n"),
+            ("list --cask -1", ""),
+            ("tap", "acme/tools\nhomebrew/cask-fonts\n"),
+            ("info ripgrep", "ripgrep: stable 14.1.0\n"),
+        ]));
+        for name in ["git", "ripgrep", "wget", "acme/tools/deploy", "other/tap/thing"] {
+            brew.cache.insert(name.to_string(), Package {
+                name: name.to_string(),
+                version: None,
+                installed: true,
+                dependencies: Vec::new(),
+                install_time: None,
+                last_update: None,
+                size: None,
+                is_cask: false,
+                note: None,
+                held: false,
+                requires_rosetta: false,
+                post_install: Vec::new(),
+            });
+        }
+
+        assert_eq!(brew.stale().unwrap(), vec!["acme/tools/deploy", "wget"]);
+    }
+
+    #[test]
+    fn test_matches_pattern() {
+        assert!(matches_pattern("python@3.12", "python@*"));
+        assert!(matches_pattern("node", "node"));
+        assert!(matches_pattern("openssl@3", "openssl@?"));
+        assert!(!matches_pattern("nodenv", "node"));
+        assert!(!matches_pattern("git", "*-experimental"));
+        assert!(matches_pattern("llvm-experimental", "*-experimental"));
+    }
+
+    #[test]
+    fn test_resolve_brew_prefers_configured_prefix() {
+        let dir = std::env::temp_dir().join(format!("kiwi-brew-prefix-test-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("bin")).unwrap();
+        std::fs::write(dir.join("bin/brew"), "#!/bin/sh\n").unwrap();
+        let prefix = dir.to_string_lossy();
+
+        assert_eq!(resolve_brew(Some(&prefix)), Some(dir.join("bin/brew")));
+        assert_eq!(resolve_brew(Some("/nonexistent/homebrew")), None);
+        let brew = Homebrew::new(dir.join("packages.json")).with_brew_prefix(Some(&prefix));
+        assert_eq!(brew.brew_path(), dir.join("bin/brew"));
+        assert!(brew.is_brew_installed());
+        assert!(!Homebrew::new(dir.join("packages.json")).with_brew_prefix(Some("/nonexistent/homebrew")).is_brew_installed());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,285 @@
+//! Entries for `/etc/hosts`, like dev domains pointed at localhost. They
+//! live in a kiwi-owned file that is tracked like any dotfile, so they sync;
+//! `kiwi hosts apply` writes them to a marked block in `/etc/hosts` through
+//! sudo, leaving the rest of the file alone. An entry can be limited to one
+//! environment.
+
+use crate::homebrew::{CommandRunner, SystemRunner};
+use crate::{shell, sudo, Dotfiles, KiwiError, Result};
+use std::fs;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+/// Name of the block in `/etc/hosts`.
+const BLOCK: &str = "hosts";
+/// Name of the entries file inside the dotfiles dir.
+const TRACKED_NAME: &str = "kiwi-hosts";
+const HEADER: &str = "# Managed by kiwi; edit with `kiwi hosts add/remove`, then `kiwi hosts apply`.\n";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub address: IpAddr,
+    pub names: Vec<String>,
+    /// Only applied in this environment; `None` means every environment
+    pub env: Option<String>,
+}
+
+impl Entry {
+    fn to_line(&self) -> String {
+        let line = format!("{} {}", self.address, self.names.join(" "));
+        match &self.env {
+            Some(env) => format!("{} # env {}", line, env),
+            None => line,
+        }
+    }
+
+    fn parse(line: &str) -> Option<Self> {
+        let (entry, comment) = line.split_once('#').unwrap_or((line, ""));
+        let mut fields = entry.split_whitespace();
+        let address = fields.next()?.parse().ok()?;
+        let names: Vec<String> = fields.map(str::to_string).collect();
+        if names.is_empty() {
+            return None;
+        }
+        let env = comment.trim().strip_prefix("env ").map(|env| env.trim().to_string());
+        Some(Self { address, names, env })
+    }
+
+    fn applies_to(&self, env: Option<&str>) -> bool {
+        self.env.is_none() || self.env.as_deref() == env
+    }
+}
+
+pub struct Hosts {
+    runner: Box<dyn CommandRunner>,
+    path: PathBuf,
+    hosts_file: PathBuf,
+}
+
+impl Hosts {
+    /// Entries kept in `path`, applied to `hosts_file`, normally `/etc/hosts`.
+    pub fn new(path: PathBuf, hosts_file: PathBuf) -> Self {
+        Self { runner: Box::new(SystemRunner), path, hosts_file }
+    }
+
+    pub fn with_runner(mut self, runner: impl CommandRunner + 'static) -> Self {
+        self.runner = Box::new(runner);
+        self
+    }
+
+    /// `~/.config/kiwi/hosts`
+    pub fn default_path() -> Result<PathBuf> {
+        let home = dirs::home_dir().ok_or_else(|| KiwiError::Config("Could not find home directory".to_string()))?;
+        Ok(home.join(".config/kiwi/hosts"))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn list(&self) -> Result<Vec<Entry>> {
+        let Ok(contents) = fs::read_to_string(&self.path) else {
+            return Ok(Vec::new());
+        };
+        Ok(contents.lines().filter_map(Entry::parse).collect())
+    }
+
+    /// Points `entry`'s names at its address, taking them from any other
+    /// entry for the same environment. Returns true when a name moved.
+    pub fn add(&self, entry: Entry) -> Result<bool> {
+        if entry.names.is_empty() {
+            return Err(KiwiError::ValidationError("Give at least one hostname".to_string()));
+        }
+        for name in &entry.names {
+            validate_hostname(name)?;
+        }
+
+        let mut entries = self.list()?;
+        let mut moved = false;
+        for existing in entries.iter_mut().filter(|e| e.env == entry.env) {
+            let before = existing.names.len();
+            existing.names.retain(|name| !entry.names.contains(name));
+            moved |= existing.names.len() != before;
+        }
+        entries.retain(|e| !e.names.is_empty());
+        entries.push(entry);
+        self.save(&entries)?;
+        Ok(moved)
+    }
+
+    /// Removes `name` from every entry. Returns whether it was there.
+    pub fn remove(&self, name: &str) -> Result<bool> {
+        let mut entries = self.list()?;
+        let mut removed = false;
+        for entry in &mut entries {
+            let before = entry.names.len();
+            entry.names.retain(|n| n != name);
+            removed |= entry.names.len() != before;
+        }
+        if !removed {
+            return Ok(false);
+        }
+        entries.retain(|e| !e.names.is_empty());
+        self.save(&entries)?;
+        Ok(true)
+    }
+
+    /// Makes sure the entries file is tracked for sync.
+    pub fn install(&self, dotfiles: &Dotfiles) -> Result<()> {
+        if !self.path.exists() {
+            self.save(&[])?;
+        }
+        let canonical = self.path.canonicalize()?;
+        if !dotfiles.list()?.iter().any(|d| d.path == canonical) {
+            dotfiles.add(&self.path, Some(TRACKED_NAME.to_string()), false)?;
+        }
+        Ok(())
+    }
+
+    /// The lines kiwi's block should hold in environment `env`.
+    pub fn block(&self, env: Option<&str>) -> Result<String> {
+        let lines: Vec<String> = self
+            .list()?
+            .iter()
+            .filter(|e| e.applies_to(env))
+            .map(|e| format!("{} {}", e.address, e.names.join(" ")))
+            .collect();
+        Ok(lines.join("\n"))
+    }
+
+    /// Whether the hosts file already has exactly the block for `env`.
+    pub fn is_applied(&self, env: Option<&str>) -> Result<bool> {
+        Ok(self.wanted_hosts_file(env)? == fs::read_to_string(&self.hosts_file).unwrap_or_default())
+    }
+
+    /// Lines outside kiwi's block that also name one of the hostnames kiwi
+    /// would apply for `env`. The resolver takes the first match, so these
+    /// can win over kiwi's.
+    pub fn conflicts(&self, env: Option<&str>) -> Result<Vec<String>> {
+        let ours: Vec<String> = self.list()?.into_iter().filter(|e| e.applies_to(env)).flat_map(|e| e.names).collect();
+        let current = fs::read_to_string(&self.hosts_file).unwrap_or_default();
+        Ok(shell::without_block(&current, BLOCK)
+            .lines()
+            .filter(|line| Entry::parse(line).is_some_and(|entry| entry.names.iter().any(|name| ours.contains(name))))
+            .map(str::to_string)
+            .collect())
+    }
+
+    /// Writes the block for `env` to the hosts file through sudo, then has
+    /// the resolver forget what it cached. Returns false when the file
+    /// already had it.
+    pub fn apply(&self, env: Option<&str>) -> Result<bool> {
+        let wanted = self.wanted_hosts_file(env)?;
+        if wanted == fs::read_to_string(&self.hosts_file).unwrap_or_default() {
+            return Ok(false);
+        }
+
+        let staging = self.path.parent().unwrap_or(Path::new("."));
+        sudo::write(self.runner.as_ref(), staging, &self.hosts_file, wanted.as_bytes())?;
+        let _ = self.runner.run_interactive("sudo", &["killall", "-HUP", "mDNSResponder"]);
+        Ok(true)
+    }
+
+    /// The hosts file with kiwi's block for `env` in it, or without the
+    /// block when there's nothing to apply.
+    fn wanted_hosts_file(&self, env: Option<&str>) -> Result<String> {
+        let block = self.block(env)?;
+        let current = fs::read_to_string(&self.hosts_file).unwrap_or_default();
+        if !block.is_empty() {
+            return Ok(shell::with_block(&current, BLOCK, &block));
+        }
+        let stripped = shell::without_block(&current, BLOCK);
+        // Without a block to remove, only line endings would change
+        Ok(if stripped.trim_end() == current.trim_end() { current } else { stripped })
+    }
+
+    fn save(&self, entries: &[Entry]) -> Result<()> {
+        let mut contents = HEADER.to_string();
+        for entry in entries {
+            contents.push_str(&entry.to_line());
+            contents.push('\n');
+        }
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&self.path, contents)?;
+        Ok(())
+    }
+}
+
+impl Default for Hosts {
+    fn default() -> Self {
+        Self::new(Self::default_path().unwrap_or_default(), PathBuf::from("/etc/hosts"))
+    }
+}
+
+/// Hostnames are dot-separated labels of letters, digits, and hyphens.
+fn validate_hostname(name: &str) -> Result<()> {
+    let valid = name.len() <= 253
+        && name.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        });
+    if !valid {
+        return Err(KiwiError::ValidationError(format!("Invalid hostname: {}", name)));
+    }
+    if name.eq_ignore_ascii_case("localhost") || name.eq_ignore_ascii_case("broadcasthost") {
+        return Err(KiwiError::ValidationError(format!("{} is set up by macOS and can't be redirected", name)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::homebrew::CommandOutput;
+
+    /// Runs `sudo cp` as a plain copy and ignores everything else.
+    struct Sudo;
+
+    impl CommandRunner for Sudo {
+        fn run(&self, program: &str, args: &[&str]) -> std::io::Result<CommandOutput> {
+            if let ("sudo", ["cp", from, to]) = (program, args) {
+                fs::copy(from, to)?;
+            }
+            Ok(CommandOutput { success: true, stdout: Vec::new(), stderr: Vec::new() })
+        }
+    }
+
+    #[test]
+    fn test_add_apply_and_remove() {
+        let dir = std::env::temp_dir().join(format!("kiwi-hosts-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let etc_hosts = dir.join("etc-hosts");
+        fs::write(&etc_hosts, "127.0.0.1 localhost\n10.0.0.5 api.test\n").unwrap();
+        let hosts = Hosts::new(dir.join("hosts"), etc_hosts.clone()).with_runner(Sudo);
+        let local = IpAddr::from([127, 0, 0, 1]);
+
+        assert!(hosts.add(Entry { address: local, names: vec!["localhost".to_string()], env: None }).is_err());
+        assert!(hosts.add(Entry { address: local, names: vec!["bad_name.test".to_string()], env: None }).is_err());
+        hosts.add(Entry { address: local, names: vec!["app.test".to_string(), "api.test".to_string()], env: None }).unwrap();
+        hosts.add(Entry { address: IpAddr::from([10, 0, 0, 9]), names: vec!["db.test".to_string()], env: Some("dev".to_string()) }).unwrap();
+        assert!(hosts.add(Entry { address: IpAddr::from([127, 0, 0, 2]), names: vec!["api.test".to_string()], env: None }).unwrap());
+        assert_eq!(hosts.list().unwrap().len(), 3);
+
+        assert_eq!(hosts.conflicts(Some("dev")).unwrap(), vec!["10.0.0.5 api.test".to_string()]);
+        assert!(!hosts.is_applied(Some("dev")).unwrap());
+        assert!(hosts.apply(Some("dev")).unwrap());
+        assert!(!hosts.apply(Some("dev")).unwrap());
+        let applied = fs::read_to_string(&etc_hosts).unwrap();
+        assert!(applied.starts_with("127.0.0.1 localhost\n10.0.0.5 api.test\n"));
+        assert!(applied.contains("127.0.0.1 app.test\n10.0.0.9 db.test\n127.0.0.2 api.test\n"));
+        assert!(!hosts.is_applied(Some("prod")).unwrap());
+
+        for name in ["app.test", "db.test", "api.test"] {
+            assert!(hosts.remove(name).unwrap());
+        }
+        hosts.apply(None).unwrap();
+        assert_eq!(fs::read_to_string(&etc_hosts).unwrap(), "127.0.0.1 localhost\n10.0.0.5 api.test\n");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,270 @@
+//! Scheduled jobs: the user's crontab and their LaunchAgents. `kiwi jobs
+//! capture` copies them to kiwi-owned files that are tracked like any
+//! dotfile, so they sync, and `kiwi jobs restore` puts them back on another
+//! Mac once each one has been reviewed. Nothing is loaded unreviewed, since
+//! a job runs code on its own.
+
+use crate::agent;
+use crate::homebrew::{CommandRunner, SystemRunner};
+use crate::{Dotfiles, KiwiError, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A LaunchAgent, summarized for review.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LaunchAgent {
+    pub path: PathBuf,
+    pub label: String,
+    /// The command line it runs
+    pub command: Vec<String>,
+    /// When it runs, e.g. `at login, every 3600s`
+    pub schedule: String,
+}
+
+impl LaunchAgent {
+    /// The program it runs, when that isn't on this Mac.
+    pub fn missing_program(&self) -> Option<&str> {
+        let program = self.command.first()?;
+        (program.starts_with('/') && !Path::new(program).exists()).then_some(program.as_str())
+    }
+}
+
+pub struct Jobs {
+    runner: Box<dyn CommandRunner>,
+    dir: PathBuf,
+    agents_dir: PathBuf,
+}
+
+impl Jobs {
+    /// Jobs captured to `dir`, with LaunchAgents installed in `agents_dir`.
+    pub fn new(dir: PathBuf, agents_dir: PathBuf) -> Self {
+        Self { runner: Box::new(SystemRunner), dir, agents_dir }
+    }
+
+    pub fn with_runner(mut self, runner: impl CommandRunner + 'static) -> Self {
+        self.runner = Box::new(runner);
+        self
+    }
+
+    /// Where a captured crontab is kept.
+    pub fn crontab_path(&self) -> PathBuf {
+        self.dir.join("crontab")
+    }
+
+    /// The user's crontab, or `None` when they don't have one.
+    pub fn current_crontab(&self) -> Result<Option<String>> {
+        let output = self.runner.run("crontab", &["-l"])?;
+        let contents = String::from_utf8_lossy(&output.stdout).to_string();
+        Ok((output.success && !contents.trim().is_empty()).then_some(contents))
+    }
+
+    /// The user's LaunchAgents, except kiwi's own.
+    pub fn installed_agents(&self) -> Result<Vec<LaunchAgent>> {
+        self.agents_in(&self.agents_dir)
+    }
+
+    /// The LaunchAgents captured so far.
+    pub fn captured_agents(&self) -> Result<Vec<LaunchAgent>> {
+        self.agents_in(&self.dir.join("LaunchAgents"))
+    }
+
+    fn agents_in(&self, dir: &Path) -> Result<Vec<LaunchAgent>> {
+        let Ok(entries) = fs::read_dir(dir) else {
+            return Ok(Vec::new());
+        };
+        let mut paths: Vec<PathBuf> = entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|e| e == "plist"))
+            .filter(|path| path.file_stem().is_some_and(|stem| stem != agent::LABEL))
+            .collect();
+        paths.sort();
+        paths.iter().map(|path| self.describe(path)).collect()
+    }
+
+    /// Reads a LaunchAgent plist, which may be binary, through `plutil`.
+    pub fn describe(&self, plist: &Path) -> Result<LaunchAgent> {
+        let output = self.runner.run("plutil", &["-convert", "json", "-o", "-", &plist.to_string_lossy()])?;
+        if !output.success {
+            return Err(KiwiError::Config(format!(
+                "Couldn't read {}: {}",
+                plist.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        let job: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+        let label = job["Label"].as_str().map(str::to_string).unwrap_or_else(|| {
+            plist.file_stem().unwrap_or_default().to_string_lossy().to_string()
+        });
+        let mut command: Vec<String> = job["ProgramArguments"]
+            .as_array()
+            .map(|args| args.iter().filter_map(|arg| arg.as_str().map(str::to_string)).collect())
+            .unwrap_or_default();
+        // `Program` is what runs; the arguments then start with its name
+        if let Some(program) = job["Program"].as_str() {
+            match command.first_mut() {
+                Some(first) => *first = program.to_string(),
+                None => command.push(program.to_string()),
+            }
+        }
+        Ok(LaunchAgent { path: plist.to_path_buf(), label, command, schedule: schedule(&job) })
+    }
+
+    /// Copies the crontab to the capture dir and tracks it. Returns whether
+    /// there was one.
+    pub fn capture_crontab(&self, dotfiles: &Dotfiles) -> Result<bool> {
+        let Some(contents) = self.current_crontab()? else {
+            return Ok(false);
+        };
+        fs::create_dir_all(&self.dir)?;
+        let path = self.crontab_path();
+        fs::write(&path, contents)?;
+        track(dotfiles, &path, "kiwi-crontab")?;
+        Ok(true)
+    }
+
+    /// Copies a LaunchAgent to the capture dir and tracks the copy.
+    pub fn capture_agent(&self, job: &LaunchAgent, dotfiles: &Dotfiles) -> Result<PathBuf> {
+        let dir = self.dir.join("LaunchAgents");
+        fs::create_dir_all(&dir)?;
+        let file_name = job.path.file_name().unwrap_or_default();
+        let path = dir.join(file_name);
+        fs::copy(&job.path, &path)?;
+        track(dotfiles, &path, &format!("kiwi-job-{}", file_name.to_string_lossy()))?;
+        Ok(path)
+    }
+
+    /// Replaces the user's crontab with the captured one.
+    pub fn restore_crontab(&self) -> Result<()> {
+        let path = self.crontab_path();
+        let output = self.runner.run("crontab", &[&path.to_string_lossy()])?;
+        if !output.success {
+            return Err(KiwiError::Config(format!(
+                "crontab rejected {}: {}",
+                path.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        Ok(())
+    }
+
+    /// Installs a captured LaunchAgent and loads it. One launchd refuses is
+    /// removed again, so it doesn't look installed.
+    pub fn restore_agent(&self, job: &LaunchAgent) -> Result<PathBuf> {
+        fs::create_dir_all(&self.agents_dir)?;
+        let installed = self.agents_dir.join(job.path.file_name().unwrap_or_default());
+        fs::copy(&job.path, &installed)?;
+        if let Err(e) = agent::load(self.runner.as_ref(), &installed, &job.label) {
+            let _ = fs::remove_file(&installed);
+            return Err(e);
+        }
+        Ok(installed)
+    }
+}
+
+impl Default for Jobs {
+    fn default() -> Self {
+        let home = dirs::home_dir().unwrap_or_default();
+        Self::new(home.join(".config/kiwi/jobs"), home.join("Library/LaunchAgents"))
+    }
+}
+
+fn track(dotfiles: &Dotfiles, path: &Path, alias: &str) -> Result<()> {
+    let canonical = path.canonicalize()?;
+    if !dotfiles.list()?.iter().any(|d| d.path == canonical) {
+        dotfiles.add(path, Some(alias.to_string()), false)?;
+    }
+    Ok(())
+}
+
+/// When a job runs, in words.
+fn schedule(job: &serde_json::Value) -> String {
+    let mut when = Vec::new();
+    if job["RunAtLoad"].as_bool() == Some(true) {
+        when.push("at login".to_string());
+    }
+    if let Some(seconds) = job["StartInterval"].as_u64() {
+        when.push(format!("every {}s", seconds));
+    }
+    if !job["StartCalendarInterval"].is_null() {
+        when.push("on a calendar schedule".to_string());
+    }
+    if !job["WatchPaths"].is_null() || !job["QueueDirectories"].is_null() {
+        when.push("when files change".to_string());
+    }
+    if job["KeepAlive"].as_bool() == Some(true) || job["KeepAlive"].is_object() {
+        when.push("kept running".to_string());
+    }
+    if when.is_empty() {
+        "on demand".to_string()
+    } else {
+        when.join(", ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::homebrew::CommandOutput;
+    use std::sync::{Arc, Mutex};
+
+    /// Treats plists as JSON already, has a one-line crontab, and records
+    /// what gets installed.
+    #[derive(Clone, Default)]
+    struct Launchd {
+        calls: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl CommandRunner for Launchd {
+        fn run(&self, program: &str, args: &[&str]) -> std::io::Result<CommandOutput> {
+            let stdout = match (program, args) {
+                ("plutil", [.., path]) => fs::read(path)?,
+                ("crontab", ["-l"]) => b"0 9 * * 1 ~/bin/weekly-report\n".to_vec(),
+                ("id", _) => b"501\n".to_vec(),
+                _ => {
+                    self.calls.lock().unwrap().push(format!("{} {}", program, args.join(" ")));
+                    Vec::new()
+                }
+            };
+            Ok(CommandOutput { success: true, stdout, stderr: Vec::new() })
+        }
+    }
+
+    #[test]
+    fn test_capture_and_restore() {
+        let dir = std::env::temp_dir().join(format!("kiwi-jobs-test-{}", std::process::id()));
+        let agents = dir.join("LaunchAgents");
+        fs::create_dir_all(&agents).unwrap();
+        fs::write(
+            agents.join("com.example.backup.plist"),
+            r#"{"Label":"com.example.backup","ProgramArguments":["/nonexistent/bin/backup","--quiet"],"RunAtLoad":true,"StartInterval":3600}"#,
+        )
+        .unwrap();
+        fs::write(agents.join(format!("{}.plist", agent::LABEL)), r#"{"Label":"dev.kiwi.watch"}"#).unwrap();
+        let dotfiles = Dotfiles::new(dir.join("dotfiles"), dir.join("dotfiles/dotfiles.json"));
+        let runner = Launchd::default();
+        let jobs = Jobs::new(dir.join("jobs"), agents.clone()).with_runner(runner.clone());
+
+        let installed = jobs.installed_agents().unwrap();
+        assert_eq!(installed.len(), 1);
+        assert_eq!(installed[0].label, "com.example.backup");
+        assert_eq!(installed[0].schedule, "at login, every 3600s");
+        assert_eq!(installed[0].missing_program(), Some("/nonexistent/bin/backup"));
+
+        assert!(jobs.capture_crontab(&dotfiles).unwrap());
+        jobs.capture_agent(&installed[0], &dotfiles).unwrap();
+        assert_eq!(dotfiles.list().unwrap().len(), 2);
+
+        // On the new Mac the agent isn't installed yet
+        fs::remove_file(agents.join("com.example.backup.plist")).unwrap();
+        let captured = jobs.captured_agents().unwrap();
+        jobs.restore_agent(&captured[0]).unwrap();
+        jobs.restore_crontab().unwrap();
+        assert!(agents.join("com.example.backup.plist").exists());
+        let calls = runner.calls.lock().unwrap();
+        assert!(calls.contains(&format!("launchctl bootstrap gui/501 {}", agents.join("com.example.backup.plist").display())));
+        assert!(calls.contains(&format!("crontab {}", jobs.crontab_path().display())));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,79 @@
+//! Secrets kept in the macOS Keychain. Synced files only ever contain a
+//! placeholder that looks the secret up when the shell starts.
+
+use crate::homebrew::{CommandRunner, SystemRunner};
+use crate::{KiwiError, Result};
+
+/// Keychain service every kiwi secret is filed under.
+const SERVICE: &str = "kiwi";
+
+/// Stops `delete_all` from looping forever if `security` keeps succeeding.
+const MAX_ENTRIES: usize = 1000;
+
+pub struct Keychain {
+    runner: Box<dyn CommandRunner>,
+}
+
+impl Default for Keychain {
+    fn default() -> Self {
+        Self { runner: Box::new(SystemRunner) }
+    }
+}
+
+impl Keychain {
+    pub fn with_runner(runner: impl CommandRunner + 'static) -> Self {
+        Self { runner: Box::new(runner) }
+    }
+
+    /// Stores or replaces the secret for `account`.
+    pub fn set(&self, account: &str, secret: &str) -> Result<()> {
+        self.security(&["add-generic-password", "-U", "-s", SERVICE, "-a", account, "-w", secret])
+    }
+
+    /// The secret for `account`, if there is one.
+    pub fn get(&self, account: &str) -> Result<Option<String>> {
+        let output = self.runner.run("security", &["find-generic-password", "-s", SERVICE, "-a", account, "-w"])?;
+        if !output.success {
+            return Ok(None);
+        }
+        let secret = String::from_utf8_lossy(&output.stdout);
+        Ok(Some(secret.strip_suffix('\n').unwrap_or(&secret).to_string()))
+    }
+
+    pub fn delete(&self, account: &str) -> Result<()> {
+        self.security(&["delete-generic-password", "-s", SERVICE, "-a", account])
+    }
+
+    /// Deletes every secret filed under kiwi's service. Returns how many went.
+    pub fn delete_all(&self) -> Result<usize> {
+        let mut deleted = 0;
+        // `security` deletes one match per call and fails once none are left
+        while deleted < MAX_ENTRIES && self.security(&["delete-generic-password", "-s", SERVICE]).is_ok() {
+            deleted += 1;
+        }
+        Ok(deleted)
+    }
+
+    fn security(&self, args: &[&str]) -> Result<()> {
+        let output = self.runner.run("security", args)?;
+        if !output.success {
+            return Err(KiwiError::Config(format!(
+                "Keychain error: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Shell command substitution that prints the secret for `account`.
+pub fn lookup_command(account: &str) -> String {
+    format!("$(security find-generic-password -s {} -a {} -w 2>/dev/null)", SERVICE, account)
+}
+
+/// The account named by a [`lookup_command`] placeholder, if `value` is one.
+pub fn parse_lookup(value: &str) -> Option<&str> {
+    value
+        .strip_prefix(&format!("$(security find-generic-password -s {} -a ", SERVICE))?
+        .strip_suffix(" -w 2>/dev/null)")
+}
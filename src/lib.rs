@@ -4,6 +4,63 @@ pub mod dotfiles;
 pub mod homebrew;
 pub mod sync;
 pub mod error;
+pub mod link;
+pub mod api;
+pub mod ui;
+pub mod search;
+pub mod export;
+pub mod crypto;
+pub mod archive;
+pub mod theme;
+pub mod shell;
+pub mod completions;
+pub mod diagnostics;
+pub mod telemetry;
+pub mod ops;
+pub mod reporter;
+pub mod blobs;
+pub mod hashing;
+pub mod template;
+pub mod environments;
+pub mod starters;
+pub mod registry;
+pub mod aliases;
+pub mod keychain;
+pub mod envvars;
+pub mod gc;
+pub mod watch;
+pub mod xcode;
+pub mod rosetta;
+pub mod gatekeeper;
+pub mod table;
+pub mod validate;
+pub mod backups;
+pub mod sensitive;
+pub mod diff;
+pub mod transfer;
+pub mod migrate;
+pub mod agent;
+pub mod teardown;
+pub mod apps;
+pub mod jobs;
+pub mod gpg;
+pub mod ssh;
+pub mod hosts;
+pub mod network;
+pub mod tweaks;
+pub mod fixes;
+pub mod plan;
+pub mod sizes;
+pub mod seed;
+pub mod sudo;
+pub mod xattrs;
+pub mod audit;
+pub mod policy;
+pub mod project;
+pub mod history;
+pub mod provenance;
+#[cfg(feature = "test-server")]
+pub mod test_server;
 
 pub use cli::Cli;
 pub use config::Config;
@@ -0,0 +1,48 @@
+use crate::{api, Result, KiwiError};
+use qrcode::render::unicode;
+use qrcode::QrCode;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LinkCode {
+    pub code: String,
+    pub expires_at: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LinkedAccount {
+    pub email: String,
+    pub token: String,
+}
+
+#[derive(Debug, Serialize)]
+struct ClaimRequest<'a> {
+    code: &'a str,
+}
+
+/// Renders a link code as a terminal-friendly QR code.
+pub fn render_qr(code: &str) -> Result<String> {
+    let qr = QrCode::new(format!("kiwi://link?code={}", code)).map_err(|e| {
+        KiwiError::Sync(format!("Failed to generate QR code: {}", e))
+    })?;
+
+    Ok(qr
+        .render::<unicode::Dense1x2>()
+        .quiet_zone(true)
+        .build())
+}
+
+/// Exchanges a link code from another machine for a device-scoped token.
+pub async fn claim_code(base_url: &str, code: &str) -> Result<LinkedAccount> {
+    let request = Client::new()
+        .post(format!("{}/link/claim", base_url.trim_end_matches('/')))
+        .json(&ClaimRequest { code: code.trim() });
+    let response = api::send(request).await?;
+
+    if !response.status().is_success() {
+        return Err(KiwiError::AuthError(api::failure(response, "Link failed").await));
+    }
+
+    Ok(response.json::<LinkedAccount>().await?)
+}
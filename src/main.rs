@@ -1,14 +1,14 @@
-use log::{info, error};
+use log::error;
 use dialoguer::{Input, Password, theme::ColorfulTheme};
 use serde::{Deserialize, Serialize};
 use reqwest::Client;
 use dotenv::dotenv;
-use std::env;
-use clap::Parser;
+use clap::{CommandFactory, FromArgMatches};
 use serde_json::json;
 use std::process;
+use std::time::Instant;
 
-use kiwi::{Result, Config, Cli};
+use kiwi::{api, audit, Result, Config, Cli, KiwiError, diagnostics, telemetry};
 
 const DEFAULT_SYNC_URL: &str = "http://34.41.188.73:8080";
 const MAX_LOGIN_ATTEMPTS: u32 = 3;
@@ -29,16 +29,10 @@ async fn register_user(email: String, password: String) -> Result<AuthResponse>
     let client = Client::new();
     let request = RegisterRequest { email, password };
     
-    let response = client
-        .post("http://34.41.188.73:8080/register")
-        .json(&request)
-        .send()
-        .await?;
+    let response = api::send(client.post("http://34.41.188.73:8080/register").json(&request)).await?;
 
     if !response.status().is_success() {
-        let status = response.status();
-        let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-        return Err(format!("Registration failed: {} - {}", status, error_text).into());
+        return Err(KiwiError::AuthError(api::failure(response, "Registration failed").await));
     }
 
     let auth_response = response.json::<AuthResponse>().await?;
@@ -49,16 +43,10 @@ async fn login_user(email: String, password: String) -> Result<AuthResponse> {
     let client = Client::new();
     let request = RegisterRequest { email, password };
     
-    let response = client
-        .post("http://34.41.188.73:8080/login")
-        .json(&request)
-        .send()
-        .await?;
+    let response = api::send(client.post("http://34.41.188.73:8080/login").json(&request)).await?;
 
     if !response.status().is_success() {
-        let status = response.status();
-        let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-        return Err(format!("Login failed: {} - {}", status, error_text).into());
+        return Err(KiwiError::AuthError(api::failure(response, "Login failed").await));
     }
 
     let auth_response = response.json::<AuthResponse>().await?;
@@ -155,10 +143,35 @@ async fn authenticate(theme: &ColorfulTheme) -> Result<AuthResponse> {
 async fn main() -> Result<()> {
     env_logger::init();
     dotenv().ok();
-    
+
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+
+    let started = Instant::now();
+    let result = run(cli).await;
+    if let Err(e) = &result {
+        // Best effort: never mask the original error with a logging failure
+        let _ = diagnostics::record_failure(e);
+    }
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Err(e) = audit::record_command(&telemetry::command_name(&matches), &args, &result) {
+        log::warn!("Couldn't write the audit journal: {}", e);
+    }
+
+    telemetry::report(&telemetry::Event {
+        command: telemetry::command_name(&matches),
+        duration_ms: started.elapsed().as_millis() as u64,
+        success: result.is_ok(),
+    }).await;
+
+    result
+}
+
+async fn run(cli: Cli) -> Result<()> {
     let mut config = Config::load()?;
-    if config.sync_token.is_some() {
-        let cli = Cli::parse();
+    cli.choose_sync_mode(&mut config)?;
+    // Without a sync URL kiwi runs in local mode and never needs an account
+    if config.sync_token.is_some() || config.sync_url.is_none() || !cli.requires_auth() {
         return cli.execute().await;
     }
     
@@ -175,15 +188,14 @@ async fn main() -> Result<()> {
             
             // Initialize user's remote storage
             let client = Client::new();
-            let _ = client
+            let request = client
                 .post(format!("{}/sync", config.sync_url.as_deref().unwrap_or(DEFAULT_SYNC_URL)))
                 .header("Authorization", format!("Bearer {}", auth.token))
                 .json(&json!({
                     "files": {},
                     "packages": []
-                }))
-                .send()
-                .await?;
+                }));
+            let _ = api::send(request).await?;
 
             config.save()?;
         }
@@ -194,6 +206,5 @@ async fn main() -> Result<()> {
     }
 
     // After successful login/registration, execute the CLI command
-    let cli = Cli::parse();
     cli.execute().await
 }
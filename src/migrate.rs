@@ -0,0 +1,393 @@
+//! Finding what's worth keeping in an old home directory, such as one
+//! inside a Time Machine backup or another account on this Mac: well-known
+//! configs, whatever the old kiwi tracked, and the brew packages and taps
+//! that were installed.
+
+use crate::homebrew::Package;
+use crate::Result;
+use std::collections::HashMap;
+use std::fs;
+use std::os::unix::fs::{lchown, MetadataExt};
+use std::path::{Component, Path, PathBuf};
+
+/// Home-relative configs worth tracking when they turn up in an old home.
+pub const KNOWN_CONFIGS: &[&str] = &[
+    ".zshrc",
+    ".zshenv",
+    ".zprofile",
+    ".bashrc",
+    ".bash_profile",
+    ".profile",
+    ".inputrc",
+    ".gitconfig",
+    ".gitignore_global",
+    ".config/git/config",
+    ".config/git/ignore",
+    ".vimrc",
+    ".config/nvim/init.lua",
+    ".config/nvim/init.vim",
+    ".tmux.conf",
+    ".config/tmux/tmux.conf",
+    ".config/fish/config.fish",
+    ".config/starship.toml",
+    ".config/alacritty/alacritty.toml",
+    ".config/kitty/kitty.conf",
+    ".config/ghostty/config",
+    ".wezterm.lua",
+    ".ssh/config",
+    ".editorconfig",
+    ".curlrc",
+    ".psqlrc",
+    ".Brewfile",
+];
+
+/// Where brew keeps its prefix on Apple Silicon and Intel, relative to the volume root.
+const BREW_PREFIXES: &[&str] = &["opt/homebrew", "usr/local"];
+
+/// How deep to look for `Users` inside a backup before giving up.
+const SEARCH_DEPTH: usize = 6;
+
+/// A config found in an old home.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Found {
+    /// Where the file is in the old home
+    pub source: PathBuf,
+    /// Its home-relative path, which is also where it lands in this home
+    pub relative: PathBuf,
+}
+
+/// User homes in a Time Machine backup, from its newest snapshot. `backup`
+/// may be the backup disk, a machine or snapshot folder inside it, or a
+/// home directory itself.
+pub fn homes_in_backup(backup: &Path) -> Vec<PathBuf> {
+    if looks_like_home(backup) {
+        return vec![backup.to_path_buf()];
+    }
+    let Some(users) = find_users_dir(backup, SEARCH_DEPTH) else {
+        return Vec::new();
+    };
+    let Ok(entries) = fs::read_dir(&users) else {
+        return Vec::new();
+    };
+    let mut homes: Vec<PathBuf> = entries
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| p.is_dir())
+        .filter(|p| p.file_name().is_some_and(|n| !n.to_string_lossy().starts_with('.') && n != "Shared" && n != "Guest"))
+        .collect();
+    homes.sort();
+    homes
+}
+
+/// The volume root a home sits on (the parent of `Users`), where brew's prefixes live.
+pub fn volume_root(home: &Path) -> Option<&Path> {
+    let users = home.parent()?;
+    (users.file_name()? == "Users").then(|| users.parent()).flatten()
+}
+
+fn looks_like_home(dir: &Path) -> bool {
+    dir.join("Library").is_dir() || KNOWN_CONFIGS.iter().any(|config| dir.join(config).is_file())
+}
+
+/// The first `Users` folder under `dir`, trying newer snapshots first.
+/// Time Machine names snapshots by date, so newer ones sort last.
+fn find_users_dir(dir: &Path, depth: usize) -> Option<PathBuf> {
+    let candidate = dir.join("Users");
+    if candidate.is_dir() {
+        return Some(candidate);
+    }
+    if depth == 0 {
+        return None;
+    }
+    let mut children: Vec<PathBuf> = fs::read_dir(dir)
+        .ok()?
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| p.is_dir() && p.file_name().is_some_and(|n| !n.to_string_lossy().starts_with('.')))
+        .collect();
+    children.sort();
+    children.into_iter().rev().find_map(|child| find_users_dir(&child, depth - 1))
+}
+
+/// Known configs in `home`, plus every file the old kiwi tracked there.
+pub fn find_configs(home: &Path) -> Vec<Found> {
+    let mut relatives: Vec<PathBuf> = KNOWN_CONFIGS.iter().map(PathBuf::from).collect();
+    for tracked in previously_tracked(home) {
+        if !relatives.contains(&tracked) {
+            relatives.push(tracked);
+        }
+    }
+
+    relatives
+        .into_iter()
+        .map(|relative| Found { source: home.join(&relative), relative })
+        .filter(|found| found.source.is_file())
+        .collect()
+}
+
+/// A dotfiles dir name for `relative` when its file name is already in
+/// `taken`: `.ssh/config` and `.config/git/config` can't both be `config`.
+pub fn alias_for(relative: &Path, taken: &[String]) -> Option<String> {
+    let file_name = relative.file_name()?.to_string_lossy().to_string();
+    if !taken.contains(&file_name) {
+        return None;
+    }
+    let folder = relative.parent().and_then(Path::file_name)?.to_string_lossy();
+    Some(format!("{}-{}", folder.trim_start_matches('.'), file_name))
+}
+
+/// Home-relative paths from the old kiwi's dotfiles index, if it had one.
+fn previously_tracked(home: &Path) -> Vec<PathBuf> {
+    let Ok(contents) = fs::read_to_string(home.join(".kiwi/dotfiles/dotfiles.json")) else {
+        return Vec::new();
+    };
+    let Ok(entries) = serde_json::from_str::<Vec<serde_json::Value>>(&contents) else {
+        return Vec::new();
+    };
+    entries
+        .iter()
+        .filter_map(|entry| entry.get("path")?.as_str().map(PathBuf::from))
+        .filter_map(|path| match path.strip_prefix("~") {
+            Ok(relative) => Some(relative.to_path_buf()),
+            Err(_) => home_relative(&path),
+        })
+        .collect()
+}
+
+/// `/Users/<name>/rest` as `rest`; tracked paths from another Mac can't
+/// be resolved against this one's home.
+fn home_relative(path: &Path) -> Option<PathBuf> {
+    let mut components = path.components();
+    if components.next() != Some(Component::RootDir) || components.next()?.as_os_str() != "Users" {
+        return None;
+    }
+    components.next()?;
+    let relative = components.as_path();
+    (!relative.as_os_str().is_empty()).then(|| relative.to_path_buf())
+}
+
+/// Packages the old Mac had: the old kiwi's manifest when there is one,
+/// otherwise what was installed on request under brew's prefixes on `root`.
+/// None of them are marked installed here.
+pub fn find_packages(home: &Path, root: Option<&Path>) -> Vec<Package> {
+    let manifest = home.join(".kiwi/dotfiles/packages.json");
+    if let Some(cache) = fs::read_to_string(manifest).ok().and_then(|c| serde_json::from_str::<HashMap<String, Package>>(&c).ok()) {
+        let mut packages: Vec<Package> = cache.into_values().collect();
+        for package in &mut packages {
+            package.installed = false;
+        }
+        packages.sort_by(|a, b| a.name.cmp(&b.name));
+        return packages;
+    }
+
+    let Some(root) = root else {
+        return Vec::new();
+    };
+    let mut packages = Vec::new();
+    for prefix in BREW_PREFIXES.iter().map(|prefix| root.join(prefix)) {
+        for (folder, is_cask) in [("Cellar", false), ("Caskroom", true)] {
+            let Ok(entries) = fs::read_dir(prefix.join(folder)) else {
+                continue;
+            };
+            for entry in entries.flatten().filter(|e| e.path().is_dir()) {
+                let name = entry.file_name().to_string_lossy().to_string();
+                if name.starts_with('.') || packages.iter().any(|p: &Package| p.name == name) {
+                    continue;
+                }
+                let version = latest_version(&entry.path());
+                if !is_cask && !installed_on_request(&entry.path(), version.as_deref()) {
+                    continue;
+                }
+                packages.push(Package {
+                    name,
+                    version,
+                    installed: false,
+                    dependencies: Vec::new(),
+                    install_time: None,
+                    last_update: None,
+                    size: None,
+                    is_cask,
+                    note: None,
+                    held: false,
+                    requires_rosetta: false,
+                    post_install: Vec::new(),
+                });
+            }
+        }
+    }
+    packages.sort_by(|a, b| a.name.cmp(&b.name));
+    packages
+}
+
+fn latest_version(package_dir: &Path) -> Option<String> {
+    let mut versions: Vec<String> = fs::read_dir(package_dir)
+        .ok()?
+        .flatten()
+        .map(|e| e.file_name().to_string_lossy().to_string())
+        .filter(|v| !v.starts_with('.'))
+        .collect();
+    versions.sort();
+    versions.pop()
+}
+
+/// Whether brew's install receipt says the user asked for this formula,
+/// rather than it coming in as a dependency. Assumes so without a receipt.
+fn installed_on_request(package_dir: &Path, version: Option<&str>) -> bool {
+    let Some(version) = version else {
+        return true;
+    };
+    let Ok(receipt) = fs::read_to_string(package_dir.join(version).join("INSTALL_RECEIPT.json")) else {
+        return true;
+    };
+    serde_json::from_str::<serde_json::Value>(&receipt)
+        .ok()
+        .and_then(|receipt| receipt.get("installed_on_request")?.as_bool())
+        .unwrap_or(true)
+}
+
+/// Taps the old Mac had: the old kiwi's taps.json, otherwise the tap
+/// checkouts under brew's prefixes on `root`.
+pub fn find_taps(home: &Path, root: Option<&Path>) -> Vec<String> {
+    if let Some(taps) = fs::read_to_string(home.join(".kiwi/dotfiles/taps.json")).ok().and_then(|c| serde_json::from_str(&c).ok()) {
+        return taps;
+    }
+
+    let Some(root) = root else {
+        return Vec::new();
+    };
+    let mut taps = Vec::new();
+    for taps_dir in [root.join("opt/homebrew/Library/Taps"), root.join("usr/local/Homebrew/Library/Taps")] {
+        let Ok(users) = fs::read_dir(&taps_dir) else {
+            continue;
+        };
+        for user in users.flatten() {
+            let Ok(repos) = fs::read_dir(user.path()) else {
+                continue;
+            };
+            for repo in repos.flatten() {
+                let repo = repo.file_name().to_string_lossy().to_string();
+                let Some(repo) = repo.strip_prefix("homebrew-") else {
+                    continue;
+                };
+                let tap = format!("{}/{}", user.file_name().to_string_lossy(), repo);
+                if !taps.contains(&tap) {
+                    taps.push(tap);
+                }
+            }
+        }
+    }
+    taps.sort();
+    taps
+}
+
+/// `contents` with paths under the old account's `/Users/<name>` pointed at
+/// `home` instead. Binary files are returned as they are.
+pub fn rehome(contents: Vec<u8>, old_home: &Path, home: &Path) -> Vec<u8> {
+    let Some(name) = old_home.file_name() else {
+        return contents;
+    };
+    let old_prefix = format!("/Users/{}/", name.to_string_lossy());
+    let new_prefix = format!("{}/", home.display());
+    match String::from_utf8(contents) {
+        Ok(text) if old_prefix != new_prefix => text.replace(&old_prefix, &new_prefix).into_bytes(),
+        Ok(text) => text.into_bytes(),
+        Err(e) => e.into_bytes(),
+    }
+}
+
+/// Under `sudo`, gives `paths` (recursively) and the folders created for
+/// them inside `home` back to the user who ran it, so their own shell can
+/// still edit them. Does nothing otherwise.
+pub fn hand_back(paths: &[PathBuf], home: &Path) -> Result<()> {
+    let id = |var: &str| std::env::var(var).ok()?.parse::<u32>().ok();
+    let (Some(uid), Some(gid)) = (id("SUDO_UID"), id("SUDO_GID")) else {
+        return Ok(());
+    };
+
+    for path in paths {
+        for ancestor in path.ancestors().skip(1).take_while(|a| a.starts_with(home) && *a != home) {
+            give_root_owned(ancestor, uid, gid)?;
+        }
+        give_all(path, uid, gid)?;
+    }
+    Ok(())
+}
+
+fn give_all(path: &Path, uid: u32, gid: u32) -> Result<()> {
+    give_root_owned(path, uid, gid)?;
+    if path.symlink_metadata().is_ok_and(|m| m.is_dir()) {
+        for entry in fs::read_dir(path)?.flatten() {
+            give_all(&entry.path(), uid, gid)?;
+        }
+    }
+    Ok(())
+}
+
+fn give_root_owned(path: &Path, uid: u32, gid: u32) -> Result<()> {
+    if path.symlink_metadata().is_ok_and(|m| m.uid() == 0) {
+        lchown(path, Some(uid), Some(gid))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_finds_home_configs_and_packages_in_newest_snapshot() {
+        let disk = std::env::temp_dir().join(format!("kiwi-migrate-test-{}", std::process::id()));
+        let machine = disk.join("Backups.backupdb/Old Mac");
+        let old = machine.join("2024-01-01-120000/Macintosh HD - Data");
+        let new = machine.join("2024-03-01-120000/Macintosh HD - Data");
+        fs::create_dir_all(old.join("Users/jane")).unwrap();
+        fs::create_dir_all(new.join("Users/Shared")).unwrap();
+        let home = new.join("Users/jane");
+        fs::create_dir_all(home.join(".kiwi/dotfiles")).unwrap();
+        fs::create_dir_all(home.join(".config/work")).unwrap();
+        fs::write(home.join(".zshrc"), "export EDITOR=nvim\n").unwrap();
+        fs::write(home.join(".config/work/tool.toml"), "a = 1\n").unwrap();
+        fs::write(home.join(".config/work/notes.md"), "# notes\n").unwrap();
+        fs::write(
+            home.join(".kiwi/dotfiles/dotfiles.json"),
+            r#"[{"path": "/Users/jane/.config/work/tool.toml"}, {"path": "~/.config/work/notes.md"}, {"path": "/etc/hosts"}]"#,
+        )
+        .unwrap();
+
+        let cellar = new.join("opt/homebrew/Cellar");
+        fs::create_dir_all(cellar.join("ripgrep/14.1.0")).unwrap();
+        fs::create_dir_all(cellar.join("pcre2/10.42")).unwrap();
+        fs::write(cellar.join("pcre2/10.42/INSTALL_RECEIPT.json"), r#"{"installed_on_request": false}"#).unwrap();
+        fs::create_dir_all(new.join("opt/homebrew/Caskroom/iterm2/3.5.0")).unwrap();
+        fs::create_dir_all(new.join("opt/homebrew/Library/Taps/homebrew/homebrew-cask-fonts")).unwrap();
+
+        assert_eq!(homes_in_backup(&disk), vec![home.clone()]);
+        assert_eq!(volume_root(&home), Some(new.as_path()));
+
+        let relatives: Vec<PathBuf> = find_configs(&home).into_iter().map(|f| f.relative).collect();
+        assert_eq!(
+            relatives,
+            vec![PathBuf::from(".zshrc"), PathBuf::from(".config/work/tool.toml"), PathBuf::from(".config/work/notes.md")]
+        );
+
+        let packages = find_packages(&home, volume_root(&home));
+        let names: Vec<(&str, bool)> = packages.iter().map(|p| (p.name.as_str(), p.is_cask)).collect();
+        assert_eq!(names, vec![("iterm2", true), ("ripgrep", false)]);
+        assert_eq!(find_taps(&home, volume_root(&home)), vec!["homebrew/cask-fonts".to_string()]);
+
+        fs::remove_dir_all(&disk).unwrap();
+    }
+
+    #[test]
+    fn test_alias_for_taken_names() {
+        let taken = vec!["config".to_string()];
+        assert_eq!(alias_for(Path::new(".ssh/config"), &taken).as_deref(), Some("ssh-config"));
+        assert_eq!(alias_for(Path::new(".zshrc"), &taken), None);
+    }
+
+    #[test]
+    fn test_rehome_rewrites_old_account_paths() {
+        let rehomed = rehome(b"source /Users/jane/.aliases\n# /Users/janet/x\n".to_vec(), Path::new("/Volumes/TM/Users/jane"), Path::new("/Users/jd"));
+        assert_eq!(rehomed, b"source /Users/jd/.aliases\n# /Users/janet/x\n");
+    }
+}
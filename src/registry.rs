@@ -0,0 +1,181 @@
+//! Shared dotfile templates and package bundles. A registry is an
+//! `index.json` listing templates; each file is fetched relative to the index
+//! and checked against its SHA-256 before anything is shown or written.
+
+use crate::{blobs, Dotfiles, KiwiError, Result};
+use serde::{Deserialize, Serialize};
+use similar::TextDiff;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Index {
+    pub templates: Vec<Entry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub author: Option<String>,
+    #[serde(default)]
+    pub files: Vec<RemoteFile>,
+    /// Homebrew packages the template expects
+    #[serde(default)]
+    pub packages: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemoteFile {
+    /// Where the file goes, relative to the home directory
+    pub path: String,
+    /// Location of the template, relative to the index
+    pub url: String,
+    pub sha256: String,
+}
+
+/// A template file that passed its checksum.
+pub struct Fetched {
+    pub path: String,
+    pub contents: String,
+}
+
+pub struct Registry {
+    client: reqwest::Client,
+    index_url: String,
+}
+
+impl Registry {
+    pub fn new(index_url: String) -> Self {
+        Self { client: reqwest::Client::new(), index_url }
+    }
+
+    pub async fn index(&self) -> Result<Index> {
+        let response = self.client.get(&self.index_url).send().await?;
+        if !response.status().is_success() {
+            return Err(KiwiError::Sync(format!("Failed to fetch template registry: {}", response.status())));
+        }
+        Ok(response.json().await?)
+    }
+
+    /// Downloads every file of `entry`, refusing files whose checksum doesn't
+    /// match the index or whose path would land outside the home directory.
+    pub async fn fetch(&self, entry: &Entry) -> Result<Vec<Fetched>> {
+        let mut fetched = Vec::new();
+        for file in &entry.files {
+            if !is_safe_path(&file.path) {
+                return Err(KiwiError::Sync(format!("Template {} has an unsafe path: {}", entry.name, file.path)));
+            }
+
+            let response = self.client.get(self.resolve(&file.url)).send().await?;
+            if !response.status().is_success() {
+                return Err(KiwiError::Sync(format!("Failed to download {}: {}", file.path, response.status())));
+            }
+            let data = response.bytes().await?;
+            let actual = blobs::hash(&data);
+            if actual != file.sha256 {
+                return Err(KiwiError::Sync(format!(
+                    "Checksum mismatch for {} in {} (expected {}, got {})",
+                    file.path, entry.name, file.sha256, actual
+                )));
+            }
+
+            let contents = String::from_utf8(data.to_vec())
+                .map_err(|_| KiwiError::Sync(format!("{} is not a text file", file.path)))?;
+            fetched.push(Fetched { path: file.path.clone(), contents });
+        }
+        Ok(fetched)
+    }
+
+    /// Absolute URLs are used as is; anything else is relative to the index.
+    fn resolve(&self, url: &str) -> String {
+        if url.starts_with("http://") || url.starts_with("https://") {
+            return url.to_string();
+        }
+        let base = self.index_url.rsplit_once('/').map_or(self.index_url.as_str(), |(base, _)| base);
+        format!("{}/{}", base, url.trim_start_matches('/'))
+    }
+}
+
+/// Entries whose name or description contains `query`, ignoring case.
+pub fn search<'a>(index: &'a Index, query: &str) -> Vec<&'a Entry> {
+    let query = query.to_lowercase();
+    index
+        .templates
+        .iter()
+        .filter(|entry| entry.name.to_lowercase().contains(&query) || entry.description.to_lowercase().contains(&query))
+        .collect()
+}
+
+/// A unified diff from `current` to `proposed` for review, labelled with `path`.
+pub fn diff(path: &str, current: &str, proposed: &str) -> String {
+    TextDiff::from_lines(current, proposed)
+        .unified_diff()
+        .header(&format!("a/{}", path), &format!("b/{}", path))
+        .to_string()
+}
+
+/// Writes fetched templates under `home` and renders them. New files are
+/// tracked as templates; templated dotfiles get a new template; other
+/// tracked files are overwritten in place. Returns the paths written.
+pub fn apply(files: &[Fetched], home: &Path, dotfiles: &Dotfiles, vars: &HashMap<String, String>) -> Result<Vec<PathBuf>> {
+    let tracked = dotfiles.list()?;
+    let mut written = Vec::new();
+
+    for file in files {
+        let path = home.join(&file.path);
+        let existing = path.canonicalize().ok().and_then(|canonical| tracked.iter().find(|d| d.path == canonical));
+        match existing {
+            Some(dotfile) if dotfile.is_template() => fs::write(dotfiles.source_for(dotfile), &file.contents)?,
+            Some(_) => fs::write(&path, &file.contents)?,
+            None => {
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::write(&path, &file.contents)?;
+                dotfiles.add_template(&path, None)?;
+            }
+        }
+        written.push(path.canonicalize()?);
+    }
+
+    dotfiles.render_paths(&written, vars)?;
+    Ok(written)
+}
+
+fn is_safe_path(path: &str) -> bool {
+    !path.is_empty() && Path::new(path).components().all(|c| matches!(c, Component::Normal(_)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_search_resolve_and_paths() {
+        let index: Index = serde_json::from_str(
+            r#"{"templates": [
+                {"name": "zsh-minimal", "description": "A tiny zshrc"},
+                {"name": "tmux-pro", "description": "Power-user TMUX setup"}
+            ]}"#,
+        )
+        .unwrap();
+        let names: Vec<&str> = search(&index, "tmux").iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, vec!["tmux-pro"]);
+        assert_eq!(search(&index, "ZSHRC").len(), 1);
+
+        let registry = Registry::new("https://example.com/kiwi/index.json".to_string());
+        assert_eq!(registry.resolve("files/zshrc"), "https://example.com/kiwi/files/zshrc");
+        assert_eq!(registry.resolve("https://cdn.test/x"), "https://cdn.test/x");
+
+        assert!(is_safe_path(".config/starship.toml"));
+        assert!(!is_safe_path("../.ssh/authorized_keys"));
+        assert!(!is_safe_path("/etc/hosts"));
+
+        let diff = diff(".zshrc", "a\nb\n", "a\nc\n");
+        assert!(diff.contains("-b") && diff.contains("+c"));
+    }
+}
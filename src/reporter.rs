@@ -0,0 +1,59 @@
+/// Receives progress and questions from long-running operations so callers
+/// can render them however they like. The CLI draws spinners and prompts;
+/// GUIs and scripts can supply their own implementation.
+pub trait Reporter {
+    /// A new step has started.
+    fn progress(&self, message: &str);
+
+    /// A step finished successfully.
+    fn success(&self, message: &str);
+
+    /// Something went wrong, but the operation is carrying on.
+    fn warning(&self, message: &str);
+
+    /// Asks whether a destructive or remote step should go ahead.
+    fn confirm(&self, prompt: &str) -> bool;
+
+    /// Whether `confirm` answers yes without asking anyone.
+    fn assumes_yes(&self) -> bool {
+        false
+    }
+
+    /// Asks which of `choices` to go with. `None` means no answer, and the
+    /// caller leaves things as they are.
+    fn choose(&self, _prompt: &str, _choices: &[&str]) -> Option<usize> {
+        None
+    }
+
+    /// `done` of `total` bytes have been transferred. The total can grow
+    /// while a transfer runs.
+    fn transfer(&self, _done: u64, _total: u64) {}
+
+    /// Runs `step` with any progress display out of the way, for steps that
+    /// write to the terminal themselves.
+    fn suspend(&self, step: &mut dyn FnMut()) {
+        step()
+    }
+}
+
+/// Discards all output and answers every confirmation with `assume_yes`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Silent {
+    pub assume_yes: bool,
+}
+
+impl Reporter for Silent {
+    fn progress(&self, _message: &str) {}
+
+    fn success(&self, _message: &str) {}
+
+    fn warning(&self, _message: &str) {}
+
+    fn confirm(&self, _prompt: &str) -> bool {
+        self.assume_yes
+    }
+
+    fn assumes_yes(&self) -> bool {
+        self.assume_yes
+    }
+}
@@ -0,0 +1,41 @@
+//! Rosetta 2, which Intel-only casks need on Apple Silicon.
+
+use crate::homebrew::{CommandRunner, SystemRunner};
+use crate::{KiwiError, Result};
+
+pub struct Rosetta {
+    runner: Box<dyn CommandRunner>,
+}
+
+impl Default for Rosetta {
+    fn default() -> Self {
+        Self { runner: Box::new(SystemRunner) }
+    }
+}
+
+impl Rosetta {
+    pub fn with_runner(runner: impl CommandRunner + 'static) -> Self {
+        Self { runner: Box::new(runner) }
+    }
+
+    /// Whether this machine is Apple Silicon, where Rosetta matters at all.
+    pub fn applies() -> bool {
+        std::env::consts::ARCH == "aarch64"
+    }
+
+    /// Whether Intel binaries can run, by running one.
+    pub fn is_installed(&self) -> bool {
+        self.runner.run("arch", &["-x86_64", "/usr/bin/true"]).is_ok_and(|output| output.success)
+    }
+
+    pub fn install(&self) -> Result<()> {
+        let output = self.runner.run("softwareupdate", &["--install-rosetta", "--agree-to-license"])?;
+        if !output.success {
+            return Err(KiwiError::Homebrew(format!(
+                "Couldn't install Rosetta 2: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,133 @@
+use crate::{Result, KiwiError};
+use std::fmt;
+use std::path::Path;
+use tokio::process::Command;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Formula,
+    Cask,
+    Mas,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Formula => write!(f, "formula"),
+            Source::Cask => write!(f, "cask"),
+            Source::Mas => write!(f, "mas"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchResult {
+    pub name: String,
+    pub description: Option<String>,
+    pub source: Source,
+    /// App Store identifier, only set for mas results
+    pub app_id: Option<String>,
+}
+
+/// Searches formulae, casks, and the Mac App Store concurrently. Sources whose
+/// tooling isn't installed (e.g. `mas`) are skipped rather than failing the search.
+pub async fn search(query: &str, brew: &Path) -> Result<Vec<SearchResult>> {
+    let brew = brew.to_string_lossy();
+    let formula_args = ["search", "--desc", "--eval-all", "--formula", query];
+    let cask_args = ["search", "--desc", "--eval-all", "--cask", query];
+    let mas_args = ["search", query];
+
+    let (formulae, casks, mas) = tokio::join!(
+        run(&brew, &formula_args),
+        run(&brew, &cask_args),
+        run("mas", &mas_args),
+    );
+
+    let mut results = Vec::new();
+    results.extend(parse_brew(&formulae.unwrap_or_default(), Source::Formula));
+    results.extend(parse_brew(&casks.unwrap_or_default(), Source::Cask));
+    results.extend(parse_mas(&mas.unwrap_or_default()));
+    Ok(results)
+}
+
+pub async fn install_mas(app_id: &str) -> Result<()> {
+    let output = Command::new("mas").args(["install", app_id]).output().await?;
+
+    if !output.status.success() {
+        return Err(KiwiError::PackageError {
+            name: app_id.to_string(),
+            message: String::from_utf8_lossy(&output.stderr).to_string(),
+        });
+    }
+    Ok(())
+}
+
+async fn run(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().await.ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+fn parse_brew(output: &str, source: Source) -> Vec<SearchResult> {
+    output
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with("==>"))
+        .map(|line| {
+            let (name, description) = match line.split_once(": ") {
+                Some((name, desc)) => (name, Some(desc.trim().to_string())),
+                None => (line, None),
+            };
+            SearchResult {
+                name: name.to_string(),
+                description,
+                source,
+                app_id: None,
+            }
+        })
+        .collect()
+}
+
+fn parse_mas(output: &str) -> Vec<SearchResult> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (id, rest) = line.trim().split_once(char::is_whitespace)?;
+            let rest = rest.trim();
+            // Lines look like `497799835  Xcode  (15.0)`
+            let name = match rest.rfind('(') {
+                Some(i) => rest[..i].trim(),
+                None => rest,
+            };
+            Some(SearchResult {
+                name: name.to_string(),
+                description: None,
+                source: Source::Mas,
+                app_id: Some(id.to_string()),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_search_output() {
+        let brew = "==> Formulae\njq: Lightweight and flexible command-line JSON processor\njqp\n";
+        let results = parse_brew(brew, Source::Formula);
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].name, "jq");
+        assert_eq!(results[0].description.as_deref(), Some("Lightweight and flexible command-line JSON processor"));
+        assert!(results[1].description.is_none());
+
+        let mas = "  497799835  Xcode          (15.0)\n  1295203466  Microsoft Remote Desktop  (10.9.4)\n";
+        let results = parse_mas(mas);
+        assert_eq!(results[0].name, "Xcode");
+        assert_eq!(results[1].app_id.as_deref(), Some("1295203466"));
+        assert_eq!(results[1].name, "Microsoft Remote Desktop");
+    }
+}
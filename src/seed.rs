@@ -0,0 +1,144 @@
+//! Offline seed bundles. A seed is a directory holding a Homebrew download
+//! cache prewarmed with every tracked package's bottles, dependencies, and
+//! cask downloads, made with `kiwi seed create` where the connection is good.
+//! `kiwi restore --seed` points brew at that cache, so installs on a Mac with
+//! a poor connection don't download them again. Bottles are built per macOS
+//! release and architecture, so a seed suits Macs like the one that made it.
+
+use crate::homebrew::Homebrew;
+use crate::reporter::Reporter;
+use crate::{KiwiError, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// What's in a seed, kept next to its cache.
+const MANIFEST: &str = "seed.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    /// When the seed was made, as RFC 3339
+    pub created: String,
+    /// `std::env::consts::ARCH` of the Mac that made it
+    pub arch: String,
+    pub packages: Vec<String>,
+    /// Taps still need the network to be added
+    pub taps: Vec<String>,
+    /// Packages that couldn't be downloaded
+    #[serde(default)]
+    pub failed: Vec<String>,
+}
+
+impl Manifest {
+    /// Whether the seed's bottles were built for this Mac's architecture.
+    pub fn matches_this_mac(&self) -> bool {
+        self.arch == std::env::consts::ARCH
+    }
+}
+
+/// The Homebrew cache inside the seed at `dir`.
+pub fn cache_dir(dir: &Path) -> PathBuf {
+    dir.join("cache")
+}
+
+/// Downloads every package `homebrew` tracks into a seed at `dir`, adding to
+/// one that's already there. Packages that fail are reported and listed in
+/// the manifest rather than stopping the rest.
+pub fn create(homebrew: Homebrew, dir: &Path, reporter: &dyn Reporter) -> Result<Manifest> {
+    let cache = cache_dir(dir);
+    fs::create_dir_all(&cache)?;
+    let homebrew = homebrew.with_download_cache(cache);
+
+    let mut manifest = Manifest {
+        created: chrono::Local::now().to_rfc3339(),
+        arch: std::env::consts::ARCH.to_string(),
+        packages: Vec::new(),
+        taps: homebrew.load_taps()?,
+        failed: Vec::new(),
+    };
+    let tracked = homebrew.tracked();
+    for (i, package) in tracked.iter().enumerate() {
+        reporter.progress(&format!("Downloading {} ({}/{})...", package.name, i + 1, tracked.len()));
+        match homebrew.fetch(&package.name, package.is_cask) {
+            Ok(()) => manifest.packages.push(package.name.clone()),
+            Err(e) => {
+                reporter.warning(&e.to_string());
+                manifest.failed.push(package.name.clone());
+            }
+        }
+    }
+
+    fs::write(dir.join(MANIFEST), serde_json::to_string_pretty(&manifest)?)?;
+    Ok(manifest)
+}
+
+/// The manifest of the seed at `dir`.
+pub fn open(dir: &Path) -> Result<Manifest> {
+    let contents = fs::read_to_string(dir.join(MANIFEST)).map_err(|_| {
+        KiwiError::ValidationError(format!("{} isn't a seed; make one with `kiwi seed create`", dir.display()))
+    })?;
+    Ok(serde_json::from_str(&contents)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::homebrew::{CommandOutput, CommandRunner, Package};
+    use crate::reporter::Silent;
+    use std::sync::{Arc, Mutex};
+
+    /// Records every command and fails fetches of `broken`.
+    #[derive(Clone, Default)]
+    struct Fetches(Arc<Mutex<Vec<String>>>);
+
+    impl CommandRunner for Fetches {
+        fn run(&self, program: &str, args: &[&str]) -> std::io::Result<CommandOutput> {
+            let line = format!("{} {}", program, args.join(" "));
+            let success = !line.ends_with("broken");
+            self.0.lock().unwrap().push(line);
+            Ok(CommandOutput { success, stdout: Vec::new(), stderr: b"Error: download failed".to_vec() })
+        }
+    }
+
+    #[test]
+    fn test_create_fills_the_cache_and_records_it() {
+        let dir = std::env::temp_dir().join(format!("kiwi-seed-test-{}", std::process::id()));
+        let packages: Vec<Package> = [("git", false), ("firefox", true), ("broken", false)]
+            .into_iter()
+            .map(|(name, is_cask)| Package {
+                name: name.to_string(),
+                version: None,
+                installed: true,
+                dependencies: Vec::new(),
+                install_time: None,
+                last_update: None,
+                size: None,
+                is_cask,
+                note: None,
+                held: false,
+                requires_rosetta: false,
+                post_install: Vec::new(),
+            })
+            .collect();
+        let runner = Fetches::default();
+        let mut homebrew = Homebrew::new(dir.join("state/packages.json")).with_runner(runner.clone());
+        fs::create_dir_all(dir.join("state")).unwrap();
+        homebrew.save_packages(&packages).unwrap();
+        homebrew.save_taps(&["acme/tools".to_string()]).unwrap();
+
+        assert!(open(&dir.join("seed")).is_err());
+        let manifest = create(homebrew, &dir.join("seed"), &Silent::default()).unwrap();
+        assert_eq!(manifest.packages, vec!["firefox".to_string(), "git".to_string()]);
+        assert_eq!(manifest.failed, vec!["broken".to_string()]);
+        assert_eq!(manifest.taps, vec!["acme/tools".to_string()]);
+        assert!(open(&dir.join("seed")).unwrap().matches_this_mac());
+
+        let calls = runner.0.lock().unwrap();
+        let cache = format!("HOMEBREW_CACHE={}", cache_dir(&dir.join("seed")).display());
+        assert!(calls.iter().all(|call| call.starts_with(&format!("env {} HOMEBREW_NO_AUTO_UPDATE=1 ", cache))));
+        assert!(calls.iter().any(|call| call.ends_with("fetch --cask firefox")));
+        assert!(calls.iter().any(|call| call.ends_with("fetch --deps git")));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,92 @@
+use crate::{Result, KiwiError};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The user's login shell, from `$SHELL`.
+pub fn detect() -> Option<String> {
+    let shell = std::env::var("SHELL").ok()?;
+    Path::new(&shell)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+}
+
+/// The rc file kiwi edits for a given shell.
+pub fn rc_file(shell: &str) -> Result<PathBuf> {
+    let home = dirs::home_dir().ok_or_else(|| {
+        KiwiError::Config("Could not find home directory".to_string())
+    })?;
+    match shell {
+        "zsh" => Ok(home.join(".zshrc")),
+        "bash" => Ok(home.join(".bashrc")),
+        "fish" => Ok(home.join(".config/fish/config.fish")),
+        _ => Err(KiwiError::ValidationError(format!("Unsupported shell: {}", shell))),
+    }
+}
+
+fn markers(name: &str) -> (String, String) {
+    (format!("# >>> kiwi {} >>>", name), format!("# <<< kiwi {} <<<", name))
+}
+
+/// Writes a marked block to an rc file, replacing any previous version of it.
+/// Returns false when the block was already present and unchanged.
+pub fn ensure_block(rc: &Path, name: &str, body: &str) -> Result<bool> {
+    let existing = fs::read_to_string(rc).unwrap_or_default();
+    let contents = with_block(&existing, name, body);
+    if contents == existing {
+        return Ok(false);
+    }
+
+    if let Some(parent) = rc.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(rc, contents)?;
+    Ok(true)
+}
+
+/// `contents` with the marked block holding `body`, appended when it
+/// wasn't there and left in place when it was already the same.
+pub fn with_block(contents: &str, name: &str, body: &str) -> String {
+    let (start, end) = markers(name);
+    let block = format!("{}\n{}\n{}\n", start, body.trim_end(), end);
+    if contents.contains(&block) {
+        return contents.to_string();
+    }
+
+    let mut result = without_block(contents, name);
+    if !result.is_empty() && !result.ends_with('\n') {
+        result.push('\n');
+    }
+    result.push_str(&block);
+    result
+}
+
+/// Removes a marked block from an rc file. Returns whether anything was removed.
+pub fn remove_block(rc: &Path, name: &str) -> Result<bool> {
+    let Ok(existing) = fs::read_to_string(rc) else {
+        return Ok(false);
+    };
+    let stripped = without_block(&existing, name);
+    if stripped == existing {
+        return Ok(false);
+    }
+    fs::write(rc, stripped)?;
+    Ok(true)
+}
+
+/// `contents` without the marked block.
+pub fn without_block(contents: &str, name: &str) -> String {
+    let (start, end) = markers(name);
+    let mut result = String::new();
+    let mut inside = false;
+    for line in contents.lines() {
+        if line.trim() == start {
+            inside = true;
+        } else if line.trim() == end {
+            inside = false;
+        } else if !inside {
+            result.push_str(line);
+            result.push('\n');
+        }
+    }
+    result
+}
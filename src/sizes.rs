@@ -0,0 +1,255 @@
+//! Download and installed sizes for packages before they're installed.
+//! `brew info --json=v2` names each formula's bottle and each cask's
+//! download; the sizes come from where brew would fetch them. A bottle's
+//! manifest in Homebrew's registry records both its download and installed
+//! size, and anything else is asked for its `Content-Length`.
+
+use futures_util::stream::{self, StreamExt};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// Homebrew's registry accepts this token for anonymous pulls.
+const REGISTRY_TOKEN: &str = "QQ==";
+/// macOS releases as bottle tags, newest first; the newest bottle for this
+/// architecture stands in when there's no bottle for every Mac.
+const MACOS_TAGS: &[&str] = &["tahoe", "sequoia", "sonoma", "ventura", "monterey", "big_sur", "catalina"];
+
+/// Where a package would be downloaded from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Download {
+    pub name: String,
+    pub url: String,
+    pub cask: bool,
+    /// The bottle's image index and its SHA-256, for formulae
+    pub manifest: Option<(String, String)>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PackageSize {
+    pub download: Option<u64>,
+    pub installed: Option<u64>,
+}
+
+/// Sizes for a set of packages, by name.
+#[derive(Debug, Clone, Default)]
+pub struct Estimate {
+    pub sizes: HashMap<String, PackageSize>,
+}
+
+impl Estimate {
+    pub fn download(&self) -> u64 {
+        self.sizes.values().filter_map(|size| size.download).sum()
+    }
+
+    pub fn installed(&self) -> u64 {
+        self.sizes.values().filter_map(|size| size.installed).sum()
+    }
+
+    /// Names whose download size couldn't be found, sorted.
+    pub fn unknown(&self) -> Vec<&str> {
+        let mut names: Vec<&str> =
+            self.sizes.iter().filter(|(_, size)| size.download.is_none()).map(|(name, _)| name.as_str()).collect();
+        names.sort();
+        names
+    }
+}
+
+#[derive(Deserialize)]
+struct InfoV2 {
+    #[serde(default)]
+    formulae: Vec<FormulaInfo>,
+    #[serde(default)]
+    casks: Vec<CaskInfo>,
+}
+
+#[derive(Deserialize)]
+struct FormulaInfo {
+    name: String,
+    versions: Versions,
+    #[serde(default)]
+    revision: u32,
+    #[serde(default)]
+    bottle: HashMap<String, BottleSpec>,
+}
+
+#[derive(Deserialize)]
+struct Versions {
+    stable: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct BottleSpec {
+    #[serde(default)]
+    rebuild: u32,
+    #[serde(default)]
+    files: HashMap<String, BottleFile>,
+}
+
+#[derive(Deserialize)]
+struct BottleFile {
+    url: String,
+    sha256: String,
+}
+
+#[derive(Deserialize)]
+struct CaskInfo {
+    token: String,
+    url: Option<String>,
+}
+
+/// The downloads in `brew info --json=v2` output, choosing bottles for
+/// `arch` (`std::env::consts::ARCH`). Formulae without a bottle build from
+/// source and are left out.
+pub fn downloads(info: &[u8], arch: &str) -> crate::Result<Vec<Download>> {
+    let info: InfoV2 = serde_json::from_slice(info)?;
+    let prefix = if arch == "aarch64" { "arm64_" } else { "" };
+
+    let mut downloads = Vec::new();
+    for formula in info.formulae {
+        let Some(bottle) = formula.bottle.get("stable") else { continue };
+        let tag = std::iter::once("all".to_string())
+            .chain(MACOS_TAGS.iter().map(|release| format!("{}{}", prefix, release)))
+            .find(|tag| bottle.files.contains_key(tag));
+        let (Some(file), Some(version)) = (tag.and_then(|tag| bottle.files.get(&tag)), formula.versions.stable) else {
+            continue;
+        };
+
+        // Images are tagged with the package version, and the rebuild when there is one
+        let mut image_tag = version;
+        if formula.revision > 0 {
+            image_tag.push_str(&format!("_{}", formula.revision));
+        }
+        if bottle.rebuild > 0 {
+            image_tag.push_str(&format!(".{}", bottle.rebuild));
+        }
+        let manifest = file
+            .url
+            .split_once("/blobs/")
+            .map(|(repository, _)| (format!("{}/manifests/{}", repository, image_tag.replace('+', "_")), file.sha256.clone()));
+        downloads.push(Download { name: formula.name, url: file.url.clone(), cask: false, manifest });
+    }
+    for cask in info.casks {
+        if let Some(url) = cask.url {
+            downloads.push(Download { name: cask.token, url, cask: true, manifest: None });
+        }
+    }
+    Ok(downloads)
+}
+
+#[derive(Deserialize)]
+struct ImageIndex {
+    #[serde(default)]
+    manifests: Vec<ImageManifest>,
+}
+
+#[derive(Deserialize)]
+struct ImageManifest {
+    #[serde(default)]
+    annotations: HashMap<String, String>,
+}
+
+/// The download and installed size an image index records for the bottle
+/// with `sha256`.
+fn bottle_size(index: &[u8], sha256: &str) -> Option<PackageSize> {
+    let index: ImageIndex = serde_json::from_slice(index).ok()?;
+    let annotations = index
+        .manifests
+        .into_iter()
+        .map(|manifest| manifest.annotations)
+        .find(|annotations| annotations.get("sh.brew.bottle.digest").is_some_and(|digest| digest == sha256))?;
+    let size = |key: &str| annotations.get(key).and_then(|value| value.parse().ok());
+    Some(PackageSize { download: size("sh.brew.bottle.size"), installed: size("sh.brew.bottle.installed_size") })
+}
+
+/// Looks up every download's size, `parallel` at a time. Lookups that fail
+/// leave that package's sizes unknown.
+pub async fn estimate(downloads: &[Download], parallel: usize) -> Estimate {
+    let client = reqwest::Client::new();
+    let sizes = stream::iter(downloads)
+        .map(|download| {
+            let client = &client;
+            async move { (download.name.clone(), size_of(client, download).await) }
+        })
+        .buffer_unordered(parallel.max(1))
+        .collect()
+        .await;
+    Estimate { sizes }
+}
+
+async fn size_of(client: &reqwest::Client, download: &Download) -> PackageSize {
+    if let Some((url, sha256)) = &download.manifest {
+        let request = client
+            .get(url)
+            .bearer_auth(REGISTRY_TOKEN)
+            .header(reqwest::header::ACCEPT, "application/vnd.oci.image.index.v1+json");
+        if let Ok(response) = request.send().await {
+            if response.status().is_success() {
+                if let Some(size) = response.bytes().await.ok().and_then(|index| bottle_size(&index, sha256)) {
+                    return size;
+                }
+            }
+        }
+    }
+
+    let mut request = client.head(&download.url);
+    if download.manifest.is_some() {
+        request = request.bearer_auth(REGISTRY_TOKEN);
+    }
+    match request.send().await {
+        Ok(response) if response.status().is_success() => PackageSize { download: response.content_length(), installed: None },
+        Ok(response) => {
+            log::debug!("Couldn't size {}: {}", download.name, response.status());
+            PackageSize::default()
+        }
+        Err(e) => {
+            log::debug!("Couldn't size {}: {}", download.name, e);
+            PackageSize::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_downloads_and_bottle_sizes() {
+        let info = br#"{
+            "formulae": [
+                {"name": "git", "versions": {"stable": "2.45.0"}, "revision": 1, "bottle": {"stable": {"rebuild": 2, "files": {
+                    "arm64_sonoma": {"url": "https://ghcr.io/v2/homebrew/core/git/blobs/sha256:aaa", "sha256": "aaa"},
+                    "arm64_sequoia": {"url": "https://ghcr.io/v2/homebrew/core/git/blobs/sha256:bbb", "sha256": "bbb"},
+                    "sonoma": {"url": "https://ghcr.io/v2/homebrew/core/git/blobs/sha256:ccc", "sha256": "ccc"}
+                }}}},
+                {"name": "from-source", "versions": {"stable": "1.0"}, "bottle": {}}
+            ],
+            "casks": [{"token": "firefox", "url": "https://download.mozilla.org/firefox.dmg"}]
+        }"#;
+
+        let arm = downloads(info, "aarch64").unwrap();
+        assert_eq!(arm.len(), 2);
+        assert_eq!(arm[0].url, "https://ghcr.io/v2/homebrew/core/git/blobs/sha256:bbb");
+        assert_eq!(
+            arm[0].manifest,
+            Some(("https://ghcr.io/v2/homebrew/core/git/manifests/2.45.0_1.2".to_string(), "bbb".to_string()))
+        );
+        assert!(arm[1].cask && arm[1].manifest.is_none());
+        assert_eq!(downloads(info, "x86_64").unwrap()[0].url, "https://ghcr.io/v2/homebrew/core/git/blobs/sha256:ccc");
+
+        let index = br#"{"manifests": [
+            {"annotations": {"sh.brew.bottle.digest": "aaa", "sh.brew.bottle.size": "1", "sh.brew.bottle.installed_size": "2"}},
+            {"annotations": {"sh.brew.bottle.digest": "bbb", "sh.brew.bottle.size": "9000000", "sh.brew.bottle.installed_size": "52000000"}}
+        ]}"#;
+        assert_eq!(bottle_size(index, "bbb"), Some(PackageSize { download: Some(9_000_000), installed: Some(52_000_000) }));
+        assert_eq!(bottle_size(index, "zzz"), None);
+
+        let estimate = Estimate {
+            sizes: HashMap::from([
+                ("git".to_string(), PackageSize { download: Some(9), installed: Some(52) }),
+                ("firefox".to_string(), PackageSize { download: None, installed: None }),
+            ]),
+        };
+        assert_eq!((estimate.download(), estimate.installed()), (9, 52));
+        assert_eq!(estimate.unknown(), vec!["firefox"]);
+    }
+}
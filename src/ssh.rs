@@ -0,0 +1,150 @@
+//! SSH keys for a new Mac. Private keys are never synced (see `sensitive`),
+//! so restores that clone repos stall until the new Mac has its own key;
+//! this generates one, hands it to the agent and Keychain, and can register
+//! the public half with GitHub.
+
+use crate::homebrew::{CommandRunner, SystemRunner};
+use crate::{shell, KiwiError, Result};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+const GITHUB_KEYS_URL: &str = "https://api.github.com/user/keys";
+
+/// Tells ssh to load keys into the agent with their passphrase from the
+/// Keychain, so it isn't asked for after every login.
+const KEYCHAIN_CONFIG: &str = "Host *\n  AddKeysToAgent yes\n  UseKeychain yes";
+
+pub struct SshKeys {
+    runner: Box<dyn CommandRunner>,
+    dir: PathBuf,
+}
+
+impl Default for SshKeys {
+    fn default() -> Self {
+        Self::new(dirs::home_dir().unwrap_or_default().join(".ssh"))
+    }
+}
+
+impl SshKeys {
+    /// Keys in `dir`, normally `~/.ssh`.
+    pub fn new(dir: PathBuf) -> Self {
+        Self { runner: Box::new(SystemRunner), dir }
+    }
+
+    pub fn with_runner(mut self, runner: impl CommandRunner + 'static) -> Self {
+        self.runner = Box::new(runner);
+        self
+    }
+
+    /// Private keys that have a public half next to them.
+    pub fn existing(&self) -> Vec<PathBuf> {
+        let Ok(entries) = std::fs::read_dir(&self.dir) else {
+            return Vec::new();
+        };
+        let mut keys: Vec<PathBuf> = entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.file_name().is_some_and(|name| name.to_string_lossy().starts_with("id_")))
+            .filter(|path| path.extension().is_none() && path.with_extension("pub").exists())
+            .collect();
+        keys.sort();
+        keys
+    }
+
+    /// Generates an ed25519 key. ssh-keygen asks for the passphrase on the
+    /// terminal itself, so it never passes through kiwi.
+    pub fn generate(&self, comment: &str) -> Result<PathBuf> {
+        let key = self.dir.join("id_ed25519");
+        if key.exists() {
+            return Err(KiwiError::ValidationError(format!("{} already exists", key.display())));
+        }
+        std::fs::create_dir_all(&self.dir)?;
+        let path = key.to_string_lossy();
+        if !self.runner.run_interactive("ssh-keygen", &["-t", "ed25519", "-C", comment, "-f", &path])? {
+            return Err(KiwiError::Config("ssh-keygen didn't create a key".to_string()));
+        }
+        Ok(key)
+    }
+
+    /// Adds `key` to the agent with its passphrase stored in the Keychain,
+    /// and has `~/.ssh/config` load it that way after every login.
+    pub fn add_to_agent(&self, key: &Path) -> Result<()> {
+        if !self.runner.run_interactive("ssh-add", &["--apple-use-keychain", &key.to_string_lossy()])? {
+            return Err(KiwiError::Config(format!("ssh-add couldn't add {}", key.display())));
+        }
+        shell::ensure_block(&self.dir.join("config"), "keychain", KEYCHAIN_CONFIG)?;
+        Ok(())
+    }
+}
+
+/// The public half of `key`.
+pub fn public_key(key: &Path) -> Result<String> {
+    Ok(std::fs::read_to_string(key.with_extension("pub"))?.trim().to_string())
+}
+
+/// Puts `text` on the clipboard. Returns whether it worked.
+pub fn copy_to_clipboard(text: &str) -> bool {
+    let Ok(mut child) = Command::new("pbcopy").stdin(Stdio::piped()).spawn() else {
+        return false;
+    };
+    let written = child.stdin.take().is_some_and(|mut stdin| stdin.write_all(text.as_bytes()).is_ok());
+    child.wait().is_ok_and(|status| status.success()) && written
+}
+
+/// Registers `public_key` with the GitHub account `token` belongs to. The
+/// token needs the `write:public_key` scope.
+pub async fn upload_to_github(token: &str, title: &str, public_key: &str) -> Result<()> {
+    let response = reqwest::Client::new()
+        .post(GITHUB_KEYS_URL)
+        .bearer_auth(token)
+        .header(reqwest::header::USER_AGENT, "kiwi")
+        .header(reqwest::header::ACCEPT, "application/vnd.github+json")
+        .json(&serde_json::json!({ "title": title, "key": public_key }))
+        .send()
+        .await?;
+    match response.status().as_u16() {
+        200..=299 => Ok(()),
+        401 | 403 => Err(KiwiError::Sync("GitHub rejected the token; it needs the write:public_key scope".to_string())),
+        422 => Err(KiwiError::Sync("GitHub already has this key".to_string())),
+        status => Err(KiwiError::Sync(format!("GitHub returned {}", status))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::homebrew::CommandOutput;
+    use std::fs;
+
+    /// Writes a key pair where ssh-keygen would.
+    struct Keygen;
+
+    impl CommandRunner for Keygen {
+        fn run(&self, program: &str, args: &[&str]) -> std::io::Result<CommandOutput> {
+            if program == "ssh-keygen" {
+                let path = args.last().copied().unwrap_or_default();
+                fs::write(path, "private\n")?;
+                fs::write(format!("{}.pub", path), "ssh-ed25519 AAAA jane@mac\n")?;
+            }
+            Ok(CommandOutput { success: true, stdout: Vec::new(), stderr: Vec::new() })
+        }
+    }
+
+    #[test]
+    fn test_generate_and_add_to_agent() {
+        let dir = std::env::temp_dir().join(format!("kiwi-ssh-test-{}", std::process::id()));
+        let keys = SshKeys::new(dir.clone()).with_runner(Keygen);
+        assert!(keys.existing().is_empty());
+
+        let key = keys.generate("jane@mac").unwrap();
+        assert_eq!(keys.existing(), vec![key.clone()]);
+        assert_eq!(public_key(&key).unwrap(), "ssh-ed25519 AAAA jane@mac");
+        assert!(keys.generate("jane@mac").is_err());
+
+        keys.add_to_agent(&key).unwrap();
+        assert!(fs::read_to_string(dir.join("config")).unwrap().contains("UseKeychain yes"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
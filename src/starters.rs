@@ -0,0 +1,154 @@
+//! Curated starter configs offered by `kiwi init`. Each pack is written as
+//! templated dotfiles the user owns afterwards; existing files are never
+//! overwritten.
+
+use crate::{Dotfiles, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub struct Pack {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// Home-relative path and template contents
+    pub files: &'static [(&'static str, &'static str)],
+    /// Homebrew packages the files expect
+    pub packages: &'static [&'static str],
+}
+
+pub const PACKS: &[Pack] = &[
+    Pack {
+        name: "zsh",
+        description: "zsh with sane history, completion, and the starship prompt",
+        files: &[(".zshrc", ZSHRC), (".config/starship.toml", STARSHIP)],
+        packages: &["starship"],
+    },
+    Pack {
+        name: "tmux",
+        description: "tmux with mouse support, vi keys, and 1-based windows",
+        files: &[(".tmux.conf", TMUX)],
+        packages: &["tmux"],
+    },
+    Pack {
+        name: "git",
+        description: "git aliases and defaults, with identity kept in ~/.gitconfig.local",
+        files: &[(".gitconfig", GITCONFIG)],
+        packages: &["git"],
+    },
+];
+
+pub fn find(name: &str) -> Option<&'static Pack> {
+    PACKS.iter().find(|pack| pack.name == name)
+}
+
+/// What applying a pack did.
+#[derive(Debug, Default)]
+pub struct Applied {
+    pub written: Vec<PathBuf>,
+    /// Files left alone because something was already there
+    pub skipped: Vec<PathBuf>,
+}
+
+/// Writes the pack's files under `home` and tracks them as templates,
+/// rendering them with `vars`.
+pub fn apply(pack: &Pack, home: &Path, dotfiles: &Dotfiles, vars: &HashMap<String, String>) -> Result<Applied> {
+    let mut applied = Applied::default();
+    for (relative, template) in pack.files {
+        let path = home.join(relative);
+        if path.symlink_metadata().is_ok() {
+            applied.skipped.push(path);
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, template)?;
+        dotfiles.add_template(&path, None)?;
+        // Tracked paths are canonical, so render by the same form
+        applied.written.push(path.canonicalize()?);
+    }
+
+    dotfiles.render_paths(&applied.written, vars)?;
+    Ok(applied)
+}
+
+const ZSHRC: &str = r#"# Starter config from kiwi; edit freely, it's yours now.
+{{#if arch == "arm64"}}eval "$(/opt/homebrew/bin/brew shellenv)"{{else}}eval "$(/usr/local/bin/brew shellenv)"{{/if}}
+
+HISTFILE="$HOME/.zsh_history"
+HISTSIZE=50000
+SAVEHIST=50000
+setopt SHARE_HISTORY HIST_IGNORE_DUPS HIST_IGNORE_SPACE
+
+autoload -Uz compinit && compinit
+zstyle ':completion:*' matcher-list 'm:{a-z}={A-Z}'
+
+export EDITOR=vim
+export KIWI_ENV="{{ environment }}"
+
+alias ll='ls -lah'
+alias ..='cd ..'
+
+eval "$(starship init zsh)"
+"#;
+
+const STARSHIP: &str = r#"# Starter config from kiwi
+add_newline = false
+
+[character]
+success_symbol = "[❯](green)"
+error_symbol = "[❯](red)"
+
+[directory]
+truncation_length = 3
+"#;
+
+const TMUX: &str = r##"# Starter config from kiwi
+set -g mouse on
+set -g base-index 1
+setw -g pane-base-index 1
+setw -g mode-keys vi
+set -g history-limit 50000
+set -sg escape-time 10
+set -g default-terminal "tmux-256color"
+
+bind r source-file ~/.tmux.conf \; display "Reloaded"
+bind | split-window -h -c "#{pane_current_path}"
+bind - split-window -v -c "#{pane_current_path}"
+"##;
+
+const GITCONFIG: &str = r#"# Starter config from kiwi. Put your name and email in ~/.gitconfig.local
+[include]
+	path = ~/.gitconfig.local
+[init]
+	defaultBranch = main
+[pull]
+	rebase = true
+[push]
+	autoSetupRemote = true
+[alias]
+	st = status -sb
+	co = checkout
+	br = branch
+	lg = log --oneline --graph --decorate
+	amend = commit --amend --no-edit
+	undo = reset --soft HEAD~1
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_packs_render_on_any_machine() {
+        let vars: HashMap<String, String> = ["environment", "user", "home", "os_version", "os_name", "arch", "hostname", "shell"]
+            .iter()
+            .map(|name| (name.to_string(), String::new()))
+            .collect();
+        for pack in PACKS {
+            for (path, template) in pack.files {
+                assert!(crate::template::render(template, &vars).is_ok(), "{} in {}", path, pack.name);
+            }
+        }
+    }
+}
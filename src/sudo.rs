@@ -0,0 +1,33 @@
+//! Writing files that belong to root or another user, like `/etc/zshrc` or
+//! `/etc/hosts`. Contents are staged somewhere kiwi can write, and only the
+//! copy runs through `sudo`, which asks for a password in the terminal.
+
+use crate::homebrew::CommandRunner;
+use crate::{KiwiError, Result};
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+/// Whether `path` belongs to a different user than `reference`, one of
+/// kiwi's own files, so kiwi can't count on writing it. Missing files don't.
+pub fn is_foreign(path: &Path, reference: &Path) -> bool {
+    match (fs::metadata(path), fs::metadata(reference)) {
+        (Ok(file), Ok(ours)) => file.uid() != ours.uid(),
+        _ => false,
+    }
+}
+
+/// Replaces `path` with `contents` through `sudo cp`, staging them in
+/// `staging` first. `cp` keeps the file's owner and mode.
+pub fn write(runner: &dyn CommandRunner, staging: &Path, path: &Path, contents: &[u8]) -> Result<()> {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let staged = staging.join(format!(".{}.staged", file_name));
+    fs::create_dir_all(staging)?;
+    fs::write(&staged, contents)?;
+    let copied = runner.run_interactive("sudo", &["cp", &staged.to_string_lossy(), &path.to_string_lossy()]);
+    let _ = fs::remove_file(&staged);
+    if !copied? {
+        return Err(KiwiError::PermissionDenied { path: path.to_path_buf() });
+    }
+    Ok(())
+}
@@ -1,104 +1,1266 @@
-use std::path::PathBuf;
-use crate::Result;
-use reqwest::Client;
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, Instant};
+use crate::{api, Result, Dotfiles, KiwiError};
+use crate::dotfiles::{self, LinkMode, SyncState};
+use crate::validate::Validator;
+use crate::backups::Backups;
+use crate::sensitive::{self, Sensitivity};
+use crate::blobs::{self, BlobStore};
+use crate::reporter::{Reporter, Silent};
+use crate::link::LinkCode;
+use crate::table::format_size;
+use crate::homebrew::{BrewEnvironment, Package, is_excluded};
+use crate::xattrs::{Attributes, Xattrs};
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
 use serde::{Deserialize, Serialize};
 use std::fs;
 
+const NDJSON: &str = "application/x-ndjson";
+/// Package and tap names as of the last sync, so a two-way sync can tell
+/// what one side dropped from what the other added.
+pub const LAST_SYNC_FILE: &str = "last-sync.json";
+/// How often pull reports progress, in manifest entries
+const PROGRESS_INTERVAL: usize = 50;
+/// How often uploads report bytes sent
+const TRANSFER_TICK: Duration = Duration::from_millis(100);
+/// Size of the pieces request bodies are streamed in
+const UPLOAD_CHUNK: usize = 64 * 1024;
+const MAX_ATTEMPTS: u32 = 3;
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+/// Longest `Retry-After` worth waiting out before handing the error back
+const MAX_RETRY_WAIT: Duration = Duration::from_secs(30);
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SyncConfig {
     pub url: String,
     pub token: String,
+    #[serde(default)]
+    pub excluded_packages: Vec<String>,
 }
 
+/// One line of a streamed (NDJSON) manifest.
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ManifestEntry {
+    Tap { name: String },
+    Package { package: Package },
+    /// A tracked dotfile sent inline, keyed by its name in the dotfiles dir
+    File { path: String, content: String },
+    /// A tracked dotfile stored as a content-addressed blob
+    Blob { path: String, hash: String },
+    /// An oversized dotfile whose contents never leave the machine
+    Pointer { path: String, pointer: FilePointer },
+    /// The pushing machine's brew prefix and HOMEBREW_* variables
+    Brew { environment: BrewEnvironment },
+    /// Finder tags and flags of a tracked dotfile
+    Xattrs { path: String, attributes: Attributes },
+}
+
+impl ManifestEntry {
+    /// The dotfiles dir name of a file entry.
+    fn file_name(&self) -> Option<&str> {
+        match self {
+            ManifestEntry::File { path, .. }
+            | ManifestEntry::Blob { path, .. }
+            | ManifestEntry::Pointer { path, .. }
+            | ManifestEntry::Xattrs { path, .. } => Some(path),
+            _ => None,
+        }
+    }
+}
+
+/// What is synced for a file added with `--pointer`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FilePointer {
+    pub hash: String,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SyncData {
+    /// Inline file contents, used when the server doesn't store blobs
+    #[serde(default)]
     pub files: std::collections::HashMap<String, String>,
+    /// File name to blob hash; contents are transferred via `/blobs`
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub blobs: HashMap<String, String>,
+    /// File name to pointer for files kept out of sync because of their size
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub pointers: HashMap<String, FilePointer>,
     pub packages: Vec<crate::homebrew::Package>,
+    #[serde(default)]
+    pub taps: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub brew: Option<BrewEnvironment>,
+    /// File name to the extended attributes synced with it
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub xattrs: HashMap<String, Attributes>,
+}
+
+/// Tracked dotfiles as push sees them, keyed by dotfiles dir name.
+struct TrackedFiles {
+    /// Path and hash of files whose contents are synced
+    files: HashMap<String, (PathBuf, String)>,
+    pointers: HashMap<String, FilePointer>,
+    /// Synced attributes of files that have any
+    xattrs: HashMap<String, Attributes>,
+    /// Credential files left out because they aren't encrypted
+    withheld: HashSet<String>,
+}
+
+/// How a push treats changes made elsewhere.
+#[derive(Debug, Clone, Default)]
+pub struct PushOptions {
+    /// Overwrite remote files that another machine changed since this one
+    /// last synced them
+    pub force: bool,
+}
+
+/// How a two-way sync settles conflicts.
+#[derive(Debug, Clone, Default)]
+pub struct MergeOptions {
+    /// Keep this machine's version of files changed on both sides, without asking
+    pub prefer_local: bool,
+    /// When not empty, push this machine's version of just these files (by
+    /// dotfiles dir name) over the remote's, leaving the other files alone
+    pub adopt: Vec<String>,
+}
+
+/// What a two-way sync moved, by dotfiles dir name.
+#[derive(Debug, Clone, Default)]
+pub struct MergeSummary {
+    pub pushed: Vec<String>,
+    pub pulled: Vec<String>,
+    /// Changed on both sides and left alone
+    pub conflicts: Vec<String>,
+    /// Deleted here since the last sync and dropped from the remote
+    pub dropped: Vec<String>,
+}
+
+/// The package and tap names both sides agreed on at the last sync, and
+/// the hash of each file the remote held then.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct LastSync {
+    packages: HashSet<String>,
+    taps: HashSet<String>,
+    #[serde(default)]
+    files: HashMap<String, String>,
+}
+
+/// The manifest files push sends along with dotfiles.
+struct LocalManifest {
+    packages: Vec<Package>,
+    taps: Vec<String>,
+    brew: Option<BrewEnvironment>,
+}
+
+/// How a pull treats what's already on this machine.
+#[derive(Debug, Clone, Default)]
+pub struct PullOptions {
+    /// Leave files that already exist locally alone
+    pub prefer_local: bool,
+    /// Apply files even when they fail validation, and replace files changed
+    /// locally since the last sync after backing them up
+    pub force: bool,
+    /// When not empty, apply only these files (by dotfiles dir name) and
+    /// leave the package manifest alone
+    pub only: Vec<String>,
+}
+
+#[derive(Default)]
+struct PulledState {
+    options: PullOptions,
+    backups: Option<Backups>,
+    packages: HashMap<String, Package>,
+    taps: Vec<String>,
+    blobs: Vec<(String, String)>,
+    brew: Option<BrewEnvironment>,
+    entries: usize,
+    /// Tracked files changed locally since their last sync, with their current hash
+    modified: HashMap<String, String>,
+    /// Files written by this pull and the hash of what was written
+    applied: HashMap<String, String>,
+    /// Locally changed files to replace anyway, as chosen for a conflict
+    replace: HashSet<String>,
+    /// Files whose local version was chosen over the pulled one, to push
+    adopted: Vec<String>,
+    xattrs: HashMap<String, Attributes>,
+    /// Hash of every file the remote holds, pulled or not
+    remote: HashMap<String, String>,
+}
+
+impl SyncData {
+    /// Flattens the manifest into the entries used by the streamed format.
+    pub fn into_entries(self) -> impl Iterator<Item = ManifestEntry> {
+        self.taps.into_iter().map(|name| ManifestEntry::Tap { name })
+            .chain(self.packages.into_iter().map(|package| ManifestEntry::Package { package }))
+            .chain(self.files.into_iter().map(|(path, content)| ManifestEntry::File { path, content }))
+            .chain(self.blobs.into_iter().map(|(path, hash)| ManifestEntry::Blob { path, hash }))
+            .chain(self.pointers.into_iter().map(|(path, pointer)| ManifestEntry::Pointer { path, pointer }))
+            .chain(self.brew.map(|environment| ManifestEntry::Brew { environment }))
+            .chain(self.xattrs.into_iter().map(|(path, attributes)| ManifestEntry::Xattrs { path, attributes }))
+    }
+}
+
+/// What `Sync::check_remote` found out about the server.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RemoteStatus {
+    pub reachable: bool,
+    pub authenticated: bool,
+    /// Round trip of the unauthenticated health request
+    pub latency: Option<Duration>,
+    pub server_version: Option<String>,
+    pub quota: Option<Quota>,
+    /// Why the check stopped short, if it did
+    pub error: Option<String>,
+}
+
+impl RemoteStatus {
+    pub fn is_healthy(&self) -> bool {
+        self.reachable && self.authenticated
+    }
+}
+
+/// Storage used by the account, in bytes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Quota {
+    pub used: u64,
+    /// `None` when the account has no limit
+    #[serde(default)]
+    pub limit: Option<u64>,
+}
+
+impl Quota {
+    /// Bytes left before the limit, or `None` without one.
+    pub fn remaining(&self) -> Option<u64> {
+        self.limit.map(|limit| limit.saturating_sub(self.used))
+    }
+
+    /// Whether 90% or more of the limit is used.
+    pub fn is_nearly_full(&self) -> bool {
+        self.limit.is_some_and(|limit| limit > 0 && self.used.saturating_mul(10) >= limit.saturating_mul(9))
+    }
+}
+
+/// One push in the account's history, as `/activity` reports it. The
+/// server works out what changed by comparing each push with the last.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Activity {
+    /// When the server took the push, as RFC 3339
+    pub at: String,
+    /// The pushing machine's name; pushes from older clients have none
+    #[serde(default)]
+    pub machine: Option<String>,
+    /// Dotfiles added, changed, or removed
+    #[serde(default)]
+    pub files: Vec<String>,
+    #[serde(default)]
+    pub packages_added: Vec<String>,
+    #[serde(default)]
+    pub packages_removed: Vec<String>,
+}
+
+impl Activity {
+    /// Whether the push changed nothing, like a repeated push.
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty() && self.packages_added.is_empty() && self.packages_removed.is_empty()
+    }
+}
+
+/// Storage usage as `/account/usage` reports it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Usage {
+    #[serde(flatten)]
+    pub quota: Quota,
+    /// Bytes stored per synced file name; servers that don't itemize
+    /// usage leave this empty
+    #[serde(default)]
+    pub files: HashMap<String, u64>,
+}
+
+impl Usage {
+    /// The `count` files taking the most space, largest first.
+    pub fn largest(&self, count: usize) -> Vec<(&str, u64)> {
+        let mut files: Vec<(&str, u64)> = self.files.iter().map(|(name, size)| (name.as_str(), *size)).collect();
+        files.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        files.truncate(count);
+        files
+    }
+}
+
+#[derive(Deserialize)]
+struct Health {
+    #[serde(default)]
+    version: Option<String>,
 }
 
 pub struct Sync {
     client: Client,
     config: SyncConfig,
     base_dir: PathBuf,
+    validator: Validator,
+    /// Back files up before a pull overwrites them
+    backup: bool,
+    /// Sent with pushes so the activity log can say who made them
+    machine: Option<String>,
+}
+
+/// Strips the trailing `/sync` older configs carry; `sync_url` once named
+/// the sync endpoint itself rather than the server it lives on.
+fn base_url(url: &str) -> &str {
+    let url = url.trim_end_matches('/');
+    url.strip_suffix("/sync").unwrap_or(url)
 }
 
 impl Sync {
-    pub fn new(config: SyncConfig, base_dir: PathBuf) -> Self {
+    pub fn new(mut config: SyncConfig, base_dir: PathBuf) -> Self {
+        config.url = base_url(&config.url).to_string();
         Self {
             client: Client::new(),
             config,
             base_dir,
+            validator: Validator::default(),
+            backup: false,
+            machine: None,
         }
     }
 
-    pub async fn check_remote_access(&self) -> Result<()> {
-        let response = self.client
-            .head(&self.config.url)
-            .header("Authorization", self.get_auth_header())
-            .send()
-            .await?;
+    /// Names this machine in the server's activity log.
+    pub fn with_machine(mut self, name: impl Into<String>) -> Self {
+        self.machine = Some(name.into());
+        self
+    }
 
-        if !response.status().is_success() {
-            return Err(format!("Failed to access remote: {}", response.status()).into());
+    /// Copies files to the managed backups area before a pull overwrites them.
+    pub fn with_backups(mut self, enabled: bool) -> Self {
+        self.backup = enabled;
+        self
+    }
+
+    pub fn with_validator(mut self, validator: Validator) -> Self {
+        self.validator = validator;
+        self
+    }
+
+    /// Checks the server without changing anything: whether it answers,
+    /// whether it takes the token, and what it says about itself. Fields a
+    /// server doesn't report are left empty rather than failing the check.
+    pub async fn check_remote(&self) -> RemoteStatus {
+        let mut status = RemoteStatus::default();
+
+        let started = Instant::now();
+        match api::send(self.client.get(self.endpoint("health"))).await {
+            Ok(response) => {
+                status.reachable = true;
+                status.latency = Some(started.elapsed());
+                if response.status().is_success() {
+                    status.server_version = response.json::<Health>().await.ok().and_then(|health| health.version);
+                }
+            }
+            Err(e) => {
+                status.error = Some(e.to_string());
+                return status;
+            }
         }
-        Ok(())
+
+        // Every server version serves the manifest behind the token; only the
+        // status matters, so the body is never read
+        match self.send(self.client.get(self.endpoint("sync"))).await {
+            Ok(response) if response.status().is_success() => status.authenticated = true,
+            Ok(response) if matches!(response.status(), StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN) => {
+                status.error = Some(api::failure(response, "The server rejected the sync token").await);
+                return status;
+            }
+            Ok(response) => {
+                status.error = Some(api::failure(response, "Failed to access remote").await);
+                return status;
+            }
+            Err(e) => {
+                status.error = Some(e.to_string());
+                return status;
+            }
+        }
+
+        // Older servers have no usage endpoint
+        status.quota = self.usage().await.ok().map(|usage| usage.quota);
+        status
+    }
+
+    /// Fails unless the server is reachable and accepts the token.
+    pub async fn check_remote_access(&self) -> Result<()> {
+        let status = self.check_remote().await;
+        if status.is_healthy() {
+            return Ok(());
+        }
+        Err(KiwiError::Sync(status.error.unwrap_or_else(|| "Remote is not reachable".to_string())))
     }
 
     pub async fn push(&self) -> Result<()> {
-        let url = &self.config.url;
-        
-        let packages_file = self.base_dir.join("packages.json");
-        let packages = if packages_file.exists() {
-            let contents = fs::read_to_string(&packages_file)?;
-            serde_json::from_str(&contents)?
+        self.push_with(PushOptions::default(), &Silent::default()).await
+    }
+
+    /// Pushes the local state, reporting bytes uploaded as they're sent.
+    /// Unless `options.force` is set, files another machine pushed since
+    /// this one last synced them are a conflict, and nothing is pushed.
+    pub async fn push_with(&self, options: PushOptions, reporter: &dyn Reporter) -> Result<()> {
+        let progress = Progress::new(reporter);
+        let LocalManifest { packages, taps, brew } = self.local_manifest()?;
+
+        let store = self.blob_store();
+        let TrackedFiles { files: tracked, pointers, mut xattrs, .. } = self.tracked_files(reporter)?;
+        let mut pushed: HashMap<String, String> = tracked
+            .iter()
+            .map(|(name, (_, hash))| (name.clone(), hash.clone()))
+            .chain(pointers.iter().map(|(name, pointer)| (name.clone(), pointer.hash.clone())))
+            .collect();
+        if !options.force && !pushed.is_empty() {
+            let conflicts = self.push_conflicts(&pushed).await?;
+            if !conflicts.is_empty() {
+                return Err(KiwiError::Conflict { files: conflicts });
+            }
+        }
+
+        let mut blobs = HashMap::new();
+        for (name, (path, hash)) in &tracked {
+            // Only read files whose contents aren't in the local store yet
+            let hash = if store.contains(hash) {
+                hash.clone()
+            } else {
+                store.put(&fs::read(path)?)?
+            };
+            blobs.insert(name.clone(), hash);
+        }
+
+        let files = if self.upload_blobs(&store, &blobs, &progress).await? {
+            HashMap::new()
         } else {
-            Vec::new()
+            // The server predates blob storage, so send text files inline
+            blobs.clear();
+            let files: HashMap<String, String> = tracked
+                .into_iter()
+                .filter_map(|(name, (path, _))| match fs::read_to_string(&path) {
+                    Ok(text) => Some((name, text)),
+                    Err(_) => {
+                        log::warn!("Skipping binary file {}; the server can't store it", name);
+                        None
+                    }
+                })
+                .collect();
+            pushed.retain(|name, _| files.contains_key(name) || pointers.contains_key(name));
+            files
         };
 
+        xattrs.retain(|name, _| pushed.contains_key(name));
         let sync_data = SyncData {
-            files: std::collections::HashMap::new(),
+            files,
+            blobs,
+            pointers,
             packages,
+            taps,
+            brew,
+            xattrs,
         };
 
-        let response = self.client
-            .post(url)
-            .header("Authorization", self.get_auth_header())
-            .json(&sync_data)
-            .send()
-            .await?;
+        let manifest = serde_json::to_vec(&sync_data)?;
+        progress.expect(manifest.len() as u64);
+        let request = self.sync_request();
+        let response = self.send_body(request, &manifest, &progress).await?;
         
         if !response.status().is_success() {
-            return Err(format!("Failed to push: {}", response.status()).into());
+            return Err(KiwiError::Sync(api::failure(response, "Failed to push").await));
         }
-        Ok(())
+        self.record_last_sync(sync_data.packages.iter().map(|p| p.name.clone()), sync_data.taps.clone(), hashes_of(&sync_data))?;
+        self.dotfiles().record_synced(&pushed, false)
+    }
+
+    /// Syncs both ways in one run: each file goes whichever way it changed
+    /// since the last sync, judged by the hash recorded then. Files changed
+    /// on both sides are conflicts, kept local with `options.prefer_local`
+    /// and otherwise settled by `reporter.choose`; unsettled ones are left
+    /// alone. A file deleted here since the last sync is dropped from the
+    /// remote. Packages and taps from either side are kept unless the other
+    /// side dropped them since the last sync.
+    pub async fn merge(&self, options: MergeOptions, reporter: &dyn Reporter) -> Result<MergeSummary> {
+        fs::create_dir_all(&self.base_dir)?;
+        let progress = Progress::new(reporter);
+        let mut remote = self.remote_data().await?;
+        let remote_hashes = hashes_of(&remote);
+        let TrackedFiles { files: tracked, pointers, xattrs, withheld } = self.tracked_files(reporter)?;
+        let local: HashMap<String, String> = tracked
+            .iter()
+            .map(|(name, (_, hash))| (name.clone(), hash.clone()))
+            .chain(pointers.iter().map(|(name, pointer)| (name.clone(), pointer.hash.clone())))
+            .collect();
+        let synced = self.synced_hashes()?;
+        let last_sync = self.last_sync()?;
+        let dotfiles = self.dotfiles();
+        let sources: HashMap<String, PathBuf> = dotfiles.list()?.iter().map(|dotfile| (dotfile.name(), dotfiles.source_for(dotfile))).collect();
+
+        let mut summary = MergeSummary::default();
+        let mut replace = HashSet::new();
+        let mut in_sync = HashMap::new();
+        // Withheld files aren't missing here, so their remote copies stay put
+        let mut names: Vec<&String> = local.keys().chain(remote_hashes.keys()).filter(|name| !withheld.contains(*name)).collect();
+        names.sort();
+        names.dedup();
+        if !options.adopt.is_empty() {
+            names.retain(|name| options.adopt.contains(name) && local.contains_key(*name));
+        }
+        for name in names {
+            let last = synced.get(name).and_then(Option::as_ref);
+            match (local.get(name), remote_hashes.get(name)) {
+                (Some(ours), Some(theirs)) if ours == theirs => {
+                    if last != Some(ours) {
+                        in_sync.insert(name.clone(), ours.clone());
+                    }
+                }
+                // Synced as it is and gone from the remote: another Mac
+                // deleted it, so it isn't pushed back
+                (Some(ours), None) if last == Some(ours) && options.adopt.is_empty() => {
+                    reporter.warning(&format!("{} was deleted on another Mac; keeping this copy but not syncing it", name));
+                }
+                (Some(_), None) => summary.pushed.push(name.clone()),
+                // Still tracked and unchanged remotely since the last sync;
+                // it was deleted here only if its source is really gone
+                (None, Some(theirs))
+                    if last == Some(theirs) && last_sync.as_ref().and_then(|last| last.files.get(name)) == Some(theirs) =>
+                {
+                    let source = sources.get(name).cloned().unwrap_or_else(|| self.base_dir.join(name));
+                    let unreadable = |e: std::io::Error| KiwiError::Sync(format!("Can't read {} ({}): {}", name, source.display(), e));
+                    match fs::metadata(&source) {
+                        Err(e) if e.kind() == std::io::ErrorKind::NotFound => summary.dropped.push(name.clone()),
+                        Err(e) => return Err(unreadable(e)),
+                        Ok(_) => {
+                            fs::read(&source).map_err(unreadable)?;
+                        }
+                    }
+                }
+                (None, Some(theirs)) => {
+                    // Pointer files have no contents to pull, and untracked
+                    // files pulled before are already here
+                    let here = fs::read(self.base_dir.join(name)).ok().map(|contents| blobs::hash(&contents));
+                    if !remote.pointers.contains_key(name) && here.as_ref() != Some(theirs) {
+                        summary.pulled.push(name.clone());
+                    }
+                }
+                (Some(ours), Some(theirs)) => {
+                    if last == Some(theirs) || (options.prefer_local && last != Some(ours)) || !options.adopt.is_empty() {
+                        summary.pushed.push(name.clone());
+                    } else if last == Some(ours) {
+                        summary.pulled.push(name.clone());
+                    } else {
+                        let choices = ["Keep this Mac's version", "Take the remote version", "Skip it for now"];
+                        let prompt = format!("{} changed both here and on the remote since the last sync", name);
+                        match reporter.choose(&prompt, &choices) {
+                            Some(0) => summary.pushed.push(name.clone()),
+                            Some(1) if !pointers.contains_key(name) && !remote.pointers.contains_key(name) => {
+                                replace.insert(name.clone());
+                                summary.pulled.push(name.clone());
+                            }
+                            _ => summary.conflicts.push(name.clone()),
+                        }
+                    }
+                }
+                (None, None) => {}
+            }
+        }
+
+        if !summary.pulled.is_empty() {
+            let mut pulled = PulledState {
+                options: PullOptions { only: summary.pulled.clone(), ..Default::default() },
+                backups: self.backup.then(|| Backups::new(&self.base_dir)),
+                modified: self.locally_modified()?,
+                replace,
+                ..Default::default()
+            };
+            for entry in remote.clone().into_entries() {
+                self.apply_entry(entry, false, &mut pulled, reporter)?;
+            }
+            self.apply_blobs(&mut pulled, false, reporter, &progress).await?;
+            self.finish_pulled(&pulled)?;
+            self.dotfiles().record_synced(&pulled.applied, true)?;
+            summary.pulled.retain(|name| pulled.applied.contains_key(name));
+        }
+
+        // Packages and taps from either side are kept, less what one side
+        // dropped since the last sync
+        let LocalManifest { packages, taps, brew } = self.local_manifest()?;
+        let remote_packages: HashSet<String> = remote
+            .packages
+            .iter()
+            .filter(|p| !is_excluded(&p.name, &self.config.excluded_packages))
+            .map(|p| p.name.clone())
+            .collect();
+        let local_packages: HashSet<String> = packages.iter().map(|p| p.name.clone()).collect();
+        let kept = |name: &String, here: &HashSet<String>, there: &HashSet<String>| {
+            !last_sync.as_ref().is_some_and(|last| last.packages.contains(name) && !(here.contains(name) && there.contains(name)))
+        };
+        let mut merged: HashMap<String, Package> = remote
+            .packages
+            .drain(..)
+            .filter(|p| remote_packages.contains(&p.name) && kept(&p.name, &local_packages, &remote_packages))
+            .map(|p| (p.name.clone(), p))
+            .collect();
+        merged.extend(
+            packages
+                .into_iter()
+                .filter(|p| kept(&p.name, &local_packages, &remote_packages))
+                .map(|p| (p.name.clone(), p)),
+        );
+        let dropped_tap = |tap: &String, there: &[String]| last_sync.as_ref().is_some_and(|last| last.taps.contains(tap)) && !there.contains(tap);
+        let mut merged_taps: Vec<String> = remote.taps.iter().filter(|tap| !dropped_tap(tap, &taps)).cloned().collect();
+        for tap in &taps {
+            if !merged_taps.contains(tap) && !dropped_tap(tap, &remote.taps) {
+                merged_taps.push(tap.clone());
+            }
+        }
+        fs::write(self.base_dir.join("packages.json"), serde_json::to_string_pretty(&merged)?)?;
+        fs::write(self.base_dir.join("taps.json"), serde_json::to_string_pretty(&merged_taps)?)?;
+        let merged_names: HashSet<String> = merged.keys().cloned().collect();
+        let packages_changed = merged_names != remote_packages || merged_taps != remote.taps;
+
+        if !summary.pushed.is_empty() || !summary.dropped.is_empty() || packages_changed {
+            let store = self.blob_store();
+            let mut blobs = HashMap::new();
+            for name in &summary.pushed {
+                if let Some((path, hash)) = tracked.get(name) {
+                    let hash = if store.contains(hash) { hash.clone() } else { store.put(&fs::read(path)?)? };
+                    blobs.insert(name.clone(), hash);
+                }
+            }
+            let inline = !self.upload_blobs(&store, &blobs, &progress).await?;
+            for name in summary.pushed.iter().chain(&summary.dropped) {
+                remote.files.remove(name);
+                remote.blobs.remove(name);
+                remote.pointers.remove(name);
+                remote.xattrs.remove(name);
+            }
+            for name in &summary.pushed {
+                if let Some(attributes) = xattrs.get(name) {
+                    remote.xattrs.insert(name.clone(), attributes.clone());
+                }
+                if let Some(pointer) = pointers.get(name) {
+                    remote.pointers.insert(name.clone(), pointer.clone());
+                } else if !inline {
+                    remote.blobs.insert(name.clone(), blobs[name].clone());
+                } else if let Ok(text) = fs::read_to_string(&tracked[name].0) {
+                    remote.files.insert(name.clone(), text);
+                } else {
+                    log::warn!("Skipping binary file {}; the server can't store it", name);
+                }
+            }
+            remote.packages = merged.into_values().collect();
+            remote.taps = merged_taps.clone();
+            remote.brew = brew.or(remote.brew);
+
+            let manifest = serde_json::to_vec(&remote)?;
+            progress.expect(manifest.len() as u64);
+            let request = self.sync_request();
+            let response = self.send_body(request, &manifest, &progress).await?;
+            if !response.status().is_success() {
+                return Err(KiwiError::Sync(api::failure(response, "Failed to push").await));
+            }
+            let stored = hashes_of(&remote);
+            summary.pushed.retain(|name| stored.contains_key(name));
+            let pushed: HashMap<String, String> = summary.pushed.iter().map(|name| (name.clone(), local[name].clone())).collect();
+            self.dotfiles().record_synced(&pushed, false)?;
+        }
+        self.dotfiles().record_synced(&in_sync, false)?;
+        self.record_last_sync(merged_names, merged_taps, hashes_of(&remote))?;
+        Ok(summary)
     }
 
     pub async fn pull(&self, prefer_local: bool) -> Result<()> {
+        self.pull_with(PullOptions { prefer_local, ..Default::default() }, &Silent::default()).await?;
+        Ok(())
+    }
+
+    /// Pulls the remote state, streaming it as NDJSON when the server supports
+    /// it so file contents are written as they arrive instead of buffered.
+    /// Files that fail a syntax check for their format are skipped with a
+    /// warning unless `options.force` is set. Returns the names of the files written.
+    pub async fn pull_with(&self, options: PullOptions, reporter: &dyn Reporter) -> Result<Vec<String>> {
+        let prefer_local = options.prefer_local;
         if !self.base_dir.exists() && !prefer_local {
             return Err("Base directory does not exist".into());
         }
+        fs::create_dir_all(&self.base_dir)?;
 
-        let url = &self.config.url;
-        let response = self.client
-            .get(url)
-            .header("Authorization", self.get_auth_header())
-            .send()
-            .await?;
+        let request = self.client
+            .get(self.endpoint("sync"))
+            .header(reqwest::header::ACCEPT, format!("{}, application/json", NDJSON));
+        let mut response = self.send(request).await?;
 
         if !response.status().is_success() {
-            return Err(format!("Failed to pull: {}", response.status()).into());
+            return Err(KiwiError::Sync(api::failure(response, "Failed to pull").await));
         }
+        let progress = Progress::new(reporter);
+        progress.expect(response.content_length().unwrap_or(0));
 
-        let sync_data: SyncData = response.json().await?;
-        
-        if !sync_data.packages.is_empty() {
+        let streamed = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.starts_with(NDJSON));
+
+        let mut pulled = PulledState {
+            options,
+            backups: self.backup.then(|| Backups::new(&self.base_dir)),
+            modified: self.locally_modified()?,
+            ..Default::default()
+        };
+        if streamed {
+            let mut buffer = Vec::new();
+            while let Some(chunk) = response.chunk().await? {
+                progress.received(chunk.len() as u64);
+                buffer.extend_from_slice(&chunk);
+                while let Some(newline) = buffer.iter().position(|&b| b == b'\n') {
+                    let line: Vec<u8> = buffer.drain(..=newline).collect();
+                    self.apply_line(&line, prefer_local, &mut pulled, reporter)?;
+                }
+            }
+            self.apply_line(&buffer, prefer_local, &mut pulled, reporter)?;
+        } else {
+            // Older servers return the whole manifest as one JSON document
+            let body = receive(response, &progress).await?;
+            let sync_data: SyncData = serde_json::from_slice(&body)?;
+            for entry in sync_data.into_entries() {
+                self.apply_entry(entry, prefer_local, &mut pulled, reporter)?;
+            }
+        }
+
+        self.apply_blobs(&mut pulled, prefer_local, reporter, &progress).await?;
+        self.finish_pulled(&pulled)?;
+
+        if !pulled.packages.is_empty() {
             let packages_file = self.base_dir.join("packages.json");
             fs::write(
                 &packages_file,
-                serde_json::to_string_pretty(&sync_data.packages)?,
+                serde_json::to_string_pretty(&pulled.packages)?,
+            )?;
+        }
+
+        if !pulled.taps.is_empty() {
+            fs::write(
+                self.base_dir.join("taps.json"),
+                serde_json::to_string_pretty(&pulled.taps)?,
             )?;
         }
 
+        if let Some(brew) = &pulled.brew {
+            fs::write(self.base_dir.join("brew.json"), serde_json::to_string_pretty(brew)?)?;
+        }
+
+        self.record_last_sync(pulled.packages.keys().cloned(), pulled.taps.clone(), std::mem::take(&mut pulled.remote))?;
+        self.dotfiles().record_synced(&pulled.applied, true)?;
+        if !pulled.adopted.is_empty() {
+            let adopt = std::mem::take(&mut pulled.adopted);
+            self.merge(MergeOptions { adopt, ..Default::default() }, reporter).await?;
+        }
+        let mut applied: Vec<String> = pulled.applied.into_keys().collect();
+        applied.sort();
+        Ok(applied)
+    }
+
+    fn apply_line(&self, line: &[u8], prefer_local: bool, pulled: &mut PulledState, reporter: &dyn Reporter) -> Result<()> {
+        if line.iter().all(u8::is_ascii_whitespace) {
+            return Ok(());
+        }
+        let entry: ManifestEntry = serde_json::from_slice(line)?;
+        self.apply_entry(entry, prefer_local, pulled, reporter)
+    }
+
+    fn apply_entry(&self, entry: ManifestEntry, prefer_local: bool, pulled: &mut PulledState, reporter: &dyn Reporter) -> Result<()> {
+        let remote = match &entry {
+            ManifestEntry::File { path, content } => Some((path, blobs::hash(content.as_bytes()))),
+            ManifestEntry::Blob { path, hash } => Some((path, hash.clone())),
+            ManifestEntry::Pointer { path, pointer } => Some((path, pointer.hash.clone())),
+            _ => None,
+        };
+        if let Some((path, hash)) = remote {
+            pulled.remote.insert(path.clone(), hash);
+        }
+        let only = &pulled.options.only;
+        if !only.is_empty() && !entry.file_name().is_some_and(|name| only.iter().any(|o| o == name)) {
+            return Ok(());
+        }
+        match entry {
+            ManifestEntry::Tap { name } => pulled.taps.push(name),
+            ManifestEntry::Package { package } => {
+                if !is_excluded(&package.name, &self.config.excluded_packages) {
+                    pulled.packages.insert(package.name.clone(), package);
+                }
+            }
+            ManifestEntry::File { path, content } => {
+                let target = self.file_target(&path)?;
+                let hash = blobs::hash(content.as_bytes());
+                if !(prefer_local && target.exists())
+                    && self.accepts(&path, content.as_bytes(), pulled, reporter)
+                    && self.prepare_overwrite(&path, &target, content.as_bytes(), &hash, pulled, reporter)?
+                {
+                    self.write_pulled(&path, &target, content.as_bytes())?;
+                    pulled.applied.insert(path, hash);
+                }
+            }
+            ManifestEntry::Blob { path, hash } => {
+                if !blobs::is_hash(&hash) {
+                    return Err(KiwiError::Sync(format!("Invalid blob hash for {}: {}", path, hash)));
+                }
+                pulled.blobs.push((path, hash));
+            }
+            ManifestEntry::Pointer { path, pointer } => {
+                let target = self.file_target(&path)?;
+                let current = fs::read(&target).ok().map(|data| blobs::hash(&data));
+                if current.as_deref() != Some(pointer.hash.as_str()) {
+                    reporter.warning(&format!(
+                        "{} is kept out of sync ({} bytes); copy it from the machine that added it",
+                        path, pointer.size
+                    ));
+                }
+            }
+            ManifestEntry::Brew { environment } => pulled.brew = Some(environment),
+            ManifestEntry::Xattrs { path, attributes } => {
+                pulled.xattrs.insert(path, attributes);
+            }
+        }
+
+        pulled.entries += 1;
+        if pulled.entries.is_multiple_of(PROGRESS_INTERVAL) {
+            reporter.progress(&format!("Received {} entries...", pulled.entries));
+        }
+        Ok(())
+    }
+
+    /// Uploads the blobs the server doesn't already have. Returns false when
+    /// the server predates blob storage.
+    async fn upload_blobs(&self, store: &BlobStore, blobs: &HashMap<String, String>, progress: &Progress<'_>) -> Result<bool> {
+        if blobs.is_empty() {
+            return Ok(true);
+        }
+
+        let mut hashes: Vec<&String> = blobs.values().collect();
+        hashes.sort();
+        hashes.dedup();
+
+        let response = self.send(self.client.post(self.endpoint("blobs/missing")).json(&hashes)).await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(false);
+        }
+        if !response.status().is_success() {
+            return Err(KiwiError::Sync(api::failure(response, "Failed to check remote blobs").await));
+        }
+
+        let missing: Vec<String> = response.json().await?;
+        let missing: Vec<&String> = missing.iter().filter(|hash| hashes.contains(hash)).collect();
+        let needed: u64 = missing.iter().filter_map(|hash| store.size(hash)).sum();
+        // Fail before uploading anything rather than partway through
+        if needed > 0 {
+            if let Some(remaining) = self.usage().await.ok().and_then(|usage| usage.quota.remaining()) {
+                if needed > remaining {
+                    return Err(KiwiError::Sync(format!(
+                        "Pushing needs {} of storage but only {} is left; run `kiwi account usage` to see what takes the space",
+                        format_size(Some(needed)),
+                        format_size(Some(remaining))
+                    )));
+                }
+            }
+        }
+        progress.expect(needed);
+        for hash in missing {
+            let request = self.client.put(self.endpoint(&format!("blobs/{}", hash)));
+            let response = self.send_body(request, &store.get(hash)?, progress).await?;
+            if !response.status().is_success() {
+                return Err(KiwiError::Sync(api::failure(response, &format!("Failed to upload blob {}", hash)).await));
+            }
+        }
+        Ok(true)
+    }
+
+    /// Writes pulled blob entries into place, downloading any blobs that
+    /// aren't in the local store yet.
+    async fn apply_blobs(&self, pulled: &mut PulledState, prefer_local: bool, reporter: &dyn Reporter, progress: &Progress<'_>) -> Result<()> {
+        let store = self.blob_store();
+        for (path, hash) in std::mem::take(&mut pulled.blobs) {
+            let target = self.file_target(&path)?;
+            if prefer_local && target.exists() {
+                continue;
+            }
+
+            if !store.contains(&hash) {
+                reporter.progress(&format!("Downloading {}...", path));
+                let response = self.send(self.client.get(self.endpoint(&format!("blobs/{}", hash)))).await?;
+                if !response.status().is_success() {
+                    return Err(KiwiError::Sync(api::failure(response, &format!("Failed to download {}", path)).await));
+                }
+                progress.expect(response.content_length().unwrap_or(0));
+                store.put_verified(&hash, &receive(response, progress).await?)?;
+            }
+
+            let contents = store.get(&hash)?;
+            if !self.accepts(&path, &contents, pulled, reporter)
+                || !self.prepare_overwrite(&path, &target, &contents, &hash, pulled, reporter)?
+            {
+                continue;
+            }
+            self.write_pulled(&path, &target, &contents)?;
+            pulled.applied.insert(path, hash);
+        }
+        Ok(())
+    }
+
+    /// Gets ready to write pulled `contents` for `name` over `target`. A
+    /// local version that differs and wasn't synced from here is only
+    /// replaced when the pull is forced or `reporter` chooses to overwrite
+    /// it, and then backed up first even with backups off; adopting it
+    /// instead queues it to be pushed. Returns false when the file should be
+    /// left alone.
+    fn prepare_overwrite(&self, name: &str, target: &Path, contents: &[u8], hash: &str, pulled: &mut PulledState, reporter: &dyn Reporter) -> Result<bool> {
+        let conflicted = pulled.modified.get(name).is_some_and(|current| current != hash);
+        if conflicted && !pulled.options.force && !pulled.replace.contains(name) {
+            let choices = ["Adopt this Mac's version and push it", "Overwrite it with the synced copy", "Skip it for now"];
+            let prompt = format!("{} is already here and differs from the synced copy", name);
+            match reporter.choose(&prompt, &choices) {
+                Some(0) => {
+                    pulled.adopted.push(name.to_string());
+                    return Ok(false);
+                }
+                Some(1) => {}
+                _ => {
+                    reporter.warning(&format!(
+                        "Keeping {}: this Mac's version differs from the remote one (use --force to replace it, or `kiwi adopt {}` to push it)",
+                        name, name
+                    ));
+                    return Ok(false);
+                }
+            }
+        }
+
+        let forced_backups;
+        let backups = match &pulled.backups {
+            Some(backups) => backups,
+            None if conflicted => {
+                forced_backups = Backups::new(&self.base_dir);
+                &forced_backups
+            }
+            None => return Ok(true),
+        };
+        let saved = backups.save(name, target, contents)?;
+        if let (true, Some(saved)) = (conflicted, saved) {
+            reporter.warning(&format!("Replacing local changes to {}; they were backed up to {}", name, saved.display()));
+        }
+        Ok(true)
+    }
+
+    /// Writes pulled `contents` for `name` to `target` in the dotfiles dir.
+    /// Writing through a link updates the tracked file, and tracked files
+    /// kiwi's user can't write go through `sudo` when they were added with it.
+    fn write_pulled(&self, name: &str, target: &Path, contents: &[u8]) -> Result<()> {
+        let Err(e) = fs::write(target, contents) else {
+            return Ok(());
+        };
+        if e.kind() != std::io::ErrorKind::PermissionDenied {
+            return Err(e.into());
+        }
+        let dotfiles = self.dotfiles();
+        match dotfiles.list()?.into_iter().find(|dotfile| dotfile.name() == name) {
+            Some(dotfile) => dotfiles.write_file(&dotfile, contents),
+            None => Err(KiwiError::PermissionDenied { path: target.to_path_buf() }),
+        }
+    }
+
+    /// Clears the quarantine flag from the files a pull wrote and sets the
+    /// attributes that came with them, except on files tracked with `--no-xattrs`.
+    fn finish_pulled(&self, pulled: &PulledState) -> Result<()> {
+        if pulled.applied.is_empty() {
+            return Ok(());
+        }
+        let skipped: HashSet<String> =
+            self.dotfiles().list()?.into_iter().filter(|dotfile| dotfile.skip_xattrs).map(|dotfile| dotfile.name()).collect();
+        let xattrs = Xattrs::default();
+        for name in pulled.applied.keys().filter(|name| !skipped.contains(*name)) {
+            // xattr follows the dotfiles dir link to the tracked file
+            let target = self.base_dir.join(name);
+            xattrs.strip_quarantine(&target);
+            if let Some(attributes) = pulled.xattrs.get(name) {
+                xattrs.write(&target, attributes);
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether pulled `contents` for `name` pass validation, or are forced through.
+    fn accepts(&self, name: &str, contents: &[u8], pulled: &PulledState, reporter: &dyn Reporter) -> bool {
+        let Some(problem) = self.validator.check(name, contents) else {
+            return true;
+        };
+        if pulled.options.force {
+            reporter.warning(&format!("Applying {} despite a failed check: {}", name, problem));
+            return true;
+        }
+        reporter.warning(&format!("Not applying {}, it would break: {} (use --force to apply it anyway)", name, problem));
+        false
+    }
+
+    fn blob_store(&self) -> BlobStore {
+        BlobStore::new(self.base_dir.join("blobs"))
+    }
+
+    fn dotfiles(&self) -> Dotfiles {
+        Dotfiles::new(self.base_dir.clone(), self.base_dir.join("dotfiles.json"))
+    }
+
+    /// Tracked files whose contents changed since their last push or pull,
+    /// or that were never synced from here, keyed by name with their current hash.
+    fn locally_modified(&self) -> Result<HashMap<String, String>> {
+        let dotfiles = self.dotfiles();
+        let tracked = dotfiles.list()?;
+        let hashes = dotfiles.hashes(&tracked)?;
+        Ok(tracked
+            .iter()
+            .filter_map(|dotfile| {
+                let current = hashes.get(&dotfiles.source_for(dotfile))?;
+                (dotfile.sync_state(Some(current)) != SyncState::Synced).then(|| (dotfile.name(), current.clone()))
+            })
+            .collect())
+    }
+
+    /// Files in `pushed` whose remote version changed since this machine
+    /// last synced them and differs from what would be pushed.
+    async fn push_conflicts(&self, pushed: &HashMap<String, String>) -> Result<Vec<String>> {
+        let remote = hashes_of(&self.remote_data().await?);
+        let synced = self.synced_hashes()?;
+
+        let mut conflicts: Vec<String> = pushed
+            .iter()
+            .filter(|(name, local)| {
+                remote.get(*name).is_some_and(|theirs| {
+                    theirs != *local && synced.get(*name).and_then(Option::as_ref) != Some(theirs)
+                })
+            })
+            .map(|(name, _)| name.clone())
+            .collect();
+        conflicts.sort();
+        Ok(conflicts)
+    }
+
+    /// The remote manifest as one document, without applying any of it.
+    pub async fn remote_data(&self) -> Result<SyncData> {
+        let request = self.client.get(self.endpoint("sync")).header(reqwest::header::ACCEPT, "application/json");
+        let response = self.send(request).await?;
+        if !response.status().is_success() {
+            return Err(KiwiError::Sync(api::failure(response, "Failed to check the remote").await));
+        }
+        Ok(response.json().await?)
+    }
+
+    /// The hash each tracked file had at its last push or pull, by name.
+    fn synced_hashes(&self) -> Result<HashMap<String, Option<String>>> {
+        Ok(self.dotfiles().list()?.into_iter().map(|dotfile| (dotfile.name(), dotfile.hash)).collect())
+    }
+
+    /// What both sides held at the last sync, if this machine has synced
+    /// since kiwi started keeping track.
+    fn last_sync(&self) -> Result<Option<LastSync>> {
+        match fs::read_to_string(self.base_dir.join(LAST_SYNC_FILE)) {
+            Ok(contents) => Ok(Some(serde_json::from_str(&contents)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn record_last_sync(
+        &self,
+        packages: impl IntoIterator<Item = String>,
+        taps: impl IntoIterator<Item = String>,
+        files: HashMap<String, String>,
+    ) -> Result<()> {
+        let last = LastSync { packages: packages.into_iter().collect(), taps: taps.into_iter().collect(), files };
+        fs::write(self.base_dir.join(LAST_SYNC_FILE), serde_json::to_string_pretty(&last)?)?;
+        Ok(())
+    }
+
+    /// The package manifest, taps, and brew environment saved locally.
+    fn local_manifest(&self) -> Result<LocalManifest> {
+        let packages_file = self.base_dir.join("packages.json");
+        let packages = if packages_file.exists() {
+            let contents = fs::read_to_string(&packages_file)?;
+            let cache: HashMap<String, Package> = serde_json::from_str(&contents)?;
+            cache
+                .into_values()
+                .filter(|p| !is_excluded(&p.name, &self.config.excluded_packages))
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        let taps_file = self.base_dir.join("taps.json");
+        let taps = if taps_file.exists() {
+            serde_json::from_str(&fs::read_to_string(&taps_file)?)?
+        } else {
+            Vec::new()
+        };
+
+        let brew_file = self.base_dir.join("brew.json");
+        let brew = if brew_file.exists() {
+            Some(serde_json::from_str(&fs::read_to_string(&brew_file)?)?)
+        } else {
+            None
+        };
+        Ok(LocalManifest { packages, taps, brew })
+    }
+
+    /// Every readable tracked dotfile, with pointer files split out.
+    /// Unchanged files are answered from the hash cache. Files that hold
+    /// credentials are only included when they're encrypted.
+    fn tracked_files(&self, reporter: &dyn Reporter) -> Result<TrackedFiles> {
+        let dotfiles = self.dotfiles();
+        let tracked = dotfiles.list()?;
+        let hashes = dotfiles.hashes(&tracked)?;
+
+        let mut files = HashMap::new();
+        let mut pointers = HashMap::new();
+        let mut xattrs = HashMap::new();
+        let mut withheld = HashSet::new();
+        let reader = Xattrs::default();
+        for dotfile in tracked {
+            match sensitive::check(&dotfile.path) {
+                Some(Sensitivity::Deny(reason)) => {
+                    log::warn!("Skipping {}: it holds {}", dotfile.path.display(), reason);
+                    continue;
+                }
+                Some(Sensitivity::Confirm(reason)) if dotfile.mode != LinkMode::Encrypt => {
+                    reporter.warning(&format!(
+                        "Not syncing {}: it holds {}; track it with `kiwi add --encrypt` to sync it",
+                        dotfile.path.display(),
+                        reason
+                    ));
+                    withheld.insert(dotfile.name());
+                    continue;
+                }
+                _ => {}
+            }
+            let source = dotfiles.source_for(&dotfile);
+            let Some(hash) = hashes.get(&source) else {
+                log::warn!("Skipping {}: not a readable file", dotfile.path.display());
+                continue;
+            };
+            if dotfile.pointer {
+                let size = fs::metadata(&source)?.len();
+                pointers.insert(dotfile.name(), FilePointer { hash: hash.clone(), size });
+            } else {
+                let attributes = if dotfile.skip_xattrs { Attributes::new() } else { reader.read(&source) };
+                if !attributes.is_empty() {
+                    xattrs.insert(dotfile.name(), attributes);
+                }
+                files.insert(dotfile.name(), (source, hash.clone()));
+            }
+        }
+        Ok(TrackedFiles { files, pointers, xattrs, withheld })
+    }
+
+    /// Resolves a remote file key inside the dotfiles dir, refusing keys
+    /// that would escape it or land on kiwi's own state.
+    fn file_target(&self, name: &str) -> Result<PathBuf> {
+        let relative = Path::new(name);
+        if name.is_empty() || !relative.components().all(|c| matches!(c, Component::Normal(_))) {
+            return Err(KiwiError::Sync(format!("Refusing to write unsafe path from remote: {}", name)));
+        }
+        if dotfiles::is_reserved(name) {
+            return Err(KiwiError::Sync(format!("Refusing to overwrite kiwi's own state from remote: {}", name)));
+        }
+        let target = self.base_dir.join(relative);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        Ok(target)
+    }
+
+    /// The remote contents of the tracked file `name`, or `None` when the
+    /// remote doesn't have it or only has its pointer.
+    pub async fn remote_file(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        let response = self.send(self.client.get(self.endpoint("sync"))).await?;
+        if !response.status().is_success() {
+            return Err(KiwiError::Sync(api::failure(response, "Failed to pull").await));
+        }
+        let mut data: SyncData = response.json().await?;
+        if let Some(content) = data.files.remove(name) {
+            return Ok(Some(content.into_bytes()));
+        }
+        let Some(hash) = data.blobs.remove(name) else {
+            return Ok(None);
+        };
+
+        let store = self.blob_store();
+        if store.contains(&hash) {
+            return Ok(Some(store.get(&hash)?));
+        }
+        let response = self.send(self.client.get(self.endpoint(&format!("blobs/{}", hash)))).await?;
+        if !response.status().is_success() {
+            return Err(KiwiError::Sync(api::failure(response, &format!("Failed to download {}", name)).await));
+        }
+        let contents = response.bytes().await?.to_vec();
+        store.put_verified(&hash, &contents)?;
+        Ok(Some(contents))
+    }
+
+    pub async fn create_link_code(&self) -> Result<LinkCode> {
+        let response = self.send(self.client.post(self.endpoint("link"))).await?;
+
+        if !response.status().is_success() {
+            return Err(KiwiError::Sync(api::failure(response, "Failed to create link code").await));
+        }
+
+        Ok(response.json::<LinkCode>().await?)
+    }
+
+    pub async fn export_account(&self) -> Result<Vec<u8>> {
+        let response = self.send(self.client.get(self.endpoint("account/export"))).await?;
+
+        if !response.status().is_success() {
+            return Err(KiwiError::Sync(api::failure(response, "Failed to export account").await));
+        }
+
+        Ok(response.bytes().await?.to_vec())
+    }
+
+    /// How much server storage the account uses, and by which files.
+    pub async fn usage(&self) -> Result<Usage> {
+        let response = self.send(self.client.get(self.endpoint("account/usage"))).await?;
+
+        if response.status() == StatusCode::NOT_FOUND {
+            return Err(KiwiError::Sync("The sync server doesn't report storage usage".to_string()));
+        }
+        if !response.status().is_success() {
+            return Err(KiwiError::Sync(api::failure(response, "Failed to get storage usage").await));
+        }
+        Ok(response.json::<Usage>().await?)
+    }
+
+    /// Every push the server has logged for the account, oldest first.
+    pub async fn activity(&self) -> Result<Vec<Activity>> {
+        let response = self.send(self.client.get(self.endpoint("activity"))).await?;
+
+        if response.status() == StatusCode::NOT_FOUND {
+            return Err(KiwiError::Sync("The sync server doesn't keep an activity log".to_string()));
+        }
+        if !response.status().is_success() {
+            return Err(KiwiError::Sync(api::failure(response, "Failed to get sync activity").await));
+        }
+        let mut activity = response.json::<Vec<Activity>>().await?;
+        activity.sort_by_key(|entry| chrono::DateTime::parse_from_rfc3339(&entry.at).ok());
+        Ok(activity)
+    }
+
+    pub async fn delete_account(&self) -> Result<()> {
+        let response = self.send(self.client.delete(self.endpoint("account"))).await?;
+
+        if !response.status().is_success() {
+            return Err(KiwiError::Sync(api::failure(response, "Failed to delete account").await));
+        }
         Ok(())
     }
 
@@ -110,11 +1272,183 @@ impl Sync {
         Ok(())
     }
 
+    /// Sends an authenticated request, retrying on 429 and 503 with the
+    /// server's `Retry-After` or exponential backoff.
+    async fn send(&self, request: RequestBuilder) -> Result<Response> {
+        self.send_with(request, None).await
+    }
+
+    /// Like `send`, streaming `body` and counting it into `progress` as the
+    /// connection takes it.
+    async fn send_body(&self, request: RequestBuilder, body: &[u8], progress: &Progress<'_>) -> Result<Response> {
+        self.send_with(request, Some((body, progress))).await
+    }
+
+    async fn send_with(&self, request: RequestBuilder, upload: Option<(&[u8], &Progress<'_>)>) -> Result<Response> {
+        let mut backoff = INITIAL_BACKOFF;
+        let mut attempt = 1;
+        // Retries are the same request as far as the server's logs go
+        let id = api::new_request_id();
+        loop {
+            let attempt_request = request
+                .try_clone()
+                .ok_or("Request can't be retried")?
+                .header("Authorization", self.get_auth_header());
+            let response = match upload {
+                Some((body, progress)) => {
+                    // A retry sends everything again, so count from where this attempt began
+                    let start = progress.done();
+                    // Sized up front so the body isn't sent chunked, which some servers refuse
+                    let attempt_request = attempt_request
+                        .header(reqwest::header::CONTENT_LENGTH, body.len())
+                        .body(progress.body(body));
+                    let sent = progress.watch(api::send_with_id(attempt_request, &id)).await;
+                    if !sent.as_ref().is_ok_and(|response| response.status().is_success()) {
+                        progress.rewind(start);
+                    }
+                    sent?
+                }
+                None => api::send_with_id(attempt_request, &id).await?,
+            };
+
+            let status = response.status();
+            if status != StatusCode::TOO_MANY_REQUESTS && status != StatusCode::SERVICE_UNAVAILABLE {
+                return Ok(response);
+            }
+
+            let retry_after = retry_after(&response);
+            let wait = retry_after.unwrap_or(backoff);
+            if attempt >= MAX_ATTEMPTS || wait > MAX_RETRY_WAIT {
+                log::warn!("Giving up on request {} after {} attempts", id, attempt);
+                return Err(KiwiError::RateLimited {
+                    retry_after: retry_after.map(|d| d.as_secs()),
+                });
+            }
+
+            log::warn!("Sync server returned {}; retrying in {:?}", status, wait);
+            tokio::time::sleep(wait).await;
+            backoff *= 2;
+            attempt += 1;
+        }
+    }
+
+    /// A push of the whole manifest, saying which machine it's from.
+    fn sync_request(&self) -> RequestBuilder {
+        let request = self.client
+            .post(self.endpoint("sync"))
+            .header(reqwest::header::CONTENT_TYPE, "application/json");
+        match &self.machine {
+            Some(machine) => request.header(api::MACHINE_HEADER, machine),
+            None => request,
+        }
+    }
+
+    fn endpoint(&self, path: &str) -> String {
+        format!("{}/{}", self.config.url.trim_end_matches('/'), path)
+    }
+
     fn get_auth_header(&self) -> String {
         format!("Bearer {}", self.config.token)
     }
 }
 
+/// Bytes moved by one push or pull, passed on to the reporter. The total
+/// grows as sizes become known, since a pull only learns each file's size
+/// when its download starts.
+struct Progress<'a> {
+    reporter: &'a dyn Reporter,
+    /// Shared with request bodies, which the connection reads from its own task
+    done: Arc<AtomicU64>,
+    total: Cell<u64>,
+}
+
+impl<'a> Progress<'a> {
+    fn new(reporter: &'a dyn Reporter) -> Self {
+        Self { reporter, done: Arc::default(), total: Cell::new(0) }
+    }
+
+    fn done(&self) -> u64 {
+        self.done.load(Ordering::Relaxed)
+    }
+
+    fn expect(&self, bytes: u64) {
+        self.total.set(self.total.get() + bytes);
+    }
+
+    fn received(&self, bytes: u64) {
+        let done = self.done.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        // Servers don't always say how much is coming
+        if done > self.total.get() {
+            self.total.set(done);
+        }
+        self.report();
+    }
+
+    fn rewind(&self, to: u64) {
+        self.done.store(to, Ordering::Relaxed);
+    }
+
+    fn report(&self) {
+        self.reporter.transfer(self.done(), self.total.get());
+    }
+
+    /// `data` as a request body that counts each chunk as it's read.
+    fn body(&self, data: &[u8]) -> reqwest::Body {
+        let done = Arc::clone(&self.done);
+        let chunks: Vec<Vec<u8>> = data.chunks(UPLOAD_CHUNK).map(<[u8]>::to_vec).collect();
+        reqwest::Body::wrap_stream(futures_util::stream::iter(chunks.into_iter().map(move |chunk| {
+            done.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+            Ok::<_, std::io::Error>(chunk)
+        })))
+    }
+
+    /// Awaits `future`, reporting progress every so often until it's done.
+    async fn watch<T>(&self, future: impl std::future::Future<Output = T>) -> T {
+        tokio::pin!(future);
+        let mut ticks = tokio::time::interval(TRANSFER_TICK);
+        loop {
+            tokio::select! {
+                output = &mut future => {
+                    self.report();
+                    return output;
+                }
+                _ = ticks.tick() => self.report(),
+            }
+        }
+    }
+}
+
+/// Reads `response`'s body, counting it into `progress`.
+async fn receive(mut response: Response, progress: &Progress<'_>) -> Result<Vec<u8>> {
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        progress.received(chunk.len() as u64);
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
+
+/// Hash of each file in `data`, by dotfiles dir name.
+pub(crate) fn hashes_of(data: &SyncData) -> HashMap<String, String> {
+    data.files
+        .iter()
+        .map(|(name, content)| (name.clone(), blobs::hash(content.as_bytes())))
+        .chain(data.blobs.iter().map(|(name, hash)| (name.clone(), hash.clone())))
+        .chain(data.pointers.iter().map(|(name, pointer)| (name.clone(), pointer.hash.clone())))
+        .collect()
+}
+
+/// Reads `Retry-After` as either delay-seconds or an HTTP date.
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(reqwest::header::RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    let seconds = (date.timestamp() - chrono::Utc::now().timestamp()).max(0);
+    Some(Duration::from_secs(seconds as u64))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -124,6 +1458,7 @@ mod tests {
         let config = SyncConfig {
             url: "https://api.example.com".to_string(),
             token: "test-token".to_string(),
+            excluded_packages: Vec::new(),
         };
         let sync = Sync::new(config, PathBuf::from("/tmp"));
         assert_eq!(sync.get_auth_header(), "Bearer test-token");
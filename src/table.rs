@@ -0,0 +1,91 @@
+//! Aligned plain-text tables for terminal output. Widths are measured on
+//! plain cells, so callers style whole lines rather than individual cells.
+
+pub struct Table {
+    headers: Vec<&'static str>,
+    rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    pub fn new(headers: &[&'static str]) -> Self {
+        Self { headers: headers.to_vec(), rows: Vec::new() }
+    }
+
+    pub fn row(&mut self, cells: Vec<String>) {
+        self.rows.push(cells);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// The header line (if `header`) followed by one line per row, columns
+    /// separated by two spaces. The last column isn't padded.
+    pub fn render(&self, header: bool) -> Vec<String> {
+        let mut widths: Vec<usize> = self.headers.iter().map(|h| h.chars().count()).collect();
+        for row in &self.rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+
+        let line = |cells: Vec<&str>| {
+            let last = cells.len().saturating_sub(1);
+            cells
+                .iter()
+                .enumerate()
+                .map(|(i, cell)| if i == last { cell.to_string() } else { format!("{:<width$}", cell, width = widths[i]) })
+                .collect::<Vec<_>>()
+                .join("  ")
+        };
+
+        let mut lines = Vec::new();
+        if header {
+            lines.push(line(self.headers.clone()));
+        }
+        lines.extend(self.rows.iter().map(|row| line(row.iter().map(String::as_str).collect())));
+        lines
+    }
+}
+
+/// `1.2 MB`, `340 KB`, `12 B`, or `-` when unknown.
+pub fn format_size(bytes: Option<u64>) -> String {
+    match bytes {
+        None => "-".to_string(),
+        Some(b) if b >= 1024 * 1024 => format!("{:.1} MB", b as f64 / 1_048_576.0),
+        Some(b) if b >= 1024 => format!("{} KB", b / 1024),
+        Some(b) => format!("{} B", b),
+    }
+}
+
+/// A Unix timestamp as a local date, or `-` when unknown.
+pub fn format_date(timestamp: Option<u64>) -> String {
+    timestamp
+        .and_then(|t| chrono::DateTime::from_timestamp(t as i64, 0))
+        .map(|t| t.with_timezone(&chrono::Local).format("%Y-%m-%d").to_string())
+        .unwrap_or_else(|| "-".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_columns_align_to_widest_cell() {
+        let mut table = Table::new(&["NAME", "VERSION", "SYNC"]);
+        table.row(vec!["git".to_string(), "2.44.0".to_string(), "synced".to_string()]);
+        table.row(vec!["ripgrep".to_string(), "14.1.0".to_string(), "-".to_string()]);
+
+        assert_eq!(
+            table.render(true),
+            vec![
+                "NAME     VERSION  SYNC",
+                "git      2.44.0   synced",
+                "ripgrep  14.1.0   -",
+            ]
+        );
+        assert_eq!(table.render(false).len(), 2);
+        assert_eq!(format_size(Some(2048)), "2 KB");
+        assert_eq!(format_size(None), "-");
+    }
+}
@@ -0,0 +1,139 @@
+//! `kiwi teardown`: undoes what kiwi set up on this Mac, so trying it is
+//! reversible. Tracked files stay where they are as real files; kiwi's
+//! state, shell hooks, background agent, and Keychain entries go.
+
+use crate::agent::Agent;
+use crate::keychain::Keychain;
+use crate::reporter::Reporter;
+use crate::{aliases, completions, envvars, migrate, project, shell, Dotfiles, KiwiError, Result};
+use clap_complete::Shell;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Rc file blocks kiwi writes. The `brew` block stays, since brew would
+/// stop working without it.
+const RC_BLOCKS: &[&str] = &[aliases::RC_BLOCK, envvars::RC_BLOCK, completions::RC_BLOCK, project::RC_BLOCK];
+const SHELLS: &[&str] = &["zsh", "bash", "fish"];
+
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Move the backups area to `~/kiwi-backups` instead of deleting it
+    pub keep_backups: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    /// Tracked paths that were links into kiwi's state and are now real files
+    pub restored: Vec<PathBuf>,
+    /// Rc files and completion scripts kiwi's hooks were removed from
+    pub shell_files: Vec<PathBuf>,
+    /// Whether the `kiwi watch` launchd agent was removed
+    pub agent: bool,
+    pub keychain_entries: usize,
+    /// Directories deleted
+    pub removed: Vec<PathBuf>,
+    /// A dotfiles dir outside `~/.kiwi`, left in place
+    pub kept: Option<PathBuf>,
+    pub backups: Option<PathBuf>,
+}
+
+pub fn run(dotfiles_dir: &Path, dotfiles: &Dotfiles, options: &Options, reporter: &dyn Reporter) -> Result<Report> {
+    let home = dirs::home_dir().ok_or_else(|| KiwiError::Config("Could not find home directory".to_string()))?;
+    let state_dir = home.join(".kiwi");
+    let mut report = Report::default();
+
+    reporter.progress("Putting real files in place of links");
+    // Home files linked into the dotfiles dir by hand resolve to it when
+    // tracked, so well-known locations are checked too
+    let mut candidates: Vec<PathBuf> = dotfiles.list()?.into_iter().map(|d| d.path).collect();
+    candidates.extend(migrate::KNOWN_CONFIGS.iter().map(|config| home.join(config)));
+    for path in candidates {
+        if !report.restored.contains(&path) && materialize(&path, &[dotfiles_dir, &state_dir])? {
+            report.restored.push(path);
+        }
+    }
+
+    reporter.progress("Removing shell hooks and completions");
+    for name in SHELLS {
+        let rc = shell::rc_file(name)?;
+        for block in RC_BLOCKS {
+            if shell::remove_block(&rc, block)? && !report.shell_files.contains(&rc) {
+                report.shell_files.push(rc.clone());
+            }
+        }
+    }
+    for shell in [Shell::Zsh, Shell::Bash, Shell::Fish] {
+        for path in completions::uninstall(shell)? {
+            if !report.shell_files.contains(&path) {
+                report.shell_files.push(path);
+            }
+        }
+    }
+
+    reporter.progress("Removing the background agent and Keychain entries");
+    report.agent = Agent::default().uninstall()?;
+    report.keychain_entries = Keychain::default().delete_all()?;
+
+    if options.keep_backups {
+        let backups = dotfiles_dir.join("backups");
+        if backups.is_dir() {
+            let destination = home.join("kiwi-backups");
+            fs::rename(&backups, &destination)?;
+            report.backups = Some(destination);
+        }
+    }
+
+    reporter.progress("Deleting kiwi's state");
+    // A dotfiles dir elsewhere may be the user's own repo, so only kiwi's copy goes
+    if !dotfiles_dir.starts_with(&state_dir) && dotfiles_dir.exists() {
+        report.kept = Some(dotfiles_dir.to_path_buf());
+    }
+    for dir in [state_dir, home.join(".config/kiwi")] {
+        if dir.exists() {
+            fs::remove_dir_all(&dir)?;
+            report.removed.push(dir);
+        }
+    }
+    Ok(report)
+}
+
+/// Replaces `path` with a copy of what it links to when that's inside one
+/// of `owned`. Returns whether it did.
+fn materialize(path: &Path, owned: &[&Path]) -> Result<bool> {
+    let Ok(link) = fs::read_link(path) else {
+        return Ok(false);
+    };
+    let target = if link.is_absolute() { link } else { path.parent().unwrap_or(Path::new("/")).join(link) };
+    if !owned.iter().any(|dir| target.starts_with(dir)) || !target.is_file() {
+        return Ok(false);
+    }
+    let contents = fs::read(&target)?;
+    fs::remove_file(path)?;
+    fs::write(path, contents)?;
+    fs::set_permissions(path, fs::metadata(&target)?.permissions())?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_materialize_only_links_into_owned_dirs() {
+        let dir = std::env::temp_dir().join(format!("kiwi-teardown-test-{}", std::process::id()));
+        let state = dir.join("state");
+        fs::create_dir_all(&state).unwrap();
+        fs::write(state.join("zshrc"), "export EDITOR=nvim\n").unwrap();
+        fs::write(dir.join("elsewhere"), "x\n").unwrap();
+        std::os::unix::fs::symlink(state.join("zshrc"), dir.join(".zshrc")).unwrap();
+        std::os::unix::fs::symlink(dir.join("elsewhere"), dir.join(".vimrc")).unwrap();
+
+        assert!(materialize(&dir.join(".zshrc"), &[&state]).unwrap());
+        assert!(!dir.join(".zshrc").symlink_metadata().unwrap().file_type().is_symlink());
+        assert_eq!(fs::read_to_string(dir.join(".zshrc")).unwrap(), "export EDITOR=nvim\n");
+        assert!(!materialize(&dir.join(".vimrc"), &[&state]).unwrap());
+        assert!(!materialize(&dir.join("elsewhere"), &[&state]).unwrap());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
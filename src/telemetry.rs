@@ -0,0 +1,53 @@
+use crate::Config;
+use clap::ArgMatches;
+use serde::Serialize;
+use std::time::Duration;
+
+const REPORT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Everything telemetry ever sends. There is no user, machine, or install
+/// identifier, and no arguments, paths, or package names.
+#[derive(Debug, Serialize)]
+pub struct Event {
+    pub command: String,
+    pub duration_ms: u64,
+    pub success: bool,
+}
+
+/// The subcommand path that was run, e.g. `account export`, without any arguments.
+pub fn command_name(matches: &ArgMatches) -> String {
+    let mut names = Vec::new();
+    let mut current = matches;
+    while let Some((name, sub)) = current.subcommand() {
+        names.push(name);
+        current = sub;
+    }
+    names.join(" ")
+}
+
+/// Sends the event if the user has opted in. Failures are ignored so
+/// telemetry can never affect a command's outcome.
+pub async fn report(event: &Event) {
+    // Loading would write a fresh config, undoing `kiwi teardown`
+    if !Config::config_path().is_ok_and(|path| path.exists()) {
+        return;
+    }
+    let Ok(config) = Config::load() else {
+        return;
+    };
+    if !config.preferences.telemetry || event.command.is_empty() {
+        return;
+    }
+    let Some(url) = config.sync_url else {
+        return;
+    };
+
+    let Ok(client) = reqwest::Client::builder().timeout(REPORT_TIMEOUT).build() else {
+        return;
+    };
+    let _ = client
+        .post(format!("{}/telemetry", url.trim_end_matches('/')))
+        .json(event)
+        .send()
+        .await;
+}
@@ -0,0 +1,268 @@
+//! Renders templated dotfiles. Templates are plain text with `{{ name }}`
+//! placeholders and `{{#if cond}} ... {{else}} ... {{/if}}` blocks, where a
+//! condition is a variable name (true when non-empty) or a comparison like
+//! `shell == "fish"` or `os_version >= 14`. Unknown names are an error so a
+//! typo never silently produces a broken config file.
+
+use crate::{Config, KiwiError, Result};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::process::Command;
+
+/// The variables available to every template: the active `environment`,
+/// `user`, `home`, the machine facts from [`system_variables`], and any
+/// custom config settings.
+pub fn variables(config: &Config) -> HashMap<String, String> {
+    let mut vars: HashMap<String, String> = config.custom_settings.clone();
+    vars.extend(system_variables());
+    vars.insert("environment".to_string(), config.environment.clone().unwrap_or_default());
+    vars.insert("user".to_string(), std::env::var("USER").unwrap_or_default());
+    vars.insert(
+        "home".to_string(),
+        dirs::home_dir().map(|h| h.display().to_string()).unwrap_or_default(),
+    );
+    vars
+}
+
+/// Facts about this machine: `os_version` (e.g. `14.4.1`), `os_name`
+/// (e.g. `sonoma`), `arch` (`arm64` or `x86_64`), `hostname`, and `shell`
+/// (the login shell's name, e.g. `zsh`).
+pub fn system_variables() -> HashMap<String, String> {
+    let os_version = command_output("sw_vers", &["-productVersion"]).unwrap_or_default();
+    let arch = match std::env::consts::ARCH {
+        "aarch64" => "arm64",
+        other => other,
+    };
+    let hostname = command_output("scutil", &["--get", "LocalHostName"])
+        .or_else(|| command_output("hostname", &["-s"]))
+        .unwrap_or_default();
+    let shell = std::env::var("SHELL")
+        .ok()
+        .and_then(|path| path.rsplit('/').next().map(str::to_string))
+        .unwrap_or_default();
+
+    HashMap::from([
+        ("os_name".to_string(), macos_name(&os_version).to_string()),
+        ("os_version".to_string(), os_version),
+        ("arch".to_string(), arch.to_string()),
+        ("hostname".to_string(), hostname),
+        ("shell".to_string(), shell),
+    ])
+}
+
+/// Marketing name for a macOS version, lowercased; empty when unknown.
+fn macos_name(version: &str) -> &'static str {
+    match version.split('.').next().and_then(|major| major.parse::<u32>().ok()) {
+        Some(11) => "big_sur",
+        Some(12) => "monterey",
+        Some(13) => "ventura",
+        Some(14) => "sonoma",
+        Some(15) => "sequoia",
+        Some(26) => "tahoe",
+        _ => "",
+    }
+}
+
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    let value = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !value.is_empty()).then_some(value)
+}
+
+pub fn render(template: &str, vars: &HashMap<String, String>) -> Result<String> {
+    let tokens = tokenize(template)?;
+    let mut output = String::with_capacity(template.len());
+    let mut position = 0;
+    render_block(&tokens, &mut position, vars, true, &mut output)?;
+
+    match tokens.get(position) {
+        None => Ok(output),
+        Some((Token::Else, line)) => Err(error("{{else}} without {{#if}}", *line)),
+        Some((_, line)) => Err(error("{{/if}} without {{#if}}", *line)),
+    }
+}
+
+enum Token<'a> {
+    Text(&'a str),
+    Var(&'a str),
+    If(&'a str),
+    Else,
+    EndIf,
+}
+
+/// Splits a template into tokens, each with the line it starts on.
+fn tokenize(template: &str) -> Result<Vec<(Token<'_>, usize)>> {
+    let mut tokens = Vec::new();
+    let mut rest = template;
+    let mut line = 1;
+
+    while let Some(start) = rest.find("{{") {
+        if start > 0 {
+            tokens.push((Token::Text(&rest[..start]), line));
+            line += rest[..start].matches('\n').count();
+        }
+        let after = &rest[start + 2..];
+        let end = after.find("}}").ok_or_else(|| error("Unclosed placeholder", line))?;
+
+        let tag = after[..end].trim();
+        let token = if let Some(condition) = tag.strip_prefix("#if") {
+            Token::If(condition.trim())
+        } else if tag == "else" {
+            Token::Else
+        } else if tag == "/if" {
+            Token::EndIf
+        } else {
+            Token::Var(tag)
+        };
+        tokens.push((token, line));
+        line += after[..end].matches('\n').count();
+        rest = &after[end + 2..];
+    }
+
+    if !rest.is_empty() {
+        tokens.push((Token::Text(rest), line));
+    }
+    Ok(tokens)
+}
+
+/// Renders tokens from `position` until an unmatched `{{else}}`/`{{/if}}` or
+/// the end, writing output only when `active`. Skipped branches are still
+/// checked so errors don't hide behind a condition.
+fn render_block(
+    tokens: &[(Token<'_>, usize)],
+    position: &mut usize,
+    vars: &HashMap<String, String>,
+    active: bool,
+    output: &mut String,
+) -> Result<()> {
+    while let Some((token, line)) = tokens.get(*position) {
+        match token {
+            Token::Else | Token::EndIf => return Ok(()),
+            Token::Text(text) => {
+                if active {
+                    output.push_str(text);
+                }
+            }
+            Token::Var(name) => {
+                let value = lookup(vars, name, *line)?;
+                if active {
+                    output.push_str(value);
+                }
+            }
+            Token::If(condition) => {
+                let matched = evaluate(condition, vars, *line)?;
+                *position += 1;
+                render_block(tokens, position, vars, active && matched, output)?;
+
+                if let Some((Token::Else, _)) = tokens.get(*position) {
+                    *position += 1;
+                    render_block(tokens, position, vars, active && !matched, output)?;
+                }
+                match tokens.get(*position) {
+                    Some((Token::EndIf, _)) => {}
+                    Some((Token::Else, else_line)) => return Err(error("Duplicate {{else}}", *else_line)),
+                    _ => return Err(error("{{#if}} is missing its {{/if}}", *line)),
+                }
+            }
+        }
+        *position += 1;
+    }
+    Ok(())
+}
+
+const OPERATORS: &[&str] = &["==", "!=", ">=", "<=", ">", "<"];
+
+fn evaluate(condition: &str, vars: &HashMap<String, String>, line: usize) -> Result<bool> {
+    // The first operator wins, so quoted values may contain operators
+    let found = condition
+        .char_indices()
+        .find_map(|(at, _)| OPERATORS.iter().find(|op| condition[at..].starts_with(**op)).map(|op| (*op, at)));
+    let Some((operator, at)) = found else {
+        if condition.is_empty() {
+            return Err(error("{{#if}} needs a condition", line));
+        }
+        return Ok(!lookup(vars, condition, line)?.is_empty());
+    };
+
+    let left = lookup(vars, condition[..at].trim(), line)?;
+    let right = condition[at + operator.len()..].trim();
+    let right = right
+        .strip_prefix('"')
+        .and_then(|r| r.strip_suffix('"'))
+        .unwrap_or(right);
+
+    Ok(match operator {
+        "==" => left == right,
+        "!=" => left != right,
+        _ => {
+            let ordering = compare_versions(left, right)
+                .ok_or_else(|| error(&format!("Can't compare '{}' and '{}' as versions", left, right), line))?;
+            match operator {
+                ">=" => ordering != Ordering::Less,
+                "<=" => ordering != Ordering::Greater,
+                ">" => ordering == Ordering::Greater,
+                _ => ordering == Ordering::Less,
+            }
+        }
+    })
+}
+
+/// Compares dotted version numbers component by component, treating
+/// missing components as zero, so `14` == `14.0` and `14.4.1` > `14`.
+fn compare_versions(left: &str, right: &str) -> Option<Ordering> {
+    let parse = |v: &str| v.split('.').map(|part| part.parse::<u64>().ok()).collect::<Option<Vec<_>>>();
+    let (left, right) = (parse(left)?, parse(right)?);
+    for i in 0..left.len().max(right.len()) {
+        let ordering = left.get(i).unwrap_or(&0).cmp(right.get(i).unwrap_or(&0));
+        if ordering != Ordering::Equal {
+            return Some(ordering);
+        }
+    }
+    Some(Ordering::Equal)
+}
+
+fn lookup<'v>(vars: &'v HashMap<String, String>, name: &str, line: usize) -> Result<&'v str> {
+    vars.get(name)
+        .map(String::as_str)
+        .ok_or_else(|| error(&format!("Unknown variable '{}'", name), line))
+}
+
+fn error(message: &str, line: usize) -> KiwiError {
+    KiwiError::Template(format!("{} at line {}", message, line))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_substitutes_and_rejects_unknown_names() {
+        let vars = HashMap::from([("environment".to_string(), "dev".to_string())]);
+        assert_eq!(render("export KIWI_ENV={{ environment }}\n", &vars).unwrap(), "export KIWI_ENV=dev\n");
+        assert_eq!(render("no placeholders", &vars).unwrap(), "no placeholders");
+
+        let err = render("a\n{{ enviroment }}", &vars).unwrap_err().to_string();
+        assert!(err.contains("enviroment") && err.contains("line 2"));
+        assert!(render("{{ environment", &vars).is_err());
+    }
+
+    #[test]
+    fn test_conditionals_cover_shells_and_os_versions() {
+        let vars = HashMap::from([
+            ("shell".to_string(), "fish".to_string()),
+            ("os_version".to_string(), "14.4.1".to_string()),
+            ("hostname".to_string(), String::new()),
+        ]);
+        let template = "{{#if shell == \"zsh\"}}export A=1{{else}}set -x A 1{{/if}}\n\
+                        {{#if os_version >= 14}}sonoma+{{#if hostname}} on {{ hostname }}{{/if}}{{/if}}";
+        assert_eq!(render(template, &vars).unwrap(), "set -x A 1\nsonoma+");
+
+        assert!(render("{{#if os_version < 13.5}}old{{/if}}", &vars).unwrap().is_empty());
+        assert_eq!(render("{{#if shell != \"a==b\"}}ok{{/if}}", &vars).unwrap(), "ok");
+        assert!(render("{{#if shell}}x", &vars).unwrap_err().to_string().contains("{{/if}}"));
+        assert!(render("x{{/if}}", &vars).is_err());
+        assert!(render("{{#if shell > 3}}x{{/if}}", &vars).is_err());
+        // Typos inside branches that aren't taken are still reported
+        assert!(render("{{#if shell == \"zsh\"}}{{ shel }}{{/if}}", &vars).is_err());
+    }
+}
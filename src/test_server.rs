@@ -0,0 +1,301 @@
+//! An in-process implementation of the sync protocol for integration tests.
+//! Enabled with the `test-server` feature; it keeps state in memory and
+//! accepts a single bearer token.
+
+use crate::{api, blobs};
+use crate::sync::{hashes_of, Activity, SyncData};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+
+pub const TEST_TOKEN: &str = "test-token";
+/// Storage limit reported by `/account/usage`, in bytes
+pub const QUOTA: u64 = 1 << 30;
+
+#[derive(Default)]
+struct State {
+    data: Option<SyncData>,
+    /// Answer pulls with a single JSON document like older servers
+    legacy: bool,
+    /// Responses to send instead of handling the next requests, in order
+    injected: Vec<(StatusCode, Vec<(String, String)>)>,
+    hits: HashMap<String, usize>,
+    blobs: HashMap<String, Vec<u8>>,
+    /// Storage limit in bytes, `QUOTA` unless set
+    quota: Option<u64>,
+    /// Every push taken, oldest first
+    activity: Vec<Activity>,
+}
+
+pub struct MockServer {
+    url: String,
+    state: Arc<Mutex<State>>,
+    shutdown: Option<oneshot::Sender<()>>,
+}
+
+impl MockServer {
+    /// Binds to an ephemeral local port and serves until dropped.
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind test server");
+        let url = format!("http://{}", listener.local_addr().expect("test server address"));
+        let state = Arc::new(Mutex::new(State::default()));
+
+        let service_state = state.clone();
+        let make_service = make_service_fn(move |_| {
+            let state = service_state.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| handle(state.clone(), req)))
+            }
+        });
+
+        let (shutdown, signal) = oneshot::channel();
+        let server = Server::from_tcp(listener)
+            .expect("start test server")
+            .serve(make_service)
+            .with_graceful_shutdown(async {
+                let _ = signal.await;
+            });
+        tokio::spawn(server);
+
+        Self { url, state, shutdown: Some(shutdown) }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// What the server currently holds, as the last push left it.
+    pub fn data(&self) -> Option<SyncData> {
+        self.state.lock().unwrap().data.clone()
+    }
+
+    /// Seeds the remote state, as if another machine had pushed it.
+    pub fn set_data(&self, data: SyncData) {
+        self.state.lock().unwrap().data = Some(data);
+    }
+
+    /// Stops streaming NDJSON manifests, as servers predating it did.
+    pub fn disable_streaming(&self) {
+        self.state.lock().unwrap().legacy = true;
+    }
+
+    /// Answers the next request with `status` and `headers` instead of handling it.
+    pub fn fail_next(&self, status: u16, headers: &[(&str, &str)]) {
+        let status = StatusCode::from_u16(status).expect("valid status code");
+        let headers = headers.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        self.state.lock().unwrap().injected.push((status, headers));
+    }
+
+    /// Limits the account's storage to `bytes`.
+    pub fn set_quota(&self, bytes: u64) {
+        self.state.lock().unwrap().quota = Some(bytes);
+    }
+
+    /// Number of distinct blobs stored.
+    pub fn blob_count(&self) -> usize {
+        self.state.lock().unwrap().blobs.len()
+    }
+
+    /// How many requests reached `path`, including injected failures.
+    pub fn hits(&self, path: &str) -> usize {
+        self.state.lock().unwrap().hits.get(path).copied().unwrap_or(0)
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+    }
+}
+
+/// Answers `req`, echoing its request ID like the real server does.
+async fn handle(state: Arc<Mutex<State>>, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let request_id = req.headers().get(api::REQUEST_ID_HEADER).cloned();
+    let mut response = route(state, req).await?;
+    if let Some(id) = request_id {
+        response.headers_mut().insert(api::REQUEST_ID_HEADER, id);
+    }
+    Ok(response)
+}
+
+async fn route(state: Arc<Mutex<State>>, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let path = req.uri().path().to_string();
+    {
+        let mut state = state.lock().unwrap();
+        *state.hits.entry(path.clone()).or_default() += 1;
+        if !state.injected.is_empty() {
+            let (status, headers) = state.injected.remove(0);
+            let mut response = Response::builder().status(status);
+            for (name, value) in headers {
+                response = response.header(name, value);
+            }
+            return Ok(response.body(Body::empty()).unwrap());
+        }
+    }
+
+    if path == "/health" {
+        return Ok(json(StatusCode::OK, r#"{"status":"OK","version":"test"}"#.to_string()));
+    }
+
+    let authorized = req
+        .headers()
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value == format!("Bearer {}", TEST_TOKEN));
+    if !authorized {
+        return Ok(json(StatusCode::UNAUTHORIZED, r#"{"error":{"code":"invalid_token","message":"Invalid or expired token"}}"#.to_string()));
+    }
+
+    let response = match (req.method(), path.as_str()) {
+        (&Method::GET, "/sync") => {
+            let (data, legacy) = {
+                let state = state.lock().unwrap();
+                (state.data.clone(), state.legacy)
+            };
+            let data = data.unwrap_or(SyncData {
+                files: HashMap::new(),
+                blobs: HashMap::new(),
+                pointers: HashMap::new(),
+                packages: Vec::new(),
+                taps: Vec::new(),
+                brew: None,
+                xattrs: HashMap::new(),
+            });
+            let wants_stream = req
+                .headers()
+                .get("Accept")
+                .and_then(|value| value.to_str().ok())
+                .is_some_and(|value| value.contains("application/x-ndjson"));
+
+            if wants_stream && !legacy {
+                ndjson(data)
+            } else {
+                json(StatusCode::OK, serde_json::to_string(&data).unwrap())
+            }
+        }
+        (&Method::POST, "/sync") => {
+            let machine = req
+                .headers()
+                .get(api::MACHINE_HEADER)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string);
+            let Ok(body) = hyper::body::to_bytes(req.into_body()).await else {
+                return Ok(status(StatusCode::BAD_REQUEST));
+            };
+            match serde_json::from_slice::<SyncData>(&body) {
+                Ok(data) => {
+                    let mut state = state.lock().unwrap();
+                    let entry = activity(machine, state.data.as_ref(), &data);
+                    state.activity.push(entry);
+                    state.data = Some(data);
+                    json(StatusCode::OK, r#"{"status":"ok"}"#.to_string())
+                }
+                Err(_) => status(StatusCode::BAD_REQUEST),
+            }
+        }
+        (&Method::POST, "/blobs/missing") => {
+            let Ok(body) = hyper::body::to_bytes(req.into_body()).await else {
+                return Ok(status(StatusCode::BAD_REQUEST));
+            };
+            let Ok(hashes) = serde_json::from_slice::<Vec<String>>(&body) else {
+                return Ok(status(StatusCode::BAD_REQUEST));
+            };
+            let state = state.lock().unwrap();
+            let missing: Vec<&String> = hashes.iter().filter(|h| !state.blobs.contains_key(*h)).collect();
+            json(StatusCode::OK, serde_json::to_string(&missing).unwrap())
+        }
+        (&Method::PUT, blob) if blob.starts_with("/blobs/") => {
+            let hash = blob.trim_start_matches("/blobs/").to_string();
+            let Ok(body) = hyper::body::to_bytes(req.into_body()).await else {
+                return Ok(status(StatusCode::BAD_REQUEST));
+            };
+            if blobs::hash(&body) != hash {
+                return Ok(status(StatusCode::BAD_REQUEST));
+            }
+            state.lock().unwrap().blobs.insert(hash, body.to_vec());
+            status(StatusCode::CREATED)
+        }
+        (&Method::GET, blob) if blob.starts_with("/blobs/") => {
+            match state.lock().unwrap().blobs.get(blob.trim_start_matches("/blobs/")) {
+                Some(data) => Response::new(Body::from(data.clone())),
+                None => status(StatusCode::NOT_FOUND),
+            }
+        }
+        (&Method::GET, "/account/usage") => {
+            let state = state.lock().unwrap();
+            let used: usize = state.blobs.values().map(Vec::len).sum();
+            let files: HashMap<&String, usize> = state
+                .data
+                .iter()
+                .flat_map(|data| &data.blobs)
+                .filter_map(|(name, hash)| state.blobs.get(hash).map(|blob| (name, blob.len())))
+                .collect();
+            let usage = serde_json::json!({ "used": used, "limit": state.quota.unwrap_or(QUOTA), "files": files });
+            json(StatusCode::OK, usage.to_string())
+        }
+        (&Method::GET, "/activity") => json(StatusCode::OK, serde_json::to_string(&state.lock().unwrap().activity).unwrap()),
+        (&Method::DELETE, "/account") => {
+            state.lock().unwrap().data = None;
+            status(StatusCode::NO_CONTENT)
+        }
+        (_, "/sync") | (_, "/account") => status(StatusCode::METHOD_NOT_ALLOWED),
+        _ => status(StatusCode::NOT_FOUND),
+    };
+    Ok(response)
+}
+
+/// What a push changed compared with the state before it.
+fn activity(machine: Option<String>, old: Option<&SyncData>, new: &SyncData) -> Activity {
+    let before = old.map(hashes_of).unwrap_or_default();
+    let after = hashes_of(new);
+    let mut files: Vec<String> = after
+        .iter()
+        .filter(|(name, hash)| before.get(*name) != Some(*hash))
+        .map(|(name, _)| name.clone())
+        .chain(before.keys().filter(|name| !after.contains_key(*name)).cloned())
+        .collect();
+    files.sort();
+
+    let names = |data: Option<&SyncData>| -> Vec<String> {
+        data.map(|data| data.packages.iter().map(|p| p.name.clone()).collect()).unwrap_or_default()
+    };
+    let (before, after) = (names(old), names(Some(new)));
+    let mut packages_added: Vec<String> = after.iter().filter(|name| !before.contains(name)).cloned().collect();
+    let mut packages_removed: Vec<String> = before.iter().filter(|name| !after.contains(name)).cloned().collect();
+    packages_added.sort();
+    packages_removed.sort();
+
+    Activity { at: chrono::Utc::now().to_rfc3339(), machine, files, packages_added, packages_removed }
+}
+
+fn ndjson(data: SyncData) -> Response<Body> {
+    let mut body = String::new();
+    for entry in data.into_entries() {
+        body.push_str(&serde_json::to_string(&entry).unwrap());
+        body.push('\n');
+    }
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/x-ndjson")
+        .body(Body::from(body))
+        .unwrap()
+}
+
+fn status(code: StatusCode) -> Response<Body> {
+    Response::builder().status(code).body(Body::empty()).unwrap()
+}
+
+fn json(code: StatusCode, body: String) -> Response<Body> {
+    Response::builder()
+        .status(code)
+        .header("Content-Type", "application/json")
+        .body(Body::from(body))
+        .unwrap()
+}
@@ -0,0 +1,86 @@
+use crate::config::Preferences;
+use colored::{Color, ColoredString, Colorize};
+use std::io::IsTerminal;
+use std::sync::OnceLock;
+
+static THEME: OnceLock<Theme> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy)]
+struct Theme {
+    accent: Color,
+    success: Color,
+    warning: Color,
+    error: Color,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            accent: Color::Blue,
+            success: Color::Green,
+            warning: Color::Yellow,
+            error: Color::Red,
+        }
+    }
+}
+
+/// Applies the color mode and palette from preferences. `auto` honors
+/// `NO_COLOR`, `CLICOLOR`, and `CLICOLOR_FORCE`, and disables color when
+/// stdout isn't a terminal.
+pub fn init(preferences: &Preferences) {
+    let enabled = match preferences.color.as_str() {
+        "always" => true,
+        "never" => false,
+        _ => auto_color_enabled(),
+    };
+    colored::control::set_override(enabled);
+
+    let defaults = Theme::default();
+    let parse = |value: &str, fallback: Color| value.parse().unwrap_or(fallback);
+    let _ = THEME.set(Theme {
+        accent: parse(&preferences.accent_color, defaults.accent),
+        success: parse(&preferences.success_color, defaults.success),
+        warning: parse(&preferences.warning_color, defaults.warning),
+        error: parse(&preferences.error_color, defaults.error),
+    });
+}
+
+fn auto_color_enabled() -> bool {
+    let var = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+
+    if var("NO_COLOR").is_some() {
+        return false;
+    }
+    if var("CLICOLOR_FORCE").is_some_and(|v| v != "0") {
+        return true;
+    }
+    if var("CLICOLOR").is_some_and(|v| v == "0") {
+        return false;
+    }
+    std::io::stdout().is_terminal()
+}
+
+fn theme() -> Theme {
+    THEME.get().copied().unwrap_or_default()
+}
+
+/// Semantic colors resolved from the configured theme.
+pub trait Themed: Colorize + Sized {
+    fn accent(self) -> ColoredString {
+        self.color(theme().accent)
+    }
+
+    fn success(self) -> ColoredString {
+        self.color(theme().success)
+    }
+
+    fn warning(self) -> ColoredString {
+        self.color(theme().warning)
+    }
+
+    fn error(self) -> ColoredString {
+        self.color(theme().error)
+    }
+}
+
+impl<T: Colorize> Themed for T {}
@@ -0,0 +1,448 @@
+//! `kiwi send` and `kiwi receive`: hands an environment archive straight to
+//! another Mac on the same network, without the sync server. The sender
+//! advertises itself over Bonjour (`dns-sd`), and the two talk over TLS,
+//! proving to each other with a PAKE that they share the one-time code the
+//! sender shows and the receiver types before the archive, itself encrypted
+//! with the code, is sent.
+
+use crate::{KiwiError, Result};
+use hmac::{Hmac, Mac};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::{CertificateDer, PrivatePkcs8KeyDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, ServerConfig, SignatureScheme};
+use sha2::Sha256;
+use spake2::{Ed25519Group, Identity, Password, Spake2};
+use std::fs::File;
+use std::io::Read;
+use std::net::SocketAddr;
+use std::process::{Child, Command, Stdio};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinSet;
+use tokio_rustls::{TlsAcceptor, TlsConnector, TlsStream};
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub const SERVICE_TYPE: &str = "_kiwi._tcp";
+
+const MAGIC: &[u8] = b"KIWI-SEND/2\n";
+/// The name in the sender's certificate; receivers don't check it.
+const SERVER_NAME: &str = "kiwi-send.local";
+const ACK: &[u8] = b"OK\n";
+/// Refuses anything bigger, so a stray peer can't make us allocate forever.
+const MAX_PAYLOAD: u64 = 1024 * 1024 * 1024;
+const READ_CHUNK: usize = 64 * 1024;
+/// How long the sender waits for a receiver to open what it was sent.
+const ACK_TIMEOUT: Duration = Duration::from_secs(120);
+/// Unambiguous characters only: no 0/o, 1/l/i.
+const CODE_ALPHABET: &[u8] = b"23456789abcdefghjkmnpqrstuvwxyz";
+const CODE_LENGTH: usize = 10;
+
+/// A random code for one transfer, e.g. `k7m2p-9xqrt`.
+pub fn one_time_code() -> Result<String> {
+    let mut random = File::open("/dev/urandom")?;
+    // Rejection sampling keeps every character equally likely
+    let limit = 256 - 256 % CODE_ALPHABET.len();
+    let mut chars = Vec::with_capacity(CODE_LENGTH);
+    let mut byte = [0u8; 1];
+    while chars.len() < CODE_LENGTH {
+        random.read_exact(&mut byte)?;
+        if (byte[0] as usize) < limit {
+            chars.push(CODE_ALPHABET[byte[0] as usize % CODE_ALPHABET.len()] as char);
+        }
+    }
+    let (first, second) = chars.split_at(CODE_LENGTH / 2);
+    Ok(format!("{}-{}", first.iter().collect::<String>(), second.iter().collect::<String>()))
+}
+
+/// The form a code is encrypted with, so case, spaces, and dashes typed
+/// by the receiver don't matter.
+pub fn normalize_code(code: &str) -> String {
+    code.chars().filter(char::is_ascii_alphanumeric).map(|c| c.to_ascii_lowercase()).collect()
+}
+
+/// A Bonjour registration that lasts until it's dropped.
+pub struct Advertisement {
+    child: Child,
+}
+
+impl Drop for Advertisement {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Registers `name` as a kiwi sender on `port`.
+pub fn advertise(name: &str, port: u16) -> std::io::Result<Advertisement> {
+    let child = Command::new("dns-sd")
+        .args(["-R", name, SERVICE_TYPE, "local", &port.to_string()])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    Ok(Advertisement { child })
+}
+
+/// Names of the kiwi senders seen on the network within `timeout`.
+pub async fn discover(timeout: Duration) -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    watch("dns-sd", &["-B", SERVICE_TYPE, "local"], timeout, |line| {
+        match parse_browse_line(line) {
+            Some((true, name)) if !names.contains(&name) => names.push(name),
+            Some((false, name)) => names.retain(|n| *n != name),
+            _ => {}
+        }
+        false
+    })
+    .await?;
+    Ok(names)
+}
+
+/// The `host:port` the sender called `name` listens on.
+pub async fn resolve(name: &str, timeout: Duration) -> Result<String> {
+    let mut address = None;
+    watch("dns-sd", &["-L", name, SERVICE_TYPE, "local"], timeout, |line| {
+        address = parse_resolve_line(line);
+        address.is_some()
+    })
+    .await?;
+    address.ok_or_else(|| KiwiError::Sync(format!("Couldn't find {} on the network", name)))
+}
+
+/// Feeds each line `program` prints to `on_line` until it returns true or
+/// `timeout` passes; `dns-sd` browses until killed.
+async fn watch(program: &str, args: &[&str], timeout: Duration, mut on_line: impl FnMut(&str) -> bool) -> Result<()> {
+    let mut child = tokio::process::Command::new(program)
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| KiwiError::Sync(format!("Couldn't run {} for Bonjour discovery: {}", program, e)))?;
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let mut lines = BufReader::new(stdout).lines();
+
+    let _ = tokio::time::timeout(timeout, async {
+        while let Ok(Some(line)) = lines.next_line().await {
+            if on_line(&line) {
+                break;
+            }
+        }
+    })
+    .await;
+    let _ = child.kill().await;
+    Ok(())
+}
+
+/// `(added, instance name)` from a `dns-sd -B` result line, e.g.
+/// `12:00:01.123  Add  2  4 local.  _kiwi._tcp.  Jane's MacBook`.
+fn parse_browse_line(line: &str) -> Option<(bool, String)> {
+    let service = format!("{}.", SERVICE_TYPE);
+    let (head, name) = line.split_once(&service)?;
+    let added = match head.split_whitespace().nth(1)? {
+        "Add" => true,
+        "Rmv" => false,
+        _ => return None,
+    };
+    let name = name.trim();
+    (!name.is_empty()).then(|| (added, name.to_string()))
+}
+
+/// `host:port` from a `dns-sd -L` result line, e.g.
+/// `... can be reached at Janes-MacBook.local.:54321 (interface 4)`.
+fn parse_resolve_line(line: &str) -> Option<String> {
+    let (_, rest) = line.split_once(" can be reached at ")?;
+    let target = rest.split_whitespace().next()?;
+    let (host, port) = target.rsplit_once(':')?;
+    port.parse::<u16>().ok()?;
+    Some(format!("{}:{}", host.trim_end_matches('.'), port))
+}
+
+/// Sends `payload` to each receiver that proves it knows `code` (in its
+/// [`normalize_code`] form), handling connections side by side, until one
+/// confirms it opened it. Returns that receiver's address.
+pub async fn serve(listener: &TcpListener, payload: &[u8], code: &str) -> Result<SocketAddr> {
+    let acceptor = TlsAcceptor::from(Arc::new(server_config()?));
+    let payload: Arc<[u8]> = payload.into();
+    let mut transfers = JoinSet::new();
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let (stream, peer) = accepted?;
+                let (acceptor, payload, code) = (acceptor.clone(), payload.clone(), code.to_string());
+                transfers.spawn(async move {
+                    let sent = tokio::time::timeout(ACK_TIMEOUT, send_to(acceptor, stream, &payload, &code)).await;
+                    (peer, matches!(sent, Ok(Ok(true))))
+                });
+            }
+            Some(done) = transfers.join_next() => match done {
+                Ok((peer, true)) => return Ok(peer),
+                // A wrong code or a stray connection; keep waiting for the right one
+                Ok((peer, false)) => log::debug!("Transfer to {} wasn't confirmed", peer),
+                Err(e) => log::debug!("Transfer task failed: {}", e),
+            },
+        }
+    }
+}
+
+/// One receiver's side of [`serve`]: the payload only goes out once the
+/// receiver has proven it knows the code, and only its acknowledgement,
+/// on that same channel, ends the transfer.
+async fn send_to(acceptor: TlsAcceptor, stream: TcpStream, payload: &[u8], code: &str) -> Result<bool> {
+    let mut stream = acceptor.accept(stream).await?;
+    let mut magic = [0u8; MAGIC.len()];
+    stream.read_exact(&mut magic).await?;
+    if magic != MAGIC {
+        return Ok(false);
+    }
+    stream.write_all(MAGIC).await?;
+    let binding = channel_binding(stream.get_ref().1)?;
+    if !authenticate(&mut stream, code, &binding, Role::Sender).await? {
+        return Ok(false);
+    }
+
+    stream.write_all(&(payload.len() as u64).to_be_bytes()).await?;
+    stream.write_all(payload).await?;
+    stream.flush().await?;
+    let mut ack = [0u8; ACK.len()];
+    stream.read_exact(&mut ack).await?;
+    Ok(ack == ACK)
+}
+
+/// An archive received from a sender, still connected until acknowledged.
+pub struct Incoming {
+    stream: TlsStream<TcpStream>,
+    pub payload: Vec<u8>,
+}
+
+impl Incoming {
+    /// Tells the sender the archive opened, so it can stop serving.
+    pub async fn acknowledge(mut self) -> Result<()> {
+        self.stream.write_all(ACK).await?;
+        self.stream.flush().await?;
+        Ok(())
+    }
+}
+
+/// Connects to a sender at `address` (`host:port`), proves both sides know
+/// `code` (in its [`normalize_code`] form), and reads its archive.
+pub async fn fetch(address: &str, code: &str) -> Result<Incoming> {
+    let stream = TcpStream::connect(address)
+        .await
+        .map_err(|e| KiwiError::Sync(format!("Couldn't connect to {}: {}", address, e)))?;
+    let connector = TlsConnector::from(Arc::new(client_config()?));
+    let server_name = ServerName::try_from(SERVER_NAME).expect("a valid DNS name");
+    let mut stream = connector
+        .connect(server_name, stream)
+        .await
+        .map_err(|_| KiwiError::Sync(format!("{} isn't running kiwi send", address)))?;
+
+    stream.write_all(MAGIC).await?;
+    let mut magic = [0u8; MAGIC.len()];
+    stream.read_exact(&mut magic).await?;
+    if magic != MAGIC {
+        return Err(KiwiError::Sync(format!("{} isn't running kiwi send", address)));
+    }
+    let binding = channel_binding(stream.get_ref().1)?;
+    if !authenticate(&mut stream, code, &binding, Role::Receiver).await? {
+        return Err(KiwiError::ValidationError(format!("{} didn't accept the code; check it and try again", address)));
+    }
+
+    let mut length = [0u8; 8];
+    stream.read_exact(&mut length).await?;
+    let length = u64::from_be_bytes(length);
+    if length > MAX_PAYLOAD {
+        return Err(KiwiError::Sync(format!("{} offered {} bytes, more than kiwi accepts", address, length)));
+    }
+    // Grown as the bytes arrive rather than sized up front from the
+    // declared length
+    let mut payload = Vec::new();
+    let mut chunk = vec![0u8; READ_CHUNK];
+    while (payload.len() as u64) < length {
+        let wanted = READ_CHUNK.min((length - payload.len() as u64) as usize);
+        let read = stream.read(&mut chunk[..wanted]).await?;
+        if read == 0 {
+            return Err(KiwiError::Sync(format!("{} closed the connection mid-transfer", address)));
+        }
+        payload.extend_from_slice(&chunk[..read]);
+    }
+    Ok(Incoming { stream: TlsStream::Client(stream), payload })
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Role {
+    Sender,
+    Receiver,
+}
+
+/// Runs SPAKE2 over the code, so neither side learns anything about a code
+/// it doesn't share, then has each side confirm the shared key bound to
+/// this TLS session: a relay between two sessions can't pass the
+/// confirmations along. The receiver confirms first, and the sender only
+/// answers a receiver that got it right. Returns whether both confirmed.
+async fn authenticate<S>(stream: &mut S, code: &str, binding: &[u8], role: Role) -> Result<bool>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let password = Password::new(code.as_bytes());
+    let (receiver, sender) = (Identity::new(b"kiwi receive"), Identity::new(b"kiwi send"));
+    let (state, message) = match role {
+        Role::Receiver => Spake2::<Ed25519Group>::start_a(&password, &receiver, &sender),
+        Role::Sender => Spake2::<Ed25519Group>::start_b(&password, &receiver, &sender),
+    };
+    stream.write_all(&message).await?;
+    let mut theirs = vec![0u8; message.len()];
+    stream.read_exact(&mut theirs).await?;
+    let Ok(key) = state.finish(&theirs) else {
+        return Ok(false);
+    };
+
+    let confirmation = |label: &[u8]| {
+        let mut mac = HmacSha256::new_from_slice(&key).expect("HMAC takes any key length");
+        mac.update(label);
+        mac.update(binding);
+        mac
+    };
+    let (ours, expected) = match role {
+        Role::Receiver => (confirmation(b"kiwi receive confirms"), confirmation(b"kiwi send confirms")),
+        Role::Sender => (confirmation(b"kiwi send confirms"), confirmation(b"kiwi receive confirms")),
+    };
+    let mut tag = [0u8; 32];
+    if role == Role::Receiver {
+        stream.write_all(&ours.finalize().into_bytes()).await?;
+        stream.flush().await?;
+        // A sender that didn't accept ours just hangs up
+        if stream.read_exact(&mut tag).await.is_err() {
+            return Ok(false);
+        }
+        return Ok(expected.verify_slice(&tag).is_ok());
+    }
+    stream.read_exact(&mut tag).await?;
+    if expected.verify_slice(&tag).is_err() {
+        return Ok(false);
+    }
+    stream.write_all(&ours.finalize().into_bytes()).await?;
+    stream.flush().await?;
+    Ok(true)
+}
+
+/// Keying material unique to this TLS session.
+fn channel_binding<Data>(connection: &rustls::ConnectionCommon<Data>) -> Result<[u8; 32]> {
+    connection
+        .export_keying_material([0u8; 32], b"EXPORTER-kiwi-send", None)
+        .map_err(|e| KiwiError::Sync(format!("TLS error: {}", e)))
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+/// TLS 1.3 with a certificate made for this transfer alone; the code, not
+/// the certificate, is what authenticates the sender.
+fn server_config() -> Result<ServerConfig> {
+    let certified = rcgen::generate_simple_self_signed(vec![SERVER_NAME.to_string()])
+        .map_err(|e| KiwiError::Sync(format!("Couldn't create a certificate for the transfer: {}", e)))?;
+    let key = PrivatePkcs8KeyDer::from(certified.key_pair.serialize_der());
+    ServerConfig::builder_with_provider(provider())
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .and_then(|builder| builder.with_no_client_auth().with_single_cert(vec![certified.cert.der().clone()], key.into()))
+        .map_err(|e| KiwiError::Sync(format!("TLS error: {}", e)))
+}
+
+fn client_config() -> Result<ClientConfig> {
+    let provider = provider();
+    Ok(ClientConfig::builder_with_provider(provider.clone())
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .map_err(|e| KiwiError::Sync(format!("TLS error: {}", e)))?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(TransferCertificate(provider)))
+        .with_no_client_auth())
+}
+
+/// Takes the sender's one-off certificate as long as the handshake is
+/// signed with its key; [`authenticate`] decides whether to trust the peer.
+#[derive(Debug)]
+struct TransferCertificate(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for TransferCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> std::result::Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        _message: &[u8],
+        _cert: &CertificateDer<'_>,
+        _dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        // Only TLS 1.3 is offered
+        Err(rustls::Error::General("TLS 1.2 isn't supported".to_string()))
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_one_time_code() {
+        let code = one_time_code().unwrap();
+        assert_eq!(code.len(), CODE_LENGTH + 1);
+        assert_eq!(code.chars().nth(CODE_LENGTH / 2), Some('-'));
+        assert_eq!(normalize_code(&code.to_uppercase().replace('-', " ")), code.replace('-', ""));
+    }
+
+    #[test]
+    fn test_parse_dns_sd_output() {
+        let add = "12:00:01.123  Add        2   4 local.               _kiwi._tcp.          Jane's MacBook";
+        assert_eq!(parse_browse_line(add), Some((true, "Jane's MacBook".to_string())));
+        let removed = "12:00:09.001  Rmv        0   4 local.               _kiwi._tcp.          Jane's MacBook";
+        assert_eq!(parse_browse_line(removed), Some((false, "Jane's MacBook".to_string())));
+        assert_eq!(parse_browse_line("Browsing for _kiwi._tcp.local"), None);
+
+        let reached = "12:00:01.500  Jane's\\032MacBook._kiwi._tcp.local. can be reached at Janes-MacBook.local.:54321 (interface 4)";
+        assert_eq!(parse_resolve_line(reached).as_deref(), Some("Janes-MacBook.local:54321"));
+        assert_eq!(parse_resolve_line("Lookup Jane's MacBook._kiwi._tcp.local"), None);
+    }
+
+    #[tokio::test]
+    async fn test_serve_until_acknowledged() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = tokio::spawn(async move { serve(&listener, b"archive bytes", "k7m2p9xqrt").await.unwrap() });
+
+        // A peer that connects and stalls doesn't hold up the others
+        let _stalled = TcpStream::connect(&address).await.unwrap();
+        // A wrong code gets nothing, and can't end the transfer
+        assert!(matches!(fetch(&address, "k7m2p9xqrs").await, Err(KiwiError::ValidationError(_))));
+        // Nor does a receiver that gives up
+        drop(fetch(&address, "k7m2p9xqrt").await.unwrap());
+        let incoming = fetch(&address, "k7m2p9xqrt").await.unwrap();
+        assert_eq!(incoming.payload, b"archive bytes");
+        incoming.acknowledge().await.unwrap();
+        assert!(server.await.unwrap().ip().is_loopback());
+    }
+}
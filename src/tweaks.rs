@@ -0,0 +1,343 @@
+//! Curated macOS settings, like key repeat or tap to click, applied with
+//! `kiwi tweaks apply <recipe>`. The recipes applied are recorded in a
+//! kiwi-owned file that is tracked like any dotfile, so a bare `kiwi tweaks
+//! apply` on another Mac applies the same ones.
+
+use crate::homebrew::{CommandRunner, SystemRunner};
+use crate::{fixes, Dotfiles, KiwiError, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Name of the recorded recipes inside the dotfiles dir.
+pub const TRACKED_NAME: &str = "kiwi-tweaks";
+const HEADER: &str = "# Managed by kiwi; recipes applied with `kiwi tweaks apply`.\n";
+
+/// A `defaults` value, written with its type flag.
+pub enum Value {
+    Bool(bool),
+    Int(i64),
+    Float(&'static str),
+    /// A string; a leading `~/` is expanded to the home directory
+    String(&'static str),
+}
+
+pub enum Change {
+    /// `defaults write`; `current_host` for per-Mac preferences
+    Default { domain: &'static str, key: &'static str, value: Value, current_host: bool },
+    /// `pmset -c`, for while on the charger; needs sudo
+    Power { setting: &'static str, value: &'static str },
+}
+
+pub struct Recipe {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub changes: &'static [Change],
+    /// Process to restart so it picks the changes up
+    pub restart: Option<&'static str>,
+}
+
+pub const RECIPES: &[Recipe] = &[
+    Recipe {
+        name: "key-repeat",
+        description: "fast key repeat, and repeating instead of the accent menu on hold",
+        changes: &[
+            Change::Default { domain: "NSGlobalDomain", key: "KeyRepeat", value: Value::Int(2), current_host: false },
+            Change::Default { domain: "NSGlobalDomain", key: "InitialKeyRepeat", value: Value::Int(15), current_host: false },
+            Change::Default { domain: "NSGlobalDomain", key: "ApplePressAndHoldEnabled", value: Value::Bool(false), current_host: false },
+        ],
+        restart: None,
+    },
+    Recipe {
+        name: "tap-to-click",
+        description: "tap the trackpad to click, built in and Bluetooth",
+        changes: &[
+            Change::Default { domain: "com.apple.AppleMultitouchTrackpad", key: "Clicking", value: Value::Bool(true), current_host: false },
+            Change::Default { domain: "com.apple.driver.AppleBluetoothMultitouch.trackpad", key: "Clicking", value: Value::Bool(true), current_host: false },
+            Change::Default { domain: "NSGlobalDomain", key: "com.apple.mouse.tapBehavior", value: Value::Int(1), current_host: true },
+        ],
+        restart: None,
+    },
+    Recipe {
+        name: "dock-autohide",
+        description: "hide the Dock, showing it without a delay",
+        changes: &[
+            Change::Default { domain: "com.apple.dock", key: "autohide", value: Value::Bool(true), current_host: false },
+            Change::Default { domain: "com.apple.dock", key: "autohide-delay", value: Value::Float("0"), current_host: false },
+        ],
+        restart: Some("Dock"),
+    },
+    Recipe {
+        name: "screenshots",
+        description: "save screenshots to ~/Pictures/Screenshots as PNG, without the window shadow",
+        changes: &[
+            Change::Default { domain: "com.apple.screencapture", key: "location", value: Value::String("~/Pictures/Screenshots"), current_host: false },
+            Change::Default { domain: "com.apple.screencapture", key: "type", value: Value::String("png"), current_host: false },
+            Change::Default { domain: "com.apple.screencapture", key: "disable-shadow", value: Value::Bool(true), current_host: false },
+        ],
+        restart: Some("SystemUIServer"),
+    },
+    Recipe {
+        name: "no-sleep-on-power",
+        description: "never sleep on the charger, turning the display off after 15 minutes (asks for your password)",
+        changes: &[
+            Change::Power { setting: "sleep", value: "0" },
+            Change::Power { setting: "displaysleep", value: "15" },
+        ],
+        restart: None,
+    },
+];
+
+pub fn find(name: &str) -> Result<&'static Recipe> {
+    RECIPES.iter().find(|recipe| recipe.name == name).ok_or_else(|| {
+        let known: Vec<&str> = RECIPES.iter().map(|recipe| recipe.name).collect();
+        KiwiError::ValidationError(format!("Unknown recipe: {} (kiwi knows {})", name, known.join(", ")))
+    })
+}
+
+pub struct Tweaks {
+    runner: Box<dyn CommandRunner>,
+    path: PathBuf,
+    home: PathBuf,
+}
+
+impl Tweaks {
+    /// Recipes recorded in `path`, with `~/` meaning `home`.
+    pub fn new(path: PathBuf, home: PathBuf) -> Self {
+        Self { runner: Box::new(SystemRunner), path, home }
+    }
+
+    pub fn with_runner(mut self, runner: impl CommandRunner + 'static) -> Self {
+        self.runner = Box::new(runner);
+        self
+    }
+
+    /// `~/.config/kiwi/tweaks`
+    pub fn default_path() -> Result<PathBuf> {
+        let home = dirs::home_dir().ok_or_else(|| KiwiError::Config("Could not find home directory".to_string()))?;
+        Ok(home.join(".config/kiwi/tweaks"))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The recipes applied so far, on this Mac or another.
+    pub fn recorded(&self) -> Vec<String> {
+        fs::read_to_string(&self.path).map(|contents| recorded_in(&contents)).unwrap_or_default()
+    }
+
+    /// Whether every setting in `recipe` already has its value.
+    pub fn is_applied(&self, recipe: &Recipe) -> bool {
+        recipe.changes.iter().all(|change| match change {
+            Change::Default { domain, key, value, current_host } => {
+                let mut args = Vec::new();
+                if *current_host {
+                    args.push("-currentHost");
+                }
+                args.extend(["read", domain, key]);
+                self.runner
+                    .run("defaults", &args)
+                    .is_ok_and(|output| output.success && String::from_utf8_lossy(&output.stdout).trim() == self.read_form(value))
+            }
+            // `pmset -g custom` lists the charger's settings as `name value`
+            Change::Power { setting, value } => self.runner.run("pmset", &["-g", "custom"]).is_ok_and(|output| {
+                let stdout = String::from_utf8_lossy(&output.stdout);
+                let charger = stdout.split("AC Power:").nth(1).unwrap_or_default();
+                charger.lines().any(|line| {
+                    let mut fields = line.split_whitespace();
+                    fields.next() == Some(setting) && fields.next() == Some(value)
+                })
+            }),
+        })
+    }
+
+    /// Applies `recipe`, restarts what needs it, and records it.
+    pub fn apply(&self, recipe: &Recipe, dotfiles: &Dotfiles) -> Result<()> {
+        self.apply_changes(recipe)?;
+        self.record(recipe.name, dotfiles)
+    }
+
+    /// The commands applying `recipe` runs, as they'd be typed.
+    pub fn commands(&self, recipe: &Recipe) -> Vec<String> {
+        recipe
+            .changes
+            .iter()
+            .map(|change| match change {
+                Change::Default { domain, key, value, current_host } => {
+                    let written = self.write_form(value);
+                    let mut args = Vec::new();
+                    if *current_host {
+                        args.push("-currentHost");
+                    }
+                    args.extend(["write", domain, key, self.type_flag(value), &written]);
+                    fixes::command_line("defaults", &args)
+                }
+                Change::Power { setting, value } => fixes::command_line("sudo", &["pmset", "-c", setting, value]),
+            })
+            .collect()
+    }
+
+    /// Applies `recipe` and restarts what needs it, without recording it;
+    /// for recipes already recorded elsewhere, like a restored Mac's.
+    pub fn apply_changes(&self, recipe: &Recipe) -> Result<()> {
+        for change in recipe.changes {
+            match change {
+                Change::Default { domain, key, value, current_host } => {
+                    let written = self.write_form(value);
+                    if let Value::String(path) = value {
+                        if path.starts_with("~/") {
+                            fs::create_dir_all(&written)?;
+                        }
+                    }
+                    let mut args = Vec::new();
+                    if *current_host {
+                        args.push("-currentHost");
+                    }
+                    args.extend(["write", domain, key, self.type_flag(value), &written]);
+                    let output = self.runner.run("defaults", &args)?;
+                    if !output.success {
+                        return Err(KiwiError::Config(format!(
+                            "defaults write {} {} failed: {}",
+                            domain,
+                            key,
+                            String::from_utf8_lossy(&output.stderr).trim()
+                        )));
+                    }
+                }
+                Change::Power { setting, value } => {
+                    if !self.runner.run_interactive("sudo", &["pmset", "-c", setting, value])? {
+                        return Err(KiwiError::Config(format!("pmset couldn't set {}", setting)));
+                    }
+                }
+            }
+        }
+        if let Some(process) = recipe.restart {
+            let _ = self.runner.run("killall", &[process]);
+        }
+        Ok(())
+    }
+
+    fn record(&self, name: &str, dotfiles: &Dotfiles) -> Result<()> {
+        let mut recorded = self.recorded();
+        if !recorded.iter().any(|r| r == name) {
+            recorded.push(name.to_string());
+        }
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&self.path, format!("{}{}\n", HEADER, recorded.join("\n")))?;
+
+        let canonical = self.path.canonicalize()?;
+        if !dotfiles.list()?.iter().any(|d| d.path == canonical) {
+            dotfiles.add(&self.path, Some(TRACKED_NAME.to_string()), false)?;
+        }
+        Ok(())
+    }
+
+    fn type_flag(&self, value: &Value) -> &'static str {
+        match value {
+            Value::Bool(_) => "-bool",
+            Value::Int(_) => "-int",
+            Value::Float(_) => "-float",
+            Value::String(_) => "-string",
+        }
+    }
+
+    fn write_form(&self, value: &Value) -> String {
+        match value {
+            Value::Bool(b) => b.to_string(),
+            Value::Int(i) => i.to_string(),
+            Value::Float(f) => f.to_string(),
+            Value::String(s) => match s.strip_prefix("~/") {
+                Some(rest) => self.home.join(rest).to_string_lossy().to_string(),
+                None => s.to_string(),
+            },
+        }
+    }
+
+    /// How `defaults read` prints a value: booleans as 1 or 0.
+    fn read_form(&self, value: &Value) -> String {
+        match value {
+            Value::Bool(b) => u8::from(*b).to_string(),
+            _ => self.write_form(value),
+        }
+    }
+}
+
+/// Recipe names in the contents of a recorded recipes file.
+pub fn recorded_in(contents: &str) -> Vec<String> {
+    contents.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')).map(str::to_string).collect()
+}
+
+impl Default for Tweaks {
+    fn default() -> Self {
+        Self::new(Self::default_path().unwrap_or_default(), dirs::home_dir().unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::homebrew::CommandOutput;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    /// Keeps written defaults in memory so reads see them, and records
+    /// everything else that runs.
+    #[derive(Clone, Default)]
+    struct Defaults {
+        values: Arc<Mutex<HashMap<String, String>>>,
+        calls: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl CommandRunner for Defaults {
+        fn run(&self, program: &str, args: &[&str]) -> std::io::Result<CommandOutput> {
+            let args: Vec<&str> = args.iter().copied().filter(|a| *a != "-currentHost").collect();
+            let mut values = self.values.lock().unwrap();
+            let (success, stdout) = match (program, args.as_slice()) {
+                ("defaults", ["read", domain, key]) => match values.get(&format!("{} {}", domain, key)) {
+                    Some(value) => (true, format!("{}\n", value)),
+                    None => (false, String::new()),
+                },
+                ("defaults", ["write", domain, key, flag, value]) => {
+                    let value = match (*flag, *value) {
+                        ("-bool", "true") => "1",
+                        ("-bool", _) => "0",
+                        _ => value,
+                    };
+                    values.insert(format!("{} {}", domain, key), value.to_string());
+                    (true, String::new())
+                }
+                _ => {
+                    self.calls.lock().unwrap().push(format!("{} {}", program, args.join(" ")));
+                    (true, String::new())
+                }
+            };
+            Ok(CommandOutput { success, stdout: stdout.into_bytes(), stderr: Vec::new() })
+        }
+    }
+
+    #[test]
+    fn test_apply_records_recipe() {
+        let dir = std::env::temp_dir().join(format!("kiwi-tweaks-test-{}", std::process::id()));
+        let dotfiles = Dotfiles::new(dir.join("dotfiles"), dir.join("dotfiles/dotfiles.json"));
+        let runner = Defaults::default();
+        let tweaks = Tweaks::new(dir.join("tweaks"), dir.join("home")).with_runner(runner.clone());
+        let screenshots = find("screenshots").unwrap();
+        let dock = find("dock-autohide").unwrap();
+
+        assert!(!tweaks.is_applied(screenshots));
+        tweaks.apply(screenshots, &dotfiles).unwrap();
+        tweaks.apply(dock, &dotfiles).unwrap();
+        tweaks.apply(screenshots, &dotfiles).unwrap();
+        assert!(tweaks.is_applied(screenshots) && tweaks.is_applied(dock));
+        assert!(!tweaks.is_applied(find("key-repeat").unwrap()));
+        assert!(dir.join("home/Pictures/Screenshots").is_dir());
+        assert_eq!(tweaks.recorded(), vec!["screenshots", "dock-autohide"]);
+        assert_eq!(dotfiles.list().unwrap().len(), 1);
+        assert_eq!(*runner.calls.lock().unwrap(), vec!["killall SystemUIServer", "killall Dock", "killall SystemUIServer"]);
+        assert!(find("natural-scrolling").is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,313 @@
+use crate::dotfiles::{Dotfile, DriftStatus};
+use crate::homebrew::Package;
+use crate::{Result, Dotfiles, Homebrew, Sync};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Direction, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Pane {
+    Dotfiles,
+    Packages,
+}
+
+enum Mode {
+    Normal,
+    /// Typing the path of a dotfile to add
+    Adding(String),
+    /// Waiting for `y` before untracking this dotfile
+    ConfirmingRemoval(PathBuf),
+}
+
+pub struct Dashboard<'a> {
+    dotfiles: &'a Dotfiles,
+    homebrew: &'a Homebrew,
+    sync: Option<&'a Sync>,
+    tracked: Vec<(Dotfile, DriftStatus)>,
+    packages: Vec<Package>,
+    outdated: Vec<String>,
+    pane: Pane,
+    dotfile_state: ListState,
+    package_state: ListState,
+    mode: Mode,
+    status: String,
+}
+
+impl<'a> Dashboard<'a> {
+    pub fn new(dotfiles: &'a Dotfiles, homebrew: &'a Homebrew, sync: Option<&'a Sync>) -> Self {
+        Self {
+            dotfiles,
+            homebrew,
+            sync,
+            tracked: Vec::new(),
+            packages: Vec::new(),
+            outdated: Vec::new(),
+            pane: Pane::Dotfiles,
+            dotfile_state: ListState::default(),
+            package_state: ListState::default(),
+            mode: Mode::Normal,
+            status: "Press ? for help".to_string(),
+        }
+    }
+
+    pub async fn run(mut self) -> Result<()> {
+        self.refresh();
+
+        let mut terminal = ratatui::init();
+        let result = self.event_loop(&mut terminal).await;
+        ratatui::restore();
+        result
+    }
+
+    async fn event_loop(&mut self, terminal: &mut DefaultTerminal) -> Result<()> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+
+            if !event::poll(Duration::from_millis(250))? {
+                continue;
+            }
+
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+
+            if let Mode::Adding(input) = &mut self.mode {
+                match key.code {
+                    KeyCode::Esc => {
+                        self.mode = Mode::Normal;
+                        self.status = "Add cancelled".to_string();
+                    }
+                    KeyCode::Enter => {
+                        let path = PathBuf::from(input.trim());
+                        self.mode = Mode::Normal;
+                        self.status = match self.dotfiles.add(&path, None, false) {
+                            Ok(()) => format!("Added {}", path.display()),
+                            Err(e) => format!("Failed to add {}: {}", path.display(), e),
+                        };
+                        self.refresh();
+                    }
+                    KeyCode::Backspace => {
+                        input.pop();
+                    }
+                    KeyCode::Char(c) => input.push(c),
+                    _ => {}
+                }
+                continue;
+            }
+
+            if let Mode::ConfirmingRemoval(path) = &self.mode {
+                let path = path.clone();
+                self.mode = Mode::Normal;
+                if key.code == KeyCode::Char('y') {
+                    self.remove(&path);
+                } else {
+                    self.status = "Remove cancelled".to_string();
+                }
+                continue;
+            }
+
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Tab => {
+                    self.pane = match self.pane {
+                        Pane::Dotfiles => Pane::Packages,
+                        Pane::Packages => Pane::Dotfiles,
+                    };
+                }
+                KeyCode::Down | KeyCode::Char('j') => self.move_selection(1),
+                KeyCode::Up | KeyCode::Char('k') => self.move_selection(-1),
+                KeyCode::Char('a') => {
+                    self.pane = Pane::Dotfiles;
+                    self.mode = Mode::Adding(String::new());
+                }
+                KeyCode::Char('d') => self.confirm_removal(),
+                KeyCode::Char('r') => {
+                    self.refresh();
+                    self.status = "Refreshed".to_string();
+                }
+                KeyCode::Char('s') => self.push().await,
+                KeyCode::Char('?') => {
+                    self.status = "tab: switch pane  j/k: move  a: add  d: remove (y confirms)  s: sync push  r: refresh  q: quit".to_string();
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn refresh(&mut self) {
+        self.tracked = self
+            .dotfiles
+            .list()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|dotfile| {
+                let drift = self.dotfiles.drift(&dotfile);
+                (dotfile, drift)
+            })
+            .collect();
+        self.packages = self.homebrew.list_installed().unwrap_or_default();
+        self.outdated = self.homebrew.outdated().unwrap_or_default();
+
+        clamp_selection(&mut self.dotfile_state, self.tracked.len());
+        clamp_selection(&mut self.package_state, self.packages.len());
+    }
+
+    fn move_selection(&mut self, delta: isize) {
+        let (state, len) = match self.pane {
+            Pane::Dotfiles => (&mut self.dotfile_state, self.tracked.len()),
+            Pane::Packages => (&mut self.package_state, self.packages.len()),
+        };
+        if len == 0 {
+            return;
+        }
+        let current = state.selected().unwrap_or(0) as isize;
+        let next = (current + delta).rem_euclid(len as isize) as usize;
+        state.select(Some(next));
+    }
+
+    /// Asks before removing the selected dotfile; `y` goes ahead.
+    fn confirm_removal(&mut self) {
+        if self.pane != Pane::Dotfiles {
+            self.status = "Packages can't be removed from the dashboard".to_string();
+            return;
+        }
+        let Some((dotfile, _)) = self.dotfile_state.selected().and_then(|i| self.tracked.get(i)) else {
+            return;
+        };
+
+        self.mode = Mode::ConfirmingRemoval(dotfile.path.clone());
+    }
+
+    fn remove(&mut self, path: &Path) {
+        self.status = match self.dotfiles.remove(path) {
+            Ok(()) => format!("Removed {}", path.display()),
+            Err(e) => format!("Failed to remove {}: {}", path.display(), e),
+        };
+        self.refresh();
+    }
+
+    async fn push(&mut self) {
+        let Some(sync) = self.sync else {
+            self.status = "Sync not configured".to_string();
+            return;
+        };
+        self.status = match sync.push().await {
+            Ok(()) => "✓ Push complete".to_string(),
+            Err(e) => format!("Push failed: {}", e),
+        };
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let rows = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Length(3), Constraint::Min(5), Constraint::Length(3)])
+            .split(frame.area());
+        let panes = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+            .split(rows[1]);
+
+        let sync_status = if self.sync.is_some() {
+            Span::styled("sync: configured", Style::default().fg(Color::Green))
+        } else {
+            Span::styled("sync: not configured", Style::default().fg(Color::Red))
+        };
+        let drifted = self.tracked.iter().filter(|(_, d)| *d != DriftStatus::Clean).count();
+        let header = Paragraph::new(Line::from(vec![
+            Span::styled("🥝 Kiwi  ", Style::default().add_modifier(Modifier::BOLD)),
+            sync_status,
+            Span::raw(format!(
+                "  dotfiles: {} ({} drifted)  packages: {} ({} outdated)",
+                self.tracked.len(),
+                drifted,
+                self.packages.len(),
+                self.outdated.len()
+            )),
+        ]))
+        .block(Block::default().borders(Borders::ALL));
+        frame.render_widget(header, rows[0]);
+
+        let dotfile_items: Vec<ListItem> = self
+            .tracked
+            .iter()
+            .map(|(dotfile, drift)| {
+                let (marker, color) = match drift {
+                    DriftStatus::Clean => ("✓", Color::Green),
+                    DriftStatus::Missing => ("✗", Color::Red),
+                    DriftStatus::Unlinked => ("~", Color::Yellow),
+                    DriftStatus::Foreign => ("!", Color::Red),
+                };
+                ListItem::new(Line::from(vec![
+                    Span::styled(format!("{} ", marker), Style::default().fg(color)),
+                    Span::raw(dotfile.path.display().to_string()),
+                ]))
+            })
+            .collect();
+        let package_items: Vec<ListItem> = self
+            .packages
+            .iter()
+            .map(|package| {
+                let version = package.version.as_deref().unwrap_or("latest");
+                if self.outdated.contains(&package.name) {
+                    ListItem::new(Line::from(vec![
+                        Span::styled("↑ ", Style::default().fg(Color::Yellow)),
+                        Span::raw(format!("{} ({})", package.name, version)),
+                    ]))
+                } else {
+                    ListItem::new(format!("  {} ({})", package.name, version))
+                }
+            })
+            .collect();
+
+        let highlight = Style::default().add_modifier(Modifier::REVERSED);
+        frame.render_stateful_widget(
+            List::new(dotfile_items)
+                .block(pane_block("Dotfiles", self.pane == Pane::Dotfiles))
+                .highlight_style(highlight),
+            panes[0],
+            &mut self.dotfile_state,
+        );
+        frame.render_stateful_widget(
+            List::new(package_items)
+                .block(pane_block("Packages", self.pane == Pane::Packages))
+                .highlight_style(highlight),
+            panes[1],
+            &mut self.package_state,
+        );
+
+        let footer = match &self.mode {
+            Mode::Adding(input) => Paragraph::new(format!("Add path: {}▏", input))
+                .block(Block::default().borders(Borders::ALL).title("Enter to add, Esc to cancel")),
+            Mode::ConfirmingRemoval(path) => Paragraph::new(format!("Remove {} from kiwi? (y/n)", path.display()))
+                .block(Block::default().borders(Borders::ALL).title("y to remove, any other key to cancel")),
+            Mode::Normal => Paragraph::new(self.status.as_str()).block(Block::default().borders(Borders::ALL)),
+        };
+        frame.render_widget(footer, rows[2]);
+    }
+}
+
+fn pane_block(title: &str, focused: bool) -> Block<'_> {
+    let style = if focused {
+        Style::default().fg(Color::Cyan)
+    } else {
+        Style::default()
+    };
+    Block::default().borders(Borders::ALL).title(title).border_style(style)
+}
+
+fn clamp_selection(state: &mut ListState, len: usize) {
+    match (state.selected(), len) {
+        (_, 0) => state.select(None),
+        (None, _) => state.select(Some(0)),
+        (Some(i), len) if i >= len => state.select(Some(len - 1)),
+        _ => {}
+    }
+}
@@ -0,0 +1,143 @@
+//! Syntax checks for configs before kiwi overwrites them, so a bad pull
+//! can't leave a shell that won't start. Data formats are parsed in
+//! process; shell and tmux configs are dry-run by their own tools, and
+//! skipped when the tool isn't installed.
+
+use crate::homebrew::{CommandRunner, SystemRunner};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Yaml,
+    Toml,
+    Zsh,
+    Bash,
+    Sh,
+    Fish,
+    Tmux,
+}
+
+impl Format {
+    /// Guesses the format from a file name, e.g. `.zshrc` or `settings.json`.
+    pub fn for_name(name: &str) -> Option<Format> {
+        let file = Path::new(name).file_name()?.to_str()?;
+        let format = match file {
+            ".zshrc" | ".zshenv" | ".zprofile" | ".zlogin" | ".zlogout" => Format::Zsh,
+            ".bashrc" | ".bash_profile" | ".bash_login" | ".bash_logout" => Format::Bash,
+            ".profile" => Format::Sh,
+            ".tmux.conf" | "tmux.conf" => Format::Tmux,
+            _ => match Path::new(file).extension()?.to_str()? {
+                "json" => Format::Json,
+                "yaml" | "yml" => Format::Yaml,
+                "toml" => Format::Toml,
+                "zsh" => Format::Zsh,
+                "bash" => Format::Bash,
+                "sh" => Format::Sh,
+                "fish" => Format::Fish,
+                _ => return None,
+            },
+        };
+        Some(format)
+    }
+}
+
+pub struct Validator {
+    runner: Box<dyn CommandRunner>,
+}
+
+impl Default for Validator {
+    fn default() -> Self {
+        Self { runner: Box::new(SystemRunner) }
+    }
+}
+
+impl Validator {
+    pub fn with_runner(runner: impl CommandRunner + 'static) -> Self {
+        Self { runner: Box::new(runner) }
+    }
+
+    /// Checks `contents` as the format `name` suggests. Returns why it's
+    /// broken, or `None` when it's valid, unrecognized, or can't be checked.
+    pub fn check(&self, name: &str, contents: &[u8]) -> Option<String> {
+        let format = Format::for_name(name)?;
+        let text = match std::str::from_utf8(contents) {
+            Ok(text) => text,
+            Err(_) => return Some("not valid UTF-8".to_string()),
+        };
+
+        match format {
+            Format::Json => serde_json::from_str::<serde_json::Value>(text).err().map(|e| e.to_string()),
+            Format::Yaml => serde_yaml::from_str::<serde_yaml::Value>(text).err().map(|e| e.to_string()),
+            Format::Toml => toml::from_str::<toml::Value>(text).err().map(|e| e.to_string()),
+            Format::Zsh => self.dry_run("zsh", &["-n"], text),
+            Format::Bash => self.dry_run("bash", &["-n"], text),
+            Format::Sh => self.dry_run("sh", &["-n"], text),
+            Format::Fish => self.dry_run("fish", &["--no-execute"], text),
+            Format::Tmux => {
+                // A private server socket, so the user's sessions are untouched
+                let socket = format!("kiwi-validate-{}", std::process::id());
+                self.dry_run("tmux", &["-L", &socket, "-f", "{}", "start-server", ";", "kill-server"], text)
+            }
+        }
+    }
+
+    /// Runs `program` with `args` on a scratch copy of `text`; `{}` in
+    /// `args` is replaced by its path, otherwise the path goes last.
+    fn dry_run(&self, program: &str, args: &[&str], text: &str) -> Option<String> {
+        let path = std::env::temp_dir().join(format!("kiwi-validate-{}-{}", std::process::id(), program));
+        fs::write(&path, text).ok()?;
+        let path_arg = path.to_string_lossy().to_string();
+        let mut args: Vec<&str> = args.iter().map(|arg| if *arg == "{}" { path_arg.as_str() } else { arg }).collect();
+        if !args.contains(&path_arg.as_str()) {
+            args.push(&path_arg);
+        }
+
+        let result = self.runner.run(program, &args);
+        let _ = fs::remove_file(&path);
+        match result {
+            Ok(output) if output.success && output.stderr.is_empty() => None,
+            Ok(output) => {
+                let stderr = String::from_utf8_lossy(&output.stderr).replace(&path_arg, "line");
+                Some(stderr.trim().lines().next().unwrap_or("check failed").to_string())
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => None,
+            Err(e) => {
+                log::warn!("Couldn't run {} to check a config: {}", program, e);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::homebrew::CommandOutput;
+
+    /// Fails any shell check whose file contains `fi` without `if`.
+    struct Shell;
+
+    impl CommandRunner for Shell {
+        fn run(&self, _program: &str, args: &[&str]) -> std::io::Result<CommandOutput> {
+            let script = fs::read_to_string(args.last().unwrap())?;
+            let broken = script.contains("fi") && !script.contains("if ");
+            let stderr = if broken { format!("{}:2: parse error near `fi'", args.last().unwrap()) } else { String::new() };
+            Ok(CommandOutput { success: !broken, stdout: Vec::new(), stderr: stderr.into_bytes() })
+        }
+    }
+
+    #[test]
+    fn test_check_by_format() {
+        let validator = Validator::with_runner(Shell);
+        assert_eq!(validator.check("settings.json", b"{\"a\": 1}"), None);
+        assert!(validator.check("settings.json", b"{\"a\": ").is_some());
+        assert!(validator.check("starship.toml", b"[character\n").is_some());
+        assert!(validator.check("config.yml", b"a: [1, 2\n").is_some());
+        assert_eq!(validator.check(".zshrc", b"if true; then\n  echo hi\nfi\n"), None);
+        assert_eq!(validator.check(".zshrc", b"echo hi\nfi\n").as_deref(), Some("line:2: parse error near `fi'"));
+        assert_eq!(validator.check("notes.txt", b"fi"), None);
+    }
+}
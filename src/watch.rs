@@ -0,0 +1,232 @@
+//! `kiwi watch`: a long-running agent that pushes tracked dotfiles when they
+//! change. Edits to the config file are picked up on the next tick, so
+//! preferences like the interval, auto_sync, and excludes apply without a restart.
+//! Files that change all the time, like shell history, don't set off a push
+//! on their own, and a push waits until edits have settled.
+
+use crate::homebrew::matches_pattern;
+use crate::ops::Context;
+use crate::reporter::Reporter;
+use crate::{Config, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+/// How often the config file and the interval are checked.
+const TICK: Duration = Duration::from_secs(2);
+/// How long changed files must stay unchanged before they're pushed
+const SETTLE: Duration = Duration::from_secs(10);
+/// Files rewritten by ordinary use rather than edited, by name. Sockets
+/// are ignored whatever they're called.
+pub const VOLATILE: &[&str] = &[
+    "*_history",
+    ".*history",
+    ".lesshst",
+    ".viminfo",
+    ".DS_Store",
+    "*.sock",
+    "*.swp",
+    "*.lock",
+    ".zcompdump*",
+];
+
+/// Notices edits to the config file by its size and mtime.
+pub struct ConfigWatcher {
+    path: PathBuf,
+    stamp: Option<(SystemTime, u64)>,
+}
+
+impl ConfigWatcher {
+    pub fn new(path: PathBuf) -> Self {
+        let stamp = stamp(&path);
+        Self { path, stamp }
+    }
+
+    /// The config as it is on disk, if it changed since the last call.
+    /// An invalid edit is returned as an error once, and the next valid
+    /// edit is picked up as usual.
+    pub fn poll(&mut self) -> Option<Result<Config>> {
+        let stamp = stamp(&self.path);
+        if stamp == self.stamp {
+            return None;
+        }
+        self.stamp = stamp;
+        Some(Config::load_from(&self.path))
+    }
+}
+
+fn stamp(path: &std::path::Path) -> Option<(SystemTime, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+/// Human-readable notes on the watched preferences that differ.
+pub fn changes(old: &Config, new: &Config) -> Vec<String> {
+    let mut changes = Vec::new();
+    if old.preferences.sync_interval_minutes != new.preferences.sync_interval_minutes {
+        changes.push(format!("sync interval is now {} minutes", new.preferences.sync_interval_minutes));
+    }
+    if old.preferences.auto_sync != new.preferences.auto_sync {
+        let state = if new.preferences.auto_sync { "on" } else { "off" };
+        changes.push(format!("auto_sync is now {}", state));
+    }
+    if old.excluded_packages != new.excluded_packages {
+        changes.push(format!("{} package exclusions", new.excluded_packages.len()));
+    }
+    if old.watch_ignore != new.watch_ignore {
+        changes.push(format!("{} ignore rules", new.watch_ignore.len()));
+    }
+    if old.sync_url != new.sync_url || old.sync_token != new.sync_token {
+        changes.push("sync credentials changed".to_string());
+    }
+    changes
+}
+
+/// Whether changes to `path` are left out of deciding to push: sockets,
+/// the built-in volatile files, and `rules`. Rules without a `/` match the
+/// file name; others match the whole path, with `~` for the home directory.
+pub fn is_ignored(path: &Path, rules: &[String]) -> bool {
+    if fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+        return true;
+    }
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let full = path.to_string_lossy();
+    let home = dirs::home_dir().map(|home| home.to_string_lossy().to_string());
+    VOLATILE.iter().any(|pattern| matches_pattern(&name, pattern))
+        || rules.iter().any(|rule| match rule.strip_prefix("~/") {
+            _ if !rule.contains('/') => matches_pattern(&name, rule),
+            Some(rest) => home.as_ref().is_some_and(|home| matches_pattern(&full, &format!("{}/{}", home, rest))),
+            None => matches_pattern(&full, rule),
+        })
+}
+
+/// Runs until the process is stopped. Every interval, tracked dotfiles are
+/// hashed, and once they've settled they're pushed if anything besides
+/// ignored files changed since the last push.
+pub async fn run(mut context: Context, config_path: PathBuf, reporter: &dyn Reporter) -> Result<()> {
+    let mut watcher = ConfigWatcher::new(config_path);
+    let mut pushed = current_hashes(&context)?;
+    let mut seen = pushed.clone();
+    let mut seen_at = Instant::now();
+    let mut last_check = Instant::now();
+    let mut settling = false;
+
+    loop {
+        tokio::time::sleep(TICK).await;
+
+        match watcher.poll() {
+            Some(Ok(config)) => {
+                let changes = changes(&context.config, &config);
+                context = Context::new(config);
+                if !changes.is_empty() {
+                    reporter.progress(&format!("Reloaded config: {}", changes.join(", ")));
+                }
+            }
+            Some(Err(e)) => reporter.warning(&format!("Ignoring config edit: {}", e)),
+            None => {}
+        }
+
+        let interval = Duration::from_secs(context.config.preferences.sync_interval_minutes * 60);
+        if !settling && last_check.elapsed() < interval {
+            continue;
+        }
+        last_check = Instant::now();
+        settling = false;
+        if !context.config.preferences.auto_sync {
+            continue;
+        }
+        let Some(sync) = &context.sync else {
+            continue;
+        };
+
+        let hashes = current_hashes(&context)?;
+        if hashes == pushed {
+            continue;
+        }
+        // Still being edited: look again every tick until it settles
+        if hashes != seen {
+            seen = hashes;
+            seen_at = Instant::now();
+            settling = true;
+            continue;
+        }
+        if seen_at.elapsed() < SETTLE {
+            settling = true;
+            continue;
+        }
+        match sync.push().await {
+            Ok(()) => {
+                reporter.success("Pushed changed dotfiles");
+                pushed = hashes;
+            }
+            Err(e) => reporter.warning(&format!("Push failed, retrying next interval: {}", e)),
+        }
+    }
+}
+
+/// Hashes of the tracked files whose changes count toward a push.
+fn current_hashes(context: &Context) -> Result<HashMap<PathBuf, String>> {
+    let tracked: Vec<_> = context
+        .dotfiles
+        .list()?
+        .into_iter()
+        .filter(|dotfile| !is_ignored(&dotfile.path, &context.config.watch_ignore))
+        .collect();
+    context.dotfiles.hashes(&tracked)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_edits_are_noticed_once() {
+        let dir = std::env::temp_dir().join(format!("kiwi-watch-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.json");
+        let mut config = Config { dotfiles_dir: dir.join("dotfiles"), ..Config::default() };
+        fs::write(&path, serde_json::to_string(&config).unwrap()).unwrap();
+
+        let mut watcher = ConfigWatcher::new(path.clone());
+        assert!(watcher.poll().is_none());
+
+        config.preferences.sync_interval_minutes = 5;
+        config.excluded_packages.push("docker*".to_string());
+        fs::write(&path, serde_json::to_string_pretty(&config).unwrap()).unwrap();
+        let reloaded = watcher.poll().unwrap().unwrap();
+        assert_eq!(reloaded.preferences.sync_interval_minutes, 5);
+        assert!(watcher.poll().is_none());
+        assert_eq!(
+            changes(&Config { dotfiles_dir: dir.join("dotfiles"), ..Config::default() }, &reloaded),
+            vec!["sync interval is now 5 minutes", "1 package exclusions"]
+        );
+
+        fs::write(&path, "{ not json").unwrap();
+        assert!(watcher.poll().unwrap().is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_volatile_files_are_ignored() {
+        let dir = std::env::temp_dir().join(format!("kiwi-watch-ignore-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let socket = dir.join("agent");
+        let _listener = std::os::unix::net::UnixListener::bind(&socket).unwrap();
+
+        assert!(is_ignored(&socket, &[]));
+        assert!(is_ignored(&dir.join(".zsh_history"), &[]));
+        assert!(is_ignored(&dir.join(".python_history"), &[]));
+        assert!(is_ignored(&dir.join(".DS_Store"), &[]));
+        assert!(!is_ignored(&dir.join(".zshrc"), &[]));
+
+        let rules = vec!["*.log".to_string(), format!("{}/hosts.*", dir.display())];
+        assert!(is_ignored(&dir.join("debug.log"), &rules));
+        assert!(is_ignored(&dir.join("hosts.yml"), &rules));
+        assert!(!is_ignored(&dir.join("config.yml"), &rules));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
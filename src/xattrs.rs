@@ -0,0 +1,134 @@
+//! Extended attributes that travel with synced files. Only the ones that mean
+//! something on another Mac are synced: Finder tags and Finder flags such as
+//! hidden or locked. The quarantine flag Gatekeeper sets on downloads is never
+//! pushed and is cleared from files a pull writes, since kiwi vouches for
+//! those. Everything goes through the `xattr` tool and is best effort.
+
+use crate::homebrew::{CommandRunner, SystemRunner};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Attributes worth carrying to another Mac.
+pub const SYNCED: &[&str] = &["com.apple.metadata:_kMDItemUserTags", "com.apple.FinderInfo"];
+pub const QUARANTINE: &str = "com.apple.quarantine";
+
+/// Attribute names to their values as hex.
+pub type Attributes = HashMap<String, String>;
+
+pub struct Xattrs {
+    runner: Box<dyn CommandRunner>,
+}
+
+impl Default for Xattrs {
+    fn default() -> Self {
+        Self { runner: Box::new(SystemRunner) }
+    }
+}
+
+impl Xattrs {
+    pub fn with_runner(runner: impl CommandRunner + 'static) -> Self {
+        Self { runner: Box::new(runner) }
+    }
+
+    /// Names of the attributes set on `path`.
+    fn names(&self, path: &Path) -> Vec<String> {
+        match self.runner.run("xattr", &[&path.to_string_lossy()]) {
+            Ok(output) if output.success => String::from_utf8_lossy(&output.stdout).lines().map(str::to_string).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// The synced attributes set on `path`.
+    pub fn read(&self, path: &Path) -> Attributes {
+        let path = path.to_string_lossy();
+        let mut attributes = Attributes::new();
+        for name in self.names(Path::new(path.as_ref())).into_iter().filter(|name| SYNCED.contains(&name.as_str())) {
+            match self.runner.run("xattr", &["-px", &name, &path]) {
+                Ok(output) if output.success => {
+                    let hex: String = String::from_utf8_lossy(&output.stdout).split_whitespace().collect();
+                    attributes.insert(name, hex.to_lowercase());
+                }
+                _ => log::debug!("Couldn't read {} from {}", name, path),
+            }
+        }
+        attributes
+    }
+
+    /// Sets `attributes` on `path`, leaving its other attributes alone.
+    /// Only synced attributes are written, whatever the remote sent.
+    pub fn write(&self, path: &Path, attributes: &Attributes) {
+        let path = path.to_string_lossy();
+        for (name, hex) in attributes.iter().filter(|(name, _)| SYNCED.contains(&name.as_str())) {
+            if !self.runner.run("xattr", &["-wx", name, hex, &path]).is_ok_and(|output| output.success) {
+                log::debug!("Couldn't set {} on {}", name, path);
+            }
+        }
+    }
+
+    /// Clears the quarantine flag from `path`. Returns whether it had one.
+    pub fn strip_quarantine(&self, path: &Path) -> bool {
+        if !self.names(path).iter().any(|name| name == QUARANTINE) {
+            return false;
+        }
+        self.runner.run("xattr", &["-d", QUARANTINE, &path.to_string_lossy()]).is_ok_and(|output| output.success)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::homebrew::CommandOutput;
+    use std::sync::{Arc, Mutex};
+
+    /// A file's attributes, kept the way `xattr` would report them.
+    #[derive(Clone, Default)]
+    struct Attrs(Arc<Mutex<Vec<(String, String)>>>);
+
+    impl CommandRunner for Attrs {
+        fn run(&self, _program: &str, args: &[&str]) -> std::io::Result<CommandOutput> {
+            let mut attrs = self.0.lock().unwrap();
+            let stdout = match args {
+                [_path] => attrs.iter().map(|(name, _)| format!("{}\n", name)).collect(),
+                ["-px", name, _] => attrs.iter().find(|(n, _)| n == name).map(|(_, hex)| hex.clone()).unwrap_or_default(),
+                ["-wx", name, hex, _] => {
+                    attrs.retain(|(n, _)| n != name);
+                    attrs.push((name.to_string(), hex.to_string()));
+                    String::new()
+                }
+                ["-d", name, _] => {
+                    attrs.retain(|(n, _)| n != name);
+                    String::new()
+                }
+                _ => String::new(),
+            };
+            Ok(CommandOutput { success: true, stdout: stdout.into_bytes(), stderr: Vec::new() })
+        }
+    }
+
+    #[test]
+    fn test_reads_synced_attributes_and_strips_quarantine() {
+        let runner = Attrs::default();
+        runner.0.lock().unwrap().extend([
+            ("com.apple.metadata:_kMDItemUserTags".to_string(), "62 70 6C 69\n73 74".to_string()),
+            (QUARANTINE.to_string(), "30 30 38 31".to_string()),
+            ("com.apple.lastuseddate#PS".to_string(), "00".to_string()),
+        ]);
+        let xattrs = Xattrs::with_runner(runner.clone());
+        let path = Path::new("/tmp/notes.md");
+
+        let attributes = xattrs.read(path);
+        assert_eq!(attributes, Attributes::from([("com.apple.metadata:_kMDItemUserTags".to_string(), "62706c697374".to_string())]));
+
+        assert!(xattrs.strip_quarantine(path));
+        assert!(!xattrs.strip_quarantine(path));
+
+        let remote = Attributes::from([
+            ("com.apple.FinderInfo".to_string(), "00".repeat(32)),
+            (QUARANTINE.to_string(), "30".to_string()),
+        ]);
+        xattrs.write(path, &remote);
+        let names: Vec<String> = runner.0.lock().unwrap().iter().map(|(name, _)| name.clone()).collect();
+        assert!(names.contains(&"com.apple.FinderInfo".to_string()));
+        assert!(!names.contains(&QUARANTINE.to_string()));
+    }
+}
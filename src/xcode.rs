@@ -0,0 +1,100 @@
+//! Xcode Command Line Tools, which brew needs to build most formulae.
+//! `xcode-select --install` only opens Apple's installer dialog, so
+//! installing means starting it and waiting for the tools to show up.
+
+use crate::homebrew::{CommandRunner, SystemRunner};
+use crate::reporter::Reporter;
+use crate::{KiwiError, Result};
+use std::time::{Duration, Instant};
+
+/// How long to wait for the installer before giving up.
+pub const INSTALL_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+pub struct CommandLineTools {
+    runner: Box<dyn CommandRunner>,
+}
+
+impl Default for CommandLineTools {
+    fn default() -> Self {
+        Self { runner: Box::new(SystemRunner) }
+    }
+}
+
+impl CommandLineTools {
+    pub fn with_runner(runner: impl CommandRunner + 'static) -> Self {
+        Self { runner: Box::new(runner) }
+    }
+
+    /// Whether a developer directory is selected, which is what brew checks.
+    pub fn is_installed(&self) -> bool {
+        self.runner.run("xcode-select", &["-p"]).is_ok_and(|output| output.success)
+    }
+
+    /// Opens the installer and waits until the tools are installed or
+    /// `timeout` passes.
+    pub async fn install(&self, timeout: Duration, reporter: &dyn Reporter) -> Result<()> {
+        if self.is_installed() {
+            return Ok(());
+        }
+
+        let output = self.runner.run("xcode-select", &["--install"])?;
+        if !output.success && !self.is_installed() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            // A dialog that's already open is fine; we just wait for it
+            if !stderr.contains("already been requested") {
+                return Err(KiwiError::Homebrew(format!("Couldn't start the Command Line Tools installer: {}", stderr.trim())));
+            }
+        }
+
+        reporter.progress("Waiting for the Command Line Tools installer to finish...");
+        let started = Instant::now();
+        while !self.is_installed() {
+            if started.elapsed() >= timeout {
+                return Err(KiwiError::Homebrew(
+                    "Timed out waiting for the Command Line Tools; finish the installer and run this again".to_string(),
+                ));
+            }
+            tokio::time::sleep(POLL_INTERVAL.min(timeout)).await;
+        }
+        reporter.success("Command Line Tools installed");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::homebrew::CommandOutput;
+    use crate::reporter::Silent;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// `xcode-select -p` fails until `--install` has been run.
+    struct Installer(Arc<AtomicUsize>);
+
+    impl CommandRunner for Installer {
+        fn run(&self, _program: &str, args: &[&str]) -> std::io::Result<CommandOutput> {
+            let success = match args {
+                ["--install"] => {
+                    self.0.fetch_add(1, Ordering::SeqCst);
+                    true
+                }
+                _ => self.0.load(Ordering::SeqCst) > 0,
+            };
+            Ok(CommandOutput { success, stdout: Vec::new(), stderr: Vec::new() })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_install_starts_the_installer_once() {
+        let started = Arc::new(AtomicUsize::new(0));
+        let tools = CommandLineTools::with_runner(Installer(started.clone()));
+        assert!(!tools.is_installed());
+
+        tools.install(Duration::from_secs(1), &Silent::default()).await.unwrap();
+        tools.install(Duration::from_secs(1), &Silent::default()).await.unwrap();
+        assert!(tools.is_installed());
+        assert_eq!(started.load(Ordering::SeqCst), 1);
+    }
+}
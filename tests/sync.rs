@@ -0,0 +1,766 @@
+use kiwi::homebrew::Package;
+use kiwi::reporter::Reporter;
+use kiwi::sync::{MergeOptions, PullOptions, PushOptions, Quota, SyncConfig, SyncData};
+use kiwi::test_server::{MockServer, QUOTA, TEST_TOKEN};
+use kiwi::{Dotfiles, KiwiError, Sync};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+fn package(name: &str, version: &str) -> Package {
+    Package {
+        name: name.to_string(),
+        version: Some(version.to_string()),
+        installed: true,
+        dependencies: Vec::new(),
+        install_time: None,
+        last_update: None,
+        size: None,
+        is_cask: false,
+        note: None,
+        held: false,
+        requires_rosetta: false,
+        post_install: Vec::new(),
+    }
+}
+
+/// A scratch dotfiles directory standing in for one machine.
+fn machine(name: &str, packages: &[Package], taps: &[&str]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("kiwi-sync-test-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+
+    if !packages.is_empty() {
+        let manifest: HashMap<&str, &Package> = packages.iter().map(|p| (p.name.as_str(), p)).collect();
+        fs::write(dir.join("packages.json"), serde_json::to_string(&manifest).unwrap()).unwrap();
+    }
+    if !taps.is_empty() {
+        fs::write(dir.join("taps.json"), serde_json::to_string(taps).unwrap()).unwrap();
+    }
+    dir
+}
+
+fn client(server: &MockServer, dir: &Path, token: &str, excluded: &[&str]) -> Sync {
+    Sync::new(
+        SyncConfig {
+            url: server.url().to_string(),
+            token: token.to_string(),
+            excluded_packages: excluded.iter().map(|e| e.to_string()).collect(),
+        },
+        dir.to_path_buf(),
+    )
+}
+
+fn local_manifest(dir: &Path) -> HashMap<String, Package> {
+    serde_json::from_str(&fs::read_to_string(dir.join("packages.json")).unwrap()).unwrap()
+}
+
+#[tokio::test]
+async fn push_then_pull_on_another_machine() {
+    let server = MockServer::start().await;
+
+    let laptop = machine("push-laptop", &[package("git", "2.44.0"), package("work-vpn", "1.0")], &["homebrew/cask-fonts"]);
+    let brew = r#"{"prefix": "/usr/local", "env": {"HOMEBREW_NO_ANALYTICS": "1"}}"#;
+    fs::write(laptop.join("brew.json"), brew).unwrap();
+    client(&server, &laptop, TEST_TOKEN, &["work-*"]).push().await.unwrap();
+
+    let remote = server.data().unwrap();
+    let names: Vec<&str> = remote.packages.iter().map(|p| p.name.as_str()).collect();
+    assert_eq!(names, vec!["git"]);
+    assert_eq!(remote.taps, vec!["homebrew/cask-fonts"]);
+
+    let desktop = machine("push-desktop", &[], &[]);
+    client(&server, &desktop, TEST_TOKEN, &[]).pull(false).await.unwrap();
+
+    let pulled = local_manifest(&desktop);
+    assert_eq!(pulled["git"].version.as_deref(), Some("2.44.0"));
+    assert!(!pulled.contains_key("work-vpn"));
+    assert!(fs::read_to_string(desktop.join("taps.json")).unwrap().contains("homebrew/cask-fonts"));
+    let homebrew = kiwi::Homebrew::new(desktop.join("packages.json"));
+    assert_eq!(homebrew.load_environment().unwrap().unwrap().prefix, "/usr/local");
+
+    fs::remove_dir_all(&laptop).unwrap();
+    fs::remove_dir_all(&desktop).unwrap();
+}
+
+#[tokio::test]
+async fn legacy_sync_endpoint_url_still_reaches_the_server() {
+    let server = MockServer::start().await;
+
+    let laptop = machine("legacy-url", &[package("git", "2.44.0")], &[]);
+    let legacy = Sync::new(
+        SyncConfig {
+            url: format!("{}/sync/", server.url()),
+            token: TEST_TOKEN.to_string(),
+            excluded_packages: Vec::new(),
+        },
+        laptop.clone(),
+    );
+    legacy.push().await.unwrap();
+
+    let names: Vec<String> = server.data().unwrap().packages.into_iter().map(|p| p.name).collect();
+    assert_eq!(names, vec!["git"]);
+    assert!(legacy.check_remote().await.is_healthy());
+
+    fs::remove_dir_all(&laptop).unwrap();
+}
+
+#[tokio::test]
+async fn conflicting_machines_resolve_to_last_writer() {
+    let server = MockServer::start().await;
+    server.set_data(SyncData {
+        files: HashMap::new(),
+        blobs: HashMap::new(),
+        pointers: HashMap::new(),
+        packages: vec![package("node", "22.0.0")],
+        taps: Vec::new(),
+        brew: None,
+        xattrs: HashMap::new(),
+    });
+
+    // Local edits are replaced by what the remote holds on pull
+    let laptop = machine("conflict-laptop", &[package("node", "20.11.0"), package("deno", "1.41.0")], &[]);
+    let sync = client(&server, &laptop, TEST_TOKEN, &[]);
+    sync.pull(false).await.unwrap();
+    let pulled = local_manifest(&laptop);
+    assert_eq!(pulled.len(), 1);
+    assert_eq!(pulled["node"].version.as_deref(), Some("22.0.0"));
+
+    // A later push from another machine overwrites the remote wholesale
+    let desktop = machine("conflict-desktop", &[package("python@3.12", "3.12.2")], &[]);
+    client(&server, &desktop, TEST_TOKEN, &[]).push().await.unwrap();
+    let names: Vec<String> = server.data().unwrap().packages.into_iter().map(|p| p.name).collect();
+    assert_eq!(names, vec!["python@3.12"]);
+    assert_eq!(server.hits("/sync"), 2);
+
+    fs::remove_dir_all(&laptop).unwrap();
+    fs::remove_dir_all(&desktop).unwrap();
+}
+
+#[tokio::test]
+async fn rejects_bad_tokens_and_missing_directories() {
+    let server = MockServer::start().await;
+    let dir = machine("auth", &[package("git", "2.44.0")], &[]);
+
+    match client(&server, &dir, "wrong-token", &[]).push().await {
+        Err(KiwiError::Sync(message)) => {
+            assert!(message.contains("401 Unauthorized - Invalid or expired token (invalid_token)"));
+            assert!(message.contains("(request ID "));
+        }
+        other => panic!("expected an auth failure, got {:?}", other.err()),
+    }
+    assert!(server.data().is_none());
+
+    let missing = dir.join("does-not-exist");
+    assert!(client(&server, &missing, TEST_TOKEN, &[]).pull(false).await.is_err());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn retries_after_rate_limiting() {
+    let server = MockServer::start().await;
+    let dir = machine("retry", &[package("git", "2.44.0")], &[]);
+    let sync = client(&server, &dir, TEST_TOKEN, &[]);
+
+    server.fail_next(429, &[("Retry-After", "0")]);
+    server.fail_next(503, &[("Retry-After", "0")]);
+    sync.push().await.unwrap();
+    assert_eq!(server.hits("/sync"), 3);
+    assert!(server.data().is_some());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn reports_rate_limiting_instead_of_waiting_too_long() {
+    let server = MockServer::start().await;
+    let dir = machine("rate-limited", &[], &[]);
+    let sync = client(&server, &dir, TEST_TOKEN, &[]);
+
+    server.fail_next(429, &[("Retry-After", "3600")]);
+    match sync.pull(false).await {
+        Err(KiwiError::RateLimited { retry_after }) => assert_eq!(retry_after, Some(3600)),
+        other => panic!("expected RateLimited, got {:?}", other.err()),
+    }
+    assert_eq!(server.hits("/sync"), 1);
+
+    for _ in 0..3 {
+        server.fail_next(429, &[("Retry-After", "0")]);
+    }
+    assert!(matches!(sync.pull(false).await, Err(KiwiError::RateLimited { retry_after: Some(0) })));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn tracked_files_travel_with_the_manifest() {
+    let server = MockServer::start().await;
+
+    let laptop = machine("files-laptop", &[], &[]);
+    let source = laptop.join("home/.zshrc");
+    fs::create_dir_all(source.parent().unwrap()).unwrap();
+    fs::write(&source, "export EDITOR=nvim\n").unwrap();
+    let dotfiles = Dotfiles::new(laptop.clone(), laptop.join("dotfiles.json"));
+    dotfiles.add(&source, None, false).unwrap();
+    assert!(dotfiles.list().unwrap()[0].last_synced.is_none());
+    client(&server, &laptop, TEST_TOKEN, &[]).push().await.unwrap();
+    let remote = server.data().unwrap();
+    assert!(remote.files.is_empty());
+    assert_eq!(remote.blobs[".zshrc"], kiwi::blobs::hash(b"export EDITOR=nvim\n"));
+
+    let sync = client(&server, &laptop, TEST_TOKEN, &[]);
+    assert_eq!(sync.remote_file(".zshrc").await.unwrap().unwrap(), b"export EDITOR=nvim\n");
+    assert!(sync.remote_file(".vimrc").await.unwrap().is_none());
+
+    // The push is recorded per dotfile, and later edits show as modified
+    let tracked = dotfiles.list().unwrap();
+    assert_eq!(tracked[0].hash.as_deref(), Some(remote.blobs[".zshrc"].as_str()));
+    assert!(tracked[0].last_synced.is_some() && tracked[0].source_mtime.is_some());
+    assert!(tracked[0].last_applied.is_none());
+    fs::write(&source, "export EDITOR=hx\n").unwrap();
+    assert!(dotfiles.pushed(&tracked).unwrap().is_empty());
+    fs::write(&source, "export EDITOR=nvim\n").unwrap();
+
+    // Streamed pull writes the file; legacy JSON pulls behave the same
+    let desktop = machine("files-desktop", &[], &[]);
+    client(&server, &desktop, TEST_TOKEN, &[]).pull(false).await.unwrap();
+    assert_eq!(fs::read_to_string(desktop.join(".zshrc")).unwrap(), "export EDITOR=nvim\n");
+
+    server.disable_streaming();
+    let older = machine("files-older", &[], &[]);
+    fs::write(older.join(".zshrc"), "# local edits\n").unwrap();
+    client(&server, &older, TEST_TOKEN, &[]).pull(true).await.unwrap();
+    assert_eq!(fs::read_to_string(older.join(".zshrc")).unwrap(), "# local edits\n");
+    client(&server, &older, TEST_TOKEN, &[]).with_backups(true).pull(false).await.unwrap();
+    assert_eq!(fs::read_to_string(older.join(".zshrc")).unwrap(), "export EDITOR=nvim\n");
+    let runs = kiwi::backups::Backups::new(&older).runs().unwrap();
+    assert_eq!(fs::read_to_string(runs[0].join(".zshrc")).unwrap(), "# local edits\n");
+
+    // Keys that would escape the dotfiles dir are rejected
+    server.set_data(SyncData {
+        files: HashMap::from([("../escape".to_string(), "x".to_string())]),
+        blobs: HashMap::new(),
+        pointers: HashMap::new(),
+        packages: Vec::new(),
+        taps: Vec::new(),
+        brew: None,
+        xattrs: HashMap::new(),
+    });
+    assert!(client(&server, &older, TEST_TOKEN, &[]).pull(false).await.is_err());
+    assert!(!older.join("../escape").exists());
+
+    // So are keys that would overwrite kiwi's own state
+    let mut data = server.data().unwrap();
+    data.files = HashMap::from([("packages.json".to_string(), "{}".to_string())]);
+    server.set_data(data);
+    fs::write(older.join("packages.json"), "{\"git\": null}").unwrap();
+    assert!(client(&server, &older, TEST_TOKEN, &[]).pull(false).await.is_err());
+    assert_eq!(fs::read_to_string(older.join("packages.json")).unwrap(), "{\"git\": null}");
+
+    for dir in [laptop, desktop, older] {
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
+
+#[tokio::test]
+async fn pulled_files_that_fail_validation_need_force() {
+    let server = MockServer::start().await;
+    server.set_data(SyncData {
+        files: HashMap::from([
+            ("settings.json".to_string(), "{\"theme\": ".to_string()),
+            ("starship.toml".to_string(), "add_newline = false\n".to_string()),
+        ]),
+        blobs: HashMap::new(),
+        pointers: HashMap::new(),
+        packages: Vec::new(),
+        taps: Vec::new(),
+        brew: None,
+        xattrs: HashMap::new(),
+    });
+
+    let desktop = machine("validate-desktop", &[], &[]);
+    let sync = client(&server, &desktop, TEST_TOKEN, &[]);
+    sync.pull(false).await.unwrap();
+    assert!(!desktop.join("settings.json").exists());
+    assert!(desktop.join("starship.toml").exists());
+
+    let forced = PullOptions { force: true, ..Default::default() };
+    sync.pull_with(forced, &kiwi::reporter::Silent::default()).await.unwrap();
+    assert_eq!(fs::read_to_string(desktop.join("settings.json")).unwrap(), "{\"theme\": ");
+
+    fs::remove_dir_all(&desktop).unwrap();
+}
+
+#[tokio::test]
+async fn partial_pull_restores_only_the_named_files() {
+    let server = MockServer::start().await;
+    server.set_data(SyncData {
+        files: HashMap::from([
+            (".zshrc".to_string(), "export EDITOR=nvim\n".to_string()),
+            (".vimrc".to_string(), "set number\n".to_string()),
+        ]),
+        blobs: HashMap::new(),
+        pointers: HashMap::new(),
+        packages: vec![package("git", "2.44.0")],
+        taps: Vec::new(),
+        brew: None,
+        xattrs: HashMap::new(),
+    });
+
+    let desktop = machine("partial-desktop", &[], &[]);
+    fs::write(desktop.join(".vimrc"), "broken\n").unwrap();
+    fs::write(desktop.join(".zshrc"), "# mine\n").unwrap();
+    let sync = client(&server, &desktop, TEST_TOKEN, &[]);
+    let options = PullOptions { only: vec![".vimrc".to_string()], ..Default::default() };
+    let applied = sync.pull_with(options, &kiwi::reporter::Silent::default()).await.unwrap();
+
+    assert_eq!(applied, vec![".vimrc"]);
+    assert_eq!(fs::read_to_string(desktop.join(".vimrc")).unwrap(), "set number\n");
+    assert_eq!(fs::read_to_string(desktop.join(".zshrc")).unwrap(), "# mine\n");
+    assert!(!desktop.join("packages.json").exists());
+
+    fs::remove_dir_all(&desktop).unwrap();
+}
+
+#[tokio::test]
+async fn identical_files_are_uploaded_once() {
+    let server = MockServer::start().await;
+
+    let laptop = machine("dedup-laptop", &[], &[]);
+    let dotfiles = Dotfiles::new(laptop.clone(), laptop.join("dotfiles.json"));
+    for name in ["gitconfig", "gitconfig-work"] {
+        let source = laptop.join("home").join(name);
+        fs::create_dir_all(source.parent().unwrap()).unwrap();
+        fs::write(&source, "[user]\n\tname = Kiwi\n").unwrap();
+        dotfiles.add(&source, None, false).unwrap();
+    }
+
+    let sync = client(&server, &laptop, TEST_TOKEN, &[]);
+    sync.push().await.unwrap();
+    sync.push().await.unwrap();
+
+    let hash = kiwi::blobs::hash(b"[user]\n\tname = Kiwi\n");
+    assert_eq!(server.blob_count(), 1);
+    assert_eq!(server.hits(&format!("/blobs/{}", hash)), 1);
+    assert_eq!(server.data().unwrap().blobs.len(), 2);
+
+    // A fresh machine downloads each blob once and verifies it
+    let desktop = machine("dedup-desktop", &[], &[]);
+    client(&server, &desktop, TEST_TOKEN, &[]).pull(false).await.unwrap();
+    assert_eq!(fs::read_to_string(desktop.join("gitconfig-work")).unwrap(), "[user]\n\tname = Kiwi\n");
+    assert_eq!(server.hits(&format!("/blobs/{}", hash)), 2);
+
+    fs::remove_dir_all(&laptop).unwrap();
+    fs::remove_dir_all(&desktop).unwrap();
+}
+
+#[tokio::test]
+async fn pointer_files_sync_only_their_hash() {
+    let server = MockServer::start().await;
+
+    let laptop = machine("pointer-laptop", &[], &[]);
+    let source = laptop.join("home/model.bin");
+    fs::create_dir_all(source.parent().unwrap()).unwrap();
+    fs::write(&source, vec![7u8; 4096]).unwrap();
+    Dotfiles::new(laptop.clone(), laptop.join("dotfiles.json")).add(&source, None, true).unwrap();
+    client(&server, &laptop, TEST_TOKEN, &[]).push().await.unwrap();
+
+    let remote = server.data().unwrap();
+    assert!(remote.blobs.is_empty());
+    assert_eq!(server.blob_count(), 0);
+    assert_eq!(remote.pointers["model.bin"].hash, kiwi::blobs::hash(&[7u8; 4096]));
+    assert_eq!(remote.pointers["model.bin"].size, 4096);
+
+    // Pulling never creates the file, it only reports it
+    let desktop = machine("pointer-desktop", &[], &[]);
+    client(&server, &desktop, TEST_TOKEN, &[]).pull(false).await.unwrap();
+    assert!(!desktop.join("model.bin").exists());
+
+    fs::remove_dir_all(&laptop).unwrap();
+    fs::remove_dir_all(&desktop).unwrap();
+}
+
+#[tokio::test]
+async fn reports_remote_status() {
+    let server = MockServer::start().await;
+    let dir = machine("status", &[package("git", "2.44.0")], &[]);
+
+    let status = client(&server, &dir, TEST_TOKEN, &[]).check_remote().await;
+    assert!(status.is_healthy());
+    assert!(status.latency.is_some());
+    assert_eq!(status.server_version.as_deref(), Some("test"));
+    assert_eq!(status.quota, Some(Quota { used: 0, limit: Some(QUOTA) }));
+
+    let rejected = client(&server, &dir, "wrong-token", &[]).check_remote().await;
+    assert!(rejected.reachable && !rejected.authenticated);
+    assert!(rejected.quota.is_none());
+    assert!(client(&server, &dir, "wrong-token", &[]).check_remote_access().await.is_err());
+
+    let url = server.url().to_string();
+    drop(server);
+    let offline = Sync::new(SyncConfig { url, token: TEST_TOKEN.to_string(), excluded_packages: Vec::new() }, dir.clone());
+    let status = offline.check_remote().await;
+    assert!(!status.reachable && status.error.is_some());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn reports_usage_and_refuses_pushes_over_quota() {
+    let server = MockServer::start().await;
+
+    let dir = machine("usage", &[], &[]);
+    let dotfiles = Dotfiles::new(dir.clone(), dir.join("dotfiles.json"));
+    let home = dir.join("home");
+    fs::create_dir_all(&home).unwrap();
+    fs::write(home.join("zshrc"), "export EDITOR=vim\n").unwrap();
+    fs::write(home.join("vimrc"), "set number\n").unwrap();
+    dotfiles.add(&home.join("zshrc"), None, false).unwrap();
+    dotfiles.add(&home.join("vimrc"), None, false).unwrap();
+
+    let sync = client(&server, &dir, TEST_TOKEN, &[]);
+    sync.push().await.unwrap();
+    let usage = sync.usage().await.unwrap();
+    assert_eq!(usage.quota.used, 29);
+    assert_eq!(usage.largest(1), vec![("zshrc", 18)]);
+
+    server.set_quota(30);
+    fs::write(home.join("zshrc"), "export EDITOR=nvim\n").unwrap();
+    match sync.push().await {
+        Err(KiwiError::Sync(message)) => assert!(message.contains("kiwi account usage")),
+        other => panic!("expected a quota error, got {:?}", other.err()),
+    }
+    assert_eq!(server.blob_count(), 2);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn activity_lists_pushes_from_each_machine() {
+    let server = MockServer::start().await;
+
+    let laptop = machine("activity-laptop", &[package("git", "2.44.0")], &[]);
+    let home = laptop.join("home");
+    fs::create_dir_all(&home).unwrap();
+    fs::write(home.join("zshrc"), "export EDITOR=vim\n").unwrap();
+    Dotfiles::new(laptop.clone(), laptop.join("dotfiles.json")).add(&home.join("zshrc"), None, false).unwrap();
+    let laptop_sync = client(&server, &laptop, TEST_TOKEN, &[]).with_machine("Laptop");
+    laptop_sync.push().await.unwrap();
+    laptop_sync.push().await.unwrap();
+
+    let desktop = machine("activity-desktop", &[package("git", "2.44.0"), package("wget", "1.24")], &[]);
+    client(&server, &desktop, TEST_TOKEN, &[]).with_machine("Desktop").push().await.unwrap();
+
+    let activity = laptop_sync.activity().await.unwrap();
+    let machines: Vec<Option<&str>> = activity.iter().map(|entry| entry.machine.as_deref()).collect();
+    assert_eq!(machines, vec![Some("Laptop"), Some("Laptop"), Some("Desktop")]);
+    assert_eq!(activity[0].files, vec!["zshrc"]);
+    assert_eq!(activity[0].packages_added, vec!["git"]);
+    assert!(activity[1].is_empty());
+    assert_eq!(activity[2].files, vec!["zshrc"]);
+    assert_eq!(activity[2].packages_added, vec!["wget"]);
+    assert!(activity[2].packages_removed.is_empty());
+
+    fs::remove_dir_all(&laptop).unwrap();
+    fs::remove_dir_all(&desktop).unwrap();
+}
+
+/// Keeps the byte counts a push or pull reports.
+#[derive(Default)]
+struct Transfers(RefCell<Vec<(u64, u64)>>);
+
+impl Reporter for Transfers {
+    fn progress(&self, _message: &str) {}
+    fn success(&self, _message: &str) {}
+    fn warning(&self, _message: &str) {}
+    fn confirm(&self, _prompt: &str) -> bool {
+        true
+    }
+
+    fn transfer(&self, done: u64, total: u64) {
+        self.0.borrow_mut().push((done, total));
+    }
+}
+
+#[tokio::test]
+async fn reports_bytes_transferred() {
+    let server = MockServer::start().await;
+
+    let laptop = machine("transfer-laptop", &[package("git", "2.44.0")], &[]);
+    let dotfiles = Dotfiles::new(laptop.clone(), laptop.join("dotfiles.json"));
+    let source = laptop.join("home").join("big.json");
+    fs::create_dir_all(source.parent().unwrap()).unwrap();
+    fs::write(&source, format!("[{}0]", "0,".repeat(100_000))).unwrap();
+    dotfiles.add(&source, None, false).unwrap();
+
+    let pushed = Transfers::default();
+    client(&server, &laptop, TEST_TOKEN, &[]).push_with(PushOptions::default(), &pushed).await.unwrap();
+    let pushed = pushed.0.into_inner();
+    let &(done, total) = pushed.last().unwrap();
+    assert_eq!(done, total);
+    assert!(total > 200_000);
+    assert!(pushed.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+
+
+    let desktop = machine("transfer-desktop", &[], &[]);
+    let pulled = Transfers::default();
+    client(&server, &desktop, TEST_TOKEN, &[])
+        .pull_with(PullOptions::default(), &pulled)
+        .await
+        .unwrap();
+    let &(done, total) = pulled.0.borrow().last().unwrap();
+    assert_eq!(done, total);
+    assert!(total > 200_000);
+
+    fs::remove_dir_all(&laptop).unwrap();
+    fs::remove_dir_all(&desktop).unwrap();
+}
+
+#[tokio::test]
+async fn force_overrides_conflicts_in_either_direction() {
+    let server = MockServer::start().await;
+    let remote_zshrc = |content: &str| SyncData {
+        files: HashMap::from([("zshrc".to_string(), content.to_string())]),
+        blobs: HashMap::new(),
+        pointers: HashMap::new(),
+        packages: Vec::new(),
+        taps: Vec::new(),
+        brew: None,
+        xattrs: HashMap::new(),
+    };
+
+    let laptop = machine("force-laptop", &[], &[]);
+    let dotfiles = Dotfiles::new(laptop.clone(), laptop.join("dotfiles.json"));
+    let source = laptop.join("home").join("zshrc");
+    fs::create_dir_all(source.parent().unwrap()).unwrap();
+    fs::write(&source, "# laptop\n").unwrap();
+    dotfiles.add(&source, None, false).unwrap();
+    let sync = client(&server, &laptop, TEST_TOKEN, &[]);
+    sync.push().await.unwrap();
+
+    // Another machine pushes its own version, then this one changes too
+    server.set_data(remote_zshrc("# desktop\n"));
+    fs::write(&source, "# laptop, edited\n").unwrap();
+    match sync.push().await {
+        Err(KiwiError::Conflict { files }) => assert_eq!(files, vec!["zshrc"]),
+        other => panic!("expected a conflict, got {:?}", other.err()),
+    }
+    assert_eq!(server.data().unwrap().files["zshrc"], "# desktop\n");
+    let forced = PushOptions { force: true };
+    sync.push_with(forced, &kiwi::reporter::Silent::default()).await.unwrap();
+    assert!(server.data().unwrap().blobs.contains_key("zshrc"));
+
+    // Local edits survive a pull unless it's forced, which backs them up
+    server.set_data(remote_zshrc("# desktop again\n"));
+    fs::write(&source, "# laptop, unsynced\n").unwrap();
+    sync.pull(false).await.unwrap();
+    assert_eq!(fs::read_to_string(&source).unwrap(), "# laptop, unsynced\n");
+
+    let forced = PullOptions { force: true, ..Default::default() };
+    sync.pull_with(forced, &kiwi::reporter::Silent::default()).await.unwrap();
+    assert_eq!(fs::read_to_string(&source).unwrap(), "# desktop again\n");
+    let runs = kiwi::backups::Backups::new(&laptop).runs().unwrap();
+    assert_eq!(fs::read_to_string(runs[0].join("zshrc")).unwrap(), "# laptop, unsynced\n");
+
+    fs::remove_dir_all(&laptop).unwrap();
+}
+
+/// Answers every choice with the same option.
+struct Chooses(usize);
+
+impl Reporter for Chooses {
+    fn progress(&self, _message: &str) {}
+    fn success(&self, _message: &str) {}
+    fn warning(&self, _message: &str) {}
+    fn confirm(&self, _prompt: &str) -> bool {
+        false
+    }
+
+    fn choose(&self, _prompt: &str, _choices: &[&str]) -> Option<usize> {
+        Some(self.0)
+    }
+}
+
+#[tokio::test]
+async fn pull_offers_to_adopt_files_already_here() {
+    let server = MockServer::start().await;
+    let track = |dir: &Path, content: &str| {
+        let source = dir.join("home").join("zshrc");
+        fs::create_dir_all(source.parent().unwrap()).unwrap();
+        fs::write(&source, content).unwrap();
+        Dotfiles::new(dir.to_path_buf(), dir.join("dotfiles.json")).add(&source, None, false).unwrap();
+        source
+    };
+
+    let laptop = machine("adopt-laptop", &[], &[]);
+    track(&laptop, "# laptop\n");
+    client(&server, &laptop, TEST_TOKEN, &[]).push().await.unwrap();
+
+    // A Mac that already had its own copy keeps it unless asked
+    let desktop = machine("adopt-desktop", &[], &[]);
+    let zshrc = track(&desktop, "# desktop\n");
+    let sync = client(&server, &desktop, TEST_TOKEN, &[]);
+    sync.pull(false).await.unwrap();
+    assert_eq!(fs::read_to_string(&zshrc).unwrap(), "# desktop\n");
+
+    // Adopting pushes it
+    sync.pull_with(PullOptions::default(), &Chooses(0)).await.unwrap();
+    assert_eq!(fs::read_to_string(&zshrc).unwrap(), "# desktop\n");
+    let remote = server.data().unwrap();
+    let store = kiwi::blobs::BlobStore::new(desktop.join("blobs"));
+    assert_eq!(store.get(&remote.blobs["zshrc"]).unwrap(), b"# desktop\n");
+
+    // Overwriting takes the remote's, backing this Mac's up
+    let studio = machine("adopt-studio", &[], &[]);
+    let zshrc = track(&studio, "# studio\n");
+    client(&server, &studio, TEST_TOKEN, &[]).pull_with(PullOptions::default(), &Chooses(1)).await.unwrap();
+    assert_eq!(fs::read_to_string(&zshrc).unwrap(), "# desktop\n");
+    let runs = kiwi::backups::Backups::new(&studio).runs().unwrap();
+    assert_eq!(fs::read_to_string(runs[0].join("zshrc")).unwrap(), "# studio\n");
+
+    for dir in [laptop, desktop, studio] {
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
+
+#[tokio::test]
+async fn two_way_sync_moves_each_file_the_way_it_changed() {
+    let server = MockServer::start().await;
+    let silent = kiwi::reporter::Silent::default();
+    let track = |dir: &Path, name: &str, content: &str| {
+        let source = dir.join("home").join(name);
+        fs::create_dir_all(source.parent().unwrap()).unwrap();
+        fs::write(&source, content).unwrap();
+        Dotfiles::new(dir.to_path_buf(), dir.join("dotfiles.json")).add(&source, None, false).unwrap();
+        source
+    };
+
+    let laptop = machine("merge-laptop", &[package("git", "2.44.0")], &[]);
+    let zshrc = track(&laptop, "zshrc", "# a\n");
+    let vimrc = track(&laptop, "vimrc", "set number\n");
+    let laptop_sync = client(&server, &laptop, TEST_TOKEN, &[]);
+    let summary = laptop_sync.merge(MergeOptions::default(), &silent).await.unwrap();
+    assert_eq!(summary.pushed, vec!["vimrc", "zshrc"]);
+
+    // The desktop adds its own file and package, and gets the laptop's
+    let desktop = machine("merge-desktop", &[package("node", "22.0.0")], &[]);
+    track(&desktop, "gitconfig", "[user]\n");
+    let summary = client(&server, &desktop, TEST_TOKEN, &[]).merge(MergeOptions::default(), &silent).await.unwrap();
+    assert_eq!(summary.pushed, vec!["gitconfig"]);
+    assert_eq!(summary.pulled, vec!["vimrc", "zshrc"]);
+    assert_eq!(fs::read_to_string(desktop.join("zshrc")).unwrap(), "# a\n");
+    let remote = server.data().unwrap();
+    assert_eq!(remote.blobs.len(), 3);
+    assert_eq!(remote.packages.len(), 2);
+
+    fs::write(&vimrc, "set relativenumber\n").unwrap();
+    let summary = laptop_sync.merge(MergeOptions::default(), &silent).await.unwrap();
+    assert_eq!(summary.pushed, vec!["vimrc"]);
+    assert_eq!(summary.pulled, vec!["gitconfig"]);
+    assert!(laptop_sync.merge(MergeOptions::default(), &silent).await.unwrap().pushed.is_empty());
+
+    // Changed on both sides: left alone unless local is preferred
+    let mut remote = server.data().unwrap();
+    remote.blobs.remove("zshrc");
+    remote.files.insert("zshrc".to_string(), "# remote\n".to_string());
+    server.set_data(remote);
+    fs::write(&zshrc, "# local\n").unwrap();
+    let summary = laptop_sync.merge(MergeOptions::default(), &silent).await.unwrap();
+    assert_eq!(summary.conflicts, vec!["zshrc"]);
+    assert_eq!(fs::read_to_string(&zshrc).unwrap(), "# local\n");
+    let summary = laptop_sync.merge(MergeOptions { prefer_local: true, ..Default::default() }, &silent).await.unwrap();
+    assert_eq!(summary.pushed, vec!["zshrc"]);
+    assert!(server.data().unwrap().blobs.contains_key("zshrc"));
+
+    fs::remove_dir_all(&laptop).unwrap();
+    fs::remove_dir_all(&desktop).unwrap();
+}
+
+#[tokio::test]
+async fn two_way_sync_drops_what_was_deleted_since_the_last_sync() {
+    let server = MockServer::start().await;
+    let silent = kiwi::reporter::Silent::default();
+    let track = |dir: &Path, name: &str| {
+        let source = dir.join("home").join(name);
+        fs::create_dir_all(source.parent().unwrap()).unwrap();
+        fs::write(&source, format!("# {}\n", name)).unwrap();
+        Dotfiles::new(dir.to_path_buf(), dir.join("dotfiles.json")).add(&source, None, false).unwrap();
+        source
+    };
+
+    let laptop = machine("drop-laptop", &[package("git", "2.44.0"), package("node", "22.0.0")], &["homebrew/cask-fonts"]);
+    track(&laptop, "zshrc");
+    let vimrc = track(&laptop, "vimrc");
+    let laptop_sync = client(&server, &laptop, TEST_TOKEN, &[]);
+    laptop_sync.merge(MergeOptions::default(), &silent).await.unwrap();
+    let desktop = machine("drop-desktop", &[], &[]);
+    track(&desktop, "vimrc");
+    let desktop_sync = client(&server, &desktop, TEST_TOKEN, &[]);
+    desktop_sync.merge(MergeOptions { prefer_local: true, ..Default::default() }, &silent).await.unwrap();
+    laptop_sync.merge(MergeOptions::default(), &silent).await.unwrap();
+
+    // The laptop deletes a file, a package, and a tap
+    fs::remove_file(&vimrc).unwrap();
+    let mut manifest = local_manifest(&laptop);
+    manifest.remove("node");
+    fs::write(laptop.join("packages.json"), serde_json::to_string(&manifest).unwrap()).unwrap();
+    fs::write(laptop.join("taps.json"), "[]").unwrap();
+    let summary = laptop_sync.merge(MergeOptions::default(), &silent).await.unwrap();
+    assert_eq!(summary.dropped, vec!["vimrc"]);
+    assert!(summary.pulled.is_empty());
+    let remote = server.data().unwrap();
+    assert!(!remote.blobs.contains_key("vimrc") && !remote.files.contains_key("vimrc"));
+    assert_eq!(remote.packages.iter().map(|p| p.name.as_str()).collect::<Vec<_>>(), vec!["git"]);
+    assert!(remote.taps.is_empty());
+
+    // The desktop follows instead of adding them back
+    let summary = desktop_sync.merge(MergeOptions::default(), &silent).await.unwrap();
+    assert!(summary.pushed.is_empty());
+    assert!(desktop.join("home/vimrc").exists());
+    assert_eq!(local_manifest(&desktop).into_keys().collect::<Vec<_>>(), vec!["git"]);
+    let remote = server.data().unwrap();
+    assert!(!remote.blobs.contains_key("vimrc") && !remote.files.contains_key("vimrc"));
+    assert_eq!(remote.packages.len(), 1);
+    assert!(remote.taps.is_empty());
+
+    // A source that's there but can't be read isn't a deletion
+    let zshrc = laptop.join("home/zshrc");
+    fs::remove_file(&zshrc).unwrap();
+    fs::create_dir(&zshrc).unwrap();
+    assert!(matches!(laptop_sync.merge(MergeOptions::default(), &silent).await, Err(KiwiError::Sync(_))));
+    assert!(server.data().unwrap().blobs.contains_key("zshrc"));
+
+    fs::remove_dir_all(&laptop).unwrap();
+    fs::remove_dir_all(&desktop).unwrap();
+}
+
+#[tokio::test]
+async fn credential_files_are_only_synced_encrypted() {
+    let server = MockServer::start().await;
+    let laptop = machine("credentials-laptop", &[], &[]);
+    let dotfiles = Dotfiles::new(laptop.clone(), laptop.join("dotfiles.json"));
+    let home = laptop.join("home");
+    fs::create_dir_all(&home).unwrap();
+    fs::write(home.join("zshrc"), "# laptop\n").unwrap();
+    fs::write(home.join(".env"), "API_KEY=secret\n").unwrap();
+    dotfiles.add(&home.join("zshrc"), None, false).unwrap();
+    dotfiles.add(&home.join(".env"), None, false).unwrap();
+
+    let sync = client(&server, &laptop, TEST_TOKEN, &[]);
+    sync.push().await.unwrap();
+    let remote = server.data().unwrap();
+    assert!(remote.blobs.contains_key("zshrc") || remote.files.contains_key("zshrc"));
+    assert!(!remote.blobs.contains_key(".env") && !remote.files.contains_key(".env"));
+
+    // A copy already on the remote isn't pulled over it either
+    let mut remote = remote;
+    remote.files.insert(".env".to_string(), "API_KEY=old\n".to_string());
+    server.set_data(remote);
+    let summary = sync.merge(MergeOptions::default(), &kiwi::reporter::Silent::default()).await.unwrap();
+    assert!(summary.pushed.is_empty() && summary.pulled.is_empty());
+    assert_eq!(fs::read_to_string(home.join(".env")).unwrap(), "API_KEY=secret\n");
+
+    fs::remove_dir_all(&laptop).unwrap();
+}